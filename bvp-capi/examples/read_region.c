@@ -0,0 +1,59 @@
+/*
+ * Writes a small volume with the C API, opens it again and reads a region of it.
+ * Build the library with `cargo build --release` and compile with
+ *   cc examples/read_region.c -Iinclude -Ltarget/release -lbvp_capi -o read_region
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "bvp.h"
+
+static int fail(BvpStatus status) {
+    const char *message = bvp_last_error();
+    fprintf(stderr, "error %d: %s\n", (int)status, message != NULL ? message : "unknown");
+    return 1;
+}
+
+int main(int argc, char **argv) {
+    const char *path = argc > 1 ? argv[1] : "example.saf";
+    uint32_t dimensions[3] = {32, 32, 32};
+    uint32_t block_dimensions[3] = {16, 16, 16};
+    size_t size = 32 * 32 * 32;
+    uint8_t *volume = malloc(size);
+    for (size_t i = 0; i < size; i++) {
+        volume[i] = (uint8_t)(i % 251);
+    }
+
+    BvpStatus status = bvp_write_volume(path, BVP_ARCHIVE_SAF, volume, size, dimensions, block_dimensions, "u8", BVP_COMPRESSION_LZ4S);
+    free(volume);
+    if (status != BVP_STATUS_OK) {
+        return fail(status);
+    }
+
+    BvpAsset *asset = NULL;
+    status = bvp_open(path, BVP_ARCHIVE_SAF, &asset);
+    if (status != BVP_STATUS_OK) {
+        return fail(status);
+    }
+    printf("%zu modalities\n", (size_t)bvp_get_modality_count(asset));
+
+    uint32_t start[3] = {8, 8, 8};
+    uint32_t end[3] = {24, 24, 24};
+    uintptr_t region_size = 0;
+    status = bvp_get_region_size(asset, 0, start, end, &region_size);
+    if (status != BVP_STATUS_OK) {
+        bvp_close(asset);
+        return fail(status);
+    }
+    uint8_t *region = malloc(region_size);
+    status = bvp_read_region(asset, 0, start, end, region, region_size);
+    bvp_close(asset);
+    if (status != BVP_STATUS_OK) {
+        free(region);
+        return fail(status);
+    }
+    printf("read %zu bytes, first voxel %u\n", (size_t)region_size, region[0]);
+    free(region);
+    return 0;
+}
@@ -0,0 +1,187 @@
+#ifndef BVP_H
+#define BVP_H
+
+/* Generated with cbindgen from bvp-capi/src/lib.rs, do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Type of archive an asset is stored in.
+ */
+typedef enum BvpArchive {
+  /**
+   * A folder with `manifest.json` and the block files.
+   */
+  BVP_ARCHIVE_NONE = 0,
+  BVP_ARCHIVE_SAF = 1,
+  BVP_ARCHIVE_ZIP = 2,
+} BvpArchive;
+
+/**
+ * Compression of the block files of a written asset.
+ */
+typedef enum BvpCompression {
+  BVP_COMPRESSION_NONE = 0,
+  BVP_COMPRESSION_LZ4S = 1,
+} BvpCompression;
+
+/**
+ * Result of a call.
+ */
+typedef enum BvpStatus {
+  BVP_STATUS_OK = 0,
+  /**
+   * A pointer is null, or a value is out of range or malformed.
+   */
+  BVP_STATUS_INVALID_ARGUMENT = 1,
+  /**
+   * The asset cannot be opened or read.
+   */
+  BVP_STATUS_READ_FAILED = 2,
+  /**
+   * The asset cannot be written.
+   */
+  BVP_STATUS_WRITE_FAILED = 3,
+  /**
+   * The buffer is smaller than the data, see `bvp_get_region_size`.
+   */
+  BVP_STATUS_BUFFER_TOO_SMALL = 4,
+  /**
+   * The library panicked, which is a bug.
+   */
+  BVP_STATUS_PANIC = 5,
+} BvpStatus;
+
+/**
+ * An opened asset, see `bvp_open`.
+ */
+typedef struct BvpAsset BvpAsset;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Returns a description of the error of the last call on this thread that failed,
+ * or null if it succeeded. The string is valid until the next call on the thread.
+ */
+const char *bvp_last_error(void);
+
+/**
+ * Opens an asset and stores it in `out_asset`. It has to be closed with `bvp_close`.
+ * * `path` - the archive file, or for `BVP_ARCHIVE_NONE` the folder or the manifest file
+ * * `archive` - the type of archive
+ * * `out_asset` - receives the asset
+ * # Safety
+ * `path` has to be a NUL terminated string and `out_asset` a valid pointer.
+ */
+BvpStatus bvp_open(const char *path, BvpArchive archive, BvpAsset **out_asset);
+
+/**
+ * Closes an asset. Does nothing for null.
+ * # Safety
+ * `asset` has to be null or returned by `bvp_open` and not closed yet.
+ */
+void bvp_close(BvpAsset *asset);
+
+/**
+ * Returns the number of modalities of an asset, or 0 for null.
+ * # Safety
+ * `asset` has to be null or an open asset.
+ */
+uintptr_t bvp_get_modality_count(const BvpAsset *asset);
+
+/**
+ * Stores the dimensions of a modality, in voxels, in `out_dimensions`.
+ * * `modality` - index of the modality
+ * * `out_dimensions` - receives X, Y and Z
+ * # Safety
+ * `asset` has to be an open asset and `out_dimensions` point to three values.
+ */
+BvpStatus bvp_get_modality_dimensions(const BvpAsset *asset,
+                                      uintptr_t modality,
+                                      uint32_t *out_dimensions);
+
+/**
+ * Writes the format of a modality as a JSON object, NUL terminated, to `buffer`,
+ * and stores its length without the NUL in `out_length`. Fails with
+ * `BVP_STATUS_BUFFER_TOO_SMALL` if it does not fit, `out_length` is set either way.
+ * * `modality` - index of the modality
+ * * `buffer` - receives the JSON, can be null if `capacity` is 0
+ * * `capacity` - size of the buffer in bytes
+ * * `out_length` - receives the length of the JSON, can be null
+ * # Safety
+ * `asset` has to be an open asset and `buffer` hold `capacity` bytes.
+ */
+BvpStatus bvp_get_format_json(const BvpAsset *asset,
+                              uintptr_t modality,
+                              char *buffer,
+                              uintptr_t capacity,
+                              uintptr_t *out_length);
+
+/**
+ * Stores the size in bytes of a region of a modality in `out_size`, as read by `bvp_read_region`.
+ * * `modality` - index of the modality
+ * * `start` - X, Y and Z of the first voxel of the region
+ * * `end` - X, Y and Z of the end of the region (exclusive)
+ * * `out_size` - receives the size
+ * # Safety
+ * `asset` has to be an open asset, `start` and `end` point to three values and `out_size` be valid.
+ */
+BvpStatus bvp_get_region_size(const BvpAsset *asset,
+                              uintptr_t modality,
+                              const uint32_t *start,
+                              const uint32_t *end,
+                              uintptr_t *out_size);
+
+/**
+ * Reconstructs a region of a modality into `buffer`, X changing fastest, see `bvp_get_region_size`
+ * for the size it needs. To read the whole volume, use the modality dimensions as `end`.
+ * * `modality` - index of the modality
+ * * `start` - X, Y and Z of the first voxel of the region
+ * * `end` - X, Y and Z of the end of the region (exclusive)
+ * * `buffer` - receives the voxels
+ * * `capacity` - size of the buffer in bytes
+ * # Safety
+ * `asset` has to be an open asset, `start` and `end` point to three values and `buffer` hold `capacity` bytes.
+ */
+BvpStatus bvp_read_region(const BvpAsset *asset,
+                          uintptr_t modality,
+                          const uint32_t *start,
+                          const uint32_t *end,
+                          uint8_t *buffer,
+                          uintptr_t capacity);
+
+/**
+ * Writes a volume as an asset with a single modality, split into blocks of the same dimensions.
+ * Blocks with the same data are stored once.
+ * * `path` - the archive file, or for `BVP_ARCHIVE_NONE` the folder to write to
+ * * `archive` - the type of archive
+ * * `data` - the voxels, X changing fastest
+ * * `size` - size of the data in bytes
+ * * `dimensions` - X, Y and Z of the volume
+ * * `block_dimensions` - X, Y and Z of the blocks
+ * * `format` - format of the voxels, as a JSON object or a shorthand such as `u8`, `u16`, `f32` or `u8x3`
+ * * `compression` - compression of the block files
+ * # Safety
+ * `path` and `format` have to be NUL terminated strings, `data` hold `size` bytes and
+ * `dimensions` and `block_dimensions` point to three values.
+ */
+BvpStatus bvp_write_volume(const char *path,
+                           BvpArchive archive,
+                           const uint8_t *data,
+                           uintptr_t size,
+                           const uint32_t *dimensions,
+                           const uint32_t *block_dimensions,
+                           const char *format,
+                           BvpCompression compression);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* BVP_H */
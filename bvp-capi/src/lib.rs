@@ -0,0 +1,331 @@
+//! C API of the bvp library, so C and C++ applications can read and write BVP assets
+//! without going through `bvp2raw`. The header is `include/bvp.h`, generated with cbindgen.
+//!
+//! All functions return a `BvpStatus`. On failure, `bvp_last_error` returns a description
+//! of the error, which stays valid until the next call on the same thread.
+//! Panics are caught at the boundary and reported as `BVP_STATUS_PANIC`.
+
+use std::{cell::RefCell, ffi::{c_char, CStr, CString}, panic::{self, AssertUnwindSafe}, path::Path, ptr, slice, str::FromStr};
+
+use tinyjson::JsonValue;
+
+use bvp::{archives::ArchiveEnum, bvpfile::BVPFile, compressions::CompressionType, formats::{self, Format}, reader::VolumeReader, vector3::Vector3, writer::VolumeWriter};
+
+/// Result of a call.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BvpStatus {
+    Ok = 0,
+    /// A pointer is null, or a value is out of range or malformed.
+    InvalidArgument = 1,
+    /// The asset cannot be opened or read.
+    ReadFailed = 2,
+    /// The asset cannot be written.
+    WriteFailed = 3,
+    /// The buffer is smaller than the data, see `bvp_get_region_size`.
+    BufferTooSmall = 4,
+    /// The library panicked, which is a bug.
+    Panic = 5
+}
+
+/// Type of archive an asset is stored in.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BvpArchive {
+    /// A folder with `manifest.json` and the block files.
+    None = 0,
+    Saf = 1,
+    Zip = 2
+}
+
+/// Compression of the block files of a written asset.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BvpCompression {
+    None = 0,
+    Lz4s = 1
+}
+
+/// An opened asset, see `bvp_open`.
+pub struct BvpAsset {
+    bvp_file: BVPFile
+}
+
+type Failure = (BvpStatus, String);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: Option<String>) {
+    // Messages with NUL bytes cannot be returned to C, the part before it is kept.
+    let message = message.map(|m| CString::new(m.split('\0').next().unwrap_or_default()).unwrap_or_default());
+    LAST_ERROR.with(|e| *e.borrow_mut() = message);
+}
+
+/// Runs the body of a call: records its error, and catches panics so they do not unwind into C.
+/// * `body` - the body of the call
+fn call<F: FnOnce() -> Result<(), Failure>>(body: F) -> BvpStatus {
+    let result = match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(r) => r,
+        Err(e) => {
+            let message = e.downcast_ref::<String>().cloned()
+                .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err((BvpStatus::Panic, message))
+        }
+    };
+    return match result {
+        Ok(()) => {
+            set_last_error(None);
+            BvpStatus::Ok
+        },
+        Err((status, message)) => {
+            set_last_error(Some(message));
+            status
+        }
+    };
+}
+
+fn invalid(message: &str) -> Failure {
+    return (BvpStatus::InvalidArgument, message.to_string());
+}
+
+/// Reads a UTF-8 string argument.
+/// # Safety
+/// `s` has to be null or a NUL terminated string.
+unsafe fn string_argument<'a>(s: *const c_char, name: &str) -> Result<&'a str, Failure> {
+    if s.is_null() {
+        return Err(invalid(&format!("`{}` is null", name)));
+    }
+    return CStr::from_ptr(s).to_str().map_err(|_| invalid(&format!("`{}` is not valid UTF-8", name)));
+}
+
+/// Reads a 3D vector argument.
+/// # Safety
+/// `v` has to be null or point to three values.
+unsafe fn vector_argument(v: *const u32, name: &str) -> Result<Vector3<u32>, Failure> {
+    if v.is_null() {
+        return Err(invalid(&format!("`{}` is null", name)));
+    }
+    let v = slice::from_raw_parts(v, 3);
+    return Ok(Vector3::from_xyz(v[0], v[1], v[2]));
+}
+
+/// Returns the asset behind a pointer.
+/// # Safety
+/// `asset` has to be null or returned by `bvp_open` and not closed.
+unsafe fn asset_argument<'a>(asset: *const BvpAsset) -> Result<&'a BvpAsset, Failure> {
+    return asset.as_ref().ok_or_else(|| invalid("`asset` is null"));
+}
+
+fn archive_type(archive: BvpArchive) -> ArchiveEnum {
+    return match archive {
+        BvpArchive::None => ArchiveEnum::None,
+        BvpArchive::Saf => ArchiveEnum::SAF,
+        BvpArchive::Zip => ArchiveEnum::ZIP
+    };
+}
+
+/// Parses a format given as a JSON object or a shorthand such as `u8`.
+fn parse_format(text: &str) -> Result<Format, Failure> {
+    let json = if text.trim_start().starts_with('{') {
+        JsonValue::from_str(text).map_err(|e| invalid(&format!("invalid format: {}", e)))?
+    } else {
+        match formats::shorthand_to_json(text) {
+            Some(j) => j,
+            None => return Err(invalid(&format!("invalid format `{}`", text)))
+        }
+    };
+    return Format::from_json(&json).map_err(|e| invalid(&e.to_string()));
+}
+
+/// Returns the root block and the format of a modality.
+fn modality_root<'a>(asset: &'a BvpAsset, modality: usize) -> Result<(usize, &'a Format), Failure> {
+    return VolumeReader::new(&asset.bvp_file).modality_root(modality).map_err(|e| invalid(&e.to_string()));
+}
+
+/// Returns a description of the error of the last call on this thread that failed,
+/// or null if it succeeded. The string is valid until the next call on the thread.
+#[no_mangle]
+pub extern "C" fn bvp_last_error() -> *const c_char {
+    return LAST_ERROR.with(|e| match &*e.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null()
+    });
+}
+
+/// Opens an asset and stores it in `out_asset`. It has to be closed with `bvp_close`.
+/// * `path` - the archive file, or for `BVP_ARCHIVE_NONE` the folder or the manifest file
+/// * `archive` - the type of archive
+/// * `out_asset` - receives the asset
+/// # Safety
+/// `path` has to be a NUL terminated string and `out_asset` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn bvp_open(path: *const c_char, archive: BvpArchive, out_asset: *mut *mut BvpAsset) -> BvpStatus {
+    return call(|| {
+        let path = string_argument(path, "path")?;
+        if out_asset.is_null() {
+            return Err(invalid("`out_asset` is null"));
+        }
+        let bvp_file = BVPFile::open(Path::new(path), &archive_type(archive))
+            .map_err(|e| (BvpStatus::ReadFailed, e.to_string()))?;
+        *out_asset = Box::into_raw(Box::new(BvpAsset { bvp_file }));
+        return Ok(());
+    });
+}
+
+/// Closes an asset. Does nothing for null.
+/// # Safety
+/// `asset` has to be null or returned by `bvp_open` and not closed yet.
+#[no_mangle]
+pub unsafe extern "C" fn bvp_close(asset: *mut BvpAsset) {
+    if !asset.is_null() {
+        drop(Box::from_raw(asset));
+    }
+}
+
+/// Returns the number of modalities of an asset, or 0 for null.
+/// # Safety
+/// `asset` has to be null or an open asset.
+#[no_mangle]
+pub unsafe extern "C" fn bvp_get_modality_count(asset: *const BvpAsset) -> usize {
+    return match asset.as_ref() {
+        Some(a) => a.bvp_file.modalities.len(),
+        None => 0
+    };
+}
+
+/// Stores the dimensions of a modality, in voxels, in `out_dimensions`.
+/// * `modality` - index of the modality
+/// * `out_dimensions` - receives X, Y and Z
+/// # Safety
+/// `asset` has to be an open asset and `out_dimensions` point to three values.
+#[no_mangle]
+pub unsafe extern "C" fn bvp_get_modality_dimensions(asset: *const BvpAsset, modality: usize, out_dimensions: *mut u32) -> BvpStatus {
+    return call(|| {
+        let asset = asset_argument(asset)?;
+        if out_dimensions.is_null() {
+            return Err(invalid("`out_dimensions` is null"));
+        }
+        let (root, _) = modality_root(asset, modality)?;
+        let dimensions = asset.bvp_file.blocks[root].dimensions;
+        slice::from_raw_parts_mut(out_dimensions, 3).copy_from_slice(&dimensions.to_array());
+        return Ok(());
+    });
+}
+
+/// Writes the format of a modality as a JSON object, NUL terminated, to `buffer`,
+/// and stores its length without the NUL in `out_length`. Fails with
+/// `BVP_STATUS_BUFFER_TOO_SMALL` if it does not fit, `out_length` is set either way.
+/// * `modality` - index of the modality
+/// * `buffer` - receives the JSON, can be null if `capacity` is 0
+/// * `capacity` - size of the buffer in bytes
+/// * `out_length` - receives the length of the JSON, can be null
+/// # Safety
+/// `asset` has to be an open asset and `buffer` hold `capacity` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bvp_get_format_json(asset: *const BvpAsset, modality: usize, buffer: *mut c_char, capacity: usize, out_length: *mut usize) -> BvpStatus {
+    return call(|| {
+        let asset = asset_argument(asset)?;
+        let (_, format) = modality_root(asset, modality)?;
+        let json = format.to_json().stringify().map_err(|e| (BvpStatus::ReadFailed, e.to_string()))?;
+        if !out_length.is_null() {
+            *out_length = json.len();
+        }
+        if buffer.is_null() || capacity < json.len() + 1 {
+            return Err((BvpStatus::BufferTooSmall, format!("the format needs {} bytes", json.len() + 1)));
+        }
+        let buffer = slice::from_raw_parts_mut(buffer as *mut u8, json.len() + 1);
+        buffer[..json.len()].copy_from_slice(json.as_bytes());
+        buffer[json.len()] = 0;
+        return Ok(());
+    });
+}
+
+/// Stores the size in bytes of a region of a modality in `out_size`, as read by `bvp_read_region`.
+/// * `modality` - index of the modality
+/// * `start` - X, Y and Z of the first voxel of the region
+/// * `end` - X, Y and Z of the end of the region (exclusive)
+/// * `out_size` - receives the size
+/// # Safety
+/// `asset` has to be an open asset, `start` and `end` point to three values and `out_size` be valid.
+#[no_mangle]
+pub unsafe extern "C" fn bvp_get_region_size(asset: *const BvpAsset, modality: usize, start: *const u32, end: *const u32, out_size: *mut usize) -> BvpStatus {
+    return call(|| {
+        let asset = asset_argument(asset)?;
+        let (start, end) = (vector_argument(start, "start")?, vector_argument(end, "end")?);
+        if out_size.is_null() {
+            return Err(invalid("`out_size` is null"));
+        }
+        let (_, format) = modality_root(asset, modality)?;
+        let extent = end.checked_sub(&start).ok_or_else(|| invalid("`start` is past `end`"))?;
+        *out_size = format.checked_count_space(extent).ok_or_else(|| invalid("the region is too large"))?;
+        return Ok(());
+    });
+}
+
+/// Reconstructs a region of a modality into `buffer`, X changing fastest, see `bvp_get_region_size`
+/// for the size it needs. To read the whole volume, use the modality dimensions as `end`.
+/// * `modality` - index of the modality
+/// * `start` - X, Y and Z of the first voxel of the region
+/// * `end` - X, Y and Z of the end of the region (exclusive)
+/// * `buffer` - receives the voxels
+/// * `capacity` - size of the buffer in bytes
+/// # Safety
+/// `asset` has to be an open asset, `start` and `end` point to three values and `buffer` hold `capacity` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bvp_read_region(asset: *const BvpAsset, modality: usize, start: *const u32, end: *const u32, buffer: *mut u8, capacity: usize) -> BvpStatus {
+    return call(|| {
+        let asset = asset_argument(asset)?;
+        let (start, end) = (vector_argument(start, "start")?, vector_argument(end, "end")?);
+        if buffer.is_null() {
+            return Err(invalid("`buffer` is null"));
+        }
+        let block = VolumeReader::new(&asset.bvp_file).read_region(modality, start, end)
+            .map_err(|e| (BvpStatus::ReadFailed, e.to_string()))?;
+        let data = block.data.unwrap_or_default();
+        if capacity < data.len() {
+            return Err((BvpStatus::BufferTooSmall, format!("the region needs {} bytes", data.len())));
+        }
+        slice::from_raw_parts_mut(buffer, data.len()).copy_from_slice(&data);
+        return Ok(());
+    });
+}
+
+/// Writes a volume as an asset with a single modality, split into blocks of the same dimensions.
+/// Blocks with the same data are stored once.
+/// * `path` - the archive file, or for `BVP_ARCHIVE_NONE` the folder to write to
+/// * `archive` - the type of archive
+/// * `data` - the voxels, X changing fastest
+/// * `size` - size of the data in bytes
+/// * `dimensions` - X, Y and Z of the volume
+/// * `block_dimensions` - X, Y and Z of the blocks
+/// * `format` - format of the voxels, as a JSON object or a shorthand such as `u8`, `u16`, `f32` or `u8x3`
+/// * `compression` - compression of the block files
+/// # Safety
+/// `path` and `format` have to be NUL terminated strings, `data` hold `size` bytes and
+/// `dimensions` and `block_dimensions` point to three values.
+#[no_mangle]
+pub unsafe extern "C" fn bvp_write_volume(
+    path: *const c_char, archive: BvpArchive, data: *const u8, size: usize,
+    dimensions: *const u32, block_dimensions: *const u32, format: *const c_char, compression: BvpCompression
+) -> BvpStatus {
+    return call(|| {
+        let path = string_argument(path, "path")?;
+        let format = parse_format(string_argument(format, "format")?)?;
+        let (dimensions, block_dimensions) = (vector_argument(dimensions, "dimensions")?, vector_argument(block_dimensions, "block_dimensions")?);
+        if data.is_null() && size > 0 {
+            return Err(invalid("`data` is null"));
+        }
+        let data = if size == 0 { Vec::new() } else { slice::from_raw_parts(data, size).to_vec() };
+        let compression = match compression {
+            BvpCompression::None => CompressionType::None,
+            BvpCompression::Lz4s => CompressionType::LZ4S
+        };
+        return VolumeWriter::new(block_dimensions)
+            .with_compression(compression)
+            .write(Path::new(path), &archive_type(archive), data, dimensions, format)
+            .map_err(|e| (BvpStatus::WriteFailed, e.to_string()));
+    });
+}
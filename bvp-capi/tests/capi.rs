@@ -0,0 +1,86 @@
+//! Writes and reads assets through the exported functions, as a C application would.
+
+use std::{env, ffi::{CStr, CString}, fs, ptr};
+
+use bvp_capi::*;
+
+fn last_error() -> String {
+    let message = bvp_last_error();
+    assert!(!message.is_null());
+    return unsafe { CStr::from_ptr(message) }.to_string_lossy().to_string();
+}
+
+#[test]
+fn write_open_and_read_region() {
+    let dir = env::temp_dir().join(format!("bvp-capi-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let dimensions = [12u32, 10, 6];
+    let volume: Vec<u8> = (0..12 * 10 * 6).map(|i| (i % 13) as u8).collect();
+
+    for (name, archive, compression) in [
+        ("asset.saf", BvpArchive::Saf, BvpCompression::Lz4s),
+        ("asset.zip", BvpArchive::Zip, BvpCompression::None),
+        ("folder", BvpArchive::None, BvpCompression::Lz4s)
+    ] {
+        let path = CString::new(dir.join(name).to_string_lossy().to_string()).unwrap();
+        let format = CString::new("u8").unwrap();
+        let status = unsafe {
+            bvp_write_volume(path.as_ptr(), archive, volume.as_ptr(), volume.len(), dimensions.as_ptr(), [4u32, 4, 4].as_ptr(), format.as_ptr(), compression)
+        };
+        assert_eq!(status, BvpStatus::Ok, "{}", last_error());
+
+        let mut asset = ptr::null_mut();
+        let status = unsafe { bvp_open(path.as_ptr(), archive, &mut asset) };
+        assert_eq!(status, BvpStatus::Ok, "{}", last_error());
+        assert!(bvp_last_error().is_null());
+        assert_eq!(unsafe { bvp_get_modality_count(asset) }, 1);
+
+        let mut read_dimensions = [0u32; 3];
+        assert_eq!(unsafe { bvp_get_modality_dimensions(asset, 0, read_dimensions.as_mut_ptr()) }, BvpStatus::Ok);
+        assert_eq!(read_dimensions, dimensions);
+
+        let mut length = 0;
+        assert_eq!(unsafe { bvp_get_format_json(asset, 0, ptr::null_mut(), 0, &mut length) }, BvpStatus::BufferTooSmall);
+        let mut json = vec![0u8; length + 1];
+        assert_eq!(unsafe { bvp_get_format_json(asset, 0, json.as_mut_ptr() as *mut _, json.len(), &mut length) }, BvpStatus::Ok);
+        assert!(CStr::from_bytes_with_nul(&json).unwrap().to_str().unwrap().contains("\"mono\""));
+
+        let (start, end) = ([3u32, 2, 1], [9u32, 7, 5]);
+        let mut size = 0;
+        assert_eq!(unsafe { bvp_get_region_size(asset, 0, start.as_ptr(), end.as_ptr(), &mut size) }, BvpStatus::Ok);
+        assert_eq!(size, 6 * 5 * 4);
+        let mut region = vec![0u8; size];
+        assert_eq!(unsafe { bvp_read_region(asset, 0, start.as_ptr(), end.as_ptr(), region.as_mut_ptr(), size - 1) }, BvpStatus::BufferTooSmall);
+        assert_eq!(unsafe { bvp_read_region(asset, 0, start.as_ptr(), end.as_ptr(), region.as_mut_ptr(), size) }, BvpStatus::Ok, "{}", last_error());
+        let mut expected = Vec::new();
+        for z in start[2]..end[2] {
+            for y in start[1]..end[1] {
+                for x in start[0]..end[0] {
+                    expected.push(volume[(x + y * 12 + z * 120) as usize]);
+                }
+            }
+        }
+        assert_eq!(region, expected);
+
+        assert_eq!(unsafe { bvp_get_modality_dimensions(asset, 1, read_dimensions.as_mut_ptr()) }, BvpStatus::InvalidArgument);
+        unsafe { bvp_close(asset) };
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn invalid_arguments_are_reported() {
+    let mut asset = ptr::null_mut();
+    assert_eq!(unsafe { bvp_open(ptr::null(), BvpArchive::Saf, &mut asset) }, BvpStatus::InvalidArgument);
+    assert!(last_error().contains("path"));
+    let path = CString::new("/nonexistent/asset.saf").unwrap();
+    assert_eq!(unsafe { bvp_open(path.as_ptr(), BvpArchive::Saf, &mut asset) }, BvpStatus::ReadFailed);
+    assert!(asset.is_null());
+    assert_eq!(unsafe { bvp_get_modality_count(ptr::null()) }, 0);
+
+    let format = CString::new("u9").unwrap();
+    let status = unsafe {
+        bvp_write_volume(path.as_ptr(), BvpArchive::Saf, [0u8].as_ptr(), 1, [1u32, 1, 1].as_ptr(), [1u32, 1, 1].as_ptr(), format.as_ptr(), BvpCompression::None)
+    };
+    assert_eq!(status, BvpStatus::InvalidArgument);
+}
@@ -0,0 +1,188 @@
+//! Benchmarks of the stages of the conversion pipeline, run with `cargo bench`.
+//!
+//! Each benchmark is run several times and the median and fastest run are printed,
+//! together with the throughput in MiB/s of voxel data. `cargo bench -- <name>` only runs
+//! benchmarks whose name contains `<name>`. `BVP_BENCH_ITERATIONS` sets the number of runs,
+//! `BVP_BENCH_VOLUME` the edge length of the volume of the end-to-end conversion (512 by default).
+//!
+//! `cargo test` runs every benchmark once, with a 64³ conversion, to check that they still work.
+
+use std::{env, fs, hint::black_box, process::Command, str::FromStr, time::{Duration, Instant}};
+
+use tinyjson::JsonValue;
+use xxhash_rust::xxh3;
+
+use bvp::{block::Block, compressions::CompressionType, dedup::{DedupResult, ShardedBlockMap}, formats::Format, vector3::Vector3};
+
+/// Returns a `u8` volume that looks like a scan: smooth values with some noise,
+/// and a zero background around a sphere, so there are repeated blocks to deduplicate.
+/// * `size` - edge length of the volume
+fn synthetic_volume(size: u32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(size as usize * size as usize * size as usize);
+    let center = size as f32 / 2.0;
+    let mut noise: u32 = 0x2545f491;
+    for z in 0..size {
+        for y in 0..size {
+            for x in 0..size {
+                let (dx, dy, dz) = (x as f32 - center, y as f32 - center, z as f32 - center);
+                let distance = (dx * dx + dy * dy + dz * dz).sqrt();
+                if distance > center * 0.8 {
+                    data.push(0);
+                    continue;
+                }
+                noise ^= noise << 13;
+                noise ^= noise >> 17;
+                noise ^= noise << 5;
+                data.push((255.0 * (1.0 - distance / center)) as u8 ^ (noise & 0x3) as u8);
+            }
+        }
+    }
+    return data;
+}
+
+fn u8_format() -> Format {
+    let json = JsonValue::from_str(
+        r#"{"family": "mono", "type": "u", "count": 1, "size": 1, "microblockDimensions": [1, 1, 1], "microblockSize": 1}"#
+    ).unwrap();
+    return Format::from_json(&json).unwrap();
+}
+
+struct Bench {
+    filter: Option<String>,
+    iterations: usize,
+    /// Run by `cargo test` rather than `cargo bench`.
+    test: bool
+}
+
+impl Bench {
+    /// Runs a benchmark and prints its timings.
+    /// * `name` - name of the benchmark
+    /// * `bytes` - number of bytes processed by one run, for the throughput
+    /// * `run` - one run of the benchmark
+    fn run<F: FnMut()>(&self, name: &str, bytes: usize, mut run: F) {
+        if let Some(filter) = &self.filter {
+            if !name.contains(filter.as_str()) {
+                return;
+            }
+        }
+        let mut times: Vec<Duration> = (0..self.iterations).map(|_| {
+            let start = Instant::now();
+            run();
+            return start.elapsed();
+        }).collect();
+        times.sort();
+        let median = times[times.len() / 2];
+        let mib_per_second = bytes as f64 / (1024.0 * 1024.0) / median.as_secs_f64();
+        println!(
+            "{:<24} median {:>10.3} ms   fastest {:>10.3} ms   {:>9.1} MiB/s",
+            name, median.as_secs_f64() * 1000.0, times[0].as_secs_f64() * 1000.0, mib_per_second
+        );
+    }
+}
+
+/// Splits a 256³ volume into blocks of 64³, as the pipeline does before compressing them.
+fn bench_block_extraction(bench: &Bench) {
+    let format = u8_format();
+    let size = 256;
+    let volume = Block::new(0, Vector3::splat(size), Some(0), Some(synthetic_volume(size)));
+    let block_size = 64;
+    bench.run("block_extraction", (size * size * size) as usize, || {
+        for z in (0..size).step_by(block_size as usize) {
+            for y in (0..size).step_by(block_size as usize) {
+                for x in (0..size).step_by(block_size as usize) {
+                    let start = Vector3::from_xyz(x, y, z);
+                    black_box(volume.get_data_in_range(start, start + block_size, &format).unwrap());
+                }
+            }
+        }
+    });
+}
+
+/// Compresses and decompresses a single 64³ block with LZ4S at the fastest and the default level.
+fn bench_lz4s(bench: &Bench) {
+    let block = synthetic_volume(64);
+    let compressed = CompressionType::LZ4S.compress(block.clone());
+    bench.run("lz4s_compress", block.len(), || {
+        black_box(CompressionType::LZ4S.compress(block.clone()));
+    });
+    bench.run("lz4s_compress_fastest", block.len(), || {
+        black_box(CompressionType::LZ4S.compress_with_level(block.clone(), 1));
+    });
+    bench.run("lz4s_decompress", block.len(), || {
+        black_box(CompressionType::LZ4S.decompress(&compressed, block.len()));
+    });
+}
+
+/// Hashes the 16³ blocks of a 256³ volume and looks them up in the deduplication map.
+fn bench_dedup(bench: &Bench) {
+    let format = u8_format();
+    let size = 256;
+    let block_size = 16;
+    let volume = Block::new(0, Vector3::splat(size), Some(0), Some(synthetic_volume(size)));
+    let mut blocks = Vec::new();
+    for z in (0..size).step_by(block_size as usize) {
+        for y in (0..size).step_by(block_size as usize) {
+            for x in (0..size).step_by(block_size as usize) {
+                let start = Vector3::from_xyz(x, y, z);
+                blocks.push(volume.get_data_in_range(start, start + block_size, &format).unwrap().data.unwrap());
+            }
+        }
+    }
+    bench.run("dedup_map", (size * size * size) as usize, || {
+        let map = ShardedBlockMap::new(1);
+        let mut duplicates = 0;
+        for (i, data) in blocks.iter().enumerate() {
+            let hash = xxh3::xxh3_64(data);
+            if let Ok(DedupResult::Existing(_)) = map.find_or_insert(hash, i, |entry| blocks[entry.key] == *data) {
+                duplicates += 1;
+            }
+        }
+        black_box(duplicates);
+    });
+}
+
+/// Converts a synthetic volume with `raw2bvp` to a SAF archive with LZ4S blocks.
+fn bench_conversion(bench: &Bench) {
+    let size = match env::var("BVP_BENCH_VOLUME") {
+        Ok(s) => s.parse::<u32>().expect("BVP_BENCH_VOLUME has to be a number"),
+        Err(_) if bench.test => 64,
+        Err(_) => 512
+    };
+    let name = format!("convert_{}", size);
+    if bench.filter.as_ref().map_or(false, |filter| !name.contains(filter.as_str())) {
+        return;
+    }
+    let dir = env::temp_dir().join(format!("bvp-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("input.raw"), synthetic_volume(size)).unwrap();
+    let dimensions = format!("{}x{}x{}", size, size, size);
+    bench.run(&name, (size * size * size) as usize, || {
+        let result = Command::new(env!("CARGO_BIN_EXE_raw2bvp"))
+            .current_dir(&dir)
+            .args([
+                "--input-file", "input.raw", "--output-file", "output.saf",
+                "--dimensions", &dimensions, "--block-dimensions", "64x64x64", "--format", "u8",
+                "--archive", "SAF", "--compression", "LZ4S", "--no-progress", "-q"
+            ])
+            .output().unwrap();
+        assert!(result.status.success(), "raw2bvp failed: {}", String::from_utf8_lossy(&result.stderr));
+    });
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`, everything else that is not a flag filters the benchmarks.
+    let filter = env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let test = !env::args().any(|arg| arg == "--bench");
+    let iterations = match env::var("BVP_BENCH_ITERATIONS") {
+        Ok(s) => s.parse::<usize>().expect("BVP_BENCH_ITERATIONS has to be a number").max(1),
+        Err(_) if test => 1,
+        Err(_) => 5
+    };
+    let bench = Bench { filter, iterations, test };
+
+    bench_block_extraction(&bench);
+    bench_lz4s(&bench);
+    bench_dedup(&bench);
+    bench_conversion(&bench);
+}
@@ -0,0 +1,34 @@
+//! Records the git commit the crate is built from in `BVP_GIT_HASH`, for the `generator` of written assets.
+//! Builds outside of a git checkout, such as from a published crate, leave it unset.
+
+use std::path::Path;
+use std::process::Command;
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8(output.stdout).ok()?;
+    return Some(text.trim().to_string());
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    let hash = match git(&["rev-parse", "--short", "HEAD"]) {
+        Some(hash) if !hash.is_empty() => hash,
+        _ => return
+    };
+    println!("cargo:rustc-env=BVP_GIT_HASH={}", hash);
+    // Rebuilt when a commit is made or another branch is checked out.
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        let git_dir = Path::new(&git_dir);
+        println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+        if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            // Refs that were packed are only in `packed-refs`.
+            let ref_file = git_dir.join(head_ref);
+            let ref_file = if ref_file.exists() { ref_file } else { git_dir.join("packed-refs") };
+            println!("cargo:rerun-if-changed={}", ref_file.display());
+        }
+    }
+}
@@ -0,0 +1,16 @@
+#![no_main]
+
+use bvp::compressions::lz4s;
+use libfuzzer_sys::fuzz_target;
+
+/// Decoded sizes are limited, so that the fuzzer does not only find large allocations.
+const MAX_SIZE: usize = 1 << 20;
+
+// The first 4 bytes are the decoded size (little endian), the rest is the compressed data.
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 4 {
+        return;
+    }
+    let size = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize % (MAX_SIZE + 1);
+    let _ = lz4s::decompress_lz4s(&data[4..].to_vec(), size);
+});
@@ -0,0 +1,19 @@
+#![no_main]
+
+use std::sync::Arc;
+
+use bvp::{bvpfile::BVPFile, file::File};
+use libfuzzer_sys::fuzz_target;
+
+// The seeds refer to the blocks `blocks/block_1.raw` to `blocks/block_8.raw`,
+// so that mutated manifests also reach the block data.
+fuzz_target!(|data: &[u8]| {
+    let manifest = match std::str::from_utf8(data) {
+        Ok(m) => m,
+        Err(_) => return
+    };
+    let files: Vec<File> = (1..=8)
+        .map(|i| File::new(format!("blocks/block_{}.raw", i), Arc::new(vec![0; 64]), None))
+        .collect();
+    let _ = BVPFile::from_manifest(manifest, &files);
+});
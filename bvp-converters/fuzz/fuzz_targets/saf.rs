@@ -0,0 +1,8 @@
+#![no_main]
+
+use bvp::{archives::saf, bytes::Bytes};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = saf::from_saf_archive(&Bytes::from(data.to_vec()));
+});
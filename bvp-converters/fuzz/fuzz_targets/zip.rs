@@ -0,0 +1,8 @@
+#![no_main]
+
+use bvp::{archives::zip, bytes::Bytes};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = zip::from_zip_archive(&Bytes::from(data.to_vec()));
+});
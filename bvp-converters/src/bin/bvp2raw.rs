@@ -0,0 +1,10 @@
+//! `bvp2raw`, the same as `bvp unpack`.
+
+#[path = "../cli/mod.rs"]
+mod cli;
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    return cli::main(Some("unpack"));
+}
@@ -0,0 +1,10 @@
+//! `bvp-diff`, the same as `bvp diff`.
+
+#[path = "../cli/mod.rs"]
+mod cli;
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    return cli::main(Some("diff"));
+}
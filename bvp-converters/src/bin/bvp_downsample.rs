@@ -0,0 +1,10 @@
+//! `bvp-downsample`, the same as `bvp downsample`.
+
+#[path = "../cli/mod.rs"]
+mod cli;
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    return cli::main(Some("downsample"));
+}
@@ -0,0 +1,10 @@
+//! `bvp-extract`, the same as `bvp extract`.
+
+#[path = "../cli/mod.rs"]
+mod cli;
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    return cli::main(Some("extract"));
+}
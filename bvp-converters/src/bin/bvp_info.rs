@@ -0,0 +1,10 @@
+//! `bvp-info`, the same as `bvp info`.
+
+#[path = "../cli/mod.rs"]
+mod cli;
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    return cli::main(Some("info"));
+}
@@ -0,0 +1,10 @@
+//! `bvp-merge`, the same as `bvp merge`.
+
+#[path = "../cli/mod.rs"]
+mod cli;
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    return cli::main(Some("merge"));
+}
@@ -0,0 +1,10 @@
+//! `bvp-meta`, the same as `bvp meta`.
+
+#[path = "../cli/mod.rs"]
+mod cli;
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    return cli::main(Some("meta"));
+}
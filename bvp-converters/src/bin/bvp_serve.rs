@@ -0,0 +1,10 @@
+//! `bvp-serve`, the same as `bvp serve`.
+
+#[path = "../cli/mod.rs"]
+mod cli;
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    return cli::main(Some("serve"));
+}
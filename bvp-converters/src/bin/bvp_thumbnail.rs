@@ -0,0 +1,10 @@
+//! `bvp-thumbnail`, the same as `bvp thumbnail`.
+
+#[path = "../cli/mod.rs"]
+mod cli;
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    return cli::main(Some("thumbnail"));
+}
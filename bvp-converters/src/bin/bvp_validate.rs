@@ -0,0 +1,10 @@
+//! `bvp-validate`, the same as `bvp validate`.
+
+#[path = "../cli/mod.rs"]
+mod cli;
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    return cli::main(Some("validate"));
+}
@@ -0,0 +1,10 @@
+//! `raw2bvp`, the same as `bvp pack`.
+
+#[path = "../cli/mod.rs"]
+mod cli;
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    return cli::main(Some("pack"));
+}
@@ -0,0 +1,48 @@
+use std::path::Path;
+
+use bvp::archives::ArchiveEnum;
+use bvp::detect;
+
+use super::{pack, unpack};
+use super::error::CliError;
+
+static HELP: &str = "bvp convert\n------------\n Usage: bvp convert <input> <output> [options]\n Inputs that are BVP assets are unpacked into the output folder, as with `bvp unpack <input> --output-dir <output>`.\n Other inputs are packed into the output, as with `bvp pack --input-file <input> --output-file <output>`,\n in a SAF or ZIP archive if the output ends with `.saf` or `.zip`, and unarchived otherwise.\n The options are those of `bvp pack` or `bvp unpack`, such as `--dimensions` and `--format` for raw inputs.\n This message can be viewed with flag `--help`.";
+
+/// Runs the command.
+/// * `arguments` - the command line arguments after the name of the command
+pub fn run(arguments: Vec<String>) -> Result<(), CliError> {
+    if arguments.iter().any(|a| a == "--help") {
+        println!("{}", HELP);
+        return Ok(());
+    }
+    let mut arguments = arguments.into_iter();
+    let input = arguments.next().ok_or("Missing input file")?;
+    let output = arguments.next().ok_or("Missing output file")?;
+    let options: Vec<String> = arguments.collect();
+
+    if let Ok(archive) = detect::detect_archive(Path::new(&input)) {
+        let archive = match archive {
+            ArchiveEnum::SAF => "SAF",
+            ArchiveEnum::ZIP => "ZIP",
+            ArchiveEnum::None => "None"
+        };
+        let mut unpack_arguments = vec![input, archive.to_string(), "--output-dir".to_string(), output];
+        unpack_arguments.extend(options);
+        return unpack::run(unpack_arguments);
+    }
+
+    let mut pack_arguments = vec!["--input-file".to_string(), input, "--output-file".to_string(), output.clone()];
+    if !options.iter().any(|o| o == "--archive" || o.starts_with("--archive=")) {
+        let lowercase = output.to_lowercase();
+        let archive = if lowercase.ends_with(".saf") {
+            "SAF"
+        } else if lowercase.ends_with(".zip") {
+            "ZIP"
+        } else {
+            "None"
+        };
+        pack_arguments.extend(["--archive".to_string(), archive.to_string()]);
+    }
+    pack_arguments.extend(options);
+    return pack::run(pack_arguments);
+}
@@ -0,0 +1,257 @@
+use std::{path::Path};
+
+use bvp::archives::ArchiveEnum;
+use bvp::detect;
+use bvp::json_aux;
+use bvp::bvpfile::BVPFile;
+use bvp::log::{self, Level};
+use bvp::reader::VolumeReader;
+use bvp::vector3::Vector3;
+
+use super::error::{CliError, ErrorKind};
+
+static HELP: &str = "bvp-diff\n------------\n Usage: bvp-diff <first_file> <second_file> [options]\n Options:\n  --archive TYPE - archive type of both files (SAF, ZIP or None, default: detected from each file)\n  --first-archive TYPE, --second-archive TYPE - archive type of a single file\n  --ignore-metadata - only compare voxel data\n  -v, -vv, -vvv / --verbose - print more diagnostics to stderr (info, debug, trace)\n  -q / --quiet - print only errors\n Fails if the assets differ.\n This message can be viewed with flag `--help`.";
+
+/// Voxel differences between the same modality of two assets.
+struct VoxelDiff {
+    voxels: usize,
+    differing_voxels: usize,
+    blocks: usize,
+    differing_blocks: usize,
+    max_abs_diff: f64,
+    rmse: f64
+}
+
+/// Adds a line to `differences` if the two values are not the same.
+fn compare<T: PartialEq + std::fmt::Debug>(differences: &mut Vec<String>, field: &str, first: &T, second: &T) {
+    if first != second {
+        differences.push(format!("{}: {:?} != {:?}", field, first, second));
+    }
+}
+
+/// Compares asset metadata, modalities and formats, and returns a line for each difference.
+fn metadata_differences(first: &BVPFile, second: &BVPFile) -> Vec<String> {
+    let mut differences = Vec::new();
+    let (a, b) = (&first.asset, &second.asset);
+    compare(&mut differences, "asset.version", &a.version, &b.version);
+    compare(&mut differences, "asset.name", &a.name, &b.name);
+    compare(&mut differences, "asset.generator", &a.generator, &b.generator);
+    compare(&mut differences, "asset.author", &a.author, &b.author);
+    compare(&mut differences, "asset.description", &a.description, &b.description);
+    compare(&mut differences, "asset.copyright", &a.copyright, &b.copyright);
+    compare(&mut differences, "asset.acquisitionTime", &a.acquisition_time, &b.acquisition_time);
+    compare(&mut differences, "asset.creationTime", &a.creation_time, &b.creation_time);
+    compare(&mut differences, "asset.extensionsUsed", &a.extensions_used, &b.extensions_used);
+    compare(&mut differences, "asset.extensionsRequired", &a.extensions_required, &b.extensions_required);
+
+    compare(&mut differences, "modalities (count)", &first.modalities.len(), &second.modalities.len());
+    for (i, (a, b)) in first.modalities.iter().zip(second.modalities.iter()).enumerate() {
+        compare(&mut differences, &format!("modalities[{}].name", i), &a.name, &b.name);
+        compare(&mut differences, &format!("modalities[{}].description", i), &a.description, &b.description);
+        compare(&mut differences, &format!("modalities[{}].semanticType", i), &a.semantic_type, &b.semantic_type);
+        compare(&mut differences, &format!("modalities[{}].volumeSize", i), &a.volume_size.to_string(), &b.volume_size.to_string());
+        compare(
+            &mut differences, &format!("modalities[{}].voxelSize", i),
+            &a.voxel_size.map(|v| v.to_string()), &b.voxel_size.map(|v| v.to_string())
+        );
+    }
+
+    compare(&mut differences, "formats (count)", &first.formats.len(), &second.formats.len());
+    for (i, (a, b)) in first.formats.iter().zip(second.formats.iter()).enumerate() {
+        // JSON objects compare equal regardless of key order, and are printed with sorted keys.
+        let (a, b) = (a.to_json(), b.to_json());
+        if a != b {
+            differences.push(format!(
+                "formats[{}]: {} != {}", i, json_aux::try_canonical_string(&a).unwrap_or_default(),
+                json_aux::try_canonical_string(&b).unwrap_or_default()
+            ));
+        }
+    }
+
+    compare(&mut differences, "blocks (count)", &first.blocks.len(), &second.blocks.len());
+    return differences;
+}
+
+/// Reconstructs the same modality of both assets and compares their voxels.
+/// Blocks are counted as the placements in the root block of the first asset.
+/// Modalities that cannot be compared are errors of kind `Verification`, assets that cannot be read of their own kind.
+fn voxel_differences(first: &BVPFile, second: &BVPFile, modality_index: usize) -> Result<VoxelDiff, CliError> {
+    let first_reader = VolumeReader::new(first);
+    let second_reader = VolumeReader::new(second);
+    let (first_root, format) = first_reader.modality_root(modality_index)?;
+    let (second_root, second_format) = second_reader.modality_root(modality_index)?;
+
+    let dimensions = first.blocks[first_root].dimensions;
+    if dimensions != second.blocks[second_root].dimensions {
+        return Err(CliError::verification(format!(
+            "dimensions differ ({} != {}), voxels cannot be compared",
+            dimensions, second.blocks[second_root].dimensions
+        )));
+    }
+    if format.microblock_size != second_format.microblock_size || format.microblock_dimensions != second_format.microblock_dimensions {
+        return Err(CliError::verification("formats differ, voxels cannot be compared"));
+    }
+
+    let first_volume = first_reader.read_modality(modality_index)?;
+    let second_volume = second_reader.read_modality(modality_index)?;
+    let first_data = first_volume.data.as_ref().unwrap();
+    let second_data = second_volume.data.as_ref().unwrap();
+
+    let microblock_size = format.microblock_size as usize;
+    let component_count = format.component_count() as usize;
+
+    let mut differing = vec![false; first_data.len() / microblock_size];
+    let mut max_abs_diff: f64 = 0.0;
+    let mut squared_sum: f64 = 0.0;
+    let mut components = 0usize;
+    for (i, (a, b)) in first_data.chunks(microblock_size).zip(second_data.chunks(microblock_size)).enumerate() {
+        if a == b {
+            components += component_count;
+            continue;
+        }
+        differing[i] = true;
+        // Block-compressed microblocks can only be compared as bytes.
+        let (a, b) = match (format.voxel_values(a), format.voxel_values(b)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => continue
+        };
+        for (a, b) in a.iter().zip(b.iter()) {
+            let diff = (a - b).abs();
+            max_abs_diff = max_abs_diff.max(diff);
+            squared_sum += diff * diff;
+            components += 1;
+        }
+    }
+
+    // A block differs if any of its microblocks differs.
+    let microblock_amount = dimensions / format.microblock_dimensions;
+    let mut differing_blocks = 0;
+    let placements = &first.blocks[first_root].placements;
+    for placement in placements {
+        let start = placement.position / format.microblock_dimensions;
+        let extent = first.blocks[placement.block].dimensions / format.microblock_dimensions;
+        let end = (start + extent).min(&microblock_amount);
+        let mut block_differs = false;
+        'block: for z in start.z..end.z {
+            for y in start.y..end.y {
+                let row = Vector3::linear_index(Vector3::from_xyz(start.x, y, z), microblock_amount);
+                if differing[row..row + (end.x - start.x) as usize].contains(&true) {
+                    block_differs = true;
+                    break 'block;
+                }
+            }
+        }
+        if block_differs {
+            differing_blocks += 1;
+        }
+    }
+
+    return Ok(VoxelDiff {
+        voxels: differing.len(),
+        differing_voxels: differing.iter().filter(|d| **d).count(),
+        blocks: placements.len(),
+        differing_blocks,
+        max_abs_diff,
+        rmse: if components > 0 { (squared_sum / components as f64).sqrt() } else { 0.0 }
+    });
+}
+
+fn parse_archive(value: Option<String>) -> Result<ArchiveEnum, CliError> {
+    return match value {
+        Some(a) => Ok(ArchiveEnum::from_string(a)?),
+        None => Err(CliError::config("Missing archive type"))
+    };
+}
+
+/// Runs the command.
+/// * `arguments` - the command line arguments after the name of the command
+pub fn run(arguments: Vec<String>) -> Result<(), CliError> {
+    let mut positional: Vec<String> = Vec::new();
+    let mut first_archive = None;
+    let mut second_archive = None;
+    let mut ignore_metadata = false;
+    let mut verbosity = 0;
+    let mut arguments_iter = arguments.into_iter();
+    while let Some(arg) = arguments_iter.next() {
+        if arg == "--help" {
+            println!("{}", HELP);
+            return Ok(());
+        } else if arg == "--archive" {
+            let archive = parse_archive(arguments_iter.next())?;
+            first_archive = Some(archive);
+            second_archive = Some(archive);
+        } else if arg == "--first-archive" {
+            first_archive = Some(parse_archive(arguments_iter.next())?);
+        } else if arg == "--second-archive" {
+            second_archive = Some(parse_archive(arguments_iter.next())?);
+        } else if arg == "--ignore-metadata" {
+            ignore_metadata = true;
+        } else if let Some(change) = log::verbosity_flag(&arg) {
+            verbosity = if change < 0 { change } else { verbosity.max(0) + change };
+        } else if arg.starts_with('-') {
+            return Err(CliError::config(format!("Unknown option `{}`", arg)));
+        } else {
+            positional.push(arg);
+        }
+    }
+    log::set_max_level(Level::from_verbosity(verbosity));
+
+    if positional.len() != 2 {
+        return Err(CliError::config("Expected two input files"));
+    }
+    let open = |path: &str, archive: Option<ArchiveEnum>| {
+        let path = Path::new(path);
+        let archive = match archive {
+            Some(a) => a,
+            None => detect::detect_archive(path)?
+        };
+        return BVPFile::open(path, &archive).map_err(|x| CliError::from(x).in_file(path.display()));
+    };
+    let first = open(&positional[0], first_archive)?;
+    let second = open(&positional[1], second_archive)?;
+
+    let mut different = false;
+    if !ignore_metadata {
+        let differences = metadata_differences(&first, &second);
+        if differences.is_empty() {
+            println!("Metadata: same");
+        } else {
+            println!("Metadata: {} differences", differences.len());
+            for difference in &differences {
+                println!("  {}", difference);
+            }
+            different = true;
+        }
+    }
+
+    let modalities = first.modalities.len().min(second.modalities.len());
+    for modality_index in 0..modalities {
+        let name = first.modalities[modality_index].name.clone().unwrap_or(format!("{}", modality_index));
+        match voxel_differences(&first, &second, modality_index) {
+            Ok(diff) => {
+                if diff.differing_voxels > 0 {
+                    different = true;
+                }
+                println!("Modality {} ({}):", modality_index, name);
+                println!("  differing voxels: {} of {}", diff.differing_voxels, diff.voxels);
+                println!("  differing blocks: {} of {}", diff.differing_blocks, diff.blocks);
+                println!("  max abs diff:     {}", diff.max_abs_diff);
+                println!("  RMSE:             {:.6}", diff.rmse);
+            },
+            Err(e) if e.kind == ErrorKind::Verification => {
+                different = true;
+                println!("Modality {} ({}): {}", modality_index, name, e.message);
+            },
+            Err(e) => return Err(e)
+        }
+    }
+    if first.modalities.len() != second.modalities.len() {
+        different = true;
+        println!("Only one asset has modalities {} and up", modalities);
+    }
+
+    if different {
+        return Err(CliError::verification("Assets differ"));
+    }
+    return Ok(());
+}
@@ -0,0 +1,306 @@
+use std::{collections::{HashMap, HashSet}, path::Path};
+
+use xxhash_rust::xxh3;
+
+use bvp::archives::ArchiveEnum;
+use bvp::detect;
+use bvp::block::Block;
+use bvp::bytes::Bytes;
+use bvp::bvpfile::BVPFile;
+use bvp::compressions::CompressionType;
+use bvp::downsample::{self, DownsampleFilter};
+use bvp::errors::ReaderError;
+use bvp::extensions::Extension;
+use bvp::file::File;
+use bvp::formats::Format;
+use bvp::layout::{self, VoxelLayout};
+use bvp::log::{self, Level};
+use bvp::modality::Modality;
+use bvp::placement::Placement;
+use bvp::reader::VolumeReader;
+use bvp::transform::{self, Transform};
+use bvp::vector3::Vector3;
+use bvp::version;
+use bvp::{log_debug, log_info};
+
+use super::error::CliError;
+
+static HELP: &str = "bvp-downsample\n------------\n Usage: bvp-downsample <input_file> [<archive type>] --output PATH [options]\n Options:\n  --output PATH - the downsampled asset\n  --output-archive TYPE - archive type of the downsampled asset (SAF, ZIP or None, default: the same as the input)\n  --factor N - how many voxels along each axis become one, such as 2, 4 or 8 (default 2)\n  --filter mean|min|max|nearest - how the voxels are combined (default `mean`, use `nearest` for segmentation masks)\n  --modality N - downsample only this modality (default: all of them)\n  --block-dimensions XxYxZ - dimensions of the blocks of the downsampled asset (default: the ones of the input)\n  --compression LZ4S|RAW - compression of the block files (default `LZ4S`)\n  --generator TEXT - generator of the downsampled asset (default: `bvp-downsample` with the version of the tool)\n  -v, -vv, -vvv / --verbose - print more diagnostics to stderr (info, debug, trace)\n  -q / --quiet - print only errors\n The input is read in slabs of blocks, so only a few slabs are in memory at once.\n This message can be viewed with flag `--help`.";
+
+/// Builds the downsampled asset while the modalities are added one after another.
+struct Downsampler<'a> {
+    source: &'a BVPFile,
+    bvp_file: BVPFile,
+    factor: u32,
+    filter: DownsampleFilter,
+    compression: CompressionType,
+    /// Block indices of data blocks, by format index and hash of the decoded data.
+    dedup_map: HashMap<(usize, u64), Vec<usize>>,
+    duplicates: usize
+}
+
+impl<'a> Downsampler<'a> {
+    /// Returns the index of the format in the downsampled asset, adding the format if there is no equal one yet.
+    /// * `format` - format of a modality
+    fn add_format(&mut self, format: &Format) -> usize {
+        // The reader returns data in linear order, which is how the downsampled blocks are stored.
+        let mut format = format.clone();
+        layout::set_layout(&mut format, VoxelLayout::Linear);
+        let json = format.to_json();
+        if let Some(i) = self.bvp_file.formats.iter().position(|f| f.to_json() == json) {
+            return i;
+        }
+        self.bvp_file.formats.push(format);
+        return self.bvp_file.formats.len() - 1;
+    }
+
+    /// Stores a block unless a block with the same data is stored already, and returns its index.
+    /// * `format_index` - index of the format in the downsampled asset
+    /// * `dimensions` - dimensions of the block
+    /// * `data` - decoded data of the block
+    fn add_block(&mut self, format_index: usize, dimensions: Vector3<u32>, data: Vec<u8>) -> Result<usize, CliError> {
+        let hash = xxh3::xxh3_64(&data);
+        let format = &self.bvp_file.formats[format_index];
+        for candidate in self.dedup_map.get(&(format_index, hash)).map(|c| c.as_slice()).unwrap_or(&[]) {
+            // Blocks only share a hash by accident, or when their data is the same.
+            let block = &self.bvp_file.blocks[*candidate];
+            let decoded = block.decoded(format)?;
+            if block.dimensions == dimensions && decoded.data.as_ref().unwrap().as_ref() == &data {
+                self.duplicates += 1;
+                return Ok(*candidate);
+            }
+        }
+        let index = self.bvp_file.blocks.len();
+        let mut block = Block::new(index, dimensions, Some(format_index), None);
+        block.data = Some(Bytes::from(self.compression.compress(data)));
+        block.data_url = Some(format!("blocks/block_{}.{}", index, self.compression.to_string()));
+        block.encoding = Some(self.compression);
+        self.bvp_file.blocks.push(block);
+        self.dedup_map.entry((format_index, hash)).or_default().push(index);
+        self.bvp_file.block_map.entry(hash).or_insert(index);
+        return Ok(index);
+    }
+
+    /// Downsamples a modality of the input, slab by slab, and adds it to the downsampled asset.
+    /// * `modality_index` - index of the modality in the input
+    /// * `block_dimensions` - dimensions of the blocks, or None for the ones of the input
+    fn add_modality(&mut self, modality_index: usize, block_dimensions: Option<Vector3<u32>>) -> Result<(), CliError> {
+        let reader = VolumeReader::new(self.source);
+        let (root, format) = reader.modality_root(modality_index)?;
+        downsample::check_format(format).map_err(|e| format!("modality {}: {}", modality_index, e))?;
+        let dimensions = self.source.blocks[root].dimensions;
+        let output_dimensions = downsample::downsampled_dimensions(dimensions, self.factor);
+        let block_dimensions = match block_dimensions {
+            Some(d) => d,
+            None => input_block_dimensions(self.source, root)?
+        }.min(&output_dimensions);
+        let format_index = self.add_format(format);
+        log_info!(
+            "modality {}: {} to {} in blocks of {} with the {} filter",
+            modality_index, dimensions, output_dimensions, block_dimensions, self.filter.to_string()
+        );
+
+        let root_index = self.bvp_file.blocks.len();
+        self.bvp_file.blocks.push(Block::new(root_index, output_dimensions, Some(format_index), None));
+        let mut placements = Vec::new();
+        let block_count = output_dimensions.div_ceil(&block_dimensions);
+        for slab in 0..block_count.z {
+            // A slab of the output is one layer of blocks, made from `factor` times as many input slices.
+            let output_start = slab * block_dimensions.z;
+            let output_end = (output_start + block_dimensions.z).min(output_dimensions.z);
+            let input_start = Vector3::from_xyz(0, 0, output_start * self.factor);
+            let input_end = Vector3::from_xyz(dimensions.x, dimensions.y, (output_end * self.factor).min(dimensions.z));
+            let region = reader.read_region(modality_index, input_start, input_end)?;
+            let data = downsample::downsample(format, region.data.as_ref().unwrap(), input_end - input_start, self.factor, self.filter)
+                .map_err(|e| e.to_string())?;
+            log_debug!("slab {} of {}: read {} to {}", slab + 1, block_count.z, input_start, input_end);
+
+            let slab_dimensions = Vector3::from_xyz(output_dimensions.x, output_dimensions.y, output_end - output_start);
+            let slab_block = Block::new(0, slab_dimensions, Some(format_index), Some(data));
+            for y in 0..block_count.y {
+                for x in 0..block_count.x {
+                    let start = block_dimensions * Vector3::from_xyz(x, y, 0);
+                    let end = (start + block_dimensions).min(&slab_dimensions);
+                    let block = slab_block.get_data_in_range(start, end, format)?;
+                    let block_data = block.data.map(Bytes::into_vec).unwrap_or_default();
+                    let index = self.add_block(format_index, end - start, block_data)?;
+                    placements.push(Placement::new(start + Vector3::from_xyz(0, 0, output_start), index));
+                }
+            }
+        }
+        self.bvp_file.blocks[root_index].placements = placements;
+
+        let source_modality = &self.source.modalities[modality_index];
+        let mut modality = Modality::new(
+            source_modality.name.clone(),
+            source_modality.description.clone(),
+            source_modality.semantic_type.clone(),
+            source_modality.volume_size,
+            source_modality.voxel_size.map(|s| s.map(|c| c * self.factor as f32)),
+            root_index
+        );
+        modality.extension_payloads = source_modality.extension_payloads.clone();
+        modality.extras = source_modality.extras.clone();
+        if let Some(t) = transform::transform(source_modality).map_err(|e| CliError::corrupt(e.to_string()))? {
+            let downsampled = downsampled_transform(&t, self.factor, self.filter);
+            transform::set_transform(&mut modality, Some(&downsampled)).map_err(|e| e.to_string())?;
+        }
+        self.bvp_file.modalities.push(modality);
+        return Ok(());
+    }
+}
+
+/// Returns the dimensions of the data blocks of a modality, found by following the first placements from its root.
+/// Fails if the placements lead back to a block on the way.
+/// * `bvp_file` - the asset
+/// * `root` - root block of the modality
+fn input_block_dimensions(bvp_file: &BVPFile, root: usize) -> Result<Vector3<u32>, ReaderError> {
+    let mut index = root;
+    let mut visited = HashSet::from([root]);
+    while let Some(child) = bvp_file.blocks[index].placements.first().map(|p| p.block).filter(|b| *b < bvp_file.blocks.len()) {
+        if !visited.insert(child) {
+            return Err(ReaderError::PlacementCycle(child));
+        }
+        index = child;
+    }
+    return Ok(bvp_file.blocks[index].dimensions);
+}
+
+/// Returns the transform of a downsampled volume. Voxels of the box filters lie at the centers of their boxes,
+/// those of the nearest filter at the first voxel of their box.
+/// * `t` - transform of the original volume
+/// * `factor` - the downsampling factor
+/// * `filter` - the filter
+fn downsampled_transform(t: &Transform, factor: u32, filter: DownsampleFilter) -> Transform {
+    let shift = match filter {
+        DownsampleFilter::Nearest => 0.0,
+        _ => (factor as f64 - 1.0) / 2.0
+    };
+    let mut downsampled = t.translated([shift; 3]);
+    for row in 0..3 {
+        for axis in 0..3 {
+            downsampled.matrix[row][axis] *= factor as f64;
+        }
+    }
+    return downsampled;
+}
+
+fn parse_archive(value: Option<String>) -> Result<ArchiveEnum, CliError> {
+    return match value {
+        Some(a) => Ok(ArchiveEnum::from_string(a)?),
+        None => Err(CliError::config("Missing archive type"))
+    };
+}
+
+/// Runs the command.
+/// * `arguments` - the command line arguments after the name of the command
+pub fn run(arguments: Vec<String>) -> Result<(), CliError> {
+    let mut positional: Vec<String> = Vec::new();
+    let mut output = None;
+    let mut output_archive = None;
+    let mut factor = 2;
+    let mut filter = DownsampleFilter::Mean;
+    let mut modality = None;
+    let mut block_dimensions = None;
+    let mut compression = CompressionType::LZ4S;
+    let mut generator = None;
+    let mut verbosity = 0;
+    let mut arguments_iter = arguments.into_iter();
+    while let Some(arg) = arguments_iter.next() {
+        if arg == "--help" {
+            println!("{}", HELP);
+            return Ok(());
+        } else if arg == "--output" {
+            output = Some(arguments_iter.next().ok_or(CliError::config("Missing value for `--output`"))?);
+        } else if arg == "--output-archive" {
+            output_archive = Some(parse_archive(arguments_iter.next())?);
+        } else if arg == "--factor" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--factor`"))?;
+            factor = value.parse::<u32>().ok().filter(|f| *f >= 2)
+                .ok_or_else(|| format!("Value of `--factor` must be an integer of at least 2 (got `{}`)", value))?;
+        } else if arg == "--filter" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--filter`"))?;
+            filter = DownsampleFilter::from_string(&value).map_err(|e| CliError::config(e.to_string()))?;
+        } else if arg == "--modality" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--modality`"))?;
+            modality = Some(value.parse::<usize>()
+                .map_err(|_| format!("Value of `--modality` must be a non-negative integer (got `{}`)", value))?);
+        } else if arg == "--block-dimensions" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--block-dimensions`"))?;
+            block_dimensions = Some(Vector3::from_string(&value).filter(|d| d.product() > 0)
+                .ok_or_else(|| format!("Value of `--block-dimensions` must be three positive integers such as `64x64x64` (got `{}`)", value))?);
+        } else if arg == "--compression" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--compression`"))?;
+            compression = CompressionType::from_string(&value).map_err(|e| CliError::config(e.to_string()))?;
+        } else if arg == "--generator" {
+            generator = Some(arguments_iter.next().ok_or(CliError::config("Missing value for `--generator`"))?);
+        } else if let Some(change) = log::verbosity_flag(&arg) {
+            verbosity = if change < 0 { change } else { verbosity.max(0) + change };
+        } else if arg.starts_with('-') {
+            return Err(CliError::config(format!("Unknown option `{}`", arg)));
+        } else {
+            positional.push(arg);
+        }
+    }
+    log::set_max_level(Level::from_verbosity(verbosity));
+
+    let output = output.ok_or(CliError::config("Missing output file, given with `--output`"))?;
+    let input_filepath = match positional.first() {
+        Some(p) => Path::new(p.as_str()),
+        None => return Err(CliError::config("Missing input file"))
+    };
+    let archive = match positional.get(1) {
+        Some(a) => ArchiveEnum::from_string(a.clone())?,
+        None => detect::detect_archive(input_filepath)?
+    };
+    let source = BVPFile::open(input_filepath, &archive)?;
+    let modalities: Vec<usize> = match modality {
+        Some(m) if m >= source.modalities.len() => {
+            return Err(CliError::config(format!("Modality {} does not exist, there are {} modalities", m, source.modalities.len())));
+        },
+        Some(m) => vec![m],
+        None => (0..source.modalities.len()).collect()
+    };
+
+    let mut downsampler = Downsampler {
+        source: &source,
+        bvp_file: BVPFile::new(),
+        factor,
+        filter,
+        compression,
+        dedup_map: HashMap::new(),
+        duplicates: 0
+    };
+    for modality_index in modalities {
+        downsampler.add_modality(modality_index, block_dimensions)?;
+    }
+
+    let mut bvp_file = downsampler.bvp_file;
+    bvp_file.asset = source.asset.clone();
+    // The signature of the input does not hold for the downsampled manifest.
+    bvp_file.asset.extension_payloads.remove(&Extension::ExtSignature.to_string());
+    bvp_file.asset.generator = Some(generator.unwrap_or_else(|| version::generator("bvp-downsample")));
+    bvp_file.asset.creation_time = Some(version::timestamp_now());
+
+    let mut writer = output_archive.unwrap_or(archive).return_writer(&output);
+    let mut written = 0;
+    for block in &bvp_file.blocks {
+        if let (Some(data), Some(data_url)) = (&block.data, &block.data_url) {
+            writer.append_file(&File::new(data_url.clone(), data.clone(), None)).map_err(|e| e.to_string())?;
+            written += 1;
+        }
+    }
+    let manifest_file = File::new(
+        "manifest.json".to_string(),
+        bvp_file.to_manifest().map_err(|e| e.to_string())?,
+        Some("application/json".to_string())
+    );
+    writer.append_file(&manifest_file).map_err(|e| e.to_string())?;
+    writer.finish(output.clone()).map_err(|e| e.to_string())?;
+
+    println!(
+        "Downsampled {} by {} into {}: {} modalities, {} blocks with data ({} duplicates removed)",
+        input_filepath.display(), factor, output, bvp_file.modalities.len(), written, downsampler.duplicates
+    );
+    return Ok(());
+}
@@ -0,0 +1,206 @@
+//! Errors of the commands, with a kind that decides the exit code, and how they are printed.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::process::ExitCode;
+
+use tinyjson::JsonValue;
+
+use bvp::errors::{ArchiveError, BlockError, BvpFileError, ConvertError, DetectError, ReaderError};
+
+/// Kinds of failures. Each has its own exit code, which stays the same between versions,
+/// so scripts can tell them apart without parsing the message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Any other failure, such as of a conversion
+    Failed,
+    /// Invalid command line arguments or configuration
+    Config,
+    /// A file could not be read or written
+    Io,
+    /// The asset is not valid, such as a broken archive or manifest
+    CorruptAsset,
+    /// A check failed: an invalid asset, a checksum or signature mismatch, or assets that differ
+    Verification
+}
+
+impl ErrorKind {
+    /// Exit code of the process when a command fails with this kind of error.
+    pub fn exit_code(&self) -> u8 {
+        return match self {
+            ErrorKind::Failed => 1,
+            ErrorKind::Config => 2,
+            ErrorKind::Io => 3,
+            ErrorKind::CorruptAsset => 4,
+            ErrorKind::Verification => 5
+        };
+    }
+
+    /// Name of the kind in JSON error output.
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ErrorKind::Failed => "failed",
+            ErrorKind::Config => "config",
+            ErrorKind::Io => "io",
+            ErrorKind::CorruptAsset => "corruptAsset",
+            ErrorKind::Verification => "verification"
+        };
+    }
+}
+
+/// Error of a command. Plain messages convert into errors of kind `Failed`.
+#[derive(Debug)]
+pub struct CliError {
+    pub kind: ErrorKind,
+    pub message: String
+}
+
+impl CliError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        return Self { kind, message: message.into() };
+    }
+
+    pub fn config(message: impl Into<String>) -> Self {
+        return Self::new(ErrorKind::Config, message);
+    }
+
+    pub fn io(message: impl Into<String>) -> Self {
+        return Self::new(ErrorKind::Io, message);
+    }
+
+    pub fn corrupt(message: impl Into<String>) -> Self {
+        return Self::new(ErrorKind::CorruptAsset, message);
+    }
+
+    pub fn verification(message: impl Into<String>) -> Self {
+        return Self::new(ErrorKind::Verification, message);
+    }
+
+    /// Prefixes the message with the file it is about, keeping the kind.
+    /// * `path` - the file
+    pub fn in_file(self, path: impl fmt::Display) -> Self {
+        return Self { kind: self.kind, message: format!("{}: {}", path, self.message) };
+    }
+
+    /// The error as a JSON object with its `kind`, `exitCode` and `message`.
+    pub fn to_json(&self) -> JsonValue {
+        let mut object = HashMap::new();
+        object.insert("kind".to_string(), JsonValue::from(self.kind.name().to_string()));
+        object.insert("exitCode".to_string(), JsonValue::from(self.kind.exit_code() as f64));
+        object.insert("message".to_string(), JsonValue::from(self.message.clone()));
+        return JsonValue::from(object);
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.message);
+    }
+}
+
+impl From<String> for CliError {
+    fn from(message: String) -> Self {
+        return Self::new(ErrorKind::Failed, message);
+    }
+}
+
+impl From<&str> for CliError {
+    fn from(message: &str) -> Self {
+        return Self::new(ErrorKind::Failed, message);
+    }
+}
+
+impl From<ArchiveError> for CliError {
+    fn from(error: ArchiveError) -> Self {
+        let kind = match error {
+            ArchiveError::SafError(_) | ArchiveError::ZipError(_) => ErrorKind::CorruptAsset,
+            ArchiveError::NotImplemented(_) => ErrorKind::Config,
+            _ => ErrorKind::Io
+        };
+        return Self::new(kind, error.to_string());
+    }
+}
+
+impl From<BvpFileError> for CliError {
+    fn from(error: BvpFileError) -> Self {
+        return match error {
+            BvpFileError::ArchiveError(e) => Self::from(e),
+            e => Self::corrupt(e.to_string())
+        };
+    }
+}
+
+impl From<BlockError> for CliError {
+    fn from(error: BlockError) -> Self {
+        return Self::corrupt(error.to_string());
+    }
+}
+
+impl From<ReaderError> for CliError {
+    fn from(error: ReaderError) -> Self {
+        let kind = match error {
+            ReaderError::ChecksumMismatch(_, _) => ErrorKind::Verification,
+            ReaderError::NoSuchModality(_) | ReaderError::InvalidRegion(_, _, _) | ReaderError::MisalignedRegion(_, _, _) => ErrorKind::Config,
+            ReaderError::DecodeAborted(_) => ErrorKind::Failed,
+            _ => ErrorKind::CorruptAsset
+        };
+        return Self::new(kind, error.to_string());
+    }
+}
+
+impl From<ConvertError> for CliError {
+    fn from(error: ConvertError) -> Self {
+        let kind = match error {
+            ConvertError::Reader(e) => return Self::from(e),
+            ConvertError::CannotWrite(_) => ErrorKind::Io,
+            ConvertError::EmptyRegion(_, _) | ConvertError::NrrdFormat | ConvertError::NrrdType(_, _) => ErrorKind::Config,
+            ConvertError::Transform(_) => ErrorKind::CorruptAsset,
+            ConvertError::Failed(_) => ErrorKind::Failed
+        };
+        return Self::new(kind, error.to_string());
+    }
+}
+
+impl From<DetectError> for CliError {
+    fn from(error: DetectError) -> Self {
+        let kind = match error {
+            DetectError::CannotRead(_, _) => ErrorKind::Io,
+            DetectError::NotAnAsset(_, _) | DetectError::Undetectable(_) => ErrorKind::Config
+        };
+        return Self::new(kind, error.to_string());
+    }
+}
+
+/// How errors are printed to stderr.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// `Error: <message>`
+    Text,
+    /// A JSON object on one line, see `CliError::to_json`
+    Json
+}
+
+impl ErrorFormat {
+    pub fn from_string(s: &str) -> Option<Self> {
+        return match s {
+            "text" => Some(ErrorFormat::Text),
+            "json" => Some(ErrorFormat::Json),
+            _ => None
+        };
+    }
+}
+
+/// Prints an error to stderr and returns the exit code of its kind.
+/// * `error` - the error
+/// * `format` - how it is printed
+pub fn report(error: &CliError, format: ErrorFormat) -> ExitCode {
+    match format {
+        ErrorFormat::Text => eprintln!("Error: {}", error.message),
+        // Only numbers that are not finite cannot be written as JSON.
+        ErrorFormat::Json => match error.to_json().stringify() {
+            Ok(text) => eprintln!("{}", text),
+            Err(_) => eprintln!("Error: {}", error.message)
+        }
+    }
+    return ExitCode::from(error.kind.exit_code());
+}
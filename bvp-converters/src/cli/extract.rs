@@ -0,0 +1,152 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use bvp::archives::ArchiveEnum;
+use bvp::detect;
+use bvp::bvpfile::BVPFile;
+use bvp::log::{self, Level};
+use bvp::log_info;
+use bvp::reader::VolumeReader;
+use bvp::vector3::Vector3;
+
+use super::error::CliError;
+
+static HELP: &str = "bvp-extract\n------------\n Usage: bvp-extract <input_file> [<archive type>] <what> [options]\n What to extract (one of):\n  --block ID - a single block, by index or data file name\n  --all-blocks - every block with data of the modality, each to its own file\n  --start X,Y,Z --end X,Y,Z - a region of the modality, the end is exclusive\n Options:\n  --modality N - modality to extract from (default 0)\n  --output PATH - output file, or output folder for `--all-blocks` (default: current folder)\n  -v, -vv, -vvv / --verbose - print more diagnostics to stderr (info, debug, trace)\n  -q / --quiet - print only errors\n Extracted data is decoded and written as raw voxels.\n This message can be viewed with flag `--help`.";
+
+/// Parses a position given as three integers separated by `,` or `x`.
+fn parse_position(option: &str, value: Option<String>) -> Result<Vector3<u32>, CliError> {
+    let value = match value {
+        Some(v) => v,
+        None => return Err(CliError::config(format!("Missing value for `{}`", option)))
+    };
+    return match Vector3::from_string(&value) {
+        Some(v) => Ok(v),
+        None => Err(CliError::config(format!("Value of `{}` must be three integers such as `0,0,0` (got `{}`)", option, value)))
+    };
+}
+
+/// Finds a block by its index or by the name of its data file.
+fn find_block(bvp_file: &BVPFile, id: &str) -> Result<usize, CliError> {
+    return match (bvp_file.find_block(id), id.parse::<usize>()) {
+        (Some(index), _) => Ok(index),
+        (None, Ok(index)) => Err(CliError::config(format!("Block {} does not exist, there are {} blocks", index, bvp_file.blocks.len()))),
+        (None, Err(_)) => Err(CliError::config(format!("No block has data file `{}`", id)))
+    };
+}
+
+/// Returns the indices of all blocks with data under a block, each index once.
+fn data_blocks_under(bvp_file: &BVPFile, root: usize) -> Vec<usize> {
+    let mut found = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack = vec![root];
+    while let Some(index) = stack.pop() {
+        if !visited.insert(index) {
+            continue;
+        }
+        let block = match bvp_file.blocks.get(index) {
+            Some(b) => b,
+            None => continue
+        };
+        if block.data.is_some() {
+            found.push(index);
+        }
+        for placement in block.placements.iter().rev() {
+            stack.push(placement.block);
+        }
+    }
+    found.sort();
+    return found;
+}
+
+fn write_file(path: &Path, data: &[u8]) -> Result<(), CliError> {
+    log_info!("writing {} bytes to {}", data.len(), path.display());
+    return fs::write(path, data).map_err(|e| CliError::io(format!("Cannot write {}: {}", path.display(), e)));
+}
+
+/// Runs the command.
+/// * `arguments` - the command line arguments after the name of the command
+pub fn run(arguments: Vec<String>) -> Result<(), CliError> {
+    let mut positional: Vec<String> = Vec::new();
+    let mut block_id = None;
+    let mut all_blocks = false;
+    let mut start = None;
+    let mut end = None;
+    let mut modality_index = 0;
+    let mut output = None;
+    let mut verbosity = 0;
+    let mut arguments_iter = arguments.into_iter();
+    while let Some(arg) = arguments_iter.next() {
+        if arg == "--help" {
+            println!("{}", HELP);
+            return Ok(());
+        } else if arg == "--block" {
+            block_id = Some(arguments_iter.next().ok_or(CliError::config("Missing value for `--block`"))?);
+        } else if arg == "--all-blocks" {
+            all_blocks = true;
+        } else if arg == "--start" {
+            start = Some(parse_position("--start", arguments_iter.next())?);
+        } else if arg == "--end" {
+            end = Some(parse_position("--end", arguments_iter.next())?);
+        } else if arg == "--modality" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--modality`"))?;
+            modality_index = value.parse::<usize>()
+                .map_err(|_| CliError::config(format!("Value of `--modality` must be a non-negative integer (got `{}`)", value)))?;
+        } else if arg == "--output" {
+            output = Some(arguments_iter.next().ok_or(CliError::config("Missing value for `--output`"))?);
+        } else if let Some(change) = log::verbosity_flag(&arg) {
+            verbosity = if change < 0 { change } else { verbosity.max(0) + change };
+        } else if arg.starts_with('-') {
+            return Err(CliError::config(format!("Unknown option `{}`", arg)));
+        } else {
+            positional.push(arg);
+        }
+    }
+    log::set_max_level(Level::from_verbosity(verbosity));
+
+    let selections = [block_id.is_some(), all_blocks, start.is_some() || end.is_some()];
+    if selections.iter().filter(|s| **s).count() != 1 {
+        return Err(CliError::config("Specify exactly one of `--block`, `--all-blocks` or `--start`/`--end`"));
+    }
+
+    let input_filepath = match positional.first() {
+        Some(p) => Path::new(p.as_str()),
+        None => return Err(CliError::config("Missing input file"))
+    };
+    let archive_tp = match positional.get(1) {
+        Some(a) => ArchiveEnum::from_string(a.clone())?,
+        None => detect::detect_archive(input_filepath)?
+    };
+    let bvp_file = BVPFile::open(input_filepath, &archive_tp)?;
+    let reader = VolumeReader::new(&bvp_file);
+
+    if let Some(id) = block_id {
+        let index = find_block(&bvp_file, &id)?;
+        let dimensions = bvp_file.blocks[index].dimensions;
+        let block = reader.read_block_region(index, Vector3::from_xyz(0, 0, 0), dimensions)?;
+        let path = output.unwrap_or(format!("block_{}.raw", index));
+        return write_file(Path::new(&path), block.data.unwrap().as_slice());
+    }
+
+    if all_blocks {
+        let (root, _) = reader.modality_root(modality_index)?;
+        let folder = output.unwrap_or(".".to_string());
+        fs::create_dir_all(&folder).map_err(|e| CliError::io(format!("Cannot create {}: {}", folder, e)))?;
+        for index in data_blocks_under(&bvp_file, root) {
+            let dimensions = bvp_file.blocks[index].dimensions;
+            let block = reader.read_block_region(index, Vector3::from_xyz(0, 0, 0), dimensions)?;
+            let path = Path::new(&folder).join(format!("block_{}.raw", index));
+            write_file(&path, block.data.unwrap().as_slice())?;
+        }
+        return Ok(());
+    }
+
+    let (start, end) = match (start, end) {
+        (Some(s), Some(e)) => (s, e),
+        _ => return Err(CliError::config("Both `--start` and `--end` are needed to extract a region"))
+    };
+    let region = reader.read_region(modality_index, start, end)?;
+    let extent = end - start;
+    let path = output.unwrap_or(format!(
+        "region_{}_{}_{}_{}x{}x{}.raw", start.x, start.y, start.z, extent.x, extent.y, extent.z
+    ));
+    return write_file(Path::new(&path), region.data.unwrap().as_slice());
+}
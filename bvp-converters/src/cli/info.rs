@@ -0,0 +1,585 @@
+use std::{collections::HashMap, fmt::Display, path::Path};
+use std::io::{self, BufWriter, Write};
+
+use tinyjson::JsonValue;
+
+use bvp::archives::ArchiveEnum;
+use bvp::detect;
+use bvp::bvpfile::BVPFile;
+use bvp::extensions::Extension;
+use bvp::log::{self, Level};
+use bvp::transform;
+use bvp::window_level;
+
+use super::error::CliError;
+
+static HELP: &str = "bvp-info\n------------\n Usage: bvp-info <input_file> [<archive type>] [options]\n Options:\n  --blocks - also list every block\n  --json - print the information as JSON, for scripting\n  -v, -vv, -vvv / --verbose - print more diagnostics to stderr (info, debug, trace)\n  -q / --quiet - print only errors\n This message can be viewed with flag `--help`.";
+
+/// Information about a single block of the asset.
+struct BlockInfo {
+    index: usize,
+    dimensions: [u32; 3],
+    format: Option<usize>,
+    encoding: Option<String>,
+    data_url: Option<String>,
+    /// Size of the data as stored in the archive
+    stored_size: usize,
+    /// Size of the data after decoding, 0 for blocks without data
+    decoded_size: usize,
+    placements: usize,
+    /// Number of placements in other blocks that refer to this block
+    referenced: usize
+}
+
+/// Blocks stored smaller than this are mostly per-file overhead in archives and requests.
+const SMALL_BLOCK_SIZE: usize = 64 * 1024;
+/// Blocks stored larger than this are slow to fetch and decode for a small view of the volume.
+const HUGE_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+/// Block dimensions are not suggested beyond this along any axis.
+const MAX_SUGGESTED_DIMENSION: u32 = 4096;
+/// Width of the longest bar of the size histogram, in characters.
+const HISTOGRAM_WIDTH: usize = 40;
+/// Number of blocks listed by index in the text output, the JSON output lists all of them.
+const LISTED_BLOCKS: usize = 10;
+
+/// Distribution of the stored sizes of data blocks, read from the archive without decoding the blocks.
+struct SizeStatistics {
+    smallest: usize,
+    median: usize,
+    largest: usize,
+    /// Number of blocks whose stored size is in `[2^(i - 1), 2^i)` bytes, for bucket `i`. Bucket 0 holds empty blocks.
+    histogram: Vec<usize>,
+    /// Compressed blocks that are not smaller than their decoded data
+    incompressible: Vec<usize>,
+    /// Blocks stored larger than `HUGE_BLOCK_SIZE`
+    huge: Vec<usize>,
+    /// The most common dimensions of data blocks, edges of the volume aside
+    typical_dimensions: Option<[u32; 3]>,
+    /// Block dimensions whose blocks would be stored between `SMALL_BLOCK_SIZE` and `HUGE_BLOCK_SIZE`,
+    /// if the current ones are outside of that range
+    suggested_dimensions: Option<[u32; 3]>
+}
+
+/// Returns the histogram bucket of a stored size.
+fn size_bucket(size: usize) -> usize {
+    return (usize::BITS - size.leading_zeros()) as usize;
+}
+
+/// Formats a power of two bytes with a binary unit.
+/// * `exponent` - the exponent of two
+fn format_power_of_two(exponent: usize) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    let unit = (exponent / 10).min(units.len() - 1);
+    return format!("{} {}", 1u64 << (exponent - unit * 10), units[unit]);
+}
+
+/// Scales block dimensions until blocks of the estimated size fit between `SMALL_BLOCK_SIZE` and `HUGE_BLOCK_SIZE`,
+/// doubling the shortest axis of blocks that are too small and halving the longest axis of blocks that are too large.
+/// Returns None if the current dimensions already fit.
+/// * `dimensions` - the current block dimensions
+/// * `stored_size` - the typical stored size of blocks with these dimensions
+fn suggest_dimensions(dimensions: [u32; 3], stored_size: usize) -> Option<[u32; 3]> {
+    let mut suggested = dimensions;
+    let mut size = stored_size as f64;
+    while size < SMALL_BLOCK_SIZE as f64 {
+        let axis = (0..3).min_by_key(|&a| suggested[a]).unwrap_or(0);
+        if suggested[axis] * 2 > MAX_SUGGESTED_DIMENSION {
+            break;
+        }
+        suggested[axis] *= 2;
+        size *= 2.0;
+    }
+    while size > HUGE_BLOCK_SIZE as f64 {
+        let axis = (0..3).max_by_key(|&a| suggested[a]).unwrap_or(0);
+        if suggested[axis] < 2 {
+            break;
+        }
+        suggested[axis] /= 2;
+        size /= 2.0;
+    }
+    if suggested == dimensions {
+        return None;
+    }
+    return Some(suggested);
+}
+
+impl SizeStatistics {
+    fn new(blocks: &[BlockInfo]) -> Self {
+        let data_blocks: Vec<&BlockInfo> = blocks.iter().filter(|b| b.data_url.is_some()).collect();
+        let mut sizes: Vec<usize> = data_blocks.iter().map(|b| b.stored_size).collect();
+        sizes.sort_unstable();
+
+        let mut histogram = Vec::new();
+        for &size in &sizes {
+            let bucket = size_bucket(size);
+            if histogram.len() <= bucket {
+                histogram.resize(bucket + 1, 0);
+            }
+            histogram[bucket] += 1;
+        }
+
+        let compressed = |b: &BlockInfo| b.encoding.as_ref().map(|e| e != "raw").unwrap_or(false);
+        let incompressible = data_blocks.iter()
+            .filter(|b| compressed(b) && b.decoded_size > 0 && b.stored_size >= b.decoded_size)
+            .map(|b| b.index)
+            .collect();
+        let huge = data_blocks.iter().filter(|b| b.stored_size > HUGE_BLOCK_SIZE).map(|b| b.index).collect();
+
+        // Blocks at the far edges of the volume are smaller, so the most common dimensions are the configured ones.
+        let mut counts: HashMap<[u32; 3], usize> = HashMap::new();
+        for block in &data_blocks {
+            *counts.entry(block.dimensions).or_default() += 1;
+        }
+        let typical_dimensions = counts.iter()
+            .max_by_key(|(d, c)| (**c, d[0] as u64 * d[1] as u64 * d[2] as u64, **d))
+            .map(|(d, _)| *d);
+        let suggested_dimensions = typical_dimensions.and_then(|dimensions| {
+            let mut typical_sizes: Vec<usize> = data_blocks.iter()
+                .filter(|b| b.dimensions == dimensions)
+                .map(|b| b.stored_size)
+                .collect();
+            typical_sizes.sort_unstable();
+            return suggest_dimensions(dimensions, typical_sizes[typical_sizes.len() / 2]);
+        });
+
+        return Self {
+            smallest: sizes.first().copied().unwrap_or(0),
+            median: sizes.get(sizes.len() / 2).copied().unwrap_or(0),
+            largest: sizes.last().copied().unwrap_or(0),
+            histogram,
+            incompressible,
+            huge,
+            typical_dimensions,
+            suggested_dimensions
+        };
+    }
+
+    /// Returns the non-empty range of histogram buckets, so that the output starts and ends with blocks.
+    fn used_buckets(&self) -> std::ops::Range<usize> {
+        let first = self.histogram.iter().position(|&c| c > 0).unwrap_or(0);
+        return first..self.histogram.len();
+    }
+}
+
+/// Summary of the blocks of an asset.
+struct Summary {
+    blocks: Vec<BlockInfo>,
+    data_blocks: usize,
+    container_blocks: usize,
+    stored_size: usize,
+    decoded_size: usize,
+    /// Decoded size of all placed data blocks, counting duplicates every time they are placed
+    placed_size: usize,
+    /// Number of placed data blocks, counting duplicates every time they are placed
+    placed_blocks: usize,
+    placements: usize,
+    sizes: SizeStatistics
+}
+
+/// Returns the decoded size and the number of all data blocks in the subtree of a block,
+/// counting blocks every time they are placed. Totals of subtrees are remembered in `totals`,
+/// so subtrees shared by many parents are only expanded once.
+/// * `bvp_file` - the asset
+/// * `blocks` - information about every block of the asset
+/// * `index` - index of the root of the subtree
+/// * `totals` - totals of already expanded subtrees, by block index
+fn subtree_totals(bvp_file: &BVPFile, blocks: &[BlockInfo], index: usize, totals: &mut Vec<Option<(usize, usize)>>) -> (usize, usize) {
+    let block = match bvp_file.blocks.get(index) {
+        Some(b) => b,
+        None => return (0, 0)
+    };
+    if let Some(total) = totals[index] {
+        return total;
+    }
+    // A block placed inside itself is counted as empty, instead of being expanded forever.
+    totals[index] = Some((0, 0));
+    let mut size = blocks[index].decoded_size;
+    let mut count = if block.data.is_some() { 1 } else { 0 };
+    for placement in &block.placements {
+        let (placed_size, placed_count) = subtree_totals(bvp_file, blocks, placement.block, totals);
+        size += placed_size;
+        count += placed_count;
+    }
+    totals[index] = Some((size, count));
+    return (size, count);
+}
+
+impl Summary {
+    fn new(bvp_file: &BVPFile) -> Self {
+        let mut referenced = vec![0; bvp_file.blocks.len()];
+        for block in &bvp_file.blocks {
+            for placement in &block.placements {
+                if placement.block < referenced.len() {
+                    referenced[placement.block] += 1;
+                }
+            }
+        }
+
+        let mut blocks = Vec::with_capacity(bvp_file.blocks.len());
+        for (i, block) in bvp_file.blocks.iter().enumerate() {
+            let decoded_size = match (&block.data, block.format.and_then(|f| bvp_file.formats.get(f))) {
+                (Some(_), Some(format)) => format.checked_count_space(block.dimensions).unwrap_or(0),
+                _ => 0
+            };
+            blocks.push(BlockInfo {
+                index: block.index,
+                dimensions: block.dimensions.to_array(),
+                format: block.format,
+                encoding: block.encoding.map(|e| e.to_string()),
+                data_url: block.data_url.clone(),
+                stored_size: block.data.as_ref().map(|d| d.len()).unwrap_or(0),
+                decoded_size,
+                placements: block.placements.len(),
+                referenced: referenced[i]
+            });
+        }
+
+        let placements = bvp_file.blocks.iter().map(|b| b.placements.len()).sum();
+        // Blocks can be nested, so every modality is expanded down to its data blocks.
+        let mut subtree_totals_by_block = vec![None; bvp_file.blocks.len()];
+        let mut placed_size = 0;
+        let mut placed_blocks = 0;
+        for modality in &bvp_file.modalities {
+            let (size, count) = subtree_totals(bvp_file, &blocks, modality.block, &mut subtree_totals_by_block);
+            placed_size += size;
+            placed_blocks += count;
+        }
+
+        return Self {
+            data_blocks: blocks.iter().filter(|b| b.data_url.is_some()).count(),
+            container_blocks: blocks.iter().filter(|b| b.placements > 0).count(),
+            stored_size: blocks.iter().map(|b| b.stored_size).sum(),
+            decoded_size: blocks.iter().map(|b| b.decoded_size).sum(),
+            placed_size,
+            placed_blocks,
+            placements,
+            sizes: SizeStatistics::new(&blocks),
+            blocks
+        };
+    }
+
+    /// Number of placed data blocks that reuse the data of another block.
+    fn duplicates(&self) -> usize {
+        return self.placed_blocks.saturating_sub(self.data_blocks);
+    }
+
+    /// Bytes of decoded data that deduplication saved.
+    fn bytes_saved(&self) -> usize {
+        return self.placed_size.saturating_sub(self.decoded_size);
+    }
+
+    /// Placed size divided by decoded size. Above 1 if identical blocks were stored only once.
+    fn dedup_ratio(&self) -> f64 {
+        if self.decoded_size == 0 {
+            return 1.0;
+        }
+        return self.placed_size as f64 / self.decoded_size as f64;
+    }
+
+    /// Decoded size divided by stored size. Above 1 if block data is compressed.
+    fn compression_ratio(&self) -> f64 {
+        if self.stored_size == 0 {
+            return 1.0;
+        }
+        return self.decoded_size as f64 / self.stored_size as f64;
+    }
+}
+
+fn optional_string(value: &Option<String>) -> String {
+    return match value {
+        Some(v) => v.clone(),
+        None => "-".to_string()
+    };
+}
+
+/// Writes a labelled value of the summary, with values aligned in a column.
+/// * `out` - where the summary is written
+/// * `label` - the name of the value
+/// * `value` - the value
+fn field(out: &mut impl Write, label: &str, value: impl Display) -> io::Result<()> {
+    return writeln!(out, "  {:<22}{}", format!("{}:", label), value);
+}
+
+/// Formats the number of blocks in a list, followed by the first few of their indices.
+fn block_indices(indices: &[usize]) -> String {
+    if indices.is_empty() {
+        return "0".to_string();
+    }
+    let listed: Vec<String> = indices.iter().take(LISTED_BLOCKS).map(|i| i.to_string()).collect();
+    let more = match indices.len() > LISTED_BLOCKS {
+        true => format!(" and {} more", indices.len() - LISTED_BLOCKS),
+        false => String::new()
+    };
+    return format!("{} (blocks {}{})", indices.len(), listed.join(", "), more);
+}
+
+fn print_text(out: &mut impl Write, bvp_file: &BVPFile, summary: &Summary, list_blocks: bool) -> io::Result<()> {
+    let asset = &bvp_file.asset;
+    writeln!(out, "Asset")?;
+    field(out, "version", &asset.version)?;
+    field(out, "name", optional_string(&asset.name))?;
+    field(out, "description", optional_string(&asset.description))?;
+    field(out, "author", optional_string(&asset.author))?;
+    field(out, "copyright", optional_string(&asset.copyright))?;
+    field(out, "generator", optional_string(&asset.generator))?;
+    field(out, "acquisition time", optional_string(&asset.acquisition_time))?;
+    field(out, "creation time", optional_string(&asset.creation_time))?;
+    field(out, "extensions used", asset.extensions_used.join(", "))?;
+    field(out, "extensions required", asset.extensions_required.join(", "))?;
+    let public_key = asset.extension_payloads.get(&Extension::ExtSignature.to_string())
+        .and_then(|p| p.get::<HashMap<String, JsonValue>>())
+        .and_then(|p| p.get("publicKey"))
+        .and_then(|k| k.get::<String>());
+    if let Some(key) = public_key {
+        field(out, "signed with key", format!("{} (check with `bvp-validate --check-signature`)", key))?;
+    }
+
+    writeln!(out, "Modalities ({})", bvp_file.modalities.len())?;
+    for (i, modality) in bvp_file.modalities.iter().enumerate() {
+        let dimensions = match bvp_file.blocks.get(modality.block) {
+            Some(b) => b.dimensions.to_string(),
+            None => "-".to_string()
+        };
+        writeln!(
+            out, "  [{}] {} (root block {}, dimensions {}, volume size {})",
+            i, optional_string(&modality.name), modality.block, dimensions, modality.volume_size
+        )?;
+        if let Some(description) = &modality.description {
+            writeln!(out, "      description: {}", description)?;
+        }
+        if let Some(semantic_type) = &modality.semantic_type {
+            writeln!(out, "      semantic type: {}", semantic_type)?;
+        }
+        if let Some(voxel_size) = &modality.voxel_size {
+            writeln!(out, "      voxel size: {}", voxel_size)?;
+        }
+        match transform::transform(modality) {
+            Ok(Some(t)) => {
+                let [x, y, z] = t.origin();
+                let spacing = t.spacing();
+                writeln!(out, "      origin: [{}, {}, {}], spacing: [{}, {}, {}]", x, y, z, spacing[0], spacing[1], spacing[2])?;
+                let direction = t.direction().map(|a| format!("[{}, {}, {}]", a[0], a[1], a[2]));
+                writeln!(out, "      direction: {}", direction.join(", "))?;
+            },
+            Ok(None) => {},
+            Err(e) => writeln!(out, "      transform: {}", e)?
+        }
+        match window_level::presets(modality) {
+            Ok(presets) => for preset in presets {
+                writeln!(out, "      window {}: center {}, width {}", optional_string(&preset.name), preset.center(), preset.width())?;
+            },
+            Err(e) => writeln!(out, "      window: {}", e)?
+        }
+    }
+
+    writeln!(out, "Formats ({})", bvp_file.formats.len())?;
+    for (i, format) in bvp_file.formats.iter().enumerate() {
+        let description = format.to_json().stringify().unwrap_or_default();
+        writeln!(out, "  [{}] {}", i, description)?;
+    }
+
+    writeln!(out, "Blocks")?;
+    field(out, "total", summary.blocks.len())?;
+    field(out, "with data", summary.data_blocks)?;
+    field(out, "with placements", summary.container_blocks)?;
+    field(out, "placements", summary.placements)?;
+    field(out, "stored size", format!("{} B", summary.stored_size))?;
+    field(out, "decoded size", format!("{} B", summary.decoded_size))?;
+    field(out, "placed size", format!("{} B", summary.placed_size))?;
+    field(out, "compression ratio", format!("{:.3}", summary.compression_ratio()))?;
+    field(out, "dedup ratio", format!("{:.3}", summary.dedup_ratio()))?;
+    field(out, "duplicates", summary.duplicates())?;
+    field(out, "bytes saved", format!("{} B", summary.bytes_saved()))?;
+
+    let sizes = &summary.sizes;
+    writeln!(out, "Block sizes")?;
+    field(out, "smallest stored", format!("{} B", sizes.smallest))?;
+    field(out, "median stored", format!("{} B", sizes.median))?;
+    field(out, "largest stored", format!("{} B", sizes.largest))?;
+    field(out, "incompressible", block_indices(&sizes.incompressible))?;
+    field(out, "huge", block_indices(&sizes.huge))?;
+    if let Some([x, y, z]) = sizes.typical_dimensions {
+        field(out, "typical dimensions", format!("{}x{}x{}", x, y, z))?;
+        match sizes.suggested_dimensions {
+            Some([x, y, z]) => field(out, "suggested dimensions", format!("{}x{}x{}", x, y, z)),
+            None => field(out, "suggested dimensions", "keep the current ones")
+        }?;
+    }
+    let largest_count = sizes.histogram.iter().copied().max().unwrap_or(0).max(1);
+    for bucket in sizes.used_buckets() {
+        let count = sizes.histogram[bucket];
+        let range = match bucket {
+            0 => "0 B".to_string(),
+            _ => format!("{} - {}", format_power_of_two(bucket - 1), format_power_of_two(bucket))
+        };
+        let bar = "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(largest_count));
+        writeln!(out, "  {:>20} {:>8} {}", range, count, bar)?;
+    }
+
+    if list_blocks {
+        writeln!(out)?;
+        writeln!(out, "{:>7} {:>16} {:>6} {:>8} {:>10} {:>10} {:>10} {:>10}  data",
+            "index", "dimensions", "format", "encoding", "stored", "decoded", "placements", "referenced")?;
+        for block in &summary.blocks {
+            writeln!(out, "{:>7} {:>16} {:>6} {:>8} {:>10} {:>10} {:>10} {:>10}  {}",
+                block.index,
+                format!("{}x{}x{}", block.dimensions[0], block.dimensions[1], block.dimensions[2]),
+                block.format.map(|f| f.to_string()).unwrap_or("-".to_string()),
+                optional_string(&block.encoding),
+                block.stored_size,
+                block.decoded_size,
+                block.placements,
+                block.referenced,
+                optional_string(&block.data_url)
+            )?;
+        }
+    }
+    return Ok(());
+}
+
+fn to_json(bvp_file: &BVPFile, summary: &Summary, list_blocks: bool) -> JsonValue {
+    let mut info = HashMap::new();
+    // Extensions are listed as declared in the manifest, not as derived from the formats.
+    let mut asset = match bvp_file.asset.to_json(&[], &[]) {
+        JsonValue::Object(o) => o,
+        _ => HashMap::new()
+    };
+    let extensions_used: Vec<JsonValue> = bvp_file.asset.extensions_used.iter().map(|e| e.clone().into()).collect();
+    let extensions_required: Vec<JsonValue> = bvp_file.asset.extensions_required.iter().map(|e| e.clone().into()).collect();
+    asset.insert("extensionsUsed".to_string(), extensions_used.into());
+    asset.insert("extensionsRequired".to_string(), extensions_required.into());
+    info.insert("asset".to_string(), asset.into());
+    info.insert(
+        "modalities".to_string(),
+        JsonValue::from(bvp_file.modalities.iter().map(|m| m.to_json()).collect::<Vec<JsonValue>>())
+    );
+    info.insert(
+        "formats".to_string(),
+        JsonValue::from(bvp_file.formats.iter().map(|f| f.to_json()).collect::<Vec<JsonValue>>())
+    );
+
+    let mut blocks = HashMap::new();
+    blocks.insert("total".to_string(), (summary.blocks.len() as f64).into());
+    blocks.insert("withData".to_string(), (summary.data_blocks as f64).into());
+    blocks.insert("withPlacements".to_string(), (summary.container_blocks as f64).into());
+    blocks.insert("placements".to_string(), (summary.placements as f64).into());
+    blocks.insert("storedSize".to_string(), (summary.stored_size as f64).into());
+    blocks.insert("decodedSize".to_string(), (summary.decoded_size as f64).into());
+    blocks.insert("placedSize".to_string(), (summary.placed_size as f64).into());
+    blocks.insert("compressionRatio".to_string(), summary.compression_ratio().into());
+    blocks.insert("dedupRatio".to_string(), summary.dedup_ratio().into());
+    blocks.insert("duplicates".to_string(), (summary.duplicates() as f64).into());
+    blocks.insert("bytesSaved".to_string(), (summary.bytes_saved() as f64).into());
+    info.insert("blocks".to_string(), blocks.into());
+
+    let sizes = &summary.sizes;
+    let indices = |list: &[usize]| JsonValue::from(list.iter().map(|i| JsonValue::from(*i as f64)).collect::<Vec<JsonValue>>());
+    let dimensions = |d: Option<[u32; 3]>| match d {
+        Some(d) => JsonValue::from(d.iter().map(|a| JsonValue::from(*a as f64)).collect::<Vec<JsonValue>>()),
+        None => JsonValue::Null
+    };
+    let mut histogram = Vec::new();
+    for bucket in sizes.used_buckets() {
+        let mut hm = HashMap::new();
+        let from = if bucket == 0 { 0 } else { 1u64 << (bucket - 1) };
+        hm.insert("from".to_string(), (from as f64).into());
+        hm.insert("to".to_string(), ((1u64 << bucket) as f64).into());
+        hm.insert("count".to_string(), (sizes.histogram[bucket] as f64).into());
+        histogram.push(hm.into());
+    }
+    let mut block_sizes = HashMap::new();
+    block_sizes.insert("smallest".to_string(), (sizes.smallest as f64).into());
+    block_sizes.insert("median".to_string(), (sizes.median as f64).into());
+    block_sizes.insert("largest".to_string(), (sizes.largest as f64).into());
+    block_sizes.insert("histogram".to_string(), JsonValue::from(histogram));
+    block_sizes.insert("incompressible".to_string(), indices(&sizes.incompressible));
+    block_sizes.insert("huge".to_string(), indices(&sizes.huge));
+    block_sizes.insert("typicalDimensions".to_string(), dimensions(sizes.typical_dimensions));
+    block_sizes.insert("suggestedDimensions".to_string(), dimensions(sizes.suggested_dimensions));
+    info.insert("blockSizes".to_string(), block_sizes.into());
+
+    if list_blocks {
+        let mut list = Vec::with_capacity(summary.blocks.len());
+        for block in &summary.blocks {
+            let mut hm = HashMap::new();
+            hm.insert("index".to_string(), (block.index as f64).into());
+            hm.insert(
+                "dimensions".to_string(),
+                JsonValue::from(block.dimensions.iter().map(|d| JsonValue::from(*d as f64)).collect::<Vec<JsonValue>>())
+            );
+            if let Some(format) = block.format {
+                hm.insert("format".to_string(), (format as f64).into());
+            }
+            if let Some(encoding) = &block.encoding {
+                hm.insert("encoding".to_string(), encoding.clone().into());
+            }
+            if let Some(data_url) = &block.data_url {
+                hm.insert("data".to_string(), data_url.clone().into());
+            }
+            hm.insert("storedSize".to_string(), (block.stored_size as f64).into());
+            hm.insert("decodedSize".to_string(), (block.decoded_size as f64).into());
+            hm.insert("placements".to_string(), (block.placements as f64).into());
+            hm.insert("referenced".to_string(), (block.referenced as f64).into());
+            list.push(hm.into());
+        }
+        info.insert("blockList".to_string(), JsonValue::from(list));
+    }
+    return info.into();
+}
+
+/// Turns the result of writing to stdout into the result of the command.
+/// A closed pipe, as when the output is piped into `head`, only means the reader
+/// has seen enough, so it ends the command normally instead of as an error.
+/// * `written` - the result of writing the output
+fn finish(written: io::Result<()>) -> Result<(), CliError> {
+    return match written {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(CliError::io(format!("Cannot write to stdout: {}", e))),
+        _ => Ok(())
+    };
+}
+
+/// Runs the command.
+/// * `arguments` - the command line arguments after the name of the command
+pub fn run(arguments: Vec<String>) -> Result<(), CliError> {
+    let mut positional: Vec<String> = Vec::new();
+    let mut list_blocks = false;
+    let mut json = false;
+    let mut verbosity = 0;
+    for arg in arguments {
+        if arg == "--help" {
+            return finish(writeln!(io::stdout().lock(), "{}", HELP));
+        } else if arg == "--blocks" {
+            list_blocks = true;
+        } else if arg == "--json" {
+            json = true;
+        } else if let Some(change) = log::verbosity_flag(&arg) {
+            verbosity = if change < 0 { change } else { verbosity.max(0) + change };
+        } else if arg.starts_with('-') {
+            return Err(CliError::config(format!("Unknown option `{}`", arg)));
+        } else {
+            positional.push(arg);
+        }
+    }
+    log::set_max_level(Level::from_verbosity(verbosity));
+
+    let input_filepath = match positional.first() {
+        Some(p) => Path::new(p.as_str()),
+        None => return Err(CliError::config("Missing input file"))
+    };
+    let archive_tp = match positional.get(1) {
+        Some(a) => ArchiveEnum::from_string(a.clone())?,
+        None => detect::detect_archive(input_filepath)?
+    };
+    let bvp_file = BVPFile::open(input_filepath, &archive_tp)?;
+    let summary = Summary::new(&bvp_file);
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    let written = if json {
+        let info = to_json(&bvp_file, &summary, list_blocks);
+        let content = info.stringify().map_err(|e| format!("Error creating JSON: {}", e))?;
+        writeln!(out, "{}", content)
+    } else {
+        print_text(&mut out, &bvp_file, &summary, list_blocks)
+    };
+    return finish(written.and_then(|_| out.flush()));
+}
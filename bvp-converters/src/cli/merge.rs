@@ -0,0 +1,404 @@
+use std::{collections::{HashMap, HashSet}, path::Path, sync::Arc};
+
+use xxhash_rust::xxh3;
+
+use bvp::anonymize::{self, AnonymizeAction, AnonymizeOptions};
+use bvp::archives::ArchiveEnum;
+use bvp::detect;
+use bvp::block::Block;
+use bvp::bvpfile::BVPFile;
+use bvp::extensions::Extension;
+use bvp::file::File;
+use bvp::formats::Format;
+use bvp::log::{self, Level};
+use bvp::modality::Modality;
+use bvp::placement::Placement;
+use bvp::reader::VolumeReader;
+use bvp::vector3::Vector3;
+use bvp::version;
+use bvp::{log_debug, log_info, log_warn};
+
+use super::error::CliError;
+
+static HELP: &str = "bvp-merge\n------------\n Usage: bvp-merge <input_file>... --output PATH [options]\n Options:\n  --output PATH - the merged asset\n  --archive TYPE - archive type of the input files (SAF, ZIP or None, default: detected from each input)\n  --output-archive TYPE - archive type of the merged asset (default: the same as the first input)\n  --tile - join the inputs into a larger volume, each input given as PATH@X,Y,Z with its offset\n  --name TEXT - name of the merged asset (default: name of the first input)\n  --generator TEXT - generator of the merged asset (default: `bvp-merge` with the version of the tool)\n  --anonymize - remove the author, acquisition time, descriptions and sensitive extras, see the README\n  --anonymize-hash SALT - replace them with hashes of the salt and the value instead, implies `--anonymize`\n  --deny-extras KEY[,KEY...] - further keys of extras to anonymize, implies `--anonymize`\n  -v, -vv, -vvv / --verbose - print more diagnostics to stderr (info, debug, trace)\n  -q / --quiet - print only errors\n By default, the modalities of all inputs are collected into one asset.\n This message can be viewed with flag `--help`.";
+
+/// Builds the merged asset while the inputs are added one after another.
+struct Merger {
+    bvp_file: BVPFile,
+    /// Block indices of merged data blocks, by format index and hash of the decoded data.
+    dedup_map: HashMap<(usize, u64), Vec<usize>>,
+    duplicates: usize
+}
+
+impl Merger {
+    fn new() -> Self {
+        return Self {
+            bvp_file: BVPFile::new(),
+            dedup_map: HashMap::new(),
+            duplicates: 0
+        };
+    }
+
+    /// Returns the index of the format in the merged asset, adding the format if there is no equal one yet.
+    /// * `format` - format of an input
+    fn add_format(&mut self, format: &Format) -> usize {
+        let json = format.to_json();
+        if let Some(i) = self.bvp_file.formats.iter().position(|f| f.to_json() == json) {
+            return i;
+        }
+        self.bvp_file.formats.push(format.clone());
+        return self.bvp_file.formats.len() - 1;
+    }
+
+    /// Returns the index of a merged data block with the same decoded data, if there is one.
+    /// * `format_index` - index of the format in the merged asset
+    /// * `hash` - hash of the decoded data
+    /// * `decoded` - the decoded data
+    fn find_duplicate(&self, format_index: usize, hash: u64, decoded: &[u8]) -> Result<Option<usize>, CliError> {
+        let candidates = match self.dedup_map.get(&(format_index, hash)) {
+            Some(c) => c,
+            None => return Ok(None)
+        };
+        let format = &self.bvp_file.formats[format_index];
+        for candidate in candidates {
+            // Blocks only share a hash by accident, or when their data is the same.
+            let candidate_data = self.bvp_file.blocks[*candidate].decoded(format)?;
+            if candidate_data.data.as_ref().unwrap().as_ref() == decoded {
+                return Ok(Some(*candidate));
+            }
+        }
+        return Ok(None);
+    }
+
+    /// Copies the blocks of an input that are reachable from the given blocks to the merged asset.
+    /// Data blocks with the same decoded data as an already merged block are not copied again.
+    /// Returns the indices of the copied blocks in the merged asset, by their index in the input.
+    /// * `source` - the input
+    /// * `roots` - blocks of the input to start from
+    fn add_blocks(&mut self, source: &BVPFile, roots: &[usize]) -> Result<HashMap<usize, usize>, CliError> {
+        let reader = VolumeReader::new(source);
+        let mut reachable = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = roots.to_vec();
+        while let Some(index) = stack.pop() {
+            if !visited.insert(index) {
+                continue;
+            }
+            let block = match source.blocks.get(index) {
+                Some(b) => b,
+                None => return Err(CliError::corrupt(format!("Block {} does not exist", index)))
+            };
+            reachable.push(index);
+            for placement in &block.placements {
+                stack.push(placement.block);
+            }
+        }
+        reachable.sort();
+
+        let mut index_map = HashMap::new();
+        let mut parents = Vec::new();
+        for index in reachable {
+            let block = &source.blocks[index];
+            let format = match block.format {
+                Some(_) => Some(self.add_format(reader.find_format(index)?)),
+                None => None
+            };
+            if block.data.is_none() {
+                let merged_index = self.bvp_file.blocks.len();
+                let mut merged_block = Block::new(merged_index, block.dimensions, format, None);
+                merged_block.extension_payloads = block.extension_payloads.clone();
+                merged_block.extras = block.extras.clone();
+                self.bvp_file.blocks.push(merged_block);
+                index_map.insert(index, merged_index);
+                parents.push(index);
+                continue;
+            }
+
+            let format_index = match format {
+                Some(f) => f,
+                None => self.add_format(reader.find_format(index)?)
+            };
+            let decoded = block.decoded(&self.bvp_file.formats[format_index])?;
+            let decoded_data = decoded.data.as_ref().unwrap();
+            let hash = xxh3::xxh3_64(decoded_data.as_slice());
+            if let Some(existing) = self.find_duplicate(format_index, hash, decoded_data)? {
+                self.duplicates += 1;
+                index_map.insert(index, existing);
+                continue;
+            }
+
+            // Data is kept as it was stored, so it is not compressed again.
+            let merged_index = self.bvp_file.blocks.len();
+            let mut merged_block = Block::new(merged_index, block.dimensions, Some(format_index), None);
+            merged_block.data = block.data.clone();
+            merged_block.data_url = Some(format!("blocks/block_{}.raw", merged_index));
+            merged_block.encoding = block.encoding.clone();
+            merged_block.checksum = block.checksum.clone();
+            merged_block.extension_payloads = block.extension_payloads.clone();
+            merged_block.extras = block.extras.clone();
+            self.bvp_file.blocks.push(merged_block);
+            self.dedup_map.entry((format_index, hash)).or_default().push(merged_index);
+            self.bvp_file.block_map.entry(hash).or_insert(merged_index);
+            index_map.insert(index, merged_index);
+        }
+
+        for index in parents {
+            let placements = source.blocks[index].placements.iter()
+                .map(|p| Placement::new(p.position, index_map[&p.block]))
+                .collect();
+            self.bvp_file.blocks[index_map[&index]].placements = placements;
+        }
+        return Ok(index_map);
+    }
+
+    /// Adds all modalities of an input to the merged asset.
+    /// * `source` - the input
+    fn add_modalities(&mut self, source: &BVPFile) -> Result<(), CliError> {
+        let roots: Vec<usize> = source.modalities.iter().map(|m| m.block).collect();
+        let index_map = self.add_blocks(source, &roots)?;
+        for modality in &source.modalities {
+            let mut merged_modality = Modality::new(
+                modality.name.clone(),
+                modality.description.clone(),
+                modality.semantic_type.clone(),
+                modality.volume_size,
+                modality.voxel_size,
+                index_map[&modality.block]
+            );
+            merged_modality.extension_payloads = modality.extension_payloads.clone();
+            merged_modality.extras = modality.extras.clone();
+            self.bvp_file.modalities.push(merged_modality);
+        }
+        return Ok(());
+    }
+
+    /// Joins the same modality of all inputs into a single volume.
+    /// The placements of input root blocks are moved into the new root block,
+    /// so the merged block tree is not deeper than the inputs.
+    /// * `sources` - the inputs, with the offsets of their volumes
+    /// * `modality_index` - index of the modality in every input
+    fn add_tiled_modality(&mut self, sources: &[(BVPFile, Vector3<u32>)], modality_index: usize) -> Result<(), CliError> {
+        let mut root_format = None;
+        let mut placements = Vec::new();
+        let mut dimensions = Vector3::from_xyz(0, 0, 0);
+        let mut covered: u64 = 0;
+        let mut extents: Vec<(Vector3<u32>, Vector3<u32>)> = Vec::new();
+
+        for (i, (source, offset)) in sources.iter().enumerate() {
+            let (root, format) = VolumeReader::new(source).modality_root(modality_index).map_err(|e| CliError::from(e).in_file(format!("input {}", i)))?;
+            let format_index = self.add_format(format);
+            match root_format {
+                None => root_format = Some(format_index),
+                Some(first) if first != format_index => {
+                    return Err(CliError::config(format!("input {}: format of modality {} differs from the first input", i, modality_index)));
+                },
+                _ => ()
+            }
+            if offset.is_any_div(&format.microblock_dimensions) {
+                return Err(CliError::config(format!("input {}: offset {} is not a multiple of microblock dimensions {}", i, offset, format.microblock_dimensions)));
+            }
+
+            let root_block = &source.blocks[root];
+            let end = *offset + root_block.dimensions;
+            for (other, (other_start, other_end)) in extents.iter().enumerate() {
+                if offset.x < other_end.x && other_start.x < end.x && offset.y < other_end.y && other_start.y < end.y
+                    && offset.z < other_end.z && other_start.z < end.z {
+                    return Err(CliError::config(format!("inputs {} and {} overlap", other, i)));
+                }
+            }
+            extents.push((*offset, end));
+            dimensions = Vector3::from_xyz(dimensions.x.max(end.x), dimensions.y.max(end.y), dimensions.z.max(end.z));
+            covered += root_block.dimensions.x as u64 * root_block.dimensions.y as u64 * root_block.dimensions.z as u64;
+
+            if root_block.data.is_some() {
+                let index_map = self.add_blocks(source, &[root])?;
+                placements.push(Placement::new(*offset, index_map[&root]));
+            } else {
+                let children: Vec<usize> = root_block.placements.iter().map(|p| p.block).collect();
+                let index_map = self.add_blocks(source, &children)?;
+                for placement in &root_block.placements {
+                    placements.push(Placement::new(*offset + placement.position, index_map[&placement.block]));
+                }
+            }
+        }
+
+        if covered < dimensions.x as u64 * dimensions.y as u64 * dimensions.z as u64 {
+            log_warn!("inputs do not cover the whole merged volume of modality {}, the gaps will be missing", modality_index);
+        }
+        let root_index = self.bvp_file.blocks.len();
+        let mut root_block = Block::new(root_index, dimensions, root_format, None);
+        root_block.placements = placements;
+        self.bvp_file.blocks.push(root_block);
+
+        // The merged modality is described by the first input, scaled to the new dimensions.
+        let (first, _) = &sources[0];
+        let first_modality = &first.modalities[modality_index];
+        let first_dimensions = first.blocks[first_modality.block].dimensions;
+        let volume_size = Vector3::from_xyz(
+            first_modality.volume_size.x / first_dimensions.x as f32 * dimensions.x as f32,
+            first_modality.volume_size.y / first_dimensions.y as f32 * dimensions.y as f32,
+            first_modality.volume_size.z / first_dimensions.z as f32 * dimensions.z as f32
+        );
+        self.bvp_file.modalities.push(Modality::new(
+            first_modality.name.clone(),
+            first_modality.description.clone(),
+            first_modality.semantic_type.clone(),
+            volume_size,
+            first_modality.voxel_size,
+            root_index
+        ));
+        return Ok(());
+    }
+}
+
+/// Parses an input of the tile mode, given as `PATH@X,Y,Z`.
+fn parse_tile(input: &str) -> Result<(&str, Vector3<u32>), CliError> {
+    let (path, offset) = match input.rsplit_once('@') {
+        Some(p) => p,
+        None => return Err(CliError::config(format!("Input `{}` has no offset, expected PATH@X,Y,Z", input)))
+    };
+    let components: Result<Vec<u32>, _> = offset.split(',').map(|c| c.trim().parse::<u32>()).collect();
+    return match components {
+        Ok(c) if c.len() == 3 => Ok((path, Vector3::from_xyz(c[0], c[1], c[2]))),
+        _ => Err(CliError::config(format!("Offset of input `{}` must be three integers such as `0,0,0`", input)))
+    };
+}
+
+fn parse_archive(value: Option<String>) -> Result<ArchiveEnum, CliError> {
+    return match value {
+        Some(a) => Ok(ArchiveEnum::from_string(a)?),
+        None => Err(CliError::config("Missing archive type"))
+    };
+}
+
+/// Runs the command.
+/// * `arguments` - the command line arguments after the name of the command
+pub fn run(arguments: Vec<String>) -> Result<(), CliError> {
+    let mut positional: Vec<String> = Vec::new();
+    let mut output = None;
+    let mut archive = None;
+    let mut output_archive = None;
+    let mut tile = false;
+    let mut name = None;
+    let mut generator = None;
+    let mut anonymize_options: Option<AnonymizeOptions> = None;
+    let mut verbosity = 0;
+    let mut arguments_iter = arguments.into_iter();
+    while let Some(arg) = arguments_iter.next() {
+        if arg == "--help" {
+            println!("{}", HELP);
+            return Ok(());
+        } else if arg == "--output" {
+            output = Some(arguments_iter.next().ok_or(CliError::config("Missing value for `--output`"))?);
+        } else if arg == "--archive" {
+            archive = Some(parse_archive(arguments_iter.next())?);
+        } else if arg == "--output-archive" {
+            output_archive = Some(parse_archive(arguments_iter.next())?);
+        } else if arg == "--tile" {
+            tile = true;
+        } else if arg == "--name" {
+            name = Some(arguments_iter.next().ok_or(CliError::config("Missing value for `--name`"))?);
+        } else if arg == "--generator" {
+            generator = Some(arguments_iter.next().ok_or(CliError::config("Missing value for `--generator`"))?);
+        } else if arg == "--anonymize" {
+            anonymize_options.get_or_insert_with(AnonymizeOptions::default);
+        } else if arg == "--anonymize-hash" {
+            let salt = arguments_iter.next().ok_or(CliError::config("Missing value for `--anonymize-hash`"))?;
+            anonymize_options.get_or_insert_with(AnonymizeOptions::default).action = AnonymizeAction::Hash(salt);
+        } else if arg == "--deny-extras" {
+            let keys = arguments_iter.next().ok_or(CliError::config("Missing value for `--deny-extras`"))?;
+            let options = anonymize_options.get_or_insert_with(AnonymizeOptions::default);
+            options.deny_list.extend(keys.split(',').map(|k| k.trim().to_string()).filter(|k| !k.is_empty()));
+        } else if let Some(change) = log::verbosity_flag(&arg) {
+            verbosity = if change < 0 { change } else { verbosity.max(0) + change };
+        } else if arg.starts_with('-') {
+            return Err(CliError::config(format!("Unknown option `{}`", arg)));
+        } else {
+            positional.push(arg);
+        }
+    }
+    log::set_max_level(Level::from_verbosity(verbosity));
+
+    let output = output.ok_or(CliError::config("Missing output file, given with `--output`"))?;
+    if positional.is_empty() {
+        return Err(CliError::config("Missing input files"));
+    }
+
+    let mut sources = Vec::new();
+    let mut first_archive = None;
+    for input in &positional {
+        let (path, offset) = match tile {
+            true => parse_tile(input)?,
+            false => (input.as_str(), Vector3::from_xyz(0, 0, 0))
+        };
+        let input_archive = match archive {
+            Some(a) => a,
+            None => detect::detect_archive(Path::new(path))?
+        };
+        first_archive.get_or_insert(input_archive);
+        log_info!("reading {}", path);
+        let source = BVPFile::open(Path::new(path), &input_archive).map_err(|x| CliError::from(x).in_file(path))?;
+        sources.push((source, offset));
+    }
+
+    let mut merger = Merger::new();
+    if tile {
+        let modalities = sources[0].0.modalities.len();
+        if sources.iter().any(|(source, _)| source.modalities.len() != modalities) {
+            return Err(CliError::config("All inputs need the same number of modalities to be tiled"));
+        }
+        for modality_index in 0..modalities {
+            merger.add_tiled_modality(&sources, modality_index)?;
+        }
+    } else {
+        for (source, _) in &sources {
+            merger.add_modalities(source)?;
+        }
+    }
+
+    let first = &sources[0].0.asset;
+    let mut bvp_file = merger.bvp_file;
+    bvp_file.asset.name = name.or(first.name.clone());
+    bvp_file.asset.author = first.author.clone();
+    bvp_file.asset.description = first.description.clone();
+    bvp_file.asset.copyright = first.copyright.clone();
+    bvp_file.asset.acquisition_time = first.acquisition_time.clone();
+    // Extensions the library does not know are kept as the first input declares them.
+    bvp_file.asset.extensions_used = first.extensions_used.clone();
+    bvp_file.asset.extensions_required = first.extensions_required.clone();
+    bvp_file.asset.extension_payloads = first.extension_payloads.clone();
+    // The signature of the first input does not hold for the merged manifest.
+    bvp_file.asset.extension_payloads.remove(&Extension::ExtSignature.to_string());
+    bvp_file.asset.extras = first.extras.clone();
+    bvp_file.asset.generator = Some(generator.unwrap_or_else(|| version::generator("bvp-merge")));
+    bvp_file.asset.creation_time = Some(version::timestamp_now());
+    if let Some(options) = &anonymize_options {
+        let changed = anonymize::anonymize(&mut bvp_file, options);
+        log_info!("anonymized {} values", changed.len());
+        for path in &changed {
+            log_debug!("anonymized {}", path);
+        }
+    }
+
+    let mut writer = output_archive.or(first_archive).unwrap_or(ArchiveEnum::None).return_writer(&output);
+    let mut written = 0;
+    for block in &bvp_file.blocks {
+        if let (Some(data), Some(data_url)) = (&block.data, &block.data_url) {
+            writer.append_file(&File::new(data_url.clone(), data.clone(), None)).map_err(|e| e.to_string())?;
+            written += 1;
+        }
+    }
+    let manifest_file = File::new(
+        "manifest.json".to_string(),
+        Arc::new(bvp_file.to_manifest().map_err(|e| e.to_string())?),
+        Some("application/json".to_string())
+    );
+    writer.append_file(&manifest_file).map_err(|e| e.to_string())?;
+    writer.finish(output.clone()).map_err(|e| e.to_string())?;
+
+    println!(
+        "Merged {} inputs into {}: {} modalities, {} blocks ({} with data, {} duplicates removed)",
+        sources.len(), output, bvp_file.modalities.len(), bvp_file.blocks.len(), written, merger.duplicates
+    );
+    return Ok(());
+}
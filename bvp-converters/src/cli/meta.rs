@@ -0,0 +1,188 @@
+use std::{collections::HashMap, fs, path::Path, str::FromStr, sync::Arc};
+
+use tinyjson::JsonValue;
+
+use bvp::anonymize::{self, AnonymizeAction, AnonymizeOptions};
+use bvp::archives::{ArchiveEnum, saf, zip};
+use bvp::detect;
+use bvp::json_aux;
+use bvp::file::File;
+use bvp::signature;
+use bvp::log::{self, Level};
+use bvp::{log_debug, log_info, log_warn};
+
+use super::error::CliError;
+
+static HELP: &str = "bvp-meta\n------------\n Usage: bvp-meta <input_file> [<archive type>] [options]\n Options:\n  --name TEXT, --author TEXT, --copyright TEXT, --description TEXT, --acquisition-time TEXT - set an asset field\n  --extras JSON - set the application specific data of the asset, any JSON value\n  --unset FIELD - remove an asset field (name, author, copyright, description, acquisitionTime or extras)\n  --anonymize - remove the author, acquisition time, descriptions and sensitive extras, see the README\n  --anonymize-hash SALT - replace them with hashes of the salt and the value instead, implies `--anonymize`\n  --deny-extras KEY[,KEY...] - further keys of extras to anonymize, implies `--anonymize`\n  --sign KEY_FILE - sign the manifest with the ed25519 key in the file, after the other changes\n  -v, -vv, -vvv / --verbose - print more diagnostics to stderr (info, debug, trace)\n  -q / --quiet - print only errors\n Without options, the asset fields are printed. Only the manifest is rewritten, block data stays as it is.\n This message can be viewed with flag `--help`.";
+
+/// Asset fields that can be edited, as command line options and manifest keys.
+const EDITABLE_FIELDS: [(&str, &str); 5] = [
+    ("--name", "name"),
+    ("--author", "author"),
+    ("--copyright", "copyright"),
+    ("--description", "description"),
+    ("--acquisition-time", "acquisitionTime")
+];
+
+/// Prints all fields of the asset object.
+/// * `asset` - the asset object of the manifest
+fn print_asset(asset: &HashMap<String, JsonValue>) {
+    let mut keys: Vec<&String> = asset.keys().collect();
+    keys.sort();
+    for key in keys {
+        let value = match &asset[key] {
+            JsonValue::String(s) => s.clone(),
+            other => other.stringify().unwrap_or_default()
+        };
+        println!("{:<18} {}", format!("{}:", key), value);
+    }
+}
+
+/// Writes the files back to where they were read from, with the archive they were read as.
+/// Archives are first written next to the original and then moved over it, so a failed
+/// write does not destroy the asset.
+/// * `files` - all files of the asset, with the updated manifest
+/// * `manifest_index` - index of the manifest in `files`
+/// * `filepath` - path to the archive file, manifest file or folder
+/// * `archive` - type of the archive
+fn write_back(files: &Vec<File>, manifest_index: usize, filepath: &Path, archive: &ArchiveEnum) -> Result<(), CliError> {
+    // Files outside of archives keep their paths, so only the manifest needs writing.
+    if filepath.is_dir() {
+        return files[manifest_index].write().map_err(|e| CliError::io(e.to_string()));
+    }
+    let contents = match archive {
+        ArchiveEnum::None => return files[manifest_index].write().map_err(|e| CliError::io(e.to_string())),
+        ArchiveEnum::SAF => saf::to_saf_archive(files).map_err(|e| e.to_string())?,
+        ArchiveEnum::ZIP => zip::to_zip_archive(files).map_err(|e| e.to_string())?
+    };
+    let temporary = filepath.with_extension("tmp");
+    fs::write(&temporary, contents).map_err(|e| CliError::io(format!("Cannot write {}: {}", temporary.display(), e)))?;
+    return fs::rename(&temporary, filepath).map_err(|e| CliError::io(format!("Cannot replace {}: {}", filepath.display(), e)));
+}
+
+/// Runs the command.
+/// * `arguments` - the command line arguments after the name of the command
+pub fn run(arguments: Vec<String>) -> Result<(), CliError> {
+    let mut positional: Vec<String> = Vec::new();
+    let mut changes: Vec<(&str, Option<JsonValue>)> = Vec::new();
+    let mut anonymize_options: Option<AnonymizeOptions> = None;
+    let mut signing_key = None;
+    let mut verbosity = 0;
+    let mut arguments_iter = arguments.into_iter();
+    'arguments: while let Some(arg) = arguments_iter.next() {
+        if arg == "--help" {
+            println!("{}", HELP);
+            return Ok(());
+        } else if arg == "--unset" {
+            let field = arguments_iter.next().ok_or(CliError::config("Missing value for `--unset`"))?;
+            let key = match EDITABLE_FIELDS.iter().find(|(_, key)| *key == field) {
+                Some((_, key)) => *key,
+                None if field == "extras" => "extras",
+                None => return Err(CliError::config(format!("Field `{}` cannot be edited", field)))
+            };
+            changes.push((key, None));
+            continue;
+        } else if arg == "--extras" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--extras`"))?;
+            let extras = JsonValue::from_str(&value).map_err(|e| CliError::config(format!("Value of `--extras` is not valid JSON: {}", e)))?;
+            changes.push(("extras", Some(extras)));
+            continue;
+        } else if arg == "--sign" {
+            let path = arguments_iter.next().ok_or(CliError::config("Missing value for `--sign`"))?;
+            let text = fs::read_to_string(&path).map_err(|e| CliError::io(format!("Cannot read {}: {}", path, e)))?;
+            signing_key = Some(signature::signing_key_from_hex(&text).map_err(|e| CliError::config(format!("{}: {}", path, e)))?);
+            continue;
+        } else if arg == "--anonymize" {
+            anonymize_options.get_or_insert_with(AnonymizeOptions::default);
+            continue;
+        } else if arg == "--anonymize-hash" {
+            let salt = arguments_iter.next().ok_or(CliError::config("Missing value for `--anonymize-hash`"))?;
+            anonymize_options.get_or_insert_with(AnonymizeOptions::default).action = AnonymizeAction::Hash(salt);
+            continue;
+        } else if arg == "--deny-extras" {
+            let keys = arguments_iter.next().ok_or(CliError::config("Missing value for `--deny-extras`"))?;
+            let options = anonymize_options.get_or_insert_with(AnonymizeOptions::default);
+            options.deny_list.extend(keys.split(',').map(|k| k.trim().to_string()).filter(|k| !k.is_empty()));
+            continue;
+        } else if let Some(change) = log::verbosity_flag(&arg) {
+            verbosity = if change < 0 { change } else { verbosity.max(0) + change };
+            continue;
+        }
+        for (flag, key) in EDITABLE_FIELDS {
+            if arg == flag {
+                let value = arguments_iter.next().ok_or(CliError::config(format!("Missing value for `{}`", flag)))?;
+                changes.push((key, Some(value.into())));
+                continue 'arguments;
+            }
+        }
+        if arg.starts_with('-') {
+            return Err(CliError::config(format!("Unknown option `{}`", arg)));
+        }
+        positional.push(arg);
+    }
+    log::set_max_level(Level::from_verbosity(verbosity));
+
+    let input_filepath = match positional.first() {
+        Some(p) => Path::new(p.as_str()),
+        None => return Err(CliError::config("Missing input file"))
+    };
+    let archive_tp = match positional.get(1) {
+        Some(a) => ArchiveEnum::from_string(a.clone())?,
+        None => detect::detect_archive(input_filepath)?
+    };
+
+    let mut files = archive_tp.read_archive(input_filepath)?;
+    let manifest_index = match files.iter().position(|file| file.name.ends_with("manifest.json")) {
+        Some(i) => i,
+        None => return Err(CliError::corrupt("No manifest in the asset"))
+    };
+    let content = String::from_utf8(files[manifest_index].data.to_vec()).map_err(|e| CliError::corrupt(format!("Cannot decode manifest: {}", e)))?;
+    // The manifest is edited as plain JSON, so everything but the asset fields stays the same.
+    let mut manifest: HashMap<String, JsonValue> = match JsonValue::from_str(&content) {
+        Ok(JsonValue::Object(o)) => o,
+        Ok(_) => return Err(CliError::corrupt("Manifest is not a JSON object")),
+        Err(e) => return Err(CliError::corrupt(format!("Invalid manifest JSON: {}", e)))
+    };
+    let was_signed = signing_key.is_none() && (!changes.is_empty() || anonymize_options.is_some())
+        && signature::verify_manifest(&manifest).is_ok();
+    let asset = match manifest.get_mut("asset") {
+        Some(JsonValue::Object(o)) => o,
+        _ => return Err(CliError::corrupt("Manifest has no asset object"))
+    };
+
+    if changes.is_empty() && anonymize_options.is_none() && signing_key.is_none() {
+        print_asset(asset);
+        return Ok(());
+    }
+    for (key, value) in changes {
+        match value {
+            Some(v) => {
+                log_info!("setting {} to `{}`", key, v.stringify().unwrap_or_default());
+                asset.insert(key.to_string(), v);
+            },
+            None => {
+                log_info!("removing {}", key);
+                asset.remove(key);
+            }
+        }
+    }
+    // Fields set above are anonymized too, so they cannot bring sensitive values back.
+    if let Some(options) = &anonymize_options {
+        let changed = anonymize::anonymize_manifest(&mut manifest, options);
+        log_info!("anonymized {} values", changed.len());
+        for path in &changed {
+            log_debug!("anonymized {}", path);
+        }
+    }
+    if let Some(key) = &signing_key {
+        signature::sign_manifest(&mut manifest, key).map_err(|e| e.to_string())?;
+        log_info!("signed the manifest with key {}", signature::to_hex(&key.public_key()));
+    } else if was_signed && signature::verify_manifest(&manifest).is_err() {
+        log_warn!("the signature of the manifest no longer matches, sign it again with `--sign`");
+    }
+
+    let text = json_aux::try_canonical_string(&JsonValue::from(manifest)).map_err(|e| format!("Error creating manifest JSON: {}", e))?;
+    let manifest_file = &files[manifest_index];
+    files[manifest_index] = File::new(manifest_file.name.clone(), Arc::new(text.into_bytes()), manifest_file.mime.clone());
+    return write_back(&files, manifest_index, input_filepath, &archive_tp);
+}
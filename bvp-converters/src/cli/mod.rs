@@ -0,0 +1,119 @@
+//! Commands of the `bvp` tool. The binaries with the old names, such as `raw2bvp`, run one of them.
+
+mod convert;
+mod diff;
+mod downsample;
+mod error;
+mod extract;
+mod info;
+mod merge;
+mod meta;
+mod pack;
+mod serve;
+mod thumbnail;
+mod unpack;
+mod validate;
+
+use std::env;
+use std::process::ExitCode;
+
+use self::error::{CliError, ErrorFormat};
+
+/// A command of the `bvp` tool.
+pub struct Command {
+    pub name: &'static str,
+    /// Name of the binary that runs only this command, if any
+    pub alias: Option<&'static str>,
+    pub summary: &'static str,
+    /// Runs the command with the arguments after its name
+    pub run: fn(Vec<String>) -> Result<(), CliError>
+}
+
+pub const COMMANDS: [Command; 12] = [
+    Command { name: "pack", alias: Some("raw2bvp"), summary: "Converts volume in raw data file to BVP", run: pack::run },
+    Command { name: "unpack", alias: Some("bvp2raw"), summary: "Converts volume in BVP format to raw data file", run: unpack::run },
+    Command { name: "convert", alias: None, summary: "Packs a raw file or unpacks a BVP asset, detected from the input", run: convert::run },
+    Command { name: "info", alias: Some("bvp-info"), summary: "Prints metadata, formats and block statistics of a BVP asset", run: info::run },
+    Command { name: "validate", alias: Some("bvp-validate"), summary: "Checks that a BVP asset conforms to the specification", run: validate::run },
+    Command { name: "diff", alias: Some("bvp-diff"), summary: "Compares metadata and voxel data of two BVP assets", run: diff::run },
+    Command { name: "extract", alias: Some("bvp-extract"), summary: "Writes single blocks or regions of a BVP asset to raw files", run: extract::run },
+    Command { name: "meta", alias: Some("bvp-meta"), summary: "Prints and edits asset metadata of a BVP asset in place", run: meta::run },
+    Command { name: "merge", alias: Some("bvp-merge"), summary: "Combines several BVP assets into one", run: merge::run },
+    Command { name: "downsample", alias: Some("bvp-downsample"), summary: "Writes a copy of a BVP asset at a lower resolution", run: downsample::run },
+    Command { name: "thumbnail", alias: Some("bvp-thumbnail"), summary: "Renders preview images of a BVP asset", run: thumbnail::run },
+    Command { name: "serve", alias: Some("bvp-serve"), summary: "Serves the manifest and blocks of a BVP asset over HTTP", run: serve::run },
+];
+
+/// Returns the help of the `bvp` tool, with its commands.
+fn help() -> String {
+    let mut lines = vec![
+        "bvp".to_string(),
+        "------------".to_string(),
+        " Usage: bvp <command> [options]".to_string(),
+        " Commands:".to_string()
+    ];
+    for command in &COMMANDS {
+        let alias = command.alias.map(|a| format!(" (also `{}`)", a)).unwrap_or_default();
+        lines.push(format!("  {} - {}{}", command.name, command.summary, alias));
+    }
+    lines.push(" The options of a command are listed with `bvp <command> --help`.".to_string());
+    lines.push(" Every command takes `--error-format text|json` to print errors as text (default) or as a JSON object.".to_string());
+    lines.push(" This message can be viewed with flag `--help`.".to_string());
+    return lines.join("\n");
+}
+
+/// Takes `--error-format FORMAT` or `--error-format=FORMAT` out of the arguments, which every command accepts.
+/// * `arguments` - the command line arguments
+fn take_error_format(arguments: &mut Vec<String>) -> Result<ErrorFormat, CliError> {
+    let mut format = ErrorFormat::Text;
+    let mut i = 0;
+    while i < arguments.len() {
+        let value = if arguments[i] == "--error-format" {
+            let value = arguments.get(i + 1).cloned().ok_or(CliError::config("Missing value for `--error-format`"))?;
+            arguments.drain(i..i + 2);
+            value
+        } else if let Some(value) = arguments[i].strip_prefix("--error-format=") {
+            let value = value.to_string();
+            arguments.remove(i);
+            value
+        } else {
+            i += 1;
+            continue;
+        };
+        format = ErrorFormat::from_string(&value)
+            .ok_or_else(|| CliError::config(format!("Unsupported error format `{}` (expected `text` or `json`)", value)))?;
+    }
+    return Ok(format);
+}
+
+/// Runs a command with the arguments of the process, and prints its error if it fails.
+/// Returns the exit code of the kind of error, see `ErrorKind`.
+/// * `command` - name of the command, or None to take it from the first argument, as `bvp` does
+pub fn main(command: Option<&str>) -> ExitCode {
+    let mut arguments: Vec<String> = env::args().skip(1).collect();
+    let error_format = match take_error_format(&mut arguments) {
+        Ok(format) => format,
+        Err(e) => return error::report(&e, ErrorFormat::Text)
+    };
+    return match run(command, arguments) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => error::report(&e, error_format)
+    };
+}
+
+/// Runs a command, see `main`.
+fn run(command: Option<&str>, mut arguments: Vec<String>) -> Result<(), CliError> {
+    let name = match command {
+        Some(name) => name.to_string(),
+        None if arguments.is_empty() => return Err(CliError::config("Missing command, see `bvp --help`")),
+        None => arguments.remove(0)
+    };
+    if command.is_none() && (name == "--help" || name == "help") {
+        println!("{}", help());
+        return Ok(());
+    }
+    return match COMMANDS.iter().find(|c| c.name == name) {
+        Some(c) => (c.run)(arguments),
+        None => Err(CliError::config(format!("Unknown command `{}`, see `bvp --help`", name)))
+    };
+}
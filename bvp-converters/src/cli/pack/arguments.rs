@@ -0,0 +1,831 @@
+use std::{env, fs, collections::HashMap, path::Path};
+
+use thiserror::Error;
+use xxhash_rust::xxh3;
+use tinyjson::JsonValue;
+
+use bvp::{config_formats::{self, ConfigFormat}, convert::{tiles::TiledVolume, BlockNaming, BlockOrder, InputLayout, ModalityInput, Parameters, WindowSetting, filters::Filter, mask::IsosurfaceMask, rescale::Rescale, DEFAULT_DEDUP_MEMORY_BLOCKS}, vector3::Vector3, remote, formats::{self, Format}, json_aux, layout::VoxelLayout, archives::{ArchiveEnum, STDIO_PATH, zip::MemberCompression}, checksum::ChecksumType, digest::DigestType, texture::TextureCompression, transform::Transform, window_level::{WindowPreset, AUTO_PRESET}, ed25519::SigningKey, signature, image::Window, version::SpecVersion, compressions::{CompressionType, MAX_COMPRESSION_LEVEL}, errors::{JsonError, FormatError, ArchiveError, CompressionError, ChecksumError, VersionError}};
+
+use super::config_validation::validate_config;
+use super::watch;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Invalid config JSON: `{0}`")]
+    InvalidJson(#[source] JsonError),
+    #[error("Could not parse config file: `{0}`")]
+    ParsingFailure(String),
+    #[error("Cannot open config file: `{0}`")]
+    CannotOpenFile(String),
+    #[error("Error retrieving format from config: `{0}`")]
+    FormatError(FormatError),
+    #[error("Error retrieving archive type from config: `{0}`")]
+    ArchiveError(ArchiveError),
+    #[error("Error retrieving compression scheme from config: `{0}`")]
+    CompressionError(CompressionError),
+    #[error("Error retrieving checksum algorithm from config: `{0}`")]
+    ChecksumError(ChecksumError),
+    #[error("Error retrieving specification version from config: `{0}`")]
+    VersionError(VersionError),
+    #[error("Invalid value for `{0}`: `{1}`")]
+    InvalidValue(String, String),
+    #[error("Missing value for `{0}`, set it in the config file or with the matching command line flag")]
+    MissingValue(String),
+    #[error("Invalid config:\n  {}", .0.join("\n  "))]
+    InvalidConfig(Vec<String>),
+}
+
+/// How the value of a config flag is turned into JSON.
+#[derive(Clone, Copy)]
+enum FlagKind {
+    /// Any text, stored as a string
+    Text,
+    /// Non-negative integer, stored as a number
+    Count,
+    /// Decimal number, stored as a number
+    Number,
+    /// Three integers, separated by `,` or `x` (e.g. `256x256x128`)
+    Dimensions,
+    /// Several dimensions separated by `,`, each as three integers separated by `x` (e.g. `512x512x512,128x128x128`)
+    DimensionsList,
+    /// Three decimal numbers, separated by `,` (e.g. `1,1,2.5`)
+    Scale,
+    /// `true` or `false`, stored as a boolean
+    Boolean,
+    /// A format JSON object, or a shorthand such as `u8`, `i16`, `f32` or `u8x3` (three u8 components)
+    Format,
+    /// Any JSON value
+    Json,
+    /// `auto`, stored as a string, or two numbers separated by `,` (e.g. `0,4095`), stored as an array
+    Window
+}
+
+/// Command line flags of raw2bvp that set config values, and the config key each of them sets.
+pub const CONFIG_FLAGS: [(&str, &str); 45] = [
+    ("--input-file", "inputFile"),
+    ("--output-file", "outputFile"),
+    ("--dimensions", "dimensions"),
+    ("--block-dimensions", "blockDimensions"),
+    ("--superblock-dimensions", "superblockDimensions"),
+    ("--format", "format"),
+    ("--input-offset", "inputOffset"),
+    ("--input-stride", "inputStride"),
+    ("--input-plane-padding", "inputPlanePadding"),
+    ("--allow-size-mismatch", "allowSizeMismatch"),
+    ("--rescale-slope", "rescaleSlope"),
+    ("--rescale-intercept", "rescaleIntercept"),
+    ("--filters", "filters"),
+    ("--isosurface-mask", "isosurfaceMask"),
+    ("--archive", "archive"),
+    ("--archive-compression", "archiveCompression"),
+    ("--compression", "compression"),
+    ("--name", "name"),
+    ("--description", "description"),
+    ("--semantic-type", "semanticType"),
+    ("--volume-scale", "volumeScale"),
+    ("--voxel-scale", "voxelScale"),
+    ("--transform", "transform"),
+    ("--window", "window"),
+    ("--author", "author"),
+    ("--copyright", "copyright"),
+    ("--acquisition-time", "acquisitionTime"),
+    ("--generator", "generator"),
+    ("--threads", "threads"),
+    ("--queue-capacity", "queueCapacity"),
+    ("--compression-level", "compressionLevel"),
+    ("--checksum", "checksum"),
+    ("--volume-checksum", "volumeChecksum"),
+    ("--record-source", "recordSource"),
+    ("--deduplication", "deduplication"),
+    ("--dedup-memory-blocks", "dedupMemoryBlocks"),
+    ("--block-naming", "blockNaming"),
+    ("--block-order", "blockOrder"),
+    ("--voxel-layout", "voxelLayout"),
+    ("--occupancy-grid", "occupancyGrid"),
+    ("--texture-compression", "textureCompression"),
+    ("--spec-version", "specVersion"),
+    ("--checkpoint", "checkpoint"),
+    ("--signing-key", "signingKey"),
+    ("--tiled", "tiled"),
+];
+
+fn flag_kind(key: &str) -> FlagKind {
+    return match key {
+        "dimensions" | "blockDimensions" | "occupancyGrid" => FlagKind::Dimensions,
+        "superblockDimensions" => FlagKind::DimensionsList,
+        "volumeScale" | "voxelScale" => FlagKind::Scale,
+        "format" => FlagKind::Format,
+        "transform" | "filters" | "isosurfaceMask" => FlagKind::Json,
+        "window" => FlagKind::Window,
+        "deduplication" | "checkpoint" | "tiled" | "recordSource" | "allowSizeMismatch" => FlagKind::Boolean,
+        "threads" | "queueCapacity" | "compressionLevel" | "dedupMemoryBlocks" | "inputOffset" | "inputStride"
+            | "inputPlanePadding" => FlagKind::Count,
+        "rescaleSlope" | "rescaleIntercept" => FlagKind::Number,
+        _ => FlagKind::Text
+    };
+}
+
+/// Converts the value of a config flag to the JSON value it would have in a config file.
+/// * `key` - config key the flag sets
+/// * `value` - value given on the command line
+pub fn flag_value_to_json(key: &str, value: &str) -> Result<JsonValue, ConfigError> {
+    let invalid = |expected: &str| {
+        return ConfigError::InvalidValue(key.to_string(), format!("expected {}, got {}", expected, value));
+    };
+    return match flag_kind(key) {
+        FlagKind::Text => Ok(JsonValue::from(value.to_string())),
+        FlagKind::Count => match value.parse::<u32>() {
+            Ok(n) => Ok(JsonValue::from(n as f64)),
+            Err(_) => Err(invalid("a non-negative integer"))
+        },
+        FlagKind::Number => match value.trim().parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(JsonValue::from(n)),
+            _ => Err(invalid("a number such as -1024 or 0.5"))
+        },
+        FlagKind::Dimensions => {
+            let components: Result<Vec<JsonValue>, _> = value.split([',', 'x'])
+                .map(|c| c.trim().parse::<u32>().map(|n| JsonValue::from(n as f64)))
+                .collect();
+            match components {
+                Ok(c) if c.len() == 3 => Ok(JsonValue::from(c)),
+                _ => Err(invalid("three integers such as 256x256x128"))
+            }
+        },
+        FlagKind::DimensionsList => {
+            let levels: Result<Vec<JsonValue>, _> = value.split(',')
+                .map(|level| {
+                    let components: Result<Vec<JsonValue>, _> = level.split('x')
+                        .map(|c| c.trim().parse::<u32>().map(|n| JsonValue::from(n as f64)))
+                        .collect();
+                    match components {
+                        Ok(c) if c.len() == 3 => Ok(JsonValue::from(c)),
+                        _ => Err(())
+                    }
+                })
+                .collect();
+            match levels {
+                Ok(l) if !l.is_empty() => Ok(JsonValue::from(l)),
+                _ => Err(invalid("dimensions such as 512x512x512, separated by `,` for several levels"))
+            }
+        },
+        FlagKind::Scale => {
+            let components: Result<Vec<JsonValue>, _> = value.split(',')
+                .map(|c| c.trim().parse::<f64>().map(JsonValue::from))
+                .collect();
+            match components {
+                Ok(c) if c.len() == 3 => Ok(JsonValue::from(c)),
+                _ => Err(invalid("three numbers such as 1,1,2.5"))
+            }
+        },
+        FlagKind::Boolean => match value {
+            "true" => Ok(JsonValue::from(true)),
+            "false" => Ok(JsonValue::from(false)),
+            _ => Err(invalid("true or false"))
+        },
+        FlagKind::Format => {
+            if value.trim_start().starts_with('{') {
+                value.parse::<JsonValue>().map_err(|e| ConfigError::ParsingFailure(e.to_string()))
+            } else {
+                formats::shorthand_to_json(value).ok_or_else(|| invalid("a format object or a shorthand such as u8, f32 or u8x3"))
+            }
+        },
+        FlagKind::Json => value.parse::<JsonValue>().map_err(|e| ConfigError::ParsingFailure(e.to_string())),
+        FlagKind::Window => {
+            if value == AUTO_PRESET {
+                Ok(JsonValue::from(value.to_string()))
+            } else {
+                match Window::from_string(value) {
+                    Some(w) => Ok(JsonValue::from(vec![JsonValue::from(w.low), JsonValue::from(w.high)])),
+                    None => Err(invalid("auto, or the lowest and highest value shown such as 0,4095"))
+                }
+            }
+        }
+    };
+}
+
+/// Returns a required config value, or an error naming the missing key.
+fn required<'a>(hashmap: &'a HashMap<String, JsonValue>, key: &str) -> Result<&'a JsonValue, ConfigError> {
+    return match hashmap.get(key) {
+        Some(v) => Ok(v),
+        None => Err(ConfigError::MissingValue(key.to_string()))
+    };
+}
+
+/// Reads a config file into a map of config keys to values.
+/// The file is TOML if its extension is `.toml`, YAML if it is `.yaml` or `.yml`, and JSON otherwise.
+/// * `filepath` - path to the config file
+pub fn read_config_file(filepath: &str) -> Result<HashMap<String, JsonValue>, ConfigError> {
+    let contents = match fs::read_to_string(filepath) {
+        Ok(c) => c,
+        Err(e) => {
+            return Err(ConfigError::CannotOpenFile(e.to_string()));
+        },
+    };
+
+    let json = match config_formats::parse(&contents, ConfigFormat::from_path(Path::new(filepath))) {
+        Ok(j) => j,
+        Err(e) => {
+            return Err(ConfigError::ParsingFailure(e.to_string()));
+        },
+    };
+    return match json.try_into() {
+        Ok(h) => Ok(h),
+        Err(e) => Err(ConfigError::ParsingFailure(e.to_string()))
+    };
+}
+
+/// Config keys holding paths, which are expanded and resolved by `resolve_config_paths`.
+const PATH_KEYS: [&str; 2] = ["inputFile", "outputFile"];
+
+/// Replaces every `${VAR}` in `value` with the value of the environment variable `VAR`.
+/// * `key` - config key the value belongs to, used in errors
+/// * `value` - value to expand
+fn expand_environment_variables(key: &str, value: &str) -> Result<String, ConfigError> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(ConfigError::InvalidValue(key.to_string(), format!("unclosed `${{` in {}", value)))
+        };
+        let name = &rest[start + 2..end];
+        match env::var(name) {
+            Ok(v) => expanded.push_str(&v),
+            Err(_) => return Err(ConfigError::InvalidValue(
+                key.to_string(),
+                format!("environment variable {} is not set", name)
+            ))
+        };
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    return Ok(expanded);
+}
+
+/// Expands environment variables in the paths of a config file and makes relative paths
+/// relative to the folder of the config file instead of the current working directory,
+/// so the same config works no matter where raw2bvp is run from.
+/// * `config` - config values read from the config file
+/// * `config_filepath` - path to the config file
+pub fn resolve_config_paths(config: &mut HashMap<String, JsonValue>, config_filepath: &str) -> Result<(), ConfigError> {
+    let config_folder = Path::new(config_filepath).parent().unwrap_or(Path::new(""));
+    for key in PATH_KEYS {
+        resolve_config_path(config, key, config_folder)?;
+    }
+    if let Some(JsonValue::Array(modalities)) = config.get_mut("modalities") {
+        for modality in modalities {
+            if let JsonValue::Object(modality) = modality {
+                resolve_config_path(modality, "inputFile", config_folder)?;
+            }
+        }
+    }
+    return Ok(());
+}
+
+/// Expands environment variables in a single path and resolves it relative to the config folder.
+/// * `config` - object holding the path
+/// * `key` - key of the path in `config`
+/// * `config_folder` - folder of the config file
+fn resolve_config_path(config: &mut HashMap<String, JsonValue>, key: &str, config_folder: &Path) -> Result<(), ConfigError> {
+    let path = match config.get(key) {
+        Some(JsonValue::String(s)) => expand_environment_variables(key, s)?,
+        // Wrong types are reported by validation.
+        _ => return Ok(())
+    };
+    // URLs and stdin/stdout are not relative to anything.
+    let resolved = if Path::new(&path).is_relative() && !remote::is_remote(&path) && path != STDIO_PATH {
+        config_folder.join(&path).to_string_lossy().to_string()
+    } else {
+        path
+    };
+    config.insert(key.to_string(), JsonValue::from(resolved));
+    return Ok(());
+}
+
+/// Creates conversion parameters from config values, as read from a config file
+/// or given on the command line.
+/// * `hashmap` - config keys mapped to their values
+pub fn parse_config_values(hashmap: &HashMap<String, JsonValue>) -> Result<Parameters, ConfigError> {
+    let problems = validate_config(hashmap);
+    if !problems.is_empty() {
+        return Err(ConfigError::InvalidConfig(problems));
+    }
+
+    let input_file = json_aux::get_string_from_json(required(hashmap, "inputFile")?).map_err(|x| ConfigError::InvalidJson(x))?;
+    let output_file = json_aux::get_string_from_json(required(hashmap, "outputFile")?).map_err(|x| ConfigError::InvalidJson(x))?;
+    let dimensions = json_aux::get_u32_dimensions_from_json(required(hashmap, "dimensions")?).map_err(|x| ConfigError::InvalidJson(x))?;
+    let block_dimensions = json_aux::get_u32_dimensions_from_json(required(hashmap, "blockDimensions")?).map_err(|x| ConfigError::InvalidJson(x))?;
+    let superblock_dimensions = match hashmap.get("superblockDimensions") {
+        Some(s) => {
+            let levels = json_aux::get_array_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            let mut superblock_dimensions = Vec::with_capacity(levels.len());
+            for level in &levels {
+                superblock_dimensions.push(json_aux::get_u32_dimensions_from_json(level).map_err(|x| ConfigError::InvalidJson(x))?);
+            }
+            superblock_dimensions
+        },
+        None => Vec::new()
+    };
+    let input_format = Format::from_json(required(hashmap, "format")?).map_err(|x| ConfigError::FormatError(x))?;
+    let archive = match hashmap.get("archive") {
+        Some(s) => {
+            match json_aux::get_string_from_json(s) {
+                Ok(a) => ArchiveEnum::from_string(a).map_err(|x| ConfigError::ArchiveError(x))?,
+                Err(e) => return Err(ConfigError::InvalidJson(e))
+            }
+        },
+        None => ArchiveEnum::None
+    };
+    let archive_compression = match hashmap.get("archiveCompression") {
+        Some(s) => {
+            let s = json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            MemberCompression::from_string(&s).map_err(|x| ConfigError::ArchiveError(ArchiveError::ZipError(x)))?
+        },
+        None => MemberCompression::Stored
+    };
+    let compression = match hashmap.get("compression") {
+        Some(s) => {
+            let s = json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            CompressionType::from_string(&s).map_err(|x| ConfigError::CompressionError(x))?
+        },
+        None => CompressionType::None
+    };
+    let name = match hashmap.get("name") {
+        Some(s) => {
+            let s = json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            Some(s)
+        },
+        None if input_file == STDIO_PATH => None,
+        None => watch::input_stem(&input_file)
+    };
+    let description = match hashmap.get("description") {
+        Some(s) => {
+            let s = json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            Some(s)
+        },
+        None => None
+    };
+    let semantic_type = match hashmap.get("semanticType") {
+        Some(s) => {
+            let s = json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            Some(s)
+        },
+        None => None
+    };
+    let volume_scale = match hashmap.get("volumeScale") {
+        Some(s) => {
+            Vector3::<f32>::from_json(s).map_err(|x| ConfigError::InvalidJson(x))?
+        },
+        None => Vector3::<f32>{ x: 1.0f32, y: 1.0f32, z: 1.0f32 }
+    };
+    let voxel_scale = match hashmap.get("voxelScale") {
+        Some(s) => {
+            Some(Vector3::<f32>::from_json(s).map_err(|x| ConfigError::InvalidJson(x))?)
+        },
+        None => None
+    };
+    let transform = parse_transform(hashmap, voxel_scale)?;
+    let window = parse_window(hashmap)?;
+    let author = match hashmap.get("author") {
+        Some(s) => {
+            Some(json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?)
+        },
+        None => None
+    };
+    let copyright = match hashmap.get("copyright") {
+        Some(s) => {
+            Some(json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?)
+        },
+        None => None
+    };
+    let acquisition_time = match hashmap.get("acquisitionTime") {
+        Some(s) => {
+            Some(json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?)
+        },
+        None => None
+    };
+    let generator = match hashmap.get("generator") {
+        Some(s) => {
+            Some(json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?)
+        },
+        None => None
+    };
+
+    let threads = match hashmap.get("threads") {
+        Some(s) => {
+            let threads = json_aux::get_u32_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            Some(threads as usize)
+        },
+        None => None
+    };
+    let queue_capacity = match hashmap.get("queueCapacity") {
+        Some(s) => {
+            let queue_capacity = json_aux::get_u32_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            Some(queue_capacity as usize)
+        },
+        None => None
+    };
+    let compression_level = match hashmap.get("compressionLevel") {
+        Some(s) => {
+            json_aux::get_u32_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?
+        },
+        None => MAX_COMPRESSION_LEVEL
+    };
+    let volume_checksum = match hashmap.get("volumeChecksum") {
+        Some(s) => {
+            let s = json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            if s == "none" {
+                None
+            } else {
+                Some(DigestType::from_string(&s).map_err(|x| ConfigError::ChecksumError(x))?)
+            }
+        },
+        None => None
+    };
+    let checksum = match hashmap.get("checksum") {
+        Some(s) => {
+            let s = json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            if s == "none" {
+                None
+            } else {
+                Some(ChecksumType::from_string(&s).map_err(|x| ConfigError::ChecksumError(x))?)
+            }
+        },
+        // The signature covers the data only through SHA-256 checksums.
+        None if hashmap.contains_key("signingKey") => Some(ChecksumType::Sha256),
+        None => None
+    };
+
+    let deduplication = match hashmap.get("deduplication") {
+        Some(JsonValue::Boolean(b)) => *b,
+        Some(other) => return Err(ConfigError::InvalidValue("deduplication".to_string(), format!("expected true or false, got {:?}", other))),
+        None => true
+    };
+    let dedup_memory_blocks = match hashmap.get("dedupMemoryBlocks") {
+        Some(s) => {
+            json_aux::get_u32_from_json(s).map_err(|x| ConfigError::InvalidJson(x))? as usize
+        },
+        None => DEFAULT_DEDUP_MEMORY_BLOCKS
+    };
+    let checkpoint = match hashmap.get("checkpoint") {
+        Some(JsonValue::Boolean(b)) => *b,
+        Some(other) => return Err(ConfigError::InvalidValue("checkpoint".to_string(), format!("expected true or false, got {:?}", other))),
+        None => false
+    };
+    let record_source = match hashmap.get("recordSource") {
+        Some(JsonValue::Boolean(b)) => *b,
+        Some(other) => return Err(ConfigError::InvalidValue("recordSource".to_string(), format!("expected true or false, got {:?}", other))),
+        None => false
+    };
+    let allow_size_mismatch = match hashmap.get("allowSizeMismatch") {
+        Some(JsonValue::Boolean(b)) => *b,
+        Some(other) => return Err(ConfigError::InvalidValue("allowSizeMismatch".to_string(), format!("expected true or false, got {:?}", other))),
+        None => false
+    };
+    let block_naming = match hashmap.get("blockNaming") {
+        Some(s) => {
+            let s = json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            BlockNaming::from_string(&s).ok_or_else(|| ConfigError::InvalidValue("blockNaming".to_string(), s))?
+        },
+        None => BlockNaming::Index
+    };
+    let block_order = match hashmap.get("blockOrder") {
+        Some(s) => {
+            let s = json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            BlockOrder::from_string(&s).ok_or_else(|| ConfigError::InvalidValue("blockOrder".to_string(), s))?
+        },
+        None => BlockOrder::Completion
+    };
+    let voxel_layout = match hashmap.get("voxelLayout") {
+        Some(s) => {
+            let s = json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            VoxelLayout::from_string(&s).map_err(|_| ConfigError::InvalidValue("voxelLayout".to_string(), s))?
+        },
+        None => VoxelLayout::Linear
+    };
+    let occupancy_grid = match hashmap.get("occupancyGrid") {
+        Some(g) => Some(json_aux::get_u32_dimensions_from_json(g).map_err(|x| ConfigError::InvalidJson(x))?),
+        None => None
+    };
+    let texture_compression = match hashmap.get("textureCompression") {
+        Some(s) => {
+            let s = json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            if s == "none" {
+                None
+            } else {
+                Some(TextureCompression::from_string(&s).ok_or_else(|| ConfigError::InvalidValue("textureCompression".to_string(), s))?)
+            }
+        },
+        None => None
+    };
+    let spec_version = match hashmap.get("specVersion") {
+        Some(s) => {
+            let s = json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            SpecVersion::writable(&s).map_err(|x| ConfigError::VersionError(x))?
+        },
+        None => SpecVersion::CURRENT
+    };
+    let signing_key = match hashmap.get("signingKey") {
+        Some(s) => {
+            let path = json_aux::get_string_from_json(s).map_err(|x| ConfigError::InvalidJson(x))?;
+            Some(read_signing_key(&path).map_err(|e| ConfigError::InvalidValue("signingKey".to_string(), e))?)
+        },
+        None => None
+    };
+
+    let tiles = match hashmap.get("tiled") {
+        Some(JsonValue::Boolean(true)) => {
+            Some(TiledVolume::open(&input_file, dimensions, &input_format, block_dimensions).map_err(|e| ConfigError::InvalidValue("inputFile".to_string(), e))?)
+        },
+        Some(JsonValue::Boolean(false)) | None => None,
+        Some(other) => return Err(ConfigError::InvalidValue("tiled".to_string(), format!("expected true or false, got {:?}", other)))
+    };
+
+    let input_layout = parse_input_layout(hashmap, InputLayout::default())?;
+    let mut additional_modalities = Vec::new();
+    if let Some(modalities) = hashmap.get("modalities") {
+        let modalities = json_aux::get_array_from_json(modalities).map_err(|x| ConfigError::InvalidJson(x))?;
+        for modality in &modalities {
+            let modality: &HashMap<String, JsonValue> = modality.get().ok_or_else(|| {
+                ConfigError::InvalidJson(JsonError::NotAnObject(modality.clone()))
+            })?;
+            let input_file = json_aux::get_string_from_json(required(modality, "inputFile")?).map_err(|x| ConfigError::InvalidJson(x))?;
+            let input_files = match expand_input_pattern(&input_file)? {
+                Some(files) => files,
+                None => {
+                    additional_modalities.push(parse_modality_input(modality, dimensions, &input_format, input_layout, volume_scale)?);
+                    continue;
+                }
+            };
+            // Every matching file becomes a modality, with the other keys of the object as a template.
+            for file in input_files {
+                let mut modality = modality.clone();
+                if let Some(JsonValue::String(name)) = modality.get("name") {
+                    let name = watch::output_path(name, Path::new(&file));
+                    modality.insert("name".to_string(), name.into());
+                }
+                modality.insert("inputFile".to_string(), file.into());
+                additional_modalities.push(parse_modality_input(&modality, dimensions, &input_format, input_layout, volume_scale)?);
+            }
+        }
+    }
+
+    let mut builder = Parameters::builder(input_file, output_file, dimensions, block_dimensions, input_format)
+        .with_name(name)
+        .with_description(description)
+        .with_semantic_type(semantic_type)
+        .with_volume_scale(volume_scale)
+        .with_voxel_scale(voxel_scale)
+        .with_transform(transform)
+        .with_window(window)
+        .with_input_layout(input_layout)
+        .with_rescale(parse_rescale(hashmap)?)
+        .with_filters(parse_filters(hashmap)?)
+        .with_isosurface_mask(parse_isosurface_mask(hashmap)?)
+        .with_superblock_dimensions(superblock_dimensions)
+        .with_archive(archive)
+        .with_archive_compression(archive_compression)
+        .with_compression(compression)
+        .with_author(author)
+        .with_copyright(copyright)
+        .with_acquisition_time(acquisition_time)
+        .with_generator(generator)
+        .with_threads(threads)
+        .with_queue_capacity(queue_capacity)
+        .with_compression_level(compression_level)
+        .with_checksum(checksum)
+        .with_volume_checksum(volume_checksum)
+        .with_record_source(record_source)
+        .with_allow_size_mismatch(allow_size_mismatch)
+        .with_deduplication(deduplication)
+        .with_dedup_memory_blocks(dedup_memory_blocks)
+        .with_block_naming(block_naming)
+        .with_block_order(block_order)
+        .with_voxel_layout(voxel_layout)
+        .with_occupancy_grid(occupancy_grid)
+        .with_texture_compression(texture_compression)
+        .with_spec_version(spec_version)
+        .with_signing_key(signing_key)
+        .with_tiles(tiles)
+        .with_checkpoint(checkpoint)
+        .with_settings_hash(settings_hash(hashmap));
+    for modality in additional_modalities {
+        builder = builder.with_modality(modality);
+    }
+    // The config was validated by key above, this only fails for values that cannot be checked there.
+    let arguments = builder.build().map_err(|e| ConfigError::InvalidConfig(vec![e.to_string()]))?;
+    return Ok(arguments);
+}
+
+/// Returns a hash of the config values, leaving out those that only change how the
+/// conversion runs and not its output.
+/// * `hashmap` - the config
+fn settings_hash(hashmap: &HashMap<String, JsonValue>) -> u64 {
+    let settings: HashMap<String, JsonValue> = hashmap.iter()
+        .filter(|(key, _)| !["threads", "queueCapacity", "checkpoint", "dedupMemoryBlocks"].contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    return xxh3::xxh3_64(json_aux::canonical_string(&JsonValue::from(settings)).as_bytes());
+}
+
+/// Returns the files matching an input path whose file name has `*` or `?` wildcards, sorted by name,
+/// or None if it has none. URLs and stdin are never patterns.
+/// * `input_file` - the input path
+fn expand_input_pattern(input_file: &str) -> Result<Option<Vec<String>>, ConfigError> {
+    let path = Path::new(input_file);
+    let pattern = match path.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => return Ok(None)
+    };
+    if remote::is_remote(input_file) || input_file == STDIO_PATH || !pattern.contains(['*', '?']) {
+        return Ok(None);
+    }
+    let folder = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new(".")
+    };
+    let entries = fs::read_dir(folder)
+        .map_err(|e| ConfigError::InvalidValue("modalities.inputFile".to_string(), format!("{}: {}", folder.display(), e)))?;
+    let mut files: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file() && watch::matches_pattern(&pattern, &entry.file_name().to_string_lossy()))
+        .map(|entry| path.with_file_name(entry.file_name()).to_string_lossy().to_string())
+        .collect();
+    if files.is_empty() {
+        return Err(ConfigError::InvalidValue("modalities.inputFile".to_string(), format!("no files match {}", input_file)));
+    }
+    files.sort();
+    return Ok(Some(files));
+}
+
+/// Creates the input of an additional modality from its config object.
+/// Dimensions, format, input layout and volume scale default to the ones of the top level volume.
+/// * `hashmap` - keys of the modality object mapped to their values
+/// * `dimensions`, `input_format`, `input_layout`, `volume_scale` - values of the top level volume
+fn parse_modality_input(hashmap: &HashMap<String, JsonValue>, dimensions: Vector3<u32>, input_format: &Format,
+    input_layout: InputLayout, volume_scale: Vector3<f32>) -> Result<ModalityInput, ConfigError>
+{
+    let input_file = json_aux::get_string_from_json(required(hashmap, "inputFile")?).map_err(|x| ConfigError::InvalidJson(x))?;
+    let dimensions = match hashmap.get("dimensions") {
+        Some(d) => json_aux::get_u32_dimensions_from_json(d).map_err(|x| ConfigError::InvalidJson(x))?,
+        None => dimensions
+    };
+    let input_format = match hashmap.get("format") {
+        Some(f) => Format::from_json(f).map_err(|x| ConfigError::FormatError(x))?,
+        None => input_format.clone()
+    };
+    let optional_string = |key: &str| {
+        return match hashmap.get(key) {
+            Some(s) => json_aux::get_string_from_json(s).map(Some).map_err(|x| ConfigError::InvalidJson(x)),
+            None => Ok(None)
+        };
+    };
+    let name = match optional_string("name")? {
+        Some(n) => Some(n),
+        None if input_file == STDIO_PATH => None,
+        None => watch::input_stem(&input_file)
+    };
+    let volume_scale = match hashmap.get("volumeScale") {
+        Some(s) => Vector3::<f32>::from_json(s).map_err(|x| ConfigError::InvalidJson(x))?,
+        None => volume_scale
+    };
+    let voxel_scale = match hashmap.get("voxelScale") {
+        Some(s) => Some(Vector3::<f32>::from_json(s).map_err(|x| ConfigError::InvalidJson(x))?),
+        None => None
+    };
+    return Ok(ModalityInput {
+        input_file,
+        dimensions,
+        input_format,
+        layout: parse_input_layout(hashmap, input_layout)?,
+        rescale: parse_rescale(hashmap)?,
+        filters: parse_filters(hashmap)?,
+        mask: None,
+        name,
+        description: optional_string("description")?,
+        semantic_type: optional_string("semanticType")?,
+        volume_scale,
+        voxel_scale,
+        transform: parse_transform(hashmap, voxel_scale)?,
+        window: parse_window(hashmap)?
+    });
+}
+
+/// Reads where the voxels of a volume are in its input file, from `inputOffset`, `inputStride` and `inputPlanePadding`.
+/// * `hashmap` - keys of the volume mapped to their values
+/// * `default` - layout used for the keys that are not given
+fn parse_input_layout(hashmap: &HashMap<String, JsonValue>, default: InputLayout) -> Result<InputLayout, ConfigError> {
+    let count = |key: &str| {
+        return match hashmap.get(key) {
+            Some(n) => json_aux::get_u32_from_json(n).map(|n| Some(n as u64)).map_err(|x| ConfigError::InvalidJson(x)),
+            None => Ok(None)
+        };
+    };
+    return Ok(InputLayout {
+        offset: count("inputOffset")?.unwrap_or(default.offset),
+        row_stride: count("inputStride")?.or(default.row_stride),
+        plane_padding: count("inputPlanePadding")?.unwrap_or(default.plane_padding)
+    });
+}
+
+/// Reads the map applied to the voxel values of a volume, from `rescaleSlope` and `rescaleIntercept`,
+/// or None if neither is given.
+/// * `hashmap` - keys of the volume mapped to their values
+fn parse_rescale(hashmap: &HashMap<String, JsonValue>) -> Result<Option<Rescale>, ConfigError> {
+    let number = |key: &str| {
+        return match hashmap.get(key) {
+            Some(n) => json_aux::get_f64_from_json(n).map(Some).map_err(|x| ConfigError::InvalidJson(x)),
+            None => Ok(None)
+        };
+    };
+    return match (number("rescaleSlope")?, number("rescaleIntercept")?) {
+        (None, None) => Ok(None),
+        (slope, intercept) => Ok(Some(Rescale::new(slope.unwrap_or(1.0), intercept.unwrap_or(0.0))))
+    };
+}
+
+/// Reads the `filters` applied to the voxels of a volume, in order.
+/// * `hashmap` - keys of the volume mapped to their values
+fn parse_filters(hashmap: &HashMap<String, JsonValue>) -> Result<Vec<Filter>, ConfigError> {
+    let filters = match hashmap.get("filters") {
+        Some(f) => json_aux::get_array_from_json(f).map_err(|x| ConfigError::InvalidJson(x))?,
+        None => return Ok(Vec::new())
+    };
+    return filters.iter()
+        .map(|f| Filter::from_json(f).map_err(|e| ConfigError::InvalidValue("filters".to_string(), e.to_string())))
+        .collect();
+}
+
+/// Reads the `isosurfaceMask` of the top level volume, an isovalue or an object with the `isovalue`,
+/// `bits` and `name` of the mask.
+/// * `hashmap` - the config
+fn parse_isosurface_mask(hashmap: &HashMap<String, JsonValue>) -> Result<Option<IsosurfaceMask>, ConfigError> {
+    let object = match hashmap.get("isosurfaceMask") {
+        Some(JsonValue::Number(n)) => return Ok(Some(IsosurfaceMask::new(*n))),
+        Some(JsonValue::Object(o)) => o,
+        Some(other) => return Err(ConfigError::InvalidJson(JsonError::NotAnObject(other.clone()))),
+        None => return Ok(None)
+    };
+    let isovalue = json_aux::get_f64_from_json(required(object, "isovalue")?).map_err(|x| ConfigError::InvalidJson(x))?;
+    let mut mask = IsosurfaceMask::new(isovalue);
+    if let Some(bits) = object.get("bits") {
+        mask.bits = json_aux::get_u32_from_json(bits).map_err(|x| ConfigError::InvalidJson(x))?;
+    }
+    if let Some(name) = object.get("name") {
+        mask.name = Some(json_aux::get_string_from_json(name).map_err(|x| ConfigError::InvalidJson(x))?);
+    }
+    return Ok(Some(mask));
+}
+
+/// Reads the `transform` of a volume. Without `spacing`, the voxel scale is the spacing of its axes.
+/// * `hashmap` - keys of the volume mapped to their values
+/// * `voxel_scale` - voxel scale of the volume
+fn parse_transform(hashmap: &HashMap<String, JsonValue>, voxel_scale: Option<Vector3<f32>>) -> Result<Option<Transform>, ConfigError> {
+    let spacing = voxel_scale.map_or([1.0; 3], |v| [v.x as f64, v.y as f64, v.z as f64]);
+    return match hashmap.get("transform") {
+        Some(t) => Transform::from_json(t, spacing).map(Some).map_err(|e| ConfigError::InvalidValue("transform".to_string(), e.to_string())),
+        None => Ok(None)
+    };
+}
+
+/// Reads a signing key from a file with its seed in hexadecimal, see `signature::signing_key_from_hex`.
+/// * `path` - path of the key file
+pub fn read_signing_key(path: &str) -> Result<SigningKey, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    return signature::signing_key_from_hex(&text).map_err(|e| e.to_string());
+}
+
+/// Reads the window/level presets given by a `window` value: `"auto"`, the lowest and highest value shown
+/// such as `[0, 4095]`, or a list of `"auto"` and preset objects with a `center`, `width` and optional `name`.
+/// * `value` - the value
+pub fn window_settings_from_json(value: &JsonValue) -> Result<Vec<WindowSetting>, String> {
+    let setting = |index: usize, j: &JsonValue| {
+        return match j {
+            JsonValue::String(s) if s == AUTO_PRESET => Ok(WindowSetting::Auto),
+            JsonValue::Object(_) => WindowPreset::from_json(index, j).map(WindowSetting::Preset).map_err(|e| e.to_string()),
+            _ => Err(format!("expected `{}` or a preset object at {}", AUTO_PRESET, index))
+        };
+    };
+    return match value {
+        JsonValue::Array(a) => match a.as_slice() {
+            [JsonValue::Number(low), JsonValue::Number(high)] => {
+                if !(low < high) || !low.is_finite() || !high.is_finite() {
+                    return Err("the lowest value has to be below the highest".to_string());
+                }
+                Ok(vec![WindowSetting::Preset(WindowPreset { name: None, window: Window::new(*low, *high) })])
+            },
+            _ => a.iter().enumerate().map(|(index, j)| setting(index, j)).collect()
+        },
+        _ => Ok(vec![setting(0, value)?])
+    };
+}
+
+/// Reads the `window` of a volume, without one it has no presets.
+/// * `hashmap` - keys of the volume mapped to their values
+fn parse_window(hashmap: &HashMap<String, JsonValue>) -> Result<Vec<WindowSetting>, ConfigError> {
+    return match hashmap.get("window") {
+        Some(w) => window_settings_from_json(w).map_err(|e| ConfigError::InvalidValue("window".to_string(), e)),
+        None => Ok(Vec::new())
+    };
+}
@@ -0,0 +1,793 @@
+use std::collections::HashMap;
+
+use tinyjson::JsonValue;
+
+use bvp::archives::{ArchiveEnum, STDIO_PATH, zip::MemberCompression};
+use bvp::checksum::ChecksumType;
+use bvp::digest::DigestType;
+use bvp::compressions::{CompressionType, MIN_COMPRESSION_LEVEL, MAX_COMPRESSION_LEVEL};
+use bvp::convert::{filters::{self, Filter}, mask::BITMASK_MICROBLOCK_DIMENSIONS, rescale::Rescale, InputLayout, WindowSetting, MAX_OCCUPANCY_GRID};
+use bvp::formats::{Format, FormatFamily, PrimitiveType};
+use bvp::texture::TextureCompression;
+use bvp::transform::Transform;
+use bvp::vector3::Vector3;
+use bvp::version::SpecVersion;
+use bvp::log_warn;
+use bvp::remote;
+
+use super::arguments::{read_signing_key, window_settings_from_json};
+
+/// Config keys raw2bvp knows about. Other keys are reported as warnings, since they are most likely typos.
+const KNOWN_KEYS: [&str; 46] = [
+    "inputFile", "outputFile", "dimensions", "blockDimensions", "superblockDimensions", "format", "archive",
+    "compression", "name", "description", "semanticType", "volumeScale", "voxelScale", "transform", "window", "author",
+    "copyright", "acquisitionTime", "threads", "queueCapacity", "compressionLevel", "checksum",
+    "modalities", "deduplication", "checkpoint", "blockNaming", "textureCompression", "specVersion",
+    "signingKey", "tiled", "archiveCompression", "dedupMemoryBlocks", "blockOrder",
+    "voxelLayout", "generator", "inputOffset", "inputStride", "inputPlanePadding",
+    "rescaleSlope", "rescaleIntercept", "filters", "isosurfaceMask", "occupancyGrid",
+    "volumeChecksum", "recordSource", "allowSizeMismatch",
+];
+/// Keys of the objects in `modalities`.
+const MODALITY_KEYS: [&str; 16] = [
+    "inputFile", "dimensions", "format", "name", "description", "semanticType", "volumeScale", "voxelScale", "transform",
+    "window", "inputOffset", "inputStride", "inputPlanePadding", "rescaleSlope", "rescaleIntercept", "filters",
+];
+/// Keys that give where the voxels are in an input file, see `InputLayout`.
+const INPUT_LAYOUT_KEYS: [&str; 3] = ["inputOffset", "inputStride", "inputPlanePadding"];
+
+/// Collects all problems found in a config, each prefixed with the path of the offending field.
+struct ConfigValidator {
+    problems: Vec<String>
+}
+
+impl ConfigValidator {
+    fn problem(&mut self, path: &str, message: &str) {
+        self.problems.push(format!("{}: {}", path, message));
+    }
+
+    /// Checks that a present value is a string and returns it.
+    fn string<'a>(&mut self, path: &str, value: &'a JsonValue) -> Option<&'a str> {
+        return match value {
+            JsonValue::String(s) => Some(s.as_str()),
+            _ => {
+                self.problem(path, "must be a string");
+                None
+            }
+        };
+    }
+
+    /// Checks that a present value is a boolean and returns it.
+    fn boolean(&mut self, path: &str, value: &JsonValue) -> Option<bool> {
+        return match value {
+            JsonValue::Boolean(b) => Some(*b),
+            _ => {
+                self.problem(path, "must be `true` or `false`");
+                None
+            }
+        };
+    }
+
+    /// Checks that a present value is a whole, non-negative number and returns it.
+    fn integer(&mut self, path: &str, value: &JsonValue) -> Option<u32> {
+        return match value {
+            JsonValue::Number(n) if n.fract() == 0.0 && *n >= 0.0 && *n <= u32::MAX as f64 => Some(*n as u32),
+            JsonValue::Number(_) => {
+                self.problem(path, "must be a non-negative integer");
+                None
+            },
+            _ => {
+                self.problem(path, "must be a number");
+                None
+            }
+        };
+    }
+
+    /// Checks that a present value is an array of three numbers and returns them.
+    fn vector3(&mut self, path: &str, value: &JsonValue) -> Option<[f64; 3]> {
+        let array = match value {
+            JsonValue::Array(a) if a.len() == 3 => a,
+            _ => {
+                self.problem(path, "must be an array of three numbers");
+                return None;
+            }
+        };
+        let mut components = [0.0; 3];
+        let mut valid = true;
+        for (i, component) in array.iter().enumerate() {
+            match component {
+                JsonValue::Number(n) => components[i] = *n,
+                _ => {
+                    self.problem(&format!("{}[{}]", path, i), "must be a number");
+                    valid = false;
+                }
+            }
+        }
+        return if valid { Some(components) } else { None };
+    }
+
+    /// Checks that a present value is an array of three positive integers and returns them.
+    fn dimensions(&mut self, path: &str, value: &JsonValue) -> Option<[u32; 3]> {
+        let components = self.vector3(path, value)?;
+        let mut dimensions = [0; 3];
+        let mut valid = true;
+        for (i, component) in components.iter().enumerate() {
+            if component.fract() != 0.0 || *component < 1.0 || *component > u32::MAX as f64 {
+                self.problem(&format!("{}[{}]", path, i), "must be a positive integer");
+                valid = false;
+            } else {
+                dimensions[i] = *component as u32;
+            }
+        }
+        return if valid { Some(dimensions) } else { None };
+    }
+
+    /// Checks `count`, `size` and `type` of a mono format or a component of a multi format.
+    /// * `path` - path of the object in the config
+    /// * `object` - the format or component object
+    fn mono_fields(&mut self, path: &str, object: &HashMap<String, JsonValue>) {
+        let count = match object.get("count") {
+            Some(v) => self.integer(&format!("{}.count", path), v),
+            None => {
+                self.problem(&format!("{}.count", path), "is required");
+                None
+            }
+        };
+        let size = match object.get("size") {
+            Some(v) => self.integer(&format!("{}.size", path), v),
+            None => {
+                self.problem(&format!("{}.size", path), "is required");
+                None
+            }
+        };
+        match object.get("type") {
+            Some(v) => match self.string(&format!("{}.type", path), v) {
+                Some("u") | Some("i") | Some("f") | None => (),
+                Some(tp) => self.problem(&format!("{}.type", path), &format!("must be `u`, `i` or `f`, got `{}`", tp))
+            },
+            None => self.problem(&format!("{}.type", path), "is required")
+        };
+        if count == Some(0) {
+            self.problem(&format!("{}.count", path), "must be at least 1");
+        }
+        if let (Some(count), Some(size)) = (count, size) {
+            if size == 0 || (count > 0 && size % count != 0) {
+                self.problem(&format!("{}.size", path), "must be a positive multiple of `count`");
+            }
+        }
+    }
+
+    /// Checks a transform, given by its `matrix` or by its `origin`, `direction` and `spacing`.
+    fn transform(&mut self, path: &str, value: &JsonValue) {
+        if let Err(e) = Transform::from_json(value, [1.0; 3]) {
+            self.problem(path, &e.to_string());
+        }
+    }
+
+    /// Checks window/level presets, see `window_settings_from_json`.
+    fn window(&mut self, path: &str, value: &JsonValue) {
+        if let Err(e) = window_settings_from_json(value) {
+            self.problem(path, &e);
+        }
+    }
+
+    /// Checks the fields of a format object and returns the format if they are all valid.
+    fn format(&mut self, path: &str, value: &JsonValue) -> Option<Format> {
+        let object = match value {
+            JsonValue::Object(o) => o,
+            _ => {
+                self.problem(path, "must be an object");
+                return None;
+            }
+        };
+        let problems_before = self.problems.len();
+
+        let family = match object.get("family") {
+            Some(v) => self.string(&format!("{}.family", path), v),
+            None => {
+                self.problem(&format!("{}.family", path), "is required");
+                None
+            }
+        };
+        match family {
+            Some("mono") => self.mono_fields(path, object),
+            Some("multi") => match object.get("components") {
+                Some(JsonValue::Array(components)) if !components.is_empty() => {
+                    for (i, component) in components.iter().enumerate() {
+                        let component_path = format!("{}.components[{}]", path, i);
+                        match component {
+                            JsonValue::Object(c) => self.mono_fields(&component_path, c),
+                            _ => self.problem(&component_path, "must be an object")
+                        }
+                    }
+                },
+                Some(_) => self.problem(&format!("{}.components", path), "must be a non-empty array of component objects"),
+                None => self.problem(&format!("{}.components", path), "is required")
+            },
+            Some("compressed") => {
+                match object.get("scheme") {
+                    Some(v) => { self.string(&format!("{}.scheme", path), v); },
+                    None => self.problem(&format!("{}.scheme", path), "is required")
+                };
+                match object.get("microblockDimensions") {
+                    Some(v) => { self.dimensions(&format!("{}.microblockDimensions", path), v); },
+                    None => self.problem(&format!("{}.microblockDimensions", path), "is required")
+                };
+                match object.get("microblockSize") {
+                    Some(v) => {
+                        if self.integer(&format!("{}.microblockSize", path), v) == Some(0) {
+                            self.problem(&format!("{}.microblockSize", path), "must be at least 1");
+                        }
+                    },
+                    None => self.problem(&format!("{}.microblockSize", path), "is required")
+                };
+            },
+            Some(family) => self.problem(&format!("{}.family", path), &format!("unsupported format family `{}`", family)),
+            None => ()
+        };
+
+        if self.problems.len() > problems_before {
+            return None;
+        }
+        return match Format::from_json(value) {
+            Ok(f) => Some(f),
+            Err(e) => {
+                self.problem(path, &e.to_string());
+                None
+            }
+        };
+    }
+}
+
+/// Returns the format blocks are split in. With texture compression, inputs have to be single
+/// channel `u8` volumes, which are encoded before they are split.
+/// * `validator` - collects the problems
+/// * `path` - path of the format in the config
+/// * `format` - the format of the input, if it is valid
+/// * `texture_compression` - the texture compression of the config, if any
+fn block_format(validator: &mut ConfigValidator, path: &str, format: Option<Format>,
+    texture_compression: Option<TextureCompression>) -> Option<Format>
+{
+    let (format, compression) = match (format, texture_compression) {
+        (Some(format), Some(compression)) => (format, compression),
+        (format, None) => return format,
+        (None, Some(compression)) => return Some(compression.format())
+    };
+    let is_u8 = match format.family() {
+        FormatFamily::Mono(m) => m.count() == 1 && m.size() == 1 && matches!(m.component_type(), PrimitiveType::Uint),
+        _ => false
+    };
+    if !is_u8 {
+        validator.problem(path, &format!("must be `u8` for texture compression `{}`", compression.to_string()));
+    }
+    return Some(compression.format());
+}
+
+/// Checks the keys of a volume that give where its voxels are in the input file.
+/// The stride is checked against the rows of the volume if its dimensions and format are given.
+/// * `validator` - collects the problems
+/// * `prefix` - path of the volume in the config followed by `.`, empty for the top level
+/// * `object` - keys of the volume mapped to their values
+/// * `dimensions`, `format` - dimensions and input format of the volume, if they are valid
+fn validate_input_layout(validator: &mut ConfigValidator, prefix: &str, object: &HashMap<String, JsonValue>,
+    dimensions: Option<[u32; 3]>, format: Option<&Format>)
+{
+    for key in INPUT_LAYOUT_KEYS {
+        if let Some(v) = object.get(key) {
+            validator.integer(&format!("{}{}", prefix, key), v);
+        }
+    }
+    let stride_path = format!("{}inputStride", prefix);
+    let stride = object.get("inputStride").and_then(|v| v.get::<f64>()).copied();
+    if let (Some(stride), Some(dimensions), Some(format)) = (stride, dimensions, format) {
+        let (row_size, _, _) = InputLayout::rows(Vector3::from_xyz(dimensions[0], dimensions[1], dimensions[2]), format);
+        if stride < row_size as f64 {
+            validator.problem(&stride_path, &format!("must be at least the size of a row, {} bytes", row_size));
+        }
+    }
+}
+
+/// Checks `rescaleSlope` and `rescaleIntercept` of a volume, and that its format can be rescaled.
+/// * `validator` - collects the problems
+/// * `prefix` - path of the volume in the config followed by `.`, empty for the top level
+/// * `object` - keys of the volume mapped to their values
+/// * `format` - input format of the volume, if it is valid
+/// * `texture_compression` - the texture compression of the config, if any
+fn validate_rescale(validator: &mut ConfigValidator, prefix: &str, object: &HashMap<String, JsonValue>,
+    format: Option<&Format>, texture_compression: Option<TextureCompression>)
+{
+    let keys: Vec<&str> = ["rescaleSlope", "rescaleIntercept"].into_iter().filter(|k| object.contains_key(*k)).collect();
+    for key in &keys {
+        if !matches!(object.get(*key), Some(JsonValue::Number(n)) if n.is_finite()) {
+            validator.problem(&format!("{}{}", prefix, key), "must be a number");
+        }
+    }
+    let path = match keys.first() {
+        Some(key) => format!("{}{}", prefix, key),
+        None => return
+    };
+    if let Some(format) = format.filter(|f| !Rescale::supports(f)) {
+        validator.problem(&path, &format!("cannot rescale the `{}` format, only mono formats with native component types", format.family().name()));
+    }
+    if texture_compression.is_some() {
+        validator.problem(&path, "cannot be used with `textureCompression`");
+    }
+}
+
+/// Checks the `filters` of a volume, and that its format can be filtered.
+/// * `validator` - collects the problems
+/// * `prefix` - path of the volume in the config followed by `.`, empty for the top level
+/// * `object` - keys of the volume mapped to their values
+/// * `format` - input format of the volume, if it is valid
+fn validate_filters(validator: &mut ConfigValidator, prefix: &str, object: &HashMap<String, JsonValue>, format: Option<&Format>) {
+    let path = format!("{}filters", prefix);
+    let filters = match object.get("filters") {
+        Some(JsonValue::Array(a)) => a,
+        Some(_) => {
+            validator.problem(&path, "must be an array of filter objects");
+            return;
+        },
+        None => return
+    };
+    for (i, filter) in filters.iter().enumerate() {
+        if let Err(e) = Filter::from_json(filter) {
+            validator.problem(&format!("{}[{}]", path, i), &e.to_string());
+        }
+    }
+    // Rescaled voxels are always in a format that can be filtered.
+    let rescaled = object.contains_key("rescaleSlope") || object.contains_key("rescaleIntercept");
+    if let Some(format) = format.filter(|f| !filters.is_empty() && !rescaled && !filters::supports(f)) {
+        validator.problem(&path, &format!("cannot filter the `{}` format, only mono formats with native component types", format.family().name()));
+    }
+}
+
+/// Checks the `isosurfaceMask` of the top level volume, an isovalue or an object with the `isovalue`,
+/// `bits` per voxel and `name` of the mask.
+/// * `validator` - collects the problems
+/// * `value` - the mask
+/// * `config` - config keys mapped to their values
+/// * `dimensions` - the dimensions and block dimensions of the config, if they are valid
+/// * `format` - input format of the volume, if it is valid
+/// * `texture_compression` - the texture compression of the config, if any
+fn validate_isosurface_mask(validator: &mut ConfigValidator, value: &JsonValue, config: &HashMap<String, JsonValue>,
+    dimensions: [Option<[u32; 3]>; 2], format: Option<&Format>, texture_compression: Option<TextureCompression>)
+{
+    let (isovalue, bits) = match value {
+        JsonValue::Number(n) => (Some(n), None),
+        JsonValue::Object(o) => {
+            for key in o.keys().filter(|k| !["isovalue", "bits", "name"].contains(&k.as_str())) {
+                log_warn!("unknown config key `isosurfaceMask.{}` is ignored", key);
+            }
+            if let Some(name) = o.get("name") {
+                validator.string("isosurfaceMask.name", name);
+            }
+            let bits = o.get("bits").and_then(|b| validator.integer("isosurfaceMask.bits", b));
+            if bits.is_some_and(|b| b != 1 && b != 8) {
+                validator.problem("isosurfaceMask.bits", "must be 1 or 8");
+            }
+            match o.get("isovalue") {
+                Some(JsonValue::Number(n)) => (Some(n), bits),
+                Some(_) => {
+                    validator.problem("isosurfaceMask.isovalue", "must be a number");
+                    (None, bits)
+                },
+                None => {
+                    validator.problem("isosurfaceMask.isovalue", "is required");
+                    (None, bits)
+                }
+            }
+        },
+        _ => {
+            validator.problem("isosurfaceMask", "must be an isovalue or an object with an `isovalue`");
+            return;
+        }
+    };
+    if isovalue.is_some_and(|n| !n.is_finite()) {
+        validator.problem("isosurfaceMask.isovalue", "must be finite");
+    }
+    // The mask is made from the voxels as they are stored, after rescaling.
+    let rescaled = config.contains_key("rescaleSlope") || config.contains_key("rescaleIntercept");
+    let readable = |f: &Format| f.first_component().is_some() && f.microblock_dimensions == Vector3::from_xyz(1, 1, 1);
+    if let Some(format) = format.filter(|f| !rescaled && !readable(f)) {
+        validator.problem("isosurfaceMask", &format!("cannot be made of a `{}` volume, its values cannot be read", format.family().name()));
+    }
+    if texture_compression.is_some() {
+        validator.problem("isosurfaceMask", "cannot be used with `textureCompression`");
+    }
+    if bits == Some(1) {
+        for (key, dimensions) in ["dimensions", "blockDimensions"].iter().zip(dimensions) {
+            if dimensions.is_some_and(|d| d[0] % BITMASK_MICROBLOCK_DIMENSIONS.x != 0) {
+                validator.problem("isosurfaceMask.bits", &format!("1 bit masks need `{}[0]` to be a multiple of 8", key));
+            }
+        }
+    }
+}
+
+/// Checks an object describing an additional modality. Only `inputFile` is required,
+/// the other keys default to the top level values.
+/// * `validator` - collects the problems
+/// * `path` - path of the object in the config
+/// * `value` - the object
+/// * `block_dimensions` - the block dimensions of the config, if they are valid
+fn validate_modality(validator: &mut ConfigValidator, path: &str, value: &JsonValue, block_dimensions: Option<[u32; 3]>,
+    texture_compression: Option<TextureCompression>)
+{
+    let modality = match value {
+        JsonValue::Object(o) => o,
+        _ => {
+            validator.problem(path, "must be an object");
+            return;
+        }
+    };
+    for key in modality.keys() {
+        if !MODALITY_KEYS.contains(&key.as_str()) {
+            log_warn!("unknown config key `{}.{}` is ignored", path, key);
+        }
+    }
+    match modality.get("inputFile") {
+        Some(v) => {
+            if let Some("") = validator.string(&format!("{}.inputFile", path), v) {
+                validator.problem(&format!("{}.inputFile", path), "must not be empty");
+            }
+        },
+        None => validator.problem(&format!("{}.inputFile", path), "is required")
+    }
+    for key in ["name", "description", "semanticType"] {
+        if let Some(v) = modality.get(key) {
+            validator.string(&format!("{}.{}", path, key), v);
+        }
+    }
+    let dimensions = modality.get("dimensions").and_then(|v| validator.dimensions(&format!("{}.dimensions", path), v));
+    let format_path = format!("{}.format", path);
+    let format = modality.get("format").and_then(|v| validator.format(&format_path, v));
+    validate_input_layout(validator, &format!("{}.", path), modality, dimensions, format.as_ref());
+    validate_rescale(validator, &format!("{}.", path), modality, format.as_ref(), texture_compression);
+    validate_filters(validator, &format!("{}.", path), modality, format.as_ref());
+    let format = block_format(validator, &format_path, format, texture_compression);
+    if let (Some(dimensions), Some(format)) = (dimensions, &format) {
+        let microblock = format.microblock_dimensions;
+        for (i, microblock) in microblock.to_array().iter().enumerate() {
+            if dimensions[i] % microblock != 0 {
+                validator.problem(
+                    &format!("{}.dimensions[{}]", path, i),
+                    &format!("must be a multiple of the microblock dimension {}", microblock)
+                );
+            }
+        }
+    }
+    if let (Some(block_dimensions), Some(format)) = (block_dimensions, format) {
+        let microblock = format.microblock_dimensions;
+        let microblock = microblock.to_array();
+        for i in 0..3 {
+            if block_dimensions[i] % microblock[i] != 0 {
+                validator.problem(
+                    &format_path,
+                    &format!("microblock dimension {} does not divide blockDimensions[{}]", microblock[i], i)
+                );
+            }
+        }
+    }
+    for key in ["volumeScale", "voxelScale"] {
+        let scale_path = format!("{}.{}", path, key);
+        if let Some(scale) = modality.get(key).and_then(|v| validator.vector3(&scale_path, v)) {
+            for (i, component) in scale.iter().enumerate() {
+                if *component <= 0.0 {
+                    validator.problem(&format!("{}[{}]", scale_path, i), "must be positive");
+                }
+            }
+        }
+    }
+    if let Some(transform) = modality.get("transform") {
+        validator.transform(&format!("{}.transform", path), transform);
+    }
+    if let Some(window) = modality.get("window") {
+        validator.window(&format!("{}.window", path), window);
+    }
+}
+
+/// Checks a config for missing required keys, wrong types and invalid values,
+/// and returns all problems found, each prefixed with the path of its field (e.g. `format.count`).
+/// Returns an empty vector if the config is valid.
+/// * `config` - config keys mapped to their values
+pub fn validate_config(config: &HashMap<String, JsonValue>) -> Vec<String> {
+    let mut validator = ConfigValidator { problems: Vec::new() };
+
+    for key in ["inputFile", "outputFile", "dimensions", "blockDimensions", "format"] {
+        if !config.contains_key(key) {
+            validator.problem(key, "is required, set it in the config file or with the matching command line flag");
+        }
+    }
+    for key in config.keys() {
+        if !KNOWN_KEYS.contains(&key.as_str()) {
+            log_warn!("unknown config key `{}` is ignored", key);
+        }
+    }
+
+    for key in ["inputFile", "outputFile", "name", "description", "semanticType", "author", "copyright", "acquisitionTime", "generator"] {
+        if let Some(value) = config.get(key) {
+            if let Some("") = validator.string(key, value) {
+                if key == "inputFile" || key == "outputFile" {
+                    validator.problem(key, "must not be empty");
+                }
+            }
+        }
+    }
+
+    // Unarchived assets are written as many files next to each other, only archives can be uploaded.
+    if let Some(output) = config.get("outputFile").and_then(|v| v.get::<String>()) {
+        let archive = config.get("archive").and_then(|v| v.get::<String>()).map(|a| a.to_lowercase());
+        if remote::is_remote(output) && !matches!(archive.as_deref(), Some("saf") | Some("zip")) {
+            validator.problem("outputFile", "can only be a URL if `archive` is `SAF` or `ZIP`");
+        }
+        if output == STDIO_PATH && !matches!(archive.as_deref(), Some("saf") | Some("zip")) {
+            validator.problem("outputFile", "can only be `-` (stdout) if `archive` is `SAF` or `ZIP`");
+        }
+        // The checkpoint is kept next to the output.
+        let checkpoint = config.get("checkpoint").and_then(|v| v.get::<bool>()).copied().unwrap_or(false);
+        if checkpoint && (remote::is_remote(output) || output == STDIO_PATH) {
+            validator.problem("checkpoint", "needs `outputFile` to be a local file");
+        }
+    }
+
+    let dimensions = config.get("dimensions").and_then(|v| validator.dimensions("dimensions", v));
+    let block_dimensions = config.get("blockDimensions").and_then(|v| validator.dimensions("blockDimensions", v));
+    let texture_compression = match config.get("textureCompression").and_then(|v| validator.string("textureCompression", v)) {
+        Some("none") | None => None,
+        Some(name) => match TextureCompression::from_string(name) {
+            Some(compression) => Some(compression),
+            None => {
+                validator.problem("textureCompression", &format!("must be `bc4` or `none`, got `{}`", name));
+                None
+            }
+        }
+    };
+    let format = config.get("format").and_then(|v| validator.format("format", v));
+    validate_input_layout(&mut validator, "", config, dimensions, format.as_ref());
+    validate_rescale(&mut validator, "", config, format.as_ref(), texture_compression);
+    validate_filters(&mut validator, "", config, format.as_ref());
+    if let Some(value) = config.get("isosurfaceMask") {
+        validate_isosurface_mask(&mut validator, value, config, [dimensions, block_dimensions], format.as_ref(), texture_compression);
+    }
+    let format = block_format(&mut validator, "format", format, texture_compression);
+
+    if let (Some(dimensions), Some(block_dimensions)) = (dimensions, block_dimensions) {
+        for i in 0..3 {
+            if block_dimensions[i] > dimensions[i] {
+                validator.problem(
+                    &format!("blockDimensions[{}]", i),
+                    &format!("must not be larger than dimensions[{}] ({})", i, dimensions[i])
+                );
+            }
+        }
+    }
+    if let (Some(block_dimensions), Some(format)) = (block_dimensions, &format) {
+        let microblock = format.microblock_dimensions;
+        let microblock = microblock.to_array();
+        for i in 0..3 {
+            if block_dimensions[i] % microblock[i] != 0 {
+                validator.problem(
+                    &format!("blockDimensions[{}]", i),
+                    &format!("must be a multiple of the microblock dimension {}", microblock[i])
+                );
+            }
+        }
+    }
+    // Blocks at the edges are cut to the volume, so it has to hold whole microblocks too.
+    if let (Some(dimensions), Some(format)) = (dimensions, &format) {
+        let microblock = format.microblock_dimensions;
+        let microblock = microblock.to_array();
+        for i in 0..3 {
+            if dimensions[i] % microblock[i] != 0 {
+                validator.problem(
+                    &format!("dimensions[{}]", i),
+                    &format!("must be a multiple of the microblock dimension {}", microblock[i])
+                );
+            }
+        }
+    }
+
+    // Every level has to hold whole superblocks of the level below, and the innermost whole blocks.
+    if let Some(value) = config.get("superblockDimensions") {
+        match value {
+            JsonValue::Array(levels) => {
+                let levels: Vec<Option<[u32; 3]>> = levels.iter().enumerate()
+                    .map(|(i, level)| validator.dimensions(&format!("superblockDimensions[{}]", i), level))
+                    .collect();
+                for (i, level) in levels.iter().enumerate() {
+                    let (inner, inner_name) = match levels.get(i + 1) {
+                        Some(inner) => (*inner, format!("superblockDimensions[{}]", i + 1)),
+                        None => (block_dimensions, "blockDimensions".to_string())
+                    };
+                    if let (Some(level), Some(inner)) = (level, inner) {
+                        for j in 0..3 {
+                            if level[j] % inner[j] != 0 {
+                                validator.problem(
+                                    &format!("superblockDimensions[{}][{}]", i, j),
+                                    &format!("must be a multiple of {}[{}] ({})", inner_name, j, inner[j])
+                                );
+                            }
+                        }
+                    }
+                }
+            },
+            _ => validator.problem("superblockDimensions", "must be an array of dimensions")
+        }
+    }
+
+    for key in ["volumeScale", "voxelScale"] {
+        if let Some(scale) = config.get(key).and_then(|v| validator.vector3(key, v)) {
+            for (i, component) in scale.iter().enumerate() {
+                if *component <= 0.0 {
+                    validator.problem(&format!("{}[{}]", key, i), "must be positive");
+                }
+            }
+        }
+    }
+    if let Some(transform) = config.get("transform") {
+        validator.transform("transform", transform);
+    }
+    if let Some(window) = config.get("window") {
+        validator.window("window", window);
+    }
+
+    if let Some(value) = config.get("archive") {
+        if let Some(archive) = validator.string("archive", value) {
+            if ArchiveEnum::from_string(archive.to_string()).is_err() {
+                validator.problem("archive", &format!("must be `SAF`, `ZIP` or `None`, got `{}`", archive));
+            }
+        }
+    }
+    if let Some(value) = config.get("archiveCompression") {
+        if let Some(compression) = validator.string("archiveCompression", value) {
+            let archive = config.get("archive").and_then(|v| v.get::<String>()).map(|a| a.to_lowercase());
+            if MemberCompression::from_string(compression).is_err() {
+                validator.problem("archiveCompression", &format!("must be `none` or `deflate`, got `{}`", compression));
+            } else if compression != "none" && archive.as_deref() != Some("zip") {
+                validator.problem("archiveCompression", "needs `archive` to be `ZIP`, other archives cannot compress their files");
+            }
+        }
+    }
+    if let Some(value) = config.get("compression") {
+        if let Some(compression) = validator.string("compression", value) {
+            if CompressionType::from_string(compression).is_err() {
+                validator.problem("compression", &format!("must be `LZ4S` or `RAW`, got `{}`", compression));
+            }
+        }
+    }
+    if let Some(value) = config.get("checksum") {
+        if let Some(checksum) = validator.string("checksum", value) {
+            if checksum != "none" && ChecksumType::from_string(checksum).is_err() {
+                validator.problem("checksum", &format!("must be `xxh3`, `crc32`, `sha256` or `none`, got `{}`", checksum));
+            } else if config.contains_key("signingKey") && ChecksumType::from_string(checksum).map_or(true, |c| !c.is_cryptographic()) {
+                validator.problem("checksum", &format!("must be `sha256` for a signed asset, got `{}`", checksum));
+            }
+        }
+    }
+    if let Some(value) = config.get("volumeChecksum") {
+        if let Some(checksum) = validator.string("volumeChecksum", value) {
+            if checksum != "none" && DigestType::from_string(checksum).is_err() {
+                validator.problem("volumeChecksum", &format!("must be `xxh3`, `sha256` or `none`, got `{}`", checksum));
+            }
+        }
+    }
+
+    if let Some(value) = config.get("specVersion") {
+        if let Some(version) = validator.string("specVersion", value) {
+            if SpecVersion::writable(version).is_err() {
+                let writable: Vec<String> = SpecVersion::WRITABLE.iter().map(|v| format!("`{}`", v.to_string())).collect();
+                validator.problem("specVersion", &format!("must be one of {}, got `{}`", writable.join(", "), version));
+            }
+        }
+    }
+
+    if let Some(value) = config.get("signingKey") {
+        if let Some(path) = validator.string("signingKey", value) {
+            if let Err(e) = read_signing_key(path) {
+                validator.problem("signingKey", &e);
+            }
+        }
+    }
+
+    if let Some(value) = config.get("modalities") {
+        match value {
+            JsonValue::Array(modalities) => {
+                for (i, modality) in modalities.iter().enumerate() {
+                    validate_modality(&mut validator, &format!("modalities[{}]", i), modality, block_dimensions, texture_compression);
+                }
+                // Stdin can only be read once.
+                let input_of = |v: &JsonValue| v.get::<HashMap<String, JsonValue>>().and_then(|o| o.get("inputFile")).and_then(|f| f.get::<String>()).cloned();
+                let main_input = config.get("inputFile").and_then(|f| f.get::<String>()).cloned();
+                let stdin_inputs = modalities.iter().map(input_of).chain([main_input]).filter(|f| f.as_deref() == Some(STDIO_PATH)).count();
+                if stdin_inputs > 1 {
+                    validator.problem("modalities", "only one input file can be `-` (stdin)");
+                }
+            },
+            _ => validator.problem("modalities", "must be an array of objects")
+        }
+    }
+
+    if let Some(value) = config.get("blockNaming") {
+        if let Some(naming) = validator.string("blockNaming", value) {
+            if naming != "index" && naming != "content" {
+                validator.problem("blockNaming", &format!("must be `index` or `content`, got `{}`", naming));
+            }
+        }
+    }
+    if let Some(value) = config.get("blockOrder") {
+        if let Some(order) = validator.string("blockOrder", value) {
+            if !["completion", "grid", "morton"].contains(&order) {
+                validator.problem("blockOrder", &format!("must be `completion`, `grid` or `morton`, got `{}`", order));
+            }
+        }
+    }
+    if let Some(value) = config.get("voxelLayout") {
+        if let Some(layout) = validator.string("voxelLayout", value) {
+            if !["linear", "morton"].contains(&layout) {
+                validator.problem("voxelLayout", &format!("must be `linear` or `morton`, got `{}`", layout));
+            }
+        }
+    }
+    if let Some(value) = config.get("occupancyGrid") {
+        if let Some(grid) = validator.dimensions("occupancyGrid", value) {
+            for (i, cells) in grid.iter().enumerate().filter(|(_, cells)| **cells > MAX_OCCUPANCY_GRID) {
+                validator.problem(&format!("occupancyGrid[{}]", i), &format!("must be at most {}, got {}", MAX_OCCUPANCY_GRID, cells));
+            }
+        }
+    }
+    if let Some(value) = config.get("checkpoint") {
+        validator.boolean("checkpoint", value);
+    }
+    if let Some(value) = config.get("deduplication") {
+        validator.boolean("deduplication", value);
+    }
+    if let Some(value) = config.get("recordSource") {
+        validator.boolean("recordSource", value);
+    }
+    if let Some(value) = config.get("allowSizeMismatch") {
+        validator.boolean("allowSizeMismatch", value);
+    }
+    // Tiled volumes are read block by block, never as a whole.
+    if let Some(true) = config.get("tiled").and_then(|v| validator.boolean("tiled", v)) {
+        let input = config.get("inputFile").and_then(|v| v.get::<String>());
+        if input.is_some_and(|i| remote::is_remote(i) || i == STDIO_PATH) {
+            validator.problem("tiled", "needs `inputFile` to be a local index file");
+        }
+        if config.get("checkpoint").and_then(|v| v.get::<bool>()).copied().unwrap_or(false) {
+            validator.problem("tiled", "cannot be used with `checkpoint`");
+        }
+        if texture_compression.is_some() {
+            validator.problem("tiled", "cannot be used with `textureCompression`");
+        }
+        if config.get("volumeChecksum").and_then(|v| v.get::<String>()).is_some_and(|c| c != "none") {
+            validator.problem("tiled", "cannot be used with `volumeChecksum`");
+        }
+        if config.get("recordSource").and_then(|v| v.get::<bool>()).copied().unwrap_or(false) {
+            validator.problem("tiled", "cannot be used with `recordSource`");
+        }
+        // Tiles are read in place, block by block, without a header, padding or rescaling.
+        for key in INPUT_LAYOUT_KEYS.iter().chain(["rescaleSlope", "rescaleIntercept", "filters", "isosurfaceMask"].iter()).filter(|k| config.contains_key(**k)) {
+            validator.problem("tiled", &format!("cannot be used with `{}`", key));
+        }
+        if let Some(Ok(settings)) = config.get("window").map(window_settings_from_json) {
+            if settings.iter().any(|s| matches!(s, WindowSetting::Auto)) {
+                validator.problem("window", "cannot be `auto` for tiled volumes, it needs the whole volume");
+            }
+        }
+    }
+
+    for key in ["threads", "queueCapacity", "dedupMemoryBlocks"] {
+        if let Some(0) = config.get(key).and_then(|v| validator.integer(key, v)) {
+            validator.problem(key, "must be at least 1");
+        }
+    }
+    if let Some(level) = config.get("compressionLevel").and_then(|v| validator.integer("compressionLevel", v)) {
+        if !(MIN_COMPRESSION_LEVEL..=MAX_COMPRESSION_LEVEL).contains(&level) {
+            validator.problem(
+                "compressionLevel",
+                &format!("must be between {} and {}", MIN_COMPRESSION_LEVEL, MAX_COMPRESSION_LEVEL)
+            );
+        }
+    }
+
+    return validator.problems;
+}
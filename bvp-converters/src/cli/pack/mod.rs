@@ -0,0 +1,302 @@
+mod arguments;
+mod config_validation;
+mod progress_bar;
+mod watch;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tinyjson::JsonValue;
+
+use bvp::archives::{ArchiveEnum, STDIO_PATH};
+use bvp::compressions::FileCompression;
+use bvp::convert::{self, ParallelMode, Parameters};
+use bvp::detect::{self, FileKind};
+use bvp::log::{self, Level};
+use bvp::{log_info, log_warn};
+use bvp::metrics::{BatchMetrics, BatchSink, PipelineMetrics};
+use bvp::prometheus;
+use bvp::progress::{NoProgress, ProgressSink};
+use bvp::remote;
+use bvp::report::{FileDigest, ReportSink};
+
+use self::arguments::ConfigError;
+use self::progress_bar::ProgressBar;
+use self::watch::{WatchOptions, NAME_PLACEHOLDER};
+use super::error::CliError;
+
+static HELP: &str = "raw2bvp\n------------\n Usage: raw2bvp [<config_file>] [options]\n  The config file is JSON, or TOML or YAML if it ends with `.toml`, `.yaml` or `.yml`.\n Options:\n  --parallel=pipeline|data - how to parallelize the conversion (default `pipeline`)\n  --progress / --no-progress - show or hide the progress line (default: shown if stderr is a terminal)\n  --report PATH - write a JSON report of the conversion (hashes, block counts, ratios, timings, settings), `-` for stdout\n  --stats - print the time spent in every stage, the bytes read and written, the compression ratio and the dedup hit rate to stderr\n  --metrics-address HOST:PORT - serve Prometheus metrics of the conversions at http://HOST:PORT/metrics\n  --watch DIR - convert every input that appears in DIR, with the config as a template, see the README\n  --watch-pattern PATTERN - names of the inputs to convert in watch mode, with `*` and `?` wildcards (default `*.raw`)\n  --watch-interval SECONDS - time between scans of the watched folder (default 2)\n  --watch-once - convert the inputs in the watched folder and exit instead of waiting for new ones\n  -v, -vv, -vvv / --verbose - print more diagnostics to stderr (info, debug, trace)\n  -q / --quiet - print only errors\n Config options (override the values in the config file, which can then be omitted):\n  --input-file PATH, --output-file PATH - input raw file, decompressed if it ends with `.gz` or `.zst`, and output file (required)\n  --dimensions XxYxZ, --block-dimensions XxYxZ - volume and block dimensions (required)\n  --superblock-dimensions XxYxZ[,XxYxZ...] - group blocks into superblocks, the outermost level first\n  --format FORMAT - voxel format, as a JSON object or a shorthand such as `u8`, `u16`, `f32` or `u8x3` (required)\n  --input-offset N, --input-stride N, --input-plane-padding N - bytes before the first voxel, from a row to the next, and after every plane of the input, see the README\n  --allow-size-mismatch true|false - convert input files that are shorter or longer than their volume, the missing voxels are zero (default `false`)\n  --rescale-slope N, --rescale-intercept N - map the voxel values to `value * slope + intercept` as they are read, see the README\n  --filters JSON - array of `gaussian`, `median` and `clamp` filters applied to the voxels before they are split, see the README\n  --isosurface-mask ISOVALUE|JSON - store a mask of the voxels at or above the isovalue as a further modality, see the README\n  --archive SAF|ZIP|None, --compression LZ4S|RAW\n  --archive-compression none|deflate - deflate the files inside ZIP archives, when that makes them smaller (default `none`)\n  --name, --description, --semantic-type, --author, --copyright, --acquisition-time, --generator TEXT\n  --volume-scale X,Y,Z, --voxel-scale X,Y,Z\n  --transform JSON - orientation of the volume, a 4x4 `matrix` or its `origin`, `direction` and `spacing`\n  --window auto|LOW,HIGH - default display range of the volume, computed from its histogram or given\n  --threads N - number of worker threads (default: number of cores)\n  --queue-capacity N - capacity of queues between pipeline stages, or batch size in data mode (default: unbounded)\n  --compression-level N - compression level from 1 (fastest) to 9 (smallest, default)\n  --checksum xxh3|crc32|sha256|none - record a checksum of every block file in the manifest (default `sha256` with `--signing-key`, otherwise `none`)\n  --volume-checksum xxh3|sha256|none - record a digest of the whole volume of every modality, which `bvp2raw --checksum` compares (default `none`)\n  --record-source true|false - record the name, size and hash of the input file in the `EXT_source` extension of the asset, and skip unchanged inputs in watch mode (default `false`)\n  --deduplication true|false - store blocks with the same data only once (default `true`)\n  --dedup-memory-blocks N - number of block hashes kept in memory for deduplication, the rest are moved to the disk (default 4194304)\n  --checkpoint true|false - keep finished blocks next to the output to resume interrupted conversions (default `false`)\n  --block-naming index|content - name block files by their index or by the hash of their content (default `index`)\n  --block-order completion|grid|morton - order of the block files in the archive, see the README (default `completion`)\n  --voxel-layout linear|morton - order of the voxels inside the blocks, see the README (default `linear`)\n  --occupancy-grid XxYxZ - record which cells of a grid over every block hold data, in the `EXT_occupancy` extension of the block, see the README\n  --texture-compression bc4|none - encode `u8` voxels as BC4 GPU textures in tiles of 4x4 voxels (default `none`)\n  --spec-version VERSION - version of the BVP specification the manifest is written in (default `1.0`)\n  --signing-key PATH - sign the manifest with the ed25519 key in the file, which needs `sha256` checksums, see the README\n  --tiled true|false - the input file is the JSON index of a volume stored as tiles, see the README (default `false`)\n This message can be viewed with flag `--help`.";
+
+/// Reads the output back for the report: the archive, or the manifest of unarchived assets.
+/// Returns `None` for outputs that cannot be read back, on stdout or remote.
+/// * `archive` - archive type of the output
+/// * `output_file` - the output
+fn output_digest(archive: &ArchiveEnum, output_file: &str) -> Option<FileDigest> {
+    let path = match archive {
+        ArchiveEnum::None => Path::new(output_file).join("manifest.json").to_string_lossy().to_string(),
+        _ if output_file == STDIO_PATH || remote::is_remote(output_file) => return None,
+        _ => output_file.to_string()
+    };
+    return fs::read(&path).ok().map(|data| FileDigest::new(&path, &data));
+}
+
+/// Returns the value of a command line option given either as
+/// `--option=value` or as `--option value`, or `None` if `arg` is a different option.
+/// * `arg` - the current argument
+/// * `option` - the option name, including the leading dashes
+/// * `arguments_iter` - the remaining arguments, used if the value is in the next argument
+fn option_value<'a, I: Iterator<Item = &'a String>>(arg: &str, option: &str, arguments_iter: &mut I) -> Result<Option<String>, CliError> {
+    if arg == option {
+        return match arguments_iter.next() {
+            Some(value) => Ok(Some(value.clone())),
+            None => Err(CliError::config(format!("Missing value for `{}`", option)))
+        };
+    }
+    return match arg.strip_prefix(option).and_then(|rest| rest.strip_prefix('=')) {
+        Some(value) => Ok(Some(value.to_string())),
+        None => Ok(None)
+    };
+}
+
+/// Fails if an input file cannot be read, and warns about inputs with the signature of a volume format,
+/// such as NRRD or NIfTI, or of a BVP archive, since they are read as raw voxels.
+/// Inputs from stdin and URLs are not checked.
+/// * `parameters` - the parameters of the conversion
+fn check_inputs(parameters: &Parameters) -> Result<(), CliError> {
+    // The input of a tiled volume is its JSON index.
+    let mut inputs = match parameters.tiles {
+        Some(_) => Vec::new(),
+        None => vec![&parameters.input_file]
+    };
+    inputs.extend(parameters.additional_modalities.iter().map(|m| &m.input_file));
+    for input in inputs {
+        if input == STDIO_PATH || remote::is_remote(input) {
+            continue;
+        }
+        let kind = detect::detect_file(Path::new(input))?;
+        // Compressed inputs are decompressed before they are read, only their contents are raw voxels.
+        match (FileCompression::from_path(input), kind) {
+            (FileCompression::Gzip, FileKind::Gzip) | (FileCompression::Zstd, FileKind::Zstd) => continue,
+            (FileCompression::None, _) => {},
+            (compression, kind) => return Err(CliError::config(
+                format!("{} is named like a {} file, but is a {} file", input, compression.to_string(), kind)
+            ))
+        }
+        if kind.is_volume_format() || matches!(kind, FileKind::Saf | FileKind::Zip) {
+            log_warn!("{} looks like a {} file, but is read as raw voxels", input, kind);
+        }
+    }
+    return Ok(());
+}
+
+/// Returns the error of a configuration. Config files that cannot be read are I/O errors.
+/// * `error` - the error
+fn config_error(error: ConfigError) -> CliError {
+    return match error {
+        ConfigError::CannotOpenFile(_) => CliError::io(error.to_string()),
+        e => CliError::config(e.to_string())
+    };
+}
+
+/// Converts a volume and writes its report, counting it in the batch metrics, if they are served.
+/// * `config` - the configuration, with the flags applied
+/// * `parallel_mode` - how the conversion is parallelized
+/// * `show_progress` - whether to draw the progress line
+/// * `report_path` - where to write the report, if anywhere
+/// * `show_stats` - whether to print the timings and throughput of the conversion to stderr
+/// * `batch` - totals of all conversions, served at the metrics endpoint
+fn convert(config: HashMap<String, JsonValue>, parallel_mode: ParallelMode, show_progress: bool, report_path: Option<&str>,
+    show_stats: bool, batch: Option<&BatchMetrics>) -> Result<(), CliError>
+{
+    let batch = match batch {
+        Some(batch) => batch,
+        None => return run_conversion(config, parallel_mode, show_progress, report_path, show_stats, None).map(|_| ())
+    };
+    batch.conversion_started();
+    let result = run_conversion(config, parallel_mode, show_progress, report_path, show_stats, Some(batch));
+    batch.conversion_finished(result.as_ref().ok());
+    return result.map(|_| ());
+}
+
+/// Converts a volume and writes its report, see `convert`. Returns the metrics of the conversion.
+fn run_conversion(config: HashMap<String, JsonValue>, parallel_mode: ParallelMode, show_progress: bool, report_path: Option<&str>,
+    show_stats: bool, batch: Option<&BatchMetrics>) -> Result<PipelineMetrics, CliError>
+{
+    let mut parameters = arguments::parse_config_values(&config).map_err(config_error)?;
+    parameters.parallel_mode = parallel_mode;
+    check_inputs(&parameters)?;
+    if report_path == Some(STDIO_PATH) && parameters.output_file == STDIO_PATH {
+        return Err(CliError::config("The report cannot be written to stdout together with the asset"));
+    }
+
+    let progress: Box<dyn ProgressSink> = if show_progress {
+        Box::new(ProgressBar::new())
+    } else {
+        Box::new(NoProgress)
+    };
+    let batch_sink;
+    let progress: &dyn ProgressSink = match batch {
+        Some(batch) => {
+            batch_sink = BatchSink::new(batch, progress.as_ref());
+            &batch_sink
+        },
+        None => progress.as_ref()
+    };
+    let report_sink = ReportSink::new(progress);
+    let archive = parameters.archive;
+    let output_file = parameters.output_file.clone();
+    let writer = archive.return_compressed_writer(&output_file, parameters.archive_compression);
+    let metrics = convert::raw_to_bvp(parameters, writer, &report_sink)?;
+    if show_stats {
+        eprintln!("{}", metrics.to_text());
+    }
+    if let Some(path) = report_path {
+        let mut settings = config;
+        settings.insert("parallel".to_string(), JsonValue::from(parallel_mode.to_string()));
+        let report = report_sink.report(&output_file, output_digest(&archive, &output_file), settings);
+        let text = report.to_json().format().map_err(|e| format!("Cannot write the report: {}", e))?;
+        if path == STDIO_PATH {
+            io::stdout().write_all(text.as_bytes()).map_err(|e| CliError::io(format!("Cannot write the report: {}", e)))?;
+        } else {
+            fs::write(path, text).map_err(|e| CliError::io(format!("Cannot write {}: {}", path, e)))?;
+        }
+    }
+
+    return Ok(metrics);
+}
+
+/// Converts the inputs that appear in a watched folder, with the configuration as a template.
+/// The output defaults to `<folder>/{name}.saf` or `.zip`, depending on the archive.
+/// * `options` - the folder, pattern and interval of the watch
+/// * `config` - the configuration, with the flags applied, without `inputFile`
+/// * `parallel_mode` - how the conversions are parallelized
+/// * `show_progress` - whether to draw the progress line
+/// * `report_path` - where to write the reports, with `{name}` in it, if anywhere
+/// * `show_stats` - whether to print the timings and throughput of every conversion
+/// * `batch` - totals of all conversions, served at the metrics endpoint
+fn watch_folder(mut options: WatchOptions, config: HashMap<String, JsonValue>, parallel_mode: ParallelMode, show_progress: bool,
+    report_path: Option<String>, show_stats: bool, batch: Option<&BatchMetrics>) -> Result<(), CliError>
+{
+    let archive = config.get("archive").and_then(|v| v.get::<String>()).map(|a| a.to_lowercase());
+    let extension = match archive.as_deref() {
+        Some("saf") => "saf",
+        Some("zip") => "zip",
+        _ => return Err(CliError::config("Watch mode needs `archive` to be `SAF` or `ZIP`"))
+    };
+    // Outputs that record their source are compared with the inputs before they are converted again.
+    if let Some(JsonValue::Boolean(true)) = config.get("recordSource") {
+        options.sources = ArchiveEnum::from_string(extension.to_string()).ok();
+    }
+    let output_template = match config.get("outputFile").and_then(|v| v.get::<String>()) {
+        Some(output) if output.contains(NAME_PLACEHOLDER) => output.clone(),
+        Some(_) => return Err(CliError::config(format!("In watch mode, `outputFile` has to contain `{}`, the name of the input", NAME_PLACEHOLDER))),
+        None => options.folder.join(format!("{}.{}", NAME_PLACEHOLDER, extension)).to_string_lossy().to_string()
+    };
+    if report_path.as_deref() == Some(STDIO_PATH) {
+        return Err(CliError::config("In watch mode, reports cannot be written to stdout"));
+    }
+
+    // Failed conversions are logged and the watch goes on, so only reading the folder can fail it.
+    return watch::watch(&options, &output_template, |input, output| {
+        let mut file_config = config.clone();
+        file_config.insert("inputFile".to_string(), JsonValue::from(input.to_string_lossy().to_string()));
+        file_config.insert("outputFile".to_string(), JsonValue::from(output.to_string()));
+        let report = report_path.as_ref().map(|path| watch::output_path(path, input));
+        return convert(file_config, parallel_mode, show_progress, report.as_deref(), show_stats, batch).map_err(|e| e.to_string());
+    }).map_err(CliError::io);
+}
+
+/// Runs the command.
+/// * `arguments` - the command line arguments after the name of the command
+pub fn run(arguments: Vec<String>) -> Result<(), CliError> {
+    let mut config_file_path = None;
+    let mut config_overrides = HashMap::new();
+    let mut parallel_mode = ParallelMode::Pipeline;
+    let mut show_progress = io::stderr().is_terminal();
+    let mut report_path = None;
+    let mut show_stats = false;
+    let mut metrics_address = None;
+    let mut watched_folder = None;
+    let mut watch_pattern = "*.raw".to_string();
+    let mut watch_interval = Duration::from_secs(2);
+    let mut watch_once = false;
+    let mut verbosity = 0;
+    let mut arguments_iter = arguments.iter();
+    'arguments: while let Some(arg) = arguments_iter.next() {
+        if arg == "--help" {
+            println!("{}", HELP);
+            return Ok(());
+        } else if let Some(mode) = option_value(arg, "--parallel", &mut arguments_iter)? {
+            parallel_mode = ParallelMode::from_string(&mode)
+                .ok_or_else(|| format!("Unsupported parallel mode `{}` (expected `pipeline` or `data`)", mode))?;
+        } else if let Some(path) = option_value(arg, "--report", &mut arguments_iter)? {
+            report_path = Some(path);
+        } else if let Some(address) = option_value(arg, "--metrics-address", &mut arguments_iter)? {
+            metrics_address = Some(address);
+        } else if let Some(pattern) = option_value(arg, "--watch-pattern", &mut arguments_iter)? {
+            watch_pattern = pattern;
+        } else if let Some(seconds) = option_value(arg, "--watch-interval", &mut arguments_iter)? {
+            let seconds = seconds.parse::<f64>().ok().filter(|s| *s > 0.0)
+                .ok_or_else(|| format!("Invalid value for `--watch-interval`: {}", seconds))?;
+            watch_interval = Duration::from_secs_f64(seconds);
+        } else if arg == "--watch-once" {
+            watch_once = true;
+        } else if let Some(folder) = option_value(arg, "--watch", &mut arguments_iter)? {
+            watched_folder = Some(PathBuf::from(folder));
+        } else if arg == "--progress" {
+            show_progress = true;
+        } else if arg == "--no-progress" {
+            show_progress = false;
+        } else if arg == "--stats" {
+            show_stats = true;
+        } else if let Some(change) = log::verbosity_flag(arg) {
+            verbosity = if change < 0 { change } else { verbosity.max(0) + change };
+        } else if arg.starts_with('-') {
+            for (flag, key) in arguments::CONFIG_FLAGS {
+                if let Some(value) = option_value(arg, flag, &mut arguments_iter)? {
+                    let value = arguments::flag_value_to_json(key, &value).map_err(config_error)?;
+                    config_overrides.insert(key.to_string(), value);
+                    continue 'arguments;
+                }
+            }
+            return Err(CliError::config(format!("Unknown option `{}`", arg)));
+        } else {
+            config_file_path = Some(arg.clone());
+        }
+    }
+
+    log::set_max_level(Level::from_verbosity(verbosity));
+
+    // Without a config file, all required values have to be given as flags.
+    let mut config = match config_file_path {
+        Some(p) => {
+            let mut config = arguments::read_config_file(&p).map_err(config_error)?;
+            arguments::resolve_config_paths(&mut config, &p).map_err(config_error)?;
+            config
+        },
+        None if config_overrides.is_empty() => return Err(CliError::config("Missing config file")),
+        None => HashMap::new()
+    };
+    config.extend(config_overrides);
+
+    let batch = match metrics_address {
+        Some(address) => {
+            let batch = Arc::new(BatchMetrics::new());
+            let served = batch.clone();
+            let address = prometheus::serve(&address, move || served.to_prometheus()).map_err(|e| CliError::io(e.to_string()))?;
+            log_info!("serving metrics on http://{}/metrics", address);
+            Some(batch)
+        },
+        None => None
+    };
+
+    if let Some(folder) = watched_folder {
+        let options = WatchOptions { folder, pattern: watch_pattern, interval: watch_interval, once: watch_once, sources: None };
+        return watch_folder(options, config, parallel_mode, show_progress, report_path, show_stats, batch.as_deref());
+    }
+    return convert(config, parallel_mode, show_progress, report_path.as_deref(), show_stats, batch.as_deref());
+}
@@ -0,0 +1,106 @@
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bvp::progress::{ProgressCounters, ProgressSink, ProgressSnapshot};
+
+/// How often the progress line is redrawn at most.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+/// Width of the bar, in characters.
+const BAR_WIDTH: usize = 30;
+
+/// Draws a single, continuously updated progress line to stderr.
+pub struct ProgressBar {
+    counters: ProgressCounters,
+    last_draw: Mutex<Option<Instant>>
+}
+
+impl ProgressBar {
+    pub fn new() -> Self {
+        return Self {
+            counters: ProgressCounters::new(),
+            last_draw: Mutex::new(None)
+        };
+    }
+
+    /// Redraws the progress line, unless it was redrawn recently and `force` is false.
+    fn draw(&self, force: bool) {
+        let mut last_draw = match self.last_draw.lock() {
+            Ok(l) => l,
+            Err(_) => return
+        };
+        if !force {
+            if let Some(last) = *last_draw {
+                if last.elapsed() < REDRAW_INTERVAL {
+                    return;
+                }
+            }
+        }
+        *last_draw = Some(Instant::now());
+
+        let line = format_line(&self.counters.snapshot());
+        let mut stderr = io::stderr();
+        // Progress output is best effort, a closed stderr should not stop the conversion.
+        let _ = write!(stderr, "\r{}", line);
+        let _ = stderr.flush();
+    }
+}
+
+impl ProgressSink for ProgressBar {
+    fn start(&self, total_blocks: usize) {
+        self.counters.start(total_blocks);
+        self.draw(true);
+    }
+
+    fn block_processed(&self, duplicate: bool) {
+        self.counters.block_processed(duplicate);
+        self.draw(false);
+    }
+
+    fn bytes_written(&self, bytes: usize) {
+        self.counters.bytes_written(bytes);
+        self.draw(false);
+    }
+
+    fn finish(&self) {
+        self.draw(true);
+        eprintln!();
+    }
+}
+
+/// Formats a duration as `hh:mm:ss`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    return format!("{:02}:{:02}:{:02}", seconds / 3600, (seconds / 60) % 60, seconds % 60);
+}
+
+/// Formats a byte count with a binary unit.
+fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    return format!("{:.1} {}", value, units[unit]);
+}
+
+fn format_line(snapshot: &ProgressSnapshot) -> String {
+    let filled = ((snapshot.fraction() * BAR_WIDTH as f64) as usize).min(BAR_WIDTH);
+    let eta = match snapshot.eta() {
+        Some(eta) => format_duration(eta),
+        None => "--:--:--".to_string()
+    };
+    return format!(
+        "[{}{}] {}/{} blocks, {} duplicates, {} written, elapsed {}, ETA {}",
+        "#".repeat(filled),
+        ".".repeat(BAR_WIDTH - filled),
+        snapshot.blocks_processed,
+        snapshot.blocks_total,
+        snapshot.duplicates,
+        format_bytes(snapshot.bytes_written),
+        format_duration(snapshot.elapsed),
+        eta
+    );
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use tinyjson::JsonValue;
+
+use bvp::archives::ArchiveEnum;
+use bvp::asset::Asset;
+use bvp::compressions::FileCompression;
+use bvp::source;
+use bvp::{log_debug, log_error, log_info};
+
+/// Placeholder in output templates, replaced by the input file name without its extension.
+pub const NAME_PLACEHOLDER: &str = "{name}";
+
+/// Settings of the watch mode.
+pub struct WatchOptions {
+    pub folder: PathBuf,
+    /// Names of the files to convert, where `*` matches any characters and `?` one character.
+    pub pattern: String,
+    /// Time between two scans of the folder.
+    pub interval: Duration,
+    /// Stop once there are no files left to convert, instead of waiting for new ones.
+    pub once: bool,
+    /// Archive type of the outputs, if they record their source and inputs with the same contents are skipped.
+    pub sources: Option<ArchiveEnum>
+}
+
+/// Returns true if a file name matches a pattern with `*` and `?` wildcards.
+/// * `pattern` - the pattern
+/// * `name` - the file name
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Position after the last `*`, and the name position it was matched to so far.
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p + 1, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // Let the last `*` match one more character.
+            star = Some((star_p, star_n + 1));
+            p = star_p;
+            n = star_n + 1;
+        } else {
+            return false;
+        }
+    }
+    return pattern[p..].iter().all(|c| *c == '*');
+}
+
+/// Returns the path of an output, with the placeholder replaced by the name of the input.
+/// * `template` - the output path, with `{name}` in it
+/// * `input` - the input file
+pub fn output_path(template: &str, input: &Path) -> String {
+    let name = input_stem(&input.to_string_lossy()).unwrap_or_default();
+    return template.replace(NAME_PLACEHOLDER, &name);
+}
+
+/// Returns the file name of an input without its extensions, `head` for `head.raw` and `head.raw.gz`.
+/// * `input` - path of the input
+pub fn input_stem(input: &str) -> Option<String> {
+    let path = FileCompression::from_path(input).strip_extension(input);
+    return Path::new(path).file_stem().map(|s| s.to_string_lossy().to_string());
+}
+
+/// Size and modification time of a file, which stop changing once it is fully written.
+fn file_state(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = fs::metadata(path).ok()?;
+    return Some((metadata.len(), metadata.modified().ok()?));
+}
+
+/// Returns true if the output of an input exists and was written after it, so it does not need converting.
+fn is_up_to_date(output: &str, input_modified: SystemTime) -> bool {
+    return file_state(Path::new(output)).is_some_and(|(_, output_modified)| output_modified >= input_modified);
+}
+
+/// Returns true if an output records an input with the same size and hash as the input file as its source,
+/// so converting it again would not change the voxels. Outputs that cannot be read are converted again.
+/// * `archive` - archive type of the output
+/// * `output` - the output
+/// * `input` - the input file
+fn has_same_source(archive: &ArchiveEnum, output: &str, input: &Path) -> bool {
+    let recorded = archive.read_archive(Path::new(output)).ok()
+        .and_then(|files| files.into_iter().find(|file| file.name.ends_with("manifest.json")))
+        .and_then(|manifest| std::str::from_utf8(&manifest.data).ok().and_then(|content| content.parse::<JsonValue>().ok()))
+        .and_then(|manifest| match manifest {
+            JsonValue::Object(o) => o.get("asset").and_then(|asset| Asset::from_json(asset).ok()),
+            _ => None
+        })
+        .and_then(|asset| source::source(&asset).ok().flatten());
+    let recorded = match recorded {
+        Some(r) => r,
+        None => return false
+    };
+    let input = input.to_string_lossy();
+    let data = match fs::read(input.as_ref()) {
+        Ok(d) => d,
+        Err(_) => return false
+    };
+    return match FileCompression::from_path(&input).decompress(data) {
+        Ok(data) => source::is_same_source(&recorded, &data),
+        Err(_) => false
+    };
+}
+
+/// Converts the files in a folder that match the pattern, and then every new or changed one as it appears.
+/// A file is converted once its size and modification time are the same in two scans,
+/// so files that are still being written, for example by an acquisition machine, are not read.
+/// Files whose output is newer than them are skipped, so the watch can be restarted, and so are files
+/// with the same contents as the source their output records, if `sources` is set.
+/// A failed conversion is logged and retried only once the file changes.
+/// * `options` - folder, pattern and interval of the watch
+/// * `output_template` - the output path of a file, with `{name}` in it
+/// * `convert` - converts an input into an output
+pub fn watch<F: FnMut(&Path, &str) -> Result<(), String>>(options: &WatchOptions, output_template: &str, mut convert: F) -> Result<(), String> {
+    log_info!("watching {} for files matching `{}`", options.folder.display(), options.pattern);
+    // State of the files when they were last seen, and when they were converted.
+    let mut seen: HashMap<PathBuf, (u64, SystemTime)> = HashMap::new();
+    let mut handled: HashMap<PathBuf, SystemTime> = HashMap::new();
+    loop {
+        let entries = fs::read_dir(&options.folder)
+            .map_err(|e| format!("Cannot read {}: {}", options.folder.display(), e))?;
+        let mut inputs: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+            .filter(|entry| matches_pattern(&options.pattern, &entry.file_name().to_string_lossy()))
+            .map(|entry| entry.path())
+            .collect();
+        inputs.sort();
+
+        let mut waiting = false;
+        for input in inputs {
+            let state = match file_state(&input) {
+                Some(s) => s,
+                None => continue
+            };
+            if handled.get(&input) == Some(&state.1) {
+                continue;
+            }
+            if seen.insert(input.clone(), state) != Some(state) {
+                log_debug!("{} changed, waiting for it to be complete", input.display());
+                waiting = true;
+                continue;
+            }
+            handled.insert(input.clone(), state.1);
+            let output = output_path(output_template, &input);
+            if is_up_to_date(&output, state.1) {
+                log_debug!("{} is up to date", output);
+                continue;
+            }
+            if options.sources.as_ref().is_some_and(|archive| has_same_source(archive, &output, &input)) {
+                log_info!("skipping {}, {} was converted from the same contents", input.display(), output);
+                continue;
+            }
+            log_info!("converting {} to {}", input.display(), output);
+            match convert(&input, &output) {
+                Ok(()) => log_info!("converted {}", input.display()),
+                Err(e) => log_error!("cannot convert {}: {}", input.display(), e)
+            }
+        }
+
+        if options.once && !waiting {
+            return Ok(());
+        }
+        thread::sleep(options.interval);
+    }
+}
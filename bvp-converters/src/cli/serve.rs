@@ -0,0 +1,516 @@
+use std::{collections::HashMap, io::{self, BufRead, BufReader, Read, Write}, net::{TcpListener, TcpStream}, path::Path, sync::Arc, thread, time::{Duration, Instant}};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use xxhash_rust::xxh3;
+
+use bvp::archives::ArchiveEnum;
+use bvp::detect;
+use bvp::bvpfile::BVPFile;
+use bvp::bytes::Bytes;
+use bvp::compressions::CompressionType;
+use bvp::log::{self, Level};
+use bvp::prometheus::{self, Exposition};
+use bvp::{log_debug, log_info, log_warn};
+
+use super::error::CliError;
+
+static HELP: &str = "bvp-serve\n------------\n Usage: bvp-serve <input_file> [<archive type>] [options]\n Options:\n  --address HOST:PORT - address to listen on (default 127.0.0.1:8080)\n  --threads N - number of connections served at the same time (default 8)\n  --allow-origin ORIGIN - send `Access-Control-Allow-Origin: ORIGIN`, for example `*`\n  --metrics - serve Prometheus metrics at /metrics\n  -v, -vv, -vvv / --verbose - print more diagnostics to stderr (info, debug, trace)\n  -q / --quiet - print only errors\n Routes:\n  GET /manifest - the manifest of the asset\n  GET /blocks/ID - the data file of a block, by index or data file name\n  GET /metrics - requests, errors and bytes served, with `--metrics`\n Blocks are sent as stored, or transcoded to raw or LZ4S, see the README.\n This message can be viewed with flag `--help`.";
+
+/// Connections that stay idle longer than this are closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time a client has to send the request line and headers, once it has started a request.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest request line or header in bytes, with its line break.
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+/// Largest number of headers of a request.
+const MAX_HEADERS: usize = 100;
+
+struct Server {
+    bvp_file: BVPFile,
+    manifest: Bytes,
+    allow_origin: Option<String>,
+    /// Counters of the served requests, if they are served at `/metrics`.
+    metrics: Option<ServerMetrics>
+}
+
+/// What the server has done since it started.
+#[derive(Default)]
+struct ServerMetrics {
+    connections: AtomicU64,
+    requests_in_flight: AtomicU64,
+    requests: AtomicU64,
+    block_requests: AtomicU64,
+    errors: AtomicU64,
+    bytes_sent: AtomicU64
+}
+
+impl ServerMetrics {
+    /// Counts a request that has been answered.
+    /// * `request` - the request
+    /// * `response` - its response
+    fn answered(&self, request: &Request, response: &Response) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if request.path.starts_with("/blocks/") {
+            self.block_requests.fetch_add(1, Ordering::Relaxed);
+        }
+        if response.status >= 400 {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        if request.method != "HEAD" {
+            self.bytes_sent.fetch_add(response.body.len() as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn to_prometheus(&self) -> String {
+        let load = |value: &AtomicU64| value.load(Ordering::Relaxed) as f64;
+        let mut exposition = Exposition::new();
+        exposition.gauge("bvp_serve_connections", "Connections that are open", load(&self.connections));
+        exposition.gauge("bvp_serve_requests_in_flight", "Requests that are being answered", load(&self.requests_in_flight));
+        exposition.counter("bvp_serve_requests_total", "Requests answered", load(&self.requests));
+        exposition.counter("bvp_serve_block_requests_total", "Requests for blocks answered", load(&self.block_requests));
+        exposition.counter("bvp_serve_errors_total", "Requests answered with an error status", load(&self.errors));
+        exposition.counter("bvp_serve_sent_bytes_total", "Size of the bodies sent", load(&self.bytes_sent));
+        return exposition.finish();
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    headers: HashMap<String, String>
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Bytes
+}
+
+impl Response {
+    fn new(status: u16, body: Bytes) -> Self {
+        return Self { status, headers: Vec::new(), body };
+    }
+
+    fn error(status: u16, message: String) -> Self {
+        return Self::new(status, Bytes::from(message.into_bytes())).with_header("Content-Type", "text/plain; charset=utf-8");
+    }
+
+    fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        return self;
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    return match status {
+        200 => "OK",
+        206 => "Partial Content",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        416 => "Range Not Satisfiable",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error"
+    };
+}
+
+/// A connection that is read until a deadline, after which reads time out, however slowly the client sends.
+struct Connection {
+    stream: TcpStream,
+    deadline: Instant
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "the client did not send the request in time"));
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        return self.stream.read(buf);
+    }
+}
+
+/// A line of the head of a request.
+enum Line {
+    Text(String),
+    /// The line is longer than `MAX_LINE_LENGTH`.
+    TooLong,
+    /// The connection ended.
+    End
+}
+
+/// Reads a line of at most `MAX_LINE_LENGTH` bytes, without its line break.
+/// * `reader` - the connection
+fn read_line(reader: &mut BufReader<Connection>) -> Result<Line, Response> {
+    let mut line = Vec::new();
+    let read = reader.by_ref().take(MAX_LINE_LENGTH as u64).read_until(b'\n', &mut line).map_err(|e| {
+        return match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Response::error(408, e.to_string()),
+            _ => Response::error(400, e.to_string())
+        };
+    })?;
+    if line.last() != Some(&b'\n') {
+        return Ok(if read == MAX_LINE_LENGTH { Line::TooLong } else { Line::End });
+    }
+    return Ok(Line::Text(String::from_utf8_lossy(&line).trim_end().to_string()));
+}
+
+/// Reads a request line and headers, or returns None at the end of the connection.
+/// Requests that are too large or too slow are errors, with the response to send.
+/// * `reader` - the connection
+fn read_request(reader: &mut BufReader<Connection>) -> Result<Option<Request>, Response> {
+    let line = match read_line(reader)? {
+        Line::Text(line) => line,
+        Line::TooLong => return Err(Response::error(400, format!("the request line is longer than {} bytes", MAX_LINE_LENGTH))),
+        Line::End => return Ok(None)
+    };
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(m), Some(t)) => (m.to_string(), t.to_string()),
+        _ => return Err(Response::error(400, format!("invalid request line `{}`", line)))
+    };
+    let mut headers = HashMap::new();
+    let mut header_count = 0;
+    loop {
+        let header = match read_line(reader)? {
+            Line::Text(header) => header,
+            Line::TooLong => return Err(Response::error(431, format!("a header is longer than {} bytes", MAX_LINE_LENGTH))),
+            Line::End => return Ok(None)
+        };
+        if header.is_empty() {
+            break;
+        }
+        header_count += 1;
+        if header_count > MAX_HEADERS {
+            return Err(Response::error(431, format!("the request has more than {} headers", MAX_HEADERS)));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+    let (path, query) = match target.split_once('?') {
+        Some((p, q)) => (p.to_string(), q),
+        None => (target, "")
+    };
+    let query = query.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    return Ok(Some(Request { method, path: percent_decode(&path), query, headers }));
+}
+
+/// Decodes `%XX` escapes in a path, so data file names can be given as they are.
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Some(byte) = std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    return String::from_utf8_lossy(&decoded).to_string();
+}
+
+/// Returns true if a list of accepted encodings includes an encoding, ignoring `q=0` entries.
+/// * `accept` - value of the `Accept-Encoding` header
+/// * `encoding` - the encoding to look for
+fn accepts(accept: &str, encoding: &str) -> bool {
+    return accept.split(',').any(|entry| {
+        let mut parts = entry.split(';');
+        let name = parts.next().unwrap_or_default().trim();
+        let refused = parts.any(|p| matches!(p.trim(), "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+        return name.eq_ignore_ascii_case(encoding) && !refused;
+    });
+}
+
+fn etag(data: &[u8], variant: &str) -> String {
+    return format!("\"{:016x}-{}\"", xxh3::xxh3_64(data), variant);
+}
+
+impl Server {
+    fn handle(&self, request: &Request) -> Response {
+        if request.method != "GET" && request.method != "HEAD" {
+            return Response::error(405, format!("method {} is not supported", request.method)).with_header("Allow", "GET, HEAD");
+        }
+        if request.path == "/manifest" || request.path == "/manifest.json" {
+            let tag = etag(&self.manifest, "manifest");
+            // The manifest changes with the asset, so clients check it every time.
+            return self.cached(request, Response::new(200, self.manifest.clone()), &tag)
+                .with_header("Content-Type", "application/json")
+                .with_header("Cache-Control", "no-cache");
+        }
+        if let Some(id) = request.path.strip_prefix("/blocks/") {
+            return self.block(request, id);
+        }
+        if let (Some(metrics), "/metrics") = (&self.metrics, request.path.as_str()) {
+            return Response::new(200, Bytes::from(metrics.to_prometheus().into_bytes()))
+                .with_header("Content-Type", prometheus::CONTENT_TYPE)
+                .with_header("Cache-Control", "no-store");
+        }
+        return Response::error(404, format!("{} is not found, use /manifest or /blocks/ID", request.path));
+    }
+
+    /// Sends the data file of a block, in the encoding the client asked for. `?encoding=raw` or
+    /// `?encoding=lz4s` choose it explicitly, otherwise blocks are sent as LZ4S only if the
+    /// `Accept-Encoding` header lists `lz4s`, and then with `Content-Encoding: lz4s`.
+    fn block(&self, request: &Request, id: &str) -> Response {
+        let index = match self.bvp_file.find_block(id) {
+            Some(i) => i,
+            None => return Response::error(404, format!("block `{}` does not exist", id))
+        };
+        let block = &self.bvp_file.blocks[index];
+        let data = match &block.data {
+            Some(d) => d,
+            None => return Response::error(404, format!("block {} has no data, it only places other blocks", index))
+        };
+        let stored = block.encoding.unwrap_or(CompressionType::None);
+        let (encoding, negotiated) = match request.query.get("encoding").map(|e| e.as_str()) {
+            Some("raw") => (CompressionType::None, false),
+            Some("lz4s") => (CompressionType::LZ4S, false),
+            Some(e) => return Response::error(400, format!("unknown encoding `{}`, use raw or lz4s", e)),
+            None => {
+                let lz4s = request.headers.get("accept-encoding").is_some_and(|a| accepts(a, "lz4s"));
+                (if lz4s { CompressionType::LZ4S } else { CompressionType::None }, lz4s)
+            }
+        };
+
+        let body = if encoding == stored {
+            data.clone()
+        } else {
+            let format = match block.format.and_then(|f| self.bvp_file.formats.get(f)) {
+                Some(f) => f,
+                None => return Response::error(500, format!("block {} has no format", index))
+            };
+            let decoded = match block.decoded(format) {
+                Ok(b) => b.data.unwrap_or_default(),
+                Err(e) => return Response::error(500, e.to_string())
+            };
+            log_debug!("transcoding block {} from {} to {}", index, stored.to_string(), encoding.to_string());
+            match encoding {
+                CompressionType::None => decoded,
+                CompressionType::LZ4S => Bytes::from(CompressionType::LZ4S.compress(decoded.to_vec()))
+            }
+        };
+
+        let tag = etag(data, &encoding.to_string());
+        let mut response = self.cached(request, Response::new(200, body), &tag)
+            .with_header("Content-Type", "application/octet-stream")
+            .with_header("X-BVP-Encoding", &encoding.to_string())
+            .with_header("Vary", "Accept-Encoding")
+            // Block files do not change while the asset is served, the ETag tells apart assets.
+            .with_header("Cache-Control", "public, max-age=86400");
+        if negotiated && encoding == CompressionType::LZ4S {
+            response = response.with_header("Content-Encoding", "lz4s");
+        }
+        return response;
+    }
+
+    /// Answers conditional requests with 304 and range requests with 206, and adds caching headers.
+    /// * `request` - the request
+    /// * `response` - the full response
+    /// * `tag` - the ETag of the body
+    fn cached(&self, request: &Request, response: Response, tag: &str) -> Response {
+        if request.headers.get("if-none-match").is_some_and(|tags| tags.split(',').any(|t| t.trim() == tag || t.trim() == "*")) {
+            return Response::new(304, Bytes::new()).with_header("ETag", tag);
+        }
+        let response = response.with_header("ETag", tag).with_header("Accept-Ranges", "bytes");
+        let range = match request.headers.get("range") {
+            Some(r) => r,
+            None => return response
+        };
+        // Ranges only apply to the same version of the body.
+        if request.headers.get("if-range").is_some_and(|t| t != tag) {
+            return response;
+        }
+        let length = response.body.len();
+        let (start, end) = match parse_range(range, length) {
+            Some(r) => r,
+            None => return Response::error(416, format!("range `{}` is not satisfiable", range))
+                .with_header("Content-Range", &format!("bytes */{}", length))
+        };
+        let mut partial = Response::new(206, response.body.slice(start..end));
+        partial.headers = response.headers;
+        return partial.with_header("Content-Range", &format!("bytes {}-{}/{}", start, end - 1, length));
+    }
+}
+
+/// Parses a single byte range, `bytes=A-B`, `bytes=A-` or `bytes=-N`, into the start and the exclusive end.
+/// * `range` - value of the `Range` header
+/// * `length` - length of the body
+fn parse_range(range: &str, length: usize) -> Option<(usize, usize)> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix = suffix.parse::<usize>().ok()?;
+            (length.saturating_sub(suffix), length)
+        },
+        (start, "") => (start.parse::<usize>().ok()?, length),
+        (start, end) => (start.parse::<usize>().ok()?, end.parse::<usize>().ok()?.saturating_add(1).min(length))
+    };
+    if start >= end || start >= length {
+        return None;
+    }
+    return Some((start, end));
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response, head: bool, allow_origin: &Option<String>) -> std::io::Result<()> {
+    let mut header = format!("HTTP/1.1 {} {}\r\nContent-Length: {}\r\n", response.status, reason(response.status), response.body.len());
+    for (name, value) in &response.headers {
+        header.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if let Some(origin) = allow_origin {
+        header.push_str(&format!("Access-Control-Allow-Origin: {}\r\nAccess-Control-Expose-Headers: ETag, Content-Range, X-BVP-Encoding\r\n", origin));
+    }
+    header.push_str("\r\n");
+    stream.write_all(header.as_bytes())?;
+    if !head {
+        stream.write_all(&response.body)?;
+    }
+    return stream.flush();
+}
+
+/// Serves requests on a connection until the client closes it.
+fn serve_connection(server: &Server, stream: TcpStream) {
+    if let Some(metrics) = &server.metrics {
+        metrics.connections.fetch_add(1, Ordering::Relaxed);
+    }
+    serve_requests(server, stream);
+    if let Some(metrics) = &server.metrics {
+        metrics.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn serve_requests(server: &Server, stream: TcpStream) {
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    let mut writer = match stream.try_clone() {
+        Ok(s) => s,
+        Err(e) => {
+            log_warn!("cannot serve {}: {}", peer, e);
+            return;
+        }
+    };
+    let mut reader = BufReader::new(Connection { stream, deadline: Instant::now() + IDLE_TIMEOUT });
+    loop {
+        // Idle connections wait for the next request, which then has to arrive quickly.
+        reader.get_mut().deadline = Instant::now() + IDLE_TIMEOUT;
+        match reader.fill_buf() {
+            Ok(buffer) if !buffer.is_empty() => (),
+            _ => return
+        }
+        reader.get_mut().deadline = Instant::now() + HEADER_TIMEOUT;
+        let request = match read_request(&mut reader) {
+            Ok(Some(r)) => r,
+            Ok(None) => return,
+            Err(response) => {
+                log_info!("{} sent a request that is not answered ({})", peer, response.status);
+                let _ = write_response(&mut writer, &response, false, &server.allow_origin);
+                return;
+            }
+        };
+        if let Some(metrics) = &server.metrics {
+            metrics.requests_in_flight.fetch_add(1, Ordering::Relaxed);
+        }
+        let response = server.handle(&request);
+        if let Some(metrics) = &server.metrics {
+            metrics.requests_in_flight.fetch_sub(1, Ordering::Relaxed);
+            metrics.answered(&request, &response);
+        }
+        log_info!("{} {} {} {} ({} bytes)", peer, request.method, request.path, response.status, response.body.len());
+        if write_response(&mut writer, &response, request.method == "HEAD", &server.allow_origin).is_err() {
+            return;
+        }
+        if request.headers.get("connection").is_some_and(|c| c.eq_ignore_ascii_case("close")) {
+            return;
+        }
+    }
+}
+
+/// Runs the command.
+/// * `arguments` - the command line arguments after the name of the command
+pub fn run(arguments: Vec<String>) -> Result<(), CliError> {
+    let mut positional: Vec<String> = Vec::new();
+    let mut address = "127.0.0.1:8080".to_string();
+    let mut threads = 8;
+    let mut allow_origin = None;
+    let mut serve_metrics = false;
+    let mut verbosity = 0;
+    let mut arguments_iter = arguments.into_iter();
+    while let Some(arg) = arguments_iter.next() {
+        if arg == "--help" {
+            println!("{}", HELP);
+            return Ok(());
+        } else if arg == "--address" {
+            address = arguments_iter.next().ok_or(CliError::config("Missing value for `--address`"))?;
+        } else if arg == "--threads" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--threads`"))?;
+            threads = match value.parse::<usize>() {
+                Ok(t) if t > 0 => t,
+                _ => return Err(CliError::config(format!("Value of `--threads` must be a positive integer (got `{}`)", value)))
+            };
+        } else if arg == "--allow-origin" {
+            allow_origin = Some(arguments_iter.next().ok_or(CliError::config("Missing value for `--allow-origin`"))?);
+        } else if arg == "--metrics" {
+            serve_metrics = true;
+        } else if let Some(change) = log::verbosity_flag(&arg) {
+            verbosity = if change < 0 { change } else { verbosity.max(0) + change };
+        } else if arg.starts_with('-') {
+            return Err(CliError::config(format!("Unknown option `{}`", arg)));
+        } else {
+            positional.push(arg);
+        }
+    }
+    log::set_max_level(Level::from_verbosity(verbosity));
+
+    let input_filepath = match positional.first() {
+        Some(p) => Path::new(p.as_str()),
+        None => return Err(CliError::config("Missing input file"))
+    };
+    let archive_tp = match positional.get(1) {
+        Some(a) => ArchiveEnum::from_string(a.clone())?,
+        None => detect::detect_archive(input_filepath)?
+    };
+    let bvp_file = BVPFile::open(input_filepath, &archive_tp)?;
+    let manifest = match bvp_file.files.iter().find(|file| file.name.ends_with("manifest.json")) {
+        Some(m) => m.data.clone(),
+        None => return Err(CliError::corrupt("The asset has no manifest"))
+    };
+    let metrics = if serve_metrics { Some(ServerMetrics::default()) } else { None };
+    let server = Arc::new(Server { bvp_file, manifest, allow_origin, metrics });
+
+    let listener = TcpListener::bind(&address).map_err(|e| format!("Cannot listen on {}: {}", address, e))?;
+    eprintln!("Serving {} on http://{}", input_filepath.display(), listener.local_addr().map(|a| a.to_string()).unwrap_or(address));
+    let workers: Vec<_> = (0..threads).map(|_| {
+        let server = server.clone();
+        let listener = listener.try_clone().map_err(|e| e.to_string());
+        return thread::spawn(move || {
+            let listener = match listener {
+                Ok(l) => l,
+                Err(e) => {
+                    log_warn!("cannot start a worker: {}", e);
+                    return;
+                }
+            };
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(s) => serve_connection(&server, s),
+                    Err(e) => log_warn!("cannot accept a connection: {}", e)
+                }
+            }
+        });
+    }).collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+    return Ok(());
+}
@@ -0,0 +1,185 @@
+use std::{fs, path::Path};
+
+use bvp::archives::ArchiveEnum;
+use bvp::detect;
+use bvp::bvpfile::BVPFile;
+use bvp::formats::Format;
+use bvp::image::{self, Window};
+use bvp::log::{self, Level};
+use bvp::reader::VolumeReader;
+use bvp::vector3::Vector3;
+use bvp::window_level;
+use bvp::{log_debug, log_info};
+
+use super::error::CliError;
+
+static HELP: &str = "bvp-thumbnail\n------------\n Usage: bvp-thumbnail <input_file> [<archive type>] [options]\n Options:\n  --slices - write the middle slice along each axis\n  --mip - write the maximum intensity projection along each axis\n  --modality N - modality to render (default 0)\n  --window LOW,HIGH - values shown as black and white (default: the window preset of the modality, or the range of the block data)\n  --output PREFIX - prefix of the written images (default `thumbnail`)\n  -v, -vv, -vvv / --verbose - print more diagnostics to stderr (info, debug, trace)\n  -q / --quiet - print only errors\n Without `--slices` and `--mip`, both are written.\n This message can be viewed with flag `--help`.";
+
+/// Thickness of the slabs read for the projections, in voxels.
+const SLAB_THICKNESS: u32 = 16;
+
+/// A grayscale image of voxel values, before windowing.
+struct Plane {
+    width: u32,
+    height: u32,
+    values: Vec<f64>
+}
+
+impl Plane {
+    fn new(width: u32, height: u32, value: f64) -> Self {
+        return Self { width, height, values: vec![value; (width * height) as usize] };
+    }
+
+    /// Windows the values and writes the plane as a PNG file.
+    /// * `path` - the output file
+    /// * `window` - maps values to intensities
+    fn write_png(&self, path: &str, window: &Window) -> Result<(), CliError> {
+        let pixels: Vec<u8> = self.values.iter().map(|v| window.apply(*v)).collect();
+        log_info!("writing {}x{} image to {}", self.width, self.height, path);
+        return fs::write(path, image::encode_png_gray(self.width, self.height, &pixels))
+            .map_err(|e| CliError::io(format!("Cannot write {}: {}", path, e)));
+    }
+}
+
+/// Reads a region of the modality and returns the values of its voxels.
+fn read_values(reader: &VolumeReader, modality_index: usize, start: Vector3<u32>, end: Vector3<u32>,
+    format: &Format) -> Result<Vec<f64>, CliError>
+{
+    let region = reader.read_region(modality_index, start, end)?;
+    return format.first_components(region.data.as_ref().unwrap()).map_err(|e| CliError::corrupt(e.to_string()));
+}
+
+/// Returns the middle slice along each axis, as slices perpendicular to X, Y and Z.
+/// Only the blocks intersecting the slices are read.
+fn middle_slices(reader: &VolumeReader, modality_index: usize, dimensions: Vector3<u32>,
+    format: &Format) -> Result<[Plane; 3], CliError>
+{
+    let middle = Vector3::from_xyz(dimensions.x / 2, dimensions.y / 2, dimensions.z / 2);
+    let x = read_values(reader, modality_index, Vector3::from_xyz(middle.x, 0, 0),
+        Vector3::from_xyz(middle.x + 1, dimensions.y, dimensions.z), format)?;
+    let y = read_values(reader, modality_index, Vector3::from_xyz(0, middle.y, 0),
+        Vector3::from_xyz(dimensions.x, middle.y + 1, dimensions.z), format)?;
+    let z = read_values(reader, modality_index, Vector3::from_xyz(0, 0, middle.z),
+        Vector3::from_xyz(dimensions.x, dimensions.y, middle.z + 1), format)?;
+    return Ok([
+        Plane { width: dimensions.y, height: dimensions.z, values: x },
+        Plane { width: dimensions.x, height: dimensions.z, values: y },
+        Plane { width: dimensions.x, height: dimensions.y, values: z }
+    ]);
+}
+
+/// Returns the maximum intensity projection along each axis. The volume is read
+/// in slabs along Z, so it is never reconstructed whole.
+fn projections(reader: &VolumeReader, modality_index: usize, dimensions: Vector3<u32>,
+    format: &Format) -> Result<[Plane; 3], CliError>
+{
+    let mut x = Plane::new(dimensions.y, dimensions.z, f64::NEG_INFINITY);
+    let mut y = Plane::new(dimensions.x, dimensions.z, f64::NEG_INFINITY);
+    let mut z = Plane::new(dimensions.x, dimensions.y, f64::NEG_INFINITY);
+    let mut slab_start = 0;
+    while slab_start < dimensions.z {
+        let slab_end = (slab_start + SLAB_THICKNESS).min(dimensions.z);
+        log_debug!("projecting slab {}..{}", slab_start, slab_end);
+        let values = read_values(reader, modality_index, Vector3::from_xyz(0, 0, slab_start),
+            Vector3::from_xyz(dimensions.x, dimensions.y, slab_end), format)?;
+        for (i, value) in values.iter().enumerate() {
+            let vx = i as u32 % dimensions.x;
+            let vy = (i as u32 / dimensions.x) % dimensions.y;
+            let vz = slab_start + i as u32 / (dimensions.x * dimensions.y);
+            let pixels = [
+                &mut x.values[(vz * dimensions.y + vy) as usize],
+                &mut y.values[(vz * dimensions.x + vx) as usize],
+                &mut z.values[(vy * dimensions.x + vx) as usize]
+            ];
+            for pixel in pixels {
+                if *value > *pixel {
+                    *pixel = *value;
+                }
+            }
+        }
+        slab_start = slab_end;
+    }
+    return Ok([x, y, z]);
+}
+
+/// Runs the command.
+/// * `arguments` - the command line arguments after the name of the command
+pub fn run(arguments: Vec<String>) -> Result<(), CliError> {
+    let mut positional: Vec<String> = Vec::new();
+    let mut slices = false;
+    let mut mip = false;
+    let mut modality_index = 0;
+    let mut window = None;
+    let mut prefix = "thumbnail".to_string();
+    let mut verbosity = 0;
+    let mut arguments_iter = arguments.into_iter();
+    while let Some(arg) = arguments_iter.next() {
+        if arg == "--help" {
+            println!("{}", HELP);
+            return Ok(());
+        } else if arg == "--slices" {
+            slices = true;
+        } else if arg == "--mip" {
+            mip = true;
+        } else if arg == "--modality" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--modality`"))?;
+            modality_index = value.parse::<usize>()
+                .map_err(|_| CliError::config(format!("Value of `--modality` must be a non-negative integer (got `{}`)", value)))?;
+        } else if arg == "--window" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--window`"))?;
+            window = match Window::from_string(&value) {
+                Some(w) => Some(w),
+                None => return Err(CliError::config(format!("Value of `--window` must be two numbers such as `0,255` (got `{}`)", value)))
+            };
+        } else if arg == "--output" {
+            prefix = arguments_iter.next().ok_or(CliError::config("Missing value for `--output`"))?;
+        } else if let Some(change) = log::verbosity_flag(&arg) {
+            verbosity = if change < 0 { change } else { verbosity.max(0) + change };
+        } else if arg.starts_with('-') {
+            return Err(CliError::config(format!("Unknown option `{}`", arg)));
+        } else {
+            positional.push(arg);
+        }
+    }
+    log::set_max_level(Level::from_verbosity(verbosity));
+    if !slices && !mip {
+        slices = true;
+        mip = true;
+    }
+
+    let input_filepath = match positional.first() {
+        Some(p) => Path::new(p.as_str()),
+        None => return Err(CliError::config("Missing input file"))
+    };
+    let archive_tp = match positional.get(1) {
+        Some(a) => ArchiveEnum::from_string(a.clone())?,
+        None => detect::detect_archive(input_filepath)?
+    };
+    let bvp_file = BVPFile::open(input_filepath, &archive_tp)?;
+    let reader = VolumeReader::new(&bvp_file);
+    let (root, format) = reader.modality_root(modality_index)?;
+    let dimensions = bvp_file.blocks[root].dimensions;
+
+    let preset = window_level::default_window(&bvp_file.modalities[modality_index]).map_err(|e| CliError::corrupt(e.to_string()))?;
+    let window = match window.or(preset) {
+        Some(w) => w,
+        None => {
+            let (low, high) = reader.value_range(root)?;
+            Window::new(low, high)
+        }
+    };
+    log_info!("window from {} to {}", window.low, window.high);
+
+    let axes = ["x", "y", "z"];
+    if slices {
+        for (plane, axis) in middle_slices(&reader, modality_index, dimensions, format)?.iter().zip(axes) {
+            plane.write_png(&format!("{}_slice_{}.png", prefix, axis), &window)?;
+        }
+    }
+    if mip {
+        for (plane, axis) in projections(&reader, modality_index, dimensions, format)?.iter().zip(axes) {
+            plane.write_png(&format!("{}_mip_{}.png", prefix, axis), &window)?;
+        }
+    }
+    return Ok(());
+}
@@ -0,0 +1,237 @@
+//! Config files of bvp2raw. They are JSON, TOML or YAML objects with the same settings as the command line flags,
+//! so an extraction can be repeated exactly.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use tinyjson::JsonValue;
+
+use bvp::archives::STDIO_PATH;
+use bvp::config_formats::{self, ConfigFormat};
+use bvp::digest::DigestType;
+use bvp::image::Window;
+use bvp::log_warn;
+use bvp::remote;
+use bvp::vector3::Vector3;
+
+use super::SliceFormat;
+use super::super::error::CliError;
+
+pub const KNOWN_KEYS: [&str; 17] = [
+    "inputFile", "archive", "outputDir", "outputName", "start", "end", "modalities", "force",
+    "verifyPlacements", "verifyChecksums", "stream", "slabThickness", "slices", "window", "bitDepth", "nhdr",
+    "checksum"
+];
+
+/// Settings of an extraction. Values that are not given are None, so flags can override a config file.
+#[derive(Default)]
+pub struct Settings {
+    pub input_file: Option<String>,
+    pub archive: Option<String>,
+    pub output_dir: Option<String>,
+    pub output_name: Option<String>,
+    pub start: Option<Vector3<u32>>,
+    pub end: Option<Vector3<u32>>,
+    /// Modalities to write, by index or name
+    pub modalities: Option<Vec<String>>,
+    pub force: Option<bool>,
+    pub verify_placements: Option<bool>,
+    pub verify_checksums: Option<bool>,
+    pub stream: Option<bool>,
+    pub slab_thickness: Option<u32>,
+    pub slices: Option<SliceFormat>,
+    pub window: Option<Window>,
+    pub bit_depth: Option<u32>,
+    pub nhdr: Option<bool>,
+    /// Algorithm of the digest computed of every reconstructed volume
+    pub checksum: Option<DigestType>
+}
+
+impl Settings {
+    /// Returns these settings, with the values of `other` where these are not given.
+    /// * `other` - settings with a lower priority, such as from a config file
+    pub fn or(self, other: Settings) -> Settings {
+        return Settings {
+            input_file: self.input_file.or(other.input_file),
+            archive: self.archive.or(other.archive),
+            output_dir: self.output_dir.or(other.output_dir),
+            output_name: self.output_name.or(other.output_name),
+            start: self.start.or(other.start),
+            end: self.end.or(other.end),
+            modalities: self.modalities.or(other.modalities),
+            force: self.force.or(other.force),
+            verify_placements: self.verify_placements.or(other.verify_placements),
+            verify_checksums: self.verify_checksums.or(other.verify_checksums),
+            stream: self.stream.or(other.stream),
+            slab_thickness: self.slab_thickness.or(other.slab_thickness),
+            slices: self.slices.or(other.slices),
+            window: self.window.or(other.window),
+            bit_depth: self.bit_depth.or(other.bit_depth),
+            nhdr: self.nhdr.or(other.nhdr),
+            checksum: self.checksum.or(other.checksum)
+        };
+    }
+}
+
+/// Collects the problems of a config file, so all of them are reported at once.
+struct ConfigReader<'a> {
+    config: &'a HashMap<String, JsonValue>,
+    problems: Vec<String>
+}
+
+impl<'a> ConfigReader<'a> {
+    fn problem(&mut self, key: &str, message: &str) {
+        self.problems.push(format!("{}: {}", key, message));
+    }
+
+    fn string(&mut self, key: &str) -> Option<String> {
+        return match self.config.get(key)? {
+            JsonValue::String(s) if !s.is_empty() => Some(s.clone()),
+            _ => {
+                self.problem(key, "must be a non-empty string");
+                None
+            }
+        };
+    }
+
+    fn boolean(&mut self, key: &str) -> Option<bool> {
+        return match self.config.get(key)? {
+            JsonValue::Boolean(b) => Some(*b),
+            _ => {
+                self.problem(key, "must be `true` or `false`");
+                None
+            }
+        };
+    }
+
+    fn positive_integer(&mut self, key: &str) -> Option<u32> {
+        return match self.config.get(key)? {
+            JsonValue::Number(n) if *n >= 1.0 && n.fract() == 0.0 && *n <= u32::MAX as f64 => Some(*n as u32),
+            _ => {
+                self.problem(key, "must be a positive integer");
+                None
+            }
+        };
+    }
+
+    /// Reads an array of numbers of the given length.
+    fn numbers(&mut self, key: &str, length: usize, integers: bool) -> Option<Vec<f64>> {
+        let value = self.config.get(key)?;
+        let numbers: Option<Vec<f64>> = match value {
+            JsonValue::Array(a) if a.len() == length => a.iter()
+                .map(|v| match v {
+                    JsonValue::Number(n) if !integers || (*n >= 0.0 && n.fract() == 0.0 && *n <= u32::MAX as f64) => Some(*n),
+                    _ => None
+                })
+                .collect(),
+            _ => None
+        };
+        if numbers.is_none() {
+            let kind = if integers { "non-negative integers" } else { "numbers" };
+            self.problem(key, &format!("must be an array of {} {}", length, kind));
+        }
+        return numbers;
+    }
+
+    fn position(&mut self, key: &str) -> Option<Vector3<u32>> {
+        let p = self.numbers(key, 3, true)?;
+        return Some(Vector3::from_xyz(p[0] as u32, p[1] as u32, p[2] as u32));
+    }
+
+    /// Reads a list of modalities, given by index or by name.
+    fn modalities(&mut self, key: &str) -> Option<Vec<String>> {
+        let selectors: Option<Vec<String>> = match self.config.get(key)? {
+            JsonValue::Array(a) if !a.is_empty() => a.iter()
+                .map(|v| match v {
+                    JsonValue::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some((*n as usize).to_string()),
+                    JsonValue::String(s) if !s.is_empty() => Some(s.clone()),
+                    _ => None
+                })
+                .collect(),
+            _ => None
+        };
+        if selectors.is_none() {
+            self.problem(key, "must be a non-empty array of modality indices or names");
+        }
+        return selectors;
+    }
+
+    /// Reads a path, relative to the folder of the config file unless it is stdin or a URL.
+    fn path(&mut self, key: &str, config_folder: &Path) -> Option<String> {
+        let path = self.string(key)?;
+        if Path::new(&path).is_relative() && !remote::is_remote(&path) && path != STDIO_PATH {
+            return Some(config_folder.join(&path).to_string_lossy().to_string());
+        }
+        return Some(path);
+    }
+}
+
+/// Reads the settings of a config file. Relative paths in it are relative to the folder of the file.
+/// The file is TOML or YAML if its extension says so, see `ConfigFormat::from_path`, and JSON otherwise.
+/// * `filepath` - path to the config file
+pub fn read_config_file(filepath: &str) -> Result<Settings, CliError> {
+    let contents = fs::read_to_string(filepath).map_err(|e| CliError::io(format!("Cannot open config file {}: {}", filepath, e)))?;
+    let config: HashMap<String, JsonValue> = match config_formats::parse(&contents, ConfigFormat::from_path(Path::new(filepath))) {
+        Ok(JsonValue::Object(o)) => o,
+        Ok(_) => return Err(CliError::config(format!("Config file {} is not an object", filepath))),
+        Err(e) => return Err(CliError::config(format!("Could not parse config file {}: {}", filepath, e)))
+    };
+    for key in config.keys() {
+        if !KNOWN_KEYS.contains(&key.as_str()) {
+            log_warn!("unknown config key `{}` is ignored", key);
+        }
+    }
+
+    let config_folder = Path::new(filepath).parent().unwrap_or(Path::new(""));
+    let mut reader = ConfigReader { config: &config, problems: Vec::new() };
+    let slices = match reader.string("slices") {
+        Some(s) => match SliceFormat::from_string(&s) {
+            Some(f) => Some(f),
+            None => {
+                reader.problem("slices", "must be `png` or `tiff`");
+                None
+            }
+        },
+        None => None
+    };
+    let bit_depth = match reader.positive_integer("bitDepth") {
+        Some(b) if b != 8 && b != 16 => {
+            reader.problem("bitDepth", "must be 8 or 16");
+            None
+        },
+        b => b
+    };
+    let checksum = match reader.string("checksum") {
+        Some(c) => match DigestType::from_string(&c) {
+            Ok(c) => Some(c),
+            Err(_) => {
+                reader.problem("checksum", "must be `xxh3` or `sha256`");
+                None
+            }
+        },
+        None => None
+    };
+    let settings = Settings {
+        input_file: reader.path("inputFile", config_folder),
+        archive: reader.string("archive"),
+        output_dir: reader.path("outputDir", config_folder),
+        // Not resolved like the paths, since it is a name in the output folder, or `-` for stdout.
+        output_name: reader.string("outputName"),
+        start: reader.position("start"),
+        end: reader.position("end"),
+        modalities: reader.modalities("modalities"),
+        force: reader.boolean("force"),
+        verify_placements: reader.boolean("verifyPlacements"),
+        verify_checksums: reader.boolean("verifyChecksums"),
+        stream: reader.boolean("stream"),
+        slab_thickness: reader.positive_integer("slabThickness"),
+        slices,
+        window: reader.numbers("window", 2, false).map(|w| Window::new(w[0], w[1])),
+        bit_depth,
+        nhdr: reader.boolean("nhdr"),
+        checksum
+    };
+    if !reader.problems.is_empty() {
+        return Err(CliError::config(format!("Invalid config {}:\n  {}", filepath, reader.problems.join("\n  "))));
+    }
+    return Ok(settings);
+}
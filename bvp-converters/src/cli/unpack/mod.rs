@@ -0,0 +1,547 @@
+mod config;
+
+use std::{path::{Path, PathBuf}, fs, io::{self, BufWriter, Read}, str};
+
+use bvp::bvpfile::BVPFile;
+use bvp::bytes::Bytes;
+use bvp::archives::{ArchiveEnum, STDIO_PATH};
+use bvp::detect;
+use bvp::digest::{self, Digest, DigestType, Hasher, HashingWriter};
+use bvp::convert::{self, RawExportOptions};
+use bvp::errors::{ConvertError, DetectError};
+use bvp::formats::{Format, PrimitiveType};
+use bvp::image::{self, Window};
+use bvp::reader::VolumeReader;
+use bvp::vector3::Vector3;
+use bvp::log::{self, Level, Span};
+use bvp::modality::Modality;
+use bvp::progress::NoProgress;
+use bvp::window_level;
+use bvp::validate::{self, Severity};
+use bvp::{log_debug, log_error, log_info, log_trace, log_warn};
+
+use super::error::CliError;
+
+use self::config::Settings;
+
+static HELP: &str = "bvp2raw\n------------\n Usage: bvp2raw [<input_file> [<archive type>]] [options]\n  The input file can be `-` to read a SAF or ZIP archive from stdin, its type is detected if not given.\n Options:\n  --config PATH - JSON, TOML or YAML config file with the same settings, see README, flags and arguments override it\n  --start X,Y,Z --end X,Y,Z - only reconstruct this region of every modality, the end is exclusive\n  --modality LIST - only write these modalities, by index or name separated by `,` (default: all)\n  --output-dir PATH - folder to write the output to (default: current folder)\n  --output-name TEMPLATE - name of the output without extension, `{modality}` and `{index}` are replaced, `-` writes the raw volume of an asset with one modality to stdout\n  --force - overwrite existing files\n  --no-verify - skip checking that placements tile their blocks without gaps or overlaps\n  --verify - check block data against the checksums in the manifest before decoding it\n  --stream - reconstruct and write the volume in slabs along Z instead of all at once\n  --slab-thickness N - thickness of the slabs in voxels, implies `--stream` (default: depth of the blocks)\n  --slices png|tiff - write each modality as a numbered stack of images along Z instead of a raw file\n  --window LOW,HIGH - values shown as black and white in images (default: the window preset of the modality, see README)\n  --bit-depth 8|16 - bits per pixel in images (default 8)\n  --nhdr - also write a detached NRRD header next to each raw file, with the orientation of the modality\n  --checksum xxh3|sha256 - print a digest of every reconstructed volume, and compare it with the digest in the manifest, see README\n  -v, -vv, -vvv / --verbose - print more diagnostics to stderr (info, debug, trace)\n  -q / --quiet - print only errors\n This message can be viewed with flag `--help`.";
+
+/// Image file formats of the slice export.
+#[derive(Clone, Copy)]
+enum SliceFormat {
+    Png,
+    Tiff
+}
+
+impl SliceFormat {
+    fn from_string(value: &str) -> Option<Self> {
+        return match value.to_lowercase().as_str() {
+            "png" => Some(Self::Png),
+            "tiff" | "tif" => Some(Self::Tiff),
+            _ => None
+        };
+    }
+
+    fn extension(&self) -> &'static str {
+        return match self {
+            Self::Png => "png",
+            Self::Tiff => "tiff"
+        };
+    }
+}
+
+/// Returns the window used when none is given. The default window/level preset of the modality is used
+/// if it has one. Otherwise, unsigned integers that fit into the pixels are kept as they are, and
+/// everything else is scaled from the smallest to the largest value stored in the blocks of the modality.
+/// * `reader` - reader of the asset
+/// * `modality` - the modality
+/// * `root` - index of the root block of the modality
+/// * `format` - format of the modality
+/// * `bit_depth` - bits per pixel in the images
+fn default_window(reader: &VolumeReader, modality: &Modality, root: usize, format: &Format, bit_depth: u32) -> Result<Window, CliError> {
+    let component = match format.first_component() {
+        Some(c) => c,
+        None => return Err(CliError::config(format!("Slices cannot be written from `{}` formats", format.family().name())))
+    };
+    if let Some(window) = window_level::default_window(modality).map_err(|e| CliError::corrupt(e.to_string()))? {
+        return Ok(window);
+    }
+    let component_bits = component.component_size() * 8;
+    if let PrimitiveType::Uint = component.component_type() {
+        if component_bits <= bit_depth {
+            return Ok(Window::new(0.0, ((1u64 << bit_depth) - 1) as f64));
+        }
+    }
+    let (low, high) = reader.value_range(root)?;
+    return Ok(Window::new(low, high));
+}
+
+/// Fails if a file already exists and may not be overwritten.
+/// * `path` - the file that will be written
+/// * `force` - true if existing files may be overwritten
+fn check_overwrite(path: &Path, force: bool) -> Result<(), CliError> {
+    if !force && path.exists() {
+        return Err(CliError::config(format!("{} already exists, use `--force` to overwrite it", path.display())));
+    }
+    return Ok(());
+}
+
+/// Returns the file name of a modality without extension.
+/// * `template` - the name given with `--output-name`, if any
+/// * `modality_name` - name of the modality, if it has one
+/// * `input_filepath` - the file the asset was read from
+/// * `index` - index of the modality
+fn output_stem(template: &Option<String>, modality_name: &Option<String>, input_filepath: &Path, index: usize) -> String {
+    let name = match modality_name {
+        Some(n) => n.clone(),
+        None => match input_filepath.file_stem() {
+            Some(f) => format!("{}_volume_{}", f.to_string_lossy(), index),
+            None => format!("default_volume_{}", index)
+        }
+    };
+    return match template {
+        Some(t) => t.replace("{modality}", &name).replace("{index}", &index.to_string()),
+        None => name
+    };
+}
+
+/// Returns the paths of the images a volume is written to, `<stem>_0000.<extension>` and up.
+/// * `stem` - start of the file names, including the folder
+/// * `depth` - number of slices
+/// * `slice_format` - format of the images
+fn slice_paths(stem: &Path, depth: u32, slice_format: SliceFormat) -> Vec<PathBuf> {
+    let digits = depth.saturating_sub(1).to_string().len().max(4);
+    return (0..depth)
+        .map(|z| PathBuf::from(format!("{}_{:0width$}.{}", stem.display(), z, slice_format.extension(), width = digits)))
+        .collect();
+}
+
+/// Writes slices of a volume as images.
+/// * `paths` - a file for every slice
+/// * `values` - first components of all voxels of the slices
+/// * `width`, `height` - dimensions of the slices
+/// * `slice_format` - format of the images
+/// * `window` - maps values to intensities
+/// * `bit_depth` - bits per pixel, 8 or 16
+fn write_slices(paths: &[PathBuf], values: &[f64], width: u32, height: u32, slice_format: SliceFormat,
+    window: &Window, bit_depth: u32) -> Result<(), CliError>
+{
+    for (slice, path) in values.chunks((width * height) as usize).zip(paths) {
+        let contents = match (slice_format, bit_depth) {
+            (SliceFormat::Png, 16) => image::encode_png_gray16(width, height, &slice.iter().map(|v| window.apply_u16(*v)).collect::<Vec<u16>>()),
+            (SliceFormat::Tiff, 16) => image::encode_tiff_gray16(width, height, &slice.iter().map(|v| window.apply_u16(*v)).collect::<Vec<u16>>()),
+            (SliceFormat::Png, _) => image::encode_png_gray(width, height, &slice.iter().map(|v| window.apply(*v)).collect::<Vec<u8>>()),
+            (SliceFormat::Tiff, _) => image::encode_tiff_gray(width, height, &slice.iter().map(|v| window.apply(*v)).collect::<Vec<u8>>())
+        };
+        log_trace!("writing {}", path.display());
+        fs::write(path, contents).map_err(|e| CliError::io(format!("Cannot write {}: {}", path.display(), e)))?;
+    }
+    return Ok(());
+}
+
+/// How modalities are reconstructed and written.
+struct ExportOptions {
+    region: Option<(Vector3<u32>, Vector3<u32>)>,
+    slice_format: Option<SliceFormat>,
+    window: Option<Window>,
+    bit_depth: u32,
+    force: bool,
+    /// Writes the raw volume to stdout instead of a file.
+    to_stdout: bool,
+    /// Writes a detached NRRD header next to the raw file.
+    nhdr: bool,
+    /// Thickness of the slabs in streaming mode. `Some(None)` picks the thickness from the blocks.
+    stream: Option<Option<u32>>,
+    /// Algorithm of the digest computed of the reconstructed voxels, if any.
+    checksum: Option<DigestType>
+}
+
+/// Returns the error of `bvp_to_raw`, naming the output if writing it failed.
+/// * `error` - the error
+/// * `output` - the file that was written
+fn raw_error(error: ConvertError, output: &Path) -> CliError {
+    return match error {
+        ConvertError::CannotWrite(e) => CliError::io(format!("Cannot write {}: {}", output.display(), e)),
+        e => CliError::from(e)
+    };
+}
+
+/// Reconstructs a modality as a raw volume, and returns the digest of the voxels if one is computed.
+/// * `reader` - reader of the asset
+/// * `modality_index` - index of the modality
+/// * `raw_options` - the region and the slabs it is reconstructed in
+/// * `sink` - receives the raw volume
+/// * `checksum` - algorithm of the digest, if any
+/// * `output` - the file that is written, for errors
+fn write_raw(reader: &VolumeReader, modality_index: usize, raw_options: &RawExportOptions, sink: impl io::Write,
+    checksum: Option<DigestType>, output: &Path) -> Result<Option<Digest>, CliError>
+{
+    return match checksum {
+        Some(algorithm) => {
+            let mut sink = HashingWriter::new(sink, algorithm);
+            convert::bvp_to_raw(reader, modality_index, raw_options, &mut sink, NoProgress).map_err(|e| raw_error(e, output))?;
+            Ok(Some(sink.digest()))
+        },
+        None => {
+            convert::bvp_to_raw(reader, modality_index, raw_options, sink, NoProgress).map_err(|e| raw_error(e, output))?;
+            Ok(None)
+        }
+    };
+}
+
+/// Reconstructs a modality and writes it as a raw file or a stack of images.
+/// In streaming mode, the volume is reconstructed and written in slabs along Z,
+/// so only the blocks intersecting one slab are decoded at a time.
+/// * `reader` - reader of the asset
+/// * `bvp_file` - the asset
+/// * `modality_index` - index of the modality
+/// * `stem` - path of the output without extension
+/// * `options` - how the modality is written
+fn export_modality(reader: &VolumeReader, bvp_file: &BVPFile, modality_index: usize, stem: &Path, options: &ExportOptions) -> Result<(), CliError> {
+    let (root, format) = reader.modality_root(modality_index)?;
+    let slab_thickness = options.stream.map(|thickness| thickness.unwrap_or_else(|| convert::default_slab_thickness(bvp_file, root)));
+    let raw_options = RawExportOptions { region: options.region, slab_thickness };
+    let (start, end) = convert::export_region(reader, modality_index, &raw_options)?;
+    let extent = end - start;
+    // Digests in the manifest cover the whole volume.
+    let region_is_volume = start == Vector3::from_xyz(0, 0, 0) && end == bvp_file.blocks[root].dimensions;
+    let mut hasher = options.checksum.map(Hasher::new);
+
+    // Both kinds of output are checked before anything is written.
+    if let Some(slice_format) = options.slice_format {
+        let paths = slice_paths(stem, extent.z, slice_format);
+        for path in &paths {
+            check_overwrite(path, options.force)?;
+        }
+        let window = match options.window {
+            Some(w) => w,
+            None => default_window(reader, &bvp_file.modalities[modality_index], root, format, options.bit_depth)?
+        };
+        log_debug!("writing {} slices of {} from {} to {}", extent.z, stem.display(), window.low, window.high);
+        for slab in convert::slabs(reader, modality_index, &raw_options)? {
+            let slab = slab?;
+            let first = (slab.start.z - start.z) as usize;
+            let last = (slab.end.z - start.z) as usize;
+            let values = format.first_components(&slab.data).map_err(|e| CliError::corrupt(e.to_string()))?;
+            write_slices(&paths[first..last], &values, extent.x, extent.y, slice_format, &window, options.bit_depth)?;
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&slab.data);
+            }
+        }
+        if let Some(hasher) = hasher {
+            let output = PathBuf::from(format!("{}_*.{}", stem.display(), slice_format.extension()));
+            check_digest(&hasher.finish(), bvp_file, modality_index, region_is_volume, &output, false)?;
+        }
+        return Ok(());
+    }
+
+    if options.to_stdout {
+        let stdout = BufWriter::new(io::stdout().lock());
+        if let Some(digest) = write_raw(reader, modality_index, &raw_options, stdout, options.checksum, Path::new("stdout"))? {
+            check_digest(&digest, bvp_file, modality_index, region_is_volume, Path::new(STDIO_PATH), true)?;
+        }
+        return Ok(());
+    }
+    let path = PathBuf::from(format!("{}.raw", stem.display()));
+    check_overwrite(&path, options.force)?;
+    log_debug!("writing {}", path.display());
+    // The volume is written next to the output and only moved there once it is complete,
+    // so a failed reconstruction leaves no truncated raw file, nor replaces an existing one.
+    let temporary = PathBuf::from(format!("{}.raw.tmp", stem.display()));
+    let file = fs::File::create(&temporary).map_err(|e| CliError::io(format!("Cannot create {}: {}", temporary.display(), e)))?;
+    let digest = match write_raw(reader, modality_index, &raw_options, BufWriter::new(file), options.checksum, &path) {
+        Ok(digest) => digest,
+        Err(e) => {
+            let _ = fs::remove_file(&temporary);
+            return Err(e);
+        }
+    };
+    fs::rename(&temporary, &path).map_err(|e| {
+        let _ = fs::remove_file(&temporary);
+        return CliError::io(format!("Cannot write {}: {}", path.display(), e));
+    })?;
+    if let Some(digest) = digest {
+        check_digest(&digest, bvp_file, modality_index, region_is_volume, &path, false)?;
+    }
+
+    if options.nhdr {
+        let header_path = PathBuf::from(format!("{}.nhdr", stem.display()));
+        check_overwrite(&header_path, options.force)?;
+        let data_file = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let header = convert::nrrd_header(format, &bvp_file.modalities[modality_index], start, extent, &data_file)?;
+        fs::write(&header_path, header).map_err(|e| CliError::io(format!("Cannot write {}: {}", header_path.display(), e)))?;
+    }
+    return Ok(());
+}
+
+/// Prints the digest of a reconstructed volume, and compares it with the digest recorded in the manifest,
+/// if the manifest has one of the same algorithm and the whole volume was reconstructed.
+/// * `digest` - digest of the reconstructed voxels
+/// * `bvp_file` - the asset
+/// * `modality_index` - index of the modality
+/// * `region_is_volume` - true if the whole volume was reconstructed
+/// * `output` - where the volume was written, printed next to the digest
+/// * `to_stderr` - prints the digest to stderr, when the volume went to stdout
+fn check_digest(digest: &Digest, bvp_file: &BVPFile, modality_index: usize, region_is_volume: bool, output: &Path, to_stderr: bool) -> Result<(), CliError> {
+    if to_stderr {
+        eprintln!("{}  {}", digest.to_string(), output.display());
+    } else {
+        println!("{}  {}", digest.to_string(), output.display());
+    }
+    let recorded = digest::volume_digest(&bvp_file.modalities[modality_index]).map_err(|e| CliError::corrupt(e.to_string()))?;
+    let recorded = match recorded {
+        Some(r) => r,
+        None => {
+            log_debug!("the manifest records no digest of modality {}", modality_index);
+            return Ok(());
+        }
+    };
+    if !region_is_volume {
+        log_info!("the digest of modality {} in the manifest is not compared, only a region was reconstructed", modality_index);
+    } else if recorded.algorithm != digest.algorithm {
+        log_info!("the digest of modality {} in the manifest is {}, not {}, and is not compared", modality_index, recorded.algorithm.to_string(), digest.algorithm.to_string());
+    } else if recorded != *digest {
+        return Err(CliError::verification(format!(
+            "The reconstructed volume of modality {} has the digest {}, but the manifest records {}",
+            modality_index, digest.to_string(), recorded.to_string()
+        )));
+    } else {
+        log_info!("the digest of modality {} matches the manifest", modality_index);
+    }
+    return Ok(());
+}
+
+/// Returns the indices of the selected modalities, in the order they were given, or all of them.
+/// * `selectors` - indices or names of modalities, if any were given
+/// * `bvp_file` - the asset
+fn select_modalities(selectors: &Option<Vec<String>>, bvp_file: &BVPFile) -> Result<Vec<usize>, CliError> {
+    let selectors = match selectors {
+        Some(s) => s,
+        None => return Ok((0..bvp_file.modalities.len()).collect())
+    };
+    let mut indices = Vec::new();
+    for selector in selectors {
+        let by_name = bvp_file.modalities.iter().position(|m| m.name.as_deref() == Some(selector.as_str()));
+        let index = match (by_name, selector.parse::<usize>()) {
+            (Some(i), _) => i,
+            (None, Ok(i)) if i < bvp_file.modalities.len() => i,
+            (None, Ok(i)) => return Err(CliError::config(format!("Modality {} does not exist, there are {} modalities", i, bvp_file.modalities.len()))),
+            (None, Err(_)) => return Err(CliError::config(format!("No modality is named `{}`", selector)))
+        };
+        if !indices.contains(&index) {
+            indices.push(index);
+        }
+    }
+    return Ok(indices);
+}
+
+/// Reads an asset from stdin, detecting its archive type from the signature at its start.
+/// Stdin cannot be read twice, so all of it is read first, as `ArchiveEnum::read_archive` does.
+fn read_stdin() -> Result<BVPFile, CliError> {
+    let mut contents = Vec::new();
+    io::stdin().lock().read_to_end(&mut contents).map_err(|e| CliError::io(format!("Cannot read stdin: {}", e)))?;
+    let kind = detect::detect_bytes(&contents[..contents.len().min(detect::SIGNATURE_LENGTH)]);
+    log_debug!("read {} bytes from stdin, a {} file", contents.len(), kind);
+    let archive = match kind.archive() {
+        Some(ArchiveEnum::None) => return Err(CliError::config("Unarchived assets cannot be read from stdin, only SAF and ZIP archives")),
+        Some(a) => a,
+        None => return Err(CliError::from(DetectError::NotAnAsset("stdin".to_string(), kind.to_string())))
+    };
+    let files = archive.read_bytes(&Bytes::from(contents))?;
+    return Ok(BVPFile::from_files(files)?);
+}
+
+/// Runs the command.
+/// * `arguments` - the command line arguments after the name of the command
+pub fn run(arguments: Vec<String>) -> Result<(), CliError> {
+    let mut arguments_iter = arguments.into_iter();
+    let mut positional: Vec<String> = Vec::new();
+    let mut settings = Settings::default();
+    let mut config_path = None;
+    let mut verbosity = 0;
+    while let Some(arg) = arguments_iter.next() {
+        if arg == "--help" {
+            println!("{}", HELP);
+            return Ok(());
+        } else if arg == "--config" {
+            config_path = Some(arguments_iter.next().ok_or(CliError::config("Missing value for `--config`"))?);
+        } else if arg == "--start" || arg == "--end" {
+            let value = arguments_iter.next().ok_or(CliError::config(format!("Missing value for `{}`", arg)))?;
+            let position = match Vector3::from_string(&value) {
+                Some(p) => p,
+                None => return Err(CliError::config(format!("Value of `{}` must be three integers such as `0,0,0` (got `{}`)", arg, value)))
+            };
+            if arg == "--start" {
+                settings.start = Some(position);
+            } else {
+                settings.end = Some(position);
+            }
+        } else if arg == "--output-dir" {
+            settings.output_dir = Some(arguments_iter.next().ok_or(CliError::config("Missing value for `--output-dir`"))?);
+        } else if arg == "--output-name" {
+            settings.output_name = Some(arguments_iter.next().ok_or(CliError::config("Missing value for `--output-name`"))?);
+        } else if arg == "--modality" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--modality`"))?;
+            settings.modalities = Some(value.split(',').map(|m| m.trim().to_string()).filter(|m| !m.is_empty()).collect());
+        } else if arg == "--force" {
+            settings.force = Some(true);
+        } else if arg == "--no-verify" {
+            settings.verify_placements = Some(false);
+        } else if arg == "--verify" {
+            settings.verify_checksums = Some(true);
+        } else if arg == "--stream" {
+            settings.stream = Some(true);
+        } else if arg == "--slab-thickness" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--slab-thickness`"))?;
+            settings.slab_thickness = match value.parse::<u32>() {
+                Ok(t) if t > 0 => Some(t),
+                _ => return Err(CliError::config(format!("Value of `--slab-thickness` must be a positive integer (got `{}`)", value)))
+            };
+        } else if arg == "--slices" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--slices`"))?;
+            settings.slices = match SliceFormat::from_string(&value) {
+                Some(f) => Some(f),
+                None => return Err(CliError::config(format!("Value of `--slices` must be `png` or `tiff` (got `{}`)", value)))
+            };
+        } else if arg == "--window" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--window`"))?;
+            settings.window = match Window::from_string(&value) {
+                Some(w) => Some(w),
+                None => return Err(CliError::config(format!("Value of `--window` must be two numbers such as `0,4095` (got `{}`)", value)))
+            };
+        } else if arg == "--bit-depth" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--bit-depth`"))?;
+            settings.bit_depth = match value.as_str() {
+                "8" => Some(8),
+                "16" => Some(16),
+                _ => return Err(CliError::config(format!("Value of `--bit-depth` must be 8 or 16 (got `{}`)", value)))
+            };
+        } else if arg == "--nhdr" {
+            settings.nhdr = Some(true);
+        } else if arg == "--checksum" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--checksum`"))?;
+            settings.checksum = match DigestType::from_string(&value) {
+                Ok(c) => Some(c),
+                Err(_) => return Err(CliError::config(format!("Value of `--checksum` must be `xxh3` or `sha256` (got `{}`)", value)))
+            };
+        } else if let Some(change) = log::verbosity_flag(&arg) {
+            verbosity = if change < 0 { change } else { verbosity.max(0) + change };
+        } else {
+            positional.push(arg);
+        }
+    }
+    log::set_max_level(Level::from_verbosity(verbosity));
+    let mut positional = positional.into_iter();
+    settings.input_file = positional.next();
+    settings.archive = positional.next();
+    // Flags and arguments override the config file.
+    if let Some(path) = config_path {
+        settings = settings.or(config::read_config_file(&path)?);
+    }
+
+    let region = match (settings.start, settings.end) {
+        (Some(s), Some(e)) => Some((s, e)),
+        (None, None) => None,
+        _ => return Err(CliError::config("Both `--start` and `--end` are needed to reconstruct a region"))
+    };
+    let input_file = match settings.input_file {
+        Some(i) => i,
+        None => return Err(CliError::config("Missing input file"))
+    };
+    let input_filepath = Path::new(input_file.as_str());
+    let output_dir = PathBuf::from(settings.output_dir.unwrap_or(".".to_string()));
+    let output_name = settings.output_name;
+    let slice_format = settings.slices;
+    let nhdr = settings.nhdr.unwrap_or(false);
+    let stream = match settings.slab_thickness {
+        Some(t) => Some(Some(t)),
+        None if settings.stream == Some(true) => Some(None),
+        None => None
+    };
+    let bvp_state = {
+        let _span = Span::enter("read_archive");
+        match settings.archive {
+            Some(a) => BVPFile::open(input_filepath, &ArchiveEnum::from_string(a)?)?,
+            None if input_file == STDIO_PATH => read_stdin()?,
+            None => BVPFile::open(input_filepath, &detect::detect_archive(input_filepath)?)?
+        }
+    };
+    log_info!("read {} files from {}", bvp_state.files.len(), input_filepath.display());
+    log_info!(
+        "{} modalities, {} blocks, {} formats",
+        bvp_state.modalities.len(), bvp_state.blocks.len(), bvp_state.formats.len()
+    );
+
+    if settings.verify_placements.unwrap_or(true) {
+        let _span = Span::enter("verify");
+        let issues = validate::validate_block_tree(&bvp_state);
+        let mut error_count = 0;
+        for issue in &issues {
+            let location = if issue.path.is_empty() { "manifest".to_string() } else { issue.path.clone() };
+            if issue.severity == Severity::Error {
+                log_error!("[{}] {}: {}", issue.code.as_str(), location, issue.message);
+                error_count += 1;
+            } else {
+                log_warn!("[{}] {}: {}", issue.code.as_str(), location, issue.message);
+            }
+        }
+        if error_count > 0 {
+            return Err(CliError::verification(format!(
+                "Block tree verification found {} errors, the volume would have gaps or overwritten voxels (use `--no-verify` to skip)",
+                error_count
+            )));
+        }
+        log_info!("verified placements of {} blocks", bvp_state.blocks.len());
+    }
+
+    let modalities = select_modalities(&settings.modalities, &bvp_state)?;
+    let to_stdout = output_name.as_deref() == Some(STDIO_PATH);
+    if nhdr && (to_stdout || slice_format.is_some()) {
+        return Err(CliError::config("NRRD headers are only written next to raw files"));
+    }
+    if to_stdout {
+        if slice_format.is_some() {
+            return Err(CliError::config("Slices cannot be written to stdout"));
+        }
+        if modalities.len() != 1 {
+            return Err(CliError::config(format!("Only one modality can be written to stdout, {} are selected", modalities.len())));
+        }
+    } else {
+        fs::create_dir_all(&output_dir).map_err(|e| CliError::io(format!("Cannot create {}: {}", output_dir.display(), e)))?;
+    }
+    let options = ExportOptions {
+        region,
+        slice_format,
+        window: settings.window,
+        bit_depth: settings.bit_depth.unwrap_or(8),
+        force: settings.force.unwrap_or(false),
+        to_stdout,
+        nhdr,
+        stream,
+        checksum: settings.checksum
+    };
+    let mut errors = Vec::new();
+    let reader = VolumeReader::new(&bvp_state).with_checksum_verification(settings.verify_checksums.unwrap_or(false));
+    for modality_index in modalities {
+        let modality = &bvp_state.modalities[modality_index];
+        let _span = Span::enter("modality");
+        let volume_stem = output_dir.join(output_stem(&output_name, &modality.name, input_filepath, modality_index));
+        if let Err(e) = export_modality(&reader, &bvp_state, modality_index, &volume_stem, &options) {
+            errors.push(e);
+        }
+    }
+
+    // The errors are reported together, with the kind of the first one.
+    if let Some(first) = errors.first() {
+        let kind = first.kind;
+        let mut message = "Finished with the following errors: ".to_string();
+
+        for error in errors {
+            message = format!("{}\n{}", message, error);
+        }
+
+        return Err(CliError::new(kind, message));
+    }
+
+    return Ok(());
+}
\ No newline at end of file
@@ -0,0 +1,95 @@
+use std::{path::Path};
+
+use tinyjson::JsonValue;
+
+use bvp::archives::ArchiveEnum;
+use bvp::detect;
+use bvp::log::{self, Level};
+use bvp::signature;
+use bvp::json_aux::ParseMode;
+use bvp::validate::{self, Issue, IssueCode, Severity, ValidationOptions};
+
+use super::error::{CliError, ErrorKind};
+
+static HELP: &str = "bvp-validate\n------------\n Usage: bvp-validate <input_file> [<archive type>] [options]\n Options:\n  --json - print the issues as a JSON array, for scripting\n  --strict - also fail on warnings, and report fields that are not in the specification as errors\n  --verify - check block data against the checksums in the manifest\n  --check-signature - require a valid signature of the manifest\n  --trusted-key HEX - public key the signature has to be made with, can be given several times, implies `--check-signature`\n  -v, -vv, -vvv / --verbose - print more diagnostics to stderr (info, debug, trace)\n  -q / --quiet - print only errors\n Fails if the asset does not conform to the BVP specification.\n This message can be viewed with flag `--help`.";
+
+/// Runs the command.
+/// * `arguments` - the command line arguments after the name of the command
+pub fn run(arguments: Vec<String>) -> Result<(), CliError> {
+    let mut positional: Vec<String> = Vec::new();
+    let mut json = false;
+    let mut strict = false;
+    let mut options = ValidationOptions::default();
+    let mut verbosity = 0;
+    let mut arguments_iter = arguments.into_iter();
+    while let Some(arg) = arguments_iter.next() {
+        if arg == "--help" {
+            println!("{}", HELP);
+            return Ok(());
+        } else if arg == "--json" {
+            json = true;
+        } else if arg == "--strict" {
+            strict = true;
+            options.parse_mode = ParseMode::Strict;
+        } else if arg == "--verify" {
+            options.verify_checksums = true;
+        } else if arg == "--check-signature" {
+            options.check_signature = true;
+        } else if arg == "--trusted-key" {
+            let value = arguments_iter.next().ok_or(CliError::config("Missing value for `--trusted-key`"))?;
+            let key = signature::from_hex(&value)
+                .ok_or(format!("Value of `--trusted-key` must be a public key of 64 hexadecimal characters (got `{}`)", value))?;
+            options.trusted_keys.push(key);
+            options.check_signature = true;
+        } else if let Some(change) = log::verbosity_flag(&arg) {
+            verbosity = if change < 0 { change } else { verbosity.max(0) + change };
+        } else if arg.starts_with('-') {
+            return Err(CliError::config(format!("Unknown option `{}`", arg)));
+        } else {
+            positional.push(arg);
+        }
+    }
+    log::set_max_level(Level::from_verbosity(verbosity));
+
+    let input_filepath = match positional.first() {
+        Some(p) => Path::new(p.as_str()),
+        None => return Err(CliError::config("Missing input file"))
+    };
+    let archive_tp = match positional.get(1) {
+        Some(a) => ArchiveEnum::from_string(a.clone())?,
+        None => detect::detect_archive(input_filepath)?
+    };
+
+    // Only broken archives are issues of the asset, a file that cannot be read at all is not an asset to validate.
+    let issues = match archive_tp.read_archive(input_filepath).map_err(CliError::from) {
+        Ok(files) => validate::validate_files_with_options(&files, &options),
+        Err(e) if e.kind == ErrorKind::CorruptAsset => vec![Issue {
+            severity: Severity::Error,
+            code: IssueCode::UnreadableArchive,
+            path: String::new(),
+            message: e.message
+        }],
+        Err(e) => return Err(e.in_file(input_filepath.display()))
+    };
+
+    if json {
+        let list: Vec<JsonValue> = issues.iter().map(|issue| issue.to_json()).collect();
+        let content = JsonValue::from(list).stringify().map_err(|e| format!("Error creating JSON: {}", e))?;
+        println!("{}", content);
+    } else {
+        for issue in &issues {
+            let location = if issue.path.is_empty() { "manifest".to_string() } else { issue.path.clone() };
+            println!("{}[{}] {}: {}", issue.severity.to_string(), issue.code.as_str(), location, issue.message);
+        }
+    }
+
+    let errors = issues.iter().filter(|issue| issue.severity == Severity::Error).count();
+    let warnings = issues.len() - errors;
+    if errors > 0 || (strict && warnings > 0) {
+        return Err(CliError::verification(format!("{} errors and {} warnings found", errors, warnings)));
+    }
+    if !json {
+        println!("Valid ({} warnings)", warnings);
+    }
+    return Ok(());
+}
@@ -0,0 +1,184 @@
+//! Removal of sensitive metadata from assets, for sharing clinical datasets.
+//!
+//! Anonymization clears the author, acquisition time and description of the asset, the descriptions
+//! of its modalities, and every value in `extras` whose key is on a deny-list. Values are either
+//! removed, or replaced by a salted hash, `anon-<hexadecimal xxh3>`, so that equal values stay equal,
+//! for example to tell apart the patients of several assets without naming them.
+
+use std::collections::HashMap;
+
+use tinyjson::JsonValue;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{bvpfile::BVPFile, json_aux};
+
+/// Keys in `extras` that are anonymized by default, mostly the identifying attributes of DICOM.
+/// Keys are compared without case and without characters other than letters and digits,
+/// so `patientName`, `PatientName` and `patient_name` are the same key.
+pub const DEFAULT_DENY_LIST: [&str; 18] = [
+    "patientName", "patientId", "patientBirthDate", "patientSex", "patientAge", "patientAddress",
+    "institutionName", "institutionAddress", "referringPhysicianName", "performingPhysicianName",
+    "operatorsName", "accessionNumber", "studyDate", "studyTime", "seriesDate", "acquisitionDate",
+    "deviceSerialNumber", "stationName",
+];
+/// Prefix of the values anonymization replaces with hashes.
+pub const HASH_PREFIX: &str = "anon-";
+
+/// What happens to sensitive values.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AnonymizeAction {
+    /// They are removed.
+    Strip,
+    /// They are replaced by the hash of the salt followed by the value. Without a secret salt,
+    /// hashes of values that are easy to guess, such as dates, can be reversed by trying them all.
+    Hash(String)
+}
+
+#[derive(Clone, Debug)]
+pub struct AnonymizeOptions {
+    pub action: AnonymizeAction,
+    /// Keys of `extras` that are anonymized, at any depth.
+    pub deny_list: Vec<String>
+}
+
+impl Default for AnonymizeOptions {
+    fn default() -> Self {
+        return Self { action: AnonymizeAction::Strip, deny_list: DEFAULT_DENY_LIST.iter().map(|k| k.to_string()).collect() };
+    }
+}
+
+impl AnonymizeOptions {
+    /// Returns true if a key of `extras` is on the deny-list.
+    /// * `key` - the key
+    pub fn is_denied(&self, key: &str) -> bool {
+        let key = normalize_key(key);
+        return self.deny_list.iter().any(|k| normalize_key(k) == key);
+    }
+
+    /// Returns the value that replaces a sensitive one, or None if it is removed.
+    /// * `value` - text of the value
+    fn replacement(&self, value: &str) -> Option<String> {
+        return match &self.action {
+            AnonymizeAction::Strip => None,
+            AnonymizeAction::Hash(salt) => Some(format!("{}{:016x}", HASH_PREFIX, xxh3_64(format!("{}{}", salt, value).as_bytes())))
+        };
+    }
+
+    /// Anonymizes an optional text field.
+    /// * `field` - the field
+    /// * `path` - path of the field, recorded in `changed`
+    /// * `changed` - paths of the anonymized values
+    fn field(&self, field: &mut Option<String>, path: &str, changed: &mut Vec<String>) {
+        if let Some(value) = field.take() {
+            *field = self.replacement(&value);
+            changed.push(path.to_string());
+        }
+    }
+
+    /// Anonymizes the values of denied keys in application specific data.
+    /// * `extras` - the data
+    /// * `path` - path of the data, recorded in `changed`
+    /// * `changed` - paths of the anonymized values
+    fn extras(&self, extras: &mut JsonValue, path: &str, changed: &mut Vec<String>) {
+        match extras {
+            JsonValue::Object(o) => {
+                let mut keys: Vec<String> = o.keys().cloned().collect();
+                keys.sort();
+                for key in keys {
+                    let value_path = format!("{}.{}", path, key);
+                    if !self.is_denied(&key) {
+                        self.extras(o.get_mut(&key).unwrap(), &value_path, changed);
+                        continue;
+                    }
+                    let value = o.remove(&key).unwrap();
+                    let text = match &value {
+                        JsonValue::String(s) => s.clone(),
+                        other => json_aux::canonical_string(other)
+                    };
+                    if let Some(replacement) = self.replacement(&text) {
+                        o.insert(key, replacement.into());
+                    }
+                    changed.push(value_path);
+                }
+            },
+            JsonValue::Array(a) => {
+                for (i, value) in a.iter_mut().enumerate() {
+                    self.extras(value, &format!("{}[{}]", path, i), changed);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    /// Anonymizes an optional text field of a manifest object.
+    /// * `object` - the object
+    /// * `key` - key of the field
+    /// * `path` - path of the object, recorded in `changed` with the key
+    /// * `changed` - paths of the anonymized values
+    fn json_field(&self, object: &mut HashMap<String, JsonValue>, key: &str, path: &str, changed: &mut Vec<String>) {
+        let mut field = match object.remove(key) {
+            Some(JsonValue::String(s)) => Some(s),
+            Some(other) => Some(json_aux::canonical_string(&other)),
+            None => None
+        };
+        self.field(&mut field, &format!("{}.{}", path, key), changed);
+        if let Some(value) = field {
+            object.insert(key.to_string(), value.into());
+        }
+    }
+}
+
+/// Lowercase key without characters other than letters and digits.
+fn normalize_key(key: &str) -> String {
+    return key.chars().filter(|c| c.is_alphanumeric()).flat_map(|c| c.to_lowercase()).collect();
+}
+
+/// Anonymizes the metadata of an asset and its modalities.
+/// Returns the paths of the anonymized values, such as `asset.author` or `modalities[0].extras.patientName`.
+/// * `bvp_file` - the asset
+/// * `options` - what is anonymized and how
+pub fn anonymize(bvp_file: &mut BVPFile, options: &AnonymizeOptions) -> Vec<String> {
+    let mut changed = Vec::new();
+    let asset = &mut bvp_file.asset;
+    options.field(&mut asset.author, "asset.author", &mut changed);
+    options.field(&mut asset.acquisition_time, "asset.acquisitionTime", &mut changed);
+    options.field(&mut asset.description, "asset.description", &mut changed);
+    if let Some(extras) = &mut asset.extras {
+        options.extras(extras, "asset.extras", &mut changed);
+    }
+    for (i, modality) in bvp_file.modalities.iter_mut().enumerate() {
+        options.field(&mut modality.description, &format!("modalities[{}].description", i), &mut changed);
+        if let Some(extras) = &mut modality.extras {
+            options.extras(extras, &format!("modalities[{}].extras", i), &mut changed);
+        }
+    }
+    return changed;
+}
+
+/// Anonymizes the metadata of a manifest given as JSON, keeping everything else as it is.
+/// Returns the paths of the anonymized values, like `anonymize`.
+/// * `manifest` - the root object of the manifest
+/// * `options` - what is anonymized and how
+pub fn anonymize_manifest(manifest: &mut HashMap<String, JsonValue>, options: &AnonymizeOptions) -> Vec<String> {
+    let mut changed = Vec::new();
+    if let Some(JsonValue::Object(asset)) = manifest.get_mut("asset") {
+        for key in ["author", "acquisitionTime", "description"] {
+            options.json_field(asset, key, "asset", &mut changed);
+        }
+        if let Some(extras) = asset.get_mut("extras") {
+            options.extras(extras, "asset.extras", &mut changed);
+        }
+    }
+    if let Some(JsonValue::Array(modalities)) = manifest.get_mut("modalities") {
+        for (i, modality) in modalities.iter_mut().enumerate() {
+            if let JsonValue::Object(modality) = modality {
+                let path = format!("modalities[{}]", i);
+                options.json_field(modality, "description", &path, &mut changed);
+                if let Some(extras) = modality.get_mut("extras") {
+                    options.extras(extras, &format!("{}.extras", path), &mut changed);
+                }
+            }
+        }
+    }
+    return changed;
+}
@@ -1,17 +1,128 @@
-use std::{fs, path::Path};
-use crate::{file::File, errors::ArchiveError};
+#[cfg(feature = "fs")]
+use std::{fs, io::{self, Read, Write}, path::Path};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use crate::{bytes::Bytes, file::File, errors::ArchiveError};
+#[cfg(feature = "fs")]
+use crate::{log_debug, remote::{self, Location}};
 
-use self::{saf::SAFWriter, zip::ZIPWriter, unarchived::RawFilesWriter};
+#[cfg(feature = "fs")]
+use self::{saf::SAFWriter, zip::{MemberCompression, ZIPWriter}, unarchived::RawFilesWriter};
 
 pub mod saf;
 pub mod zip;
+#[cfg(feature = "fs")]
 pub mod unarchived;
 
+/// Writes the files of an asset to an archive on the file system.
+#[cfg(feature = "fs")]
 pub trait ArchiveWriter {
-    fn append_file(&mut self, file: &File) -> Result<(), String>;
-    fn finish(&self, path: String) -> Result<(), String>;
+    fn append_file(&mut self, file: &File) -> Result<(), ArchiveError>;
+    fn finish(&mut self, path: String) -> Result<(), ArchiveError>;
 }
 
+#[cfg(feature = "fs")]
+impl<W: ArchiveWriter + ?Sized> ArchiveWriter for Box<W> {
+    fn append_file(&mut self, file: &File) -> Result<(), ArchiveError> {
+        return (**self).append_file(file);
+    }
+
+    fn finish(&mut self, path: String) -> Result<(), ArchiveError> {
+        return (**self).finish(path);
+    }
+}
+
+#[cfg(feature = "fs")]
+impl<W: ArchiveWriter + ?Sized> ArchiveWriter for &mut W {
+    fn append_file(&mut self, file: &File) -> Result<(), ArchiveError> {
+        return (**self).append_file(file);
+    }
+
+    fn finish(&mut self, path: String) -> Result<(), ArchiveError> {
+        return (**self).finish(path);
+    }
+}
+
+/// Path that stands for stdin when reading an archive and for stdout when writing one.
+pub const STDIO_PATH: &str = "-";
+
+/// Writes a finished archive to a local path, a URL (see `remote`) or stdout.
+/// * `path` - the path or URL, or `-` for stdout
+/// * `data` - bytes of the archive
+#[cfg(feature = "fs")]
+fn write_archive(path: &String, data: &[u8]) -> Result<(), ArchiveError> {
+    if path == STDIO_PATH {
+        let mut stdout = io::stdout().lock();
+        return stdout.write_all(data).and_then(|_| stdout.flush()).map_err(|err| ArchiveError::WriteFailed("stdout".to_string(), err));
+    }
+    if remote::is_remote(path) {
+        return remote::write(path, data).map_err(ArchiveError::RemoteError);
+    }
+    return fs::write(path, data).map_err(|err| ArchiveError::WriteFailed(path.clone(), err));
+}
+
+/// Members each thread should have at least, so small archives are not read on more threads than it is worth.
+const MEMBERS_PER_THREAD: usize = 16;
+/// Most threads reading members at once; beyond this, copying is bound by memory bandwidth.
+const MAX_READ_THREADS: usize = 8;
+
+/// Reads the members of an archive on a pool of threads and returns them in the order of `members`.
+/// Threads take the next member when they finish one, so a few large members do not hold up the rest.
+/// If reading several members fails, the error of the first of them is returned.
+/// Members are read on the calling thread when no parallelism is available, such as on wasm.
+/// * `members` - where each member is found in the archive
+/// * `read` - reads one member
+pub(crate) fn read_members<T, R, E, F>(members: &[T], read: F) -> Result<Vec<R>, E>
+    where T: Sync, R: Send, E: Send, F: Fn(&T) -> Result<R, E> + Sync
+{
+    let parallelism = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let threads = parallelism.min(MAX_READ_THREADS).min(members.len() / MEMBERS_PER_THREAD);
+    if threads <= 1 {
+        return members.iter().map(read).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let worker = || {
+        let mut read_members = Vec::new();
+        // Members are taken in order, so after a failure every member before it has already been
+        // taken and is still read, in case one of them fails as well and its error is returned.
+        while !failed.load(Ordering::Relaxed) {
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            if index >= members.len() {
+                break;
+            }
+            let result = read(&members[index]);
+            if result.is_err() {
+                failed.store(true, Ordering::Relaxed);
+            }
+            read_members.push((index, result));
+        }
+        return read_members;
+    };
+    let results = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads).map(|_| scope.spawn(&worker)).collect();
+        return handles.into_iter()
+            .flat_map(|handle| match handle.join() {
+                Ok(r) => r,
+                Err(panic) => std::panic::resume_unwind(panic)
+            })
+            .collect::<Vec<_>>();
+    });
+
+    let mut slots: Vec<Option<Result<R, E>>> = (0..members.len()).map(|_| None).collect();
+    for (index, result) in results {
+        slots[index] = Some(result);
+    }
+    let mut files = Vec::with_capacity(members.len());
+    // Only members after a failed one are skipped, so the first error is found before any gaps.
+    for result in slots.into_iter().flatten() {
+        files.push(result?);
+    }
+    return Ok(files);
+}
+
+#[derive(Clone, Copy)]
 pub enum ArchiveEnum {
     SAF,
     ZIP,
@@ -19,16 +130,28 @@ pub enum ArchiveEnum {
 }
 
 impl ArchiveEnum {
-    pub fn return_writer(&self) -> Box<dyn ArchiveWriter + Send> {
+    /// Returns a writer for an archive of this type.
+    /// * `output` - the archive file, or the folder unarchived files are written to
+    #[cfg(feature = "fs")]
+    pub fn return_writer(&self, output: &str) -> Box<dyn ArchiveWriter + Send> {
+        return self.return_compressed_writer(output, MemberCompression::Stored);
+    }
+
+    /// Returns a writer for an archive of this type, which compresses the members of ZIP archives.
+    /// SAF archives and unarchived files are never compressed.
+    /// * `output` - the archive file, or the folder unarchived files are written to
+    /// * `compression` - how members of ZIP archives are compressed
+    #[cfg(feature = "fs")]
+    pub fn return_compressed_writer(&self, output: &str, compression: MemberCompression) -> Box<dyn ArchiveWriter + Send> {
         match self {
             Self::SAF => {
                 return Box::new(SAFWriter::new());
             },
             Self::ZIP => {
-                return Box::new(ZIPWriter::new());
+                return Box::new(ZIPWriter::new().with_compression(compression));
             },
             Self::None => {
-                return Box::new(RawFilesWriter::new());
+                return Box::new(RawFilesWriter::new(output));
             }
         }
     }
@@ -43,27 +166,50 @@ impl ArchiveEnum {
         }
     }
 
+    /// Reads an archive from memory and returns raw files inside, which share the buffer of the archive.
+    /// Unarchived assets are made of several files, so they cannot be read this way.
+    /// * `data` - bytes of the archive, such as a `Vec<u8>` converted with `Bytes::from`
+    pub fn read_bytes(&self, data: &Bytes) -> Result<Vec<File>, ArchiveError> {
+        return match self {
+            ArchiveEnum::SAF => saf::from_saf_archive(data).map_err(|x| ArchiveError::SafError(x)),
+            ArchiveEnum::ZIP => zip::from_zip_archive(data).map_err(|x| ArchiveError::ZipError(x)),
+            ArchiveEnum::None => Err(ArchiveError::NotImplemented("reading an unarchived asset from memory".to_string()))
+        };
+    }
+
     /// Reads the archive file/folder and returns raw files inside.
-    /// * `filepath` - path to file/folder to read
+    /// * `filepath` - path to file/folder to read, or `-` to read an archive from stdin
+    #[cfg(feature = "fs")]
     pub fn read_archive(&self, filepath: &Path) -> Result<Vec<File>, ArchiveError> {
+        if filepath == Path::new(STDIO_PATH) {
+            if let ArchiveEnum::None = self {
+                return Err(ArchiveError::NotImplemented("unarchived assets cannot be read from stdin".to_string()));
+            }
+            let mut contents = Vec::new();
+            io::stdin().lock().read_to_end(&mut contents).map_err(|e| ArchiveError::CannotRead(e.to_string()))?;
+            log_debug!("read {} bytes from stdin", contents.len());
+            return self.read_bytes(&Bytes::from(contents));
+        }
+        let location = Location::parse(&filepath.to_string_lossy()).map_err(ArchiveError::RemoteError)?;
+        if location.is_remote() {
+            log_debug!("reading {} as a remote {}", filepath.display(), if let ArchiveEnum::None = self { "manifest" } else { "archive" });
+            return match self {
+                ArchiveEnum::None => unarchived::from_manifest_location(&location),
+                _ => self.read_bytes(&Bytes::from(location.read().map_err(ArchiveError::RemoteError)?))
+            };
+        }
+        log_debug!("reading {} as {}", filepath.display(), if filepath.is_dir() { "a folder" } else { "a file" });
         if filepath.is_dir() {
             return unarchived::from_folder(filepath);
         } else if filepath.is_file() {
             return match self {
                 ArchiveEnum::None => unarchived::from_manifest_file(&filepath),
-                ArchiveEnum::SAF => {
-                    let contents = match fs::read(filepath) {
-                        Ok(v) => v,
-                        Err(e) => return Err(ArchiveError::CannotRead(e.to_string()))
-                    };
-                    saf::from_saf_archive(&contents).map_err(|x| ArchiveError::SafError(x))
-                },
-                ArchiveEnum::ZIP => {
+                _ => {
                     let contents = match fs::read(filepath) {
                         Ok(v) => v,
                         Err(e) => return Err(ArchiveError::CannotRead(e.to_string()))
                     };
-                    zip::from_zip_archive(&contents).map_err(|x| ArchiveError::ZipError(x))
+                    self.read_bytes(&Bytes::from(contents))
                 }
             }
         }
@@ -1,21 +1,25 @@
-use std::{collections::HashMap, io::{Read}, str::FromStr, fs};
-use std::sync::Arc;
+use std::{collections::HashMap, str::FromStr};
 use tinyjson::JsonValue;
 
-use crate::{file::File, errors::{SafError}};
+use crate::{bytes::Bytes, file::File, errors::SafError};
+#[cfg(feature = "fs")]
+use crate::errors::ArchiveError;
 use crate::json_aux;
 
+#[cfg(feature = "fs")]
 use super::ArchiveWriter;
 
 const SAF_IDENTIFIER_LENGTH: usize = 12;
 const SAF_IDENTIFIER: [u8; 12] = [0xab, 0x53, 0x41, 0x46, 0x20, 0x31, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a];
 
+#[cfg(feature = "fs")]
 struct SAFFileEntry {
     path: String,
     mime: Option<String>,
     size: usize
 }
 
+#[cfg(feature = "fs")]
 impl SAFFileEntry {
     pub fn as_json(&self) -> JsonValue {
         let mut hm = HashMap::new();
@@ -28,11 +32,13 @@ impl SAFFileEntry {
     }
 }
 
+#[cfg(feature = "fs")]
 pub struct SAFWriter {
     file_content: Vec<u8>,
     file_metadata: Vec<SAFFileEntry>
 }
 
+#[cfg(feature = "fs")]
 impl SAFWriter {
     pub fn new() -> Self {
         return Self {
@@ -42,8 +48,9 @@ impl SAFWriter {
     }
 }
 
+#[cfg(feature = "fs")]
 impl ArchiveWriter for SAFWriter {
-    fn append_file(&mut self, file: &File) -> Result<(), String> {
+    fn append_file(&mut self, file: &File) -> Result<(), ArchiveError> {
         let file_entry = SAFFileEntry {
             path: file.name.clone(),
             mime: file.mime.clone(),
@@ -57,20 +64,23 @@ impl ArchiveWriter for SAFWriter {
         return Ok(());
     }
 
-    fn finish(&self, path: String) -> Result<(), String> {
+    fn finish(&mut self, path: String) -> Result<(), ArchiveError> {
         let mut manifest = Vec::new();
         for file in &self.file_metadata {
             manifest.push(file.as_json());
         }
         let json: JsonValue = manifest.into();
-        let text = match json.stringify() {
+        let text = match json_aux::try_canonical_string(&json) {
             Ok(t) => t,
             Err(e) => {
-                return Err(e.to_string());
+                return Err(ArchiveError::SafError(SafError::ManifestCorrupt(e.to_string())));
             },
         };
         let manifest_buffer = text.as_bytes();
         let manifest_size = manifest_buffer.len();
+        if manifest_size > u32::MAX as usize {
+            return Err(ArchiveError::SafError(SafError::ManifestTooLarge(manifest_size)));
+        }
         let manifest_size_buffer = (manifest_size as u32).to_le_bytes();
         let saf_size = SAF_IDENTIFIER_LENGTH + manifest_size_buffer.len() + manifest_buffer.len() + self.file_metadata.iter().map(|e| e.size).sum::<usize>();
         let mut saf: Vec<u8> = Vec::with_capacity(saf_size);
@@ -87,15 +97,27 @@ impl ArchiveWriter for SAFWriter {
             saf.push(*el);
         }
 
-        fs::write(path, saf.as_slice()).map_err(|err| err.to_string())?;
+        super::write_archive(&path, saf.as_slice())?;
     
         return Ok(());
     }
 }
 
+/// Returns bytes of the archive, or an error if it ends before them.
+/// * `data` - SAF file as bytes
+/// * `offset` - index of the first byte
+/// * `length` - number of bytes
+/// * `what` - what the bytes hold, for the error
+fn get_bytes<'d>(data: &'d [u8], offset: usize, length: usize, what: &str) -> Result<&'d [u8], SafError> {
+    return match offset.checked_add(length).and_then(|end| data.get(offset..end)) {
+        Some(bytes) => Ok(bytes),
+        None => Err(SafError::OutOfBounds(what.to_string(), offset, length))
+    };
+}
+
 /// Checks if provided data has a valid SAF identifier.
 /// * `data` - raw bytes as vector of u8
-pub fn check_identifier(data: &Vec<u8>) -> Result<(), SafError> {
+pub fn check_identifier(data: &[u8]) -> Result<(), SafError> {
     if data.len() < SAF_IDENTIFIER_LENGTH {
         return Err(SafError::BrokenFile);
     }
@@ -110,27 +132,23 @@ pub fn check_identifier(data: &Vec<u8>) -> Result<(), SafError> {
 /// Returns SAF manifest size.
 /// * `vec` - SAF file as bytes array
 /// * `offset` - index of the byte at which the manifest size starts
-pub fn get_manifest_size(vec: &Vec<u8>, offset: usize) -> Result<u32, SafError> {
-    if vec.len() < offset + 4 {
-        return Err(SafError::BrokenFile);
-    }
-    let mut arr = [0, 0, 0, 0];
-    for i in 0..4 {
-        arr[i] = vec[offset + i];
-    }
-    return Ok(u32::from_le_bytes(arr));
+pub fn get_manifest_size(vec: &[u8], offset: usize) -> Result<u32, SafError> {
+    let bytes = get_bytes(vec, offset, 4, "the manifest size")?;
+    return Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
 }
 
 /// Extracts SAF manifest from SAF file and returns it as a JSON object.
 /// * `vec` - SAF file as bytes array
 /// * `offset` - index of the byte at which the manifest starts
 /// * `length` - the length of manifest in bytes
-pub fn get_manifest(vec: &Vec<u8>, offset: usize, length: usize) -> Result<JsonValue, SafError> {
-    let mut bytes = &vec[offset..offset + length];
-    let mut text = String::new();
-    bytes.read_to_string(&mut text).unwrap();
+pub fn get_manifest(vec: &[u8], offset: usize, length: usize) -> Result<JsonValue, SafError> {
+    let bytes = get_bytes(vec, offset, length, "the manifest")?;
+    let text = match std::str::from_utf8(bytes) {
+        Ok(t) => t,
+        Err(e) => return Err(SafError::ManifestCorrupt(format!("not valid UTF-8 ({})", e)))
+    };
 
-    match JsonValue::from_str(&text) {
+    match JsonValue::from_str(text) {
         Ok(j) => {
             return Ok(j);
         },
@@ -154,7 +172,7 @@ pub fn to_saf_archive(files: &Vec<File>) -> Result<Vec<u8>, SafError> {
         manifest.push(file_hashmap.into());
     }
     let json = JsonValue::from(manifest);
-    let text = match json.stringify() {
+    let text = match json_aux::try_canonical_string(&json) {
         Ok(t) => t,
         Err(e) => {
             return Err(SafError::ManifestCorrupt(e.to_string()));
@@ -162,6 +180,9 @@ pub fn to_saf_archive(files: &Vec<File>) -> Result<Vec<u8>, SafError> {
     };
     let manifest_buffer = text.as_bytes();
     let manifest_size = manifest_buffer.len();
+    if manifest_size > u32::MAX as usize {
+        return Err(SafError::ManifestTooLarge(manifest_size));
+    }
     let manifest_size_buffer = (manifest_size as u32).to_le_bytes();
     let saf_size = SAF_IDENTIFIER_LENGTH + manifest_size_buffer.len() + manifest_buffer.len() + files.iter().map(|e| e.data.len()).sum::<usize>();
     let mut saf: Vec<u8> = Vec::with_capacity(saf_size);
@@ -183,9 +204,9 @@ pub fn to_saf_archive(files: &Vec<File>) -> Result<Vec<u8>, SafError> {
     return Ok(saf);
 }
 
-/// Extracts files from SAF archive. Returns a vectors of raw files.
+/// Extracts files from SAF archive. Returns a vectors of raw files, which share the buffer of the archive.
 /// * `saf` - SAF file as bytes
-pub fn from_saf_archive(saf: &Vec<u8>) -> Result<Vec<File>, SafError> {
+pub fn from_saf_archive(saf: &Bytes) -> Result<Vec<File>, SafError> {
     check_identifier(saf)?;
     
     let mut offset = SAF_IDENTIFIER_LENGTH;
@@ -200,27 +221,27 @@ pub fn from_saf_archive(saf: &Vec<u8>) -> Result<Vec<File>, SafError> {
 
     let mut files = Vec::new();
 
-    for file_entry in manifest_files {
+    for (i, file_entry) in manifest_files.iter().enumerate() {
         match file_entry {
             JsonValue::Object(o) => {
-                let path = match json_aux::get_string_from_json(&o["path"]) {
+                let field = |name: &str| o.get(name).ok_or_else(|| SafError::MissingField(i, name.to_string()));
+                let path = match json_aux::get_string_from_json(field("path")?) {
                     Ok(p) => p,
                     Err(e) => return Err(SafError::InvalidJson(e))
                 };
                 let mime = match o.get("mime") {
                     Some(s) => match json_aux::get_string_from_json(s) {
-                        Ok(m) => m,
+                        Ok(m) => Some(m),
                         Err(e) => return Err(SafError::InvalidJson(e))
                     },
-                    None => String::new()
+                    None => None
                 };
-                let size = match json_aux::get_u32_from_json(&o["size"]) {
+                let size = match json_aux::get_u64_from_json(field("size")?) {
                     Ok(s) => s as usize,
                     Err(e) => return Err(SafError::InvalidJson(e))
                 };
-                let data = saf[offset..offset+size].to_vec();
-                let file = File::new(path, Arc::new(data), Some(mime));
-                files.push(file);
+                get_bytes(saf, offset, size, &format!("file `{}`", path))?;
+                files.push(File::new(path, saf.slice(offset..offset + size), mime));
                 offset += size;
             },
             _ => ()
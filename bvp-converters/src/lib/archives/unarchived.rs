@@ -1,51 +1,178 @@
-use std::{path::Path, fs, str::FromStr, collections::HashMap};
+use std::{path::{Component, Path, PathBuf}, fs, str::FromStr, collections::{HashMap, HashSet}, thread};
 use std::sync::Arc;
 
+use crossbeam::channel::{self, Sender};
 use tinyjson::JsonValue;
 
-use crate::{errors::{ArchiveError}, file::File};
+use crate::{errors::{ArchiveError}, file::File, log_warn, remote::Location};
 
 use super::ArchiveWriter;
 
+/// Largest number of threads writing files at the same time.
+const MAX_WRITER_THREADS: usize = 8;
+/// Number of files waiting for a writer thread, after which appending a file blocks.
+const QUEUE_LENGTH: usize = 32;
+
+/// Writes the files of an asset to a folder, at their names in the manifest relative to it.
+/// Files are written by a pool of threads, which keeps fast disks and network file systems busy
+/// with many small block files.
 pub struct RawFilesWriter {
+    folder: PathBuf,
+    sender: Option<Sender<File>>,
+    workers: Vec<thread::JoinHandle<Result<(), ArchiveError>>>,
+    /// Folders that were already created or checked, so that each is only looked at once
+    folders: HashSet<PathBuf>
+}
 
+/// Writes files from the queue until it is closed.
+fn write_files(receiver: channel::Receiver<File>) -> Result<(), ArchiveError> {
+    for file in receiver {
+        fs::write(&file.name, file.data.as_slice()).map_err(|err| ArchiveError::WriteFailed(file.name.clone(), err))?;
+    }
+    return Ok(());
 }
 
 impl RawFilesWriter {
-    pub fn new() -> Self {
-        return Self {  };
+    /// * `folder` - the folder of the asset, created if it does not exist
+    pub fn new(folder: &str) -> Self {
+        return Self { folder: PathBuf::from(folder), sender: None, workers: Vec::new(), folders: HashSet::new() };
     }
-}
 
-impl ArchiveWriter for RawFilesWriter {
-    fn append_file(&mut self, file: &File) -> Result<(), String> {
-        let path = Path::new(&file.name);
-        if path.parent().is_some() {
-            let parent = path.parent().unwrap();
-            if !parent.try_exists().expect("Error: Cannot determine if folder exists") {
-                fs::create_dir_all(parent).map_err(|err| err.to_string())?;
-            } else if parent.is_file() {
-                return Err("Destination folder is a file!".to_string());
+    /// Starts the writer threads, before the first file is written.
+    fn start(&mut self) -> Sender<File> {
+        let (sender, receiver) = channel::bounded(QUEUE_LENGTH);
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(MAX_WRITER_THREADS);
+        for _ in 0..threads {
+            let receiver = receiver.clone();
+            self.workers.push(thread::spawn(move || write_files(receiver)));
+        }
+        self.sender = Some(sender.clone());
+        return sender;
+    }
+
+    /// Closes the queue, waits for the writer threads and returns the first error they had.
+    fn join(&mut self) -> Result<(), ArchiveError> {
+        self.sender = None;
+        let mut result = Ok(());
+        for worker in self.workers.drain(..) {
+            let worker_result = match worker.join() {
+                Ok(r) => r,
+                Err(_) => Err(ArchiveError::CannotWrite("a writer thread panicked".to_string()))
+            };
+            if result.is_ok() {
+                result = worker_result;
             }
         }
+        return result;
+    }
 
-        fs::write(path, file.data.as_slice()).map_err(|err| err.to_string())?;
+    /// Creates the folder of a file, unless it exists.
+    /// * `parent` - the folder
+    fn create_folder(&mut self, parent: &Path) -> Result<(), ArchiveError> {
+        if self.folders.contains(parent) {
+            return Ok(());
+        }
+        let exists = parent.try_exists().map_err(|err| ArchiveError::WriteFailed(parent.display().to_string(), err))?;
+        if !exists {
+            fs::create_dir_all(parent).map_err(|err| ArchiveError::WriteFailed(parent.display().to_string(), err))?;
+        } else if parent.is_file() {
+            return Err(ArchiveError::CannotWrite(format!("destination folder `{}` is a file", parent.display())));
+        }
+        self.folders.insert(parent.to_path_buf());
         return Ok(());
     }
+}
 
-    fn finish(&self, _path: String) -> Result<(), String> {
+impl ArchiveWriter for RawFilesWriter {
+    fn append_file(&mut self, file: &File) -> Result<(), ArchiveError> {
+        let path = self.folder.join(&file.name);
+        if let Some(parent) = path.parent() {
+            self.create_folder(parent)?;
+        }
+
+        // Writer threads only stop early when they fail, so their error is returned right away.
+        if self.workers.iter().any(|w| w.is_finished()) {
+            return self.join();
+        }
+        let sender = match &self.sender {
+            Some(s) => s.clone(),
+            None => self.start()
+        };
+        let file = File::new(path.to_string_lossy().to_string(), file.data.clone(), file.mime.clone());
+        if sender.send(file).is_err() {
+            return self.join();
+        }
         return Ok(());
     }
+
+    fn finish(&mut self, _path: String) -> Result<(), ArchiveError> {
+        return self.join();
+    }
 }
 
 pub fn from_manifest_file(filepath: &Path) -> Result<Vec<File>, ArchiveError> {
-    let mut files = Vec::new();
-
     let manifest_contents = match fs::read(filepath) {
         Ok(v) => v,
         Err(e) => return Err(ArchiveError::CannotRead(e.to_string()))
     };
+    return from_manifest_data(filepath.to_string_lossy().to_string(), manifest_contents, |data_path| {
+        let full_data_path = Path::new(filepath.parent().unwrap()).join(data_path);
+        return fs::read(&full_data_path).map_err(|x| ArchiveError::CannotRead(format!("Could not read file {} ({})", full_data_path.display(), x)));
+    });
+}
+
+/// Reads a manifest and the data files next to it from a URL, see `remote`.
+/// * `location` - location of the manifest
+pub fn from_manifest_location(location: &Location) -> Result<Vec<File>, ArchiveError> {
+    let manifest_contents = location.read().map_err(ArchiveError::RemoteError)?;
+    return from_manifest_data("manifest.json".to_string(), manifest_contents, |data_path| {
+        return location.sibling(data_path).read().map_err(ArchiveError::RemoteError);
+    });
+}
+
+/// Keys whose string values name files of the asset, such as `data` of blocks and `file` of transfer function lookup tables.
+const FILE_KEYS: [&str; 3] = ["data", "file", "uri"];
+
+/// Returns true if a string can be the name of a file next to the manifest, rather than a URL or a path outside the asset.
+/// * `name` - the string
+fn is_asset_file(name: &str) -> bool {
+    let path = Path::new(name);
+    return !name.is_empty()
+        && !name.contains("://")
+        && !name.starts_with("data:")
+        && path.is_relative()
+        && !path.has_root()
+        && path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+}
+
+/// Collects the names of files referenced by a part of the manifest, in the order they appear.
+/// * `json` - the part of the manifest
+/// * `key` - the key the part is stored under in its object, if it is in one
+/// * `names` - the collected names
+fn referenced_files(json: &JsonValue, key: Option<&str>, names: &mut Vec<String>) {
+    match json {
+        JsonValue::String(name) if key.is_some_and(|k| FILE_KEYS.contains(&k)) && is_asset_file(name) => {
+            names.push(name.clone());
+        },
+        JsonValue::Array(a) => a.iter().for_each(|v| referenced_files(v, None, names)),
+        JsonValue::Object(o) => {
+            let mut keys: Vec<&String> = o.keys().collect();
+            keys.sort();
+            for key in keys {
+                referenced_files(&o[key], Some(key), names);
+            }
+        },
+        _ => {}
+    }
+}
 
+/// Returns the manifest and the files it references: the data files of its blocks, and files referenced
+/// from extensions, such as lookup tables of transfer functions.
+/// * `manifest_name` - name of the manifest file
+/// * `manifest_contents` - the manifest
+/// * `read_data` - reads a data file, given its name in the manifest
+fn from_manifest_data<F: Fn(&str) -> Result<Vec<u8>, ArchiveError>>(manifest_name: String, manifest_contents: Vec<u8>, read_data: F) -> Result<Vec<File>, ArchiveError> {
+    let mut files = Vec::new();
     let content = match std::str::from_utf8(&manifest_contents) {
         Ok(c) => c,
         Err(e) => {
@@ -55,19 +182,53 @@ pub fn from_manifest_file(filepath: &Path) -> Result<Vec<File>, ArchiveError> {
 
     let json = JsonValue::from_str(&content).map_err(|x| ArchiveError::NotValidFile(format!("Invalid JSON ({})", x)))?;
     let hash_map: HashMap<String, JsonValue> = json.try_into().map_err(|_| ArchiveError::NotValidFile("Invalid JSON".to_string()))?;
-    let blocks: Vec<JsonValue> = hash_map["blocks"].clone().try_into().map_err(|_| ArchiveError::NotValidFile("Invalid JSON".to_string()))?;
-    for block in blocks {
-        let block: HashMap<String, JsonValue> = block.try_into().map_err(|_| ArchiveError::NotValidFile("Invalid JSON".to_string()))?;
-        if block.get("data").is_some() {
-            let data_path: String = block["data"].clone().try_into().map_err(|_| ArchiveError::NotValidFile("Invalid JSON".to_string()))?;
-            let full_data_path = Path::new(filepath.parent().unwrap()).join(&data_path);
-            let data_content = fs::read(&full_data_path).map_err(|x| ArchiveError::CannotRead(format!("Could not read file {} ({})", full_data_path.display(), x)))?;
+    let blocks = match hash_map.get("blocks") {
+        Some(JsonValue::Array(blocks)) => blocks,
+        Some(_) => return Err(ArchiveError::NotValidFile("`blocks` of the manifest is not an array".to_string())),
+        None => return Err(ArchiveError::NotValidFile("The manifest has no `blocks`".to_string()))
+    };
+    let mut loaded = HashSet::new();
+    for (i, block) in blocks.iter().enumerate() {
+        let block = match block {
+            JsonValue::Object(block) => block,
+            _ => return Err(ArchiveError::NotValidFile(format!("Block {} of the manifest is not an object", i)))
+        };
+        if let Some(data) = block.get("data") {
+            let data_path = match data {
+                JsonValue::String(data_path) => data_path.clone(),
+                _ => return Err(ArchiveError::NotValidFile(format!("`data` of block {} is not a string", i)))
+            };
+            // Blocks are only read from the folder of the asset, not from anywhere the manifest points to.
+            if !is_asset_file(&data_path) {
+                return Err(ArchiveError::NotValidFile(format!("`data` of block {} is not a file of the asset (`{}`)", i, data_path)));
+            }
+            if !loaded.insert(data_path.clone()) {
+                continue;
+            }
+            let data_content = read_data(&data_path)?;
             let file = File::new(data_path, Arc::new(data_content), None);
             files.push(file);
         }
     }
 
-    let manifest_file = File::new(filepath.to_string_lossy().to_string(), Arc::new(manifest_contents), Some("application/json".to_string()));
+    // Other references are not required by the reader, so missing ones are left to the code that uses them.
+    let mut names = Vec::new();
+    let mut keys: Vec<&String> = hash_map.keys().filter(|k| *k != "blocks").collect();
+    keys.sort();
+    for key in keys {
+        referenced_files(&hash_map[key], Some(key), &mut names);
+    }
+    for name in names {
+        if !loaded.insert(name.clone()) {
+            continue;
+        }
+        match read_data(&name) {
+            Ok(data) => files.push(File::new(name, Arc::new(data), None)),
+            Err(e) => log_warn!("cannot read {}, which is referenced by the manifest: {}", name, e)
+        }
+    }
+
+    let manifest_file = File::new(manifest_name, Arc::new(manifest_contents), Some("application/json".to_string()));
     files.push(manifest_file);
 
     return Ok(files);
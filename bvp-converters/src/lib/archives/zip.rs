@@ -1,14 +1,77 @@
-use std::{sync::Arc, fs};
+
+use std::fmt;
 
 use chrono::{Datelike, Timelike};
 
-use crate::{file::File, errors::ZipError};
+use crate::{bytes::Bytes, compressions::deflate, file::File, errors::ZipError};
+#[cfg(feature = "fs")]
+use crate::errors::ArchiveError;
 
+use super::read_members;
+#[cfg(feature = "fs")]
 use super::ArchiveWriter;
 
 static LOCAL_FILE_HEADER_SIG: u32 = 0x04034b50;
 static CENTRAL_DIR_FILE_HEADER_SIG: u32 = 0x02014b50;
 static EOCD_SIG: u32 = 0x06054b50;
+static ZIP64_EOCD_SIG: u32 = 0x06064b50;
+static ZIP64_EOCD_LOCATOR_SIG: u32 = 0x07064b50;
+static ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+/// Version needed to extract archives that use ZIP64 (4.5).
+static ZIP64_VERSION: u16 = 45;
+/// Sizes, offsets and counts that do not fit into their fields are stored in ZIP64 records,
+/// and the fields are set to these values.
+static ZIP64_MARKER_U32: u32 = u32::MAX;
+static ZIP64_MARKER_U16: u16 = u16::MAX;
+/// Compression methods of members.
+static METHOD_STORED: u16 = 0;
+static METHOD_DEFLATE: u16 = 8;
+/// Version needed to extract deflated members (2.0).
+static DEFLATE_VERSION: u16 = 20;
+/// Deflate level of compressed members, a balance between size and speed as in zlib.
+#[cfg(feature = "fs")]
+static DEFLATE_LEVEL: u32 = 6;
+
+/// How members of ZIP archives are compressed, on top of the compression of blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemberCompression {
+    Stored,
+    /// Members are deflated, unless that does not make them smaller.
+    Deflate
+}
+
+impl MemberCompression {
+    pub fn from_string(s: &str) -> Result<Self, ZipError> {
+        return match s.to_lowercase().as_str() {
+            "none" | "stored" => Ok(Self::Stored),
+            "deflate" => Ok(Self::Deflate),
+            _ => Err(ZipError::UnsupportedMemberCompression(s.to_string()))
+        };
+    }
+
+    /// Returns the data of a member as it is written, with its compression method.
+    /// * `data` - data of the member
+    #[cfg(feature = "fs")]
+    fn compress(&self, data: &[u8]) -> (Option<Vec<u8>>, u16) {
+        if let Self::Deflate = self {
+            let deflated = deflate::deflate(data, DEFLATE_LEVEL);
+            if deflated.len() < data.len() {
+                return (Some(deflated), METHOD_DEFLATE);
+            }
+        }
+        return (None, METHOD_STORED);
+    }
+}
+
+impl fmt::Display for MemberCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Stored => "none",
+            Self::Deflate => "deflate"
+        };
+        return write!(f, "{}", name);
+    }
+}
 
 struct CentralDirectoryHeader {
     version_made: u16,
@@ -17,19 +80,50 @@ struct CentralDirectoryHeader {
     compression_method: u16,
     last_modified_time_date: [u16; 2],
     crc32: u32,
-    compressed_size: u32,
-    uncompressed_size: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
     disk_number: u16,
     internal_attributes: u16,
     external_attributes: u32,
-    relative_offset: u32,
+    relative_offset: u64,
     filename: String,
-    extra_field: String,
     comment: String
 }
 
+/// Returns the value of a 32-bit field, or the ZIP64 marker if it does not fit.
+/// * `value` - the value
+fn field_u32(value: u64) -> u32 {
+    if value >= ZIP64_MARKER_U32 as u64 {
+        return ZIP64_MARKER_U32;
+    }
+    return value as u32;
+}
+
+/// Builds a ZIP64 extended information extra field, or an empty one if there are no values.
+/// * `values` - the values that do not fit into their 32-bit fields, in the order of the specification
+fn zip64_extra_field(values: &[u64]) -> Vec<u8> {
+    if values.is_empty() {
+        return Vec::new();
+    }
+    let mut extra = Vec::with_capacity(4 + 8 * values.len());
+    extra.extend_from_slice(&ZIP64_EXTRA_FIELD_ID.to_le_bytes());
+    extra.extend_from_slice(&(8 * values.len() as u16).to_le_bytes());
+    for value in values {
+        extra.extend_from_slice(&value.to_le_bytes());
+    }
+    return extra;
+}
+
 impl CentralDirectoryHeader {
-    pub fn simple_new(file: &File, offset: u32) -> Self {
+    pub fn simple_new(file: &File, offset: u64) -> Self {
+        return Self::new(file, offset, METHOD_STORED, file.data.len() as u64);
+    }
+
+    /// * `file` - the member, with its uncompressed data
+    /// * `offset` - offset of its local file header from the start of the archive
+    /// * `compression_method` - how its data is compressed
+    /// * `compressed_size` - size of its data as written
+    fn new(file: &File, offset: u64, compression_method: u16, compressed_size: u64) -> Self {
         let mod_datetime = chrono::offset::Utc::now();
         let mod_day = mod_datetime.day() as u16;
         let mod_month = mod_datetime.month() as u16;
@@ -41,28 +135,61 @@ impl CentralDirectoryHeader {
         let date = mod_day | (mod_month << 5) | (mod_year << 9);
         let time = mod_second | (mod_minute << 5) | (mod_hour << 11);
 
-        return Self {
+        let mut header = Self {
             version_made: 0,
             extraction_version: 0,
             general_purpose_bit: 0,
-            compression_method: 0,
+            compression_method,
             last_modified_time_date: [time, date],
             crc32: compute_crc32(&file.data),
-            compressed_size: file.data.len() as u32,
-            uncompressed_size: file.data.len() as u32,
+            compressed_size,
+            uncompressed_size: file.data.len() as u64,
             disk_number: 1,
             internal_attributes: 0,
             external_attributes: 0,
             relative_offset: offset,
             filename: file.name.clone(),
-            extra_field: String::new(),
             comment: String::new()
+        };
+        if !header.central_extra_field().is_empty() {
+            header.version_made = ZIP64_VERSION;
+            header.extraction_version = ZIP64_VERSION;
+        } else if compression_method == METHOD_DEFLATE {
+            header.version_made = DEFLATE_VERSION;
+            header.extraction_version = DEFLATE_VERSION;
+        }
+        return header;
+    }
+
+    /// Returns the extra field of the local file header. If the file is too large,
+    /// it holds both sizes, as the specification requires.
+    fn local_extra_field(&self) -> Vec<u8> {
+        if field_u32(self.uncompressed_size) == ZIP64_MARKER_U32 || field_u32(self.compressed_size) == ZIP64_MARKER_U32 {
+            return zip64_extra_field(&[self.uncompressed_size, self.compressed_size]);
         }
+        return Vec::new();
+    }
+
+    /// Returns the extra field of the central directory file header, which holds
+    /// the sizes and the offset that do not fit into their fields.
+    fn central_extra_field(&self) -> Vec<u8> {
+        let mut values = Vec::new();
+        for value in [self.uncompressed_size, self.compressed_size, self.relative_offset] {
+            if field_u32(value) == ZIP64_MARKER_U32 {
+                values.push(value);
+            }
+        }
+        return zip64_extra_field(&values);
     }
 
     pub fn file_header_bytes(&self) -> Vec<u8> {
         let filename_bytes = self.filename.as_bytes();
-        let extra_bytes = self.extra_field.as_bytes();
+        let extra_bytes = self.local_extra_field();
+        let (compressed_size, uncompressed_size) = if extra_bytes.is_empty() {
+            (self.compressed_size as u32, self.uncompressed_size as u32)
+        } else {
+            (ZIP64_MARKER_U32, ZIP64_MARKER_U32)
+        };
         return [
             &LOCAL_FILE_HEADER_SIG.to_le_bytes() as &[u8],
             &self.extraction_version.to_le_bytes() as &[u8],
@@ -71,8 +198,8 @@ impl CentralDirectoryHeader {
             &self.last_modified_time_date[0].to_le_bytes() as &[u8],
             &self.last_modified_time_date[1].to_le_bytes() as &[u8],
             &self.crc32.to_le_bytes() as &[u8],
-            &self.compressed_size.to_le_bytes() as &[u8],
-            &self.uncompressed_size.to_le_bytes() as &[u8],
+            &compressed_size.to_le_bytes() as &[u8],
+            &uncompressed_size.to_le_bytes() as &[u8],
             &(filename_bytes.len() as u16).to_le_bytes() as &[u8],
             &(extra_bytes.len() as u16).to_le_bytes() as &[u8],
             &filename_bytes,
@@ -80,13 +207,13 @@ impl CentralDirectoryHeader {
         ].concat();
     }
 
-    pub fn file_entry_header_len(&self) -> u32 {
-        return 30 + self.filename.as_bytes().len() as u32 + self.extra_field.as_bytes().len() as u32;
+    pub fn file_entry_header_len(&self) -> u64 {
+        return 30 + self.filename.as_bytes().len() as u64 + self.local_extra_field().len() as u64;
     }
 
     pub fn central_dir_file_header(&self) -> Vec<u8> {
         let filename_bytes = self.filename.as_bytes();
-        let extra_bytes = self.extra_field.as_bytes();
+        let extra_bytes = self.central_extra_field();
         let comment_bytes = self.comment.as_bytes();
         return [
             &CENTRAL_DIR_FILE_HEADER_SIG.to_le_bytes() as &[u8],
@@ -97,15 +224,15 @@ impl CentralDirectoryHeader {
             &self.last_modified_time_date[0].to_le_bytes() as &[u8],
             &self.last_modified_time_date[1].to_le_bytes() as &[u8],
             &self.crc32.to_le_bytes() as &[u8],
-            &self.compressed_size.to_le_bytes() as &[u8],
-            &self.uncompressed_size.to_le_bytes() as &[u8],
+            &field_u32(self.compressed_size).to_le_bytes() as &[u8],
+            &field_u32(self.uncompressed_size).to_le_bytes() as &[u8],
             &(filename_bytes.len() as u16).to_le_bytes() as &[u8],
             &(extra_bytes.len() as u16).to_le_bytes() as &[u8],
             &(comment_bytes.len() as u16).to_le_bytes() as &[u8],
             &self.disk_number.to_le_bytes() as &[u8],
             &self.internal_attributes.to_le_bytes() as &[u8],
             &self.external_attributes.to_le_bytes() as &[u8],
-            &self.relative_offset.to_le_bytes() as &[u8],
+            &field_u32(self.relative_offset).to_le_bytes() as &[u8],
             &filename_bytes,
             &extra_bytes,
             &comment_bytes
@@ -113,35 +240,92 @@ impl CentralDirectoryHeader {
     }
 }
 
+/// Builds the end of the central directory. If there are too many files, or the
+/// central directory is too large or too far into the archive, a ZIP64 end of central
+/// directory record and its locator are written before it.
+/// * `records` - number of files in the archive
+/// * `central_dir_size` - size of the central directory in bytes
+/// * `central_dir_offset` - offset of the central directory from the start of the archive
+fn end_of_central_directory(records: usize, central_dir_size: u64, central_dir_offset: u64) -> Vec<u8> {
+    let mut end = Vec::new();
+    let needs_zip64 = records >= ZIP64_MARKER_U16 as usize
+        || field_u32(central_dir_size) == ZIP64_MARKER_U32
+        || field_u32(central_dir_offset) == ZIP64_MARKER_U32;
+    if needs_zip64 {
+        let zip64_eocd_offset = central_dir_offset + central_dir_size;
+        end.append(&mut [
+            &ZIP64_EOCD_SIG.to_le_bytes() as &[u8],
+            &44u64.to_le_bytes() as &[u8],
+            &ZIP64_VERSION.to_le_bytes() as &[u8],
+            &ZIP64_VERSION.to_le_bytes() as &[u8],
+            &0u32.to_le_bytes() as &[u8],
+            &0u32.to_le_bytes() as &[u8],
+            &(records as u64).to_le_bytes() as &[u8],
+            &(records as u64).to_le_bytes() as &[u8],
+            &central_dir_size.to_le_bytes() as &[u8],
+            &central_dir_offset.to_le_bytes() as &[u8],
+            &ZIP64_EOCD_LOCATOR_SIG.to_le_bytes() as &[u8],
+            &0u32.to_le_bytes() as &[u8],
+            &zip64_eocd_offset.to_le_bytes() as &[u8],
+            &1u32.to_le_bytes() as &[u8]
+        ].concat());
+    }
+
+    let records = if records >= ZIP64_MARKER_U16 as usize { ZIP64_MARKER_U16 } else { records as u16 };
+    end.append(&mut [
+        &EOCD_SIG.to_le_bytes() as &[u8],
+        &1u16.to_le_bytes() as &[u8],
+        &1u16.to_le_bytes() as &[u8],
+        &records.to_le_bytes() as &[u8],
+        &records.to_le_bytes() as &[u8],
+        &field_u32(central_dir_size).to_le_bytes() as &[u8],
+        &field_u32(central_dir_offset).to_le_bytes() as &[u8],
+        &0u16.to_le_bytes() as &[u8]
+    ].concat());
+    return end;
+}
+
+#[cfg(feature = "fs")]
 pub struct ZIPWriter {
     file_contents: Vec<u8>,
-    central_file_headers: Vec<CentralDirectoryHeader>
+    central_file_headers: Vec<CentralDirectoryHeader>,
+    compression: MemberCompression
 }
 
+#[cfg(feature = "fs")]
 impl ZIPWriter {
     pub fn new() -> Self {
         return Self {
             file_contents: Vec::new(),
-            central_file_headers: Vec::new()
+            central_file_headers: Vec::new(),
+            compression: MemberCompression::Stored
         };
     }
+
+    /// Sets how members are compressed.
+    /// * `compression` - the compression of members
+    pub fn with_compression(mut self, compression: MemberCompression) -> Self {
+        self.compression = compression;
+        return self;
+    }
 }
 
+#[cfg(feature = "fs")]
 impl ArchiveWriter for ZIPWriter {
-    fn append_file(&mut self, file: &File) -> Result<(), String> {
-        let offset = self.file_contents.len() as u32;
-        let file_header = CentralDirectoryHeader::simple_new(file, offset);
+    fn append_file(&mut self, file: &File) -> Result<(), ArchiveError> {
+        let offset = self.file_contents.len() as u64;
+        let (compressed, method) = self.compression.compress(&file.data);
+        let data = compressed.as_deref().unwrap_or(file.data.as_slice());
+        let file_header = CentralDirectoryHeader::new(file, offset, method, data.len() as u64);
 
         self.file_contents.append(&mut file_header.file_header_bytes());
-        for d in file.data.iter() {
-            self.file_contents.push(*d);
-        }
+        self.file_contents.extend_from_slice(data);
         self.central_file_headers.push(file_header);
 
         return Ok(());
     }
 
-    fn finish(&self, path: String) -> Result<(), String> {
+    fn finish(&mut self, path: String) -> Result<(), ArchiveError> {
         let zip_size = self.file_contents.len();
         let mut zip = Vec::with_capacity(zip_size);
         for el in &self.file_contents {
@@ -156,27 +340,18 @@ impl ArchiveWriter for ZIPWriter {
             zip.append(&mut central_dir_file_header);
         }
 
-        let mut eocd = [
-            &EOCD_SIG.to_le_bytes() as &[u8],
-            &1u16.to_le_bytes() as &[u8],
-            &1u16.to_le_bytes() as &[u8],
-            &(self.central_file_headers.len() as u16).to_le_bytes() as &[u8],
-            &(self.central_file_headers.len() as u16).to_le_bytes() as &[u8],
-            &(central_dir_size as u32).to_le_bytes() as &[u8],
-            &(central_dir_offset as u32).to_le_bytes() as &[u8],
-            &0u16.to_le_bytes() as &[u8]
-        ].concat();
+        let mut eocd = end_of_central_directory(self.central_file_headers.len(), central_dir_size as u64, central_dir_offset as u64);
 
         zip.append(&mut eocd);
 
-        fs::write(path, zip.as_slice()).map_err(|err| err.to_string())?;
+        super::write_archive(&path, zip.as_slice())?;
         
         return Ok(());
     }
 }
 
 
-pub fn compute_crc32(data: &Vec<u8>) -> u32 {
+pub fn compute_crc32(data: &[u8]) -> u32 {
     let mut hasher = crc32fast::Hasher::new();
     hasher.update(&data);
     return hasher.finalize();
@@ -190,7 +365,7 @@ pub fn to_zip_archive(files: &Vec<File>) -> Result<Vec<u8>, ZipError> {
     let mut offset = 0;
     for file in files {
         let file_header = CentralDirectoryHeader::simple_new(file, offset);
-        offset += file_header.file_entry_header_len() + file.data.len() as u32;
+        offset += file_header.file_entry_header_len() + file.data.len() as u64;
 
         zip.append(&mut file_header.file_header_bytes());
         for d in file.data.iter() {
@@ -207,23 +382,14 @@ pub fn to_zip_archive(files: &Vec<File>) -> Result<Vec<u8>, ZipError> {
         zip.append(&mut central_dir_file_header);
     }
 
-    let mut eocd = [
-        &EOCD_SIG.to_le_bytes() as &[u8],
-        &1u16.to_le_bytes() as &[u8],
-        &1u16.to_le_bytes() as &[u8],
-        &(central_file_headers.len() as u16).to_le_bytes() as &[u8],
-        &(central_file_headers.len() as u16).to_le_bytes() as &[u8],
-        &(central_dir_size as u32).to_le_bytes() as &[u8],
-        &(central_dir_offset as u32).to_le_bytes() as &[u8],
-        &0u16.to_le_bytes() as &[u8]
-    ].concat();
+    let mut eocd = end_of_central_directory(central_file_headers.len(), central_dir_size as u64, central_dir_offset);
 
     zip.append(&mut eocd);
 
     return Ok(zip);
 }
 
-pub fn find_eocd(data: &Vec<u8>) -> Result<usize, ZipError> {
+pub fn find_eocd(data: &[u8]) -> Result<usize, ZipError> {
     let mut i = (data.len() as i64) - 1;
     while i - 3 >= 0 {
         let b1 = data[(i - 3) as usize] as u32;
@@ -239,7 +405,34 @@ pub fn find_eocd(data: &Vec<u8>) -> Result<usize, ZipError> {
     return Err(ZipError::CorruptFile("EOCD not found".to_string()));
 }
 
-pub fn get_u32_from_data(data: &Vec<u8>, offset: usize) -> u32 {
+/// Returns a record of the archive, or an error if the archive ends before it.
+/// Fields of the record can then be read with `get_u16_from_data` and the like without checking.
+/// * `data` - the archive
+/// * `offset` - index of the first byte of the record
+/// * `length` - length of the record
+/// * `record` - name of the record, for the error
+pub fn get_record<'d>(data: &'d [u8], offset: usize, length: usize, record: &str) -> Result<&'d [u8], ZipError> {
+    return match offset.checked_add(length).and_then(|end| data.get(offset..end)) {
+        Some(r) => Ok(r),
+        None => Err(ZipError::OutOfBounds(record.to_string(), offset, length))
+    };
+}
+
+/// Same as `get_record`, but also checks that the record starts with its signature.
+/// * `data` - the archive
+/// * `offset` - index of the first byte of the record
+/// * `length` - length of the record, including the signature
+/// * `signature` - the signature of the record
+/// * `record` - name of the record, for the error
+fn get_signed_record<'d>(data: &'d [u8], offset: usize, length: usize, signature: u32, record: &str) -> Result<&'d [u8], ZipError> {
+    let bytes = get_record(data, offset, length, record)?;
+    if get_u32_from_data(bytes, 0) != signature {
+        return Err(ZipError::InvalidSignature(record.to_string(), offset));
+    }
+    return Ok(bytes);
+}
+
+pub fn get_u32_from_data(data: &[u8], offset: usize) -> u32 {
     let b1 = data[offset] as u32;
     let b2 = data[offset + 1] as u32;
     let b3 = data[offset + 2] as u32;
@@ -247,59 +440,171 @@ pub fn get_u32_from_data(data: &Vec<u8>, offset: usize) -> u32 {
     return b1 | (b2 << 8) | (b3 << 16) | (b4 << 24);
 }
 
-pub fn get_u16_from_data(data: &Vec<u8>, offset: usize) -> u16 {
+pub fn get_u64_from_data(data: &[u8], offset: usize) -> u64 {
+    let low = get_u32_from_data(data, offset) as u64;
+    let high = get_u32_from_data(data, offset + 4) as u64;
+    return low | (high << 32);
+}
+
+pub fn get_u16_from_data(data: &[u8], offset: usize) -> u16 {
     let b1 = data[offset] as u16;
     let b2 = data[offset + 1] as u16;
     return b1 | (b2 << 8);
 }
 
-pub fn get_file_from_cdfh(data: &Vec<u8>, offset: usize) -> Result<(File, usize), ZipError> {
-    let uncompressed_size = get_u32_from_data(data, offset + 24) as usize;
-    let filename_length = get_u16_from_data(data, offset + 28) as usize;
-    let extra_length = get_u16_from_data(data, offset + 30) as usize;
-    let comment_length = get_u16_from_data(data, offset + 32) as usize;
-    let file_offset = get_u32_from_data(data, offset + 42) as usize;
-    let filename_bytes = &data[(offset + 46)..(offset + 46 + filename_length)];
+/// Returns the values of the ZIP64 extended information in an extra field, or none if it is not there.
+/// * `extra` - the extra field
+fn get_zip64_values(extra: &[u8]) -> Result<Vec<u64>, ZipError> {
+    let mut i = 0;
+    while i + 4 <= extra.len() {
+        let id = get_u16_from_data(extra, i);
+        let size = get_u16_from_data(extra, i + 2) as usize;
+        let field = get_record(extra, i + 4, size, "extra field")?;
+        if id == ZIP64_EXTRA_FIELD_ID {
+            return Ok(field.chunks_exact(8).map(|value| get_u64_from_data(value, 0)).collect());
+        }
+        i += 4 + size;
+    }
+    return Ok(Vec::new());
+}
+
+/// Where a member is stored in the archive and how, as told by its central directory file header.
+struct MemberEntry {
+    filename: String,
+    compression_method: u16,
+    crc32: u32,
+    compressed_size: usize,
+    uncompressed_size: usize,
+    file_offset: usize
+}
+
+/// Parses a central directory file header and returns the member it describes with the size of the header.
+/// The sizes and CRC are taken from the central directory, which has them also for members written with
+/// a data descriptor (general purpose bit 3), whose local file headers leave them empty.
+/// * `data` - the archive
+/// * `offset` - index of the first byte of the central directory file header
+fn get_member_entry(data: &[u8], offset: usize) -> Result<(MemberEntry, usize), ZipError> {
+    let header = get_signed_record(data, offset, 46, CENTRAL_DIR_FILE_HEADER_SIG, "central directory file header")?;
+    let compression_method = get_u16_from_data(header, 10);
+    let crc32 = get_u32_from_data(header, 16);
+    let mut compressed_size = get_u32_from_data(header, 20) as u64;
+    let mut uncompressed_size = get_u32_from_data(header, 24) as u64;
+    let filename_length = get_u16_from_data(header, 28) as usize;
+    let extra_length = get_u16_from_data(header, 30) as usize;
+    let comment_length = get_u16_from_data(header, 32) as usize;
+    let mut file_offset = get_u32_from_data(header, 42) as u64;
+    let filename_bytes = get_record(data, offset + 46, filename_length, "file name")?;
     let filename = match std::str::from_utf8(filename_bytes) {
         Ok(s) => s.to_string(),
         Err(e) => return Err(ZipError::CorruptFile(format!("Not valid UTF ({})", e)))
     };
-    let cdfh_size = 46 + filename_length + extra_length + comment_length;
-    let lfh_filename_length = get_u16_from_data(data, file_offset + 26);
-    let lfh_extra_length = get_u16_from_data(data, file_offset + 28);
-    let lfh_size = (30 + lfh_filename_length + lfh_extra_length) as usize;
-    
-    let mut file_data = Vec::with_capacity(uncompressed_size);
-    for i in 0..uncompressed_size {
-        file_data.push(data[file_offset + lfh_size + i]);
+    if uncompressed_size == ZIP64_MARKER_U32 as u64 || compressed_size == ZIP64_MARKER_U32 as u64 || file_offset == ZIP64_MARKER_U32 as u64 {
+        let extra = get_record(data, offset + 46 + filename_length, extra_length, "extra field")?;
+        let mut values = get_zip64_values(extra)?.into_iter();
+        let missing = || ZipError::CorruptFile(format!("ZIP64 extra field of `{}` is missing a value", filename));
+        if uncompressed_size == ZIP64_MARKER_U32 as u64 {
+            uncompressed_size = values.next().ok_or_else(missing)?;
+        }
+        if compressed_size == ZIP64_MARKER_U32 as u64 {
+            compressed_size = values.next().ok_or_else(missing)?;
+        }
+        if file_offset == ZIP64_MARKER_U32 as u64 {
+            file_offset = values.next().ok_or_else(missing)?;
+        }
     }
+    let cdfh_size = 46 + filename_length + extra_length + comment_length;
+    let entry = MemberEntry {
+        filename,
+        compression_method,
+        crc32,
+        compressed_size: compressed_size as usize,
+        uncompressed_size: uncompressed_size as usize,
+        file_offset: file_offset as usize
+    };
+    return Ok((entry, cdfh_size));
+}
 
-    let file = File::new(filename, Arc::new(file_data), None);
+/// Reads a member out of the archive. Stored members share the buffer of the archive, deflated members
+/// are inflated and checked against their size and CRC.
+/// * `data` - the archive
+/// * `entry` - the member, as parsed from its central directory file header
+fn read_member(data: &Bytes, entry: &MemberEntry) -> Result<File, ZipError> {
+    let filename = &entry.filename;
+    let local_header = get_signed_record(data, entry.file_offset, 30, LOCAL_FILE_HEADER_SIG, "local file header")?;
+    let lfh_filename_length = get_u16_from_data(local_header, 26) as usize;
+    let lfh_extra_length = get_u16_from_data(local_header, 28) as usize;
+    let lfh_size = 30 + lfh_filename_length + lfh_extra_length;
+
+    let data_offset = entry.file_offset.saturating_add(lfh_size);
+    let file_data = get_record(data, data_offset, entry.compressed_size, &format!("data of `{}`", filename))?;
+    let file_data = if entry.compression_method == METHOD_STORED {
+        data.slice(data_offset..data_offset + entry.compressed_size)
+    } else if entry.compression_method == METHOD_DEFLATE {
+        let inflated = deflate::inflate(file_data, entry.uncompressed_size).map_err(|e| ZipError::CorruptMember(filename.clone(), e))?;
+        // Stored members are not checked, to keep reading large archives fast, but a corrupt
+        // compressed member is likely to still inflate to something.
+        if inflated.len() != entry.uncompressed_size || compute_crc32(&inflated) != entry.crc32 {
+            return Err(ZipError::CorruptFile(format!("member `{}` does not match its size and CRC", filename)));
+        }
+        Bytes::from(inflated)
+    } else {
+        return Err(ZipError::UnsupportedCompression(filename.clone(), entry.compression_method));
+    };
+
+    return Ok(File::new(filename.clone(), file_data, None));
+}
+
+/// Reads a member of the archive, given its central directory file header, and returns it with the size of the header.
+/// * `data` - the archive
+/// * `offset` - index of the first byte of the central directory file header
+pub fn get_file_from_cdfh(data: &Bytes, offset: usize) -> Result<(File, usize), ZipError> {
+    let (entry, cdfh_size) = get_member_entry(data, offset)?;
+    let file = read_member(data, &entry)?;
     return Ok((file, cdfh_size));
 }
 
-pub fn from_zip_archive(zip: &Vec<u8>) -> Result<Vec<File>, ZipError> {
-    let mut files = Vec::new();
+/// Reads the number of records and the central directory offset from the ZIP64 end of central directory record.
+/// * `data` - the archive
+/// * `eocd_start` - index of the byte after the end of central directory signature
+fn find_zip64_eocd(data: &[u8], eocd_start: usize) -> Result<(u64, usize), ZipError> {
+    // The locator is 20 bytes long and directly precedes the end of central directory record.
+    let locator_offset = match eocd_start.checked_sub(4 + 20) {
+        Some(l) => l,
+        None => return Err(ZipError::OutOfBounds("ZIP64 end of central directory locator".to_string(), 0, 20))
+    };
+    let locator = get_signed_record(data, locator_offset, 20, ZIP64_EOCD_LOCATOR_SIG, "ZIP64 end of central directory locator")?;
+    let record_offset = get_u64_from_data(locator, 8) as usize;
+    let record = get_signed_record(data, record_offset, 56, ZIP64_EOCD_SIG, "ZIP64 end of central directory record")?;
+    let records_amount = get_u64_from_data(record, 32);
+    let central_directory_offset = get_u64_from_data(record, 48) as usize;
+    return Ok((records_amount, central_directory_offset));
+}
+
+/// Reads the members of an archive. The central directory is parsed first, then the members are
+/// read on several threads. Stored members share the buffer of the archive.
+/// * `zip` - bytes of the archive
+pub fn from_zip_archive(zip: &Bytes) -> Result<Vec<File>, ZipError> {
+    let mut entries = Vec::new();
 
     let eocd_start = find_eocd(zip)?;
-    let records_amount = if eocd_start + 8 < zip.len() {
-        get_u16_from_data(zip, eocd_start + 6)
-    } else {
-        return Err(ZipError::CorruptFile("EOCD is missing total number of records".to_string()));
-    };
-    let central_directory_offset = if eocd_start + 16 < zip.len() {
-        get_u32_from_data(zip, eocd_start + 12) as usize
+    // The record without its signature.
+    let eocd = get_record(zip, eocd_start, 18, "end of central directory record")?;
+    let records_amount = get_u16_from_data(eocd, 6);
+    let central_directory_offset = get_u32_from_data(eocd, 12) as usize;
+
+    let (records_amount, central_directory_offset) = if records_amount == ZIP64_MARKER_U16 || central_directory_offset == ZIP64_MARKER_U32 as usize {
+        find_zip64_eocd(zip, eocd_start)?
     } else {
-        return Err(ZipError::CorruptFile("EOCD is missing central directory offset".to_string()));
+        (records_amount as u64, central_directory_offset)
     };
 
     let mut offset = 0usize;
     for _ in 0..records_amount {
-        let i = central_directory_offset + offset;
-        let (file, cdfh_size) = get_file_from_cdfh(zip, i)?;
-        files.push(file);
+        let i = central_directory_offset.saturating_add(offset);
+        let (entry, cdfh_size) = get_member_entry(zip, i)?;
+        entries.push(entry);
         offset += cdfh_size;
     }
 
-    return Ok(files);
-}
\ No newline at end of file
+    return read_members(&entries, |entry| read_member(zip, entry));
+}
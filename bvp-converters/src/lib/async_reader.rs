@@ -0,0 +1,233 @@
+//! Reading volumes from async code, such as web backends serving regions of assets.
+//!
+//! `AsyncVolumeReader` reconstructs regions like `VolumeReader`, but fetches and decodes the
+//! blocks of a region concurrently through an `AsyncBlockStore` and can be awaited without
+//! blocking the executor. The futures only use `std`, so they run on any executor, such as tokio
+//! or async-std, and the library does not depend on one.
+
+use std::{collections::HashMap, future::{poll_fn, Future}, panic::{self, AssertUnwindSafe}, pin::Pin, sync::{Arc, Mutex}, task::{Context, Poll, Waker}, thread};
+
+use crossbeam::channel::{self, Sender};
+
+use crate::{block::Block, bvpfile::BVPFile, errors::ReaderError, reader::{decode_block, VolumeReader}, vector3::Vector3};
+
+/// Blocks fetched at the same time by a reader, unless set with `with_concurrency`.
+const DEFAULT_CONCURRENCY: usize = 32;
+
+/// Where an `AsyncVolumeReader` gets the blocks of an asset from.
+pub trait AsyncBlockStore: Send + Sync {
+    /// Returns the asset. Its blocks do not need to have their data, only `fetch` reads it.
+    fn manifest(&self) -> &BVPFile;
+
+    /// Returns a block with data, decoded.
+    /// * `block_index` - index of the block
+    fn fetch(&self, block_index: usize) -> impl Future<Output = Result<Block, ReaderError>> + Send;
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Result of a block decoded on another thread, and the task waiting for it.
+struct Slot {
+    result: Option<Result<Block, ReaderError>>,
+    waker: Option<Waker>
+}
+
+/// Completes when a worker has decoded a block.
+pub struct DecodedBlock {
+    slot: Arc<Mutex<Slot>>
+}
+
+impl Future for DecodedBlock {
+    type Output = Result<Block, ReaderError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.slot.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(result) = slot.result.take() {
+            return Poll::Ready(result);
+        }
+        slot.waker = Some(cx.waker().clone());
+        return Poll::Pending;
+    }
+}
+
+/// Sets the result of a `DecodedBlock`. If the job is dropped without a result,
+/// because decoding panicked, the block completes with an error instead of never.
+struct Completer {
+    slot: Arc<Mutex<Slot>>,
+    block_index: usize
+}
+
+impl Completer {
+    fn complete(&self, result: Result<Block, ReaderError>) {
+        let mut slot = self.slot.lock().unwrap_or_else(|e| e.into_inner());
+        slot.result = Some(result);
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Completer {
+    fn drop(&mut self) {
+        let done = self.slot.lock().map_or(true, |s| s.result.is_some());
+        if !done {
+            self.complete(Err(ReaderError::DecodeAborted(self.block_index)));
+        }
+    }
+}
+
+/// A block store for assets that are in memory, such as those read with `read_archive`.
+/// Blocks are decoded by a pool of worker threads, so decoding does not block the executor.
+pub struct MemoryBlockStore {
+    bvp_file: Arc<BVPFile>,
+    verify_checksums: bool,
+    jobs: Sender<Job>
+}
+
+impl MemoryBlockStore {
+    /// Starts the worker threads. They stop when the store is dropped.
+    /// * `bvp_file` - the asset, with the data of its blocks
+    /// * `threads` - number of worker threads, at least one
+    pub fn new(bvp_file: Arc<BVPFile>, threads: usize) -> Self {
+        let (jobs, receiver) = channel::unbounded::<Job>();
+        for _ in 0..threads.max(1) {
+            let receiver = receiver.clone();
+            thread::spawn(move || {
+                while let Ok(job) = receiver.recv() {
+                    // A panicking job completes its block with an error, the worker keeps going.
+                    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                }
+            });
+        }
+        return Self { bvp_file, verify_checksums: false, jobs };
+    }
+
+    /// Sets whether block data is checked against its recorded checksum before it is decoded.
+    /// * `verify` - whether to check the checksums
+    pub fn with_checksum_verification(mut self, verify: bool) -> Self {
+        self.verify_checksums = verify;
+        return self;
+    }
+}
+
+impl AsyncBlockStore for MemoryBlockStore {
+    fn manifest(&self) -> &BVPFile {
+        return &self.bvp_file;
+    }
+
+    /// Queues the block for decoding right away, so blocks are decoded while others are awaited.
+    fn fetch(&self, block_index: usize) -> impl Future<Output = Result<Block, ReaderError>> + Send {
+        let slot = Arc::new(Mutex::new(Slot { result: None, waker: None }));
+        let completer = Completer { slot: slot.clone(), block_index };
+        let format_index = VolumeReader::new(&self.bvp_file).find_format_index(block_index);
+        match format_index {
+            Err(e) => completer.complete(Err(e)),
+            Ok(format_index) => {
+                let bvp_file = self.bvp_file.clone();
+                let verify_checksums = self.verify_checksums;
+                let job: Job = Box::new(move || {
+                    let block = &bvp_file.blocks[block_index];
+                    completer.complete(decode_block(block, &bvp_file.formats[format_index], verify_checksums));
+                });
+                // Sending only fails without workers, then the dropped job completes the block.
+                let _ = self.jobs.send(job);
+            }
+        }
+        return DecodedBlock { slot };
+    }
+}
+
+/// Reconstructs volumes from the block tree of a BVP asset, with blocks from an `AsyncBlockStore`.
+pub struct AsyncVolumeReader<S: AsyncBlockStore> {
+    store: S,
+    concurrency: usize
+}
+
+impl<S: AsyncBlockStore> AsyncVolumeReader<S> {
+    pub fn new(store: S) -> Self {
+        return Self { store, concurrency: DEFAULT_CONCURRENCY };
+    }
+
+    /// Sets how many blocks are fetched at the same time. Decoded blocks are copied
+    /// into the region as they arrive, so this also bounds the memory they take.
+    /// * `concurrency` - number of blocks, at least one
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        return self;
+    }
+
+    pub fn store(&self) -> &S {
+        return &self.store;
+    }
+
+    /// Reconstructs a region of a block into a single unencoded block, see `VolumeReader::read_block_region`.
+    /// Blocks placed several times are fetched once.
+    /// * `block_index` - index of the block to read from
+    /// * `start` - start of the region, relative to the block
+    /// * `end` - end of the region (exclusive), relative to the block
+    pub async fn read_block_region(&self, block_index: usize, start: Vector3<u32>, end: Vector3<u32>) -> Result<Block, ReaderError> {
+        let reader = VolumeReader::new(self.store.manifest());
+        let mut plan = reader.plan_region(block_index, start, end)?;
+
+        let mut origins: HashMap<usize, Vec<Vector3<u32>>> = HashMap::new();
+        let mut order = Vec::new();
+        for (piece_index, origin) in std::mem::take(&mut plan.pieces) {
+            let entry = origins.entry(piece_index).or_default();
+            if entry.is_empty() {
+                order.push(piece_index);
+            }
+            entry.push(origin);
+        }
+
+        let mut queue = order.into_iter();
+        let mut in_flight = Vec::new();
+        loop {
+            while in_flight.len() < self.concurrency {
+                match queue.next() {
+                    Some(index) => in_flight.push((index, Box::pin(self.store.fetch(index)))),
+                    None => break
+                }
+            }
+            if in_flight.is_empty() {
+                break;
+            }
+            let (index, result) = poll_fn(|cx| {
+                for i in 0..in_flight.len() {
+                    if let Poll::Ready(result) = in_flight[i].1.as_mut().poll(cx) {
+                        return Poll::Ready((in_flight.swap_remove(i).0, result));
+                    }
+                }
+                return Poll::Pending;
+            }).await;
+            let decoded = result?;
+            let mut positions = origins.remove(&index).unwrap_or_default();
+            if let Some(last) = positions.pop() {
+                for origin in positions {
+                    plan.paste(decoded.clone(), origin)?;
+                }
+                plan.paste(decoded, last)?;
+            }
+        }
+        return plan.finish();
+    }
+
+    /// Reconstructs a region of a modality into a single unencoded block.
+    /// * `modality_index` - index of the modality
+    /// * `start` - start of the region
+    /// * `end` - end of the region (exclusive)
+    pub async fn read_region(&self, modality_index: usize, start: Vector3<u32>, end: Vector3<u32>) -> Result<Block, ReaderError> {
+        let (root_block_index, _) = VolumeReader::new(self.store.manifest()).modality_root(modality_index)?;
+        return self.read_block_region(root_block_index, start, end).await;
+    }
+
+    /// Reconstructs the whole volume of a modality into a single unencoded block.
+    /// * `modality_index` - index of the modality
+    pub async fn read_modality(&self, modality_index: usize) -> Result<Block, ReaderError> {
+        let (root_block_index, _) = VolumeReader::new(self.store.manifest()).modality_root(modality_index)?;
+        let dimensions = self.store.manifest().blocks[root_block_index].dimensions;
+        return match self.read_block_region(root_block_index, Vector3::from_xyz(0, 0, 0), dimensions).await {
+            Err(ReaderError::RegionUncovered(_, unwritten, total)) => Err(ReaderError::Uncovered(modality_index, unwritten, total)),
+            result => result
+        };
+    }
+}
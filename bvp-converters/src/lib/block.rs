@@ -1,18 +1,47 @@
-use std::{collections::HashMap};
+use std::{borrow::Cow, collections::HashMap};
 
 use tinyjson::JsonValue;
 
-use crate::{placement::Placement, formats::Format, vector3::Vector3, json_aux::{get_u32_from_json, get_string_from_json}, file::File, errors::{BlockError, JsonError}, compressions::{CompressionType}};
+use crate::{bytes::Bytes, placement::Placement, formats::Format, vector3::Vector3, mapping::{ObjectReader, ObjectWriter}, file::{self, FileIndex}, layout::{self, VoxelLayout}, errors::BlockError, compressions::{CompressionType}, checksum::Checksum, extensions::ExtensionPayloads};
 
-#[derive(Debug)]
+/// Blocks are equal if all their fields are. Data is compared last, and only when the
+/// checksums (if recorded) are equal, so blocks with different checksums are not compared byte by byte.
+#[derive(Clone, Debug)]
 pub struct Block {
     pub index: usize,
     pub dimensions: Vector3<u32>,
     pub placements: Vec<Placement>,
     pub format: Option<usize>,
-    pub data: Option<Vec<u8>>,
+    /// Block data, as stored in the file (can be encoded). When a block is read
+    /// from an archive, this is shared with `File.data` instead of copied.
+    pub data: Option<Bytes>,
     pub data_url: Option<String>,
-    pub encoding: Option<CompressionType>
+    pub encoding: Option<CompressionType>,
+    /// Checksum of the stored data, if the asset records one.
+    pub checksum: Option<Checksum>,
+    pub extension_payloads: ExtensionPayloads,
+    /// Application specific data, kept as it is.
+    pub extras: Option<JsonValue>
+}
+
+impl PartialEq for Block {
+    fn eq(&self, other: &Self) -> bool {
+        let same_data = || match (&self.data, &other.data) {
+            (Some(a), Some(b)) => file::same_data(a, b),
+            (None, None) => true,
+            _ => false
+        };
+        return self.index == other.index
+            && self.dimensions == other.dimensions
+            && self.format == other.format
+            && self.data_url == other.data_url
+            && self.encoding == other.encoding
+            && self.checksum == other.checksum
+            && self.placements == other.placements
+            && self.extension_payloads == other.extension_payloads
+            && self.extras == other.extras
+            && same_data();
+    }
 }
 
 impl Block {
@@ -22,12 +51,25 @@ impl Block {
             dimensions,
             placements: Vec::new(),
             format,
-            data,
+            data: data.map(Bytes::from),
             encoding: None,
-            data_url: None
+            data_url: None,
+            checksum: None,
+            extension_payloads: HashMap::new(),
+            extras: None
         }
     }
 
+    /// Returns the size of the unencoded data of the block in bytes,
+    /// or an error if it does not fit into memory, as with dimensions from a malicious manifest.
+    /// * `format` - the format of the data
+    fn checked_size(&self, format: &Format) -> Result<usize, BlockError> {
+        return match format.checked_count_space(self.dimensions) {
+            Some(size) => Ok(size),
+            None => Err(BlockError::Overflow(self.index))
+        };
+    }
+
     /// At the given offset, copy data from another block.
     /// If blocks have defines formats, these need to be the same.
     /// * `offset` - a vector representing offset inside target block where data of source block should start
@@ -35,8 +77,11 @@ impl Block {
     /// * `format` - a format to interpret data in source block
     pub fn set_data_in_range(&mut self, offset: Vector3<u32>, block: &Block, format: &Format) -> Result<(), BlockError> {       
         let start = offset;
-        let end = offset + block.dimensions;
-        let extent = end - start;
+        let end = match offset.checked_add(&block.dimensions) {
+            Some(e) => e,
+            None => return Err(BlockError::Overflow(block.index))
+        };
+        let extent = block.dimensions;
         if self.data.is_none() {
             return Err(BlockError::NoData(self.index));
         }
@@ -65,58 +110,125 @@ impl Block {
         }
 
         let microblock_size = format.microblock_size;
-        let microblock_start = (start / microblock_dimensions).to_u32();
-        let microblock_amount_in_range = (extent / microblock_dimensions).to_u32();
-        let microblock_amount_in_block = (self.dimensions / microblock_dimensions).to_u32();
+        let microblock_start = start / microblock_dimensions;
+        let microblock_amount_in_range = extent / microblock_dimensions;
+        let microblock_amount_in_block = self.dimensions / microblock_dimensions;
 
-        let src_bytes;
-        let src_original_len = format.count_space(block.dimensions) as usize;
+        let src_original_len = block.checked_size(format)?;
+        let dest_len = self.checked_size(format)?;
         let data = block.data.as_ref().unwrap();
-        let compression_scheme = &block.encoding;
-        if block.encoding.is_none() {
-            src_bytes = data.to_vec();
-        } else {
-            src_bytes = compression_scheme.as_ref().unwrap().decompress(data, src_original_len);
+        // Unencoded data is read in place, only encoded data needs a new buffer.
+        let src_bytes: Cow<[u8]> = match &block.encoding {
+            None | Some(CompressionType::None) => Cow::Borrowed(data.as_slice()),
+            Some(compression_scheme) => Cow::Owned(compression_scheme.decompress(data, src_original_len))
+        };
+        if src_bytes.len() < src_original_len {
+            return Err(BlockError::DataSizeMismatch(block.index, src_bytes.len(), src_original_len));
         }
 
-        let dest_bytes = self.data.as_mut().unwrap();
+        // The destination data is cloned here only if it is shared with another block or file.
+        let dest_bytes = self.data.as_mut().unwrap().make_mut();
+        if dest_bytes.len() < dest_len {
+            return Err(BlockError::DataSizeMismatch(self.index, dest_bytes.len(), dest_len));
+        }
 
-        for x in 0..microblock_amount_in_range.x {
+        // Microblocks are stored with X changing fastest, so every row of microblocks
+        // along X is contiguous in both blocks and can be copied at once. Microblocks are
+        // copied whole, so block-compressed formats are copied without decoding them.
+        let row_length = microblock_amount_in_range.x as usize * microblock_size as usize;
+        for z in 0..microblock_amount_in_range.z {
             for y in 0..microblock_amount_in_range.y {
-                for z in 0..microblock_amount_in_range.z {
-                    let local_microblock_index = Vector3::from_xyz(x, y, z);
-                    let global_microblock_index = local_microblock_index + microblock_start;
-                    let src_microblock_index = Vector3::linear_index(local_microblock_index, microblock_amount_in_range);
-                    let dest_microblock_index = Vector3::linear_index(global_microblock_index, microblock_amount_in_block);
-
-                    for i in 0..microblock_size {
-                        let dest_index = i as usize + dest_microblock_index * microblock_size as usize;
-                        let src_index = i as usize + src_microblock_index * microblock_size as usize;
-                        dest_bytes[dest_index] = src_bytes[src_index];
-                    }
-                }
+                let local_microblock_index = Vector3::from_xyz(0, y, z);
+                let global_microblock_index = local_microblock_index + microblock_start;
+                let src_microblock_index = Vector3::linear_index(local_microblock_index, microblock_amount_in_range);
+                let dest_microblock_index = Vector3::linear_index(global_microblock_index, microblock_amount_in_block);
+
+                let src_index = src_microblock_index * microblock_size as usize;
+                let dest_index = dest_microblock_index * microblock_size as usize;
+                dest_bytes[dest_index..dest_index + row_length]
+                    .copy_from_slice(&src_bytes[src_index..src_index + row_length]);
             }
         }
         return Ok(());
     }
 
+    /// Returns a new block with the decoded data of self.
+    /// Data that is not encoded is shared instead of copied.
+    /// * `format` - a format to interpret data in self
+    pub fn decoded(&self, format: &Format) -> Result<Block, BlockError> {
+        let data = match &self.data {
+            Some(d) => d,
+            None => return Err(BlockError::NoData(self.index))
+        };
+        let decoded_data = match &self.encoding {
+            None | Some(CompressionType::None) => data.clone(),
+            Some(compression_scheme) => {
+                let original_len = self.checked_size(format)?;
+                Bytes::from(compression_scheme.decompress(data, original_len))
+            }
+        };
+        let mut block = Block::new(self.index, self.dimensions, self.format, None);
+        block.data = Some(decoded_data);
+        return Ok(block);
+    }
+
+    /// Returns a new block with the decoded data of self, its microblocks reordered from linear order to a layout.
+    /// * `format` - a format to interpret data in self
+    /// * `layout` - the layout to store the data in
+    pub fn to_layout(&self, format: &Format, layout: VoxelLayout) -> Result<Block, BlockError> {
+        return self.reordered(format, layout, true);
+    }
+
+    /// Returns a new block with the decoded data of self, its microblocks reordered from a layout to linear order.
+    /// * `format` - a format to interpret data in self
+    /// * `layout` - the layout the data is stored in
+    pub fn from_layout(&self, format: &Format, layout: VoxelLayout) -> Result<Block, BlockError> {
+        return self.reordered(format, layout, false);
+    }
+
+    fn reordered(&self, format: &Format, layout: VoxelLayout, to_layout: bool) -> Result<Block, BlockError> {
+        let decoded = self.decoded(format)?;
+        if layout == VoxelLayout::Linear {
+            return Ok(decoded);
+        }
+        let microblock_dimensions = format.microblock_dimensions;
+        if self.dimensions.is_any_div(&microblock_dimensions) {
+            return Err(BlockError::BlockInvalidSize(self.index, self.dimensions, microblock_dimensions));
+        }
+        let size = self.checked_size(format)?;
+        let src = decoded.data.as_ref().unwrap();
+        if src.len() != size {
+            return Err(BlockError::DataSizeMismatch(self.index, src.len(), size));
+        }
+
+        let microblock_size = format.microblock_size as usize;
+        let mut dest = vec![0u8; size];
+        let order = layout::microblock_order(layout, self.dimensions / microblock_dimensions);
+        for (stored, linear) in order.into_iter().enumerate() {
+            let (from, to) = if to_layout { (linear, stored) } else { (stored, linear) };
+            dest[to * microblock_size..(to + 1) * microblock_size]
+                .copy_from_slice(&src[from * microblock_size..(from + 1) * microblock_size]);
+        }
+        return Ok(Block::new(self.index, self.dimensions, self.format, Some(dest)));
+    }
+
     /// Copy a portion of data from self to a new block and return it.
     /// * `start` - position of source block (self) where the copy operation should start
     /// * `end` - position of source block (self) where copy operation should end
     /// * `format` - a format to interpret data in source block
     pub fn get_data_in_range(&self, start: Vector3<u32>, end: Vector3<u32>, format: &Format) -> Result<Block, BlockError> {
-        let extent = end - start;
         if self.data.is_none() {
             return Err(BlockError::NoData(self.index));
         }
-        if extent.is_any_lt(Vector3::from_xyz(0, 0, 0)) {
-            return Err(BlockError::StartGreaterThanEnd(self.index, start, end));
-        }
+        let extent = match end.checked_sub(&start) {
+            Some(e) => e,
+            None => return Err(BlockError::StartGreaterThanEnd(self.index, start, end))
+        };
         if start.is_any_lt(Vector3::from_xyz(0, 0, 0)) {
             return Err(BlockError::StartOutOfBounds(self.index, start));
         }
         if end.is_any_gt(self.dimensions) {
-            return Err(BlockError::EndOutOfBounds(self.index, start));
+            return Err(BlockError::EndOutOfBounds(self.index, end));
         }
 
         let microblock_dimensions = format.microblock_dimensions;
@@ -124,139 +236,109 @@ impl Block {
             return Err(BlockError::BlockInvalidPosition(self.index, start, microblock_dimensions));
         }
         if extent.is_any_div(&microblock_dimensions) {
-            return Err(BlockError::BlockInvalidSize(self.index, start, microblock_dimensions));
+            return Err(BlockError::BlockInvalidSize(self.index, extent, microblock_dimensions));
         }
 
         let microblock_size = format.microblock_size;
-        let microblock_start = (start / microblock_dimensions).to_u32();
-        let microblock_amount_in_range = (extent / microblock_dimensions).to_u32();
-        let microblock_amount_in_block = (self.dimensions / microblock_dimensions).to_u32();
+        let microblock_start = start / microblock_dimensions;
+        let microblock_amount_in_range = extent / microblock_dimensions;
+        let microblock_amount_in_block = self.dimensions / microblock_dimensions;
 
         let mut block = Block::new(0, extent, self.format, None);
-        let src_bytes = &self.data.as_ref().unwrap();
-        let dest_vec_size = format.count_space(extent) as usize;
-        let mut dest_bytes = Vec::with_capacity(dest_vec_size);
-        // God, I sure hope dest_bytes and src_bytes are of the same size...
-        unsafe { dest_bytes.set_len(dest_vec_size); }
+        let data = self.data.as_ref().unwrap();
+        let src_len = self.checked_size(format)?;
+        // As in `set_data_in_range`, only encoded data is decoded into a new buffer.
+        let src_bytes: Cow<[u8]> = match &self.encoding {
+            None | Some(CompressionType::None) => Cow::Borrowed(data.as_slice()),
+            Some(compression_scheme) => Cow::Owned(compression_scheme.decompress(data, src_len))
+        };
+        if src_bytes.len() < src_len {
+            return Err(BlockError::DataSizeMismatch(self.index, src_bytes.len(), src_len));
+        }
+        let dest_vec_size = block.checked_size(format)?;
+        let mut dest_bytes = vec![0u8; dest_vec_size];
         
-        for x in 0..microblock_amount_in_range.x {
+        // Same as in `set_data_in_range`, rows of microblocks along X are copied at once.
+        let row_length = microblock_amount_in_range.x as usize * microblock_size as usize;
+        for z in 0..microblock_amount_in_range.z {
             for y in 0..microblock_amount_in_range.y {
-                for z in 0..microblock_amount_in_range.z {
-                    let local_microblock_index = Vector3::from_xyz(x, y, z);
-                    let global_microblock_index = local_microblock_index + microblock_start;
-                    let src_microblock_index = Vector3::linear_index(global_microblock_index, microblock_amount_in_block);
-                    let dest_microblock_index = Vector3::linear_index(local_microblock_index, microblock_amount_in_range);
-                    for i in 0..microblock_size {
-                        let dest_index = i as usize + dest_microblock_index * microblock_size as usize;
-                        let src_index = i as usize + src_microblock_index * microblock_size as usize;
-                        dest_bytes[dest_index] = src_bytes[src_index];
-                    }
-                }
+                let local_microblock_index = Vector3::from_xyz(0, y, z);
+                let global_microblock_index = local_microblock_index + microblock_start;
+                let src_microblock_index = Vector3::linear_index(global_microblock_index, microblock_amount_in_block);
+                let dest_microblock_index = Vector3::linear_index(local_microblock_index, microblock_amount_in_range);
+
+                let src_index = src_microblock_index * microblock_size as usize;
+                let dest_index = dest_microblock_index * microblock_size as usize;
+                dest_bytes[dest_index..dest_index + row_length]
+                    .copy_from_slice(&src_bytes[src_index..src_index + row_length]);
             }
         }
 
-        block.data = Some(dest_bytes);
+        block.data = Some(Bytes::from(dest_bytes));
         return Ok(block);
     }
 
     /// Converts self to JSON object and returns JsonValue.
     pub fn to_json(&self) -> JsonValue {
-        let mut hm = HashMap::new();
-        let mut placements = Vec::new();
-        for placement in &self.placements {
-            placements.push(placement.to_json());
-        }
-        hm.insert("placements".to_string(), placements.into());
-        hm.insert("dimensions".to_string(), self.dimensions.to_json());
-        if self.format.is_some() {
-            hm.insert("format".to_string(), (self.format.unwrap() as f64).into());
-        }
-        if self.data_url.is_some() {
-            hm.insert("data".to_string(), self.data_url.as_ref().unwrap().clone().into());
-        }
-        if self.encoding.is_some() {
-            hm.insert("encoding".to_string(), self.encoding.as_ref().unwrap().to_string().into());
-        }
-
-        return hm.into();
+        let placements: Vec<JsonValue> = self.placements.iter().map(|p| p.to_json()).collect();
+        return ObjectWriter::new()
+            .field("placements", &placements)
+            .field("dimensions", &self.dimensions)
+            .optional("format", &self.format)
+            .optional("data", &self.data_url)
+            .optional("encoding", &self.encoding.as_ref().map(|e| e.to_string()))
+            .optional("checksum", &self.checksum.as_ref().map(|c| c.to_string()))
+            .payloads(&self.extension_payloads)
+            .extras(&self.extras)
+            .finish();
     }
 
     /// Creates a block out of JSON object and returns it.
+    /// JSON errors are at pointers relative to the block, see `BlockError::at`.
     /// * `index` - index of the block inside BVP manifest file
     /// * `j` - JSON value, should be an object
-    /// * `files` - vector of volume files to pull data from
-    pub fn from_json(index: usize, j: &JsonValue, files: &Vec<File>) -> Result<Self, BlockError> {
-        // All of this is probably not optimal...
-        match j {
-            JsonValue::Object(o) => {
-                let dimensions = Vector3::<u32>::from_json(&o["dimensions"]).map_err(|x| BlockError::InvalidJson(index, x))?;
-                let mut placements = Vec::new();
-                match &o["placements"] {
-                    JsonValue::Array(a) => {
-                        for el in a {
-                            let placement = match Placement::from_json(index, &el) {
-                                Ok(p) => p,
-                                Err(e) => return Err(BlockError::InvalidPlacement(index, e))
-                            };
-                            placements.push(placement);
-                        }
-                    },
-                    _ => {
-                        return Err(BlockError::InvalidJson(index, JsonError::NotAnArray(o["placements"].clone())));
-                    }
-                };
-                let mut block = Block {
-                    index,
-                    dimensions,
-                    placements,
-                    format: None,
-                    data: None,
-                    data_url: None,
-                    encoding: None
-                };
-
-                match o.get("format") {
-                    Some(f) => {
-                        let format = get_u32_from_json(f);
-                        if format.is_err() {
-                            return Err(BlockError::InvalidJson(index, format.unwrap_err()));
-                        }
-                        block.format = Some(format.unwrap() as usize);
-                    },
-                    None => ()
-                }
-
-                match o.get("data") {
-                    Some(d) => {
-                        let data_url = match get_string_from_json(d) {
-                            Ok(d) => d,
-                            Err(e) => return Err(BlockError::InvalidJson(index, e))
-                        };
-                        let encoding = match get_string_from_json(&o["encoding"]) {
-                            Ok(e) => match CompressionType::from_string(&e) {
-                                Ok(e) => e,
-                                Err(e) => return Err(BlockError::InvalidCompression(index, e)),
-                            },
-                            Err(e) => return Err(BlockError::InvalidJson(index, e))
-                        };
-                        
-                        for file in files {
-                            if file.name == data_url {
-                                let data = file.data.to_vec();
-                                block.data_url = Some(data_url);
-                                block.encoding = Some(encoding);
-                                block.data = Some(data);
-                                break;
-                            }
-                        }
-                    },
-                    None => ()
-                }
-                return Ok(block);
-            },
-            _ => ()
+    /// * `files` - files of the asset by name, to pull data from
+    pub fn from_json(index: usize, j: &JsonValue, files: &FileIndex) -> Result<Self, BlockError> {
+        let invalid = |e| BlockError::InvalidJson(index, e);
+        let o = ObjectReader::new(j).map_err(invalid)?;
+        let dimensions = o.required("dimensions").map_err(invalid)?;
+        let mut placements = Vec::new();
+        for (i, el) in o.elements("placements").map_err(invalid)?.iter().enumerate() {
+            let placement = Placement::from_json(index, el).map_err(|e| BlockError::InvalidPlacement(index, e.at(&format!("/placements/{}", i))))?;
+            placements.push(placement);
         }
-        return Err(BlockError::InvalidJson(index, JsonError::NotAnObject(j.clone())));
+        let mut block = Block {
+            index,
+            dimensions,
+            placements,
+            format: None,
+            data: None,
+            data_url: None,
+            encoding: None,
+            checksum: None,
+            extension_payloads: o.payloads().map_err(invalid)?,
+            extras: o.extras()
+        };
+        block.format = o.optional::<u32>("format").map_err(invalid)?.map(|f| f as usize);
+
+        if let Some(data_url) = o.optional::<String>("data").map_err(invalid)? {
+            let encoding = o.required::<String>("encoding").map_err(invalid)?;
+            let encoding = CompressionType::from_string(&encoding).map_err(|e| BlockError::InvalidCompression(index, e))?;
+            let checksum = match o.optional::<String>("checksum").map_err(invalid)? {
+                Some(c) => Some(Checksum::from_string(&c).map_err(|e| BlockError::InvalidChecksum(index, e))?),
+                None => None
+            };
+
+            // The data is only there if the file is, but the rest is kept so that
+            // the file can be fetched later, for example by a viewer in the browser.
+            if let Some(file) = files.get(&data_url) {
+                block.data = Some(file.data.clone());
+            }
+            block.checksum = checksum;
+            block.data_url = Some(data_url);
+            block.encoding = Some(encoding);
+        }
+        return Ok(block);
     }
 
     /// Check if data in two blocks is the same.
@@ -1,11 +1,17 @@
-use std::{collections::{HashMap, HashSet}, str::FromStr};
+use std::{collections::{BTreeSet, HashMap}, str};
+#[cfg(feature = "fs")]
+use std::path::Path;
 
 use tinyjson::{JsonValue};
 
-use crate::{block::Block, formats::Format, asset::Asset, modality::Modality, file::File, errors::{BvpFileError, JsonError}};
+#[cfg(feature = "fs")]
+use crate::archives::ArchiveEnum;
+use crate::{block::Block, bytes::Bytes, formats::Format, asset::Asset, modality::Modality, file::File, errors::{BvpFileError, ReaderError}, extensions::Extension, json_aux::{self, ParseMode}, manifest::ManifestReader, mapping::ObjectWriter, version::SpecVersion};
+use crate::reader::{BlockMeta, VolumeReader};
+use crate::log_debug;
 
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct BVPFile {
     pub asset: Asset,
     pub modalities: Vec<Modality>,
@@ -18,7 +24,7 @@ pub struct BVPFile {
 impl BVPFile {
     pub fn new() -> Self {
         let asset = Asset {
-            version: "1.0".to_string(),
+            version: SpecVersion::CURRENT.to_string(),
             name: None,
             generator: None,
             author: None,
@@ -27,7 +33,9 @@ impl BVPFile {
             acquisition_time: None,
             creation_time: None,
             extensions_required: Vec::new(),
-            extensions_used: Vec::new()
+            extensions_used: Vec::new(),
+            extension_payloads: HashMap::new(),
+            extras: None
         };
         let modalities = Vec::new();
         let blocks = Vec::new();
@@ -44,104 +52,161 @@ impl BVPFile {
         }
     }
 
-    pub fn to_manifest(&self) -> Result<Vec<u8>, String> {
-        let mut formats = Vec::new();
-        let mut modalities = Vec::new();
-        let mut blocks = Vec::new();
-        let mut extensions = HashSet::new();
+    /// Returns the blocks with data, each with a function that decodes its data, see `VolumeReader::blocks`.
+    pub fn iter_blocks(&self) -> impl Iterator<Item = (BlockMeta, impl FnOnce() -> Result<Bytes, ReaderError> + '_)> + '_ {
+        return VolumeReader::new(self).blocks();
+    }
+
+    /// Finds a block by its index or by the name of its data file, either the full name
+    /// as in the manifest or only the last part of it.
+    /// * `id` - index or data file name of the block
+    pub fn find_block(&self, id: &str) -> Option<usize> {
+        if let Ok(index) = id.parse::<usize>() {
+            return if index < self.blocks.len() { Some(index) } else { None };
+        }
+        return self.blocks.iter().position(|block| match &block.data_url {
+            Some(data_url) => data_url == id || data_url.rsplit('/').next() == Some(id),
+            None => false
+        });
+    }
 
+    /// Returns the names of the extensions used in the asset and of those that are required.
+    /// Extensions of the library are declared if the manifest needs them. Other extensions
+    /// that were declared when the asset was read are kept, as well as the extensions
+    /// of any payloads attached to manifest nodes.
+    pub fn declared_extensions(&self) -> (Vec<String>, Vec<String>) {
+        let mut used = BTreeSet::new();
+        let mut required = BTreeSet::new();
+        let mut known = Vec::new();
         for format in &self.formats {
-            formats.push(format.to_json().into());
-            if format.extension.is_some() {
-                extensions.insert(format.extension.unwrap());
+            known.extend(format.extensions.iter().copied());
+        }
+        if self.blocks.iter().any(|b| b.checksum.is_some()) {
+            known.push(Extension::ExtChecksum);
+        }
+        for extension in known {
+            used.insert(extension.to_string());
+            if extension.is_required() {
+                required.insert(extension.to_string());
             }
         }
-        for modality in &self.modalities {
-            modalities.push(modality.to_json());
+
+        for name in &self.asset.extensions_used {
+            if Extension::from_string(name).is_none() {
+                used.insert(name.clone());
+            }
+        }
+        for name in &self.asset.extensions_required {
+            if Extension::from_string(name).is_none() {
+                used.insert(name.clone());
+                required.insert(name.clone());
+            }
         }
-        for block in &self.blocks {
-            blocks.push(block.to_json());
+        let payloads = [&self.asset.extension_payloads].into_iter()
+            .chain(self.modalities.iter().map(|m| &m.extension_payloads))
+            .chain(self.formats.iter().map(|f| &f.extension_payloads))
+            .chain(self.blocks.iter().map(|b| &b.extension_payloads));
+        for payload in payloads {
+            for name in payload.keys() {
+                used.insert(name.clone());
+                // Payloads of required extensions, such as the voxel layout of a format, change how the data is read.
+                if Extension::from_string(name).map_or(false, |e| e.is_required()) {
+                    required.insert(name.clone());
+                }
+            }
         }
+        return (used.into_iter().collect(), required.into_iter().collect());
+    }
 
-        let asset = self.asset.to_json(extensions);
-        let mut manifest = HashMap::new();
-        manifest.insert("asset".to_string(), asset);
-        manifest.insert("formats".to_string(), formats.into());
-        manifest.insert("modalities".to_string(), modalities.into());
-        manifest.insert("blocks".to_string(), blocks.into());
+    pub fn to_manifest(&self) -> Result<Vec<u8>, BvpFileError> {
+        let formats: Vec<JsonValue> = self.formats.iter().map(|f| f.to_json()).collect();
+        let modalities: Vec<JsonValue> = self.modalities.iter().map(|m| m.to_json()).collect();
+        let blocks: Vec<JsonValue> = self.blocks.iter().map(|b| b.to_json()).collect();
 
-        let v = JsonValue::from(manifest);
-        let content = match v.stringify() {
+        let (extensions_used, extensions_required) = self.declared_extensions();
+        let v = ObjectWriter::new()
+            .field("asset", &self.asset.to_json(&extensions_used, &extensions_required))
+            .field("formats", &formats)
+            .field("modalities", &modalities)
+            .field("blocks", &blocks)
+            .finish();
+        let content = match json_aux::try_canonical_string(&v) {
             Ok(c) => c,
             Err(e) => {
-                return Err(format!("Error creating manifest JSON: {}", e));
+                return Err(BvpFileError::CannotSerialize(e.to_string()));
             },
         };
         return Ok(content.into_bytes());
     }
 
-    pub fn from_manifest(manifest_content: &str, files: &Vec<File>) -> Result<Self, BvpFileError> {
-        let mut state = BVPFile::new();
-        let json = match JsonValue::from_str(manifest_content) {
-            Ok(j) => match j {
-                JsonValue::Object(o) => o,
-                _ => {
-                    return Err(BvpFileError::InvalidJson(JsonError::NotAnObject(j.clone())));
-                }
-            },
-            Err(e) => {
-                return Err(BvpFileError::BrokenManifest(e.to_string()));
-            },
-        };
+    /// Reads a BVP asset from an archive file or folder.
+    /// * `filepath` - path to the archive file, manifest file or folder
+    /// * `archive` - type of the archive
+    #[cfg(feature = "fs")]
+    pub fn open(filepath: &Path, archive: &ArchiveEnum) -> Result<Self, BvpFileError> {
+        return Self::open_with_mode(filepath, archive, ParseMode::Lenient);
+    }
 
-        state.asset = match Asset::from_json(&json["asset"]) {
-            Ok(a) => a,
-            Err(e) => return Err(BvpFileError::AssetError(e))
-        };
+    /// Reads a BVP asset from an archive file or folder.
+    /// * `filepath` - path to the archive file, manifest file or folder
+    /// * `archive` - type of the archive
+    /// * `mode` - how strictly the manifest is read
+    #[cfg(feature = "fs")]
+    pub fn open_with_mode(filepath: &Path, archive: &ArchiveEnum, mode: ParseMode) -> Result<Self, BvpFileError> {
+        let files = archive.read_archive(filepath).map_err(BvpFileError::ArchiveError)?;
+        return Self::from_files_with_mode(files, mode);
+    }
 
-        match &json["blocks"] {
-            JsonValue::Array(a) => {
-                for (i, el) in a.iter().enumerate() {
-                    let block = match Block::from_json(i, &el, files) {
-                        Ok(b) => b,
-                        Err(e) => return Err(BvpFileError::BlockError(e))
-                    };
-                    state.blocks.push(block);
-                }
-            },
-            _ => {
-                return Err(BvpFileError::InvalidJson(JsonError::NotAnArray(json["block"].clone())));
-            }
-        };
-        match &json["modalities"] {
-            JsonValue::Array(a) => {
-                for (i, el) in a.iter().enumerate() {
-                    let modality = match Modality::from_json(i, &el) {
-                        Ok(m) => m,
-                        Err(e) => return Err(BvpFileError::ModalityError(e))
-                    };
-                    state.modalities.push(modality);
-                }
-            },
-            _ => {
-                return Err(BvpFileError::InvalidJson(JsonError::NotAnArray(json["modalities"].clone())));
-            }
+    /// Finds the manifest among the files of an asset and creates a BVPFile from it.
+    /// The files are kept in `BVPFile.files`.
+    /// * `files` - all files of the asset, as read from the archive
+    pub fn from_files(files: Vec<File>) -> Result<Self, BvpFileError> {
+        return Self::from_files_with_mode(files, ParseMode::Lenient);
+    }
+
+    /// Finds the manifest among the files of an asset and creates a BVPFile from it.
+    /// The files are kept in `BVPFile.files`.
+    /// * `files` - all files of the asset, as read from the archive
+    /// * `mode` - how strictly the manifest is read
+    pub fn from_files_with_mode(files: Vec<File>, mode: ParseMode) -> Result<Self, BvpFileError> {
+        let manifest = match files.iter().find(|file| file.name.ends_with("manifest.json")) {
+            Some(m) => m,
+            None => return Err(BvpFileError::MissingManifest)
         };
-        match &json["formats"] {
-            JsonValue::Array(a) => {
-                for el in a {
-                    let format = match Format::from_json(&el) {
-                        Ok(f) => f,
-                        Err(e) => return Err(BvpFileError::FormatError(e))
-                    };
-                    state.formats.push(format);
-                }
-            },
-            _ => {
-                return Err(BvpFileError::InvalidJson(JsonError::NotAnArray(json["formats"].clone())));
-            }
+        let content = match str::from_utf8(&manifest.data) {
+            Ok(c) => c,
+            Err(e) => return Err(BvpFileError::BrokenManifest(format!("Cannot decode manifest file: {}", e)))
         };
 
+        let mut state = Self::from_manifest_with_mode(content, &files, mode)?;
+        state.files = files;
+        return Ok(state);
+    }
+
+    /// Creates a BVPFile from a manifest, with all blocks parsed.
+    /// See `ManifestReader` for reading the blocks one at a time.
+    /// * `manifest_content` - the manifest
+    /// * `files` - all files of the asset, to pull block data from
+    pub fn from_manifest(manifest_content: &str, files: &Vec<File>) -> Result<Self, BvpFileError> {
+        return Self::from_manifest_with_mode(manifest_content, files, ParseMode::Lenient);
+    }
+
+    /// Creates a BVPFile from a manifest, with all blocks parsed.
+    /// * `manifest_content` - the manifest
+    /// * `files` - all files of the asset, to pull block data from
+    /// * `mode` - how strictly the manifest is read, see `ParseMode`
+    pub fn from_manifest_with_mode(manifest_content: &str, files: &[File], mode: ParseMode) -> Result<Self, BvpFileError> {
+        let reader = ManifestReader::with_mode(manifest_content, files, mode)?;
+        let mut state = BVPFile::new();
+        state.blocks.reserve(reader.block_count());
+        for block in reader.blocks() {
+            state.blocks.push(block?);
+        }
+        log_debug!("parsed {} blocks", state.blocks.len());
+        state.asset = reader.asset;
+        state.modalities = reader.modalities;
+        state.formats = reader.formats;
+
         return Ok(state);
     }
 }
\ No newline at end of file
@@ -0,0 +1,107 @@
+//! Shared, immutable byte buffers. Files read from an archive are slices of the archive buffer,
+//! and blocks share the data of their files, so opening an asset does not copy its data.
+
+use std::fmt;
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+/// A cheaply cloneable view into a shared buffer. Clones and slices point into the same buffer,
+/// which is freed when the last of them is dropped.
+#[derive(Clone)]
+pub struct Bytes {
+    buffer: Arc<Vec<u8>>,
+    start: usize,
+    end: usize
+}
+
+impl Bytes {
+    pub fn new() -> Self {
+        return Self::from(Vec::new());
+    }
+
+    /// Returns a view of a part of these bytes, sharing the buffer.
+    /// Panics if the range is out of bounds, as slicing does.
+    /// * `range` - the part, relative to the start of these bytes
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        assert!(range.start <= range.end && range.end <= self.len(), "range {:?} out of bounds of {} bytes", range, self.len());
+        return Self { buffer: self.buffer.clone(), start: self.start + range.start, end: self.start + range.end };
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        return &self.buffer[self.start..self.end];
+    }
+
+    /// Returns true if both views are the same part of the same buffer, without comparing bytes.
+    /// * `other` - the other view
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        return Arc::ptr_eq(&self.buffer, &other.buffer) && self.start == other.start && self.end == other.end;
+    }
+
+    /// Returns the bytes for writing. They are copied to a buffer of their own first, unless
+    /// this is already the only view of all of its buffer, as `Arc::make_mut` does.
+    pub fn make_mut(&mut self) -> &mut [u8] {
+        let whole = self.start == 0 && self.end == self.buffer.len();
+        if !whole || Arc::get_mut(&mut self.buffer).is_none() {
+            *self = Self::from(self.as_slice().to_vec());
+        }
+        return Arc::get_mut(&mut self.buffer).unwrap().as_mut_slice();
+    }
+
+    /// Returns the bytes as a vector. The buffer is taken without copying if this is the only view of all of it.
+    pub fn into_vec(self) -> Vec<u8> {
+        if self.start == 0 && self.end == self.buffer.len() {
+            return match Arc::try_unwrap(self.buffer) {
+                Ok(buffer) => buffer,
+                Err(buffer) => buffer.as_ref().clone()
+            };
+        }
+        return self.as_slice().to_vec();
+    }
+}
+
+impl Default for Bytes {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        return self.as_slice();
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        return self.as_slice();
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(buffer: Vec<u8>) -> Self {
+        return Self::from(Arc::new(buffer));
+    }
+}
+
+impl From<Arc<Vec<u8>>> for Bytes {
+    fn from(buffer: Arc<Vec<u8>>) -> Self {
+        let end = buffer.len();
+        return Self { buffer, start: 0, end };
+    }
+}
+
+impl PartialEq for Bytes {
+    fn eq(&self, other: &Self) -> bool {
+        return self.ptr_eq(other) || self.as_slice() == other.as_slice();
+    }
+}
+
+impl Eq for Bytes {}
+
+impl fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "Bytes({} bytes)", self.len());
+    }
+}
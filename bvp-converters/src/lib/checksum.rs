@@ -0,0 +1,108 @@
+use std::fmt;
+
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{digest::Sha256, errors::ChecksumError};
+
+/// Hash algorithms that can be used to check integrity of block data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumType {
+    Xxh3,
+    Crc32,
+    Sha256
+}
+
+impl ChecksumType {
+    pub fn from_string(s: &str) -> Result<Self, ChecksumError> {
+        return match s {
+            "xxh3" | "XXH3" => Ok(Self::Xxh3),
+            "crc32" | "CRC32" => Ok(Self::Crc32),
+            "sha256" | "SHA256" => Ok(Self::Sha256),
+            _ => Err(ChecksumError::UnsupportedAlgorithm(s.to_string()))
+        }
+    }
+
+    /// Returns the length of the checksum in bytes.
+    pub fn length(&self) -> usize {
+        return match self {
+            ChecksumType::Xxh3 => 8,
+            ChecksumType::Crc32 => 4,
+            ChecksumType::Sha256 => 32
+        }
+    }
+
+    /// Returns true if the algorithm is a cryptographic hash, so that a signed checksum
+    /// also vouches for the data. Only SHA-256 is.
+    pub fn is_cryptographic(&self) -> bool {
+        return *self == ChecksumType::Sha256;
+    }
+}
+
+impl fmt::Display for ChecksumType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ChecksumType::Xxh3 => "xxh3",
+            ChecksumType::Crc32 => "crc32",
+            ChecksumType::Sha256 => "sha256"
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+/// Checksum of a data file, as stored in the file (after encoding).
+/// In the manifest, it is written as `<algorithm>:<hexadecimal value>`, for example `crc32:1c291ca3`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: ChecksumType,
+    /// The hash, big-endian.
+    pub value: Vec<u8>
+}
+
+impl Checksum {
+    /// Computes the checksum of the data.
+    /// * `algorithm` - the hash algorithm to use
+    /// * `data` - bytes to hash
+    pub fn compute(algorithm: ChecksumType, data: &[u8]) -> Self {
+        let value = match algorithm {
+            ChecksumType::Xxh3 => xxh3_64(data).to_be_bytes().to_vec(),
+            ChecksumType::Crc32 => crc32fast::hash(data).to_be_bytes().to_vec(),
+            ChecksumType::Sha256 => {
+                let mut hasher = Sha256::new();
+                hasher.update(data);
+                hasher.finish().to_vec()
+            }
+        };
+        return Self { algorithm, value };
+    }
+
+    /// Returns true if the data has the same checksum.
+    /// * `data` - bytes to check
+    pub fn verify(&self, data: &[u8]) -> bool {
+        return Self::compute(self.algorithm, data) == *self;
+    }
+
+    pub fn from_string(s: &str) -> Result<Self, ChecksumError> {
+        let (algorithm, value) = match s.split_once(':') {
+            Some(parts) => parts,
+            None => return Err(ChecksumError::InvalidValue(s.to_string()))
+        };
+        let algorithm = ChecksumType::from_string(algorithm)?;
+        let length = algorithm.length();
+        if value.is_empty() || value.len() > 2 * length || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ChecksumError::InvalidValue(s.to_string()));
+        }
+        // Shorter values have leading zeros left out.
+        let value = format!("{:0>width$}", value, width = 2 * length);
+        let value = (0..length)
+            .map(|i| u8::from_str_radix(&value[2 * i..2 * i + 2], 16).unwrap())
+            .collect();
+        return Ok(Self { algorithm, value });
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value: String = self.value.iter().map(|b| format!("{:02x}", b)).collect();
+        return write!(f, "{}:{}", self.algorithm, value);
+    }
+}
@@ -0,0 +1,576 @@
+//! Deflate (RFC 1951), the compression of members of ZIP archives.
+//!
+//! Huffman codes are decoded one bit at a time with the canonical code counts, as in zlib's `puff`,
+//! which is short and easy to check. The encoder finds matches with hash chains and writes every
+//! block with its own Huffman codes, without zlib's lazy matching. Blocks of BVP assets are usually
+//! compressed with LZ4S already, so deflate mostly compresses manifests and raw blocks and does not
+//! have to be fast.
+
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use crate::errors::CompressionError;
+
+/// Longest Huffman code in deflate, in bits.
+const MAX_CODE_LENGTH: usize = 15;
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769,
+    1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/// Order in which the code lengths of the code length code are stored in dynamic blocks.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn corrupt(message: &str) -> CompressionError {
+    return CompressionError::CorruptData(format!("deflate: {}", message));
+}
+
+/// Reads bits of a deflate stream, least significant bit of each byte first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u64,
+    count: u32
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        return Self { data, position: 0, buffer: 0, count: 0 };
+    }
+
+    /// Reads `count` bits, at most 32, as a number whose lowest bit was read first.
+    fn bits(&mut self, count: u32) -> Result<u32, CompressionError> {
+        while self.count < count {
+            let byte = match self.data.get(self.position) {
+                Some(b) => *b,
+                None => return Err(corrupt("the stream ends early"))
+            };
+            self.buffer |= (byte as u64) << self.count;
+            self.count += 8;
+            self.position += 1;
+        }
+        let value = (self.buffer & ((1u64 << count) - 1)) as u32;
+        self.buffer >>= count;
+        self.count -= count;
+        return Ok(value);
+    }
+
+    /// Skips to the next byte boundary, where stored blocks start.
+    fn align(&mut self) {
+        let skipped = self.count % 8;
+        self.buffer >>= skipped;
+        self.count -= skipped;
+    }
+
+    /// Copies bytes from a byte boundary to the output.
+    /// * `length` - number of bytes
+    /// * `output` - the output
+    fn copy_bytes(&mut self, length: usize, output: &mut Vec<u8>) -> Result<(), CompressionError> {
+        let buffered = ((self.count / 8) as usize).min(length);
+        for _ in 0..buffered {
+            output.push(self.bits(8)? as u8);
+        }
+        let rest = length - buffered;
+        let bytes = match self.data.get(self.position..self.position + rest) {
+            Some(b) => b,
+            None => return Err(corrupt("a stored block ends early"))
+        };
+        output.extend_from_slice(bytes);
+        self.position += rest;
+        return Ok(());
+    }
+}
+
+/// A canonical Huffman code, given by the number of codes of each length and the symbols in code order.
+struct Huffman {
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    symbols: Vec<u16>
+}
+
+impl Huffman {
+    /// Builds the code from the code length of every symbol, 0 for symbols that do not occur.
+    /// * `lengths` - code lengths, by symbol
+    fn new(lengths: &[u8]) -> Result<Self, CompressionError> {
+        let mut counts = [0u16; MAX_CODE_LENGTH + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        // Codes may be incomplete, as a distance code with a single code is, but never over-subscribed.
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(corrupt("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_CODE_LENGTH + 2];
+        for length in 1..=MAX_CODE_LENGTH {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0u16; offsets[MAX_CODE_LENGTH + 1] as usize];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        return Ok(Self { counts, symbols });
+    }
+
+    /// Reads one symbol.
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, CompressionError> {
+        // `code` is the bits read so far, `first` the first code of the current length
+        // and `index` the index of that code in `symbols`.
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..=MAX_CODE_LENGTH {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        return Err(corrupt("invalid Huffman code"));
+    }
+}
+
+/// Returns the literal/length and distance codes of blocks compressed with the fixed codes.
+fn fixed_codes() -> Result<(Huffman, Huffman), CompressionError> {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    return Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?));
+}
+
+/// Reads the literal/length and distance codes at the start of a block compressed with dynamic codes.
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), CompressionError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(corrupt("too many codes in a dynamic block"));
+    }
+
+    let mut code_length_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_length_lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_length_lengths)?;
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = code_length_code.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => match i.checked_sub(1) {
+                Some(previous) => (lengths[previous], 3 + reader.bits(2)? as usize),
+                None => return Err(corrupt("a repeated code length has nothing to repeat"))
+            },
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize)
+        };
+        if i + repeat > lengths.len() {
+            return Err(corrupt("code lengths of a dynamic block run over"));
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(corrupt("a dynamic block has no end code"));
+    }
+    return Ok((Huffman::new(&lengths[..literal_count])?, Huffman::new(&lengths[literal_count..])?));
+}
+
+/// Decodes the symbols of a compressed block until its end code.
+fn inflate_block(reader: &mut BitReader, literals: &Huffman, distances: &Huffman, output: &mut Vec<u8>) -> Result<(), CompressionError> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        if symbol < 256 {
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(corrupt("invalid length code"));
+        }
+        let length = LENGTH_BASE[symbol] as usize + reader.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+        let symbol = distances.decode(reader)? as usize;
+        if symbol >= DISTANCE_BASE.len() {
+            return Err(corrupt("invalid distance code"));
+        }
+        let distance = DISTANCE_BASE[symbol] as usize + reader.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+        if distance > output.len() {
+            return Err(corrupt("a match reaches before the start of the data"));
+        }
+        // Matches can overlap the bytes they produce, so they are copied byte by byte.
+        let start = output.len() - distance;
+        for i in 0..length {
+            output.push(output[start + i]);
+        }
+    }
+}
+
+/// Decompresses a raw deflate stream, without a zlib or gzip header.
+/// * `source` - the compressed data
+/// * `size` - the expected size of the decompressed data, only used to reserve memory
+pub fn inflate(source: &[u8], size: usize) -> Result<Vec<u8>, CompressionError> {
+    return inflate_prefix(source, size).map(|(output, _)| output);
+}
+
+/// Decompresses a raw deflate stream at the start of the data, and returns the decompressed data
+/// and the size of the stream, so the data after it can be read, such as the trailer of a gzip member.
+/// * `source` - the compressed data, followed by anything
+/// * `size` - the expected size of the decompressed data, only used to reserve memory
+pub fn inflate_prefix(source: &[u8], size: usize) -> Result<(Vec<u8>, usize), CompressionError> {
+    let mut output = Vec::with_capacity(size);
+    let mut reader = BitReader::new(source);
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let length = reader.bits(16)?;
+                let complement = reader.bits(16)?;
+                if length != !complement & 0xffff {
+                    return Err(corrupt("length of a stored block does not match its complement"));
+                }
+                reader.copy_bytes(length as usize, &mut output)?;
+            },
+            1 => {
+                let (literals, distances) = fixed_codes()?;
+                inflate_block(&mut reader, &literals, &distances, &mut output)?;
+            },
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &literals, &distances, &mut output)?;
+            },
+            _ => return Err(corrupt("invalid block type"))
+        }
+        if last {
+            // The bits left in the last byte are padding.
+            return Ok((output, reader.position));
+        }
+    }
+}
+
+/// Distance matches can reach back.
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+/// Number of symbols written in one block, each block has its own Huffman codes.
+const BLOCK_SYMBOLS: usize = 1 << 16;
+/// Longest code of the code length code, in bits.
+const MAX_CODE_LENGTH_LENGTH: u8 = 7;
+const END_OF_BLOCK: usize = 256;
+
+/// A literal byte, or a match of `length` bytes `distance` bytes back.
+#[derive(Clone, Copy)]
+enum Symbol {
+    Literal(u8),
+    Match(u16, u16)
+}
+
+/// Writes bits of a deflate stream, least significant bit of each byte first.
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u64,
+    count: u32
+}
+
+impl BitWriter {
+    fn new(capacity: usize) -> Self {
+        return Self { output: Vec::with_capacity(capacity), buffer: 0, count: 0 };
+    }
+
+    /// Writes the lowest `count` bits of `value`, lowest first.
+    fn bits(&mut self, value: u32, count: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, whose first bit is its most significant one.
+    fn code(&mut self, code: u16, length: u8) {
+        let reversed = code.reverse_bits() >> (16 - length as u32);
+        self.bits(reversed as u32, length as u32);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.output.push(self.buffer as u8);
+        }
+        return self.output;
+    }
+}
+
+/// Returns the index of the largest base that is not above a value, the code of a length or distance.
+fn base_index(bases: &[u16], value: usize) -> usize {
+    return bases.iter().rposition(|&base| base as usize <= value).unwrap_or(0);
+}
+
+/// Returns the Huffman code length of every symbol, with no code longer than `limit`.
+/// Codes that would be too long are avoided by flattening the frequencies and building the code again.
+/// * `frequencies` - how often each symbol occurs
+/// * `limit` - the longest allowed code
+fn code_lengths(frequencies: &[u32], limit: u8) -> Vec<u8> {
+    let mut frequencies = frequencies.to_vec();
+    // A code needs two symbols to be complete, which some decoders require.
+    for symbol in 0..2 {
+        if frequencies.iter().filter(|&&f| f > 0).count() < 2 && frequencies[symbol] == 0 {
+            frequencies[symbol] = 1;
+        }
+    }
+    loop {
+        let lengths = huffman_lengths(&frequencies);
+        if lengths.iter().all(|&l| l <= limit) {
+            return lengths;
+        }
+        for frequency in frequencies.iter_mut().filter(|f| **f > 0) {
+            *frequency = (*frequency >> 1).max(1);
+        }
+    }
+}
+
+/// Returns the code length of every symbol in a Huffman code of the frequencies, 0 for symbols that do not occur.
+fn huffman_lengths(frequencies: &[u32]) -> Vec<u8> {
+    let mut parents: Vec<usize> = vec![usize::MAX; frequencies.len()];
+    let mut heap = BinaryHeap::new();
+    for (symbol, &frequency) in frequencies.iter().enumerate() {
+        if frequency > 0 {
+            heap.push(Reverse((frequency as u64, symbol)));
+        }
+    }
+    while heap.len() > 1 {
+        let Reverse((a_weight, a)) = heap.pop().unwrap();
+        let Reverse((b_weight, b)) = heap.pop().unwrap();
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[a] = node;
+        parents[b] = node;
+        heap.push(Reverse((a_weight + b_weight, node)));
+    }
+    return (0..frequencies.len()).map(|symbol| {
+        if frequencies[symbol] == 0 {
+            return 0;
+        }
+        let mut length = 0;
+        let mut node = symbol;
+        while parents[node] != usize::MAX {
+            node = parents[node];
+            length += 1;
+        }
+        return length;
+    }).collect();
+}
+
+/// Returns the canonical codes of code lengths, as in section 3.2.2 of RFC 1951.
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut counts = [0u16; MAX_CODE_LENGTH + 1];
+    for &length in lengths {
+        counts[length as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut next = [0u16; MAX_CODE_LENGTH + 1];
+    let mut code = 0u16;
+    for length in 1..=MAX_CODE_LENGTH {
+        code = (code + counts[length - 1]) << 1;
+        next[length] = code;
+    }
+    return lengths.iter().map(|&length| {
+        if length == 0 {
+            return 0;
+        }
+        let code = next[length as usize];
+        next[length as usize] += 1;
+        return code;
+    }).collect();
+}
+
+/// Run length encodes code lengths with the symbols 16, 17 and 18 of the code length code,
+/// and returns the symbols with the values of their extra bits.
+fn encode_code_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut encoded = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let length = lengths[i];
+        let run = lengths[i..].iter().take_while(|&&l| l == length).count();
+        if length == 0 && run >= 11 {
+            let run = run.min(138);
+            encoded.push((18, (run - 11) as u8));
+            i += run;
+        } else if length == 0 && run >= 3 {
+            encoded.push((17, (run - 3) as u8));
+            i += run;
+        } else if length != 0 && run >= 4 {
+            // The first length is written as it is, the rest repeat it.
+            encoded.push((length, 0));
+            let repeat = (run - 1).min(6);
+            encoded.push((16, (repeat - 3) as u8));
+            i += 1 + repeat;
+        } else {
+            encoded.push((length, 0));
+            i += 1;
+        }
+    }
+    return encoded;
+}
+
+/// Writes one block with its own Huffman codes.
+/// * `writer` - the output
+/// * `symbols` - literals and matches of the block
+/// * `last` - whether this is the last block of the stream
+fn write_block(writer: &mut BitWriter, symbols: &[Symbol], last: bool) {
+    let mut literal_frequencies = [0u32; 286];
+    let mut distance_frequencies = [0u32; 30];
+    for symbol in symbols {
+        match *symbol {
+            Symbol::Literal(byte) => literal_frequencies[byte as usize] += 1,
+            Symbol::Match(length, distance) => {
+                literal_frequencies[257 + base_index(&LENGTH_BASE, length as usize)] += 1;
+                distance_frequencies[base_index(&DISTANCE_BASE, distance as usize)] += 1;
+            }
+        }
+    }
+    literal_frequencies[END_OF_BLOCK] += 1;
+    let literal_lengths = code_lengths(&literal_frequencies, MAX_CODE_LENGTH as u8);
+    let distance_lengths = code_lengths(&distance_frequencies, MAX_CODE_LENGTH as u8);
+    let literal_count = literal_lengths.iter().rposition(|&l| l > 0).unwrap_or(0).max(END_OF_BLOCK) + 1;
+    let distance_count = distance_lengths.iter().rposition(|&l| l > 0).unwrap_or(0) + 1;
+
+    let lengths: Vec<u8> = [&literal_lengths[..literal_count], &distance_lengths[..distance_count]].concat();
+    let encoded_lengths = encode_code_lengths(&lengths);
+    let mut code_length_frequencies = [0u32; 19];
+    for &(symbol, _) in &encoded_lengths {
+        code_length_frequencies[symbol as usize] += 1;
+    }
+    let code_length_lengths = code_lengths(&code_length_frequencies, MAX_CODE_LENGTH_LENGTH);
+    let code_length_codes = canonical_codes(&code_length_lengths);
+    let code_length_count = CODE_LENGTH_ORDER.iter().rposition(|&s| code_length_lengths[s] > 0).unwrap_or(0).max(3) + 1;
+
+    writer.bits(last as u32, 1);
+    writer.bits(2, 2);
+    writer.bits((literal_count - 257) as u32, 5);
+    writer.bits((distance_count - 1) as u32, 5);
+    writer.bits((code_length_count - 4) as u32, 4);
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        writer.bits(code_length_lengths[symbol] as u32, 3);
+    }
+    for &(symbol, extra) in &encoded_lengths {
+        let symbol = symbol as usize;
+        writer.code(code_length_codes[symbol], code_length_lengths[symbol]);
+        match symbol {
+            16 => writer.bits(extra as u32, 2),
+            17 => writer.bits(extra as u32, 3),
+            18 => writer.bits(extra as u32, 7),
+            _ => {}
+        }
+    }
+
+    let literal_codes = canonical_codes(&literal_lengths);
+    let distance_codes = canonical_codes(&distance_lengths);
+    for symbol in symbols {
+        match *symbol {
+            Symbol::Literal(byte) => writer.code(literal_codes[byte as usize], literal_lengths[byte as usize]),
+            Symbol::Match(length, distance) => {
+                let index = base_index(&LENGTH_BASE, length as usize);
+                writer.code(literal_codes[257 + index], literal_lengths[257 + index]);
+                writer.bits((length - LENGTH_BASE[index]) as u32, LENGTH_EXTRA[index] as u32);
+                let index = base_index(&DISTANCE_BASE, distance as usize);
+                writer.code(distance_codes[index], distance_lengths[index]);
+                writer.bits((distance - DISTANCE_BASE[index]) as u32, DISTANCE_EXTRA[index] as u32);
+            }
+        }
+    }
+    writer.code(literal_codes[END_OF_BLOCK], literal_lengths[END_OF_BLOCK]);
+}
+
+fn hash(data: &[u8], position: usize) -> usize {
+    let value = (data[position] as u32) << 16 | (data[position + 1] as u32) << 8 | data[position + 2] as u32;
+    return (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize;
+}
+
+/// Compresses data to a raw deflate stream, without a zlib or gzip header.
+/// Levels go from `MIN_COMPRESSION_LEVEL` (fastest) to `MAX_COMPRESSION_LEVEL` (smallest output)
+/// and set how many earlier positions are tried for each match.
+/// * `source` - data to compress
+/// * `level` - compression level
+pub fn deflate(source: &[u8], level: u32) -> Vec<u8> {
+    let max_chain = 1usize << (level.clamp(super::MIN_COMPRESSION_LEVEL, super::MAX_COMPRESSION_LEVEL) + 1);
+    // The latest position of every hash, and for every position of the window the previous one with the same hash.
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous = vec![usize::MAX; WINDOW_SIZE];
+    let insert = |position: usize, head: &mut Vec<usize>, previous: &mut Vec<usize>| {
+        if position + MIN_MATCH <= source.len() {
+            let h = hash(source, position);
+            previous[position % WINDOW_SIZE] = head[h];
+            head[h] = position;
+        }
+    };
+
+    let mut writer = BitWriter::new(source.len() / 2);
+    let mut symbols = Vec::with_capacity(BLOCK_SYMBOLS);
+    let mut i = 0;
+    while i < source.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+        if i + MIN_MATCH <= source.len() {
+            let longest = (source.len() - i).min(MAX_MATCH);
+            let mut candidate = head[hash(source, i)];
+            let mut chain = max_chain;
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain > 0 {
+                let length = source[candidate..candidate + longest].iter().zip(&source[i..i + longest]).take_while(|(a, b)| a == b).count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = i - candidate;
+                    if length == longest {
+                        break;
+                    }
+                }
+                // Slots of the window are reused, so a chain ends once it leads to a newer position.
+                let next = previous[candidate % WINDOW_SIZE];
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain -= 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH {
+            symbols.push(Symbol::Match(best_length as u16, best_distance as u16));
+            for position in i..i + best_length {
+                insert(position, &mut head, &mut previous);
+            }
+            i += best_length;
+        } else {
+            symbols.push(Symbol::Literal(source[i]));
+            insert(i, &mut head, &mut previous);
+            i += 1;
+        }
+        if symbols.len() == BLOCK_SYMBOLS && i < source.len() {
+            write_block(&mut writer, &symbols, false);
+            symbols.clear();
+        }
+    }
+    write_block(&mut writer, &symbols, true);
+    return writer.finish();
+}
@@ -0,0 +1,86 @@
+//! gzip (RFC 1952) decompression, for raw volumes that are shipped as `.gz` files.
+//! The members are deflate streams, see `deflate::inflate_prefix`.
+
+use crate::compressions::deflate;
+use crate::errors::CompressionError;
+
+const FLAG_HEADER_CRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+const RESERVED_FLAGS: u8 = 0xe0;
+
+fn corrupt(message: &str) -> CompressionError {
+    return CompressionError::CorruptData(format!("gzip: {}", message));
+}
+
+/// Returns the bytes at a position, or an error if the data ends before them.
+fn bytes(data: &[u8], position: usize, count: usize) -> Result<&[u8], CompressionError> {
+    return data.get(position..position + count).ok_or_else(|| corrupt("the data ends early"));
+}
+
+/// Returns the size of the header of a member.
+/// * `data` - the member
+fn header_size(data: &[u8]) -> Result<usize, CompressionError> {
+    let header = bytes(data, 0, 10)?;
+    if header[0..2] != [0x1f, 0x8b] {
+        return Err(corrupt("a member does not start with the gzip signature"));
+    }
+    if header[2] != 8 {
+        return Err(CompressionError::Unsupported(format!("gzip compression method {}", header[2])));
+    }
+    let flags = header[3];
+    if flags & RESERVED_FLAGS != 0 {
+        return Err(corrupt("reserved flags are set"));
+    }
+    let mut position = 10;
+    if flags & FLAG_EXTRA != 0 {
+        let length = bytes(data, position, 2)?;
+        position += 2 + u16::from_le_bytes([length[0], length[1]]) as usize;
+    }
+    // The file name and the comment end with a zero byte.
+    for flag in [FLAG_NAME, FLAG_COMMENT] {
+        if flags & flag != 0 {
+            match data.get(position..).and_then(|rest| rest.iter().position(|b| *b == 0)) {
+                Some(end) => position += end + 1,
+                None => return Err(corrupt("the data ends early"))
+            }
+        }
+    }
+    if flags & FLAG_HEADER_CRC != 0 {
+        position += 2;
+    }
+    return Ok(position);
+}
+
+/// Decompresses gzip data, which can hold many members one after the other, as `cat a.gz b.gz` writes them.
+/// The CRC-32 and the size of every member are checked.
+/// * `source` - the compressed data
+pub fn decompress(source: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    let mut output = Vec::new();
+    let mut position = 0;
+    while position < source.len() {
+        let member = &source[position..];
+        let start = header_size(member)?;
+        let stream = member.get(start..).ok_or_else(|| corrupt("the data ends early"))?;
+        let (data, size) = deflate::inflate_prefix(stream, 0)?;
+        let trailer = bytes(member, start + size, 8)?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let length = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32fast::hash(&data) {
+            return Err(corrupt("the CRC-32 of a member does not match its data"));
+        }
+        // The size is stored modulo 2^32.
+        if length != data.len() as u32 {
+            return Err(corrupt("the size of a member does not match its data"));
+        }
+        // Most files have one member, which is not copied.
+        if output.is_empty() {
+            output = data;
+        } else {
+            output.extend_from_slice(&data);
+        }
+        position += start + size + 8;
+    }
+    return Ok(output);
+}
@@ -1,6 +1,6 @@
 use std::num::Wrapping;
 
-pub fn read_u32(src: &Vec<u8>, i: usize) -> u32 {
+pub fn read_u32(src: &[u8], i: usize) -> u32 {
     let b1 = src[i] as u32;
     let b2 = src[i+1] as u32;
     let b3 = src[i+2] as u32;
@@ -30,14 +30,18 @@ pub fn create_hash_table() -> Vec<u32> {
     return hash_table;
 }
 
-pub fn compress_lz4s(src: &Vec<u8>) -> Vec<u8> {
+/// Compresses data with LZ4S.
+/// * `src` - data to compress
+/// * `search_step` - how many bytes to skip ahead when no match is found.
+///   1 checks every position, larger steps are faster, but find fewer matches.
+pub fn compress_lz4s(src: &[u8], search_step: usize) -> Vec<u8> {
     let mut hash_table = create_hash_table(); // Table for looking up already written data
     let src_len_f64 = src.len() as f64;
     let dest_len = (src_len_f64 + (src_len_f64 / 255.0) + 16.0).floor() as usize;
     let mut dest: Vec<u8> = Vec::with_capacity(dest_len);
 
     let mut src_index = 0;
-    let search_step = 1;
+    let search_step = search_step.max(1);
     let mut literal_start = src_index;
 
     while src_index + 4 < src.len() {
@@ -146,10 +150,32 @@ pub fn compress_lz4s(src: &Vec<u8>) -> Vec<u8> {
     return dest;
 }
 
-pub fn decompress_lz4s(src: &Vec<u8>, size: usize) -> Vec<u8> {
-    let mut dest = Vec::with_capacity(size);
+/// Decodes LZ4S data. Decoding stops at the end token, or where the data is corrupt,
+/// so a result shorter than `size` means the data was truncated or invalid.
+/// * `src` - the encoded data
+/// * `size` - the decoded size, to allocate the result
+pub fn decompress_lz4s(src: &[u8], size: usize) -> Vec<u8> {
+    // The size comes from the manifest, so only as much is allocated as the data can decode to:
+    // every byte of a length adds at most 255 to it.
+    let mut dest = Vec::with_capacity(size.min(src.len().saturating_mul(255)));
     let mut src_index = 0;
 
+    // Reads a length that continues in the following bytes while they are 0xff.
+    let read_length = |base: u32, src_index: &mut usize| -> Option<u32> {
+        let mut length = base;
+        if length == 0x0f {
+            loop {
+                let byte = *src.get(*src_index)?;
+                *src_index += 1;
+                length = length.checked_add(byte as u32)?;
+                if byte != 0xff {
+                    break;
+                }
+            }
+        }
+        return Some(length);
+    };
+
     while src_index < src.len() {
         let token = src[src_index];
         src_index += 1;
@@ -158,38 +184,37 @@ pub fn decompress_lz4s(src: &Vec<u8>, size: usize) -> Vec<u8> {
         }
 
         // Copy uncompressed data
-        let mut literal_count = token as u32 >> 4;
-        if literal_count == 0x0f {
-            literal_count += src[src_index] as u32;
-            while src[src_index] == 0xff {
-                src_index += 1;
-                literal_count += src[src_index] as u32;
-            }
-            src_index += 1;
-        }
-
-        for _ in 0..literal_count {
-            dest.push(src[src_index]);
-            src_index += 1;
-        }
+        let literal_count = match read_length(token as u32 >> 4, &mut src_index) {
+            Some(l) => l as usize,
+            None => break
+        };
+        let literals = match src.get(src_index..src_index + literal_count) {
+            Some(l) => l,
+            None => break
+        };
+        dest.extend_from_slice(literals);
+        src_index += literal_count;
 
         // Copy mach data
-
-        let mut match_length = (token & 0x0f) as u32;
-        // This little line causes segmentation fault
-        // if match offset is missing (this was the case in old implementations)
-        let offset = ((src[src_index + 0] as u32) << 0) | ((src[src_index + 1] as u32) << 8);
+        // Old implementations had no match offset after the last literals.
+        let offset = match src.get(src_index..src_index + 2) {
+            Some(o) => o[0] as usize | (o[1] as usize) << 8,
+            None => break
+        };
         src_index += 2;
-        let mut match_index = dest.len() - offset as usize;
-        if match_length == 0x0f {
-            match_length += src[src_index] as u32;
-            while src[src_index] == 0xff {
-                src_index += 1;
-                match_length += src[src_index] as u32;
-            }
-            src_index += 1;
+        let match_length = match read_length((token & 0x0f) as u32, &mut src_index) {
+            Some(l) => l as usize,
+            None => break
+        };
+        if match_length == 0 {
+            continue;
         }
-        
+        // Matches cannot start before the data or grow it past its decoded size.
+        if offset == 0 || offset > dest.len() || dest.len() + match_length > size {
+            break;
+        }
+        let mut match_index = dest.len() - offset;
+
         for _ in 0..match_length {
             dest.push(dest[match_index]);
             match_index += 1;
@@ -197,4 +222,4 @@ pub fn decompress_lz4s(src: &Vec<u8>, size: usize) -> Vec<u8> {
     }
 
     return dest;
-}
\ No newline at end of file
+}
@@ -1,8 +1,18 @@
-use crate::errors::CompressionError;
+use std::fmt;
 
+use crate::{bytes::Bytes, errors::CompressionError};
+
+pub mod deflate;
+pub mod gzip;
 pub mod lz4s;
+pub mod zstd;
+
+/// The fastest compression level.
+pub const MIN_COMPRESSION_LEVEL: u32 = 1;
+/// The compression level giving the smallest output, used by default.
+pub const MAX_COMPRESSION_LEVEL: u32 = 9;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CompressionType {
     None,
     LZ4S
@@ -24,17 +34,112 @@ impl CompressionType {
         }
     }
 
+    /// Compresses data with the default (best) compression level.
+    /// * `source` - data to compress
     pub fn compress(&self, source: Vec<u8>) -> Vec<u8> {
+        return self.compress_with_level(source, MAX_COMPRESSION_LEVEL);
+    }
+
+    /// Compresses data with the given compression level.
+    /// Levels go from `MIN_COMPRESSION_LEVEL` (fastest) to `MAX_COMPRESSION_LEVEL` (smallest output)
+    /// and are clamped to that range. Schemes without levels ignore it.
+    /// * `source` - data to compress
+    /// * `level` - compression level
+    pub fn compress_with_level(&self, source: Vec<u8>, level: u32) -> Vec<u8> {
         return match self {
-            CompressionType::LZ4S => lz4s::compress_lz4s(&source),
+            CompressionType::LZ4S => lz4s::compress_lz4s(&source, Self::search_step(level)),
             CompressionType::None => source
         }
     }
 
-    pub fn decompress(&self, source: &Vec<u8>, size: usize) -> Vec<u8> {
+    /// Same as `compress_with_level`, for data that is shared. Data that is not compressed
+    /// stays shared instead of being copied.
+    /// * `source` - data to compress
+    /// * `level` - compression level
+    pub fn compress_shared(&self, source: &Bytes, level: u32) -> Bytes {
+        return match self {
+            CompressionType::LZ4S => Bytes::from(lz4s::compress_lz4s(source, Self::search_step(level))),
+            CompressionType::None => source.clone()
+        }
+    }
+
+    /// Returns how far LZ4S moves on after a failed match at a compression level. The highest
+    /// level checks every position for a match, each level below skips one more byte.
+    /// * `level` - compression level, clamped to the supported range
+    fn search_step(level: u32) -> usize {
+        let level = level.clamp(MIN_COMPRESSION_LEVEL, MAX_COMPRESSION_LEVEL);
+        return (MAX_COMPRESSION_LEVEL - level + 1) as usize;
+    }
+
+    pub fn decompress(&self, source: &[u8], size: usize) -> Vec<u8> {
         return match self {
             CompressionType::LZ4S => lz4s::decompress_lz4s(&source, size),
             CompressionType::None => source.to_vec()
         }
     }
-}
\ No newline at end of file
+}
+/// Compression of a whole input file, which is undone before its voxels are read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileCompression {
+    None,
+    /// `.gz` files
+    Gzip,
+    /// `.zst` files
+    Zstd
+}
+
+impl FileCompression {
+    /// Returns the compression of a file from its extension, `.gz` or `.zst`, such as `head.raw.gz`.
+    /// The query and fragment of URLs are left out.
+    /// * `path` - path or URL of the file
+    pub fn from_path(path: &str) -> Self {
+        let path = match path.contains("://") {
+            true => path.split(['?', '#']).next().unwrap_or(path),
+            false => path
+        };
+        let path = path.to_lowercase();
+        if path.ends_with(".gz") {
+            return FileCompression::Gzip;
+        }
+        if path.ends_with(".zst") {
+            return FileCompression::Zstd;
+        }
+        return FileCompression::None;
+    }
+
+    /// Returns the path without the extension of the compression, `head.raw` for `head.raw.gz`.
+    /// * `path` - path of the file
+    pub fn strip_extension<'a>(&self, path: &'a str) -> &'a str {
+        let extension = match self {
+            FileCompression::None => return path,
+            FileCompression::Gzip => ".gz",
+            FileCompression::Zstd => ".zst"
+        };
+        let stem = path.len().saturating_sub(extension.len());
+        return match path.get(stem..) {
+            Some(end) if end.eq_ignore_ascii_case(extension) => &path[..stem],
+            _ => path
+        };
+    }
+
+    /// Decompresses the contents of a file.
+    /// * `data` - the contents
+    pub fn decompress(&self, data: Vec<u8>) -> Result<Vec<u8>, CompressionError> {
+        return match self {
+            FileCompression::None => Ok(data),
+            FileCompression::Gzip => gzip::decompress(&data),
+            FileCompression::Zstd => zstd::decompress(&data)
+        };
+    }
+}
+
+impl fmt::Display for FileCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FileCompression::None => "none",
+            FileCompression::Gzip => "gzip",
+            FileCompression::Zstd => "zstd"
+        };
+        return write!(f, "{}", name);
+    }
+}
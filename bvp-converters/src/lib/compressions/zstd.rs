@@ -0,0 +1,739 @@
+//! Zstandard (RFC 8878) decompression, for raw volumes that are shipped as `.zst` files.
+//!
+//! Only the decoder is implemented, as in the RFC, without the speed tricks of the reference
+//! implementation: FSE and Huffman tables are plain arrays indexed by the state, and bits are read
+//! from a position in the stream instead of a refilled container. Frames that need a dictionary are
+//! not supported, since raw volumes are compressed without one.
+
+use xxhash_rust::xxh64;
+
+use crate::errors::CompressionError;
+
+const FRAME_MAGIC: u32 = 0xfd2f_b528;
+/// Skippable frames have magic numbers from this one to `SKIPPABLE_MAGIC | 0xf`.
+const SKIPPABLE_MAGIC: u32 = 0x184d_2a50;
+/// Largest size of the decompressed data of a block.
+const MAX_BLOCK_SIZE: usize = 128 * 1024;
+
+/// Largest accuracy logs of the FSE tables.
+const MAX_LITERAL_LENGTH_LOG: u32 = 9;
+const MAX_MATCH_LENGTH_LOG: u32 = 9;
+const MAX_OFFSET_LOG: u32 = 8;
+const MAX_WEIGHT_LOG: u32 = 6;
+/// Longest Huffman code of literals, in bits.
+const MAX_HUFFMAN_BITS: u32 = 11;
+
+/// Distributions of the predefined FSE tables, with -1 for symbols of "less than 1" probability.
+const LITERAL_LENGTH_DEFAULT: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1
+];
+const MATCH_LENGTH_DEFAULT: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1,
+    -1, -1, -1, -1, -1
+];
+const OFFSET_DEFAULT: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1
+];
+
+/// Baselines and numbers of extra bits of the literal length codes from 16 on, below which the code is the length.
+const LITERAL_LENGTH_CODES: [(u32, u32); 20] = [
+    (16, 1), (18, 1), (20, 1), (22, 1), (24, 2), (28, 2), (32, 3), (40, 3), (48, 4), (64, 6),
+    (128, 7), (256, 8), (512, 9), (1024, 10), (2048, 11), (4096, 12), (8192, 13), (16384, 14), (32768, 15), (65536, 16)
+];
+/// Baselines and numbers of extra bits of the match length codes from 32 on, below which the length is the code plus 3.
+const MATCH_LENGTH_CODES: [(u32, u32); 21] = [
+    (35, 1), (37, 1), (39, 1), (41, 1), (43, 2), (47, 2), (51, 3), (59, 3), (67, 4), (83, 4), (99, 5),
+    (131, 7), (259, 8), (515, 9), (1027, 10), (2051, 11), (4099, 12), (8195, 13), (16387, 14), (32771, 15), (65539, 16)
+];
+
+fn corrupt(message: &str) -> CompressionError {
+    return CompressionError::CorruptData(format!("zstd: {}", message));
+}
+
+/// Returns the position of the highest set bit of a value that is not 0.
+fn highest_bit(value: u32) -> u32 {
+    return 31 - value.leading_zeros();
+}
+
+/// Reads bytes of a frame from the start.
+struct ByteReader<'a> {
+    data: &'a [u8],
+    position: usize
+}
+
+impl<'a> ByteReader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], CompressionError> {
+        let bytes = match self.data.get(self.position..self.position + count) {
+            Some(b) => b,
+            None => return Err(corrupt("the data ends early"))
+        };
+        self.position += count;
+        return Ok(bytes);
+    }
+
+    /// Reads a little endian number of up to 8 bytes.
+    fn number(&mut self, count: usize) -> Result<u64, CompressionError> {
+        let bytes = self.bytes(count)?;
+        return Ok(bytes.iter().rev().fold(0, |value, b| (value << 8) | *b as u64));
+    }
+}
+
+/// Reads the bits of a stream that is written forwards, lowest bit of each byte first, as FSE table descriptions are.
+struct ForwardBits<'a> {
+    data: &'a [u8],
+    position: usize
+}
+
+impl<'a> ForwardBits<'a> {
+    /// Returns the next `count` bits without reading them. Bits after the end of the data are 0.
+    fn peek(&self, count: u32) -> u32 {
+        let mut value = 0u64;
+        for i in 0..5 {
+            let byte = self.data.get(self.position / 8 + i).copied().unwrap_or(0);
+            value |= (byte as u64) << (8 * i);
+        }
+        return ((value >> (self.position % 8)) & ((1u64 << count) - 1)) as u32;
+    }
+
+    fn read(&mut self, count: u32) -> Result<u32, CompressionError> {
+        if self.position + count as usize > self.data.len() * 8 {
+            return Err(corrupt("an FSE table description ends early"));
+        }
+        let value = self.peek(count);
+        self.position += count as usize;
+        return Ok(value);
+    }
+}
+
+/// Reads the bits of a stream that is written backwards, as Huffman and FSE coded streams are.
+/// The last byte ends with a 1 bit above the first bit of the stream, and bits are read from the
+/// highest one down. Reading past the start gives zeros, which is how the streams end.
+struct BackwardBits<'a> {
+    data: &'a [u8],
+    /// Number of bits that are left to be read
+    remaining: i64
+}
+
+impl<'a> BackwardBits<'a> {
+    fn new(data: &'a [u8]) -> Result<Self, CompressionError> {
+        let last = match data.last() {
+            Some(b) if *b != 0 => *b,
+            _ => return Err(corrupt("a bitstream does not end with a 1 bit"))
+        };
+        let remaining = data.len() as i64 * 8 - 8 + highest_bit(last as u32) as i64;
+        return Ok(Self { data, remaining });
+    }
+
+    /// Returns the next `count` bits, at most 32, without reading them.
+    fn peek(&self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        let start = self.remaining - count as i64;
+        if start < 0 {
+            // Only the bits left are read, followed by zeros.
+            let available = self.remaining.max(0) as u32;
+            let bits = self.bits_at(0, available);
+            return bits << (count - available);
+        }
+        return self.bits_at(start as usize, count);
+    }
+
+    /// Returns `count` bits from a bit position, the lowest bit of the stream being 0.
+    fn bits_at(&self, start: usize, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        let mut value = 0u64;
+        for i in 0..5 {
+            let byte = self.data.get(start / 8 + i).copied().unwrap_or(0);
+            value |= (byte as u64) << (8 * i);
+        }
+        return ((value >> (start % 8)) & ((1u64 << count) - 1)) as u32;
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let value = self.peek(count);
+        self.remaining -= count as i64;
+        return value;
+    }
+
+    /// Whether more bits were read than the stream has.
+    fn overflowed(&self) -> bool {
+        return self.remaining < 0;
+    }
+}
+
+/// An FSE decoding table, indexed by the state.
+#[derive(Clone)]
+struct FseTable {
+    accuracy_log: u32,
+    /// Symbol, number of bits to read and the state they are added to, for every state
+    entries: Vec<(u8, u32, u32)>
+}
+
+impl FseTable {
+    /// Builds the table of a distribution, as in section 4.1.1 of RFC 8878.
+    /// * `counts` - normalized count of every symbol, -1 for symbols with a probability below 1
+    /// * `accuracy_log` - log of the table size
+    fn new(counts: &[i16], accuracy_log: u32) -> Result<Self, CompressionError> {
+        let size = 1usize << accuracy_log;
+        let mut symbols = vec![0u8; size];
+        // Symbols with a probability below 1 take one state each at the end of the table.
+        let mut high = size;
+        for (symbol, count) in counts.iter().enumerate() {
+            if *count == -1 {
+                if high == 0 {
+                    return Err(corrupt("an FSE distribution has too many symbols"));
+                }
+                high -= 1;
+                symbols[high] = symbol as u8;
+            }
+        }
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mask = size - 1;
+        let mut position = 0;
+        for (symbol, count) in counts.iter().enumerate() {
+            for _ in 0..(*count).max(0) {
+                symbols[position] = symbol as u8;
+                position = (position + step) & mask;
+                while position >= high {
+                    position = (position + step) & mask;
+                }
+            }
+        }
+        if position != 0 {
+            return Err(corrupt("an FSE distribution does not fill its table"));
+        }
+
+        let mut next: Vec<u32> = counts.iter().map(|c| if *c == -1 { 1 } else { (*c).max(0) as u32 }).collect();
+        let mut entries = Vec::with_capacity(size);
+        for symbol in symbols {
+            let state = next[symbol as usize];
+            next[symbol as usize] += 1;
+            let bits = accuracy_log - highest_bit(state);
+            let baseline = (state << bits) - size as u32;
+            entries.push((symbol, bits, baseline));
+        }
+        return Ok(Self { accuracy_log, entries });
+    }
+
+    /// A table that always gives the same symbol, without reading bits.
+    fn rle(symbol: u8) -> Self {
+        return Self { accuracy_log: 0, entries: vec![(symbol, 0, 0)] };
+    }
+
+    /// Reads the description of a table at the start of the data, and returns the table and the size of its description.
+    /// * `data` - the data
+    /// * `max_symbol` - the largest symbol the table can have
+    /// * `max_log` - the largest accuracy log the table can have
+    fn read(data: &[u8], max_symbol: usize, max_log: u32) -> Result<(Self, usize), CompressionError> {
+        let mut bits = ForwardBits { data, position: 0 };
+        let accuracy_log = bits.read(4)? + 5;
+        if accuracy_log > max_log {
+            return Err(corrupt("an FSE table is too large"));
+        }
+        let mut counts: Vec<i16> = Vec::new();
+        let mut remaining = (1i32 << accuracy_log) + 1;
+        let mut threshold = 1i32 << accuracy_log;
+        let mut bit_count = accuracy_log + 1;
+        let mut previous_zero = false;
+        while remaining > 1 {
+            if previous_zero {
+                // A count of 0 is followed by the number of further zeros, in 2 bit steps.
+                loop {
+                    let repeat = bits.read(2)?;
+                    counts.extend(std::iter::repeat(0).take(repeat as usize));
+                    if repeat != 3 {
+                        break;
+                    }
+                }
+            }
+            if counts.len() > max_symbol {
+                return Err(corrupt("an FSE table has too many symbols"));
+            }
+            let max = (2 * threshold - 1) - remaining;
+            let low = bits.peek(bit_count - 1) as i32;
+            let mut value = if low < max {
+                bits.read(bit_count - 1)? as i32
+            } else {
+                let value = bits.read(bit_count)? as i32;
+                if value >= threshold { value - max } else { value }
+            };
+            value -= 1;
+            remaining -= value.abs();
+            counts.push(value as i16);
+            previous_zero = value == 0;
+            while remaining < threshold {
+                bit_count -= 1;
+                threshold >>= 1;
+            }
+        }
+        if remaining != 1 || counts.len() > max_symbol + 1 {
+            return Err(corrupt("an FSE table description is invalid"));
+        }
+        let table = Self::new(&counts, accuracy_log)?;
+        return Ok((table, (bits.position + 7) / 8));
+    }
+
+    fn initial_state(&self, bits: &mut BackwardBits) -> usize {
+        return bits.read(self.accuracy_log) as usize;
+    }
+
+    fn symbol(&self, state: usize) -> u8 {
+        return self.entries[state].0;
+    }
+
+    fn next_state(&self, state: usize, bits: &mut BackwardBits) -> usize {
+        let (_, count, baseline) = self.entries[state];
+        return (baseline + bits.read(count)) as usize;
+    }
+}
+
+/// A Huffman decoding table of literals, indexed by the next `max_bits` bits of a stream.
+#[derive(Clone)]
+struct HuffmanTable {
+    max_bits: u32,
+    /// Symbol and length of its code, for every value of the next bits
+    entries: Vec<(u8, u32)>
+}
+
+impl HuffmanTable {
+    /// Reads the description of a table at the start of the data, and returns the table and the size of its description.
+    fn read(data: &[u8]) -> Result<(Self, usize), CompressionError> {
+        let header = match data.first() {
+            Some(h) => *h as usize,
+            None => return Err(corrupt("a Huffman table description is missing"))
+        };
+        let (mut weights, size) = if header >= 128 {
+            // Weights of 4 bits each, the first in the high bits of a byte.
+            let count = header - 127;
+            let size = 1 + (count + 1) / 2;
+            let bytes = match data.get(1..size) {
+                Some(b) => b,
+                None => return Err(corrupt("a Huffman table description ends early"))
+            };
+            let weights: Vec<u8> = (0..count).map(|i| if i % 2 == 0 { bytes[i / 2] >> 4 } else { bytes[i / 2] & 0xf }).collect();
+            (weights, size)
+        } else {
+            let size = 1 + header;
+            let compressed = match data.get(1..size) {
+                Some(c) => c,
+                None => return Err(corrupt("a Huffman table description ends early"))
+            };
+            (fse_weights(compressed)?, size)
+        };
+
+        // The weight of the last symbol is left out, it fills the code up to a power of two.
+        let mut sum = 0u32;
+        for weight in &weights {
+            if *weight as u32 > MAX_HUFFMAN_BITS {
+                return Err(corrupt("a Huffman weight is too large"));
+            }
+            if *weight > 0 {
+                sum += 1 << (weight - 1);
+            }
+        }
+        if sum == 0 {
+            return Err(corrupt("a Huffman table has no symbols"));
+        }
+        let max_bits = highest_bit(sum) + 1;
+        let rest = (1u32 << max_bits) - sum;
+        if max_bits > MAX_HUFFMAN_BITS || !rest.is_power_of_two() || weights.len() > 255 {
+            return Err(corrupt("the Huffman weights do not make a code"));
+        }
+        weights.push((highest_bit(rest) + 1) as u8);
+
+        // Codes are assigned from the lowest weights, which have the longest codes, and by symbol.
+        let mut entries = Vec::with_capacity(1 << max_bits);
+        for weight in 1..=max_bits as u8 {
+            let length = max_bits + 1 - weight as u32;
+            for (symbol, _) in weights.iter().enumerate().filter(|(_, w)| **w == weight) {
+                let count = 1usize << (weight - 1);
+                entries.extend(std::iter::repeat((symbol as u8, length)).take(count));
+            }
+        }
+        return Ok((Self { max_bits, entries }, size));
+    }
+
+    /// Decodes a stream of literals.
+    /// * `data` - the stream
+    /// * `count` - number of literals
+    /// * `output` - the output
+    fn decode(&self, data: &[u8], count: usize, output: &mut Vec<u8>) -> Result<(), CompressionError> {
+        let mut bits = BackwardBits::new(data)?;
+        for _ in 0..count {
+            let (symbol, length) = self.entries[bits.peek(self.max_bits) as usize];
+            bits.read(length);
+            output.push(symbol);
+        }
+        if bits.remaining != 0 {
+            return Err(corrupt("a Huffman stream does not end with its literals"));
+        }
+        return Ok(());
+    }
+}
+
+/// Decodes the FSE compressed weights of a Huffman table, with two states taking turns.
+/// * `data` - the table description and the stream
+fn fse_weights(data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    let (table, size) = FseTable::read(data, 255, MAX_WEIGHT_LOG)?;
+    let mut bits = BackwardBits::new(&data[size..])?;
+    let mut states = [table.initial_state(&mut bits), table.initial_state(&mut bits)];
+    let mut weights = Vec::new();
+    // When a state reads past the start of the stream, the other one gives the last weight.
+    for turn in 0.. {
+        if weights.len() > 255 {
+            return Err(corrupt("too many Huffman weights"));
+        }
+        let current = turn % 2;
+        weights.push(table.symbol(states[current]));
+        states[current] = table.next_state(states[current], &mut bits);
+        if bits.overflowed() {
+            weights.push(table.symbol(states[1 - current]));
+            break;
+        }
+    }
+    return Ok(weights);
+}
+
+/// Tables that later blocks of a frame can repeat.
+#[derive(Default)]
+struct FrameState {
+    huffman: Option<HuffmanTable>,
+    literal_lengths: Option<FseTable>,
+    offsets: Option<FseTable>,
+    match_lengths: Option<FseTable>,
+    /// The last three offsets, the most recent first
+    repeat_offsets: [usize; 3]
+}
+
+/// Decodes the literals section of a compressed block, and returns the literals and the size of the section.
+/// * `data` - the block, from the literals section on
+/// * `state` - tables of the previous blocks
+fn literals_section(data: &[u8], state: &mut FrameState) -> Result<(Vec<u8>, usize), CompressionError> {
+    let mut reader = ByteReader { data, position: 0 };
+    let first = reader.number(1)? as usize;
+    let block_type = first & 3;
+    let size_format = (first >> 2) & 3;
+    if block_type < 2 {
+        // Raw or RLE literals, with the regenerated size in 5, 12 or 20 bits.
+        let size = match size_format {
+            0 | 2 => first >> 3,
+            1 => (first >> 4) + ((reader.number(1)? as usize) << 4),
+            _ => (first >> 4) + ((reader.number(2)? as usize) << 4)
+        };
+        if size > MAX_BLOCK_SIZE {
+            return Err(corrupt("a block has too many literals"));
+        }
+        let literals = match block_type {
+            0 => reader.bytes(size)?.to_vec(),
+            _ => vec![reader.bytes(1)?[0]; size]
+        };
+        return Ok((literals, reader.position));
+    }
+
+    let (header_size, size_bits, streams) = match size_format {
+        0 => (3, 10, 1),
+        1 => (3, 10, 4),
+        2 => (4, 14, 4),
+        _ => (5, 18, 4)
+    };
+    reader.position = 0;
+    let header = reader.number(header_size)? as usize;
+    let mask = (1 << size_bits) - 1;
+    let regenerated_size = (header >> 4) & mask;
+    let compressed_size = (header >> (4 + size_bits)) & mask;
+    if regenerated_size > MAX_BLOCK_SIZE {
+        return Err(corrupt("a block has too many literals"));
+    }
+    let mut compressed = reader.bytes(compressed_size)?;
+    if block_type == 2 {
+        let (table, size) = HuffmanTable::read(compressed)?;
+        state.huffman = Some(table);
+        compressed = &compressed[size..];
+    }
+    let table = match &state.huffman {
+        Some(t) => t,
+        None => return Err(corrupt("literals repeat a Huffman table of no previous block"))
+    };
+
+    let mut literals = Vec::with_capacity(regenerated_size);
+    if streams == 1 {
+        table.decode(compressed, regenerated_size, &mut literals)?;
+    } else {
+        // Three stream sizes come first, the fourth stream is the rest.
+        if compressed.len() < 6 {
+            return Err(corrupt("the jump table of the literals is missing"));
+        }
+        let mut ends = [0usize; 4];
+        let mut end = 6;
+        for i in 0..3 {
+            end += u16::from_le_bytes([compressed[2 * i], compressed[2 * i + 1]]) as usize;
+            ends[i] = end;
+        }
+        ends[3] = compressed.len();
+        if end > compressed.len() {
+            return Err(corrupt("the literal streams are larger than the literals"));
+        }
+        let stream_size = (regenerated_size + 3) / 4;
+        let mut start = 6;
+        for (i, end) in ends.iter().enumerate() {
+            let count = if i < 3 { stream_size } else { regenerated_size.saturating_sub(3 * stream_size) };
+            table.decode(&compressed[start..*end], count, &mut literals)?;
+            start = *end;
+        }
+        if literals.len() != regenerated_size {
+            return Err(corrupt("the literal streams have the wrong size"));
+        }
+    }
+    return Ok((literals, reader.position));
+}
+
+/// Reads the table of one kind of sequence codes for its compression mode.
+/// * `reader` - the block, at the table
+/// * `mode` - the compression mode
+/// * `previous` - the table of the previous block, which mode 3 repeats
+/// * `default` - the predefined distribution and its accuracy log
+/// * `max_symbol`, `max_log` - the largest code and accuracy log
+fn sequence_table(reader: &mut ByteReader, mode: u8, previous: &mut Option<FseTable>, default: (&[i16], u32),
+    max_symbol: usize, max_log: u32) -> Result<FseTable, CompressionError>
+{
+    let table = match mode {
+        0 => FseTable::new(default.0, default.1)?,
+        1 => {
+            let symbol = reader.bytes(1)?[0];
+            if symbol as usize > max_symbol {
+                return Err(corrupt("a sequence code is out of range"));
+            }
+            FseTable::rle(symbol)
+        },
+        2 => {
+            let (table, size) = FseTable::read(&reader.data[reader.position..], max_symbol, max_log)?;
+            reader.position += size;
+            table
+        },
+        _ => match previous {
+            Some(t) => t.clone(),
+            None => return Err(corrupt("sequences repeat a table of no previous block"))
+        }
+    };
+    *previous = Some(table.clone());
+    return Ok(table);
+}
+
+/// Returns the offset a sequence copies from and updates the repeated offsets, as in section 3.1.1.5 of RFC 8878.
+/// * `value` - the decoded offset value
+/// * `literal_length` - the number of literals of the sequence, the repeated offsets shift when it is 0
+/// * `repeat` - the last three offsets
+fn sequence_offset(value: usize, literal_length: usize, repeat: &mut [usize; 3]) -> Result<usize, CompressionError> {
+    if value > 3 {
+        let offset = value - 3;
+        *repeat = [offset, repeat[0], repeat[1]];
+        return Ok(offset);
+    }
+    let index = value - 1 + if literal_length == 0 { 1 } else { 0 };
+    let offset = match index {
+        0 => return Ok(repeat[0]),
+        1 | 2 => repeat[index],
+        _ => match repeat[0].checked_sub(1) {
+            Some(o) if o > 0 => o,
+            _ => return Err(corrupt("a repeated offset is 0"))
+        }
+    };
+    *repeat = match index {
+        1 => [offset, repeat[0], repeat[2]],
+        _ => [offset, repeat[0], repeat[1]]
+    };
+    return Ok(offset);
+}
+
+/// Decodes a compressed block and appends its data to the output.
+/// * `data` - the block
+/// * `state` - tables and offsets of the previous blocks
+/// * `output` - the output
+/// * `frame_start` - where the frame starts in the output, offsets cannot reach before it
+fn compressed_block(data: &[u8], state: &mut FrameState, output: &mut Vec<u8>, frame_start: usize) -> Result<(), CompressionError> {
+    let (literals, literals_size) = literals_section(data, state)?;
+    let mut reader = ByteReader { data, position: literals_size };
+    let first = reader.number(1)? as usize;
+    let sequence_count = match first {
+        0 => {
+            output.extend_from_slice(&literals);
+            return Ok(());
+        },
+        1..=127 => first,
+        128..=254 => ((first - 128) << 8) + reader.number(1)? as usize,
+        _ => reader.number(2)? as usize + 0x7f00
+    };
+    let modes = reader.number(1)? as u8;
+    if modes & 3 != 0 {
+        return Err(corrupt("reserved bits of the sequence modes are set"));
+    }
+    let literal_lengths = sequence_table(&mut reader, modes >> 6, &mut state.literal_lengths,
+        (&LITERAL_LENGTH_DEFAULT, 6), 35, MAX_LITERAL_LENGTH_LOG)?;
+    let offsets = sequence_table(&mut reader, (modes >> 4) & 3, &mut state.offsets,
+        (&OFFSET_DEFAULT, 5), 31, MAX_OFFSET_LOG)?;
+    let match_lengths = sequence_table(&mut reader, (modes >> 2) & 3, &mut state.match_lengths,
+        (&MATCH_LENGTH_DEFAULT, 6), 52, MAX_MATCH_LENGTH_LOG)?;
+
+    let mut bits = BackwardBits::new(&data[reader.position..])?;
+    let mut literal_length_state = literal_lengths.initial_state(&mut bits);
+    let mut offset_state = offsets.initial_state(&mut bits);
+    let mut match_length_state = match_lengths.initial_state(&mut bits);
+    let mut literal_position = 0;
+    for i in 0..sequence_count {
+        let offset_code = offsets.symbol(offset_state) as u32;
+        let match_code = match_lengths.symbol(match_length_state) as usize;
+        let literal_code = literal_lengths.symbol(literal_length_state) as usize;
+        // Extra bits come in the order offset, match length, literal length.
+        let offset_value = (1usize << offset_code) + bits.read(offset_code) as usize;
+        let match_length = match match_code {
+            0..=31 => match_code + 3,
+            _ => {
+                let (baseline, extra) = MATCH_LENGTH_CODES[match_code - 32];
+                (baseline + bits.read(extra)) as usize
+            }
+        };
+        let literal_length = match literal_code {
+            0..=15 => literal_code,
+            _ => {
+                let (baseline, extra) = LITERAL_LENGTH_CODES[literal_code - 16];
+                (baseline + bits.read(extra)) as usize
+            }
+        };
+        let offset = sequence_offset(offset_value, literal_length, &mut state.repeat_offsets)?;
+
+        let sequence_literals = match literals.get(literal_position..literal_position + literal_length) {
+            Some(l) => l,
+            None => return Err(corrupt("a sequence has more literals than the block"))
+        };
+        output.extend_from_slice(sequence_literals);
+        literal_position += literal_length;
+        if offset > output.len() - frame_start {
+            return Err(corrupt("a match starts before the frame"));
+        }
+        // Matches can overlap the data they copy, so they are copied one byte at a time when they do.
+        let start = output.len() - offset;
+        if offset >= match_length {
+            output.extend_from_within(start..start + match_length);
+        } else {
+            for j in 0..match_length {
+                output.push(output[start + j]);
+            }
+        }
+
+        if i + 1 < sequence_count {
+            literal_length_state = literal_lengths.next_state(literal_length_state, &mut bits);
+            match_length_state = match_lengths.next_state(match_length_state, &mut bits);
+            offset_state = offsets.next_state(offset_state, &mut bits);
+        }
+    }
+    if bits.remaining != 0 {
+        return Err(corrupt("the sequences do not end with their bitstream"));
+    }
+    output.extend_from_slice(&literals[literal_position..]);
+    return Ok(());
+}
+
+/// Decodes a frame and appends its data to the output, returning the size of the frame.
+/// * `data` - the frame, after its magic number
+/// * `output` - the output
+fn frame(data: &[u8], output: &mut Vec<u8>) -> Result<usize, CompressionError> {
+    let mut reader = ByteReader { data, position: 0 };
+    let descriptor = reader.number(1)? as u8;
+    let content_size_flag = descriptor >> 6;
+    let single_segment = descriptor & 0x20 != 0;
+    let has_checksum = descriptor & 0x04 != 0;
+    let dictionary_flag = descriptor & 3;
+    if descriptor & 0x08 != 0 {
+        return Err(corrupt("a reserved bit of the frame header is set"));
+    }
+    if !single_segment {
+        // The window size does not matter, all of the data is kept.
+        reader.bytes(1)?;
+    }
+    let dictionary_id = match dictionary_flag {
+        0 => 0,
+        1 => reader.number(1)?,
+        2 => reader.number(2)?,
+        _ => reader.number(4)?
+    };
+    if dictionary_id != 0 {
+        return Err(CompressionError::Unsupported("zstd frames with a dictionary".to_string()));
+    }
+    let content_size = match (content_size_flag, single_segment) {
+        (0, false) => None,
+        (0, true) => Some(reader.number(1)?),
+        (1, _) => Some(reader.number(2)? + 256),
+        (2, _) => Some(reader.number(4)?),
+        _ => Some(reader.number(8)?)
+    };
+    // Only a hint, a wrong size in the header is found at the end of the frame.
+    if let Some(size) = content_size {
+        let _ = output.try_reserve(size as usize);
+    }
+
+    let frame_start = output.len();
+    let mut state = FrameState { repeat_offsets: [1, 4, 8], ..Default::default() };
+    loop {
+        let header = reader.number(3)? as usize;
+        let last = header & 1 != 0;
+        let size = header >> 3;
+        match (header >> 1) & 3 {
+            0 => output.extend_from_slice(reader.bytes(size)?),
+            1 => {
+                let byte = reader.bytes(1)?[0];
+                output.resize(output.len() + size, byte);
+            },
+            2 => {
+                if size > MAX_BLOCK_SIZE {
+                    return Err(corrupt("a block is too large"));
+                }
+                compressed_block(reader.bytes(size)?, &mut state, output, frame_start)?;
+            },
+            _ => return Err(corrupt("a block has the reserved type"))
+        }
+        if last {
+            break;
+        }
+    }
+
+    let frame_data = &output[frame_start..];
+    if content_size.is_some_and(|size| size != frame_data.len() as u64) {
+        return Err(corrupt("a frame does not have the size in its header"));
+    }
+    if has_checksum {
+        let checksum = reader.number(4)? as u32;
+        if checksum != xxh64::xxh64(frame_data, 0) as u32 {
+            return Err(corrupt("the checksum of a frame does not match its data"));
+        }
+    }
+    return Ok(reader.position);
+}
+
+/// Decompresses Zstandard data, which can hold many frames. Skippable frames are left out.
+/// * `source` - the compressed data
+pub fn decompress(source: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    let mut output = Vec::new();
+    let mut position = 0;
+    while position < source.len() {
+        let mut reader = ByteReader { data: source, position };
+        let magic = reader.number(4)? as u32;
+        if magic & !0xf == SKIPPABLE_MAGIC {
+            let size = reader.number(4)? as usize;
+            reader.bytes(size)?;
+            position = reader.position;
+        } else if magic == FRAME_MAGIC {
+            position = reader.position + frame(&source[reader.position..], &mut output)?;
+        } else {
+            return Err(corrupt("the data is not a frame"));
+        }
+    }
+    return Ok(output);
+}
@@ -0,0 +1,45 @@
+//! Text formats of config files. TOML and YAML files are read into the same JSON values as JSON files,
+//! so the tools validate and use every config the same way, whatever format it was written in.
+//! Manifest types in a config, such as its `format`, are read from these values as from a manifest, see `mapping`.
+
+use std::path::Path;
+
+use tinyjson::JsonValue;
+
+use crate::errors::ConfigFormatError;
+
+pub mod toml;
+pub mod yaml;
+
+/// Formats of config files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml
+}
+
+impl ConfigFormat {
+    /// Returns the format of a config file from its extension, `.toml`, `.yaml` or `.yml`.
+    /// Files with any other extension are JSON.
+    /// * `path` - the config file
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        return match extension.as_deref() {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Json
+        };
+    }
+}
+
+/// Parses the text of a config file.
+/// * `text` - contents of the file
+/// * `format` - format of the file
+pub fn parse(text: &str, format: ConfigFormat) -> Result<JsonValue, ConfigFormatError> {
+    return match format {
+        ConfigFormat::Json => text.parse().map_err(|e: tinyjson::JsonParseError| ConfigFormatError::Json(e.to_string())),
+        ConfigFormat::Toml => toml::parse(text),
+        ConfigFormat::Yaml => yaml::parse(text)
+    };
+}
@@ -0,0 +1,386 @@
+//! Reader of TOML documents. Tables, arrays of tables, inline tables, dotted keys, all string
+//! and number forms are supported. Dates and times are kept as strings, such as `acquisitionTime`
+//! in the config of raw2bvp, since JSON has no type for them.
+
+use std::collections::HashMap;
+
+use tinyjson::JsonValue;
+
+use crate::errors::ConfigFormatError;
+
+/// Parses a TOML document into a JSON object.
+/// * `text` - the document
+pub fn parse(text: &str) -> Result<JsonValue, ConfigFormatError> {
+    let mut parser = Parser { chars: text.chars().collect(), position: 0, line: 1 };
+    let mut root = HashMap::new();
+    // Path of the table of the last header. Arrays on the path stand for their last table.
+    let mut current: Vec<String> = Vec::new();
+    loop {
+        parser.skip_blank_lines();
+        match parser.peek() {
+            None => break,
+            Some('[') => {
+                parser.next();
+                let array = parser.peek() == Some('[');
+                if array {
+                    parser.next();
+                }
+                parser.skip_spaces();
+                let path = parser.key()?;
+                parser.skip_spaces();
+                parser.expect(']')?;
+                if array {
+                    parser.expect(']')?;
+                }
+                let (last, parent) = path.split_last().unwrap();
+                let table = parser.table(&mut root, parent)?;
+                if array {
+                    match table.entry(last.clone()).or_insert_with(|| JsonValue::Array(Vec::new())) {
+                        JsonValue::Array(a) => a.push(JsonValue::Object(HashMap::new())),
+                        _ => return Err(parser.error(format!("`{}` is not an array of tables", path.join("."))))
+                    }
+                } else {
+                    parser.table(table, std::slice::from_ref(last))?;
+                }
+                current = path;
+            },
+            Some(_) => {
+                let path = parser.key()?;
+                parser.skip_spaces();
+                parser.expect('=')?;
+                parser.skip_spaces();
+                let value = parser.value()?;
+                let (last, parent) = path.split_last().unwrap();
+                let full: Vec<String> = current.iter().chain(parent).cloned().collect();
+                let table = parser.table(&mut root, &full)?;
+                if table.insert(last.clone(), value).is_some() {
+                    return Err(parser.error(format!("`{}` is defined twice", path.join("."))));
+                }
+            }
+        }
+        parser.end_of_line()?;
+    }
+    return Ok(JsonValue::Object(root));
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    line: usize
+}
+
+impl Parser {
+    fn error(&self, message: String) -> ConfigFormatError {
+        return ConfigFormatError::Toml(self.line, message);
+    }
+
+    fn peek(&self) -> Option<char> {
+        return self.chars.get(self.position).copied();
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        return Some(c);
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        return text.chars().enumerate().all(|(i, c)| self.chars.get(self.position + i) == Some(&c));
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ConfigFormatError> {
+        return match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(self.error(format!("expected `{}`, found `{}`", expected, c))),
+            None => Err(self.error(format!("expected `{}` at the end of the document", expected)))
+        };
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ') | Some('\t')) {
+            self.next();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.next();
+            }
+        }
+    }
+
+    /// Skips whitespace, comments and line breaks.
+    fn skip_blank_lines(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+            match self.peek() {
+                Some('\n') | Some('\r') => {
+                    self.next();
+                },
+                _ => return
+            }
+        }
+    }
+
+    /// Fails unless only a comment follows on the line.
+    fn end_of_line(&mut self) -> Result<(), ConfigFormatError> {
+        self.skip_spaces();
+        self.skip_comment();
+        if self.peek() == Some('\r') {
+            self.next();
+        }
+        return match self.next() {
+            None | Some('\n') => Ok(()),
+            Some(c) => Err(self.error(format!("unexpected `{}` after a value", c)))
+        };
+    }
+
+    /// Returns the table at a path under `root`, creating missing tables.
+    fn table<'a>(&self, root: &'a mut HashMap<String, JsonValue>, path: &[String]) -> Result<&'a mut HashMap<String, JsonValue>, ConfigFormatError> {
+        let mut table = root;
+        for key in path {
+            let value = table.entry(key.clone()).or_insert_with(|| JsonValue::Object(HashMap::new()));
+            let value = match value {
+                JsonValue::Array(a) => match a.last_mut() {
+                    Some(v) => v,
+                    None => return Err(self.error(format!("`{}` is an empty array", key)))
+                },
+                v => v
+            };
+            table = match value {
+                JsonValue::Object(o) => o,
+                _ => return Err(self.error(format!("`{}` is not a table", key)))
+            };
+        }
+        return Ok(table);
+    }
+
+    /// Parses a key, made of bare or quoted keys separated by dots.
+    fn key(&mut self) -> Result<Vec<String>, ConfigFormatError> {
+        let mut path = Vec::new();
+        loop {
+            let part = match self.peek() {
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                _ => {
+                    let mut part = String::new();
+                    while let Some(c) = self.peek().filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-') {
+                        part.push(c);
+                        self.next();
+                    }
+                    if part.is_empty() {
+                        return Err(self.error("expected a key".to_string()));
+                    }
+                    part
+                }
+            };
+            path.push(part);
+            self.skip_spaces();
+            if self.peek() != Some('.') {
+                return Ok(path);
+            }
+            self.next();
+            self.skip_spaces();
+        }
+    }
+
+    fn value(&mut self) -> Result<JsonValue, ConfigFormatError> {
+        return match self.peek() {
+            Some('"') => Ok(JsonValue::String(self.basic_string()?)),
+            Some('\'') => Ok(JsonValue::String(self.literal_string()?)),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some(_) if self.starts_with("true") => {
+                self.position += 4;
+                Ok(JsonValue::Boolean(true))
+            },
+            Some(_) if self.starts_with("false") => {
+                self.position += 5;
+                Ok(JsonValue::Boolean(false))
+            },
+            Some(_) => self.number_or_date(),
+            None => Err(self.error("expected a value".to_string()))
+        };
+    }
+
+    fn array(&mut self) -> Result<JsonValue, ConfigFormatError> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        loop {
+            self.skip_blank_lines();
+            if self.peek() == Some(']') {
+                self.next();
+                return Ok(JsonValue::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_blank_lines();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(JsonValue::Array(values)),
+                _ => return Err(self.error("expected `,` or `]` in an array".to_string()))
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<JsonValue, ConfigFormatError> {
+        self.expect('{')?;
+        let mut table = HashMap::new();
+        self.skip_spaces();
+        if self.peek() == Some('}') {
+            self.next();
+            return Ok(JsonValue::Object(table));
+        }
+        loop {
+            self.skip_spaces();
+            let path = self.key()?;
+            self.skip_spaces();
+            self.expect('=')?;
+            self.skip_spaces();
+            let value = self.value()?;
+            let (last, parent) = path.split_last().unwrap();
+            if self.table(&mut table, parent)?.insert(last.clone(), value).is_some() {
+                return Err(self.error(format!("`{}` is defined twice", path.join("."))));
+            }
+            self.skip_spaces();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(JsonValue::Object(table)),
+                _ => return Err(self.error("expected `,` or `}` in an inline table".to_string()))
+            }
+        }
+    }
+
+    /// Parses a string in double quotes, or in triple double quotes over several lines.
+    fn basic_string(&mut self) -> Result<String, ConfigFormatError> {
+        let multiline = self.starts_with("\"\"\"");
+        self.position += if multiline { 3 } else { 1 };
+        // A line break right after the opening quotes is not part of the string.
+        if multiline && self.starts_with("\n") {
+            self.next();
+        } else if multiline && self.starts_with("\r\n") {
+            self.position += 1;
+            self.next();
+        }
+        let mut s = String::new();
+        loop {
+            if multiline && self.starts_with("\"\"\"") {
+                self.position += 3;
+                return Ok(s);
+            }
+            match self.next() {
+                Some('"') if !multiline => return Ok(s),
+                Some('\\') => {
+                    if multiline && matches!(self.peek(), Some('\n') | Some('\r') | Some(' ') | Some('\t')) {
+                        // A backslash at the end of a line joins it with the next non-blank text.
+                        while matches!(self.peek(), Some('\n') | Some('\r') | Some(' ') | Some('\t')) {
+                            self.next();
+                        }
+                        continue;
+                    }
+                    s.push(self.escape()?);
+                },
+                Some('\n') if !multiline => return Err(self.error("unterminated string".to_string())),
+                Some(c) => s.push(c),
+                None => return Err(self.error("unterminated string".to_string()))
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, ConfigFormatError> {
+        let c = match self.next() {
+            Some('b') => '\u{8}',
+            Some('t') => '\t',
+            Some('n') => '\n',
+            Some('f') => '\u{c}',
+            Some('r') => '\r',
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some(u @ ('u' | 'U')) => {
+                let length = if u == 'u' { 4 } else { 8 };
+                let digits: String = (0..length).filter_map(|_| self.next()).collect();
+                match u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32) {
+                    Some(c) => c,
+                    None => return Err(self.error(format!("invalid unicode escape `\\{}{}`", u, digits)))
+                }
+            },
+            Some(c) => return Err(self.error(format!("invalid escape `\\{}`", c))),
+            None => return Err(self.error("unterminated string".to_string()))
+        };
+        return Ok(c);
+    }
+
+    /// Parses a string in single quotes, or in triple single quotes over several lines, without escapes.
+    fn literal_string(&mut self) -> Result<String, ConfigFormatError> {
+        let multiline = self.starts_with("'''");
+        self.position += if multiline { 3 } else { 1 };
+        if multiline && self.starts_with("\n") {
+            self.next();
+        }
+        let mut s = String::new();
+        loop {
+            if multiline && self.starts_with("'''") {
+                self.position += 3;
+                return Ok(s);
+            }
+            match self.next() {
+                Some('\'') if !multiline => return Ok(s),
+                Some('\n') if !multiline => return Err(self.error("unterminated string".to_string())),
+                Some(c) => s.push(c),
+                None => return Err(self.error("unterminated string".to_string()))
+            }
+        }
+    }
+
+    fn number_or_date(&mut self) -> Result<JsonValue, ConfigFormatError> {
+        let mut token = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_alphanumeric() || "+-._:".contains(*c)) {
+            token.push(c);
+            self.next();
+        }
+        // A time after a date is separated by a space.
+        if is_date(&token) && self.peek() == Some(' ') && self.chars.get(self.position + 1).map_or(false, |c| c.is_ascii_digit()) {
+            token.push('T');
+            self.next();
+            while let Some(c) = self.peek().filter(|c| c.is_ascii_alphanumeric() || "+-.:".contains(*c)) {
+                token.push(c);
+                self.next();
+            }
+        }
+        if is_date(&token) || token.contains(':') {
+            return Ok(JsonValue::String(token));
+        }
+        let digits = token.replace('_', "");
+        let unsigned = digits.trim_start_matches(['+', '-']);
+        let sign = if digits.starts_with('-') { -1.0 } else { 1.0 };
+        let radix = match unsigned.get(..2) {
+            Some("0x") => Some(16),
+            Some("0o") => Some(8),
+            Some("0b") => Some(2),
+            _ => None
+        };
+        let number = match radix {
+            Some(r) => u64::from_str_radix(&unsigned[2..], r).ok().map(|n| n as f64),
+            None if unsigned == "inf" => Some(f64::INFINITY * sign),
+            None if unsigned == "nan" => Some(f64::NAN),
+            None if !unsigned.is_empty() && unsigned.chars().all(|c| c.is_ascii_digit() || "eE+-.".contains(c)) => digits.parse::<f64>().ok(),
+            None => None
+        };
+        return match number {
+            Some(n) => Ok(JsonValue::Number(n)),
+            None if token.is_empty() => Err(self.error(format!("unexpected `{}`", self.peek().unwrap_or(' ')))),
+            None => Err(self.error(format!("invalid value `{}`", token)))
+        };
+    }
+}
+
+/// Returns true if a token starts with a date, such as `2023-05-17`.
+fn is_date(token: &str) -> bool {
+    let bytes = token.as_bytes();
+    return bytes.len() >= 10 && bytes[4] == b'-' && bytes[7] == b'-'
+        && bytes[..4].iter().chain(&bytes[5..7]).chain(&bytes[8..10]).all(|b| b.is_ascii_digit());
+}
@@ -0,0 +1,526 @@
+//! Reader of YAML documents, for the subset that config files use: block mappings and sequences,
+//! flow collections such as `[64, 64, 64]`, quoted and plain scalars, and literal (`|`) and folded (`>`)
+//! block scalars. Anchors, aliases, tags and several documents in a file are not supported.
+
+use std::collections::HashMap;
+
+use tinyjson::JsonValue;
+
+use crate::errors::ConfigFormatError;
+
+/// Parses a YAML document into a JSON value. An empty document is `null`.
+/// * `text` - the document
+pub fn parse(text: &str) -> Result<JsonValue, ConfigFormatError> {
+    let mut lines = Vec::new();
+    for (i, raw) in text.lines().enumerate() {
+        let raw = raw.strip_suffix('\r').unwrap_or(raw);
+        let content = strip_comment(raw).trim_end();
+        let trimmed = content.trim_start();
+        // Only one document is read, so its markers are skipped.
+        let blank = trimmed.is_empty() || trimmed == "---" || trimmed == "...";
+        let indent = content.len() - trimmed.len();
+        if !blank && content[..indent].contains('\t') {
+            return Err(ConfigFormatError::Yaml(i + 1, "tabs cannot be used for indentation".to_string()));
+        }
+        lines.push(Line { number: i + 1, indent, content: trimmed.to_string(), raw: raw.to_string(), blank });
+    }
+    let mut parser = Parser { lines, index: 0 };
+    parser.skip_blank();
+    let indent = match parser.current() {
+        Some(line) => line.indent,
+        None => return Ok(JsonValue::Null)
+    };
+    let value = parser.node(indent)?;
+    parser.skip_blank();
+    if let Some(line) = parser.current() {
+        return Err(ConfigFormatError::Yaml(line.number, format!("unexpected `{}`", line.content)));
+    }
+    return Ok(value);
+}
+
+struct Line {
+    number: usize,
+    indent: usize,
+    /// The text after the indentation, without comment
+    content: String,
+    /// The whole line, for block scalars
+    raw: String,
+    blank: bool
+}
+
+impl Line {
+    fn is_sequence_item(&self) -> bool {
+        return self.content == "-" || self.content.starts_with("- ");
+    }
+}
+
+/// Returns true if a quote after this character starts a quoted scalar, rather than being part of a plain one.
+fn starts_quote(previous: char) -> bool {
+    return previous.is_whitespace() || "[{,:-".contains(previous);
+}
+
+/// Returns a line without its comment. A comment starts with `#` at the start of the line
+/// or after a space, outside of quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if (c == '"' || c == '\'') && starts_quote(previous) => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => return &line[..i],
+            None => {}
+        }
+        previous = c;
+    }
+    return line;
+}
+
+/// Returns the position of the colon that separates the key of a mapping entry from its value, if any.
+fn mapping_colon(content: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut depth = 0;
+    let mut previous = ' ';
+    let mut chars = content.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None => match c {
+                '"' | '\'' if starts_quote(previous) => quote = Some(c),
+                '[' | '{' => depth += 1,
+                ']' | '}' => depth -= 1,
+                ':' if depth == 0 && chars.peek().map_or(true, |(_, n)| *n == ' ') => return Some(i),
+                _ => {}
+            }
+        }
+        previous = c;
+    }
+    return None;
+}
+
+/// Returns true if the brackets of a flow collection are all closed.
+fn is_balanced(text: &str) -> bool {
+    let mut quote = None;
+    let mut depth = 0;
+    let mut previous = ' ';
+    for c in text.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None => match c {
+                '"' | '\'' if starts_quote(previous) => quote = Some(c),
+                '[' | '{' => depth += 1,
+                ']' | '}' => depth -= 1,
+                _ => {}
+            }
+        }
+        previous = c;
+    }
+    return depth <= 0;
+}
+
+struct Parser {
+    lines: Vec<Line>,
+    index: usize
+}
+
+impl Parser {
+    fn error(&self, message: String) -> ConfigFormatError {
+        let number = self.lines.get(self.index).or(self.lines.last()).map_or(1, |l| l.number);
+        return ConfigFormatError::Yaml(number, message);
+    }
+
+    fn current(&self) -> Option<&Line> {
+        return self.lines.get(self.index);
+    }
+
+    fn skip_blank(&mut self) {
+        while self.current().map_or(false, |l| l.blank) {
+            self.index += 1;
+        }
+    }
+
+    /// Parses the node that starts at the current line, indented by `indent`.
+    fn node(&mut self, indent: usize) -> Result<JsonValue, ConfigFormatError> {
+        self.skip_blank();
+        let line = match self.current() {
+            Some(l) => l,
+            None => return Ok(JsonValue::Null)
+        };
+        if line.is_sequence_item() {
+            return self.sequence(indent);
+        }
+        if mapping_colon(&line.content).is_some() {
+            return self.mapping(indent);
+        }
+        let content = line.content.clone();
+        return self.inline_value(content);
+    }
+
+    fn sequence(&mut self, indent: usize) -> Result<JsonValue, ConfigFormatError> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank();
+            let line = match self.current() {
+                Some(l) if l.indent == indent && l.is_sequence_item() => l,
+                _ => break
+            };
+            let rest = line.content[1..].trim_start().to_string();
+            if rest.is_empty() {
+                self.index += 1;
+                self.skip_blank();
+                let item = match self.current() {
+                    Some(next) if next.indent > indent => {
+                        let next_indent = next.indent;
+                        self.node(next_indent)?
+                    },
+                    _ => JsonValue::Null
+                };
+                items.push(item);
+                continue;
+            }
+            // The rest of the line is a node of its own, as if it started on the next line at its column.
+            let offset = indent + line.content.len() - rest.len();
+            let line = &mut self.lines[self.index];
+            line.indent = offset;
+            line.content = rest;
+            items.push(self.node(offset)?);
+        }
+        return Ok(JsonValue::Array(items));
+    }
+
+    fn mapping(&mut self, indent: usize) -> Result<JsonValue, ConfigFormatError> {
+        let mut entries = HashMap::new();
+        loop {
+            self.skip_blank();
+            let line = match self.current() {
+                Some(l) if l.indent == indent && !l.is_sequence_item() => l,
+                Some(l) if l.indent > indent => return Err(self.error(format!("unexpected indentation of `{}`", l.content))),
+                _ => break
+            };
+            let colon = match mapping_colon(&line.content) {
+                Some(c) => c,
+                None => return Err(self.error(format!("expected `key: value`, found `{}`", line.content)))
+            };
+            let mut flow = Flow { chars: line.content[..colon].chars().collect(), position: 0 };
+            let key = flow.key().map_err(|e| self.error(e))?;
+            let rest = line.content[colon + 1..].trim().to_string();
+            let value = if rest.starts_with('|') || rest.starts_with('>') {
+                self.index += 1;
+                self.block_scalar(indent, &rest)?
+            } else if !rest.is_empty() {
+                self.inline_value(rest)?
+            } else {
+                self.index += 1;
+                self.skip_blank();
+                match self.current() {
+                    // Sequences may be indented as much as the key they belong to.
+                    Some(next) if next.indent > indent || (next.indent == indent && next.is_sequence_item()) => {
+                        let next_indent = next.indent;
+                        self.node(next_indent)?
+                    },
+                    _ => JsonValue::Null
+                }
+            };
+            if entries.insert(key.clone(), value).is_some() {
+                return Err(self.error(format!("`{}` is defined twice", key)));
+            }
+        }
+        return Ok(JsonValue::Object(entries));
+    }
+
+    /// Parses a value written on one line, or a flow collection that continues on the following lines.
+    /// Leaves the current line after the value.
+    fn inline_value(&mut self, mut text: String) -> Result<JsonValue, ConfigFormatError> {
+        let start = self.index;
+        if text.starts_with('[') || text.starts_with('{') {
+            while !is_balanced(&text) {
+                self.index += 1;
+                match self.lines.get(self.index) {
+                    Some(l) if l.blank => continue,
+                    Some(l) => {
+                        text.push(' ');
+                        text.push_str(&l.content);
+                    },
+                    None => {
+                        self.index = start;
+                        return Err(self.error("unterminated flow collection".to_string()));
+                    }
+                }
+            }
+        }
+        let mut flow = Flow { chars: text.chars().collect(), position: 0 };
+        let value = flow.value(false).map_err(|e| self.error(e))?;
+        flow.skip_spaces();
+        if flow.position < flow.chars.len() {
+            let rest: String = flow.chars[flow.position..].iter().collect();
+            return Err(self.error(format!("unexpected `{}` after a value", rest)));
+        }
+        self.index += 1;
+        return Ok(value);
+    }
+
+    /// Parses a literal or folded block scalar that follows a key.
+    /// * `indent` - indentation of the key
+    /// * `header` - the indicator after the key, such as `|` or `>-`
+    fn block_scalar(&mut self, indent: usize, header: &str) -> Result<JsonValue, ConfigFormatError> {
+        let folded = header.starts_with('>');
+        let chomping = header[1..].chars().find(|c| *c == '-' || *c == '+');
+        let mut text_lines: Vec<String> = Vec::new();
+        let mut content_indent = None;
+        while let Some(line) = self.current() {
+            let raw_trimmed = line.raw.trim_start();
+            let raw_indent = line.raw.len() - raw_trimmed.len();
+            if !raw_trimmed.is_empty() && raw_indent <= indent {
+                break;
+            }
+            if raw_trimmed.is_empty() {
+                text_lines.push(String::new());
+            } else {
+                let content = *content_indent.get_or_insert(raw_indent);
+                if raw_indent < content {
+                    return Err(self.error("a block scalar is indented less than its first line".to_string()));
+                }
+                text_lines.push(line.raw[content..].to_string());
+            }
+            self.index += 1;
+        }
+        let content_lines = text_lines.iter().rposition(|l| !l.is_empty()).map_or(0, |i| i + 1);
+        let trailing = text_lines.len() - content_lines;
+        let mut text = if folded {
+            let mut text = String::new();
+            for (i, line) in text_lines[..content_lines].iter().enumerate() {
+                if i > 0 {
+                    // Lines are joined with spaces, and empty lines stand for line breaks.
+                    let previous_empty = text_lines[i - 1].is_empty();
+                    if line.is_empty() {
+                        text.push('\n');
+                    } else if !previous_empty {
+                        text.push(' ');
+                    }
+                }
+                text.push_str(line);
+            }
+            text
+        } else {
+            text_lines[..content_lines].join("\n")
+        };
+        match chomping {
+            Some('-') => {},
+            Some(_) => text.push_str(&"\n".repeat(trailing + 1)),
+            None if content_lines > 0 => text.push('\n'),
+            None => {}
+        }
+        return Ok(JsonValue::String(text));
+    }
+}
+
+/// Parser of flow values: scalars, and collections in brackets and braces.
+struct Flow {
+    chars: Vec<char>,
+    position: usize
+}
+
+impl Flow {
+    fn peek(&self) -> Option<char> {
+        return self.chars.get(self.position).copied();
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().map_or(false, |c| c.is_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    /// Parses a value. In collections, plain scalars end at `,`, `]`, `}` and, for keys, at `:`.
+    fn value(&mut self, in_collection: bool) -> Result<JsonValue, String> {
+        self.skip_spaces();
+        return match self.peek() {
+            Some('[') => self.sequence(),
+            Some('{') => self.mapping(),
+            Some('"') => Ok(JsonValue::String(self.double_quoted()?)),
+            Some('\'') => Ok(JsonValue::String(self.single_quoted()?)),
+            _ => Ok(plain_scalar(&self.plain(in_collection)))
+        };
+    }
+
+    /// Parses a key of a mapping, which has to be a scalar.
+    fn key(&mut self) -> Result<String, String> {
+        return match self.value(true)? {
+            JsonValue::String(s) => Ok(s),
+            JsonValue::Number(n) => Ok(n.to_string()),
+            JsonValue::Boolean(b) => Ok(b.to_string()),
+            JsonValue::Null => Ok(String::new()),
+            _ => Err("keys have to be scalars".to_string())
+        };
+    }
+
+    fn plain(&mut self, in_collection: bool) -> String {
+        let mut s = String::new();
+        while let Some(c) = self.peek() {
+            let ends_key = c == ':' && self.chars.get(self.position + 1).map_or(true, |n| n.is_whitespace() || ",]}".contains(*n));
+            if in_collection && (",]}".contains(c) || ends_key) {
+                break;
+            }
+            s.push(c);
+            self.position += 1;
+        }
+        return s.trim().to_string();
+    }
+
+    fn sequence(&mut self) -> Result<JsonValue, String> {
+        self.position += 1;
+        let mut items = Vec::new();
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some(']') => {
+                    self.position += 1;
+                    return Ok(JsonValue::Array(items));
+                },
+                None => return Err("unterminated flow sequence".to_string()),
+                _ => {}
+            }
+            items.push(self.value(true)?);
+            self.skip_spaces();
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some(']') => {},
+                _ => return Err("expected `,` or `]` in a flow sequence".to_string())
+            }
+        }
+    }
+
+    fn mapping(&mut self) -> Result<JsonValue, String> {
+        self.position += 1;
+        let mut entries = HashMap::new();
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some('}') => {
+                    self.position += 1;
+                    return Ok(JsonValue::Object(entries));
+                },
+                None => return Err("unterminated flow mapping".to_string()),
+                _ => {}
+            }
+            let key = self.key()?;
+            self.skip_spaces();
+            let value = if self.peek() == Some(':') {
+                self.position += 1;
+                self.value(true)?
+            } else {
+                JsonValue::Null
+            };
+            if entries.insert(key.clone(), value).is_some() {
+                return Err(format!("`{}` is defined twice", key));
+            }
+            self.skip_spaces();
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some('}') => {},
+                _ => return Err("expected `,` or `}` in a flow mapping".to_string())
+            }
+        }
+    }
+
+    fn double_quoted(&mut self) -> Result<String, String> {
+        self.position += 1;
+        let mut s = String::new();
+        loop {
+            let c = self.peek().ok_or("unterminated string")?;
+            self.position += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let e = self.peek().ok_or("unterminated string")?;
+                    self.position += 1;
+                    let length = match e {
+                        'x' => 2,
+                        'u' => 4,
+                        'U' => 8,
+                        _ => 0
+                    };
+                    if length > 0 {
+                        let digits: String = self.chars.iter().skip(self.position).take(length).collect();
+                        self.position += length;
+                        match u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32) {
+                            Some(c) => s.push(c),
+                            None => return Err(format!("invalid escape `\\{}{}`", e, digits))
+                        }
+                        continue;
+                    }
+                    s.push(match e {
+                        '0' => '\0',
+                        'a' => '\u{7}',
+                        'b' => '\u{8}',
+                        't' => '\t',
+                        'n' => '\n',
+                        'v' => '\u{b}',
+                        'f' => '\u{c}',
+                        'r' => '\r',
+                        'e' => '\u{1b}',
+                        ' ' => ' ',
+                        '"' => '"',
+                        '/' => '/',
+                        '\\' => '\\',
+                        _ => return Err(format!("invalid escape `\\{}`", e))
+                    });
+                },
+                c => s.push(c)
+            }
+        }
+    }
+
+    fn single_quoted(&mut self) -> Result<String, String> {
+        self.position += 1;
+        let mut s = String::new();
+        loop {
+            let c = self.peek().ok_or("unterminated string")?;
+            self.position += 1;
+            if c == '\'' {
+                // A quote is written as two quotes.
+                if self.peek() == Some('\'') {
+                    self.position += 1;
+                    s.push('\'');
+                    continue;
+                }
+                return Ok(s);
+            }
+            s.push(c);
+        }
+    }
+}
+
+/// Returns the value of a plain scalar, resolved as in the core schema of YAML 1.2.
+fn plain_scalar(text: &str) -> JsonValue {
+    match text {
+        "" | "~" | "null" | "Null" | "NULL" => return JsonValue::Null,
+        "true" | "True" | "TRUE" => return JsonValue::Boolean(true),
+        "false" | "False" | "FALSE" => return JsonValue::Boolean(false),
+        ".inf" | ".Inf" | ".INF" | "+.inf" | "+.Inf" | "+.INF" => return JsonValue::Number(f64::INFINITY),
+        "-.inf" | "-.Inf" | "-.INF" => return JsonValue::Number(f64::NEG_INFINITY),
+        ".nan" | ".NaN" | ".NAN" => return JsonValue::Number(f64::NAN),
+        _ => {}
+    }
+    if let Some(hex) = text.strip_prefix("0x") {
+        if let Ok(n) = u64::from_str_radix(hex, 16) {
+            return JsonValue::Number(n as f64);
+        }
+    }
+    if let Some(octal) = text.strip_prefix("0o") {
+        if let Ok(n) = u64::from_str_radix(octal, 8) {
+            return JsonValue::Number(n as f64);
+        }
+    }
+    // Rust also parses words such as `inf` and `infinity`, which are strings in YAML.
+    let numeric = text.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c)) && text.chars().any(|c| c.is_ascii_digit());
+    if numeric {
+        if let Ok(n) = text.parse::<f64>() {
+            return JsonValue::Number(n);
+        }
+    }
+    return JsonValue::String(text.to_string());
+}
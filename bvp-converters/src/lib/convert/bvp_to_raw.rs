@@ -0,0 +1,175 @@
+//! Reconstruction of the modalities of an asset as raw voxel data, as `bvp2raw` writes them.
+
+use std::io::Write;
+
+use crate::bvpfile::BVPFile;
+use crate::bytes::Bytes;
+use crate::errors::ConvertError;
+use crate::formats::{Format, FormatFamily, PrimitiveType};
+use crate::modality::Modality;
+use crate::progress::ProgressSink;
+use crate::reader::VolumeReader;
+use crate::transform;
+use crate::vector3::Vector3;
+use crate::version;
+use crate::{log_debug, log_info};
+
+/// Which part of a modality is reconstructed, and in how many pieces.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RawExportOptions {
+    /// Start and end (exclusive) of the region to reconstruct. Defaults to the whole modality.
+    pub region: Option<(Vector3<u32>, Vector3<u32>)>,
+    /// Thickness of the slabs along Z the region is reconstructed in, rounded up to microblocks,
+    /// so only the blocks intersecting one slab are decoded at a time. Defaults to the whole region at once.
+    pub slab_thickness: Option<u32>
+}
+
+/// A part of a reconstructed region, with all of its voxels along X and Y from `start.z` to `end.z`.
+pub struct Slab {
+    pub start: Vector3<u32>,
+    pub end: Vector3<u32>,
+    /// Voxels in linear order, X changing fastest
+    pub data: Bytes
+}
+
+/// Returns the start and end of the region of a modality that is reconstructed with the options.
+/// Fails if the region is empty.
+/// * `reader` - reader of the asset
+/// * `modality_index` - index of the modality
+/// * `options` - the region, if any
+pub fn export_region(reader: &VolumeReader, modality_index: usize, options: &RawExportOptions) -> Result<(Vector3<u32>, Vector3<u32>), ConvertError> {
+    let (start, end) = match options.region {
+        Some(region) => region,
+        None => {
+            let (root, _) = reader.modality_root(modality_index).map_err(ConvertError::Reader)?;
+            (Vector3::from_xyz(0, 0, 0), reader.bvp_file().blocks[root].dimensions)
+        }
+    };
+    if start.x >= end.x || start.y >= end.y || start.z >= end.z {
+        return Err(ConvertError::EmptyRegion(start, end));
+    }
+    return Ok((start, end));
+}
+
+/// Returns the thickness of slabs that most blocks placed in the root block fit into,
+/// so that each of them is decoded about once.
+/// * `bvp_file` - the asset
+/// * `root` - index of the root block of the modality
+pub fn default_slab_thickness(bvp_file: &BVPFile, root: usize) -> u32 {
+    let root_block = &bvp_file.blocks[root];
+    return root_block.placements.iter()
+        .filter_map(|p| bvp_file.blocks.get(p.block))
+        .map(|b| b.dimensions.z)
+        .max()
+        .unwrap_or(root_block.dimensions.z);
+}
+
+/// Returns the slabs of the region of a modality, from the lowest Z up. Each slab is reconstructed
+/// when the iterator reaches it, so only one of them is in memory at a time.
+/// * `reader` - reader of the asset
+/// * `modality_index` - index of the modality
+/// * `options` - the region and the thickness of the slabs
+pub fn slabs<'a>(reader: &'a VolumeReader<'a>, modality_index: usize, options: &RawExportOptions)
+    -> Result<impl ExactSizeIterator<Item = Result<Slab, ConvertError>> + 'a, ConvertError>
+{
+    let (_, format) = reader.modality_root(modality_index).map_err(ConvertError::Reader)?;
+    let (start, end) = export_region(reader, modality_index, options)?;
+    if options.region.is_some() {
+        log_info!("reconstructing region from {} to {} of modality {}", start, end, modality_index);
+    }
+    let thickness = match options.slab_thickness {
+        // Slabs have to start and end on microblocks.
+        Some(thickness) => {
+            let microblock_depth = format.microblock_dimensions.z;
+            thickness.div_ceil(microblock_depth).max(1) * microblock_depth
+        },
+        None => end.z - start.z
+    };
+    let streamed = options.slab_thickness.is_some();
+
+    return Ok((start.z..end.z).step_by(thickness as usize).map(move |slab_start| {
+        let slab_end = (slab_start + thickness).min(end.z);
+        if streamed {
+            log_debug!("reconstructing slab {}..{}", slab_start, slab_end);
+        }
+        let slab_start = Vector3::from_xyz(start.x, start.y, slab_start);
+        let slab_end = Vector3::from_xyz(end.x, end.y, slab_end);
+        let block = reader.read_region(modality_index, slab_start, slab_end).map_err(ConvertError::Reader)?;
+        return Ok(Slab { start: slab_start, end: slab_end, data: block.data.unwrap_or_default() });
+    }));
+}
+
+/// Reconstructs the region of a modality and writes its voxels to a sink, slab by slab, see `slabs`.
+/// The progress sink counts slabs as blocks: `start` receives the number of slabs, and `block_processed`
+/// and `bytes_written` are called for every slab after it is written.
+/// * `reader` - reader of the asset
+/// * `modality_index` - index of the modality
+/// * `options` - the region and the thickness of the slabs
+/// * `sink` - receives the voxels in linear order, X changing fastest
+/// * `progress` - receives the events of the reconstruction, a reference to a sink works too
+pub fn bvp_to_raw(reader: &VolumeReader, modality_index: usize, options: &RawExportOptions, mut sink: impl Write,
+    progress: impl ProgressSink) -> Result<(), ConvertError>
+{
+    let slabs = slabs(reader, modality_index, options)?;
+    progress.start(slabs.len());
+    for slab in slabs {
+        let slab = slab?;
+        sink.write_all(&slab.data).map_err(ConvertError::CannotWrite)?;
+        progress.block_processed(false);
+        progress.bytes_written(slab.data.len());
+    }
+    sink.flush().map_err(ConvertError::CannotWrite)?;
+    progress.finish();
+    return Ok(());
+}
+
+/// Returns a detached NRRD header for a raw file, with the spacing, or the orientation
+/// if the modality has a transform. Only formats of one component type can be described.
+/// * `format` - format of the voxels
+/// * `modality` - the modality the raw file was reconstructed from
+/// * `start` - first voxel of the reconstructed region
+/// * `extent` - dimensions of the raw file
+/// * `data_file` - name of the raw file
+pub fn nrrd_header(format: &Format, modality: &Modality, start: Vector3<u32>, extent: Vector3<u32>, data_file: &str) -> Result<String, ConvertError> {
+    let mono = match format.family() {
+        FormatFamily::Mono(m) => m,
+        _ => return Err(ConvertError::NrrdFormat)
+    };
+    let tp = match (mono.component_type(), mono.component_size()) {
+        (PrimitiveType::Uint, 1) => "uint8",
+        (PrimitiveType::Int, 1) => "int8",
+        (PrimitiveType::Uint, 2) => "uint16",
+        (PrimitiveType::Int, 2) => "int16",
+        (PrimitiveType::Uint, 4) => "uint32",
+        (PrimitiveType::Int, 4) => "int32",
+        (PrimitiveType::Uint, 8) => "uint64",
+        (PrimitiveType::Int, 8) => "int64",
+        (PrimitiveType::Float, 4) => "float",
+        (PrimitiveType::Float, 8) => "double",
+        (tp, size) => return Err(ConvertError::NrrdType(size as usize, tp.to_string()))
+    };
+    // Components are the fastest axis of the raw file.
+    let vector = mono.count() > 1;
+    let mut lines = vec!["NRRD0004".to_string(), format!("# written by {}", version::generator("bvp2raw")), format!("type: {}", tp)];
+    lines.push(format!("dimension: {}", if vector { 4 } else { 3 }));
+    let sizes = format!("{} {} {}", extent.x, extent.y, extent.z);
+    lines.push(format!("sizes: {}", if vector { format!("{} {}", mono.count(), sizes) } else { sizes }));
+    lines.push(format!("kinds: {}domain domain domain", if vector { "vector " } else { "" }));
+    lines.push("endian: little".to_string());
+    lines.push("encoding: raw".to_string());
+    match transform::transform(modality).map_err(ConvertError::Transform)? {
+        Some(t) => {
+            let t = t.translated([start.x as f64, start.y as f64, start.z as f64]);
+            let axes: Vec<String> = t.axes().iter().map(|a| format!("({},{},{})", a[0], a[1], a[2])).collect();
+            let [x, y, z] = t.origin();
+            lines.push("space dimension: 3".to_string());
+            lines.push(format!("space directions: {}{}", if vector { "none " } else { "" }, axes.join(" ")));
+            lines.push(format!("space origin: ({},{},{})", x, y, z));
+        },
+        None => if let Some(voxel_size) = modality.voxel_size {
+            lines.push(format!("spacings: {}{} {} {}", if vector { "nan " } else { "" }, voxel_size.x, voxel_size.y, voxel_size.z));
+        }
+    }
+    lines.push(format!("data file: {}", data_file));
+    return Ok(lines.join("\n") + "\n");
+}
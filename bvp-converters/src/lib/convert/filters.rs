@@ -0,0 +1,277 @@
+//! Filters applied to the voxels of an input before it is split into blocks, for cleaning noisy volumes
+//! such as micro-CT scans during the conversion:
+//!
+//! ```json
+//! "filters": [
+//!     { "type": "median", "radius": 1 },
+//!     { "type": "gaussian", "sigma": 0.8 },
+//!     { "type": "clamp", "min": 0, "max": 4000 }
+//! ]
+//! ```
+//!
+//! Filters are applied in order. The volume is filtered in slabs of planes along Z on several threads,
+//! every slab reading the planes within the reach of the filter around it, and the voxels near the edges
+//! of the volume see the edge voxels repeated beyond it. Integer results are rounded and clamped to the
+//! range of the format, so the format of the volume does not change.
+
+use std::collections::HashMap;
+use std::mem;
+use std::thread;
+
+use itertools::iproduct;
+use tinyjson::JsonValue;
+
+use crate::convert::rescale::{self, Rescale};
+use crate::errors::FilterError;
+use crate::formats::{Format, MonoFormat};
+use crate::vector3::Vector3;
+
+/// Largest standard deviation of Gaussian smoothing, in voxels. A slab holds the planes within three
+/// standard deviations of it.
+pub const MAX_GAUSSIAN_SIGMA: f64 = 10.0;
+/// Largest radius of the median filter, which sorts the cube of `2 * radius + 1` voxels around every voxel.
+pub const MAX_MEDIAN_RADIUS: u32 = 3;
+/// Number of planes of a slab.
+const SLAB_PLANES: usize = 8;
+
+/// A filter applied to the voxels of an input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    /// Gaussian smoothing, with the standard deviation in voxels
+    Gaussian { sigma: f64 },
+    /// Median of the cube of voxels at most `radius` voxels away in every direction
+    Median { radius: u32 },
+    /// Limits the values to a range, either end can be infinite
+    Clamp { min: f64, max: f64 }
+}
+
+impl Filter {
+    /// Returns the name of the filter, its `type` in configs.
+    pub fn name(&self) -> &str {
+        return match self {
+            Filter::Gaussian { .. } => "gaussian",
+            Filter::Median { .. } => "median",
+            Filter::Clamp { .. } => "clamp"
+        };
+    }
+
+    /// Checks that the settings of the filter are in range.
+    pub fn check(&self) -> Result<(), FilterError> {
+        let invalid = |field: &str, expected: &str| {
+            return Err(FilterError::InvalidValue(field.to_string(), self.name().to_string(), expected.to_string()));
+        };
+        return match *self {
+            Filter::Gaussian { sigma } if !(sigma > 0.0 && sigma <= MAX_GAUSSIAN_SIGMA) => {
+                invalid("sigma", &format!("a number above 0 and at most {}", MAX_GAUSSIAN_SIGMA))
+            },
+            Filter::Median { radius } if radius == 0 || radius > MAX_MEDIAN_RADIUS => {
+                invalid("radius", &format!("an integer from 1 to {}", MAX_MEDIAN_RADIUS))
+            },
+            Filter::Clamp { min, max } if min.is_nan() || max.is_nan() || min > max => {
+                invalid("min", "a number not above `max`")
+            },
+            _ => Ok(())
+        };
+    }
+
+    /// Reads a filter from its config object, see the module documentation.
+    /// `radius` of a median filter defaults to 1, and `min` or `max` of a clamp to no limit.
+    /// * `j` - the object
+    pub fn from_json(j: &JsonValue) -> Result<Self, FilterError> {
+        let object: &HashMap<String, JsonValue> = j.get().ok_or(FilterError::MissingType)?;
+        let tp = match object.get("type") {
+            Some(JsonValue::String(s)) => s.as_str(),
+            _ => return Err(FilterError::MissingType)
+        };
+        let number = |field: &str| {
+            return match object.get(field) {
+                Some(JsonValue::Number(n)) => Ok(Some(*n)),
+                Some(_) => Err(FilterError::InvalidValue(field.to_string(), tp.to_string(), "a number".to_string())),
+                None => Ok(None)
+            };
+        };
+        let filter = match tp {
+            "gaussian" => match number("sigma")? {
+                Some(sigma) => Filter::Gaussian { sigma },
+                None => return Err(FilterError::InvalidValue("sigma".to_string(), tp.to_string(), "given".to_string()))
+            },
+            "median" => match number("radius")? {
+                Some(radius) if radius.fract() != 0.0 || radius < 0.0 => {
+                    return Err(FilterError::InvalidValue("radius".to_string(), tp.to_string(), "an integer".to_string()));
+                },
+                radius => Filter::Median { radius: radius.map_or(1, |r| r.min(u32::MAX as f64) as u32) }
+            },
+            "clamp" => match (number("min")?, number("max")?) {
+                (None, None) => return Err(FilterError::InvalidValue("min".to_string(), tp.to_string(), "given, or `max`".to_string())),
+                (min, max) => Filter::Clamp { min: min.unwrap_or(f64::NEG_INFINITY), max: max.unwrap_or(f64::INFINITY) }
+            },
+            other => return Err(FilterError::UnknownType(other.to_string()))
+        };
+        filter.check()?;
+        return Ok(filter);
+    }
+
+    /// Returns how many voxels away from a voxel the filter reads.
+    fn reach(&self) -> usize {
+        return match *self {
+            Filter::Gaussian { sigma } => (3.0 * sigma).ceil() as usize,
+            Filter::Median { radius } => radius as usize,
+            Filter::Clamp { .. } => 0
+        };
+    }
+}
+
+/// Whether the voxels of a format can be filtered. Only mono formats with native component types can,
+/// every component is filtered on its own.
+/// * `format` - format of the volume
+pub fn supports(format: &Format) -> bool {
+    return Rescale::supports(format);
+}
+
+/// Applies filters to the voxels of a volume, in order, and returns the filtered voxels in the same format.
+/// Volumes in formats that cannot be filtered are returned as they are.
+/// * `filters` - the filters
+/// * `data` - voxels of the volume, with X changing fastest
+/// * `dimensions` - dimensions of the volume
+/// * `format` - format of the voxels
+/// * `threads` - number of threads slabs are filtered on
+pub fn apply(filters: &[Filter], mut data: Vec<u8>, dimensions: Vector3<u32>, format: &Format, threads: usize) -> Vec<u8> {
+    let component = match Rescale::input_component(format) {
+        Some(c) => c,
+        None => return data
+    };
+    for filter in filters {
+        data = apply_filter(filter, &data, dimensions, component, threads);
+    }
+    return data;
+}
+
+/// Sizes of a volume as the filters index its values, every component of a voxel being a value.
+#[derive(Clone, Copy)]
+struct Grid {
+    x: usize,
+    y: usize,
+    z: usize,
+    components: usize
+}
+
+impl Grid {
+    /// Returns the number of values in a plane.
+    fn plane(&self) -> usize {
+        return self.x * self.y * self.components;
+    }
+
+    /// Returns the index of a value among the values of a slab starting at plane `first`.
+    fn index(&self, first: usize, z: usize, y: usize, x: usize, component: usize) -> usize {
+        return (((z - first) * self.y + y) * self.x + x) * self.components + component;
+    }
+}
+
+/// Applies one filter to the voxels of a volume.
+/// * `filter` - the filter
+/// * `data` - voxels of the volume
+/// * `dimensions` - dimensions of the volume
+/// * `format` - format of the voxels
+/// * `threads` - number of threads slabs are filtered on
+fn apply_filter(filter: &Filter, data: &[u8], dimensions: Vector3<u32>, format: &MonoFormat, threads: usize) -> Vec<u8> {
+    let grid = Grid { x: dimensions.x as usize, y: dimensions.y as usize, z: dimensions.z as usize, components: format.count() as usize };
+    let plane_bytes = grid.plane() * format.component_size() as usize;
+    let mut filtered = vec![0u8; data.len()];
+    if plane_bytes == 0 {
+        return filtered;
+    }
+    let mut slabs: Vec<(usize, &mut [u8])> = filtered.chunks_mut(SLAB_PLANES * plane_bytes).enumerate().collect();
+    let per_thread = slabs.len().div_ceil(threads.max(1));
+    thread::scope(|scope| {
+        while !slabs.is_empty() {
+            let rest = slabs.split_off(per_thread.min(slabs.len()));
+            let own = mem::replace(&mut slabs, rest);
+            scope.spawn(move || {
+                for (slab, target) in own {
+                    let start = slab * SLAB_PLANES;
+                    let end = start + target.len() / plane_bytes;
+                    // Planes the filter reads around the slab.
+                    let first = start.saturating_sub(filter.reach());
+                    let last = (end + filter.reach()).min(grid.z);
+                    let mut values = vec![0.0; (last - first) * grid.plane()];
+                    rescale::read_components(format, &data[first * plane_bytes..last * plane_bytes], &mut values);
+                    let filtered = filter_slab(filter, &mut values, grid, first, start, end);
+                    rescale::write_components(format, &filtered, target);
+                }
+            });
+        }
+    });
+    return filtered;
+}
+
+/// Returns the filtered values of the planes from `start` to `end`.
+/// * `filter` - the filter
+/// * `values` - values of the planes from `first` to the last plane the filter reads, changed by smoothing
+/// * `grid` - sizes of the volume
+/// * `first` - first plane of `values`
+/// * `start`, `end` - planes to filter
+fn filter_slab(filter: &Filter, values: &mut [f64], grid: Grid, first: usize, start: usize, end: usize) -> Vec<f64> {
+    let slab = &values[(start - first) * grid.plane()..(end - first) * grid.plane()];
+    let last = first + values.len() / grid.plane();
+    return match *filter {
+        Filter::Clamp { min, max } => slab.iter().map(|v| v.clamp(min, max)).collect(),
+        Filter::Gaussian { sigma } => {
+            let reach = filter.reach() as isize;
+            let kernel: Vec<f64> = (-reach..=reach).map(|i| (-((i * i) as f64) / (2.0 * sigma * sigma)).exp()).collect();
+            let total: f64 = kernel.iter().sum();
+            let kernel: Vec<f64> = kernel.iter().map(|w| w / total).collect();
+            // Smoothing is separable, X and Y in place in all planes, then Z into the slab.
+            let mut line = Vec::new();
+            for (z, y, c) in iproduct!(first..last, 0..grid.y, 0..grid.components) {
+                convolve(values, &kernel, grid.index(first, z, y, 0, c), grid.components, grid.x, &mut line);
+            }
+            for (z, x, c) in iproduct!(first..last, 0..grid.x, 0..grid.components) {
+                convolve(values, &kernel, grid.index(first, z, 0, x, c), grid.x * grid.components, grid.y, &mut line);
+            }
+            let mut smoothed = vec![0.0; (end - start) * grid.plane()];
+            for (z, i) in iproduct!(start..end, 0..grid.plane()) {
+                smoothed[(z - start) * grid.plane() + i] = kernel.iter().enumerate()
+                    .map(|(k, w)| {
+                        let source = (z as isize + k as isize - reach).clamp(0, grid.z as isize - 1) as usize;
+                        return w * values[(source - first) * grid.plane() + i];
+                    })
+                    .sum();
+            }
+            smoothed
+        },
+        Filter::Median { radius } => {
+            let radius = radius as isize;
+            let mut neighbours = Vec::new();
+            let mut medians = Vec::with_capacity(slab.len());
+            let clamp = |value: usize, offset: isize, length: usize| (value as isize + offset).clamp(0, length as isize - 1) as usize;
+            for (z, y, x, c) in iproduct!(start..end, 0..grid.y, 0..grid.x, 0..grid.components) {
+                neighbours.clear();
+                for (dz, dy, dx) in iproduct!(-radius..=radius, -radius..=radius, -radius..=radius) {
+                    let (nz, ny, nx) = (clamp(z, dz, grid.z), clamp(y, dy, grid.y), clamp(x, dx, grid.x));
+                    neighbours.push(values[grid.index(first, nz, ny, nx, c)]);
+                }
+                let middle = neighbours.len() / 2;
+                medians.push(*neighbours.select_nth_unstable_by(middle, |a, b| a.total_cmp(b)).1);
+            }
+            medians
+        }
+    };
+}
+
+/// Convolves a line of values with a kernel in place, with the values at the ends repeated beyond them.
+/// * `values` - the values the line is in
+/// * `kernel` - weights of the kernel, an odd number centred on the value
+/// * `start` - index of the first value of the line
+/// * `stride` - distance between neighbouring values of the line
+/// * `length` - number of values of the line
+/// * `line` - buffer for the original values of the line
+fn convolve(values: &mut [f64], kernel: &[f64], start: usize, stride: usize, length: usize, line: &mut Vec<f64>) {
+    let reach = (kernel.len() / 2) as isize;
+    line.clear();
+    line.extend((0..length).map(|i| values[start + i * stride]));
+    for i in 0..length {
+        values[start + i * stride] = kernel.iter().enumerate()
+            .map(|(k, w)| w * line[(i as isize + k as isize - reach).clamp(0, length as isize - 1) as usize])
+            .sum();
+    }
+}
@@ -0,0 +1,68 @@
+//! Binary masks of the voxels of a volume at or above an isovalue, the inside of its isosurface. The mask is
+//! stored as a further modality split into the same blocks as the volume, so renderers can test silhouettes
+//! and skip empty space without decoding the volume, and its blocks that are all inside or all outside are
+//! deduplicated to one each.
+//!
+//! Masks are stored with 8 bits per voxel, as `u8` voxels of 0 or 1, or with 1 bit per voxel in the
+//! compressed format `bitmask`, whose microblocks are rows of 8 voxels along X, one byte each, with the
+//! first voxel in the lowest bit.
+
+use crate::extensions::Extension;
+use crate::formats::{CompressedFormat, Format, FormatFamily, MonoFormat, PrimitiveType};
+use crate::vector3::Vector3;
+
+/// Scheme of the compressed format of masks with 1 bit per voxel.
+pub const BITMASK_SCHEME: &str = "bitmask";
+/// Dimensions of the voxels stored in a byte of a mask with 1 bit per voxel.
+pub const BITMASK_MICROBLOCK_DIMENSIONS: Vector3<u32> = Vector3 { x: 8, y: 1, z: 1 };
+/// Name of the mask modality if none is given.
+pub const DEFAULT_MASK_NAME: &str = "mask";
+
+/// A mask of the top level volume of a conversion.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IsosurfaceMask {
+    /// Voxels whose first component is at least this value are inside
+    pub isovalue: f64,
+    /// Bits per voxel, 1 or 8
+    pub bits: u32,
+    /// Name of the mask modality. Defaults to `DEFAULT_MASK_NAME`.
+    pub name: Option<String>
+}
+
+impl IsosurfaceMask {
+    /// Returns a mask with 8 bits per voxel and the default name.
+    /// * `isovalue` - voxels whose first component is at least this value are inside
+    pub fn new(isovalue: f64) -> Self {
+        return Self { isovalue, bits: 8, name: None };
+    }
+
+    /// Returns the format the mask is stored in.
+    pub fn format(&self) -> Format {
+        if self.bits == 1 {
+            let family = FormatFamily::Compressed(CompressedFormat::new(BITMASK_SCHEME));
+            return Format::new(BITMASK_MICROBLOCK_DIMENSIONS, 1, family, vec![Extension::ExtFormatCompressed]);
+        }
+        return Format::new(Vector3::from_xyz(1, 1, 1), 1, FormatFamily::Mono(MonoFormat::new(1, 1, PrimitiveType::Uint)), Vec::new());
+    }
+
+    /// Returns the mask of a volume in the format of the mask, or None if the values of its format cannot be read.
+    /// With 1 bit per voxel, the X dimension of the volume has to be a multiple of 8.
+    /// * `data` - voxels of the volume, with X changing fastest
+    /// * `format` - format of the voxels
+    pub fn generate(&self, data: &[u8], format: &Format) -> Option<Vec<u8>> {
+        let component = format.first_component()?;
+        let component_size = component.component_size() as usize;
+        let voxel_size = format.microblock_size as usize;
+        if format.microblock_dimensions != Vector3::from_xyz(1, 1, 1) || voxel_size == 0 {
+            return None;
+        }
+        let inside = data.chunks_exact(voxel_size).map(|voxel| component.component_value(&voxel[..component_size]) >= self.isovalue);
+        if self.bits == 1 {
+            let voxels: Vec<bool> = inside.collect();
+            return Some(voxels.chunks(8)
+                .map(|row| row.iter().enumerate().fold(0u8, |byte, (i, inside)| byte | ((*inside as u8) << i)))
+                .collect());
+        }
+        return Some(inside.map(|inside| inside as u8).collect());
+    }
+}
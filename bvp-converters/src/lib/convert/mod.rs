@@ -0,0 +1,34 @@
+//! Conversions between raw volumes and BVP assets, as `raw2bvp` and `bvp2raw` do them, for applications
+//! that embed conversions with their own output and progress handling. `raw_to_bvp` writes an asset
+//! to any `ArchiveWriter`, and `bvp_to_raw` writes the voxels of a modality to any `io::Write`.
+
+mod bvp_to_raw;
+pub mod filters;
+pub mod mask;
+mod parameters;
+mod raw_to_bvp;
+pub mod rescale;
+pub mod tiles;
+
+use crate::archives::ArchiveWriter;
+use crate::errors::ConvertError;
+use crate::metrics::PipelineMetrics;
+use crate::progress::ProgressSink;
+
+pub use bvp_to_raw::{bvp_to_raw, default_slab_thickness, export_region, nrrd_header, slabs, RawExportOptions, Slab};
+pub use parameters::{BlockNaming, BlockOrder, InputLayout, ModalityInput, ParallelMode, Parameters, ParametersBuilder, WindowSetting, DEFAULT_DEDUP_MEMORY_BLOCKS, MAX_OCCUPANCY_GRID};
+
+/// Converts raw volumes into a BVP asset and writes its files to an archive writer, which is finished
+/// with `parameters.output_file`. The inputs are read from the files given in the parameters.
+/// Returns where the conversion spent its time, and how much it read and wrote.
+/// * `parameters` - the inputs and settings of the conversion
+/// * `sink` - writer the files of the asset are written to, for example the one
+///   `parameters.archive.return_compressed_writer` returns
+/// * `progress` - receives the events of the conversion, a reference to a sink works too
+pub fn raw_to_bvp(parameters: Parameters, mut sink: impl ArchiveWriter + Send, progress: impl ProgressSink) -> Result<PipelineMetrics, ConvertError> {
+    let metrics = match parameters.parallel_mode {
+        ParallelMode::Pipeline => raw_to_bvp::raw_to_bvp_parallel(&parameters, &mut sink, &progress),
+        ParallelMode::Data => raw_to_bvp::raw_to_bvp_data_parallel(&parameters, &mut sink, &progress)
+    };
+    return metrics.map_err(ConvertError::Failed);
+}
@@ -0,0 +1,835 @@
+//! Parameters of a conversion from raw volumes, as `raw2bvp` reads them from its config file,
+//! or as applications create them with `Parameters::builder`.
+
+use std::fmt;
+
+use crate::archives::{ArchiveEnum, STDIO_PATH, zip::MemberCompression};
+use crate::checksum::ChecksumType;
+use crate::compressions::{CompressionType, MAX_COMPRESSION_LEVEL};
+use crate::digest::DigestType;
+use crate::convert::filters::{self, Filter};
+use crate::convert::mask::{IsosurfaceMask, BITMASK_MICROBLOCK_DIMENSIONS, DEFAULT_MASK_NAME};
+use crate::convert::rescale::Rescale;
+use crate::convert::tiles::TiledVolume;
+use crate::ed25519::SigningKey;
+use crate::errors::ParametersError;
+use crate::formats::Format;
+use crate::layout::VoxelLayout;
+use crate::remote;
+use crate::texture::TextureCompression;
+use crate::transform::Transform;
+use crate::vector3::Vector3;
+use crate::version::SpecVersion;
+use crate::window_level::WindowPreset;
+
+/// Number of block hashes the deduplication map keeps in memory, unless `dedupMemoryBlocks` is set.
+/// Below this, the map needs a few hundred megabytes.
+pub const DEFAULT_DEDUP_MEMORY_BLOCKS: usize = 1 << 22;
+/// Largest number of cells along an axis of the occupancy bitmap of a block, which is stored in the manifest.
+pub const MAX_OCCUPANCY_GRID: u32 = 16;
+
+/// Strategy used to parallelize the conversion.
+#[derive(Clone, Copy, Debug)]
+pub enum ParallelMode {
+    /// Three stage pipeline: one thread splits the inputs into blocks, workers deduplicate
+    /// and compress them, and one thread writes their files as they are finished.
+    Pipeline,
+    /// The block grid in batches: blocks are extracted and compressed in parallel,
+    /// and deduplicated and written in grid order between the parallel steps.
+    Data
+}
+
+impl ParallelMode {
+    pub fn from_string(s: &str) -> Option<Self> {
+        return match s {
+            "pipeline" => Some(ParallelMode::Pipeline),
+            "data" => Some(ParallelMode::Data),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for ParallelMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ParallelMode::Pipeline => "pipeline",
+            ParallelMode::Data => "data"
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+/// How block files are named in the output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockNaming {
+    /// `blocks/block_<index>.raw`, by the index of the block in the manifest
+    Index,
+    /// `blocks/<xxh3 of the file>.<encoding>`, by the content of the file
+    Content
+}
+
+impl BlockNaming {
+    pub fn from_string(s: &str) -> Option<Self> {
+        return match s {
+            "index" => Some(Self::Index),
+            "content" => Some(Self::Content),
+            _ => None
+        }
+    }
+}
+
+/// Order of the block files in the archive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockOrder {
+    /// In the order the blocks are finished, which differs between runs of the pipeline
+    Completion,
+    /// By the position of their first placement, with X changing fastest
+    Grid,
+    /// By the Morton (Z-order) code of their first placement, so that neighbouring blocks are close
+    Morton
+}
+
+impl BlockOrder {
+    pub fn from_string(s: &str) -> Option<Self> {
+        return match s {
+            "completion" => Some(Self::Completion),
+            "grid" => Some(Self::Grid),
+            "morton" => Some(Self::Morton),
+            _ => None
+        }
+    }
+}
+
+/// Where a window/level preset of a modality comes from.
+#[derive(Clone, Debug)]
+pub enum WindowSetting {
+    /// Computed from the histogram of the volume during the conversion
+    Auto,
+    /// Given in the config
+    Preset(WindowPreset)
+}
+
+/// Where the voxels are in a raw input file, for files with a header or with padded rows and planes.
+/// Rows are lines of voxels along X, or of microblocks for formats whose microblocks hold more than one voxel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InputLayout {
+    /// Number of bytes before the first voxel, such as a header
+    pub offset: u64,
+    /// Number of bytes from the start of a row to the start of the next, if rows are padded
+    pub row_stride: Option<u64>,
+    /// Number of bytes of padding after every plane
+    pub plane_padding: u64
+}
+
+impl InputLayout {
+    /// Returns the size of a row in bytes, the number of rows of a plane and the number of planes of a volume.
+    /// * `dimensions` - dimensions of the volume
+    /// * `format` - format of the voxels
+    pub fn rows(dimensions: Vector3<u32>, format: &Format) -> (u64, u64, u64) {
+        let microblock = format.microblock_dimensions;
+        // A row is at most `u32::MAX` microblocks of `u32::MAX` bytes, which fits into `u64`.
+        let row_size = (dimensions.x / microblock.x) as u64 * format.microblock_size as u64;
+        return (row_size, (dimensions.y / microblock.y) as u64, (dimensions.z / microblock.z) as u64);
+    }
+
+    /// Whether the voxels start the file and follow each other without padding.
+    /// * `dimensions` - dimensions of the volume
+    /// * `format` - format of the voxels
+    pub fn is_packed(&self, dimensions: Vector3<u32>, format: &Format) -> bool {
+        let (row_size, _, _) = Self::rows(dimensions, format);
+        return self.offset == 0 && self.row_stride.unwrap_or(row_size) == row_size && self.plane_padding == 0;
+    }
+
+    /// Returns the number of bytes a file needs to hold a volume in this layout.
+    /// The padding after the last row and plane can be left out.
+    /// * `dimensions` - dimensions of the volume
+    /// * `format` - format of the voxels
+    pub fn file_size(&self, dimensions: Vector3<u32>, format: &Format) -> u64 {
+        let (row_size, rows, planes) = Self::rows(dimensions, format);
+        let row_stride = self.row_stride.unwrap_or(row_size);
+        let plane_stride = rows * row_stride + self.plane_padding;
+        return self.offset + (planes - 1) * plane_stride + (rows - 1) * row_stride + row_size;
+    }
+
+    /// Returns the number of bytes of a file that holds a volume in this layout with the padding after its last row and plane.
+    /// Files of a volume hold at least `file_size` and at most this many bytes.
+    /// * `dimensions` - dimensions of the volume
+    /// * `format` - format of the voxels
+    pub fn padded_file_size(&self, dimensions: Vector3<u32>, format: &Format) -> u64 {
+        let (row_size, rows, planes) = Self::rows(dimensions, format);
+        let row_stride = self.row_stride.unwrap_or(row_size);
+        return self.offset + planes * (rows * row_stride + self.plane_padding);
+    }
+}
+
+/// A volume that is converted into one modality of the asset.
+#[derive(Clone)]
+pub struct ModalityInput {
+    pub input_file: String,
+    pub dimensions: Vector3<u32>,
+    pub input_format: Format,
+    /// Where the voxels are in the input file. Defaults to the file holding only the voxels.
+    pub layout: InputLayout,
+    /// Map applied to the voxel values as they are read, if any. The voxels are then stored in the format `Rescale::output_format` gives.
+    pub rescale: Option<Rescale>,
+    /// Filters applied to the voxels after they are rescaled, in order, see `filters`.
+    pub filters: Vec<Filter>,
+    /// If set, the voxels are not read from `input_file`, they are this mask of the top level volume.
+    pub mask: Option<IsosurfaceMask>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub semantic_type: Option<String>,
+    pub volume_scale: Vector3<f32>,
+    pub voxel_scale: Option<Vector3<f32>>,
+    /// Orientation of the volume, stored in the `EXT_transform` extension.
+    pub transform: Option<Transform>,
+    /// Window/level presets of the volume, stored in the `EXT_window_level` extension.
+    pub window: Vec<WindowSetting>
+}
+
+pub struct Parameters {
+    pub input_file: String,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub semantic_type: Option<String>,
+    pub volume_scale: Vector3<f32>,
+    pub voxel_scale: Option<Vector3<f32>>,
+    pub transform: Option<Transform>,
+    pub window: Vec<WindowSetting>,
+    pub output_file: String,
+    pub dimensions: Vector3<u32>,
+    pub block_dimensions: Vector3<u32>,
+    /// Dimensions of the superblocks on each level of the block hierarchy, the outermost first.
+    /// Empty if data blocks are placed directly in the root block.
+    pub superblock_dimensions: Vec<Vector3<u32>>,
+    pub input_format: Format,
+    /// Where the voxels are in `input_file`. Defaults to the file holding only the voxels.
+    pub input_layout: InputLayout,
+    /// Map applied to the voxel values of `input_file` as they are read, if any.
+    pub rescale: Option<Rescale>,
+    /// Filters applied to the voxels of `input_file` after they are rescaled, in order.
+    pub filters: Vec<Filter>,
+    /// Mask of the top level volume stored as the last modality, if any, see `mask`.
+    pub isosurface_mask: Option<IsosurfaceMask>,
+    pub archive: ArchiveEnum,
+    /// How members of ZIP archives are compressed. Defaults to storing them.
+    pub archive_compression: MemberCompression,
+    pub compression: CompressionType,
+    pub author: Option<String>,
+    pub copyright: Option<String>,
+    pub acquisition_time: Option<String>,
+    /// `generator` of the asset. Defaults to `raw2bvp` with the version of the library, see `version::generator`.
+    pub generator: Option<String>,
+    /// Number of worker threads. Defaults to the available parallelism.
+    pub threads: Option<usize>,
+    /// Capacity of queues between pipeline stages (block batch size in data mode).
+    /// Defaults to unbounded queues.
+    pub queue_capacity: Option<usize>,
+    pub compression_level: u32,
+    /// Algorithm of the checksums recorded for block data, if any.
+    pub checksum: Option<ChecksumType>,
+    /// Algorithm of the digests recorded for the whole volume of every modality, if any, see `digest`.
+    pub volume_checksum: Option<DigestType>,
+    /// Whether the size and hash of the top level input file are recorded in the asset, see `source`. Defaults to false.
+    pub record_source: bool,
+    /// Whether inputs whose files are shorter or longer than their volume are converted, with a warning.
+    /// The missing voxels are zero and the bytes after the volume are left out. Defaults to false.
+    pub allow_size_mismatch: bool,
+    /// Whether blocks with the same data are stored only once. Defaults to true.
+    pub deduplication: bool,
+    /// Number of block hashes the deduplication map keeps in memory. Conversions with more
+    /// blocks move the rest to the disk. Defaults to `DEFAULT_DEDUP_MEMORY_BLOCKS`.
+    pub dedup_memory_blocks: usize,
+    /// How block files are named. Defaults to naming them by index.
+    pub block_naming: BlockNaming,
+    /// Order of the block files in the archive. Defaults to the order they are finished in.
+    pub block_order: BlockOrder,
+    /// Order of the voxels inside the blocks. Defaults to linear.
+    pub voxel_layout: VoxelLayout,
+    /// Number of cells along each axis of the blocks in their occupancy bitmaps, see `occupancy`.
+    /// Blocks get no bitmaps by default.
+    pub occupancy_grid: Option<Vector3<u32>>,
+    /// GPU texture compression the voxels are encoded in, if any.
+    pub texture_compression: Option<TextureCompression>,
+    /// Version of the specification the manifest is written in. Defaults to the current one.
+    pub spec_version: SpecVersion,
+    /// Volumes converted into further modalities of the same asset, for example other
+    /// channels or lower resolution levels. Their blocks are deduplicated together.
+    pub additional_modalities: Vec<ModalityInput>,
+    /// Whether a journal is kept so an interrupted conversion can be resumed. Defaults to false.
+    pub checkpoint: bool,
+    /// Key the manifest is signed with, in the `EXT_signature` extension, if any.
+    pub signing_key: Option<SigningKey>,
+    /// Tiles of the top level volume, if `input_file` is the index of a tiled volume.
+    pub tiles: Option<TiledVolume>,
+    /// How the conversion is parallelized. Defaults to the pipeline.
+    pub parallel_mode: ParallelMode,
+    /// Hash of the settings that change the output, used to tell whether a checkpoint belongs to this conversion.
+    pub settings_hash: u64
+}
+
+impl Parameters {
+    /// Returns a builder of the parameters of a conversion, with the defaults of `raw2bvp`
+    /// for everything that is not given here.
+    /// * `input_file` - path of the raw volume, `-` for stdin, or a URL
+    /// * `output_file` - path of the asset, `-` for stdout, or a URL
+    /// * `dimensions` - dimensions of the volume
+    /// * `block_dimensions` - dimensions of the blocks, a multiple of the microblock dimensions of the format
+    /// * `input_format` - format of the voxels in the input
+    pub fn builder(input_file: impl Into<String>, output_file: impl Into<String>, dimensions: Vector3<u32>,
+        block_dimensions: Vector3<u32>, input_format: Format) -> ParametersBuilder
+    {
+        return ParametersBuilder::new(input_file.into(), output_file.into(), dimensions, block_dimensions, input_format);
+    }
+
+    /// Checks that the parameters describe a conversion that can run: dimensions are at least 1, blocks
+    /// hold whole microblocks and fit in the volume, superblocks hold whole blocks, and the output can be
+    /// written. `raw2bvp` checks the same in its config, where the problems are found by key.
+    pub fn validate(&self) -> Result<(), ParametersError> {
+        if self.input_file.is_empty() {
+            return Err(ParametersError::EmptyPath("input_file".to_string()));
+        }
+        if self.output_file.is_empty() {
+            return Err(ParametersError::EmptyPath("output_file".to_string()));
+        }
+        // Unarchived assets are written as many files next to each other, only archives can be streamed or uploaded.
+        let archived = matches!(self.archive, ArchiveEnum::SAF | ArchiveEnum::ZIP);
+        if remote::is_remote(&self.output_file) && !archived {
+            return Err(ParametersError::UnarchivedOutput("a URL".to_string()));
+        }
+        if self.output_file == STDIO_PATH && !archived {
+            return Err(ParametersError::UnarchivedOutput("`-` (stdout)".to_string()));
+        }
+        // The checkpoint is kept next to the output.
+        if self.checkpoint && (remote::is_remote(&self.output_file) || self.output_file == STDIO_PATH) {
+            return Err(ParametersError::CheckpointOutput);
+        }
+
+        check_dimensions("block_dimensions", self.block_dimensions)?;
+        if let Some(mask) = &self.isosurface_mask {
+            self.validate_mask(mask)?;
+        }
+        for (i, input) in self.modality_inputs().iter().enumerate().filter(|(_, input)| input.mask.is_none()) {
+            let (prefix, format_field) = match i {
+                0 => (String::new(), "block_dimensions".to_string()),
+                i => (format!("additional_modalities[{}].", i - 1), format!("additional_modalities[{}].input_format", i - 1))
+            };
+            // Texture compressed blocks are in the format of the compression, whatever the input is.
+            let microblock = match &self.texture_compression {
+                Some(compression) => compression.format().microblock_dimensions,
+                None => input.input_format.microblock_dimensions
+            };
+            check_dimensions(&format!("{}dimensions", prefix), input.dimensions)?;
+            check_multiple(&format_field, self.block_dimensions, microblock)?;
+            // Blocks at the edges are cut to the volume, so it has to hold whole microblocks too.
+            check_multiple(&format!("{}dimensions", prefix), input.dimensions, microblock)?;
+            let (row_size, _, _) = InputLayout::rows(input.dimensions, &input.input_format);
+            if let Some(row_stride) = input.layout.row_stride.filter(|stride| *stride < row_size) {
+                let field = if i == 0 { "input_layout".to_string() } else { format!("{}layout", prefix) };
+                return Err(ParametersError::RowStride(field, row_stride, row_size));
+            }
+            if let Some(rescale) = input.rescale {
+                let field = if i == 0 { "rescale".to_string() } else { format!("{}rescale", prefix) };
+                if !rescale.slope.is_finite() || !rescale.intercept.is_finite() {
+                    return Err(ParametersError::Rescale(field, "its slope and intercept must be finite".to_string()));
+                }
+                if !Rescale::supports(&input.input_format) {
+                    return Err(ParametersError::Rescale(field, "only mono formats with native component types can be rescaled".to_string()));
+                }
+                // The encoded and the tiled volumes are never held as voxels that could be rescaled.
+                if self.texture_compression.is_some() {
+                    return Err(ParametersError::Rescale(field, "cannot be used with texture compression".to_string()));
+                }
+                if i == 0 && self.tiles.is_some() {
+                    return Err(ParametersError::Rescale(field, "cannot be used with a tiled input".to_string()));
+                }
+            }
+            if !input.filters.is_empty() {
+                let field = if i == 0 { "filters".to_string() } else { format!("{}filters", prefix) };
+                for filter in &input.filters {
+                    filter.check().map_err(|e| ParametersError::Filters(field.clone(), e.to_string()))?;
+                }
+                // Rescaled voxels are always in a format that can be filtered.
+                if input.rescale.is_none() && !filters::supports(&input.input_format) {
+                    return Err(ParametersError::Filters(field, "only mono formats with native component types can be filtered".to_string()));
+                }
+                if i == 0 && self.tiles.is_some() {
+                    return Err(ParametersError::Filters(field, "cannot be used with a tiled input".to_string()));
+                }
+            }
+        }
+        // Tiles are read in place, without a layout.
+        if self.tiles.is_some() && !self.input_layout.is_packed(self.dimensions, &self.input_format) {
+            return Err(ParametersError::TiledLayout);
+        }
+        // The digest is computed of the whole volume, which tiled inputs never hold.
+        if self.tiles.is_some() && self.volume_checksum.is_some() {
+            return Err(ParametersError::TiledVolumeChecksum);
+        }
+        if self.signing_key.is_some() && !self.checksum.is_some_and(|c| c.is_cryptographic()) {
+            return Err(ParametersError::SigningChecksum);
+        }
+        if self.tiles.is_some() && self.record_source {
+            return Err(ParametersError::TiledSource);
+        }
+        for i in 0..3 {
+            if self.block_dimensions[i] > self.dimensions[i] {
+                return Err(ParametersError::BlockLargerThanVolume(self.block_dimensions, self.dimensions));
+            }
+        }
+
+        // Every level has to hold whole superblocks of the level below, and the innermost whole blocks.
+        for (i, level) in self.superblock_dimensions.iter().enumerate() {
+            let field = format!("superblock_dimensions[{}]", i);
+            let inner = self.superblock_dimensions.get(i + 1).copied().unwrap_or(self.block_dimensions);
+            check_dimensions(&field, *level)?;
+            check_multiple(&field, *level, inner)?;
+        }
+        if let Some(grid) = self.occupancy_grid {
+            check_dimensions("occupancy_grid", grid)?;
+            if grid.is_any_gt(Vector3::splat(MAX_OCCUPANCY_GRID)) {
+                return Err(ParametersError::OccupancyGrid(grid, MAX_OCCUPANCY_GRID));
+            }
+        }
+        return Ok(());
+    }
+
+    /// Checks the isosurface mask: it is made from voxels of the top level volume that can be read, and
+    /// masks with 1 bit per voxel need the X dimensions of the volume and the blocks to be multiples of 8.
+    /// * `mask` - the mask
+    fn validate_mask(&self, mask: &IsosurfaceMask) -> Result<(), ParametersError> {
+        let invalid = |message: &str| Err(ParametersError::IsosurfaceMask(message.to_string()));
+        if !mask.isovalue.is_finite() {
+            return invalid("the isovalue must be finite");
+        }
+        if mask.bits != 1 && mask.bits != 8 {
+            return invalid("masks have 1 or 8 bits per voxel");
+        }
+        // The mask is made from the voxels of the top level volume, as they are stored.
+        if self.texture_compression.is_some() || self.tiles.is_some() {
+            return invalid("cannot be made of texture compressed or tiled volumes");
+        }
+        let readable = match self.rescale {
+            Some(_) => true,
+            None => self.input_format.first_component().is_some() && self.input_format.microblock_dimensions == Vector3::from_xyz(1, 1, 1)
+        };
+        if !readable {
+            return invalid("cannot be made of volumes whose values cannot be read");
+        }
+        if mask.bits == 1 {
+            check_multiple("isosurface_mask", self.dimensions, BITMASK_MICROBLOCK_DIMENSIONS)?;
+            check_multiple("isosurface_mask", self.block_dimensions, BITMASK_MICROBLOCK_DIMENSIONS)?;
+        }
+        return Ok(());
+    }
+
+    /// Returns all volumes to convert, the one given by the top level options first.
+    pub fn modality_inputs(&self) -> Vec<ModalityInput> {
+        let mut inputs = vec![ModalityInput {
+            input_file: self.input_file.clone(),
+            dimensions: self.dimensions,
+            input_format: self.input_format.clone(),
+            layout: self.input_layout,
+            rescale: self.rescale,
+            filters: self.filters.clone(),
+            mask: None,
+            name: self.name.clone(),
+            description: self.description.clone(),
+            semantic_type: self.semantic_type.clone(),
+            volume_scale: self.volume_scale,
+            voxel_scale: self.voxel_scale,
+            transform: self.transform,
+            window: self.window.clone()
+        }];
+        inputs.extend(self.additional_modalities.iter().cloned());
+        if let Some(mask) = &self.isosurface_mask {
+            inputs.push(ModalityInput {
+                input_file: self.input_file.clone(),
+                dimensions: self.dimensions,
+                input_format: mask.format(),
+                layout: InputLayout::default(),
+                rescale: None,
+                filters: Vec::new(),
+                mask: Some(mask.clone()),
+                name: Some(mask.name.clone().unwrap_or_else(|| DEFAULT_MASK_NAME.to_string())),
+                description: None,
+                semantic_type: Some("mask".to_string()),
+                volume_scale: self.volume_scale,
+                voxel_scale: self.voxel_scale,
+                transform: self.transform,
+                window: Vec::new()
+            });
+        }
+        return inputs;
+    }
+}
+
+/// Returns an error if any of the dimensions is 0.
+/// * `field` - name of the dimensions in the parameters
+/// * `dimensions` - the dimensions
+fn check_dimensions(field: &str, dimensions: Vector3<u32>) -> Result<(), ParametersError> {
+    if dimensions.to_array().contains(&0) {
+        return Err(ParametersError::ZeroDimensions(field.to_string(), dimensions));
+    }
+    return Ok(());
+}
+
+/// Returns an error if the dimensions are not a multiple of the others in every direction.
+/// * `field` - name of the dimensions in the parameters
+/// * `dimensions` - the dimensions
+/// * `divisor` - dimensions they have to be a multiple of
+fn check_multiple(field: &str, dimensions: Vector3<u32>, divisor: Vector3<u32>) -> Result<(), ParametersError> {
+    for i in 0..3 {
+        if divisor[i] == 0 || dimensions[i] % divisor[i] != 0 {
+            return Err(ParametersError::NotMultiple(field.to_string(), dimensions, divisor));
+        }
+    }
+    return Ok(());
+}
+
+/// Builds the parameters of a conversion, for applications that convert volumes without writing a config file.
+/// Every value that is not set has the default of `raw2bvp`, and `build` validates the parameters.
+pub struct ParametersBuilder {
+    parameters: Parameters
+}
+
+impl ParametersBuilder {
+    fn new(input_file: String, output_file: String, dimensions: Vector3<u32>, block_dimensions: Vector3<u32>,
+        input_format: Format) -> Self
+    {
+        let parameters = Parameters {
+            input_file,
+            name: None,
+            description: None,
+            semantic_type: None,
+            volume_scale: Vector3::from_xyz(1.0, 1.0, 1.0),
+            voxel_scale: None,
+            transform: None,
+            window: Vec::new(),
+            output_file,
+            dimensions,
+            block_dimensions,
+            superblock_dimensions: Vec::new(),
+            input_format,
+            input_layout: InputLayout::default(),
+            rescale: None,
+            filters: Vec::new(),
+            isosurface_mask: None,
+            archive: ArchiveEnum::None,
+            archive_compression: MemberCompression::Stored,
+            compression: CompressionType::None,
+            author: None,
+            copyright: None,
+            acquisition_time: None,
+            generator: None,
+            threads: None,
+            queue_capacity: None,
+            compression_level: MAX_COMPRESSION_LEVEL,
+            checksum: None,
+            volume_checksum: None,
+            record_source: false,
+            allow_size_mismatch: false,
+            deduplication: true,
+            dedup_memory_blocks: DEFAULT_DEDUP_MEMORY_BLOCKS,
+            block_naming: BlockNaming::Index,
+            block_order: BlockOrder::Completion,
+            voxel_layout: VoxelLayout::Linear,
+            occupancy_grid: None,
+            texture_compression: None,
+            spec_version: SpecVersion::CURRENT,
+            additional_modalities: Vec::new(),
+            checkpoint: false,
+            signing_key: None,
+            tiles: None,
+            parallel_mode: ParallelMode::Pipeline,
+            settings_hash: 0
+        };
+        return Self { parameters };
+    }
+
+    /// Sets the name of the top level modality.
+    /// * `name` - the name
+    pub fn with_name(mut self, name: Option<String>) -> Self {
+        self.parameters.name = name;
+        return self;
+    }
+
+    /// Sets the description of the top level modality.
+    /// * `description` - the description
+    pub fn with_description(mut self, description: Option<String>) -> Self {
+        self.parameters.description = description;
+        return self;
+    }
+
+    /// Sets the semantic type of the top level modality, such as `CT`.
+    /// * `semantic_type` - the semantic type
+    pub fn with_semantic_type(mut self, semantic_type: Option<String>) -> Self {
+        self.parameters.semantic_type = semantic_type;
+        return self;
+    }
+
+    /// Sets the size of the top level volume in the scene. Defaults to 1 in every direction.
+    /// * `volume_scale` - the size
+    pub fn with_volume_scale(mut self, volume_scale: Vector3<f32>) -> Self {
+        self.parameters.volume_scale = volume_scale;
+        return self;
+    }
+
+    /// Sets the size of a voxel of the top level volume.
+    /// * `voxel_scale` - the size
+    pub fn with_voxel_scale(mut self, voxel_scale: Option<Vector3<f32>>) -> Self {
+        self.parameters.voxel_scale = voxel_scale;
+        return self;
+    }
+
+    /// Sets the orientation of the top level volume.
+    /// * `transform` - the transform
+    pub fn with_transform(mut self, transform: Option<Transform>) -> Self {
+        self.parameters.transform = transform;
+        return self;
+    }
+
+    /// Sets the window/level presets of the top level volume.
+    /// * `window` - the presets
+    pub fn with_window(mut self, window: Vec<WindowSetting>) -> Self {
+        self.parameters.window = window;
+        return self;
+    }
+
+    /// Sets the dimensions of the superblocks on each level, the outermost first.
+    /// * `superblock_dimensions` - the dimensions
+    pub fn with_superblock_dimensions(mut self, superblock_dimensions: Vec<Vector3<u32>>) -> Self {
+        self.parameters.superblock_dimensions = superblock_dimensions;
+        return self;
+    }
+
+    /// Sets the archive the asset is written to. Defaults to none, so its files are written to a folder.
+    /// * `archive` - the archive
+    pub fn with_archive(mut self, archive: ArchiveEnum) -> Self {
+        self.parameters.archive = archive;
+        return self;
+    }
+
+    /// Sets how members of ZIP archives are compressed.
+    /// * `archive_compression` - the compression
+    pub fn with_archive_compression(mut self, archive_compression: MemberCompression) -> Self {
+        self.parameters.archive_compression = archive_compression;
+        return self;
+    }
+
+    /// Sets the compression of the block files. Defaults to none.
+    /// * `compression` - the compression
+    pub fn with_compression(mut self, compression: CompressionType) -> Self {
+        self.parameters.compression = compression;
+        return self;
+    }
+
+    /// Sets the author of the asset.
+    /// * `author` - the author
+    pub fn with_author(mut self, author: Option<String>) -> Self {
+        self.parameters.author = author;
+        return self;
+    }
+
+    /// Sets the copyright of the asset.
+    /// * `copyright` - the copyright
+    pub fn with_copyright(mut self, copyright: Option<String>) -> Self {
+        self.parameters.copyright = copyright;
+        return self;
+    }
+
+    /// Sets when the volume was acquired.
+    /// * `acquisition_time` - the time
+    pub fn with_acquisition_time(mut self, acquisition_time: Option<String>) -> Self {
+        self.parameters.acquisition_time = acquisition_time;
+        return self;
+    }
+
+    /// Sets the `generator` of the asset.
+    /// * `generator` - the generator
+    pub fn with_generator(mut self, generator: Option<String>) -> Self {
+        self.parameters.generator = generator;
+        return self;
+    }
+
+    /// Sets the number of worker threads.
+    /// * `threads` - the number of threads
+    pub fn with_threads(mut self, threads: Option<usize>) -> Self {
+        self.parameters.threads = threads;
+        return self;
+    }
+
+    /// Sets the capacity of the queues between pipeline stages.
+    /// * `queue_capacity` - the capacity
+    pub fn with_queue_capacity(mut self, queue_capacity: Option<usize>) -> Self {
+        self.parameters.queue_capacity = queue_capacity;
+        return self;
+    }
+
+    /// Sets the compression level. Defaults to the highest.
+    /// * `compression_level` - the level
+    pub fn with_compression_level(mut self, compression_level: u32) -> Self {
+        self.parameters.compression_level = compression_level;
+        return self;
+    }
+
+    /// Sets the algorithm of the checksums of block data.
+    /// * `checksum` - the algorithm
+    pub fn with_checksum(mut self, checksum: Option<ChecksumType>) -> Self {
+        self.parameters.checksum = checksum;
+        return self;
+    }
+
+    /// Sets the algorithm of the digests recorded for the whole volume of every modality, or None for no digests.
+    /// * `volume_checksum` - the algorithm
+    pub fn with_volume_checksum(mut self, volume_checksum: Option<DigestType>) -> Self {
+        self.parameters.volume_checksum = volume_checksum;
+        return self;
+    }
+
+    /// Sets whether the size and hash of the top level input file are recorded in the asset.
+    /// * `record_source` - whether the source is recorded
+    pub fn with_record_source(mut self, record_source: bool) -> Self {
+        self.parameters.record_source = record_source;
+        return self;
+    }
+
+    /// Sets whether inputs whose files do not have the size of their volume are converted anyway.
+    /// * `allow_size_mismatch` - whether the sizes may differ
+    pub fn with_allow_size_mismatch(mut self, allow_size_mismatch: bool) -> Self {
+        self.parameters.allow_size_mismatch = allow_size_mismatch;
+        return self;
+    }
+
+    /// Sets whether blocks with the same data are stored only once.
+    /// * `deduplication` - whether blocks are deduplicated
+    pub fn with_deduplication(mut self, deduplication: bool) -> Self {
+        self.parameters.deduplication = deduplication;
+        return self;
+    }
+
+    /// Sets how many block hashes the deduplication map keeps in memory.
+    /// * `dedup_memory_blocks` - the number of hashes
+    pub fn with_dedup_memory_blocks(mut self, dedup_memory_blocks: usize) -> Self {
+        self.parameters.dedup_memory_blocks = dedup_memory_blocks;
+        return self;
+    }
+
+    /// Sets how block files are named.
+    /// * `block_naming` - the naming
+    pub fn with_block_naming(mut self, block_naming: BlockNaming) -> Self {
+        self.parameters.block_naming = block_naming;
+        return self;
+    }
+
+    /// Sets the order of the block files in the archive.
+    /// * `block_order` - the order
+    pub fn with_block_order(mut self, block_order: BlockOrder) -> Self {
+        self.parameters.block_order = block_order;
+        return self;
+    }
+
+    /// Sets the order of the voxels inside the blocks.
+    /// * `voxel_layout` - the layout
+    pub fn with_voxel_layout(mut self, voxel_layout: VoxelLayout) -> Self {
+        self.parameters.voxel_layout = voxel_layout;
+        return self;
+    }
+
+    /// Sets the GPU texture compression the voxels are encoded in.
+    /// * `texture_compression` - the compression
+    pub fn with_texture_compression(mut self, texture_compression: Option<TextureCompression>) -> Self {
+        self.parameters.texture_compression = texture_compression;
+        return self;
+    }
+
+    /// Sets the version of the specification the manifest is written in.
+    /// * `spec_version` - the version
+    pub fn with_spec_version(mut self, spec_version: SpecVersion) -> Self {
+        self.parameters.spec_version = spec_version;
+        return self;
+    }
+
+    /// Sets the key the manifest is signed with.
+    /// * `signing_key` - the key
+    pub fn with_signing_key(mut self, signing_key: Option<SigningKey>) -> Self {
+        self.parameters.signing_key = signing_key;
+        return self;
+    }
+
+    /// Sets the tiles of the top level volume, if `input_file` is the index of a tiled volume.
+    /// * `tiles` - the tiles
+    pub fn with_tiles(mut self, tiles: Option<TiledVolume>) -> Self {
+        self.parameters.tiles = tiles;
+        return self;
+    }
+
+    /// Sets whether a journal is kept so an interrupted conversion can be resumed.
+    /// * `checkpoint` - whether a journal is kept
+    pub fn with_checkpoint(mut self, checkpoint: bool) -> Self {
+        self.parameters.checkpoint = checkpoint;
+        return self;
+    }
+
+    /// Sets how the conversion is parallelized.
+    /// * `parallel_mode` - the strategy
+    pub fn with_parallel_mode(mut self, parallel_mode: ParallelMode) -> Self {
+        self.parameters.parallel_mode = parallel_mode;
+        return self;
+    }
+
+    /// Sets the hash of the settings that change the output. A checkpoint is only resumed by a conversion with the same hash.
+    /// * `settings_hash` - the hash
+    pub fn with_settings_hash(mut self, settings_hash: u64) -> Self {
+        self.parameters.settings_hash = settings_hash;
+        return self;
+    }
+
+    /// Sets where the voxels are in the input file, for files with a header or padding.
+    /// * `input_layout` - the layout
+    pub fn with_input_layout(mut self, input_layout: InputLayout) -> Self {
+        self.parameters.input_layout = input_layout;
+        return self;
+    }
+
+    /// Sets the map applied to the voxel values of the top level volume as they are read.
+    /// * `rescale` - the map
+    pub fn with_rescale(mut self, rescale: Option<Rescale>) -> Self {
+        self.parameters.rescale = rescale;
+        return self;
+    }
+
+    /// Sets the filters applied to the voxels of the top level volume, in order.
+    /// * `filters` - the filters
+    pub fn with_filters(mut self, filters: Vec<Filter>) -> Self {
+        self.parameters.filters = filters;
+        return self;
+    }
+
+    /// Sets the number of cells along each axis of the blocks in their occupancy bitmaps, or None for no bitmaps.
+    /// * `occupancy_grid` - the number of cells
+    pub fn with_occupancy_grid(mut self, occupancy_grid: Option<Vector3<u32>>) -> Self {
+        self.parameters.occupancy_grid = occupancy_grid;
+        return self;
+    }
+
+    /// Sets the mask of the top level volume that is stored as the last modality.
+    /// * `isosurface_mask` - the mask
+    pub fn with_isosurface_mask(mut self, isosurface_mask: Option<IsosurfaceMask>) -> Self {
+        self.parameters.isosurface_mask = isosurface_mask;
+        return self;
+    }
+
+    /// Adds a volume that is converted into a further modality of the asset.
+    /// * `modality` - the volume
+    pub fn with_modality(mut self, modality: ModalityInput) -> Self {
+        self.parameters.additional_modalities.push(modality);
+        return self;
+    }
+
+    /// Validates the parameters and returns them, see `Parameters::validate`.
+    pub fn build(self) -> Result<Parameters, ParametersError> {
+        self.parameters.validate()?;
+        return Ok(self.parameters);
+    }
+}
@@ -0,0 +1,441 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use tinyjson::JsonValue;
+use xxhash_rust::xxh3;
+
+use crate::archives::ArchiveEnum;
+use crate::block::Block;
+use crate::bvpfile::BVPFile;
+use crate::checksum::Checksum;
+use crate::dedup::ShardedBlockMap;
+use crate::file::File;
+use crate::vector3::Vector3;
+use crate::{log_debug, log_info};
+use crate::convert::Parameters;
+use crate::convert::raw_to_bvp::{block_file_name, extract_block_data, set_block_occupancy, BlockRange, StoredBlock};
+
+/// Name of the journal in the checkpoint folder.
+const JOURNAL_NAME: &str = "journal.jsonl";
+/// Name of the file in the checkpoint folder holding the data of stored blocks, one after another.
+const DATA_NAME: &str = "blocks.bin";
+/// Changes when the journal is written differently, so older journals are not resumed.
+const JOURNAL_VERSION: u32 = 3;
+/// The data and the journal are synced to the disk after this many block ranges,
+/// or this many bytes of data, whichever comes first.
+const SYNC_RANGES: usize = 1024;
+const SYNC_BYTES: u64 = 64 << 20;
+
+/// Root block and start of a block range, which identify it among the ranges of a conversion.
+type RangeKey = (usize, u32, u32, u32);
+
+fn range_key(range: &BlockRange) -> RangeKey {
+    return (range.0, range.1.x, range.1.y, range.1.z);
+}
+
+/// What an earlier run did with a block range.
+#[derive(Clone, Copy)]
+pub enum JournalEntry {
+    /// Stored as a new block, with the hash of its data before compression,
+    /// and where its file is in the data file of the checkpoint.
+    Stored { block: usize, hash: u64, offset: u64, length: u64 },
+    /// Placed as a duplicate of a stored block.
+    Duplicate { block: usize }
+}
+
+/// A journal as read from the checkpoint folder.
+enum Journal {
+    /// Written by a conversion with other inputs or settings.
+    Other,
+    /// Of an interrupted conversion, with the ranges it finished.
+    Partial(HashMap<RangeKey, JournalEntry>),
+    /// Of a finished conversion, with the hash of its output.
+    Complete(String)
+}
+
+/// What has to be done in a conversion with checkpoints.
+pub enum CheckpointState {
+    /// A finished conversion with the same inputs and settings wrote the output, and it is unchanged.
+    UpToDate,
+    Active(Arc<Checkpoint>)
+}
+
+/// Journal of a conversion with `checkpoint` enabled, kept in the folder `<outputFile>.checkpoint`.
+///
+/// Archives are only written when the conversion finishes, so every stored block is also
+/// appended to a data file in the folder as it is compressed, and every finished block range
+/// to the journal. Both are buffered, and synced to the disk every `SYNC_RANGES` ranges or
+/// `SYNC_BYTES` bytes, the data before the journal, so the journal never refers to data that is
+/// not on the disk. An interrupted conversion with the same inputs and settings continues from
+/// the journal: finished ranges are not extracted and compressed again, their blocks are read
+/// from the data file. When the conversion finishes, the data is deleted and the journal only
+/// keeps the hash of the output, so running it again does nothing while the output is unchanged.
+pub struct Checkpoint {
+    folder: PathBuf,
+    fingerprint: String,
+    files: Mutex<CheckpointFiles>,
+    /// The data file opened for reading the blocks of an earlier run.
+    previous_data: Mutex<fs::File>,
+    previous: HashMap<RangeKey, JournalEntry>
+}
+
+/// The files a checkpoint appends to, with what has not been synced yet.
+struct CheckpointFiles {
+    data: BufWriter<fs::File>,
+    data_length: u64,
+    journal: fs::File,
+    /// Journal lines of ranges whose data may not be on the disk yet.
+    pending: String,
+    unsynced_ranges: usize,
+    unsynced_bytes: u64
+}
+
+impl CheckpointFiles {
+    /// Syncs the data file, then writes the pending journal lines and syncs the journal.
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.data.flush()?;
+        self.data.get_ref().sync_data()?;
+        if !self.pending.is_empty() {
+            self.journal.write_all(self.pending.as_bytes())?;
+            self.journal.sync_data()?;
+            self.pending.clear();
+        }
+        self.unsynced_ranges = 0;
+        self.unsynced_bytes = 0;
+        return Ok(());
+    }
+}
+
+/// Returns a hash of everything the output depends on: the settings and the input data.
+/// * `parameters` - parsed conversion parameters
+/// * `bvp_file` - BVPFile holding the root blocks with the input data
+/// * `root_count` - number of root blocks
+fn fingerprint(parameters: &Parameters, bvp_file: &BVPFile, root_count: usize) -> String {
+    let mut text = format!("{}:{:016x}", JOURNAL_VERSION, parameters.settings_hash);
+    for root_block in &bvp_file.blocks[..root_count] {
+        let data = root_block.data.as_ref().map(|d| d.as_slice()).unwrap_or(&[]);
+        text.push_str(&format!(":{:016x}", xxh3::xxh3_64(data)));
+    }
+    return format!("{:016x}", xxh3::xxh3_64(text.as_bytes()));
+}
+
+/// Returns the hash of the output as it is now, or `None` if it cannot be read:
+/// the archive, or the manifest of unarchived assets.
+/// * `parameters` - parsed conversion parameters
+fn output_hash(parameters: &Parameters) -> Option<String> {
+    let path = match parameters.archive {
+        ArchiveEnum::None => PathBuf::from(&parameters.output_file).join("manifest.json"),
+        _ => PathBuf::from(&parameters.output_file)
+    };
+    return fs::read(path).ok().map(|data| format!("{:016x}", xxh3::xxh3_64(&data)));
+}
+
+/// Reads the entries of a journal. Lines that cannot be read, like the last one
+/// of an interrupted write, are skipped.
+/// * `text` - contents of the journal
+/// * `fingerprint` - fingerprint of the current conversion
+fn parse_journal(text: &str, fingerprint: &str) -> Journal {
+    let mut lines = text.lines().map(|line| line.parse::<JsonValue>().ok());
+    let header = lines.next().flatten();
+    let header_fingerprint = header.as_ref()
+        .and_then(|h| h.get::<HashMap<String, JsonValue>>())
+        .and_then(|h| h.get("fingerprint"))
+        .and_then(|f| f.get::<String>());
+    if header_fingerprint.map(|f| f.as_str()) != Some(fingerprint) {
+        return Journal::Other;
+    }
+
+    let mut entries = HashMap::new();
+    for line in lines.flatten() {
+        let object = match line.get::<HashMap<String, JsonValue>>() {
+            Some(o) => o,
+            None => continue
+        };
+        if let Some(hash) = object.get("complete").and_then(|c| c.get::<String>()) {
+            return Journal::Complete(hash.clone());
+        }
+        let number = |key: &str| object.get(key).and_then(|v| v.get::<f64>()).map(|n| *n as usize);
+        let start = object.get("start").and_then(|s| Vector3::<u32>::from_json(s).ok());
+        let (root, start) = match (number("root"), start) {
+            (Some(r), Some(s)) => (r, s),
+            _ => continue
+        };
+        let key = (root, start.x, start.y, start.z);
+        if let Some(block) = number("duplicateOf") {
+            entries.insert(key, JournalEntry::Duplicate { block });
+        } else if let (Some(block), Some(hash), Some(offset), Some(length)) =
+            (number("block"), object.get("hash").and_then(|h| h.get::<String>()), number("offset"), number("length"))
+        {
+            if let Ok(hash) = u64::from_str_radix(hash, 16) {
+                entries.insert(key, JournalEntry::Stored { block, hash, offset: offset as u64, length: length as u64 });
+            }
+        }
+    }
+    return Journal::Partial(entries);
+}
+
+/// Returns the line of the journal for a block range.
+/// * `range` - the block range
+/// * `entry` - what was done with it
+fn entry_line(range: RangeKey, entry: &JournalEntry) -> String {
+    let (root, x, y, z) = range;
+    let mut hm = HashMap::new();
+    hm.insert("root".to_string(), JsonValue::from(root as f64));
+    hm.insert("start".to_string(), Vector3::from_xyz(x, y, z).to_json());
+    match entry {
+        JournalEntry::Stored { block, hash, offset, length } => {
+            hm.insert("block".to_string(), JsonValue::from(*block as f64));
+            hm.insert("hash".to_string(), JsonValue::from(format!("{:016x}", hash)));
+            hm.insert("offset".to_string(), JsonValue::from(*offset as f64));
+            hm.insert("length".to_string(), JsonValue::from(*length as f64));
+        },
+        JournalEntry::Duplicate { block } => {
+            hm.insert("duplicateOf".to_string(), JsonValue::from(*block as f64));
+        }
+    }
+    return json_line(hm);
+}
+
+fn json_line(hm: HashMap<String, JsonValue>) -> String {
+    return format!("{}\n", JsonValue::from(hm).stringify().unwrap_or_default());
+}
+
+impl Checkpoint {
+    /// Reads the journal of an earlier run and prepares the checkpoint folder.
+    /// A journal of other inputs or settings is discarded, so is one of a finished
+    /// conversion whose output has changed since.
+    /// * `parameters` - parsed conversion parameters
+    /// * `bvp_file` - BVPFile holding the root blocks with the input data
+    /// * `root_count` - number of root blocks
+    pub fn open(parameters: &Parameters, bvp_file: &BVPFile, root_count: usize) -> Result<CheckpointState, String> {
+        let folder = PathBuf::from(format!("{}.checkpoint", parameters.output_file));
+        let journal_path = folder.join(JOURNAL_NAME);
+        let fingerprint = fingerprint(parameters, bvp_file, root_count);
+
+        let mut previous = match fs::read_to_string(&journal_path) {
+            Err(_) => HashMap::new(),
+            Ok(text) => match parse_journal(&text, &fingerprint) {
+                Journal::Other => {
+                    log_info!("{} is of other inputs or settings, starting over", journal_path.display());
+                    HashMap::new()
+                },
+                Journal::Complete(hash) => {
+                    if output_hash(parameters).as_deref() == Some(hash.as_str()) {
+                        return Ok(CheckpointState::UpToDate);
+                    }
+                    log_info!("{} has changed since it was written, starting over", parameters.output_file);
+                    HashMap::new()
+                },
+                Journal::Partial(entries) => entries
+            }
+        };
+        if previous.is_empty() {
+            let _ = fs::remove_dir_all(&folder);
+        }
+        fs::create_dir_all(&folder).map_err(|e| format!("Cannot create {}: {}", folder.display(), e))?;
+
+        // Blocks of the interrupted run only count if their data was written, and so do the duplicates of them.
+        // They keep their indices, which only depend on the position of their range.
+        let data_path = folder.join(DATA_NAME);
+        let data_length = fs::metadata(&data_path).map(|m| m.len()).unwrap_or(0);
+        previous.retain(|_, entry| match entry {
+            JournalEntry::Stored { offset, length, .. } => offset.checked_add(*length).is_some_and(|end| end <= data_length),
+            JournalEntry::Duplicate { .. } => true
+        });
+        let stored: HashSet<usize> = previous.values()
+            .filter_map(|entry| match entry {
+                JournalEntry::Stored { block, .. } => Some(*block),
+                JournalEntry::Duplicate { .. } => None
+            })
+            .collect();
+        previous.retain(|_, entry| match entry {
+            JournalEntry::Duplicate { block } => stored.contains(block),
+            JournalEntry::Stored { .. } => true
+        });
+        if !previous.is_empty() {
+            log_info!("resuming from {}, {} block ranges are done", journal_path.display(), previous.len());
+        }
+
+        // Data after the last block in the journal was written after the last sync, and is dropped.
+        let data_length = previous.values()
+            .filter_map(|entry| match entry {
+                JournalEntry::Stored { offset, length, .. } => Some(offset + length),
+                JournalEntry::Duplicate { .. } => None
+            })
+            .max()
+            .unwrap_or(0);
+        let data = OpenOptions::new().create(true).append(true).open(&data_path)
+            .and_then(|file| file.set_len(data_length).and_then(|_| file.sync_all()).map(|_| file))
+            .map_err(|e| format!("Cannot open {}: {}", data_path.display(), e))?;
+        let previous_data = fs::File::open(&data_path).map_err(|e| format!("Cannot open {}: {}", data_path.display(), e))?;
+
+        // The journal is written again without the entries that were dropped, so a second interruption resumes as well.
+        let mut hm = HashMap::new();
+        hm.insert("fingerprint".to_string(), JsonValue::from(fingerprint.clone()));
+        let mut text = json_line(hm);
+        for (key, entry) in &previous {
+            text.push_str(&entry_line(*key, entry));
+        }
+        write_synced(&folder, &journal_path, &text)?;
+        let journal = OpenOptions::new().append(true).open(&journal_path)
+            .map_err(|e| format!("Cannot open {}: {}", journal_path.display(), e))?;
+
+        let files = CheckpointFiles {
+            data: BufWriter::new(data),
+            data_length,
+            journal,
+            pending: String::new(),
+            unsynced_ranges: 0,
+            unsynced_bytes: 0
+        };
+        return Ok(CheckpointState::Active(Arc::new(Self {
+            folder,
+            fingerprint,
+            files: Mutex::new(files),
+            previous_data: Mutex::new(previous_data),
+            previous
+        })));
+    }
+
+    /// Adds the restored blocks to the deduplication map, so new blocks with the same data become duplicates.
+    /// * `ranges` - all block ranges of the conversion
+    /// * `block_map` - the deduplication map
+    pub fn restore_block_map(&self, ranges: &[BlockRange], block_map: &ShardedBlockMap<StoredBlock>) -> Result<(), String> {
+        for range in ranges {
+            if let Some(JournalEntry::Stored { block, hash, .. }) = self.previous.get(&range_key(range)) {
+                block_map.insert(*hash, StoredBlock { range: *range, data: None }, *block).map_err(|e| e.to_string())?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Returns what an earlier run did with a block range, if it finished it.
+    /// * `range` - the block range
+    pub fn previous(&self, range: &BlockRange) -> Option<JournalEntry> {
+        return self.previous.get(&range_key(range)).copied();
+    }
+
+    /// Returns the block and the block file of a range stored by an earlier run.
+    /// * `range` - the block range
+    /// * `block_index` - index of the block, as returned by `previous`
+    /// * `bvp_file` - BVPFile holding the root blocks
+    /// * `parameters` - parsed conversion parameters
+    pub fn restore_block(&self, range: &BlockRange, block_index: usize, bvp_file: &BVPFile, parameters: &Parameters) -> Result<(Block, File), String> {
+        let path = self.folder.join(DATA_NAME);
+        let (offset, length) = match self.previous.get(&range_key(range)) {
+            Some(JournalEntry::Stored { offset, length, .. }) => (*offset, *length),
+            _ => return Err(format!("Block {} is not in {}", block_index, path.display()))
+        };
+        let mut data = vec![0; length as usize];
+        {
+            let mut file = self.previous_data.lock().map_err(|_| "The checkpoint lock has been poisoned".to_string())?;
+            file.seek(SeekFrom::Start(offset))
+                .and_then(|_| file.read_exact(&mut data))
+                .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        }
+        let (root_block_index, block_start, block_end) = *range;
+        let mut block = Block::new(block_index, block_end - block_start, bvp_file.blocks[root_block_index].format, None);
+        let name = block_file_name(parameters.block_naming, block_index, &data, parameters.compression);
+        block.encoding = Some(parameters.compression);
+        block.data_url = Some(name.clone());
+        block.checksum = parameters.checksum.map(|algorithm| Checksum::compute(algorithm, &data));
+        // The journal does not keep occupancy, so it is computed from the input again.
+        if parameters.occupancy_grid.is_some() {
+            let block_data = extract_block_data(bvp_file, parameters.tiles.as_ref(), range)?;
+            let format = &bvp_file.formats[block.format.unwrap()];
+            set_block_occupancy(&mut block, &block_data, format, parameters)?;
+        }
+        log_debug!("block {} at {} restored from {}", block_index, block_start, path.display());
+        return Ok((block, File::new(name, Arc::new(data), None)));
+    }
+
+    /// Records a finished block range, with the data of its block if it stored one,
+    /// and syncs the files when enough is written.
+    /// * `range` - the block range
+    /// * `data` - the block file of a stored block
+    /// * `entry` - the journal entry, given the offset of the data
+    fn record(&self, range: &BlockRange, data: &[u8], entry: impl FnOnce(u64) -> JournalEntry) -> Result<(), String> {
+        let mut files = self.files.lock().map_err(|_| "The checkpoint lock has been poisoned".to_string())?;
+        let offset = files.data_length;
+        if !data.is_empty() {
+            files.data.write_all(data).map_err(|e| format!("Cannot write {}: {}", self.folder.join(DATA_NAME).display(), e))?;
+        }
+        files.data_length += data.len() as u64;
+        files.pending.push_str(&entry_line(range_key(range), &entry(offset)));
+        files.unsynced_ranges += 1;
+        files.unsynced_bytes += data.len() as u64;
+        if files.unsynced_ranges >= SYNC_RANGES || files.unsynced_bytes >= SYNC_BYTES {
+            files.sync().map_err(|e| format!("Cannot write the checkpoint in {}: {}", self.folder.display(), e))?;
+        }
+        return Ok(());
+    }
+
+    /// Records a new block. Its data is synced to the disk before the journal line.
+    /// * `range` - the block range
+    /// * `block_index` - index of the block
+    /// * `hash` - hash of the data before compression, used for deduplication
+    /// * `data` - the block file, as stored in the archive
+    pub fn record_stored(&self, range: &BlockRange, block_index: usize, hash: u64, data: &[u8]) -> Result<(), String> {
+        let length = data.len() as u64;
+        return self.record(range, data, |offset| JournalEntry::Stored { block: block_index, hash, offset, length });
+    }
+
+    /// Records a block range placed as a duplicate.
+    /// * `range` - the block range
+    /// * `block_index` - index of the block with the same data
+    pub fn record_duplicate(&self, range: &BlockRange, block_index: usize) -> Result<(), String> {
+        return self.record(range, &[], |_| JournalEntry::Duplicate { block: block_index });
+    }
+
+    /// Syncs the ranges recorded so far to the disk, so a conversion that is stopped afterwards resumes from them.
+    pub fn sync(&self) -> Result<(), String> {
+        let mut files = self.files.lock().map_err(|_| "The checkpoint lock has been poisoned".to_string())?;
+        return files.sync().map_err(|e| format!("Cannot write the checkpoint in {}: {}", self.folder.display(), e));
+    }
+
+    /// Marks the conversion as finished: the block files are deleted and the journal
+    /// only keeps the hash of the output.
+    /// * `parameters` - parsed conversion parameters
+    pub fn complete(&self, parameters: &Parameters) -> Result<(), String> {
+        let mut header = HashMap::new();
+        header.insert("fingerprint".to_string(), JsonValue::from(self.fingerprint.clone()));
+        let mut text = json_line(header);
+        if let Some(hash) = output_hash(parameters) {
+            let mut hm = HashMap::new();
+            hm.insert("complete".to_string(), JsonValue::from(hash));
+            text.push_str(&json_line(hm));
+        }
+        // Nothing recorded is needed anymore.
+        if let Ok(mut files) = self.files.lock() {
+            files.pending.clear();
+        }
+        let _ = fs::remove_dir_all(&self.folder);
+        fs::create_dir_all(&self.folder).map_err(|e| format!("Cannot create {}: {}", self.folder.display(), e))?;
+        return write_synced(&self.folder, &self.folder.join(JOURNAL_NAME), &text);
+    }
+}
+
+impl Drop for Checkpoint {
+    /// Syncs what is left when the conversion stops, also when it fails.
+    fn drop(&mut self) {
+        if let Ok(mut files) = self.files.lock() {
+            let _ = files.sync();
+        }
+    }
+}
+
+/// Replaces a file in the checkpoint folder with text that is on the disk when this returns,
+/// by writing and syncing a temporary file and renaming it.
+/// * `folder` - the checkpoint folder
+/// * `path` - the file to replace
+/// * `text` - its new contents
+fn write_synced(folder: &PathBuf, path: &PathBuf, text: &str) -> Result<(), String> {
+    let temporary_path = folder.join(format!("{}.tmp", JOURNAL_NAME));
+    return fs::File::create(&temporary_path)
+        .and_then(|mut file| file.write_all(text.as_bytes()).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&temporary_path, path))
+        .map_err(|e| format!("Cannot write {}: {}", path.display(), e));
+}
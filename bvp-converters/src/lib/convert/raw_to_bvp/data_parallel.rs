@@ -0,0 +1,235 @@
+use std::time::Instant;
+
+use crossbeam::scope;
+use xxhash_rust::xxh3;
+
+use crate::archives::ArchiveWriter;
+use crate::block::Block;
+use crate::bytes::Bytes;
+use crate::checksum::Checksum;
+use crate::dedup::{DedupResult, ShardedBlockMap};
+use crate::file::File;
+use crate::metrics::{MetricsSink, PipelineMetrics, PipelineStage};
+use crate::log::Span;
+use crate::log_debug;
+use crate::progress::ProgressSink;
+use crate::tree::BlockTreeBuilder;
+use crate::convert::Parameters;
+use crate::convert::raw_to_bvp::{dedup_map, initialize_bvp_file, open_checkpoint, worker_count, finalize_bvp_file, block_index, block_ranges, is_same_block, extract_block_data, set_block_occupancy, block_file_name, BlockFiles, JournalEntry, StoredBlock};
+
+/// How many blocks each worker gets per batch, unless the queue capacity is set. Only one batch of blocks is held
+/// in memory at a time, so this bounds memory use while keeping workers busy.
+const BLOCKS_PER_WORKER_IN_BATCH: usize = 16;
+
+/// Applies `f` to all items on `worker_count` threads
+/// and returns the results in the same order as the items.
+/// * `items` - items to process, split into one contiguous chunk per worker
+/// * `worker_count` - number of threads to use
+/// * `f` - function to apply to every item
+fn parallel_map<T, R, F>(items: Vec<T>, worker_count: usize, f: F) -> Vec<R>
+    where T: Send, R: Send, F: Fn(T) -> R + Sync
+{
+    let chunk_size = items.len().div_ceil(worker_count.max(1)).max(1);
+    let mut chunks: Vec<Vec<T>> = Vec::new();
+    let mut items = items.into_iter();
+    loop {
+        let chunk: Vec<T> = items.by_ref().take(chunk_size).collect();
+        if chunk.is_empty() {
+            break;
+        }
+        chunks.push(chunk);
+    }
+
+    let f = &f;
+    return scope(|scope| {
+        let handles: Vec<_> = chunks.into_iter()
+            .map(|chunk| scope.spawn(move |_| chunk.into_iter().map(f).collect::<Vec<R>>()))
+            .collect();
+
+        handles.into_iter()
+            .flat_map(|handle| handle.join().expect("A data parallel worker panicked."))
+            .collect()
+    })
+        .expect("Scope failed to execute.");
+}
+
+/// Converts the input volume by processing the block grid in batches.
+///
+/// Every batch goes through three steps:
+///   - blocks are extracted from the root blocks and hashed in parallel,
+///   - hashes are deduplicated on the calling thread in grid order,
+///     so the block indices do not depend on thread timing,
+///   - unique blocks are compressed in parallel and then written in grid order.
+///
+/// This is simpler than the crossbeam pipeline in `raw_to_bvp_parallel`,
+/// at the cost of workers idling while a batch is deduplicated and written.
+/// Returns where the conversion spent its time, and how much it read and wrote.
+pub(crate) fn raw_to_bvp_data_parallel(
+    parameters: &Parameters,
+    writer: &mut (dyn ArchiveWriter + Send),
+    progress: &dyn ProgressSink,
+) -> Result<PipelineMetrics, String> {
+    let metrics = MetricsSink::new(progress);
+    let progress: &dyn ProgressSink = &metrics;
+
+    let worker_count = worker_count(parameters)?;
+    let batch_size = parameters.queue_capacity
+        .unwrap_or(worker_count * BLOCKS_PER_WORKER_IN_BATCH);
+
+    let inputs = parameters.modality_inputs();
+    let bvp_file = initialize_bvp_file(&inputs, parameters, worker_count, progress)?;
+    let encoding = parameters.compression;
+    let checkpoint = match open_checkpoint(parameters, &bvp_file, inputs.len())? {
+        Some(c) => c,
+        None => return Ok(metrics.metrics())
+    };
+
+    let block_ranges = block_ranges(&inputs, parameters.block_dimensions);
+    progress.start(block_ranges.len());
+
+    // The map is shared by all modalities, so blocks repeated in different modalities are stored once.
+    let block_map: ShardedBlockMap<StoredBlock> = dedup_map(parameters, block_ranges.len());
+    if let (Some(checkpoint), true) = (&checkpoint, parameters.deduplication) {
+        checkpoint.restore_block_map(&block_ranges, &block_map)?;
+    }
+    let mut tree = BlockTreeBuilder::new(inputs.len());
+
+    let started = Instant::now();
+    let mut block_files = BlockFiles::new(parameters);
+    {
+        let _span = Span::enter("batches");
+        log_debug!("{} blocks in batches of {} on {} workers", block_ranges.len(), batch_size, worker_count);
+
+        for (batch_index, batch) in block_ranges.chunks(batch_size).enumerate() {
+            // Extract and hash blocks in parallel.
+            let extracted_blocks = parallel_map(batch.to_vec(), worker_count, |range| {
+                // Ranges finished by an interrupted run are taken from the checkpoint.
+                if checkpoint.as_ref().is_some_and(|c| c.previous(&range).is_some()) {
+                    return Ok(None);
+                }
+                let (_, block_start, block_end) = range;
+                let timer = Instant::now();
+                let block_data = Bytes::from(extract_block_data(&bvp_file, parameters.tiles.as_ref(), &range)?);
+                progress.work_done(PipelineStage::Extract, timer.elapsed());
+                // Without deduplication, nothing needs to be hashed.
+                let timer = Instant::now();
+                let block_data_hash = if parameters.deduplication { xxh3::xxh3_64(block_data.as_slice()) } else { 0 };
+                progress.work_done(PipelineStage::Deduplicate, timer.elapsed());
+                Ok::<_, String>(Some((block_end - block_start, block_data, block_data_hash)))
+            });
+
+            // Deduplicate in grid order. On hash collisions, the raw data is compared
+            // with the data the map shares with the stored block.
+            let mut unique_blocks = Vec::new();
+            let mut new_blocks = Vec::new();
+            let mut restored_files = Vec::new();
+            for (batch_position, (range, extracted_block)) in batch.iter().zip(extracted_blocks).enumerate() {
+                let (root_block_index, block_start, _) = *range;
+                let new_block_index = block_index(inputs.len(), batch_index * batch_size + batch_position);
+                let (block_dimensions, block_data, block_data_hash) = match (extracted_block?, &checkpoint) {
+                    (Some(extracted), _) => extracted,
+                    (None, Some(checkpoint)) => {
+                        match checkpoint.previous(range) {
+                            Some(JournalEntry::Stored { block: block_id, .. }) => {
+                                let (restored_block, file) = checkpoint.restore_block(range, block_id, &bvp_file, parameters)?;
+                                let format = &bvp_file.formats[restored_block.format.unwrap()];
+                                progress.block_stored(format.checked_count_space(restored_block.dimensions).unwrap_or(0), file.data.len());
+                                progress.block_processed(false);
+                                // Restored blocks already have their file name and checksum.
+                                tree.add_block(root_block_index, block_start, restored_block);
+                                restored_files.push(file);
+                            },
+                            Some(JournalEntry::Duplicate { block: block_id }) => {
+                                tree.add_placement(root_block_index, block_start, block_id);
+                                progress.block_processed(true);
+                            },
+                            None => unreachable!("only ranges in the checkpoint are skipped")
+                        };
+                        continue;
+                    },
+                    (None, None) => unreachable!("ranges are only skipped with a checkpoint")
+                };
+                let format_index = bvp_file.blocks[root_block_index].format.unwrap();
+
+                let timer = Instant::now();
+                let dedup_result = if parameters.deduplication {
+                    let stored = StoredBlock { range: *range, data: Some(block_data.clone()) };
+                    block_map.find_or_insert_as(block_data_hash, stored, new_block_index, |entry| {
+                        return is_same_block(&bvp_file, parameters.tiles.as_ref(), &entry.key, range, &block_data);
+                    }).map_err(|e| e.to_string())?
+                } else {
+                    DedupResult::New(new_block_index)
+                };
+                progress.work_done(PipelineStage::Deduplicate, timer.elapsed());
+
+                let block_id = match dedup_result {
+                    DedupResult::Existing(same_hash_block_id) => {
+                        if let Some(checkpoint) = &checkpoint {
+                            checkpoint.record_duplicate(range, same_hash_block_id)?;
+                        }
+                        tree.add_placement(root_block_index, block_start, same_hash_block_id);
+                        progress.block_processed(true);
+                        continue;
+                    },
+                    DedupResult::New(block_id) => block_id,
+                };
+
+                let mut new_block = Block::new(
+                    block_id,
+                    block_dimensions,
+                    Some(format_index),
+                    None,
+                );
+                new_block.encoding = Some(encoding);
+                set_block_occupancy(&mut new_block, &block_data, &bvp_file.formats[format_index], parameters)?;
+
+                new_blocks.push((root_block_index, block_start, new_block));
+                unique_blocks.push((*range, block_id, block_data, block_data_hash));
+                progress.block_processed(false);
+            }
+
+            // Compress unique blocks in parallel and write them in order.
+            let files = parallel_map(unique_blocks, worker_count, |(range, block_id, block_data, block_data_hash)| {
+                let decoded_size = block_data.len();
+                let timer = Instant::now();
+                let compressed_block_data = encoding.compress_shared(&block_data, parameters.compression_level);
+                progress.work_done(PipelineStage::Compress, timer.elapsed());
+                progress.block_stored(decoded_size, compressed_block_data.len());
+                if let Some(checkpoint) = &checkpoint {
+                    checkpoint.record_stored(&range, block_id, block_data_hash, &compressed_block_data)?;
+                }
+                let block_url = block_file_name(parameters.block_naming, block_id, &compressed_block_data, encoding);
+                Ok::<_, String>(File::new(block_url, compressed_block_data, None))
+            }).into_iter().collect::<Result<Vec<File>, String>>()?;
+            for ((root_block_index, block_start, mut block), file) in new_blocks.into_iter().zip(&files) {
+                block.data_url = Some(file.name.clone());
+                block.checksum = parameters.checksum.map(|algorithm| Checksum::compute(algorithm, &file.data));
+                tree.add_block(root_block_index, block_start, block);
+            }
+            for file in restored_files.iter().chain(&files) {
+                block_files.write(writer, file, progress)?;
+            }
+        }
+    }
+
+    progress.stage_finished("blocks", started.elapsed());
+    // Every block is done, so a failure while writing the asset resumes without converting any again.
+    if let Some(checkpoint) = &checkpoint {
+        checkpoint.sync()?;
+    }
+
+    finalize_bvp_file(
+        writer,
+        block_files,
+        bvp_file,
+        block_map.into_block_map().map_err(|e| e.to_string())?,
+        tree,
+        parameters,
+        progress,
+    )?;
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.complete(parameters)?;
+    }
+
+    Ok(metrics.metrics())
+}
@@ -0,0 +1,743 @@
+mod checkpoint;
+mod data_parallel;
+mod parallel;
+mod sequential;
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::thread::available_parallelism;
+use std::time::Instant;
+
+use itertools::iproduct;
+use xxhash_rust::xxh3;
+
+use crate::archives::{ArchiveWriter, STDIO_PATH};
+use crate::block::Block;
+use crate::bytes::Bytes;
+use crate::compressions::{CompressionType, FileCompression};
+use crate::dedup::{ShardedBlockMap, SpillKey, SpillSettings};
+use crate::digest::{self, Digest};
+use crate::bvpfile::BVPFile;
+use crate::version;
+use crate::file::File;
+use crate::formats::Format;
+use crate::layout::{self, VoxelLayout};
+use crate::log::Span;
+use crate::metrics::PipelineStage;
+use crate::occupancy::{self, Occupancy};
+use crate::{log_error, log_info, log_warn};
+use crate::modality::Modality;
+use crate::placement::Placement;
+use crate::progress::ProgressSink;
+use crate::remote;
+use crate::report::FileDigest;
+use crate::signature;
+use crate::source;
+use crate::transform;
+use crate::tree::BlockTreeBuilder;
+use crate::window_level::{self, WindowPreset, AUTO_PRESET};
+use crate::vector3::Vector3;
+use crate::convert::{BlockNaming, BlockOrder, InputLayout, ModalityInput, Parameters, WindowSetting};
+use crate::convert::filters;
+use crate::convert::tiles::TiledVolume;
+use checkpoint::{Checkpoint, CheckpointState, JournalEntry};
+
+pub(crate) use data_parallel::raw_to_bvp_data_parallel;
+pub(crate) use parallel::raw_to_bvp_parallel;
+//pub use sequential::raw_to_bvp_sequential;
+
+/// Root block a block is extracted from, and the start and end of the block inside it.
+type BlockRange = (usize, Vector3<u32>, Vector3<u32>);
+/// A placement inside one of the root blocks, with the index of that root block.
+type RootPlacement = (usize, Placement);
+
+/// What the deduplication map keeps of a stored block: the range it was extracted from and,
+/// while its entry is in memory, its data, which new blocks with the same hash are compared with.
+/// Entries read back from the disk and blocks restored from a checkpoint only have their range.
+#[derive(Clone)]
+struct StoredBlock {
+    range: BlockRange,
+    data: Option<Bytes>
+}
+
+impl SpillKey for StoredBlock {
+    const SIZE: usize = <BlockRange as SpillKey>::SIZE;
+
+    fn write(&self, out: &mut Vec<u8>) {
+        self.range.write(out);
+    }
+
+    fn read(bytes: &[u8]) -> Self {
+        return Self { range: BlockRange::read(bytes), data: None };
+    }
+}
+
+/// Opens the checkpoint of the conversion, if `checkpoint` is enabled. Returns `None` if the output
+/// is already up to date and nothing needs to be done.
+/// * `parameters` - parsed conversion parameters
+/// * `bvp_file` - BVPFile holding the root blocks with the input data
+/// * `root_count` - number of root blocks
+fn open_checkpoint(parameters: &Parameters, bvp_file: &BVPFile, root_count: usize) -> Result<Option<Option<Arc<Checkpoint>>>, String> {
+    if !parameters.checkpoint {
+        return Ok(Some(None));
+    }
+    return match Checkpoint::open(parameters, bvp_file, root_count)? {
+        CheckpointState::UpToDate => {
+            log_info!("{} is up to date with the inputs and settings, nothing to do", parameters.output_file);
+            Ok(None)
+        },
+        CheckpointState::Active(checkpoint) => Ok(Some(Some(checkpoint)))
+    };
+}
+
+/// Creates the deduplication map of a conversion. When there are more blocks than the map
+/// keeps in memory, it moves hashes to `<outputFile>.dedup`, or to the temporary folder for outputs
+/// that are not local files.
+/// Blocks are stored in the map with the index of their range, see `block_index`, and with their
+/// data while they are in memory, see `StoredBlock`.
+/// * `parameters` - parsed conversion parameters
+/// * `block_count` - number of block ranges of the conversion
+fn dedup_map(parameters: &Parameters, block_count: usize) -> ShardedBlockMap<StoredBlock> {
+    if !parameters.deduplication || block_count <= parameters.dedup_memory_blocks {
+        return ShardedBlockMap::new(0);
+    }
+    let folder = if parameters.output_file == STDIO_PATH || remote::is_remote(&parameters.output_file) {
+        env::temp_dir().join(format!("bvp-dedup-{}", process::id()))
+    } else {
+        PathBuf::from(format!("{}.dedup", parameters.output_file))
+    };
+    log_info!(
+        "{} blocks, more than the {} kept in memory for deduplication, the rest go to {}",
+        block_count, parameters.dedup_memory_blocks, folder.display()
+    );
+    return ShardedBlockMap::with_spill(0, SpillSettings { folder, memory_entries: parameters.dedup_memory_blocks });
+}
+
+/// Returns the number of worker threads to use for the conversion.
+/// * `parameters` - parsed conversion parameters
+fn worker_count(parameters: &Parameters) -> Result<usize, String> {
+    return match parameters.threads {
+        Some(threads) => Ok(threads),
+        None => available_parallelism()
+            .map(|count| count.into())
+            .map_err(|err| err.to_string())
+    };
+}
+
+/// Reads a raw input file, from stdin, a URL or the file system.
+/// Files named `.gz` or `.zst` are decompressed, see `FileCompression::from_path`.
+/// * `filepath` - the input
+fn read_input_file(filepath: &str) -> Result<Vec<u8>, String> {
+    if filepath == STDIO_PATH {
+        let mut data = Vec::new();
+        return match io::stdin().lock().read_to_end(&mut data) {
+            Ok(_) => Ok(data),
+            Err(e) => Err(format!("Could not read stdin: {}", e))
+        };
+    }
+    let data = if remote::is_remote(filepath) {
+        remote::read(filepath).map_err(|e| e.to_string())?
+    } else {
+        match fs::read(filepath) {
+            Ok(v) => v,
+            Err(_) => {
+                return Err("Could not read file".to_string());
+            }
+        }
+    };
+    let compression = FileCompression::from_path(filepath);
+    if compression == FileCompression::None {
+        return Ok(data);
+    }
+    let compressed_size = data.len();
+    let data = compression.decompress(data).map_err(|e| format!("Could not decompress {}: {}", filepath, e))?;
+    log_info!("decompressed {} from {} to {} bytes", filepath, compressed_size, data.len());
+    return Ok(data);
+}
+
+/// Returns the voxels of a raw input without its header and padding, see `InputLayout`.
+/// * `data` - contents of the input file, at least `InputLayout::file_size` bytes
+/// * `input` - the input
+fn strip_layout(mut data: Vec<u8>, input: &ModalityInput) -> Vec<u8> {
+    let layout = input.layout;
+    if layout.is_packed(input.dimensions, &input.input_format) {
+        return data;
+    }
+    let (row_size, rows, planes) = InputLayout::rows(input.dimensions, &input.input_format);
+    let row_stride = layout.row_stride.unwrap_or(row_size);
+    if row_stride == row_size && layout.plane_padding == 0 {
+        // Only a header, the voxels are moved to the front.
+        data.drain(..layout.offset as usize);
+        data.truncate((row_size * rows * planes) as usize);
+        return data;
+    }
+    let mut voxels = Vec::with_capacity((row_size * rows * planes) as usize);
+    let plane_stride = rows * row_stride + layout.plane_padding;
+    for (z, y) in iproduct!(0..planes, 0..rows) {
+        let start = (layout.offset + z * plane_stride + y * row_stride) as usize;
+        voxels.extend_from_slice(&data[start..start + row_size as usize]);
+    }
+    return voxels;
+}
+
+/// Returns how the size of an input file differs from the size of its volume, or None if it fits.
+/// Files can leave out the padding after the last row and plane, but nothing may follow it.
+/// * `input` - the input
+/// * `size` - size of the file in bytes, decompressed
+fn size_mismatch(input: &ModalityInput, size: u64) -> Option<String> {
+    let needed = input.layout.file_size(input.dimensions, &input.input_format);
+    let padded = input.layout.padded_file_size(input.dimensions, &input.input_format);
+    if size < needed {
+        return Some(format!("{} holds {} bytes, but a volume of dimensions {} needs {}", input.input_file, size, input.dimensions, needed));
+    }
+    if size > padded {
+        return Some(format!("{} holds {} bytes, {} more than a volume of dimensions {} needs", input.input_file, size, size - padded, input.dimensions));
+    }
+    return None;
+}
+
+/// Checks the sizes of the local, uncompressed input files before any of them is read, so a conversion fails
+/// at once and not after reading the inputs before it. Other inputs are checked once they are read.
+/// * `inputs` - the volumes to convert
+/// * `parameters` - parsed conversion parameters
+fn check_file_sizes(inputs: &[ModalityInput], parameters: &Parameters) -> Result<(), String> {
+    if parameters.allow_size_mismatch {
+        return Ok(());
+    }
+    for (root_block_index, input) in inputs.iter().enumerate() {
+        let read = input.mask.is_none() && (root_block_index > 0 || parameters.tiles.is_none());
+        let file = &input.input_file;
+        if !read || file == STDIO_PATH || remote::is_remote(file) || FileCompression::from_path(file) != FileCompression::None {
+            continue;
+        }
+        let size = match fs::metadata(file) {
+            Ok(metadata) => metadata.len(),
+            // Reported when the file is read.
+            Err(_) => continue
+        };
+        if let Some(mismatch) = size_mismatch(input, size) {
+            return Err(format!("{}, set `allowSizeMismatch` to convert it anyway", mismatch));
+        }
+    }
+    return Ok(());
+}
+
+/// Returns the index of a format in a BVPFile, adding it if it is not there yet.
+/// Inputs with the same format share it, so their blocks can be deduplicated together.
+/// * `bvp` - the BVPFile
+/// * `format` - the format
+/// * `voxel_layout` - order of the voxels inside the blocks of the format
+fn format_index(bvp: &mut BVPFile, mut format: Format, voxel_layout: VoxelLayout) -> usize {
+    layout::set_layout(&mut format, voxel_layout);
+    let format_json = format.to_json();
+    return match bvp.formats.iter().position(|f| f.to_json() == format_json) {
+        Some(i) => i,
+        None => {
+            bvp.formats.push(format);
+            bvp.formats.len() - 1
+        }
+    };
+}
+
+/// Reads the input files and creates a BVPFile instance holding the input formats
+/// and a root block with all the input data for every modality. The root block of
+/// the `i`-th input is at index `i`, all inputs are kept in memory until the end.
+/// The root block of a tiled first input holds no data, and root blocks hold the input data in linear order
+/// whatever the voxel layout of the blocks.
+/// * `inputs` - the volumes to convert
+/// * `parameters` - parsed conversion parameters
+/// * `threads` - number of threads the inputs are filtered on
+/// * `progress` - receives the inputs as they are read
+fn initialize_bvp_file(inputs: &[ModalityInput], parameters: &Parameters, threads: usize, progress: &dyn ProgressSink) -> Result<BVPFile, String> {
+    let _span = Span::enter("read_input");
+    let started = Instant::now();
+    let (tiles, voxel_layout) = (parameters.tiles.as_ref(), parameters.voxel_layout);
+    check_file_sizes(inputs, parameters)?;
+    let mut bvp = BVPFile::new();
+    for (root_block_index, input) in inputs.iter().enumerate() {
+        // Blocks of a tiled input are read from its tiles as they are needed.
+        if let (0, Some(tiles)) = (root_block_index, tiles) {
+            log_info!("{} holds {} tiles", input.input_file, tiles.tiles.len());
+            let format_index = format_index(&mut bvp, input.input_format.clone(), voxel_layout);
+            bvp.blocks.push(Block::new(root_block_index, input.dimensions, Some(format_index), None));
+            continue;
+        }
+        // The mask is made of the top level volume, as it is stored in its root block.
+        if let Some(mask) = &input.mask {
+            let source = &bvp.blocks[0];
+            let source_format = source.format.and_then(|f| bvp.formats.get(f)).ok_or("the masked volume has no format")?;
+            let data = source.data.as_ref().map(|d| d.as_slice()).unwrap_or(&[]);
+            let mask_data = mask.generate(data, source_format).ok_or("cannot read the values of the masked volume")?;
+            log_info!("masked {} at isovalue {}, {} bytes", input.input_file, mask.isovalue, mask_data.len());
+            let format_index = format_index(&mut bvp, mask.format(), voxel_layout);
+            bvp.blocks.push(Block::new(root_block_index, input.dimensions, Some(format_index), Some(mask_data)));
+            continue;
+        }
+        let mut raw_input_data = read_input_file(&input.input_file)?;
+        log_info!("read {} bytes from {}", raw_input_data.len(), input.input_file);
+        progress.input_read(&input.input_file, &raw_input_data);
+        if root_block_index == 0 && parameters.record_source {
+            source::set_source(&mut bvp.asset, &FileDigest::new(&input.input_file, &raw_input_data));
+        }
+
+        if let Some(mismatch) = size_mismatch(input, raw_input_data.len() as u64) {
+            if !parameters.allow_size_mismatch {
+                return Err(format!("{}, set `allowSizeMismatch` to convert it anyway", mismatch));
+            }
+            log_warn!("{}, missing voxels are zero and the bytes after the volume are left out", mismatch);
+            raw_input_data.resize(input.layout.file_size(input.dimensions, &input.input_format) as usize, 0);
+        }
+        let raw_input_data = strip_layout(raw_input_data, input);
+        let (raw_input_data, input_format) = match input.rescale.as_ref().filter(|r| !r.is_identity()) {
+            Some(rescale) => {
+                let _span = Span::enter("rescale");
+                // Checked by `Parameters::validate`.
+                let (rescaled, output_format) = rescale.apply(&raw_input_data, &input.input_format)
+                    .ok_or_else(|| format!("cannot rescale the format of {}", input.input_file))?;
+                log_info!("rescaled {} with slope {} and intercept {}", input.input_file, rescale.slope, rescale.intercept);
+                (rescaled, output_format)
+            },
+            None => (raw_input_data, input.input_format.clone())
+        };
+        let raw_input_data = match input.filters.is_empty() {
+            true => raw_input_data,
+            false => {
+                let _span = Span::enter("filter");
+                let names: Vec<&str> = input.filters.iter().map(|f| f.name()).collect();
+                log_info!("filtering {} with {}", input.input_file, names.join(", "));
+                filters::apply(&input.filters, raw_input_data, input.dimensions, &input_format, threads)
+            }
+        };
+
+        // Encoded volumes are split like any other, blocks copy whole tiles.
+        let (raw_input_data, input_format) = match parameters.texture_compression {
+            Some(compression) => {
+                let _span = Span::enter("texture_compression");
+                let encoded = compression.encode(&raw_input_data, input.dimensions);
+                log_info!("encoded {} as {}, {} bytes", input.input_file, compression.to_string(), encoded.len());
+                (encoded, compression.format())
+            },
+            None => (raw_input_data, input_format)
+        };
+
+        let format_index = format_index(&mut bvp, input_format, voxel_layout);
+        let root_block = Block::new(
+            root_block_index,
+            input.dimensions,
+            Some(format_index),
+            Some(raw_input_data),
+        );
+        bvp.blocks.push(root_block);
+    }
+
+    progress.stage_finished("read_input", started.elapsed());
+    return Ok(bvp);
+}
+
+/// Returns the window/level presets of a modality, computing the automatic ones from the volume in its root block.
+/// Automatic presets are left out, with a warning, for volumes whose values cannot be read, such as texture compressed ones.
+/// * `bvp` - the BVP file, with the data of the root blocks
+/// * `root_block_index` - root block of the modality
+/// * `input` - the volume of the modality
+fn window_presets(bvp: &BVPFile, root_block_index: usize, input: &ModalityInput) -> Vec<WindowPreset> {
+    let mut presets = Vec::new();
+    for setting in &input.window {
+        match setting {
+            WindowSetting::Preset(p) => presets.push(p.clone()),
+            WindowSetting::Auto => {
+                let root_block = &bvp.blocks[root_block_index];
+                let format = root_block.format.and_then(|f| bvp.formats.get(f));
+                let data = root_block.data.as_ref().map(|d| d.as_slice()).unwrap_or(&[]);
+                let [low, high] = window_level::AUTO_PERCENTILES;
+                match format.and_then(|f| window_level::auto_window(f, data, low, high)) {
+                    Some(window) => {
+                        log_info!("automatic window of {} is {} to {}", input.input_file, window.low, window.high);
+                        presets.push(WindowPreset { name: Some(AUTO_PRESET.to_string()), window });
+                    },
+                    None => log_warn!("cannot compute an automatic window for {}, its values are not readable or all the same", input.input_file)
+                }
+            }
+        }
+    }
+    return presets;
+}
+
+/// Returns the block ranges every root block is split into, root by root in grid order, with X changing fastest.
+/// * `inputs` - the volumes to convert
+/// * `block_dimensions` - dimensions of the blocks
+fn block_ranges(inputs: &[ModalityInput], block_dimensions: Vector3<u32>) -> Vec<BlockRange> {
+    let mut ranges = Vec::new();
+    for (root_block_index, input) in inputs.iter().enumerate() {
+        let dimensions = input.dimensions;
+        let block_count = dimensions.div_ceil(&block_dimensions);
+        for (z, y, x) in iproduct!(0..block_count.z, 0..block_count.y, 0..block_count.x) {
+            let block_start = block_dimensions * Vector3::from_xyz(x, y, z);
+            let block_end = (block_start + block_dimensions).min(&dimensions);
+            ranges.push((root_block_index, block_start, block_end));
+        }
+    }
+    return ranges;
+}
+
+/// Returns the index of the block a range is stored in, if it is not a duplicate: the position of the range
+/// in `block_ranges`, after the root blocks. Workers can number blocks without waiting on each other that way,
+/// and `renumber_blocks` closes the gaps the duplicates leave.
+/// * `root_count` - number of root blocks
+/// * `position` - position of the range among all block ranges
+fn block_index(root_count: usize, position: usize) -> usize {
+    return root_count + position;
+}
+
+/// Returns the name of the file a block is stored in.
+/// * `naming` - how block files are named
+/// * `block_index` - index of the block in the manifest
+/// * `data` - data of the block, as written to the file
+/// * `encoding` - encoding of the data
+fn block_file_name(naming: BlockNaming, block_index: usize, data: &[u8], encoding: CompressionType) -> String {
+    return match naming {
+        BlockNaming::Index => format!("blocks/block_{}.raw", block_index),
+        BlockNaming::Content => format!("blocks/{:016x}.{}", xxh3::xxh3_64(data), encoding.to_string())
+    };
+}
+
+/// Block files of the conversion on their way to the archive. Depending on `blockOrder`, they are
+/// written as they are finished, or held back and written in order once all blocks are placed.
+/// Files named by index are held back too when deduplication is enabled, since their blocks can be
+/// numbered again at the end, see `renumber_blocks`.
+pub(crate) struct BlockFiles {
+    order: BlockOrder,
+    hold_back: bool,
+    /// Names of the files written or held back so far
+    written: HashSet<String>,
+    pending: Vec<File>
+}
+
+impl BlockFiles {
+    /// * `parameters` - parsed conversion parameters
+    pub(crate) fn new(parameters: &Parameters) -> Self {
+        let order = parameters.block_order;
+        let hold_back = order != BlockOrder::Completion || (parameters.block_naming == BlockNaming::Index && parameters.deduplication);
+        return Self { order, hold_back, written: HashSet::new(), pending: Vec::new() };
+    }
+
+    /// Writes a block file, unless a file with the same name was already written. With content
+    /// naming, blocks that are not deduplicated (or differ only in their dimensions) can have the
+    /// same file, which is then stored once. Files that are held back are only kept until `flush`.
+    /// * `writer` - archive writer
+    /// * `file` - the block file
+    /// * `progress` - receives the size of the written file and the time spent writing it
+    pub(crate) fn write(&mut self, writer: &mut (dyn ArchiveWriter + Send), file: &File, progress: &dyn ProgressSink) -> Result<(), String> {
+        if !self.written.insert(file.name.clone()) {
+            return Ok(());
+        }
+        if self.hold_back {
+            // The data is shared, so this does not copy it.
+            self.pending.push(file.clone());
+            return Ok(());
+        }
+        return write_block_file(writer, file, progress);
+    }
+
+    /// Renames held back files. Returns false and renames nothing if one of the files was already written.
+    /// * `names` - new names of files, by their old names
+    fn rename(&mut self, names: &HashMap<String, String>) -> bool {
+        let pending: HashSet<&str> = self.pending.iter().map(|file| file.name.as_str()).collect();
+        if names.keys().any(|name| !pending.contains(name.as_str())) {
+            return false;
+        }
+        for file in &mut self.pending {
+            if let Some(name) = names.get(&file.name) {
+                file.name = name.clone();
+            }
+        }
+        return true;
+    }
+
+    /// Writes the held back block files, ordered by the first placement of their blocks in the
+    /// root blocks: the root block first, then the position of the placement on the block grid.
+    /// In completion order, they are written in the order they were finished.
+    /// * `writer` - archive writer
+    /// * `blocks` - blocks created during conversion
+    /// * `root_placements` - placements of the created blocks inside the root blocks
+    /// * `block_dimensions` - dimensions of the block grid
+    /// * `progress` - receives the size of the written files and the time spent writing them
+    fn flush(&mut self, writer: &mut (dyn ArchiveWriter + Send), blocks: &[Block], root_placements: &[RootPlacement],
+        block_dimensions: Vector3<u32>, progress: &dyn ProgressSink) -> Result<(), String>
+    {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut pending = std::mem::take(&mut self.pending);
+        if self.order == BlockOrder::Completion {
+            for file in &pending {
+                write_block_file(writer, file, progress)?;
+            }
+            return Ok(());
+        }
+        let mut block_keys: HashMap<usize, (usize, u64, u64, u64)> = HashMap::new();
+        for (root_block_index, placement) in root_placements {
+            let cell = placement.position / block_dimensions;
+            let key = match self.order {
+                BlockOrder::Morton => (*root_block_index, cell.morton_code(), 0, 0),
+                _ => (*root_block_index, cell.z as u64, cell.y as u64, cell.x as u64)
+            };
+            let entry = block_keys.entry(placement.block).or_insert(key);
+            *entry = (*entry).min(key);
+        }
+        // Several blocks can share a file with content naming, it is placed by the first of them.
+        let mut file_keys: HashMap<&str, (usize, u64, u64, u64)> = HashMap::new();
+        for block in blocks {
+            if let (Some(name), Some(key)) = (&block.data_url, block_keys.get(&block.index)) {
+                let entry = file_keys.entry(name.as_str()).or_insert(*key);
+                *entry = (*entry).min(*key);
+            }
+        }
+        pending.sort_by_key(|file| file_keys.get(file.name.as_str()).copied().unwrap_or((usize::MAX, u64::MAX, u64::MAX, u64::MAX)));
+        for file in &pending {
+            write_block_file(writer, file, progress)?;
+        }
+        return Ok(());
+    }
+}
+
+/// Appends a block file to the archive.
+/// * `writer` - archive writer
+/// * `file` - the block file
+/// * `progress` - receives the size of the written file and the time spent writing it
+fn write_block_file(writer: &mut (dyn ArchiveWriter + Send), file: &File, progress: &dyn ProgressSink) -> Result<(), String> {
+    let timer = Instant::now();
+    writer.append_file(file)
+        .map_err(|err| {
+            log_error!("could not write {}: {}", file.name, err);
+            err.to_string()
+        })?;
+    progress.work_done(PipelineStage::Write, timer.elapsed());
+    progress.bytes_written(file.data.len());
+    return Ok(());
+}
+
+/// Returns the data of a block range, extracted from its root block, or read from the tiles of a tiled input.
+/// * `bvp_file` - BVPFile holding the root blocks
+/// * `tiles` - tiles of the first input, if it is tiled
+/// * `range` - the block range
+fn extract_block_data(bvp_file: &BVPFile, tiles: Option<&TiledVolume>, range: &BlockRange) -> Result<Vec<u8>, String> {
+    let (root_block_index, start, end) = *range;
+    let root_block = &bvp_file.blocks[root_block_index];
+    let format = &bvp_file.formats[root_block.format.unwrap()];
+    let mut block = match (root_block_index, tiles) {
+        (0, Some(tiles)) => {
+            let data = tiles.read_range(start, end).map_err(|err| {
+                log_error!("could not read block from {} to {}: {}", start, end, err);
+                err
+            })?;
+            Block::new(0, end - start, root_block.format, Some(data))
+        },
+        _ => root_block.get_data_in_range(start, end, format)
+            .map_err(|err| {
+                log_error!("could not extract block from {} to {}: {}", start, end, err);
+                err.to_string()
+            })?
+    };
+    // Root blocks hold linear data, blocks are stored in the layout of the format.
+    let voxel_layout = layout::layout(format).map_err(|err| err.to_string())?;
+    if voxel_layout != VoxelLayout::Linear {
+        block = block.to_layout(format, voxel_layout).map_err(|err| err.to_string())?;
+    }
+    // The extracted block is not shared yet, so this does not copy the data.
+    return block.data
+        .map(Bytes::into_vec)
+        .ok_or_else(|| String::from("Block does not have data!"));
+}
+
+/// Records the occupancy bitmap of a new block, if the conversion makes them. All blocks of a format
+/// have cells of the same size, so blocks at the edges of the volume have fewer of them.
+/// * `block` - the block
+/// * `data` - unencoded data of the block, as extracted
+/// * `format` - format of the block
+/// * `parameters` - parsed conversion parameters
+fn set_block_occupancy(block: &mut Block, data: &[u8], format: &Format, parameters: &Parameters) -> Result<(), String> {
+    let grid = match parameters.occupancy_grid {
+        Some(grid) => grid,
+        None => return Ok(())
+    };
+    let cell_dimensions = occupancy::cell_dimensions(grid, parameters.block_dimensions, format.microblock_dimensions);
+    let block_occupancy = Occupancy::compute(data, block.dimensions, format, cell_dimensions).map_err(|e| e.to_string())?;
+    occupancy::set_occupancy(block, &block_occupancy);
+    return Ok(());
+}
+
+/// Returns true if a block stored earlier holds the same data as a new block,
+/// which is checked when their hashes are the same. Blocks with different dimensions
+/// (at the edges of volumes) or from root blocks with different formats are never the same.
+/// The data kept with the stored block is compared, only blocks without it are extracted again.
+/// * `bvp_file` - BVPFile holding the root blocks
+/// * `tiles` - tiles of the first input, if it is tiled
+/// * `stored` - the stored block
+/// * `range` - where the new block was extracted from
+/// * `data` - data of the new block
+fn is_same_block(bvp_file: &BVPFile, tiles: Option<&TiledVolume>, stored: &StoredBlock, range: &BlockRange, data: &[u8]) -> bool {
+    let (root_block_index, start, end) = stored.range;
+    let (new_root_block_index, new_start, new_end) = *range;
+    let root_block = &bvp_file.blocks[root_block_index];
+    if root_block.format != bvp_file.blocks[new_root_block_index].format || end - start != new_end - new_start {
+        return false;
+    }
+    if let Some(stored_data) = &stored.data {
+        return stored_data.as_slice() == data;
+    }
+    return match extract_block_data(bvp_file, tiles, &stored.range) {
+        Ok(same_hash_data) => same_hash_data == data,
+        Err(_) => false,
+    };
+}
+
+/// Renames the files of blocks that are named by index after the blocks were numbered with `BlockTreeBuilder::renumber`.
+/// Other block files keep their names.
+/// * `tree` - blocks created during conversion, with their new indices
+/// * `block_files` - block files, of which those held back are renamed
+/// * `parameters` - parsed conversion parameters
+fn rename_block_files(tree: &mut BlockTreeBuilder, block_files: &mut BlockFiles, parameters: &Parameters) {
+    if parameters.block_naming != BlockNaming::Index {
+        return;
+    }
+    let mut names = HashMap::new();
+    for block in tree.blocks() {
+        if let Some(name) = &block.data_url {
+            let new_name = block_file_name(BlockNaming::Index, block.index, &[], parameters.compression);
+            if *name != new_name {
+                names.insert(name.clone(), new_name);
+            }
+        }
+    }
+    // Files are held back whenever they can be renamed, so this only fails if that changes.
+    if block_files.rename(&names) {
+        for block in tree.blocks_mut() {
+            if let Some(new_name) = block.data_url.as_ref().and_then(|name| names.get(name)) {
+                block.data_url = Some(new_name.clone());
+            }
+        }
+    } else {
+        log_warn!("{} block files were written before their blocks were numbered, they keep their names", names.len());
+    }
+}
+
+/// Logs how many blocks were stored, how many were deduplicated and how much data that saved.
+/// * `bvp_file` - BVPFile holding the input formats
+/// * `bvp_block_vec` - blocks created during conversion
+/// * `bvp_root_block_placements_vec` - placements of the created blocks inside the root blocks
+/// * `deduplication` - whether deduplication was enabled
+fn log_dedup_statistics(bvp_file: &BVPFile, bvp_block_vec: &[Block], bvp_root_block_placements_vec: &[RootPlacement], deduplication: bool) {
+    if !deduplication {
+        log_info!("{} blocks, deduplication disabled", bvp_block_vec.len());
+        return;
+    }
+    let decoded_sizes: HashMap<usize, u64> = bvp_block_vec.iter()
+        .map(|block| {
+            let size = match block.format {
+                Some(format) => bvp_file.formats[format].checked_count_space(block.dimensions).map_or(0, |size| size as u64),
+                None => 0
+            };
+            return (block.index, size);
+        })
+        .collect();
+    let unique_size: u64 = decoded_sizes.values().sum();
+    let placed_size: u64 = bvp_root_block_placements_vec.iter()
+        .map(|(_, placement)| decoded_sizes.get(&placement.block).copied().unwrap_or(0))
+        .sum();
+    let duplicates = bvp_root_block_placements_vec.len() - bvp_block_vec.len();
+    log_info!(
+        "{} unique blocks, {} duplicates found, {} of {} bytes saved",
+        bvp_block_vec.len(), duplicates, placed_size - unique_size, placed_size
+    );
+}
+
+/// Fills in the modality and asset metadata of a converted BVPFile,
+/// writes the manifest and finishes the archive.
+/// * `writer` - archive writer
+/// * `block_files` - block files, of which those held back are written before the manifest
+/// * `bvp_file` - BVPFile holding the input formats and the root blocks
+/// * `bvp_block_map` - block data hashes, mapped to block indices, with those the dedup map moved to the disk
+/// * `bvp_block_vec` - blocks created during conversion
+/// * `bvp_root_block_placements_vec` - placements of the created blocks inside the root blocks
+/// * `parameters` - parsed conversion parameters, with the converted volumes, one for each root block
+/// * `progress` - receives the size of the manifest, the time spent and the end of the conversion
+fn finalize_bvp_file(
+    writer: &mut (dyn ArchiveWriter + Send),
+    mut block_files: BlockFiles,
+    mut bvp_file: BVPFile,
+    mut bvp_block_map: HashMap<u64, usize>,
+    mut tree: BlockTreeBuilder,
+    parameters: &Parameters,
+    progress: &dyn ProgressSink,
+) -> Result<(), String> {
+    let _span = Span::enter("finalize");
+    let inputs = parameters.modality_inputs();
+    let started = Instant::now();
+    log_dedup_statistics(&bvp_file, tree.blocks(), tree.placements(), parameters.deduplication);
+    for (root_block_index, input) in inputs.iter().enumerate() {
+        let mut modality = Modality::new(
+            input.name.clone(),
+            input.description.clone(),
+            input.semantic_type.clone(),
+            input.volume_scale,
+            input.voxel_scale,
+            root_block_index,
+        );
+        transform::set_transform(&mut modality, input.transform.as_ref()).map_err(|e| e.to_string())?;
+        let presets = window_presets(&bvp_file, root_block_index, input);
+        window_level::set_presets(&mut modality, &presets).map_err(|e| e.to_string())?;
+        // Root blocks hold the voxels as reconstructed, in linear order.
+        if let (Some(algorithm), Some(data)) = (parameters.volume_checksum, &bvp_file.blocks[root_block_index].data) {
+            digest::set_volume_digest(&mut modality, &Digest::compute(algorithm, data));
+        }
+        bvp_file.modalities.push(modality);
+    }
+
+    bvp_file.asset.author = parameters.author.clone();
+    bvp_file.asset.copyright = parameters.copyright.clone();
+    bvp_file.asset.acquisition_time = parameters.acquisition_time.clone();
+    bvp_file.asset.generator = Some(parameters.generator.clone().unwrap_or_else(|| version::generator("raw2bvp")));
+    bvp_file.asset.name = parameters.name.clone();
+    bvp_file.asset.description = parameters.description.clone();
+
+    bvp_file.asset.creation_time = Some(version::timestamp_now());
+    bvp_file.asset.version = parameters.spec_version.to_string();
+
+    // Workers add blocks in completion order, and number them with gaps.
+    let renumbered = tree.renumber();
+    for index in bvp_block_map.values_mut() {
+        if let Some(new_index) = renumbered.get(index) {
+            *index = *new_index;
+        }
+    }
+    rename_block_files(&mut tree, &mut block_files, parameters);
+    block_files.flush(writer, tree.blocks(), tree.placements(), parameters.block_dimensions, progress)?;
+
+    bvp_file.block_map = bvp_block_map;
+    tree.build(&mut bvp_file, &parameters.superblock_dimensions).map_err(|e| e.to_string())?;
+    if let Some(key) = &parameters.signing_key {
+        signature::sign(&mut bvp_file, key).map_err(|e| e.to_string())?;
+        log_info!("signed the manifest with key {}", signature::to_hex(&key.public_key()));
+    }
+
+    let manifest_data = bvp_file.to_manifest().map_err(|e| e.to_string())?;
+    let manifest_file = File::new(
+        "manifest.json".to_string(),
+        Arc::new(manifest_data),
+        Some("application/json".to_string()),
+    );
+
+    writer.append_file(&manifest_file).map_err(|e| e.to_string())?;
+    progress.bytes_written(manifest_file.data.len());
+    writer.finish(parameters.output_file.clone()).map_err(|e| e.to_string())?;
+    progress.stage_finished("finalize", started.elapsed());
+    progress.finish();
+
+    Ok(())
+}
+
@@ -0,0 +1,482 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::archives::ArchiveWriter;
+use crossbeam::{channel, scope};
+use crossbeam::channel::{Receiver, Sender};
+use crossbeam::thread::{Scope, ScopedJoinHandle};
+use xxhash_rust::xxh3;
+
+use crate::block::Block;
+use crate::bvpfile::BVPFile;
+use crate::bytes::Bytes;
+use crate::checksum::Checksum;
+use crate::dedup::{DedupResult, ShardedBlockMap};
+use crate::file::File;
+use crate::metrics::{MetricsSink, PipelineMetrics, PipelineStage};
+use crate::progress::ProgressSink;
+use crate::tree::BlockTreeBuilder;
+use crate::log::Span;
+use crate::{log_debug, log_trace};
+use crate::vector3::Vector3;
+use crate::convert::Parameters;
+use crate::convert::raw_to_bvp::{block_index, block_ranges, dedup_map, initialize_bvp_file, open_checkpoint, worker_count, finalize_bvp_file, is_same_block, extract_block_data, set_block_occupancy, block_file_name, BlockFiles, BlockRange, Checkpoint, JournalEntry, StoredBlock};
+
+const TREE_POISONED: &str = "Some thread panicked while holding the shared block tree.";
+
+
+struct StageOnePipelineResult {
+    pub block_start: Vector3<u32>,
+    pub block_end: Vector3<u32>,
+
+    pub format_index: usize,
+    pub parent_block_index: usize,
+    /// Index of the block if the range is not a duplicate, see `block_index`.
+    pub block_index: usize,
+}
+
+struct StageTwoPipelineResult {
+    file_to_write: File,
+}
+
+/// What the stage two workers share, besides the channels between the stages.
+/// The encoding, checksum, deduplication and naming of blocks are taken from `parameters`.
+#[derive(Clone)]
+struct StageTwoShared<'a> {
+    block_map: Arc<ShardedBlockMap<StoredBlock>>,
+    tree: Arc<Mutex<BlockTreeBuilder>>,
+    bvp_file: Arc<BVPFile>,
+    checkpoint: Option<Arc<Checkpoint>>,
+    parameters: &'a Parameters,
+    progress: &'a dyn ProgressSink,
+}
+
+
+/// Creates a channel between two pipeline stages,
+/// bounded if a capacity is given and unbounded otherwise.
+fn stage_channel<T>(capacity: Option<usize>) -> (Sender<T>, Receiver<T>) {
+    return match capacity {
+        Some(capacity) => channel::bounded(capacity),
+        None => channel::unbounded()
+    };
+}
+
+
+/*
+ * Pipeline, stage 1
+ */
+
+/// Spawn stage one thread for the pipeline.
+///
+/// This stage has a single thread that goes through the block ranges all root blocks
+/// are split into and sends them as "work packets" through the provided `Sender`.
+fn spawn_stage_1<'scope, 'scope_env: 'scope>(
+    scope: &'scope Scope<'scope_env>,
+    stage_one_result_channel_tx: Sender<StageOnePipelineResult>,
+    block_ranges: Vec<BlockRange>,
+    root_count: usize,
+    bvp_file: Arc<BVPFile>,
+) {
+    scope.spawn(move |_| {
+        let _span = Span::enter("stage_1");
+        log_debug!("generating {} block ranges", block_ranges.len());
+
+        for (position, (root_block_index, block_start, block_end)) in block_ranges.into_iter().enumerate() {
+            let sent = stage_one_result_channel_tx.send(StageOnePipelineResult {
+                block_start,
+                block_end,
+                format_index: bvp_file.blocks[root_block_index].format.unwrap(),
+                parent_block_index: root_block_index,
+                block_index: block_index(root_count, position),
+            });
+            if sent.is_err() {
+                // All stage two workers have stopped, they report their own errors.
+                log_debug!("stage two stopped receiving, no more ranges are generated");
+                break;
+            }
+        }
+    });
+}
+
+
+/*
+ * Pipeline, stage 2
+ */
+
+/// Run a worker for the stage two of the pipeline.
+///
+/// This stage uses the ranges provided by first stage and generates smaller blocks of data,
+/// performs deduplication and compresses them.
+///
+/// Block hashes are looked up in `bvp_shared_block_map`, which is sharded so workers rarely
+/// wait on each other. New blocks and placements are added to `bvp_shared_tree`, which is
+/// only locked for a single addition. The tree is built after the pipeline concludes,
+/// to finalize the `BVPFile` instance before writing the manifest.
+fn run_stage_2_worker(
+    stage_one_result_channel_rx: Arc<Receiver<StageOnePipelineResult>>,
+    stage_two_result_queue_tx: Arc<Sender<StageTwoPipelineResult>>,
+    shared: StageTwoShared,
+) -> Result<(), String> {
+    let _span = Span::enter("stage_2");
+    let StageTwoShared {
+        block_map: bvp_shared_block_map,
+        tree: bvp_shared_tree,
+        bvp_file,
+        checkpoint,
+        parameters,
+        progress,
+    } = shared;
+    let encoding = parameters.compression;
+    let compression_level = parameters.compression_level;
+    let checksum = parameters.checksum;
+    let deduplication = parameters.deduplication;
+    let block_naming = parameters.block_naming;
+    // Parses blocks to be saved in separate files (and performs deduplication).
+    loop {
+        let prepared_work = match stage_one_result_channel_rx.recv() {
+            Ok(work) => work,
+            Err(_) => {
+                // This happens when the channel sender has been dropped and there is
+                // no more work to receive.
+                break;
+            }
+        };
+
+        let range = (prepared_work.parent_block_index, prepared_work.block_start, prepared_work.block_end);
+        let send_file = |file: File| {
+            return stage_two_result_queue_tx.send(StageTwoPipelineResult { file_to_write: file })
+                .map_err(|_| "Stage three stopped before all blocks were written".to_string());
+        };
+
+        // Ranges finished by an interrupted run are taken from the checkpoint.
+        if let Some(entry) = checkpoint.as_ref().and_then(|c| c.previous(&range)) {
+            match entry {
+                JournalEntry::Stored { block: block_id, .. } => {
+                    let (restored_block, file) = checkpoint.as_ref().unwrap().restore_block(&range, block_id, &bvp_file, parameters)?;
+                    let decoded_size = bvp_file.formats[prepared_work.format_index].checked_count_space(restored_block.dimensions).unwrap_or(0);
+                    progress.block_stored(decoded_size, file.data.len());
+                    bvp_shared_tree.lock().expect(TREE_POISONED)
+                        .add_block(prepared_work.parent_block_index, prepared_work.block_start, restored_block);
+                    send_file(file)?;
+                    progress.block_processed(false);
+                },
+                JournalEntry::Duplicate { block: block_id } => {
+                    bvp_shared_tree.lock().expect(TREE_POISONED)
+                        .add_placement(prepared_work.parent_block_index, prepared_work.block_start, block_id);
+                    progress.block_processed(true);
+                }
+            };
+            continue;
+        }
+
+        let timer = Instant::now();
+        let block_data = Bytes::from(extract_block_data(&bvp_file, parameters.tiles.as_ref(), &range)?);
+        progress.work_done(PipelineStage::Extract, timer.elapsed());
+        let block_dimensions = prepared_work.block_end - prepared_work.block_start;
+
+        // Check if block with the same hash exists.
+        // If a hash collision is found, the raw data is compared with the data
+        // the map shares with the stored block, before assuming the blocks
+        // are actually the same. Without deduplication, nothing is hashed.
+        let timer = Instant::now();
+        let block_data_hash = if deduplication { xxh3::xxh3_64(block_data.as_slice()) } else { 0 };
+        let dedup_result = if deduplication {
+            bvp_shared_block_map.find_or_insert_as(
+                block_data_hash,
+                StoredBlock { range, data: Some(block_data.clone()) },
+                prepared_work.block_index,
+                |entry| is_same_block(&bvp_file, parameters.tiles.as_ref(), &entry.key, &range, &block_data),
+            ).map_err(|e| e.to_string())?
+        } else {
+            DedupResult::New(prepared_work.block_index)
+        };
+        progress.work_done(PipelineStage::Deduplicate, timer.elapsed());
+
+        let block_id = match dedup_result {
+            DedupResult::Existing(same_hash_block_id) => {
+                // Real collision, we can deduplicate and don't need to write another file.
+                if let Some(checkpoint) = &checkpoint {
+                    checkpoint.record_duplicate(&range, same_hash_block_id)?;
+                }
+                bvp_shared_tree.lock().expect(TREE_POISONED)
+                    .add_placement(prepared_work.parent_block_index, prepared_work.block_start, same_hash_block_id);
+                progress.block_processed(true);
+
+                continue;
+            },
+            DedupResult::New(block_id) => block_id,
+        };
+
+        // No collision and no possibility of deduplication,
+        // schedule block for writing to file and store its index.
+        // The checksum and content-addressed names cover the data as stored,
+        // so the block needs compressing first.
+        let mut new_block = Block::new(
+            block_id,
+            block_dimensions,
+            Some(prepared_work.format_index),
+            None,
+        );
+        set_block_occupancy(&mut new_block, &block_data, &bvp_file.formats[prepared_work.format_index], parameters)?;
+        let decoded_size = block_data.len();
+        let timer = Instant::now();
+        let compressed_block_data = encoding.compress_shared(&block_data, compression_level);
+        progress.work_done(PipelineStage::Compress, timer.elapsed());
+        progress.block_stored(decoded_size, compressed_block_data.len());
+        if let Some(checkpoint) = &checkpoint {
+            checkpoint.record_stored(&range, block_id, block_data_hash, &compressed_block_data)?;
+        }
+        let block_url = block_file_name(block_naming, block_id, &compressed_block_data, encoding);
+
+        new_block.encoding = Some(encoding);
+        new_block.data_url = Some(block_url.clone());
+        new_block.checksum = checksum.map(|algorithm| Checksum::compute(algorithm, &compressed_block_data));
+
+        bvp_shared_tree.lock().expect(TREE_POISONED)
+            .add_block(prepared_work.parent_block_index, prepared_work.block_start, new_block);
+        progress.block_processed(false);
+
+        log_trace!("block {} at {} stored as {}", block_id, prepared_work.block_start, block_url);
+
+        send_file(File::new(
+            block_url,
+            compressed_block_data,
+            None,
+        ))?;
+    }
+
+    Ok(())
+}
+
+/// Spawn stage two threads for the pipeline.
+///
+/// See `run_stage_2_worker` for more information.
+fn spawn_stage_2<'progress: 'scope_env, 'scope, 'scope_env: 'scope>(
+    number_of_workers: usize,
+    scope: &'scope Scope<'scope_env>,
+    stage_one_result_channel_rx: Arc<Receiver<StageOnePipelineResult>>,
+    stage_two_result_queue_tx: Arc<Sender<StageTwoPipelineResult>>,
+    shared: StageTwoShared<'progress>,
+) -> Vec<ScopedJoinHandle<'scope, Result<(), String>>> {
+    let mut handles = Vec::with_capacity(number_of_workers);
+    for _ in 0..number_of_workers {
+        let stage_one_result_channel_rx_clone = stage_one_result_channel_rx.clone();
+        let stage_two_result_queue_tx_clone = stage_two_result_queue_tx.clone();
+        let shared_clone = shared.clone();
+
+        handles.push(scope.spawn(move |_| {
+            run_stage_2_worker(
+                stage_one_result_channel_rx_clone,
+                stage_two_result_queue_tx_clone,
+                shared_clone,
+            )
+        }));
+    }
+    handles
+}
+
+
+/*
+ * Stage 3
+ */
+/// Run a worker for the stage three of the pipeline.
+///
+/// This stage receives parsed data blocks from the second stage and
+/// writes them into the .bvp (ZIP) file.
+fn run_stage_3_worker(
+    stage_two_result_queue_rx: Receiver<StageTwoPipelineResult>,
+    writer: &mut (dyn ArchiveWriter + Send),
+    mut block_files: BlockFiles,
+    progress: &dyn ProgressSink,
+) -> Result<BlockFiles, String> {
+    let _span = Span::enter("stage_3");
+    // Receive queued files to write and write them to disk as the requests are coming in.
+    loop {
+        let stage_two_work = match stage_two_result_queue_rx.recv() {
+            Ok(work) => work,
+            Err(_) => {
+                // This happens when the channel sender has been dropped and there is
+                // no more work to receive.
+                break;
+            }
+        };
+
+        block_files.write(writer, &stage_two_work.file_to_write, progress)?;
+    }
+
+    Ok(block_files)
+}
+
+/// Spawn stage three worker for the pipeline.
+///
+/// See `run_stage_3_worker` for more information.
+fn spawn_stage_3<'writer: 'scope_env, 'progress: 'scope_env, 'scope, 'scope_env: 'scope>(
+    scope: &'scope Scope<'scope_env>,
+    stage_two_result_queue_rx: Receiver<StageTwoPipelineResult>,
+    writer: &'writer mut (dyn ArchiveWriter + Send),
+    block_files: BlockFiles,
+    progress: &'progress dyn ProgressSink,
+) -> ScopedJoinHandle<'scope, Result<BlockFiles, String>> {
+    scope.spawn(move |_| {
+        run_stage_3_worker(
+            stage_two_result_queue_rx,
+            writer,
+            block_files,
+            progress,
+        )
+    })
+}
+
+
+/*
+ * Entry function
+ */
+
+/// Converts the inputs with the three stage pipeline described below.
+/// Returns where the conversion spent its time, and how much it read and wrote.
+/// * `parameters` - parsed conversion parameters
+/// * `writer` - archive writer the asset is written to
+/// * `progress` - receives the events of the conversion
+pub(crate) fn raw_to_bvp_parallel(
+    parameters: &Parameters,
+    writer: &mut (dyn ArchiveWriter + Send),
+    progress: &dyn ProgressSink,
+) -> Result<PipelineMetrics, String> {
+    let metrics = MetricsSink::new(progress);
+    let progress: &dyn ProgressSink = &metrics;
+
+    // Use as many workers as requested, or as there are available cores on the system.
+    let stage_two_worker_count = worker_count(parameters)?;
+
+    // Set up inter-stage channels/queues/maps/vectors.
+    // Bounded queues make faster stages wait for slower ones instead of buffering blocks.
+    let (stage_one_result_channel_tx, stage_one_result_channel_rx) =
+        stage_channel::<StageOnePipelineResult>(parameters.queue_capacity);
+    let stage_one_result_channel_rx_arc = Arc::new(stage_one_result_channel_rx);
+
+    let (stage_two_result_channel_tx, stage_two_result_channel_rx) =
+        stage_channel::<StageTwoPipelineResult>(parameters.queue_capacity);
+    let stage_two_result_channel_tx_arc = Arc::new(stage_two_result_channel_tx);
+
+    // Read input files and initialize BVPFile instance (as much as we need to before going parallel).
+    let inputs = parameters.modality_inputs();
+    let bvp = initialize_bvp_file(&inputs, &parameters, stage_two_worker_count, progress)?;
+    let checkpoint = match open_checkpoint(parameters, &bvp, inputs.len())? {
+        Some(c) => c,
+        None => return Ok(metrics.metrics())
+    };
+    let block_ranges = block_ranges(&inputs, parameters.block_dimensions);
+
+    // The map is shared by all modalities, so blocks repeated in different modalities are stored once.
+    let bvp_shared_block_map: Arc<ShardedBlockMap<StoredBlock>> = Arc::new(dedup_map(parameters, block_ranges.len()));
+    if let (Some(checkpoint), true) = (&checkpoint, parameters.deduplication) {
+        checkpoint.restore_block_map(&block_ranges, &bvp_shared_block_map)?;
+    }
+    let bvp_shared_tree: Arc<Mutex<BlockTreeBuilder>> = Arc::new(Mutex::new(BlockTreeBuilder::new(inputs.len())));
+
+    // The pipeline will now have read-only access to the BVPFile.
+    // After the pipeline concludes, we'll unwrap the `Arc` and finish adding any
+    // missing fields before finalizing the .bvp file.
+    let bvp_arc = Arc::new(bvp);
+
+    // Spawn pipeline stages and wait for finish.
+    // Pipeline is made out of three stages:
+    //   - First stage (single thread) sends all the block ranges we need to parse
+    //     the raw data of every input into smaller blocks.
+    //   - Second stage (as many threads as cores) uses the ranges provided by first stage
+    //     and generates smaller blocks of data, performs deduplication and compresses the data.
+    //   - Third stage (single thread) receives parsed data blocks from the second stage and
+    //     writes them into the .bvp file.
+    // The pipeline is constructed using a thread scope from `crossbeam` - stages run in parallel
+    // and each stage shuts down when it has completed all the work the previous stage can provide.
+    progress.start(block_ranges.len());
+    let started = Instant::now();
+
+    let block_files = scope(|scope| {
+        // Stage 1 (generate block ranges)
+        spawn_stage_1(
+            scope,
+            stage_one_result_channel_tx,
+            block_ranges,
+            inputs.len(),
+            bvp_arc.clone(),
+        );
+
+        // Stage 2 (parse smaller blocks, deduplicate and compress)
+        let stage_two_handles = spawn_stage_2(
+            stage_two_worker_count,
+            scope,
+            stage_one_result_channel_rx_arc,
+            stage_two_result_channel_tx_arc,
+            StageTwoShared {
+                block_map: bvp_shared_block_map.clone(),
+                tree: bvp_shared_tree.clone(),
+                bvp_file: bvp_arc.clone(),
+                checkpoint: checkpoint.clone(),
+                parameters,
+                progress,
+            },
+        );
+
+        // Stage 3 (write queued files to zip)
+        let stage_three_handle = spawn_stage_3(
+            scope,
+            stage_two_result_channel_rx,
+            &mut *writer,
+            BlockFiles::new(parameters),
+            progress,
+        );
+
+        // Wait for all workers. If stage three fails, stage two workers fail too,
+        // because they cannot send their blocks anymore, so its error is reported first.
+        let mut stage_two_results = Vec::with_capacity(stage_two_handles.len());
+        for handle in stage_two_handles {
+            stage_two_results.push(handle.join()
+                .unwrap_or_else(|_| Err(String::from("A stage two worker panicked."))));
+        }
+        let block_files = stage_three_handle.join()
+            .unwrap_or_else(|_| Err(String::from("The stage three worker panicked.")))?;
+        stage_two_results.into_iter().collect::<Result<Vec<()>, String>>()?;
+
+        Ok::<BlockFiles, String>(block_files)
+    })
+        .map_err(|_| String::from("Scope failed to execute."))??;
+    progress.stage_finished("blocks", started.elapsed());
+    // Every block is done, so a failure while writing the asset resumes without converting any again.
+    if let Some(checkpoint) = &checkpoint {
+        checkpoint.sync()?;
+    }
+
+    // Unwrap `Arc`s and `Mutex`es that must, at this point, have only one strong reference
+    // and no other threads can access them. We could technically keep them as-is,
+    // but unwrapping into original types allows us cleaner access.
+    let bvp_file = Arc::try_unwrap(bvp_arc)
+        .expect("BUG: Something is holding a strong reference somehow.");
+
+    let bvp_block_map = Arc::try_unwrap(bvp_shared_block_map)
+        .unwrap_or_else(|_| panic!("BUG: Something is still holding a strong reference."))
+        .into_block_map()
+        .map_err(|e| e.to_string())?;
+
+    let bvp_tree = Arc::try_unwrap(bvp_shared_tree)
+        .unwrap_or_else(|_| panic!("BUG: Something is still holding a strong reference."))
+        .into_inner()
+        .expect(TREE_POISONED);
+
+    // Finalize BVPFile, generate and write the manifest and close the zip file writer.
+    finalize_bvp_file(
+        writer,
+        block_files,
+        bvp_file,
+        bvp_block_map,
+        bvp_tree,
+        parameters,
+        progress,
+    )?;
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.complete(parameters)?;
+    }
+
+    Ok(metrics.metrics())
+}
+
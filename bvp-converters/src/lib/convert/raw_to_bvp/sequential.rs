@@ -1,14 +1,14 @@
 /*use std::sync::Arc;
 use xxhash_rust::xxh3;
-use bvp::block::Block;
-use bvp::bvpfile::BVPFile;
-use bvp::compressions::CompressionType;
-use bvp::file::File;
-use bvp::modality::Modality;
-use bvp::placement::Placement;
-use bvp::vector3::Vector3;
+use crate::block::Block;
+use crate::bvpfile::BVPFile;
+use crate::compressions::CompressionType;
+use crate::file::File;
+use crate::modality::Modality;
+use crate::placement::Placement;
+use crate::vector3::Vector3;
 use crate::arguments;
-use crate::raw_to_bvp::read_input_file;
+use crate::convert::raw_to_bvp::read_input_file;
 
 /// Iterates through blocks of data in volume,
 /// schedules unique data for writing to file,
@@ -21,7 +21,7 @@ use crate::raw_to_bvp::read_input_file;
 /// * `bvp_state` is a state tracking object for single BVP file
 fn volume2block(parent_block_index: usize, dimensions: Vector3<u32>, block_dimensions: Vector3<u32>, format_index: usize, encoding: CompressionType, bvp_state: &mut BVPFile) -> Result<(), String> {
 
-    let block_count = (dimensions / block_dimensions).ceil();
+    let block_count = dimensions.div_ceil(&block_dimensions);
     let format = &bvp_state.formats[format_index];
 
     for x in 0..block_count.x {
@@ -112,8 +112,7 @@ pub fn raw_to_bvp_sequential(
 
     volume2block(0, parameters.dimensions, parameters.block_dimensions, root_block_index, parameters.compression, &mut bvp_file)?;
 
-    let time = chrono::offset::Utc::now();
-    bvp_file.asset.creation_time = Some(time.timestamp().to_string()); // IN ISO format!!!
+    bvp_file.asset.creation_time = Some(bvp::version::timestamp_now());
     bvp_file.files.push(File::new("manifest.json".to_string(), Arc::new(bvp_file.to_manifest()?), Some("application/json".to_string())));
 
     parameters.archive.write_files(&bvp_file.files, parameters.output_file).map_err(|x| format!("{}", x))?;
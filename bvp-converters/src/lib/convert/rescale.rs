@@ -0,0 +1,175 @@
+//! Linear rescaling of voxel values as they are imported, `value * slope + intercept`, such as turning the
+//! integers a CT scanner stores into Hounsfield units, or normalizing floating point volumes.
+//!
+//! The rescaled voxels are written in a format that holds all of their values: for integer inputs with an
+//! integer slope and intercept the smallest integer type that holds the rescaled values of the volume,
+//! otherwise floats, `f64` for inputs with components of 4 bytes or more except `f32`.
+
+use crate::formats::{Format, FormatFamily, MonoFormat, PrimitiveType};
+use crate::vector3::Vector3;
+
+/// Integer component types the rescaled voxels can be stored in, from the smallest.
+const INTEGER_TYPES: [(PrimitiveType, u32); 6] = [
+    (PrimitiveType::Uint, 1), (PrimitiveType::Int, 1),
+    (PrimitiveType::Uint, 2), (PrimitiveType::Int, 2),
+    (PrimitiveType::Uint, 4), (PrimitiveType::Int, 4)
+];
+
+/// Number of components rescaled at once, so the values of a chunk stay in the cache between reading and writing.
+const CHUNK_VOXELS: usize = 4096;
+
+/// A linear map of the voxel values of an input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rescale {
+    pub slope: f64,
+    pub intercept: f64
+}
+
+impl Rescale {
+    pub fn new(slope: f64, intercept: f64) -> Self {
+        return Self { slope, intercept };
+    }
+
+    /// Whether values are left as they are.
+    pub fn is_identity(&self) -> bool {
+        return self.slope == 1.0 && self.intercept == 0.0;
+    }
+
+    /// Whether the values of a format can be rescaled. Only mono formats with native component types can.
+    /// * `format` - format of the input
+    pub fn supports(format: &Format) -> bool {
+        return Self::input_component(format).is_some();
+    }
+
+    /// Returns the mono format of the input, or None if its values cannot be rescaled.
+    /// * `format` - format of the input
+    pub(super) fn input_component(format: &Format) -> Option<&MonoFormat> {
+        let mono = match format.family() {
+            FormatFamily::Mono(m) => m,
+            _ => return None
+        };
+        let native = match mono.component_type() {
+            PrimitiveType::Float => [4, 8].contains(&mono.component_size()),
+            PrimitiveType::Int | PrimitiveType::Uint => [1, 2, 4, 8].contains(&mono.component_size())
+        };
+        return if native && mono.count() > 0 { Some(mono) } else { None };
+    }
+
+    /// Returns the format the rescaled voxels are stored in, with as many components as the input,
+    /// or None if the values of the format cannot be rescaled.
+    /// * `format` - format of the input
+    /// * `data` - voxels of the input, whose rescaled values the format holds
+    pub fn output_format(&self, format: &Format, data: &[u8]) -> Option<Format> {
+        let mono = Self::input_component(format)?;
+        let size = mono.component_size();
+        let tp = *mono.component_type();
+        let (tp, size) = match tp {
+            PrimitiveType::Float => (PrimitiveType::Float, size),
+            _ if self.slope.fract() == 0.0 && self.intercept.fract() == 0.0 => {
+                let (min, max) = value_range(mono, data);
+                let ends = [min * self.slope + self.intercept, max * self.slope + self.intercept];
+                let (low, high) = (ends[0].min(ends[1]), ends[0].max(ends[1]));
+                INTEGER_TYPES.iter()
+                    .copied()
+                    .find(|(tp, size)| {
+                        let (min, max) = integer_range(*tp, *size);
+                        return min <= low && high <= max;
+                    })
+                    .unwrap_or((PrimitiveType::Float, 8))
+            },
+            _ if size >= 4 => (PrimitiveType::Float, 8),
+            _ => (PrimitiveType::Float, 4)
+        };
+        let count = mono.count();
+        let family = FormatFamily::Mono(MonoFormat::new(count, count * size, tp));
+        return Some(Format::new(Vector3::from_xyz(1, 1, 1), count * size, family, Vec::new()));
+    }
+
+    /// Rescales the voxels of an input and returns them with their format, see `output_format`,
+    /// or None if the values of the format cannot be rescaled.
+    /// * `data` - voxels in the input format
+    /// * `format` - format of the input
+    pub fn apply(&self, data: &[u8], format: &Format) -> Option<(Vec<u8>, Format)> {
+        let output_format = self.output_format(format, data)?;
+        let input = Self::input_component(format)?;
+        let output = Self::input_component(&output_format)?;
+        let (input_size, output_size) = (input.component_size() as usize, output.component_size() as usize);
+        let components = data.len() / input_size;
+        let mut rescaled = vec![0u8; components * output_size];
+        let mut values = vec![0.0f64; CHUNK_VOXELS];
+        for (source, target) in data.chunks(CHUNK_VOXELS * input_size).zip(rescaled.chunks_mut(CHUNK_VOXELS * output_size)) {
+            let values = &mut values[..source.len() / input_size];
+            read_components(input, source, values);
+            for value in values.iter_mut() {
+                *value = *value * self.slope + self.intercept;
+            }
+            write_components(output, values, target);
+        }
+        return Some((rescaled, output_format));
+    }
+}
+
+/// Returns the smallest and largest value of an integer component type.
+/// * `tp` - the type, signed or unsigned
+/// * `size` - size of a component in bytes
+fn integer_range(tp: PrimitiveType, size: u32) -> (f64, f64) {
+    let bits = 8 * size.min(8) as i32;
+    return match tp {
+        PrimitiveType::Int => (-(2f64.powi(bits - 1)), 2f64.powi(bits - 1) - 1.0),
+        _ => (0.0, 2f64.powi(bits) - 1.0)
+    };
+}
+
+/// Returns the smallest and largest component of the voxels, 0 for both if there are none.
+/// * `format` - format of the voxels
+/// * `data` - the voxels
+fn value_range(format: &MonoFormat, data: &[u8]) -> (f64, f64) {
+    let size = format.component_size() as usize;
+    let mut values = vec![0.0f64; CHUNK_VOXELS];
+    let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+    for source in data.chunks(CHUNK_VOXELS * size) {
+        let values = &mut values[..source.len() / size];
+        read_components(format, source, values);
+        for value in values.iter() {
+            min = min.min(*value);
+            max = max.max(*value);
+        }
+    }
+    return if min <= max { (min, max) } else { (0.0, 0.0) };
+}
+
+/// Reads the components of voxels as numbers. The common types are read with their own loops,
+/// which the compiler vectorizes, the rest through `MonoFormat::component_value`.
+/// * `format` - format of the voxels
+/// * `source` - the voxels
+/// * `values` - receives the values, one for every component of `source`
+pub(super) fn read_components(format: &MonoFormat, source: &[u8], values: &mut [f64]) {
+    let size = format.component_size() as usize;
+    let components = source.chunks_exact(size).zip(values.iter_mut());
+    match (format.component_type(), size) {
+        (PrimitiveType::Uint, 1) => components.for_each(|(c, v)| *v = c[0] as f64),
+        (PrimitiveType::Int, 1) => components.for_each(|(c, v)| *v = c[0] as i8 as f64),
+        (PrimitiveType::Uint, 2) => components.for_each(|(c, v)| *v = u16::from_le_bytes([c[0], c[1]]) as f64),
+        (PrimitiveType::Int, 2) => components.for_each(|(c, v)| *v = i16::from_le_bytes([c[0], c[1]]) as f64),
+        (PrimitiveType::Float, 4) => components.for_each(|(c, v)| *v = f32::from_le_bytes([c[0], c[1], c[2], c[3]]) as f64),
+        _ => components.for_each(|(c, v)| *v = format.component_value(c))
+    }
+}
+
+/// Writes numbers as components of voxels, the inverse of `read_components`.
+/// Integers are rounded and clamped to the range of the component.
+/// * `format` - format of the voxels
+/// * `values` - the values
+/// * `target` - receives the voxels, with room for every value
+pub(super) fn write_components(format: &MonoFormat, values: &[f64], target: &mut [u8]) {
+    let size = format.component_size() as usize;
+    let components = target.chunks_exact_mut(size).zip(values.iter());
+    match (format.component_type(), size) {
+        (PrimitiveType::Uint, 1) => components.for_each(|(c, v)| c[0] = v.round() as u8),
+        (PrimitiveType::Int, 1) => components.for_each(|(c, v)| c[0] = v.round() as i8 as u8),
+        (PrimitiveType::Uint, 2) => components.for_each(|(c, v)| c.copy_from_slice(&(v.round() as u16).to_le_bytes())),
+        (PrimitiveType::Int, 2) => components.for_each(|(c, v)| c.copy_from_slice(&(v.round() as i16).to_le_bytes())),
+        (PrimitiveType::Float, 4) => components.for_each(|(c, v)| c.copy_from_slice(&(*v as f32).to_le_bytes())),
+        _ => components.for_each(|(c, v)| format.write_component_value(*v, c))
+    }
+}
@@ -0,0 +1,183 @@
+//! Inputs that are already chunked on disk, a folder of raw tiles with an index:
+//!
+//! ```json
+//! {
+//!     "tiles": [
+//!         { "file": "tile_0_0_0.raw", "position": [0, 0, 0], "dimensions": [512, 512, 64] },
+//!         { "file": "tile_1_0_0.raw", "position": [512, 0, 0], "dimensions": [512, 512, 64] }
+//!     ]
+//! }
+//! ```
+//!
+//! Tile files are resolved relative to the index. Each block is read directly from the tiles it
+//! overlaps, so the whole volume is never held in memory.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use itertools::iproduct;
+use tinyjson::JsonValue;
+
+use crate::compressions::FileCompression;
+use crate::formats::Format;
+use crate::json_aux;
+use crate::log_warn;
+use crate::vector3::Vector3;
+
+/// A tile of the volume, stored in its own raw file.
+pub struct Tile {
+    pub file: String,
+    pub position: Vector3<u32>,
+    pub dimensions: Vector3<u32>
+}
+
+/// A volume made of tiles, with the tiles every block of the conversion overlaps.
+pub struct TiledVolume {
+    pub tiles: Vec<Tile>,
+    /// Size of a microblock of the format, in bytes.
+    microblock_size: usize,
+    microblock_dimensions: Vector3<u32>,
+    block_dimensions: Vector3<u32>,
+    /// Indices of the tiles overlapping each block, by the position of the block in the block grid.
+    cells: HashMap<(u32, u32, u32), Vec<usize>>
+}
+
+/// Reads one tile of the index.
+/// * `j` - the tile
+/// * `index_folder` - folder of the index, tile files are relative to it
+fn tile_from_json(j: &JsonValue, index_folder: &Path) -> Result<Tile, String> {
+    let hashmap = match j {
+        JsonValue::Object(o) => o,
+        _ => return Err("every tile has to be an object".to_string())
+    };
+    let field = |key: &str| hashmap.get(key).ok_or_else(|| format!("a tile has no `{}`", key));
+    let file = json_aux::get_string_from_json(field("file")?).map_err(|e| e.to_string())?;
+    let position = json_aux::get_u32_dimensions_from_json(field("position")?).map_err(|e| e.to_string())?;
+    let dimensions = json_aux::get_u32_dimensions_from_json(field("dimensions")?).map_err(|e| e.to_string())?;
+    let file = match Path::new(&file).is_relative() {
+        true => index_folder.join(&file).to_string_lossy().to_string(),
+        false => file
+    };
+    return Ok(Tile { file, position, dimensions });
+}
+
+/// Returns true if two tiles have voxels in common.
+fn overlap(a: &Tile, b: &Tile) -> bool {
+    let from = a.position.max(&b.position);
+    let to = (a.position + a.dimensions).min(&(b.position + b.dimensions));
+    return from.x < to.x && from.y < to.y && from.z < to.z;
+}
+
+impl TiledVolume {
+    /// Reads the index of a tiled volume and checks that its tiles fit the volume and do not overlap.
+    /// * `index_file` - path of the index
+    /// * `dimensions` - dimensions of the volume
+    /// * `format` - format of the voxels
+    /// * `block_dimensions` - dimensions of the blocks the volume is split into
+    pub fn open(index_file: &str, dimensions: Vector3<u32>, format: &Format, block_dimensions: Vector3<u32>) -> Result<Self, String> {
+        let text = fs::read_to_string(index_file).map_err(|e| format!("cannot read {}: {}", index_file, e))?;
+        let index = text.parse::<JsonValue>().map_err(|e| format!("cannot parse {}: {}", index_file, e))?;
+        let list = match index.get::<HashMap<String, JsonValue>>().and_then(|o| o.get("tiles")) {
+            Some(JsonValue::Array(list)) if !list.is_empty() => list,
+            _ => return Err(format!("{} has no `tiles` array", index_file))
+        };
+        let index_folder = Path::new(index_file).parent().unwrap_or(Path::new(""));
+        let tiles = list.iter().map(|j| tile_from_json(j, index_folder)).collect::<Result<Vec<Tile>, String>>()?;
+
+        let microblock_dimensions = format.microblock_dimensions;
+        for tile in &tiles {
+            let end = tile.position + tile.dimensions;
+            if tile.dimensions.is_any_lt(Vector3::from_xyz(1, 1, 1)) || end.is_any_gt(dimensions) {
+                return Err(format!("tile {} at {} of {} is not inside the volume", tile.file, tile.position, tile.dimensions));
+            }
+            if tile.position.is_any_div(&microblock_dimensions) || tile.dimensions.is_any_div(&microblock_dimensions) {
+                return Err(format!("tile {} is not aligned to microblocks of {}", tile.file, microblock_dimensions));
+            }
+            // Tiles are read in place, a block at a time, which compressed files cannot be.
+            if FileCompression::from_path(&tile.file) != FileCompression::None {
+                return Err(format!("tile {} is compressed, tiles have to be raw files", tile.file));
+            }
+            let size = match format.checked_count_space(tile.dimensions) {
+                Some(size) => size as u64,
+                None => return Err(format!("tile {} of dimensions {} does not fit into memory", tile.file, tile.dimensions))
+            };
+            let file_size = fs::metadata(&tile.file).map_err(|e| format!("cannot read {}: {}", tile.file, e))?.len();
+            if file_size < size {
+                return Err(format!("{} holds {} bytes, but a tile of dimensions {} needs {}", tile.file, file_size, tile.dimensions, size));
+            }
+        }
+
+        // Map the tiles to the blocks they overlap, which is all a block has to look at.
+        let mut cells: HashMap<(u32, u32, u32), Vec<usize>> = HashMap::new();
+        for (i, tile) in tiles.iter().enumerate() {
+            let first = tile.position / block_dimensions;
+            let last = (tile.position + tile.dimensions - Vector3::from_xyz(1, 1, 1)) / block_dimensions;
+            for (x, y, z) in iproduct!(first.x..=last.x, first.y..=last.y, first.z..=last.z) {
+                cells.entry((x, y, z)).or_default().push(i);
+            }
+        }
+        for overlapping in cells.values() {
+            for (n, &a) in overlapping.iter().enumerate() {
+                for &b in &overlapping[n + 1..] {
+                    let (a, b) = (&tiles[a], &tiles[b]);
+                    if overlap(a, b) {
+                        return Err(format!("tiles {} and {} overlap", a.file, b.file));
+                    }
+                }
+            }
+        }
+        let covered = tiles.iter().filter_map(|t| t.dimensions.multiply_elements()).fold(0, u64::saturating_add);
+        let total = dimensions.multiply_elements().unwrap_or(u64::MAX);
+        if covered < total {
+            log_warn!("the tiles of {} cover {} of {} voxels, the others are zero", index_file, covered, total);
+        }
+
+        return Ok(Self {
+            tiles,
+            microblock_size: format.microblock_size as usize,
+            microblock_dimensions,
+            block_dimensions,
+            cells
+        });
+    }
+
+    /// Reads the data of a block from the tiles it overlaps. Voxels that are not in any tile are zero.
+    /// * `start`, `end` - the block, aligned to the block grid
+    pub fn read_range(&self, start: Vector3<u32>, end: Vector3<u32>) -> Result<Vec<u8>, String> {
+        let microblocks = (end - start) / self.microblock_dimensions;
+        let size = microblocks.cast::<usize>().and_then(|m| m.checked_product()).and_then(|n| n.checked_mul(self.microblock_size));
+        let mut data = match size {
+            Some(size) => vec![0u8; size],
+            None => return Err(format!("a block of dimensions {} does not fit into memory", end - start))
+        };
+        let cell = start / self.block_dimensions;
+        let overlapping = match self.cells.get(&(cell.x, cell.y, cell.z)) {
+            Some(o) => o,
+            None => return Ok(data)
+        };
+        for &i in overlapping {
+            let tile = &self.tiles[i];
+            let from = start.max(&tile.position);
+            let to = end.min(&(tile.position + tile.dimensions));
+            // Rows of microblocks along X are contiguous in both the tile file and the block.
+            let tile_microblocks = tile.dimensions / self.microblock_dimensions;
+            let in_tile = (from - tile.position) / self.microblock_dimensions;
+            let in_block = (from - start) / self.microblock_dimensions;
+            let extent = (to - from) / self.microblock_dimensions;
+            let row_length = extent.x as usize * self.microblock_size;
+
+            let mut file = fs::File::open(&tile.file).map_err(|e| format!("cannot open {}: {}", tile.file, e))?;
+            for (z, y) in iproduct!(0..extent.z, 0..extent.y) {
+                let source = ((in_tile.z + z) as u64 * tile_microblocks.y as u64 + (in_tile.y + y) as u64) * tile_microblocks.x as u64
+                    + in_tile.x as u64;
+                let target = Vector3::linear_index(in_block + Vector3::from_xyz(0, y, z), microblocks) * self.microblock_size;
+                file.seek(SeekFrom::Start(source * self.microblock_size as u64))
+                    .and_then(|_| file.read_exact(&mut data[target..target + row_length]))
+                    .map_err(|e| format!("cannot read {}: {}", tile.file, e))?;
+            }
+        }
+        return Ok(data);
+    }
+}
@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::errors::DedupError;
+use crate::vector3::Vector3;
+
+/// Number of independently locked shards. Hashes are spread
+/// over the shards by their lowest bits, so this should stay a power of two.
+const SHARD_COUNT: usize = 64;
+
+/// Number of records in a page of a spilled run. Only the first hash of every page
+/// is kept in memory, so a lookup reads one or two pages from the disk.
+const PAGE_RECORDS: usize = 256;
+
+/// Size of the Bloom filter of a spilled run, in bits per entry.
+/// With `BLOOM_PROBES` probes this lets through about one hash in a hundred that is not in the run.
+const BLOOM_BITS_PER_ENTRY: usize = 10;
+
+/// Number of bits set in the Bloom filter for every hash.
+const BLOOM_PROBES: u64 = 4;
+
+/// A single entry in the deduplication map.
+/// * `block` - index of the block holding the data
+/// * `key` - whatever the caller needs to compare data on hash collisions
+#[derive(Clone)]
+pub struct DedupEntry<K> {
+    pub block: usize,
+    pub key: K
+}
+
+/// The outcome of a lookup in the deduplication map.
+pub enum DedupResult {
+    /// Same data was already seen and stored in the block with this index.
+    Existing(usize),
+    /// Data is new and stored with this block index, allocated or given by the caller.
+    New(usize)
+}
+
+/// Keys that can be written to the disk when the deduplication map spills.
+/// Every key of a type is written with the same number of bytes.
+pub trait SpillKey: Sized {
+    /// Number of bytes of a written key.
+    const SIZE: usize;
+
+    /// Appends exactly `SIZE` bytes to `out`.
+    fn write(&self, out: &mut Vec<u8>);
+
+    /// Reads a key from the `SIZE` bytes written by `write`.
+    fn read(bytes: &[u8]) -> Self;
+}
+
+impl SpillKey for u32 {
+    const SIZE: usize = 4;
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read(bytes: &[u8]) -> Self {
+        return u32::from_le_bytes(bytes[..4].try_into().unwrap());
+    }
+}
+
+impl SpillKey for usize {
+    const SIZE: usize = 8;
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(*self as u64).to_le_bytes());
+    }
+
+    fn read(bytes: &[u8]) -> Self {
+        return u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize;
+    }
+}
+
+impl<T: SpillKey> SpillKey for Vector3<T> {
+    const SIZE: usize = 3 * T::SIZE;
+
+    fn write(&self, out: &mut Vec<u8>) {
+        self.x.write(out);
+        self.y.write(out);
+        self.z.write(out);
+    }
+
+    fn read(bytes: &[u8]) -> Self {
+        return Vector3 {
+            x: T::read(bytes),
+            y: T::read(&bytes[T::SIZE..]),
+            z: T::read(&bytes[2 * T::SIZE..])
+        };
+    }
+}
+
+impl<A: SpillKey, B: SpillKey, C: SpillKey> SpillKey for (A, B, C) {
+    const SIZE: usize = A::SIZE + B::SIZE + C::SIZE;
+
+    fn write(&self, out: &mut Vec<u8>) {
+        self.0.write(out);
+        self.1.write(out);
+        self.2.write(out);
+    }
+
+    fn read(bytes: &[u8]) -> Self {
+        return (A::read(bytes), B::read(&bytes[A::SIZE..]), C::read(&bytes[A::SIZE + B::SIZE..]));
+    }
+}
+
+/// Returns the name of the index file of a shard.
+/// * `shard` - index of the shard
+fn shard_file_name(shard: usize) -> String {
+    return format!("shard-{:02}.dedup", shard);
+}
+
+/// Removes the index files of all shards from a folder, and the folder if it is then empty.
+/// * `folder` - folder of the index files
+fn remove_index_files(folder: &Path) {
+    for shard in 0..SHARD_COUNT {
+        let _ = fs::remove_file(folder.join(shard_file_name(shard)));
+    }
+    let _ = fs::remove_dir(folder);
+}
+
+/// Where and when the deduplication map moves its entries to the disk.
+/// * `folder` - folder for the index files, created on the first spill and removed with the map
+/// * `memory_entries` - number of entries kept in memory, over all shards
+pub struct SpillSettings {
+    pub folder: PathBuf,
+    pub memory_entries: usize
+}
+
+/// Entries of a shard written to its index file, sorted by hash.
+/// Every record holds the hash, the block index and the written key.
+/// * `offset` - position of the first record in the index file
+/// * `count` - number of records
+/// * `page_hashes` - hash of the first record of every page
+/// * `bloom` - Bloom filter of the hashes of the run
+struct Run {
+    offset: u64,
+    count: usize,
+    page_hashes: Vec<u64>,
+    bloom: Vec<u64>
+}
+
+/// Returns the bits of a Bloom filter that are set for a hash.
+/// The lowest bits of hashes in a shard are the same, so they are left out.
+/// * `hash` - the hash
+/// * `bits` - size of the filter in bits
+fn bloom_bits(hash: u64, bits: usize) -> impl Iterator<Item = usize> {
+    let base = hash >> SHARD_COUNT.trailing_zeros();
+    let step = hash.rotate_left(32) | 1;
+    return (0..BLOOM_PROBES).map(move |i| (base.wrapping_add(i.wrapping_mul(step)) % bits as u64) as usize);
+}
+
+impl Run {
+    fn may_contain(&self, hash: u64) -> bool {
+        let bits = self.bloom.len() * 64;
+        return bloom_bits(hash, bits).all(|bit| self.bloom[bit / 64] & (1 << (bit % 64)) != 0);
+    }
+}
+
+/// Entries of one shard, in memory and on the disk.
+/// * `entries` - entries that were not spilled yet
+/// * `count` - number of entries in `entries`
+/// * `cached` - spilled entries that were found again, kept in memory until the next spill
+/// * `runs` - spilled entries, one run for every spill
+/// * `file` - index file holding the runs, opened on the first spill
+/// * `version` - changes whenever an entry is stored or spilled, so lookups see if they missed one
+struct Shard<K> {
+    entries: HashMap<u64, Vec<DedupEntry<K>>>,
+    count: usize,
+    cached: HashMap<u64, Vec<DedupEntry<K>>>,
+    runs: Vec<Run>,
+    file: Option<File>,
+    version: u64
+}
+
+impl<K: SpillKey + Clone> Shard<K> {
+    fn new() -> Self {
+        return Self {
+            entries: HashMap::new(),
+            count: 0,
+            cached: HashMap::new(),
+            runs: Vec::new(),
+            file: None,
+            version: 0
+        };
+    }
+
+    /// Returns copies of the entries in memory with a hash.
+    /// * `hash` - hash of the data
+    fn in_memory(&self, hash: u64) -> Vec<DedupEntry<K>> {
+        return [self.entries.get(&hash), self.cached.get(&hash)].into_iter().flatten().flatten().cloned().collect();
+    }
+
+    /// Reads the entries with a hash from the spilled runs.
+    /// * `hash` - hash of the data
+    /// * `path` - path of the index file, for errors
+    fn on_disk(&mut self, hash: u64, path: &PathBuf) -> Result<Vec<DedupEntry<K>>, DedupError> {
+        let mut found = Vec::new();
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => return Ok(found)
+        };
+        let read_error = |e| DedupError::CannotRead(path.display().to_string(), e);
+        let record_size = 16 + K::SIZE;
+        let mut page = Vec::new();
+
+        for run in self.runs.iter().filter(|run| run.may_contain(hash)) {
+            // The previous page can end with the same hash as the first of the found page.
+            let mut first_record = run.page_hashes.partition_point(|first| *first < hash).saturating_sub(1) * PAGE_RECORDS;
+            'pages: while first_record < run.count {
+                let records = PAGE_RECORDS.min(run.count - first_record);
+                page.resize(PAGE_RECORDS * record_size, 0);
+                let bytes = &mut page[..records * record_size];
+                file.seek(SeekFrom::Start(run.offset + (first_record * record_size) as u64)).map_err(read_error)?;
+                file.read_exact(bytes).map_err(read_error)?;
+                for record in bytes.chunks_exact(record_size) {
+                    let record_hash = u64::from_le_bytes(record[..8].try_into().unwrap());
+                    if record_hash > hash {
+                        break 'pages;
+                    }
+                    if record_hash < hash {
+                        continue;
+                    }
+                    found.push(DedupEntry {
+                        block: u64::from_le_bytes(record[8..16].try_into().unwrap()) as usize,
+                        key: K::read(&record[16..])
+                    });
+                }
+                first_record += records;
+            }
+        }
+        return Ok(found);
+    }
+
+    /// Reads the hashes and block indices of all spilled entries, run by run.
+    /// * `path` - path of the index file, for errors
+    fn spilled(&mut self, path: &Path) -> Result<Vec<(u64, usize)>, DedupError> {
+        let mut found = Vec::new();
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => return Ok(found)
+        };
+        let read_error = |e| DedupError::CannotRead(path.display().to_string(), e);
+        let record_size = 16 + K::SIZE;
+        let mut bytes = Vec::new();
+        for run in &self.runs {
+            bytes.resize(run.count * record_size, 0);
+            file.seek(SeekFrom::Start(run.offset)).map_err(read_error)?;
+            file.read_exact(&mut bytes).map_err(read_error)?;
+            found.extend(bytes.chunks_exact(record_size).map(|record| (
+                u64::from_le_bytes(record[..8].try_into().unwrap()),
+                u64::from_le_bytes(record[8..16].try_into().unwrap()) as usize
+            )));
+        }
+        return Ok(found);
+    }
+
+    /// Stores an entry and spills the entries in memory to a new run, if there are too many of them.
+    /// * `hash` - hash of the data
+    /// * `entry` - the new entry
+    /// * `limit` - number of entries the shard keeps in memory, `None` if it never spills
+    /// * `path` - path of the index file
+    fn push(&mut self, hash: u64, entry: DedupEntry<K>, limit: Option<usize>, path: impl FnOnce() -> PathBuf) -> Result<(), DedupError> {
+        self.entries.entry(hash).or_default().push(entry);
+        self.count += 1;
+        self.version += 1;
+        return match limit {
+            Some(limit) if self.count >= limit => self.spill(&path()),
+            _ => Ok(())
+        };
+    }
+
+    /// Writes all entries in memory to a new run at the end of the index file.
+    /// * `path` - path of the index file
+    fn spill(&mut self, path: &PathBuf) -> Result<(), DedupError> {
+        let write_error = |e| DedupError::CannotSpill(path.display().to_string(), e);
+        if self.file.is_none() {
+            if let Some(folder) = path.parent() {
+                fs::create_dir_all(folder).map_err(write_error)?;
+            }
+            let file = OpenOptions::new().read(true).write(true).create(true).truncate(true)
+                .open(path).map_err(write_error)?;
+            self.file = Some(file);
+        }
+        let file = self.file.as_mut().unwrap();
+
+        let mut entries: Vec<(u64, DedupEntry<K>)> = mem::take(&mut self.entries).into_iter()
+            .flat_map(|(hash, entries)| entries.into_iter().map(move |entry| (hash, entry)))
+            .collect();
+        // Sorting is stable, so entries with the same hash stay in the order they were stored.
+        entries.sort_by_key(|(hash, _)| *hash);
+
+        let bloom_words = (entries.len() * BLOOM_BITS_PER_ENTRY).div_ceil(64).max(1);
+        let mut bloom = vec![0u64; bloom_words];
+        let mut page_hashes = Vec::with_capacity(entries.len().div_ceil(PAGE_RECORDS));
+        let mut buffer = Vec::with_capacity(entries.len() * (16 + K::SIZE));
+        for (i, (hash, entry)) in entries.iter().enumerate() {
+            if i % PAGE_RECORDS == 0 {
+                page_hashes.push(*hash);
+            }
+            for bit in bloom_bits(*hash, bloom_words * 64) {
+                bloom[bit / 64] |= 1 << (bit % 64);
+            }
+            buffer.extend_from_slice(&hash.to_le_bytes());
+            buffer.extend_from_slice(&(entry.block as u64).to_le_bytes());
+            entry.key.write(&mut buffer);
+        }
+
+        let offset = file.seek(SeekFrom::End(0)).map_err(write_error)?;
+        file.write_all(&buffer).map_err(write_error)?;
+        self.runs.push(Run { offset, count: entries.len(), page_hashes, bloom });
+        self.count = 0;
+        self.cached.clear();
+        self.version += 1;
+        return Ok(());
+    }
+}
+
+/// Block deduplication map that can be shared between threads.
+///
+/// Instead of one lock around the whole map, hashes are split into
+/// `SHARD_COUNT` shards, each behind its own lock, so workers only wait on each other
+/// when their blocks land in the same shard. Block indices are handed out by an atomic
+/// counter, which means they are unique, but not ordered by position in the volume,
+/// unless the caller gives them with `find_or_insert_as`.
+///
+/// A map created with `with_spill` keeps a limited number of entries in memory. When a shard
+/// is full, its entries are sorted by hash and appended to the index file of the shard as a run.
+/// Only a Bloom filter and the first hash of every page of a run stay in memory,
+/// about two bytes per spilled entry, so lookups of new data rarely touch the disk.
+pub struct ShardedBlockMap<K> {
+    shards: Vec<Mutex<Shard<K>>>,
+    next_index: AtomicUsize,
+    spill: Option<SpillSettings>
+}
+
+impl<K: SpillKey + Clone> ShardedBlockMap<K> {
+    /// Creates an empty map that keeps all entries in memory.
+    /// * `first_index` - the first block index that will be allocated
+    pub fn new(first_index: usize) -> Self {
+        let mut shards = Vec::with_capacity(SHARD_COUNT);
+        for _ in 0..SHARD_COUNT {
+            shards.push(Mutex::new(Shard::new()));
+        }
+        return Self {
+            shards,
+            next_index: AtomicUsize::new(first_index),
+            spill: None
+        };
+    }
+
+    /// Creates an empty map that moves entries to the disk when there are too many of them.
+    /// Index files left in the folder by a map that was not dropped, such as one of a
+    /// conversion that crashed, are removed first.
+    /// * `first_index` - the first block index that will be allocated
+    /// * `settings` - where the entries are moved to and how many of them are kept in memory
+    pub fn with_spill(first_index: usize, settings: SpillSettings) -> Self {
+        remove_index_files(&settings.folder);
+        let mut map = Self::new(first_index);
+        map.spill = Some(settings);
+        return map;
+    }
+
+    fn shard_index(hash: u64) -> usize {
+        return hash as usize & (SHARD_COUNT - 1);
+    }
+
+    /// Returns the number of entries a shard keeps in memory, `None` if the map never spills.
+    fn shard_limit(&self) -> Option<usize> {
+        return self.spill.as_ref().map(|spill| (spill.memory_entries / SHARD_COUNT).max(1));
+    }
+
+    fn shard_path(&self, shard: usize) -> PathBuf {
+        let folder = self.spill.as_ref().map(|spill| spill.folder.clone()).unwrap_or_default();
+        return folder.join(shard_file_name(shard));
+    }
+
+    /// Looks up data with the given hash. If an entry with the same hash exists
+    /// and `is_equal` confirms the data is really the same, its block index is returned.
+    /// Otherwise a new block index is allocated and stored together with `key`.
+    /// Only the shard the hash belongs to is locked, and not while `is_equal` runs.
+    /// * `hash` - hash of the block data
+    /// * `key` - stored with the new entry, passed to `is_equal` on later collisions
+    /// * `is_equal` - compares the data of a stored entry with the data being looked up
+    pub fn find_or_insert<F>(&self, hash: u64, key: K, is_equal: F) -> Result<DedupResult, DedupError>
+        where F: Fn(&DedupEntry<K>) -> bool
+    {
+        return self.find_or_insert_with(hash, key, is_equal, || self.next_index.fetch_add(1, Ordering::Relaxed));
+    }
+
+    /// Same as `find_or_insert`, but new data is stored with the given block index instead of an allocated one,
+    /// for callers that number blocks themselves, such as by their position in the volume.
+    /// * `hash` - hash of the block data
+    /// * `key` - stored with the new entry, passed to `is_equal` on later collisions
+    /// * `block` - index of the block if the data is new
+    /// * `is_equal` - compares the data of a stored entry with the data being looked up
+    pub fn find_or_insert_as<F>(&self, hash: u64, key: K, block: usize, is_equal: F) -> Result<DedupResult, DedupError>
+        where F: Fn(&DedupEntry<K>) -> bool
+    {
+        return self.find_or_insert_with(hash, key, is_equal, || block);
+    }
+
+    fn find_or_insert_with<F, I>(&self, hash: u64, key: K, is_equal: F, new_index: I) -> Result<DedupResult, DedupError>
+        where F: Fn(&DedupEntry<K>) -> bool, I: FnOnce() -> usize
+    {
+        let index = Self::shard_index(hash);
+        let path = self.shard_path(index);
+        loop {
+            // The entries are copied out of the shard, so other workers can use it while data is compared.
+            let (in_memory, on_disk, version) = {
+                let mut shard = self.lock_shard(index);
+                (shard.in_memory(hash), shard.on_disk(hash, &path)?, shard.version)
+            };
+            if let Some(entry) = in_memory.iter().find(|entry| is_equal(entry)) {
+                return Ok(DedupResult::Existing(entry.block));
+            }
+            if let Some(entry) = on_disk.iter().find(|entry| is_equal(entry)) {
+                // Data that repeats often is only read from the disk once after every spill. The key
+                // of the new data is cached with the block, since the data is the same.
+                let mut shard = self.lock_shard(index);
+                shard.cached.entry(hash).or_default().push(DedupEntry { block: entry.block, key });
+                return Ok(DedupResult::Existing(entry.block));
+            }
+
+            let mut shard = self.lock_shard(index);
+            // Otherwise another worker stored or spilled entries in the meantime, which are compared as well.
+            if shard.version == version {
+                let block = new_index();
+                shard.push(hash, DedupEntry { block, key }, self.shard_limit(), || path)?;
+                return Ok(DedupResult::New(block));
+            }
+        }
+    }
+
+    fn lock_shard(&self, index: usize) -> MutexGuard<'_, Shard<K>> {
+        return self.shards[index].lock().expect("Dedup map shard lock has been poisoned!");
+    }
+
+    /// Stores an entry for a block whose index was allocated before, such as a block
+    /// restored from an earlier run. Its index has to be below the first allocated index.
+    /// * `hash` - hash of the block data
+    /// * `key` - passed to `is_equal` on later collisions
+    /// * `block` - index of the block holding the data
+    pub fn insert(&self, hash: u64, key: K, block: usize) -> Result<(), DedupError> {
+        let index = Self::shard_index(hash);
+        let mut shard = self.lock_shard(index);
+        return shard.push(hash, DedupEntry { block, key }, self.shard_limit(), || self.shard_path(index));
+    }
+
+    /// Allocates a new block index without storing anything, for blocks
+    /// that are not deduplicated.
+    pub fn allocate_index(&self) -> usize {
+        return self.next_index.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Consumes the map and returns a plain map from hashes to block indices.
+    /// When several blocks share the same hash, the first one stored is kept.
+    /// Entries that were moved to the disk are read back, so the plain map has all of them.
+    pub fn into_block_map(mut self) -> Result<HashMap<u64, usize>, DedupError> {
+        let mut block_map = HashMap::new();
+        for (index, shard) in mem::take(&mut self.shards).into_iter().enumerate() {
+            let mut shard = shard.into_inner()
+                .expect("Dedup map shard lock has been poisoned!");
+            // Spilled entries were stored before those in memory.
+            for (hash, block) in shard.spilled(&self.shard_path(index))? {
+                block_map.entry(hash).or_insert(block);
+            }
+            for (hash, entries) in shard.cached.into_iter().chain(shard.entries) {
+                if let Some(entry) = entries.first() {
+                    block_map.entry(hash).or_insert(entry.block);
+                }
+            }
+        }
+        return Ok(block_map);
+    }
+}
+
+impl<K> Drop for ShardedBlockMap<K> {
+    /// Removes the index files and their folder, if it is empty.
+    fn drop(&mut self) {
+        if let Some(spill) = &self.spill {
+            remove_index_files(&spill.folder);
+        }
+    }
+}
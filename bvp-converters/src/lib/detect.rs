@@ -0,0 +1,154 @@
+//! Detection of the kind of an input file from the signature at its start, so tools can read
+//! assets without being told their archive type and tell volume files apart from raw voxels.
+
+#[cfg(feature = "fs")]
+use std::{fs, io::Read, path::Path};
+use std::fmt;
+
+use crate::archives::{saf, ArchiveEnum};
+#[cfg(feature = "fs")]
+use crate::{archives::STDIO_PATH, errors::DetectError, log_debug, remote::Location};
+
+/// Number of bytes at the start of a file that are enough to detect its kind.
+/// The signature of NIfTI-1 files is the furthest in, at offset 344.
+pub const SIGNATURE_LENGTH: usize = 352;
+
+/// Kinds of input files, by their signature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    /// A BVP asset in a SAF archive
+    Saf,
+    /// A BVP asset in a ZIP archive
+    Zip,
+    /// A JSON object, such as the manifest of an unarchived asset
+    Manifest,
+    /// A folder, such as one with the files of an unarchived asset
+    Folder,
+    Nrrd,
+    /// NIfTI-1 or NIfTI-2, as a single file or the header of a pair
+    Nifti,
+    /// DICOM with the preamble and `DICM` prefix
+    Dicom,
+    /// TIFF or BigTIFF, in either byte order
+    Tiff,
+    Gzip,
+    Zstd,
+    /// Anything else, read as raw voxels
+    Raw
+}
+
+impl FileKind {
+    /// Returns the archive type assets of this kind are read with, or None if the file is not an asset.
+    pub fn archive(&self) -> Option<ArchiveEnum> {
+        return match self {
+            FileKind::Saf => Some(ArchiveEnum::SAF),
+            FileKind::Zip => Some(ArchiveEnum::ZIP),
+            FileKind::Manifest | FileKind::Folder => Some(ArchiveEnum::None),
+            _ => None
+        };
+    }
+
+    /// Returns true for volume files in a format other than raw voxels, which raw2bvp would misread.
+    pub fn is_volume_format(&self) -> bool {
+        return matches!(self, FileKind::Nrrd | FileKind::Nifti | FileKind::Dicom | FileKind::Tiff | FileKind::Gzip | FileKind::Zstd);
+    }
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FileKind::Saf => "SAF",
+            FileKind::Zip => "ZIP",
+            FileKind::Manifest => "JSON",
+            FileKind::Folder => "folder",
+            FileKind::Nrrd => "NRRD",
+            FileKind::Nifti => "NIfTI",
+            FileKind::Dicom => "DICOM",
+            FileKind::Tiff => "TIFF",
+            FileKind::Gzip => "gzip",
+            FileKind::Zstd => "zstd",
+            FileKind::Raw => "raw"
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+/// Returns true if the bytes at an offset of the data are the given ones.
+fn has_at(data: &[u8], offset: usize, expected: &[u8]) -> bool {
+    return data.get(offset..offset + expected.len()) == Some(expected);
+}
+
+/// Detects the kind of a file from its first bytes. Files without a known signature are raw.
+/// * `head` - the start of the file, at least `SIGNATURE_LENGTH` bytes unless the file is shorter
+pub fn detect_bytes(head: &[u8]) -> FileKind {
+    if saf::check_identifier(head).is_ok() {
+        return FileKind::Saf;
+    }
+    // Local file header, or the end of central directory record of an empty archive
+    if has_at(head, 0, b"PK\x03\x04") || has_at(head, 0, b"PK\x05\x06") {
+        return FileKind::Zip;
+    }
+    if has_at(head, 0, b"NRRD000") {
+        return FileKind::Nrrd;
+    }
+    if has_at(head, 344, b"n+1\0") || has_at(head, 344, b"ni1\0") || has_at(head, 4, b"n+2\0") || has_at(head, 4, b"ni2\0") {
+        return FileKind::Nifti;
+    }
+    if has_at(head, 128, b"DICM") {
+        return FileKind::Dicom;
+    }
+    if has_at(head, 0, b"II*\0") || has_at(head, 0, b"MM\0*") || has_at(head, 0, b"II+\0") || has_at(head, 0, b"MM\0+") {
+        return FileKind::Tiff;
+    }
+    if has_at(head, 0, &[0x1f, 0x8b]) {
+        return FileKind::Gzip;
+    }
+    if has_at(head, 0, &[0x28, 0xb5, 0x2f, 0xfd]) {
+        return FileKind::Zstd;
+    }
+    // A brace followed by a key or the end of the object, so voxels that start with 0x7b stay raw.
+    let mut json = head.iter().skip_while(|b| b.is_ascii_whitespace());
+    if json.next() == Some(&b'{') && matches!(json.find(|b| !b.is_ascii_whitespace()), Some(b'"') | Some(b'}')) {
+        return FileKind::Manifest;
+    }
+    return FileKind::Raw;
+}
+
+/// Detects the kind of a local file or folder from its signature.
+/// * `path` - the file or folder
+#[cfg(feature = "fs")]
+pub fn detect_file(path: &Path) -> Result<FileKind, DetectError> {
+    if path.is_dir() {
+        return Ok(FileKind::Folder);
+    }
+    let cannot_read = |e| DetectError::CannotRead(path.display().to_string(), e);
+    let file = fs::File::open(path).map_err(cannot_read)?;
+    let mut head = Vec::with_capacity(SIGNATURE_LENGTH);
+    file.take(SIGNATURE_LENGTH as u64).read_to_end(&mut head).map_err(cannot_read)?;
+    let kind = detect_bytes(&head);
+    log_debug!("{} is a {} file", path.display(), kind);
+    return Ok(kind);
+}
+
+/// Returns the archive type of an asset, detected from the signature of a local file or from the
+/// extension of a URL, where `.saf` and `.zip` are archives and anything else is a manifest.
+/// Fails for files that are not assets, and for stdin, whose archive type has to be given.
+/// * `path` - the asset, as given to `ArchiveEnum::read_archive`
+#[cfg(feature = "fs")]
+pub fn detect_archive(path: &Path) -> Result<ArchiveEnum, DetectError> {
+    let name = path.display().to_string();
+    if path == Path::new(STDIO_PATH) {
+        return Err(DetectError::Undetectable(name));
+    }
+    if Location::parse(&name).map_or(false, |l| l.is_remote()) {
+        let lowercase = name.to_lowercase();
+        if lowercase.ends_with(".saf") {
+            return Ok(ArchiveEnum::SAF);
+        } else if lowercase.ends_with(".zip") {
+            return Ok(ArchiveEnum::ZIP);
+        }
+        return Ok(ArchiveEnum::None);
+    }
+    let kind = detect_file(path)?;
+    return kind.archive().ok_or(DetectError::NotAnAsset(name, kind.to_string()));
+}
@@ -0,0 +1,288 @@
+//! Digests of whole reconstructed volumes, for checking a conversion end to end. `raw2bvp` can record the
+//! digest of every modality in the `EXT_checksum` payload of the modality,
+//!
+//! ```json
+//! "extensions": {
+//!     "EXT_checksum": { "volume": "sha256:2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae" }
+//! }
+//! ```
+//!
+//! and `bvp2raw --checksum` computes the digest of the volume it reconstructs and compares the two.
+//! The digest covers the voxels of the modality as `bvp2raw` writes them, the whole volume with X changing
+//! fastest, so it is the digest of the raw input when the voxels were not changed during the conversion.
+//! Unlike block checksums, which only detect damaged files, digests can be cryptographic.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+use tinyjson::JsonValue;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::{errors::{ChecksumError, JsonError}, extensions::Extension, json_aux, modality::Modality, signature};
+
+/// Hash algorithms of volume digests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DigestType {
+    Xxh3,
+    Sha256
+}
+
+impl DigestType {
+    pub fn from_string(s: &str) -> Result<Self, ChecksumError> {
+        return match s {
+            "xxh3" | "XXH3" => Ok(Self::Xxh3),
+            "sha256" | "SHA256" => Ok(Self::Sha256),
+            _ => Err(ChecksumError::UnsupportedAlgorithm(s.to_string()))
+        }
+    }
+
+    /// Returns the length of the digests of the algorithm in bytes.
+    fn length(&self) -> usize {
+        return match self {
+            DigestType::Xxh3 => 8,
+            DigestType::Sha256 => 32
+        };
+    }
+}
+
+impl fmt::Display for DigestType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DigestType::Xxh3 => "xxh3",
+            DigestType::Sha256 => "sha256"
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+/// Digest of a volume. It is written as `<algorithm>:<hexadecimal value>`, like block checksums.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Digest {
+    pub algorithm: DigestType,
+    /// The hash, big endian
+    pub value: Vec<u8>
+}
+
+impl Digest {
+    /// Computes the digest of data held in memory.
+    /// * `algorithm` - the hash algorithm to use
+    /// * `data` - bytes to hash
+    pub fn compute(algorithm: DigestType, data: &[u8]) -> Self {
+        let mut hasher = Hasher::new(algorithm);
+        hasher.update(data);
+        return hasher.finish();
+    }
+
+    pub fn from_string(s: &str) -> Result<Self, ChecksumError> {
+        let (algorithm, value) = match s.split_once(':') {
+            Some(parts) => parts,
+            None => return Err(ChecksumError::InvalidValue(s.to_string()))
+        };
+        let algorithm = DigestType::from_string(algorithm)?;
+        if value.len() != 2 * algorithm.length() || !value.is_ascii() {
+            return Err(ChecksumError::InvalidValue(s.to_string()));
+        }
+        let value = (0..value.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| ChecksumError::InvalidValue(s.to_string()))?;
+        return Ok(Self { algorithm, value });
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}:{}", self.algorithm, signature::to_hex(&self.value));
+    }
+}
+
+/// Computes a digest of data given in parts, such as the slabs of a volume.
+pub enum Hasher {
+    Xxh3(Box<Xxh3>),
+    Sha256(Sha256)
+}
+
+impl Hasher {
+    pub fn new(algorithm: DigestType) -> Self {
+        return match algorithm {
+            DigestType::Xxh3 => Hasher::Xxh3(Box::new(Xxh3::new())),
+            DigestType::Sha256 => Hasher::Sha256(Sha256::new())
+        };
+    }
+
+    /// Adds the next part of the data.
+    /// * `data` - the part
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Xxh3(h) => h.update(data),
+            Hasher::Sha256(h) => h.update(data)
+        }
+    }
+
+    /// Returns the digest of all data added so far.
+    pub fn finish(&self) -> Digest {
+        return match self {
+            Hasher::Xxh3(h) => Digest { algorithm: DigestType::Xxh3, value: h.digest().to_be_bytes().to_vec() },
+            Hasher::Sha256(h) => Digest { algorithm: DigestType::Sha256, value: h.clone().finish().to_vec() }
+        };
+    }
+}
+
+/// Writer that computes the digest of everything written through it.
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Hasher
+}
+
+impl<W: Write> HashingWriter<W> {
+    /// * `inner` - where the data is written
+    /// * `algorithm` - the hash algorithm to use
+    pub fn new(inner: W, algorithm: DigestType) -> Self {
+        return Self { inner, hasher: Hasher::new(algorithm) };
+    }
+
+    /// Returns the digest of the data written so far.
+    pub fn digest(&self) -> Digest {
+        return self.hasher.finish();
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        return Ok(written);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.inner.flush();
+    }
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2
+];
+const SHA256_INITIAL: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19
+];
+
+/// SHA-256 (FIPS 180-4) of data given in parts.
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    /// Bytes of the current chunk that is not full yet
+    buffer: [u8; 64],
+    buffered: usize,
+    length: u64
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        return Self { state: SHA256_INITIAL, buffer: [0; 64], buffered: 0, length: 0 };
+    }
+
+    /// Adds the next part of the data.
+    /// * `data` - the part
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        if self.buffered > 0 {
+            let taken = data.len().min(64 - self.buffered);
+            self.buffer[self.buffered..self.buffered + taken].copy_from_slice(&data[..taken]);
+            self.buffered += taken;
+            data = &data[taken..];
+            if self.buffered < 64 {
+                return;
+            }
+            let chunk = self.buffer;
+            self.compress(&chunk);
+            self.buffered = 0;
+        }
+        let mut chunks = data.chunks_exact(64);
+        for chunk in &mut chunks {
+            self.compress(chunk.try_into().unwrap());
+        }
+        let rest = chunks.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    /// Returns the hash of all data added.
+    pub fn finish(mut self) -> [u8; 32] {
+        let bits = self.length.wrapping_mul(8);
+        let mut padding = vec![0x80u8];
+        padding.resize((119 - self.buffered) % 64 + 1, 0);
+        padding.extend_from_slice(&bits.to_be_bytes());
+        let length = self.length;
+        self.update(&padding);
+        self.length = length;
+
+        let mut hash = [0u8; 32];
+        for (bytes, s) in hash.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&s.to_be_bytes());
+        }
+        return hash;
+    }
+
+    fn compress(&mut self, chunk: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for (i, word) in chunk.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(choice).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
+
+/// Returns the digest of the whole volume of a modality, if the asset records one.
+/// * `modality` - the modality
+pub fn volume_digest(modality: &Modality) -> Result<Option<Digest>, ChecksumError> {
+    let payload = match modality.extension_payloads.get(&Extension::ExtChecksum.to_string()) {
+        Some(JsonValue::Object(o)) => o,
+        Some(j) => return Err(ChecksumError::InvalidJson(JsonError::NotAnObject(j.clone()))),
+        None => return Ok(None)
+    };
+    return match payload.get("volume") {
+        Some(j) => Digest::from_string(&json_aux::get_string_from_json(j).map_err(ChecksumError::InvalidJson)?).map(Some),
+        None => Ok(None)
+    };
+}
+
+/// Records the digest of the whole volume of a modality.
+/// * `modality` - the modality
+/// * `digest` - digest of its reconstructed voxels
+pub fn set_volume_digest(modality: &mut Modality, digest: &Digest) {
+    let mut payload = HashMap::new();
+    payload.insert("volume".to_string(), JsonValue::from(digest.to_string()));
+    modality.extension_payloads.insert(Extension::ExtChecksum.to_string(), payload.into());
+}
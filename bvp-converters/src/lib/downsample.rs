@@ -0,0 +1,147 @@
+//! Reduction of volumes to a lower resolution, for previews and lightweight copies of assets.
+//!
+//! Every voxel of the downsampled volume is computed from a box of `factor` voxels along each axis
+//! of the original, with fewer at the far edges when the dimensions are not multiples of the factor.
+//! Components are filtered independently of each other.
+
+use std::fmt;
+
+use crate::{errors::DownsampleError, formats::{Format, FormatFamily, MonoFormat}, vector3::Vector3};
+
+/// How the voxels of a box are combined into one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DownsampleFilter {
+    /// Average of the box, rounded for integers.
+    Mean,
+    /// Smallest value of the box.
+    Min,
+    /// Largest value of the box, which keeps thin bright structures such as vessels visible.
+    Max,
+    /// First voxel of the box, which keeps labels of segmentation masks intact.
+    Nearest
+}
+
+impl DownsampleFilter {
+    pub fn from_string(s: &str) -> Result<Self, DownsampleError> {
+        return match s {
+            "mean" => Ok(Self::Mean),
+            "min" => Ok(Self::Min),
+            "max" => Ok(Self::Max),
+            "nearest" => Ok(Self::Nearest),
+            _ => Err(DownsampleError::UnknownFilter(s.to_string()))
+        };
+    }
+}
+
+impl fmt::Display for DownsampleFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Mean => "mean",
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Nearest => "nearest"
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+/// Returns the dimensions of a downsampled volume, rounded up so that no voxels are lost.
+/// * `dimensions` - dimensions of the original volume
+/// * `factor` - the downsampling factor
+pub fn downsampled_dimensions(dimensions: Vector3<u32>, factor: u32) -> Vector3<u32> {
+    return dimensions.div_ceil(&Vector3::splat(factor.max(1)));
+}
+
+/// Returns every component of a voxel with its offset in the voxel, or an error if the values of the format cannot be written.
+/// * `format` - the format
+fn components(format: &Format) -> Result<Vec<(&MonoFormat, usize)>, DownsampleError> {
+    let unsupported = || DownsampleError::UnsupportedFormat(format.family().name().to_string());
+    if format.microblock_dimensions != Vector3::splat(1) {
+        return Err(unsupported());
+    }
+    let monos: Vec<&MonoFormat> = match format.family() {
+        FormatFamily::Mono(m) => vec![m],
+        FormatFamily::Multi(m) => m.components().iter().collect(),
+        FormatFamily::Compressed(_) => return Err(unsupported())
+    };
+    let mut components = Vec::new();
+    let mut offset = 0;
+    for mono in monos {
+        let size = mono.component_size() as usize;
+        for _ in 0..mono.count() {
+            components.push((mono, offset));
+            offset += size;
+        }
+    }
+    return Ok(components);
+}
+
+/// Returns an error if volumes of a format cannot be downsampled.
+/// * `format` - the format
+pub fn check_format(format: &Format) -> Result<(), DownsampleError> {
+    return components(format).map(|_| ());
+}
+
+/// Downsamples a volume and returns the voxels of the result, whose dimensions are given by `downsampled_dimensions`.
+/// * `format` - format of the voxels
+/// * `data` - voxels of the volume, X changing fastest
+/// * `dimensions` - dimensions of the volume
+/// * `factor` - the downsampling factor along every axis, at least 2
+/// * `filter` - how the voxels of a box are combined
+pub fn downsample(format: &Format, data: &[u8], dimensions: Vector3<u32>, factor: u32, filter: DownsampleFilter) -> Result<Vec<u8>, DownsampleError> {
+    if factor < 2 {
+        return Err(DownsampleError::InvalidFactor(factor));
+    }
+    let components = components(format)?;
+    let voxel_size = format.microblock_size as usize;
+    let expected_size = match format.checked_count_space(dimensions) {
+        Some(size) => size,
+        None => return Err(DownsampleError::TooLarge(dimensions))
+    };
+    if data.len() < expected_size {
+        return Err(DownsampleError::DataSizeMismatch(data.len(), dimensions, expected_size));
+    }
+
+    let output_dimensions = downsampled_dimensions(dimensions, factor);
+    let output_size = match format.checked_count_space(output_dimensions) {
+        Some(size) => size,
+        None => return Err(DownsampleError::TooLarge(output_dimensions))
+    };
+    let mut output = vec![0u8; output_size];
+    let mut box_voxels = Vec::with_capacity((factor * factor * factor) as usize);
+    for z in 0..output_dimensions.z {
+        for y in 0..output_dimensions.y {
+            for x in 0..output_dimensions.x {
+                let start = Vector3::from_xyz(x, y, z) * factor;
+                let end = (start + Vector3::splat(factor)).min(&dimensions);
+                let target = Vector3::linear_index(Vector3::from_xyz(x, y, z), output_dimensions) * voxel_size;
+                let target = &mut output[target..target + voxel_size];
+                if filter == DownsampleFilter::Nearest {
+                    let source = Vector3::linear_index(start, dimensions) * voxel_size;
+                    target.copy_from_slice(&data[source..source + voxel_size]);
+                    continue;
+                }
+
+                box_voxels.clear();
+                for bz in start.z..end.z {
+                    for by in start.y..end.y {
+                        let row = Vector3::linear_index(Vector3::from_xyz(start.x, by, bz), dimensions) * voxel_size;
+                        box_voxels.extend((0..(end.x - start.x) as usize).map(|i| row + i * voxel_size));
+                    }
+                }
+                for (component, offset) in &components {
+                    let size = component.component_size() as usize;
+                    let values = box_voxels.iter().map(|voxel| component.component_value(&data[voxel + offset..voxel + offset + size]));
+                    let value = match filter {
+                        DownsampleFilter::Mean => values.sum::<f64>() / box_voxels.len() as f64,
+                        DownsampleFilter::Min => values.fold(f64::INFINITY, f64::min),
+                        DownsampleFilter::Max => values.fold(f64::NEG_INFINITY, f64::max),
+                        DownsampleFilter::Nearest => unreachable!()
+                    };
+                    component.write_component_value(value, &mut target[*offset..offset + size]);
+                }
+            }
+        }
+    }
+    return Ok(output);
+}
@@ -0,0 +1,436 @@
+//! Ed25519 signatures (RFC 8032), used to sign manifests, see `signature`.
+//!
+//! The arithmetic follows TweetNaCl: field elements are 16 limbs of 16 bits, and points are in
+//! extended coordinates. It favours being short and easy to check over speed, which does not matter
+//! for signing one manifest.
+//!
+//! It is not constant-time. Points are swapped without branches as in TweetNaCl, but nothing keeps
+//! the compiler from adding branches or the timing of the arithmetic from depending on the secret
+//! scalar. Signing keys should only be used where others cannot measure how long signing takes.
+
+/// Length of seeds, from which the secret and public key are derived, and of public keys.
+pub const KEY_LENGTH: usize = 32;
+/// Length of signatures.
+pub const SIGNATURE_LENGTH: usize = 64;
+
+const SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22, 0x7137449123ef65cd, 0xb5c0fbcfec4d3b2f, 0xe9b5dba58189dbbc,
+    0x3956c25bf348b538, 0x59f111f1b605d019, 0x923f82a4af194f9b, 0xab1c5ed5da6d8118,
+    0xd807aa98a3030242, 0x12835b0145706fbe, 0x243185be4ee4b28c, 0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f, 0x80deb1fe3b1696b1, 0x9bdc06a725c71235, 0xc19bf174cf692694,
+    0xe49b69c19ef14ad2, 0xefbe4786384f25e3, 0x0fc19dc68b8cd5b5, 0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275, 0x4a7484aa6ea6e483, 0x5cb0a9dcbd41fbd4, 0x76f988da831153b5,
+    0x983e5152ee66dfab, 0xa831c66d2db43210, 0xb00327c898fb213f, 0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2, 0xd5a79147930aa725, 0x06ca6351e003826f, 0x142929670a0e6e70,
+    0x27b70a8546d22ffc, 0x2e1b21385c26c926, 0x4d2c6dfc5ac42aed, 0x53380d139d95b3df,
+    0x650a73548baf63de, 0x766a0abb3c77b2a8, 0x81c2c92e47edaee6, 0x92722c851482353b,
+    0xa2bfe8a14cf10364, 0xa81a664bbc423001, 0xc24b8b70d0f89791, 0xc76c51a30654be30,
+    0xd192e819d6ef5218, 0xd69906245565a910, 0xf40e35855771202a, 0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8, 0x1e376c085141ab53, 0x2748774cdf8eeb99, 0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63, 0x4ed8aa4ae3418acb, 0x5b9cca4f7763e373, 0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc, 0x78a5636f43172f60, 0x84c87814a1f0ab72, 0x8cc702081a6439ec,
+    0x90befffa23631e28, 0xa4506cebde82bde9, 0xbef9a3f7b2c67915, 0xc67178f2e372532b,
+    0xca273eceea26619c, 0xd186b8c721c0c207, 0xeada7dd6cde0eb1e, 0xf57d4f7fee6ed178,
+    0x06f067aa72176fba, 0x0a637dc5a2c898a6, 0x113f9804bef90dae, 0x1b710b35131c471b,
+    0x28db77f523047d84, 0x32caab7b40c72493, 0x3c9ebe0a15c9bebc, 0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6, 0x597f299cfc657e2a, 0x5fcb6fab3ad6faec, 0x6c44198c4a475817,
+];
+const SHA512_INITIAL: [u64; 8] = [
+    0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+    0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179
+];
+
+/// Returns the SHA-512 hash of the concatenation of the parts.
+/// * `parts` - the data to hash
+fn sha512(parts: &[&[u8]]) -> [u8; 64] {
+    let mut state = SHA512_INITIAL;
+    let total: usize = parts.iter().map(|p| p.len()).sum();
+    let mut message: Vec<u8> = Vec::with_capacity(total + 129);
+    for part in parts {
+        message.extend_from_slice(part);
+    }
+    message.push(0x80);
+    while message.len() % 128 != 112 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((total as u128) * 8).to_be_bytes());
+
+    for chunk in message.chunks_exact(128) {
+        let mut w = [0u64; 80];
+        for (i, word) in chunk.chunks_exact(8).enumerate() {
+            w[i] = u64::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+            let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..80 {
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let choice = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(choice).wrapping_add(SHA512_K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut hash = [0u8; 64];
+    for (bytes, s) in hash.chunks_exact_mut(8).zip(state) {
+        bytes.copy_from_slice(&s.to_be_bytes());
+    }
+    return hash;
+}
+
+/// Element of the field of integers modulo 2^255 - 19, in 16 limbs of 16 bits.
+type Field = [i64; 16];
+/// Point of the curve in extended coordinates (X, Y, Z, T).
+type Point = [Field; 4];
+
+const ZERO: Field = [0; 16];
+const ONE: Field = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+/// The curve constant d.
+const D: Field = [
+    0x78a3, 0x1359, 0x4dca, 0x75eb, 0xd8ab, 0x4141, 0x0a4d, 0x0070, 0xe898, 0x7779, 0x4079, 0x8cc7, 0xfe73, 0x2b6f, 0x6cee, 0x5203
+];
+/// 2 * d.
+const D2: Field = [
+    0xf159, 0x26b2, 0x9b94, 0xebd6, 0xb156, 0x8283, 0x149a, 0x00e0, 0xd130, 0xeef3, 0x80f2, 0x198e, 0xfce7, 0x56df, 0xd9dc, 0x2406
+];
+/// Coordinates of the base point.
+const BASE_X: Field = [
+    0xd51a, 0x8f25, 0x2d60, 0xc956, 0xa7b2, 0x9525, 0xc760, 0x692c, 0xdc5c, 0xfdd6, 0xe231, 0xc0a4, 0x53fe, 0xcd6e, 0x36d3, 0x2169
+];
+const BASE_Y: Field = [
+    0x6658, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666
+];
+/// A square root of -1.
+const SQRT_M1: Field = [
+    0xa0b0, 0x4a0e, 0x1b27, 0xc4ee, 0xe478, 0xad2f, 0x1806, 0x2f43, 0xd7a7, 0x3dfb, 0x0099, 0x2b4d, 0xdf0b, 0x4fc1, 0x2480, 0x2b83
+];
+/// Order of the base point, little endian.
+const ORDER: [i64; 32] = [
+    0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10
+];
+
+fn carry(o: &mut Field) {
+    for i in 0..16 {
+        o[i] += 1 << 16;
+        let c = o[i] >> 16;
+        if i < 15 {
+            o[i + 1] += c - 1;
+        } else {
+            o[0] += 38 * (c - 1);
+        }
+        o[i] -= c << 16;
+    }
+}
+
+/// Swaps `p` and `q` if `b` is 1, in constant time.
+fn select(p: &mut Field, q: &mut Field, b: i64) {
+    let c = !(b - 1);
+    for i in 0..16 {
+        let t = c & (p[i] ^ q[i]);
+        p[i] ^= t;
+        q[i] ^= t;
+    }
+}
+
+fn pack_field(n: &Field) -> [u8; 32] {
+    let mut t = *n;
+    carry(&mut t);
+    carry(&mut t);
+    carry(&mut t);
+    for _ in 0..2 {
+        let mut m = ZERO;
+        m[0] = t[0] - 0xffed;
+        for i in 1..15 {
+            m[i] = t[i] - 0xffff - ((m[i - 1] >> 16) & 1);
+            m[i - 1] &= 0xffff;
+        }
+        m[15] = t[15] - 0x7fff - ((m[14] >> 16) & 1);
+        let b = (m[15] >> 16) & 1;
+        m[14] &= 0xffff;
+        select(&mut t, &mut m, 1 - b);
+    }
+    let mut o = [0u8; 32];
+    for i in 0..16 {
+        o[2 * i] = (t[i] & 0xff) as u8;
+        o[2 * i + 1] = (t[i] >> 8) as u8;
+    }
+    return o;
+}
+
+fn unpack_field(n: &[u8; 32]) -> Field {
+    let mut o = ZERO;
+    for i in 0..16 {
+        o[i] = n[2 * i] as i64 + ((n[2 * i + 1] as i64) << 8);
+    }
+    o[15] &= 0x7fff;
+    return o;
+}
+
+fn parity(a: &Field) -> u8 {
+    return pack_field(a)[0] & 1;
+}
+
+fn add(a: &Field, b: &Field) -> Field {
+    let mut o = ZERO;
+    for i in 0..16 {
+        o[i] = a[i] + b[i];
+    }
+    return o;
+}
+
+fn sub(a: &Field, b: &Field) -> Field {
+    let mut o = ZERO;
+    for i in 0..16 {
+        o[i] = a[i] - b[i];
+    }
+    return o;
+}
+
+fn mul(a: &Field, b: &Field) -> Field {
+    let mut t = [0i64; 31];
+    for i in 0..16 {
+        for j in 0..16 {
+            t[i + j] += a[i] * b[j];
+        }
+    }
+    for i in 0..15 {
+        t[i] += 38 * t[i + 16];
+    }
+    let mut o = ZERO;
+    o.copy_from_slice(&t[..16]);
+    carry(&mut o);
+    carry(&mut o);
+    return o;
+}
+
+fn square(a: &Field) -> Field {
+    return mul(a, a);
+}
+
+fn invert(i: &Field) -> Field {
+    let mut c = *i;
+    for a in (0..=253).rev() {
+        c = square(&c);
+        if a != 2 && a != 4 {
+            c = mul(&c, i);
+        }
+    }
+    return c;
+}
+
+/// Returns i^((p - 5) / 8), used to compute square roots.
+fn pow2523(i: &Field) -> Field {
+    let mut c = *i;
+    for a in (0..=250).rev() {
+        c = square(&c);
+        if a != 1 {
+            c = mul(&c, i);
+        }
+    }
+    return c;
+}
+
+/// Adds `q` to `p`.
+fn add_point(p: &mut Point, q: &Point) {
+    let a = mul(&sub(&p[1], &p[0]), &sub(&q[1], &q[0]));
+    let b = mul(&add(&p[0], &p[1]), &add(&q[0], &q[1]));
+    let c = mul(&mul(&p[3], &q[3]), &D2);
+    let d = mul(&p[2], &q[2]);
+    let d = add(&d, &d);
+    let (e, f, g, h) = (sub(&b, &a), sub(&d, &c), add(&d, &c), add(&b, &a));
+    *p = [mul(&e, &f), mul(&h, &g), mul(&g, &f), mul(&e, &h)];
+}
+
+fn swap_points(p: &mut Point, q: &mut Point, b: i64) {
+    for i in 0..4 {
+        select(&mut p[i], &mut q[i], b);
+    }
+}
+
+fn pack_point(p: &Point) -> [u8; 32] {
+    let zi = invert(&p[2]);
+    let (x, y) = (mul(&p[0], &zi), mul(&p[1], &zi));
+    let mut r = pack_field(&y);
+    r[31] ^= parity(&x) << 7;
+    return r;
+}
+
+/// Returns the point `s * q`, with a ladder that takes the same time for every scalar.
+fn scalar_mult(q: &Point, s: &[u8; 32]) -> Point {
+    let mut p = [ZERO, ONE, ONE, ZERO];
+    let mut q = *q;
+    for i in (0..256).rev() {
+        let b = ((s[i / 8] >> (i & 7)) & 1) as i64;
+        swap_points(&mut p, &mut q, b);
+        let p_copy = p;
+        add_point(&mut q, &p_copy);
+        add_point(&mut p, &p_copy);
+        swap_points(&mut p, &mut q, b);
+    }
+    return p;
+}
+
+fn scalar_base(s: &[u8; 32]) -> Point {
+    return scalar_mult(&[BASE_X, BASE_Y, ONE, mul(&BASE_X, &BASE_Y)], s);
+}
+
+/// Decodes a point and negates it, or returns None if the bytes are not a point of the curve.
+fn unpack_negated(p: &[u8; 32]) -> Option<Point> {
+    let y = unpack_field(p);
+    let num = square(&y);
+    let den = mul(&num, &D);
+    let num = sub(&num, &ONE);
+    let den = add(&ONE, &den);
+    let den2 = square(&den);
+    let den4 = square(&den2);
+    let den6 = mul(&den4, &den2);
+    let t = mul(&mul(&den6, &num), &den);
+    let t = mul(&mul(&mul(&pow2523(&t), &num), &den), &den);
+    let mut x = mul(&t, &den);
+    if pack_field(&mul(&square(&x), &den)) != pack_field(&num) {
+        x = mul(&x, &SQRT_M1);
+    }
+    if pack_field(&mul(&square(&x), &den)) != pack_field(&num) {
+        return None;
+    }
+    if parity(&x) == (p[31] >> 7) {
+        x = sub(&ZERO, &x);
+    }
+    return Some([x, y, ONE, mul(&x, &y)]);
+}
+
+/// Reduces a number of 64 bytes modulo the order of the base point.
+fn reduce_scalar(x: &mut [i64; 64]) -> [u8; 32] {
+    for i in (32..64).rev() {
+        let mut c = 0;
+        let mut j = i - 32;
+        while j < i - 12 {
+            x[j] += c - 16 * x[i] * ORDER[j - (i - 32)];
+            c = (x[j] + 128) >> 8;
+            x[j] -= c << 8;
+            j += 1;
+        }
+        x[j] += c;
+        x[i] = 0;
+    }
+    let mut c = 0;
+    for j in 0..32 {
+        x[j] += c - (x[31] >> 4) * ORDER[j];
+        c = x[j] >> 8;
+        x[j] &= 255;
+    }
+    for j in 0..32 {
+        x[j] -= c * ORDER[j];
+    }
+    let mut r = [0u8; 32];
+    for i in 0..32 {
+        x[i + 1] += x[i] >> 8;
+        r[i] = (x[i] & 255) as u8;
+    }
+    return r;
+}
+
+fn reduce_hash(hash: &[u8; 64]) -> [u8; 32] {
+    let mut x = [0i64; 64];
+    for (x, h) in x.iter_mut().zip(hash) {
+        *x = *h as i64;
+    }
+    return reduce_scalar(&mut x);
+}
+
+/// Returns true if a scalar of a signature is below the order, so signatures cannot be altered.
+fn is_canonical(s: &[u8]) -> bool {
+    for i in (0..32).rev() {
+        if (s[i] as i64) != ORDER[i] {
+            return (s[i] as i64) < ORDER[i];
+        }
+    }
+    return false;
+}
+
+/// Returns the secret scalar and the prefix of the nonces of a seed.
+fn expand_seed(seed: &[u8; KEY_LENGTH]) -> ([u8; 32], [u8; 32]) {
+    let hash = sha512(&[seed]);
+    let mut scalar: [u8; 32] = hash[..32].try_into().unwrap();
+    scalar[0] &= 248;
+    scalar[31] &= 127;
+    scalar[31] |= 64;
+    return (scalar, hash[32..].try_into().unwrap());
+}
+
+/// A secret key, given by its seed of 32 bytes.
+#[derive(Clone)]
+pub struct SigningKey {
+    seed: [u8; KEY_LENGTH],
+    public_key: [u8; KEY_LENGTH]
+}
+
+impl SigningKey {
+    pub fn from_seed(seed: [u8; KEY_LENGTH]) -> Self {
+        let (scalar, _) = expand_seed(&seed);
+        let public_key = pack_point(&scalar_base(&scalar));
+        return Self { seed, public_key };
+    }
+
+    pub fn public_key(&self) -> [u8; KEY_LENGTH] {
+        return self.public_key;
+    }
+
+    /// Signs a message.
+    /// * `message` - the message
+    pub fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_LENGTH] {
+        let (scalar, prefix) = expand_seed(&self.seed);
+        let r = reduce_hash(&sha512(&[&prefix, message]));
+        let big_r = pack_point(&scalar_base(&r));
+        let h = reduce_hash(&sha512(&[&big_r, &self.public_key, message]));
+        let mut x = [0i64; 64];
+        for i in 0..32 {
+            x[i] = r[i] as i64;
+        }
+        for i in 0..32 {
+            for j in 0..32 {
+                x[i + j] += h[i] as i64 * scalar[j] as i64;
+            }
+        }
+        let s = reduce_scalar(&mut x);
+        let mut signature = [0u8; SIGNATURE_LENGTH];
+        signature[..32].copy_from_slice(&big_r);
+        signature[32..].copy_from_slice(&s);
+        return signature;
+    }
+}
+
+/// Returns true if a signature of a message was made with the secret key of a public key.
+/// * `public_key` - the public key
+/// * `message` - the message
+/// * `signature` - the signature
+pub fn verify(public_key: &[u8; KEY_LENGTH], message: &[u8], signature: &[u8; SIGNATURE_LENGTH]) -> bool {
+    if !is_canonical(&signature[32..]) {
+        return false;
+    }
+    let negated_key = match unpack_negated(public_key) {
+        Some(p) => p,
+        None => return false
+    };
+    let h = reduce_hash(&sha512(&[&signature[..32], public_key, message]));
+    let mut p = scalar_mult(&negated_key, &h);
+    add_point(&mut p, &scalar_base(signature[32..].try_into().unwrap()));
+    return pack_point(&p) == signature[..32];
+}
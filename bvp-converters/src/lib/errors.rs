@@ -1,8 +1,76 @@
+use std::io;
+
 use thiserror::Error;
 use tinyjson::JsonValue;
 
 use crate::vector3::Vector3;
 
+/// Any error of the library. All error types of the library convert into it,
+/// so applications can use `?` on every library call and still match on the kind of error.
+#[derive(Error, Debug)]
+pub enum BvpError {
+    #[error("{0}")]
+    BvpFile(#[from] BvpFileError),
+    #[error("{0}")]
+    Asset(#[from] AssetError),
+    #[error("{0}")]
+    Modality(#[from] ModalityError),
+    #[error("{0}")]
+    Block(#[from] BlockError),
+    #[error("{0}")]
+    Placement(#[from] PlacementError),
+    #[error("{0}")]
+    Format(#[from] FormatError),
+    #[error("{0}")]
+    Reader(#[from] ReaderError),
+    #[error("{0}")]
+    Archive(#[from] ArchiveError),
+    #[error("{0}")]
+    File(#[from] FileError),
+    #[error("{0}")]
+    Json(#[from] JsonError),
+    #[error("{0}")]
+    Compression(#[from] CompressionError),
+    #[error("{0}")]
+    Checksum(#[from] ChecksumError),
+    #[error("{0}")]
+    Version(#[from] VersionError),
+    #[error("{0}")]
+    Writer(#[from] WriterError),
+    #[error("{0}")]
+    TransferFunction(#[from] TransferFunctionError),
+    #[error("{0}")]
+    Transform(#[from] TransformError),
+    #[error("{0}")]
+    WindowLevel(#[from] WindowLevelError),
+    #[error("{0}")]
+    Layout(#[from] LayoutError),
+    #[error("{0}")]
+    Occupancy(#[from] OccupancyError),
+    #[error("{0}")]
+    Source(#[from] SourceError),
+    #[error("{0}")]
+    Tree(#[from] TreeError),
+    #[error("{0}")]
+    Signature(#[from] SignatureError),
+    #[error("{0}")]
+    Downsample(#[from] DownsampleError),
+    #[error("{0}")]
+    Dedup(#[from] DedupError),
+    #[error("{0}")]
+    Metrics(#[from] MetricsError),
+    #[error("{0}")]
+    Convert(#[from] ConvertError),
+    #[error("{0}")]
+    Detect(#[from] DetectError),
+    #[error("{0}")]
+    ConfigFormat(#[from] ConfigFormatError),
+    #[error("{0}")]
+    Parameters(#[from] ParametersError),
+    #[error("{0}")]
+    Filter(#[from] FilterError)
+}
+
 
 #[derive(Error, Debug)]
 pub enum PlacementError {
@@ -10,18 +78,45 @@ pub enum PlacementError {
     InvalidJson(usize, #[source] JsonError)
 }
 
+impl PlacementError {
+    /// Returns the error with its JSON pointer prefixed, see `JsonError::at`.
+    pub fn at(self, pointer: &str) -> Self {
+        return match self {
+            PlacementError::InvalidJson(block, e) => PlacementError::InvalidJson(block, e.at(pointer))
+        };
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum ModalityError {
     #[error("Invalid JSON at modality `{0}`: `{1}`")]
     InvalidJson(usize, #[source] JsonError)
 }
 
+impl ModalityError {
+    /// Returns the error with its JSON pointer prefixed, see `JsonError::at`.
+    pub fn at(self, pointer: &str) -> Self {
+        return match self {
+            ModalityError::InvalidJson(index, e) => ModalityError::InvalidJson(index, e.at(pointer))
+        };
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum AssetError {
     #[error("Invalid JSON at asset: `{0}`")]
     InvalidJson(#[source] JsonError)
 }
 
+impl AssetError {
+    /// Returns the error with its JSON pointer prefixed, see `JsonError::at`.
+    pub fn at(self, pointer: &str) -> Self {
+        return match self {
+            AssetError::InvalidJson(e) => AssetError::InvalidJson(e.at(pointer))
+        };
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum BvpFileError {
     #[error("Error in asset: `{0}`")]
@@ -31,11 +126,62 @@ pub enum BvpFileError {
     #[error("Invalid manifest: `{0}`")]
     BrokenManifest(String),
     #[error("Block error: `{0}`")]
-    BlockError(BlockError),
+    BlockError(#[source] BlockError),
     #[error("Modality error: `{0}`")]
-    ModalityError(ModalityError),
+    ModalityError(#[source] ModalityError),
     #[error("Format error: `{0}`")]
-    FormatError(FormatError)
+    FormatError(#[source] FormatError),
+    #[error("Archive error: `{0}`")]
+    ArchiveError(#[source] ArchiveError),
+    #[error("Missing manifest file")]
+    MissingManifest,
+    #[error("Asset requires extension `{0}`, which is not supported")]
+    UnsupportedExtension(String),
+    #[error("Version error: `{0}`")]
+    VersionError(#[source] VersionError),
+    #[error("Cannot create manifest JSON: `{0}`")]
+    CannotSerialize(String),
+    #[error("Manifest does not follow the schema: {0}")]
+    Schema(String),
+    #[error("`{0}` refers to block `{1}`, but the asset has `{2}` blocks")]
+    NoSuchBlock(String, usize, usize),
+    #[error("`{0}` refers to format `{1}`, but the asset has `{2}` formats")]
+    NoSuchFormat(String, usize, usize)
+}
+
+impl BvpFileError {
+    /// Returns the JSON pointer of the manifest value the error is in, if it is known,
+    /// such as `/blocks/42/placements/3/position`.
+    pub fn pointer(&self) -> Option<&str> {
+        return match self {
+            BvpFileError::InvalidJson(e)
+            | BvpFileError::AssetError(AssetError::InvalidJson(e))
+            | BvpFileError::ModalityError(ModalityError::InvalidJson(_, e))
+            | BvpFileError::FormatError(FormatError::InvalidJson(e))
+            | BvpFileError::BlockError(BlockError::InvalidJson(_, e))
+            | BvpFileError::BlockError(BlockError::InvalidPlacement(_, PlacementError::InvalidJson(_, e))) => e.pointer(),
+            BvpFileError::FormatError(FormatError::MissingField(pointer))
+            | BvpFileError::NoSuchBlock(pointer, _, _)
+            | BvpFileError::NoSuchFormat(pointer, _, _) => Some(pointer),
+            _ => None
+        };
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum FileError {
+    #[error("Cannot create folder `{0}`: `{1}`")]
+    CannotCreateFolder(String, #[source] io::Error),
+    #[error("Cannot write file `{0}`: `{1}`")]
+    CannotWrite(String, #[source] io::Error)
+}
+
+#[derive(Error, Debug)]
+pub enum VersionError {
+    #[error("Invalid specification version `{0}`")]
+    Invalid(String),
+    #[error("Specification version `{0}` is not supported")]
+    Unsupported(String)
 }
 
 
@@ -56,33 +202,124 @@ pub enum BlockError {
     #[error("Block `{0}` cannot contain whole microblocks (`{1}` not divisible by `{2}`)")]
     BlockInvalidSize(usize, Vector3<u32>, Vector3<u32>),
     #[error("Invalid compression scheme in block `{0}`: `{1}`")]
-    InvalidCompression(usize, CompressionError),
+    InvalidCompression(usize, #[source] CompressionError),
+    #[error("Invalid checksum in block `{0}`: `{1}`")]
+    InvalidChecksum(usize, #[source] ChecksumError),
     #[error("Invalid JSON at block `{0}`: `{1}`")]
     InvalidJson(usize, #[source] JsonError),
+    #[error("Block `{0}`: position or size overflows")]
+    Overflow(usize),
+    #[error("Block `{0}` has `{1}` bytes of data, but its dimensions and format need `{2}`")]
+    DataSizeMismatch(usize, usize, usize),
     #[error("Invalid placement at block `{0}`: `{1}`")]
     InvalidPlacement(usize, #[source] PlacementError)
 }
 
+impl BlockError {
+    /// Returns the error with its JSON pointer prefixed, if it has one, see `JsonError::at`.
+    pub fn at(self, pointer: &str) -> Self {
+        return match self {
+            BlockError::InvalidJson(index, e) => BlockError::InvalidJson(index, e.at(pointer)),
+            BlockError::InvalidPlacement(index, e) => BlockError::InvalidPlacement(index, e.at(pointer)),
+            e => e
+        };
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ReaderError {
+    #[error("Modality `{0}` does not exist")]
+    NoSuchModality(usize),
+    #[error("Block `{0}` does not exist")]
+    NoSuchBlock(usize),
+    #[error("Format `{0}` does not exist")]
+    NoSuchFormat(usize),
+    #[error("No format found in the blocks under block `{0}`")]
+    NoFormat(usize),
+    #[error("Modality `{0}`: `{1}` of `{2}` microblocks are not covered by any block")]
+    Uncovered(usize, usize, usize),
+    #[error("Block `{0}`: `{1}` of `{2}` microblocks of the region are not covered by any block")]
+    RegionUncovered(usize, usize, usize),
+    #[error("Region from `{0}` to `{1}` is empty or outside of dimensions `{2}`")]
+    InvalidRegion(Vector3<u32>, Vector3<u32>, Vector3<u32>),
+    #[error("Region from `{0}` to `{1}` is not on microblock boundaries (`{2}`)")]
+    MisalignedRegion(Vector3<u32>, Vector3<u32>, Vector3<u32>),
+    #[error("Block `{0}` is nested more than `{1}` levels deep, it is probably placed inside itself")]
+    TooDeep(usize, usize),
+    #[error("Block `{0}` is placed inside itself")]
+    PlacementCycle(usize),
+    #[error("Block `{0}`: data does not match checksum `{1}`")]
+    ChecksumMismatch(usize, String),
+    #[error("Block `{0}`: decoding was aborted")]
+    DecodeAborted(usize),
+    #[error("Format error: `{0}`")]
+    FormatError(#[source] FormatError),
+    #[error("Layout error: `{0}`")]
+    LayoutError(#[source] LayoutError),
+    #[error("Block error: `{0}`")]
+    BlockError(#[source] BlockError)
+}
+
+#[derive(Error, Debug)]
+pub enum WriterError {
+    #[error("Block dimensions `{0}` are zero or not a multiple of the microblock dimensions `{1}`")]
+    InvalidBlockDimensions(Vector3<u32>, Vector3<u32>),
+    #[error("Volume dimensions `{0}` are zero, too large or not a multiple of the microblock dimensions `{1}`")]
+    InvalidDimensions(Vector3<u32>, Vector3<u32>),
+    #[error("Volume holds `{0}` bytes, but dimensions `{1}` need `{2}`")]
+    DataSizeMismatch(usize, Vector3<u32>, usize),
+    #[error("Block error: `{0}`")]
+    BlockError(#[source] BlockError),
+    #[error("BVP file error: `{0}`")]
+    BvpFileError(#[source] BvpFileError),
+    #[error("Archive error: `{0}`")]
+    ArchiveError(#[source] ArchiveError)
+}
+
 #[derive(Error, Debug)]
 pub enum JsonError {
-    #[error("JSON value `{0:?}` is not a number")]
+    #[error("Expected a number, got {}", crate::json_aux::summary(.0))]
     NotANumber(JsonValue),
-    #[error("JSON value `{0:?}` is not an array")]
+    #[error("Expected an array, got {}", crate::json_aux::summary(.0))]
     NotAnArray(JsonValue),
-    #[error("JSON value `{0:?}` is not a string")]
+    #[error("Expected a string, got {}", crate::json_aux::summary(.0))]
     NotAString(JsonValue),
-    #[error("JSON value `{0:?}` is not a 3D vector")]
+    #[error("Expected a 3D vector, got {}", crate::json_aux::summary(.0))]
     NotAVector3(JsonValue),
-    #[error("JSON value `{0:?}` is not an object")]
+    #[error("Expected an object, got {}", crate::json_aux::summary(.0))]
     NotAnObject(JsonValue),
+    #[error("JSON object has no `{0}`")]
+    MissingKey(String),
+    /// An error in the value at a JSON pointer of the manifest, such as `/blocks/42/placements/3/position`
+    #[error("At `{0}`: {1}")]
+    At(String, Box<JsonError>)
+}
+
+impl JsonError {
+    /// Returns the error at a location, prefixing the location if it is already at one.
+    /// * `pointer` - JSON pointer of the value the error is in, relative to where the error is
+    pub fn at(self, pointer: &str) -> Self {
+        return match self {
+            JsonError::At(inner, e) => JsonError::At(format!("{}{}", pointer, inner), e),
+            e => JsonError::At(pointer.to_string(), Box::new(e))
+        };
+    }
+
+    /// Returns the JSON pointer of the value the error is in, if it is known.
+    pub fn pointer(&self) -> Option<&str> {
+        return match self {
+            JsonError::At(pointer, _) => Some(pointer),
+            _ => None
+        };
+    }
 }
 
 #[derive(Error, Debug)]
 pub enum ArchiveError {
     #[error("SAF error: `{0}`")]
-    SafError(SafError),
+    SafError(#[source] SafError),
     #[error("ZIP error: `{0}`")]
-    ZipError(ZipError),
+    ZipError(#[source] ZipError),
     #[error("The provided archive format is not supported (`{0}`)")]
     NotImplemented(String),
     #[error("Archive file or folder does not exist (`{0}`)")]
@@ -92,7 +329,29 @@ pub enum ArchiveError {
     #[error("Not a valid file: `{0}`")]
     NotValidFile(String),
     #[error("Cannot write file: `{0}`")]
-    CannotWrite(String)
+    CannotWrite(String),
+    #[error("Cannot write `{0}`: `{1}`")]
+    WriteFailed(String, #[source] io::Error),
+    #[error("Remote file error: `{0}`")]
+    RemoteError(#[source] RemoteError)
+}
+
+#[derive(Error, Debug)]
+pub enum RemoteError {
+    #[error("Invalid URL `{0}`, expected `s3://bucket/key` or `gs://bucket/key`")]
+    InvalidUrl(String),
+    #[error("Cannot run curl, which is needed for remote files: `{0}`")]
+    CannotRunCurl(#[source] io::Error),
+    #[error("Request to `{0}` failed: `{1}`")]
+    RequestFailed(String, String),
+    #[error("The size of `{0}` is not known")]
+    UnknownSize(String),
+    #[error("`{0}` does not support range requests (requested bytes {1})")]
+    RangeNotSupported(String, String),
+    #[error("Cannot access `{0}`: `{1}`")]
+    CannotAccess(String, #[source] io::Error),
+    #[error("Curl option `{0}` contains control characters")]
+    InvalidOption(String)
 }
 
 #[derive(Error, Debug)]
@@ -103,20 +362,48 @@ pub enum SafError {
     BrokenFile,
     #[error("SAF manifest is corrupt: `{0}`")]
     ManifestCorrupt(String),
+    #[error("SAF manifest of {0} bytes does not fit into the 32-bit manifest size")]
+    ManifestTooLarge(usize),
+    #[error("SAF archive is too short for {0} ({2} bytes at offset {1})")]
+    OutOfBounds(String, usize, usize),
+    #[error("SAF manifest entry {0} is missing `{1}`")]
+    MissingField(usize, String),
     #[error("Invalid JSON: `{0}`")]
-    InvalidJson(JsonError)
+    InvalidJson(#[source] JsonError)
 }
 
 #[derive(Error, Debug)]
 pub enum ZipError {
     #[error("ZIP archive is corrupt: `{0}`")]
-    CorruptFile(String)
+    CorruptFile(String),
+    #[error("ZIP archive is too short for {0} ({2} bytes at offset {1})")]
+    OutOfBounds(String, usize, usize),
+    #[error("ZIP archive has no valid {0} at offset {1}")]
+    InvalidSignature(String, usize),
+    #[error("Member `{0}` of the ZIP archive uses compression method {1}, only stored and deflate are supported")]
+    UnsupportedCompression(String, u16),
+    #[error("Unsupported compression of ZIP members (`{0}`), expected `none` or `deflate`")]
+    UnsupportedMemberCompression(String),
+    #[error("Member `{0}` of the ZIP archive cannot be decompressed: `{1}`")]
+    CorruptMember(String, #[source] CompressionError)
 }
 
 #[derive(Error, Debug)]
 pub enum CompressionError {
     #[error("Unsupported compression (`{0}`)")]
-    Unsupported(String)
+    Unsupported(String),
+    #[error("Compressed data is corrupt: `{0}`")]
+    CorruptData(String)
+}
+
+#[derive(Error, Debug)]
+pub enum ChecksumError {
+    #[error("Unsupported checksum algorithm (`{0}`)")]
+    UnsupportedAlgorithm(String),
+    #[error("Invalid checksum (`{0}`)")]
+    InvalidValue(String),
+    #[error("Invalid JSON at checksum: `{0}`")]
+    InvalidJson(#[source] JsonError)
 }
 
 #[derive(Error, Debug)]
@@ -124,7 +411,245 @@ pub enum FormatError {
     #[error("Invalid mono format component type (`{0}`)")]
     MonoInvalidComponentType(String),
     #[error("Invalid JSON for format: `{0}`")]
-    InvalidJson(JsonError),
+    InvalidJson(#[source] JsonError),
     #[error("Unsupported format family: `{0}`")]
-    UnsupportedFormatFamily(String)
-}
\ No newline at end of file
+    UnsupportedFormatFamily(String),
+    /// The JSON pointer of the missing field
+    #[error("Format is missing field `{0}`")]
+    MissingField(String),
+    #[error("Invalid size `{1}` for `{0}` components, it has to be a positive multiple of the count")]
+    InvalidSize(u32, u32),
+    #[error("Multi format has no components")]
+    NoComponents,
+    #[error("Invalid microblock of dimensions `{0}` and size `{1}`")]
+    InvalidMicroblock(Vector3<u32>, u32),
+    #[error("Voxel values of `{0}` formats cannot be read")]
+    NoVoxelValues(String)
+}
+
+impl FormatError {
+    /// Returns the error with its JSON pointer prefixed, if it has one, see `JsonError::at`.
+    pub fn at(self, pointer: &str) -> Self {
+        return match self {
+            FormatError::InvalidJson(e) => FormatError::InvalidJson(e.at(pointer)),
+            FormatError::MissingField(field) => FormatError::MissingField(format!("{}{}", pointer, field)),
+            e => e
+        };
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum TransferFunctionError {
+    #[error("Invalid JSON at transfer function `{0}`: `{1}`")]
+    InvalidJson(usize, #[source] JsonError),
+    #[error("Transfer function `{0}` is missing field `{1}`")]
+    MissingField(usize, String),
+    #[error("Transfer function `{0}` has neither control points nor a lookup table")]
+    Empty(usize),
+    #[error("Transfer function `{0}`: control points have to be 1D or 2D and all of the same dimension")]
+    InvalidPoints(usize),
+    #[error("Transfer function `{0}`: colors have to be 4 components (RGBA) between 0 and 1")]
+    InvalidColor(usize),
+    #[error("Transfer function `{0}`: domain has to give a range for each of its `{1}` dimensions")]
+    InvalidDomain(usize, usize),
+    #[error("Lookup table `{0}` does not exist in the asset")]
+    MissingLutFile(String),
+    #[error("Lookup table `{0}` has `{1}` bytes, but its dimensions need `{2}`")]
+    LutSizeMismatch(String, usize, usize)
+}
+
+#[derive(Error, Debug)]
+pub enum TransformError {
+    #[error("Invalid JSON at transform: `{0}`")]
+    InvalidJson(#[source] JsonError),
+    #[error("Invalid transform matrix: {0}")]
+    InvalidMatrix(String)
+}
+
+#[derive(Error, Debug)]
+pub enum WindowLevelError {
+    #[error("Invalid JSON at window preset `{0}`: `{1}`")]
+    InvalidJson(usize, #[source] JsonError),
+    #[error("Window preset `{0}` is missing `{1}`")]
+    MissingField(usize, String),
+    #[error("Window preset `{0}` has to have a finite center and a positive width")]
+    InvalidWindow(usize)
+}
+
+#[derive(Error, Debug)]
+pub enum TreeError {
+    #[error("Expected `{0}` root blocks, the asset has `{1}` blocks")]
+    RootCount(usize, usize),
+    #[error("Block `{0}` is added more than once")]
+    DuplicateBlock(usize),
+    #[error("A placement refers to block `{0}`, which is not added")]
+    UnknownBlock(usize),
+    #[error("A placement is in root block `{0}`, which does not exist")]
+    UnknownRoot(usize)
+}
+
+#[derive(Error, Debug)]
+pub enum LayoutError {
+    #[error("Invalid JSON at voxel layout: `{0}`")]
+    InvalidJson(#[source] JsonError),
+    #[error("Voxel layout is missing `order`")]
+    MissingOrder,
+    #[error("Unknown voxel layout `{0}`, expected `linear` or `morton`")]
+    UnknownOrder(String)
+}
+
+#[derive(Error, Debug)]
+pub enum OccupancyError {
+    #[error("Invalid JSON at occupancy: `{0}`")]
+    InvalidJson(#[source] JsonError),
+    #[error("Occupancy is missing `{0}`")]
+    MissingKey(String),
+    #[error("Occupancy cell dimensions `{0}` are zero or not a multiple of the microblock dimensions `{1}`")]
+    InvalidCellDimensions(Vector3<u32>, Vector3<u32>),
+    #[error("Occupancy bitmap is not the hexadecimal text of `{0}` bytes")]
+    InvalidBitmap(usize),
+    #[error("Block data of `{0}` bytes is too short for its dimensions")]
+    DataTooShort(usize),
+    #[error("Cannot compute occupancy: {0}")]
+    Layout(String),
+    #[error("Occupancy of `{0}` cells does not fit into memory")]
+    TooManyCells(Vector3<u32>)
+}
+
+#[derive(Error, Debug)]
+pub enum SourceError {
+    #[error("Invalid JSON at source: `{0}`")]
+    InvalidJson(#[source] JsonError),
+    #[error("Source is missing `{0}`")]
+    MissingKey(String)
+}
+
+#[derive(Error, Debug)]
+pub enum SignatureError {
+    #[error("The asset is not signed")]
+    Missing,
+    #[error("Invalid JSON at signature: `{0}`")]
+    InvalidJson(#[source] JsonError),
+    #[error("Unsupported signature algorithm `{0}`, only ed25519 is supported")]
+    UnsupportedAlgorithm(String),
+    #[error("Invalid signature: {0}")]
+    Invalid(String),
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+    #[error("The signature does not match the manifest, it was changed after signing")]
+    Mismatch,
+    #[error("Cannot sign the manifest: {0}")]
+    InvalidManifest(String),
+    #[error("Block `{0}` has data without a SHA-256 checksum, so a signature does not cover it")]
+    UnsignedData(usize)
+}
+
+#[derive(Error, Debug)]
+pub enum DownsampleError {
+    #[error("Downsampling factor has to be at least 2, got `{0}`")]
+    InvalidFactor(u32),
+    #[error("Unknown downsampling filter `{0}` (expected `mean`, `min`, `max` or `nearest`)")]
+    UnknownFilter(String),
+    #[error("Voxels of format `{0}` cannot be downsampled, only mono and multi formats with microblocks of a single voxel can")]
+    UnsupportedFormat(String),
+    #[error("Data has {0} bytes, but a volume of dimensions {1} needs {2}")]
+    DataSizeMismatch(usize, Vector3<u32>, usize),
+    #[error("A volume of dimensions {0} does not fit into memory")]
+    TooLarge(Vector3<u32>)
+}
+
+#[derive(Error, Debug)]
+pub enum DedupError {
+    #[error("Cannot write the deduplication index to `{0}`: `{1}`")]
+    CannotSpill(String, #[source] io::Error),
+    #[error("Cannot read the deduplication index from `{0}`: `{1}`")]
+    CannotRead(String, #[source] io::Error)
+}
+
+#[derive(Error, Debug)]
+pub enum MetricsError {
+    #[error("Cannot serve metrics on `{0}`: `{1}`")]
+    CannotListen(String, #[source] io::Error)
+}
+
+#[derive(Error, Debug)]
+pub enum ConvertError {
+    #[error("{0}")]
+    Failed(String),
+    #[error("Region from {0} to {1} is empty")]
+    EmptyRegion(Vector3<u32>, Vector3<u32>),
+    #[error("Cannot write the volume: {0}")]
+    CannotWrite(#[source] io::Error),
+    #[error("NRRD headers can only be written for `mono` formats")]
+    NrrdFormat,
+    #[error("NRRD has no type for {0} byte `{1}` components")]
+    NrrdType(usize, String),
+    #[error("{0}")]
+    Reader(#[source] ReaderError),
+    #[error("{0}")]
+    Transform(#[source] TransformError)
+}
+
+#[derive(Error, Debug)]
+pub enum DetectError {
+    #[error("Cannot read {0}: {1}")]
+    CannotRead(String, #[source] io::Error),
+    #[error("{0} is a {1} file, not a BVP asset")]
+    NotAnAsset(String, String),
+    #[error("The archive type of {0} cannot be detected, it has to be given")]
+    Undetectable(String)
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigFormatError {
+    #[error("Invalid JSON: {0}")]
+    Json(String),
+    #[error("Invalid TOML on line {0}: {1}")]
+    Toml(usize, String),
+    #[error("Invalid YAML on line {0}: {1}")]
+    Yaml(usize, String)
+}
+
+#[derive(Error, Debug)]
+pub enum ParametersError {
+    #[error("`{0}` must not be empty")]
+    EmptyPath(String),
+    #[error("`{0}` must be at least 1 in every direction, got {1}")]
+    ZeroDimensions(String, Vector3<u32>),
+    #[error("`{0}` {1} must be a multiple of {2}")]
+    NotMultiple(String, Vector3<u32>, Vector3<u32>),
+    #[error("Blocks of {0} do not fit in the volume of {1}")]
+    BlockLargerThanVolume(Vector3<u32>, Vector3<u32>),
+    #[error("The output can only be {0} if it is a SAF or ZIP archive")]
+    UnarchivedOutput(String),
+    #[error("Checkpoints need the output to be a local file")]
+    CheckpointOutput,
+    #[error("The row stride of `{0}` is {1} bytes, shorter than a row of {2} bytes")]
+    RowStride(String, u64, u64),
+    #[error("Tiled inputs cannot have a header or padding, their tiles are raw files")]
+    TiledLayout,
+    #[error("The volume checksum of tiled inputs cannot be computed, they are never read whole")]
+    TiledVolumeChecksum,
+    #[error("The source of tiled inputs cannot be recorded, they are not a single file")]
+    TiledSource,
+    #[error("Invalid `{0}`: {1}")]
+    Rescale(String, String),
+    #[error("Invalid `{0}`: {1}")]
+    Filters(String, String),
+    #[error("Invalid isosurface mask: {0}")]
+    IsosurfaceMask(String),
+    #[error("`occupancy_grid` {0} must be at most {1} cells in every direction")]
+    OccupancyGrid(Vector3<u32>, u32),
+    #[error("Signed assets need `checksum` to be `sha256`, other checksums do not let the signature cover the data")]
+    SigningChecksum
+}
+
+#[derive(Error, Debug)]
+pub enum FilterError {
+    #[error("A filter must be an object with a `type`")]
+    MissingType,
+    #[error("Unknown filter type `{0}`, expected `gaussian`, `median` or `clamp`")]
+    UnknownType(String),
+    #[error("`{0}` of a `{1}` filter must be {2}")]
+    InvalidValue(String, String, String)
+}
@@ -1,21 +1,77 @@
+use std::collections::HashMap;
 use std::hash::Hash;
+use std::sync::Mutex;
 
+use tinyjson::JsonValue;
+
+use crate::errors::JsonError;
+
+/// Extensions of the BVP format this library implements.
 #[derive(Clone, Copy, Debug)]
 pub enum Extension {
-    ExtFormatMono
+    ExtFormatMono,
+    ExtFormatMulti,
+    ExtFormatCompressed,
+    ExtChecksum,
+    ExtTransferFunction,
+    ExtTransform,
+    ExtWindowLevel,
+    ExtSignature,
+    ExtVoxelLayout,
+    ExtOccupancy,
+    ExtSource
 }
 
+/// JSON payloads of extensions attached to a manifest node (the asset, a modality, a format or a block),
+/// by the name of the extension. They are stored in the `extensions` object of the node.
+pub type ExtensionPayloads = HashMap<String, JsonValue>;
+
+/// Names of extensions registered by the application, see `register_extension`.
+static APPLICATION_EXTENSIONS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
 impl Extension {
+    /// All extensions this library implements.
+    pub const ALL: [Extension; 11] = [
+        Extension::ExtFormatMono, Extension::ExtFormatMulti, Extension::ExtFormatCompressed, Extension::ExtChecksum,
+        Extension::ExtTransferFunction, Extension::ExtTransform, Extension::ExtWindowLevel, Extension::ExtSignature,
+        Extension::ExtVoxelLayout, Extension::ExtOccupancy, Extension::ExtSource
+    ];
+
     pub fn to_string(&self) -> String {
         return match self {
-            Extension::ExtFormatMono => "EXT_format_mono".to_string()
+            Extension::ExtFormatMono => "EXT_format_mono".to_string(),
+            Extension::ExtFormatMulti => "EXT_format_multi".to_string(),
+            Extension::ExtFormatCompressed => "EXT_format_compressed".to_string(),
+            Extension::ExtChecksum => "EXT_checksum".to_string(),
+            Extension::ExtTransferFunction => "EXT_transfer_function".to_string(),
+            Extension::ExtTransform => "EXT_transform".to_string(),
+            Extension::ExtWindowLevel => "EXT_window_level".to_string(),
+            Extension::ExtSignature => "EXT_signature".to_string(),
+            Extension::ExtVoxelLayout => "EXT_voxel_layout".to_string(),
+            Extension::ExtOccupancy => "EXT_occupancy".to_string(),
+            Extension::ExtSource => "EXT_source".to_string()
+        }
+    }
+
+    /// Returns the extension with the given name, or None if the library does not implement it.
+    pub fn from_string(s: &str) -> Option<Self> {
+        return Self::ALL.iter().find(|e| e.to_string() == s).copied();
+    }
+
+    /// Returns true if readers have to support the extension to read the asset.
+    /// Checksums, transfer functions, transforms, window presets, signatures, occupancy bitmaps and sources can be ignored, the data is readable without them.
+    pub fn is_required(&self) -> bool {
+        return match self {
+            Extension::ExtFormatMono | Extension::ExtFormatMulti | Extension::ExtFormatCompressed | Extension::ExtVoxelLayout => true,
+            Extension::ExtChecksum | Extension::ExtTransferFunction | Extension::ExtTransform | Extension::ExtWindowLevel
+                | Extension::ExtSignature | Extension::ExtOccupancy | Extension::ExtSource => false
         }
     }
 }
 
 impl PartialEq for Extension {
     fn eq(&self, other: &Self) -> bool {
-        self == other
+        core::mem::discriminant(self) == core::mem::discriminant(other)
     }
 }
 
@@ -25,4 +81,46 @@ impl Hash for Extension {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         core::mem::discriminant(self).hash(state);
     }
-}
\ No newline at end of file
+}
+
+/// Registers an extension implemented by the application, so that assets which require it
+/// can be read. Payloads of the extension are kept in the `extension_payloads` of manifest nodes.
+/// * `name` - name of the extension, for example `EXT_my_annotations`
+pub fn register_extension(name: &str) {
+    let mut extensions = APPLICATION_EXTENSIONS.lock()
+        .expect("Some thread panicked while registering an extension.");
+    if !extensions.iter().any(|e| e == name) {
+        extensions.push(name.to_string());
+    }
+}
+
+/// Returns true if an extension is implemented by the library or registered by the application.
+/// * `name` - name of the extension
+pub fn is_supported(name: &str) -> bool {
+    if Extension::from_string(name).is_some() {
+        return true;
+    }
+    return APPLICATION_EXTENSIONS.lock()
+        .expect("Some thread panicked while registering an extension.")
+        .iter().any(|e| e == name);
+}
+
+/// Reads the `extensions` object of a manifest node. Payloads of all extensions are kept,
+/// whether they are known or not, so they are written back unchanged.
+/// * `o` - the manifest node
+pub fn payloads_from_json(o: &HashMap<String, JsonValue>) -> Result<ExtensionPayloads, JsonError> {
+    return match o.get("extensions") {
+        Some(JsonValue::Object(payloads)) => Ok(payloads.clone()),
+        Some(j) => Err(JsonError::NotAnObject(j.clone()).at("/extensions")),
+        None => Ok(HashMap::new())
+    };
+}
+
+/// Writes payloads to the `extensions` object of a manifest node, if there are any.
+/// * `payloads` - the payloads of the node
+/// * `hm` - the manifest node
+pub fn payloads_to_json(payloads: &ExtensionPayloads, hm: &mut HashMap<String, JsonValue>) {
+    if !payloads.is_empty() {
+        hm.insert("extensions".to_string(), payloads.clone().into());
+    }
+}
@@ -1,34 +1,76 @@
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
 use std::{path::Path, fs};
-use std::sync::Arc;
 
-#[derive(Debug)]
+use crate::bytes::Bytes;
+#[cfg(feature = "fs")]
+use crate::errors::FileError;
+
+#[derive(Clone, Debug)]
 pub struct File {
     pub name: String,
-    pub data: Arc<Vec<u8>>,
+    /// Contents of the file. Files read from an archive share the buffer of the archive.
+    pub data: Bytes,
     pub mime: Option<String>
 }
 
 impl File {
-    pub fn new(name: String, data: Arc<Vec<u8>>, mime: Option<String>) -> Self {
-        Self { name, data, mime }
+    /// * `name` - path of the file in the asset
+    /// * `data` - contents of the file, a `Vec<u8>`, `Arc<Vec<u8>>` or a slice of a shared buffer
+    /// * `mime` - media type of the file, if known
+    pub fn new(name: String, data: impl Into<Bytes>, mime: Option<String>) -> Self {
+        Self { name, data: data.into(), mime }
     }
 
-    pub fn write(&self) -> Result<(), String> {
+    #[cfg(feature = "fs")]
+    pub fn write(&self) -> Result<(), FileError> {
         let path = Path::new(&self.name);
         let prefix = path.parent().unwrap();
         match fs::create_dir_all(prefix) {
             Ok(_) => (),
-            Err(_) => {
-                return Err(format!("Could not create path {:?}", prefix));
+            Err(e) => {
+                return Err(FileError::CannotCreateFolder(prefix.display().to_string(), e));
             },
         };
         match fs::write(&self.name, self.data.as_slice()) {
             Ok(_) => (),
             Err(e) => {
-                return Err(format!("Error writing file {}: {}", self.name, e));
+                return Err(FileError::CannotWrite(self.name.clone(), e));
             },
         };
 
         return Ok(());
     }
+}
+
+impl PartialEq for File {
+    fn eq(&self, other: &Self) -> bool {
+        return self.name == other.name && self.mime == other.mime && same_data(&self.data, &other.data);
+    }
+}
+
+/// Returns true if two buffers hold the same bytes. Buffers shared between blocks
+/// and files are not compared byte by byte.
+/// * `a`, `b` - the buffers
+pub fn same_data(a: &Bytes, b: &Bytes) -> bool {
+    return a == b;
+}
+
+/// Files of an asset by name, so that the data of a block can be found
+/// without going through all files.
+pub struct FileIndex<'a> {
+    files: HashMap<&'a str, &'a File>
+}
+
+impl<'a> FileIndex<'a> {
+    /// * `files` - all files of the asset
+    pub fn new(files: &'a [File]) -> Self {
+        return Self { files: files.iter().map(|file| (file.name.as_str(), file)).collect() };
+    }
+
+    /// Returns the file with the given name, if there is one.
+    /// * `name` - name of the file, as in the manifest
+    pub fn get(&self, name: &str) -> Option<&'a File> {
+        return self.files.get(name).copied();
+    }
 }
\ No newline at end of file
@@ -2,9 +2,19 @@ use std::{collections::HashMap};
 
 use tinyjson::JsonValue;
 
-use crate::{vector3::Vector3, json_aux::{get_string_from_json, get_u32_from_json}, errors::{FormatError, JsonError}, extensions::Extension};
+use crate::{vector3::Vector3, mapping::{FromJson, ObjectReader, ObjectWriter}, errors::FormatError, extensions::{Extension, ExtensionPayloads}};
 
-#[derive(Clone, Debug)]
+/// Reads a field of a format object, or fails with its JSON pointer if it is missing or invalid.
+/// * `o` - the format object
+/// * `key` - name of the field
+fn field<T: FromJson>(o: &ObjectReader, key: &str) -> Result<T, FormatError> {
+    if !o.contains(key) {
+        return Err(FormatError::MissingField(format!("/{}", key)));
+    }
+    return o.required(key).map_err(FormatError::InvalidJson);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PrimitiveType {
     Int,
     Uint,
@@ -30,7 +40,7 @@ impl PrimitiveType {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct MonoFormat {
     count: u32,
     tp: PrimitiveType,
@@ -42,127 +52,387 @@ impl MonoFormat {
         return Self { count, size, tp };
     }
 
-    pub fn from_hashmap(o: &HashMap<String, JsonValue>) -> Result<(FormatFamily, Vector3<u32>, u32, Option<Extension>), FormatError> {
-        let count = match get_u32_from_json(&o["count"]) {
-            Ok(c) => c,
-            Err(e) => return Err(FormatError::InvalidJson(e))
-        };
-        let size = match get_u32_from_json(&o["size"]) {
-            Ok(s) => s,
-            Err(e) => return Err(FormatError::InvalidJson(e))
+    /// Returns the number of components of a voxel.
+    pub fn count(&self) -> u32 {
+        return self.count;
+    }
+
+    /// Returns the size of a voxel in bytes.
+    pub fn size(&self) -> u32 {
+        return self.size;
+    }
+
+    pub fn component_type(&self) -> &PrimitiveType {
+        return &self.tp;
+    }
+
+    /// Returns the size of a single component in bytes.
+    pub fn component_size(&self) -> u32 {
+        return self.size / self.count;
+    }
+
+    /// Interprets little endian bytes of a single component as a number.
+    /// Sizes without a native type (see `EXT_format_mono`) are read as integers of that many bytes.
+    /// * `bytes` - the bytes of the component, `component_size()` long
+    pub fn component_value(&self, bytes: &[u8]) -> f64 {
+        match (&self.tp, bytes.len()) {
+            (PrimitiveType::Float, 4) => return f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            (PrimitiveType::Float, 8) => {
+                let mut b = [0u8; 8];
+                b.copy_from_slice(bytes);
+                return f64::from_le_bytes(b);
+            },
+            _ => ()
         };
-        let tp = match get_string_from_json(&o["type"]) {
-            Ok(t) => t,
-            Err(e) => return Err(FormatError::InvalidJson(e))
+        let mut value: u64 = 0;
+        for (i, byte) in bytes.iter().take(8).enumerate() {
+            value |= (*byte as u64) << (8 * i);
+        }
+        return match &self.tp {
+            PrimitiveType::Int if !bytes.is_empty() && bytes.len() < 8 => {
+                // Sign extend from the highest bit of the component.
+                let shift = 64 - 8 * bytes.len() as u32;
+                (((value << shift) as i64) >> shift) as f64
+            },
+            PrimitiveType::Int => value as i64 as f64,
+            _ => value as f64
         };
-        let prim = PrimitiveType::from_string(&tp)?;
-        let mut ext = None;
+    }
 
-        let component_size = size / count;
-        match &prim {
-            PrimitiveType::Float => {
-                if component_size != 4 && component_size != 8 {
-                    ext = Some(Extension::ExtFormatMono);
-                }
-            },
+    /// Writes a number as little endian bytes of a single component, the inverse of `component_value`.
+    /// Integers are rounded and clamped to the range of the component.
+    /// * `value` - the number
+    /// * `bytes` - the bytes of the component, `component_size()` long
+    pub fn write_component_value(&self, value: f64, bytes: &mut [u8]) {
+        match (&self.tp, bytes.len()) {
+            (PrimitiveType::Float, 4) => return bytes.copy_from_slice(&(value as f32).to_le_bytes()),
+            (PrimitiveType::Float, 8) => return bytes.copy_from_slice(&value.to_le_bytes()),
+            _ => ()
+        };
+        let bits = 8 * bytes.len().min(8) as u32;
+        let integer = match &self.tp {
             PrimitiveType::Int => {
-                if component_size != 1 && component_size != 2 && component_size != 4 {
-                    ext = Some(Extension::ExtFormatMono);
-                }
+                let max = if bits >= 64 { i64::MAX as f64 } else { ((1u64 << (bits - 1)) - 1) as f64 };
+                value.round().clamp(-max - 1.0, max) as i64 as u64
             },
-            PrimitiveType::Uint => {
-                if component_size != 1 && component_size != 2 && component_size != 4 {
-                    ext = Some(Extension::ExtFormatMono);
-                }
+            _ => {
+                let max = if bits >= 64 { u64::MAX as f64 } else { ((1u64 << bits) - 1) as f64 };
+                value.round().clamp(0.0, max) as u64
             }
+        };
+        for (i, byte) in bytes.iter_mut().take(8).enumerate() {
+            *byte = (integer >> (8 * i)) as u8;
+        }
+    }
+
+    /// Returns the values of all components of a voxel.
+    /// * `voxel` - the bytes of the voxel, `size()` long
+    pub fn values(&self, voxel: &[u8]) -> Vec<f64> {
+        let component_size = self.component_size().max(1) as usize;
+        return voxel.chunks(component_size).take(self.count as usize).map(|c| self.component_value(c)).collect();
+    }
+
+    /// Returns the extension needed by the size of the components, if they have no native type.
+    fn extension(&self) -> Option<Extension> {
+        let native = match &self.tp {
+            PrimitiveType::Float => [4, 8].contains(&self.component_size()),
+            PrimitiveType::Int | PrimitiveType::Uint => [1, 2, 4].contains(&self.component_size())
+        };
+        return if native { None } else { Some(Extension::ExtFormatMono) };
+    }
+
+    /// Writes `count`, `size` and `type` to an object.
+    /// * `writer` - a format object, or a component of a multi format
+    fn write(&self, writer: ObjectWriter) -> ObjectWriter {
+        return writer
+            .field("count", &self.count)
+            .field("type", &self.tp.to_string())
+            .field("size", &self.size);
+    }
+
+    /// Reads `count`, `size` and `type` of an object.
+    /// * `o` - a format object, or a component of a multi format
+    fn parse(o: &ObjectReader) -> Result<Self, FormatError> {
+        let count: u32 = field(o, "count")?;
+        let size: u32 = field(o, "size")?;
+        let tp: String = field(o, "type")?;
+        if count == 0 || size == 0 || size % count != 0 {
+            return Err(FormatError::InvalidSize(count, size));
         }
+        let prim = PrimitiveType::from_string(&tp)?;
+        return Ok(MonoFormat::new(count, size, prim));
+    }
 
-        let mono = MonoFormat::new(count, size, prim);
-        let mono_family = FormatFamily::Mono(mono);
+    pub fn from_hashmap(o: &HashMap<String, JsonValue>) -> Result<(FormatFamily, Vector3<u32>, u32, Vec<Extension>), FormatError> {
+        let mono = Self::parse(&ObjectReader::from_map(o))?;
+        let extensions = mono.extension().into_iter().collect();
         let microblock_dimensions = Vector3::from_xyz(1, 1, 1);
-        let microblock_size = count * size;
+        // `size` is the size of a whole voxel, which is the microblock of mono formats.
+        let microblock_size = mono.size;
+
+        return Ok((FormatFamily::Mono(mono), microblock_dimensions, microblock_size, extensions));
+    }
+}
+
+/// Voxels made of several mono components with their own types, stored one after
+/// another in every voxel, for example a `u8` RGB color followed by a `u16` label (`EXT_format_multi`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultiFormat {
+    components: Vec<MonoFormat>
+}
+
+impl MultiFormat {
+    pub fn new(components: Vec<MonoFormat>) -> Self {
+        return Self { components };
+    }
+
+    pub fn components(&self) -> &[MonoFormat] {
+        return &self.components;
+    }
+
+    /// Returns the size of a voxel in bytes.
+    pub fn size(&self) -> u32 {
+        return self.components.iter().map(|c| c.size).sum();
+    }
+
+    /// Returns the values of all components of a voxel, in the order of the components.
+    /// * `voxel` - the bytes of the voxel, `size()` long
+    pub fn values(&self, voxel: &[u8]) -> Vec<f64> {
+        let mut values = Vec::new();
+        let mut offset = 0;
+        for component in &self.components {
+            let end = (offset + component.size as usize).min(voxel.len());
+            values.extend(component.values(&voxel[offset..end]));
+            offset = end;
+        }
+        return values;
+    }
 
-        return Ok((mono_family, microblock_dimensions, microblock_size, ext));
+    pub fn from_hashmap(o: &HashMap<String, JsonValue>) -> Result<(FormatFamily, Vector3<u32>, u32, Vec<Extension>), FormatError> {
+        let array: Vec<JsonValue> = field(&ObjectReader::from_map(o), "components")?;
+        if array.is_empty() {
+            return Err(FormatError::NoComponents);
+        }
+        let mut components = Vec::new();
+        let mut extensions = vec![Extension::ExtFormatMulti];
+        for (i, j) in array.iter().enumerate() {
+            let pointer = format!("/components/{}", i);
+            let component = match ObjectReader::new(j) {
+                Ok(c) => MonoFormat::parse(&c).map_err(|e| e.at(&pointer))?,
+                Err(e) => return Err(FormatError::InvalidJson(e.at(&pointer)))
+            };
+            if let Some(extension) = component.extension() {
+                if !extensions.contains(&extension) {
+                    extensions.push(extension);
+                }
+            }
+            components.push(component);
+        }
+
+        let multi = MultiFormat::new(components);
+        let microblock_size = multi.size();
+        return Ok((FormatFamily::Multi(multi), Vector3::from_xyz(1, 1, 1), microblock_size, extensions));
     }
 }
 
-#[derive(Clone, Debug)]
+/// Block-compressed formats, such as GPU texture compression, where every microblock
+/// is an opaque block of `microblockSize` bytes that encodes all of its voxels (`EXT_format_compressed`).
+/// The bytes can be copied between blocks, but voxel values cannot be read without a decoder for the scheme.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompressedFormat {
+    scheme: String
+}
+
+impl CompressedFormat {
+    pub fn new(scheme: &str) -> Self {
+        return Self { scheme: scheme.to_string() };
+    }
+
+    /// Returns the name of the compression scheme, for example `bc4`.
+    pub fn scheme(&self) -> &str {
+        return &self.scheme;
+    }
+
+    pub fn from_hashmap(o: &HashMap<String, JsonValue>) -> Result<(FormatFamily, Vector3<u32>, u32, Vec<Extension>), FormatError> {
+        let o = ObjectReader::from_map(o);
+        let scheme: String = field(&o, "scheme")?;
+        let microblock_dimensions: Vector3<u32> = field(&o, "microblockDimensions")?;
+        let microblock_size: u32 = field(&o, "microblockSize")?;
+        if microblock_dimensions.checked_product().map_or(true, |v| v == 0) || microblock_size == 0 {
+            return Err(FormatError::InvalidMicroblock(microblock_dimensions, microblock_size));
+        }
+
+        let compressed = CompressedFormat::new(&scheme);
+        return Ok((FormatFamily::Compressed(compressed), microblock_dimensions, microblock_size, vec![Extension::ExtFormatCompressed]));
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum FormatFamily {
-    Mono(MonoFormat)
+    Mono(MonoFormat),
+    Multi(MultiFormat),
+    Compressed(CompressedFormat)
 }
 
 impl FormatFamily {
-    pub fn to_json(&self, hm: &mut HashMap<String, JsonValue>) {
-        match self {
-            FormatFamily::Mono(m) => {
-                hm.insert("family".to_string(), "mono".to_string().into());
-                hm.insert("count".to_string(), (m.count as f64).into());
-                hm.insert("type".to_string(), m.tp.to_string().into());
-                hm.insert("size".to_string(), (m.size as f64).into());
-            }
+    pub fn name(&self) -> &str {
+        return match self {
+            FormatFamily::Mono(_) => "mono",
+            FormatFamily::Multi(_) => "multi",
+            FormatFamily::Compressed(_) => "compressed"
         }
     }
 
-    pub fn from_hashmap(o: &HashMap<String, JsonValue>) -> Result<(Self, Vector3<u32>, u32, Option<Extension>), FormatError> {
-        let family = match get_string_from_json(&o["family"]) {
-            Ok(f) => f,
-            Err(e) => return Err(FormatError::InvalidJson(e))
+    /// Writes `family` and the fields of the family to a format object.
+    /// * `writer` - the format object
+    pub fn write(&self, writer: ObjectWriter) -> ObjectWriter {
+        let writer = writer.field("family", self.name());
+        return match self {
+            FormatFamily::Mono(m) => m.write(writer),
+            FormatFamily::Multi(m) => {
+                let components: Vec<JsonValue> = m.components.iter().map(|c| c.write(ObjectWriter::new()).finish()).collect();
+                writer.field("components", &components)
+            },
+            FormatFamily::Compressed(c) => writer.field("scheme", &c.scheme)
         };
+    }
+
+    pub fn from_hashmap(o: &HashMap<String, JsonValue>) -> Result<(Self, Vector3<u32>, u32, Vec<Extension>), FormatError> {
+        let family: String = field(&ObjectReader::from_map(o), "family")?;
 
         return match family.as_str() {
-            "mono" => {
-                MonoFormat::from_hashmap(o)
-            },
+            "mono" => MonoFormat::from_hashmap(o),
+            "multi" => MultiFormat::from_hashmap(o),
+            "compressed" => CompressedFormat::from_hashmap(o),
             _ => return Err(FormatError::UnsupportedFormatFamily(family))
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Format {
     pub microblock_dimensions: Vector3<u32>,
     pub microblock_size: u32,
     family: FormatFamily,
-    pub extension: Option<Extension>
+    /// Extensions readers need to support the format.
+    pub extensions: Vec<Extension>,
+    pub extension_payloads: ExtensionPayloads
 }
 
 impl Format {
-    pub fn new(microblock_dimensions: Vector3<u32>, microblock_size: u32, family: FormatFamily, ext: Option<Extension>) -> Self {
-        return Self { microblock_dimensions, microblock_size, family, extension: ext };
+    pub fn new(microblock_dimensions: Vector3<u32>, microblock_size: u32, family: FormatFamily, extensions: Vec<Extension>) -> Self {
+        return Self { microblock_dimensions, microblock_size, family, extensions, extension_payloads: HashMap::new() };
+    }
+
+    pub fn family(&self) -> &FormatFamily {
+        return &self.family;
     }
 
     pub fn to_json(&self) -> JsonValue {
-        let mut hm = HashMap::new();
-        hm.insert("microblockSize".to_string(), (self.microblock_size as f64).into());
-        hm.insert("microblockDimensions".to_string(), self.microblock_dimensions.to_json());
-        self.family.to_json(&mut hm);
-        return hm.into();
+        let writer = ObjectWriter::new()
+            .field("microblockSize", &self.microblock_size)
+            .field("microblockDimensions", &self.microblock_dimensions);
+        return self.family.write(writer).payloads(&self.extension_payloads).finish();
     }
 
-    /// Returns the size of an amount of microblocks in bytes.
+    /// Returns the first component of voxels, or None if voxel values of the format cannot be read.
+    pub fn first_component(&self) -> Option<&MonoFormat> {
+        return match &self.family {
+            FormatFamily::Mono(m) => Some(m),
+            FormatFamily::Multi(m) => m.components.first(),
+            FormatFamily::Compressed(_) => None
+        }
+    }
+
+    /// Returns the number of components of a voxel. Block-compressed formats have none that can be read.
+    pub fn component_count(&self) -> u32 {
+        return match &self.family {
+            FormatFamily::Mono(m) => m.count,
+            FormatFamily::Multi(m) => m.components.iter().map(|c| c.count).sum(),
+            FormatFamily::Compressed(_) => 0
+        }
+    }
+
+    /// Returns the values of all components of a voxel, or None if voxel values of the format cannot be read.
+    /// * `voxel` - the bytes of the voxel, `microblock_size` long
+    pub fn voxel_values(&self, voxel: &[u8]) -> Option<Vec<f64>> {
+        return match &self.family {
+            FormatFamily::Mono(m) => Some(m.values(voxel)),
+            FormatFamily::Multi(m) => Some(m.values(voxel)),
+            FormatFamily::Compressed(_) => None
+        }
+    }
+
+    /// Returns the value of the first component of every voxel in decoded data.
+    /// * `data` - decoded data in this format
+    pub fn first_components(&self, data: &[u8]) -> Result<Vec<f64>, FormatError> {
+        let component = match self.first_component() {
+            Some(c) => c,
+            None => return Err(FormatError::NoVoxelValues(self.family.name().to_string()))
+        };
+        let component_size = component.component_size().max(1) as usize;
+        return Ok(data.chunks(self.microblock_size as usize)
+            .map(|voxel| component.component_value(&voxel[..component_size.min(voxel.len())]))
+            .collect());
+    }
+
+    /// Returns the size of an amount of microblocks in bytes, or None if it does not fit into `u64`.
     /// * `microblock_amount` - amount of microblocks
-    pub fn count_microblocks(&self, microblock_amount: u32) -> u32 {
-        return self.microblock_size * microblock_amount;
+    pub fn count_microblocks(&self, microblock_amount: u64) -> Option<u64> {
+        return (self.microblock_size as u64).checked_mul(microblock_amount);
     }
 
-    /// Returns the size of a block in bytes.
+    /// Returns the size of a block in bytes, or None if it does not fit into `u64`. The size is
+    /// computed in `u64`, so blocks beyond 4 GiB are counted on 32-bit targets too.
+    /// Data that is allocated or written is sized with `checked_count_space`.
     /// * `dimensions` - dimensions of the block
-    pub fn count_space(&self, dimensions: Vector3<u32>) -> u32 {
-        let microblock_amount_vec = (dimensions / self.microblock_dimensions).to_u32();
-        let microblock_amount = microblock_amount_vec.multiply_elements();
+    pub fn count_space(&self, dimensions: Vector3<u32>) -> Option<u64> {
+        let microblock_amount = (dimensions / self.microblock_dimensions).multiply_elements()?;
         return self.count_microblocks(microblock_amount);
     }
 
+    /// Same as `count_space`, but in `usize`, so None also when the block does not fit into memory.
+    /// * `dimensions` - dimensions of the block
+    pub fn checked_count_space(&self, dimensions: Vector3<u32>) -> Option<usize> {
+        let microblock_amount = (dimensions / self.microblock_dimensions).cast::<usize>()?;
+        return microblock_amount.checked_product()?.checked_mul(self.microblock_size as usize);
+    }
+
+    /// Reads a format. JSON errors are at pointers relative to the format, see `FormatError::at`.
+    /// * `j` - the format object
     pub fn from_json(j: &JsonValue) -> Result<Self, FormatError> {
-        return match j {
-            JsonValue::Object(o) => {
-                let (family, mb_dim, mb_size, ext) = FormatFamily::from_hashmap(o)?;
-                let format = Self::new(mb_dim, mb_size, family, ext);
-                Ok(format)
-            },
-            _ => {
-                Err(FormatError::InvalidJson(JsonError::NotAnObject(j.clone())))
-            }
-        }
+        let o = ObjectReader::new(j).map_err(FormatError::InvalidJson)?;
+        let (family, mb_dim, mb_size, extensions) = FormatFamily::from_hashmap(o.object())?;
+        let mut format = Self::new(mb_dim, mb_size, family, extensions);
+        format.extension_payloads = o.payloads().map_err(FormatError::InvalidJson)?;
+        return Ok(format);
     }
-}
\ No newline at end of file
+}
+
+/// Parses a format shorthand `<type><bits>[x<count>]`, for example `u16` or `f32x3`,
+/// into the JSON object of the mono format it stands for, as used by `raw2bvp`.
+/// Returns None if it is not a shorthand, or if the size of a voxel does not fit in a `u32`.
+/// * `value` - the shorthand
+pub fn shorthand_to_json(value: &str) -> Option<JsonValue> {
+    let (component, count) = match value.split_once('x') {
+        Some((component, count)) => (component, count.parse::<u32>().ok()?),
+        None => (value, 1)
+    };
+    let tp = component.get(..1)?;
+    if tp != "u" && tp != "i" && tp != "f" {
+        return None;
+    }
+    let bits = component[1..].parse::<u32>().ok()?;
+    if bits == 0 || bits % 8 != 0 || count == 0 {
+        return None;
+    }
+    // Shorthands of voxels larger than a size can describe are invalid.
+    let size = count.checked_mul(bits / 8)?;
+
+    let mut format = HashMap::new();
+    format.insert("family".to_string(), JsonValue::from("mono".to_string()));
+    format.insert("type".to_string(), JsonValue::from(tp.to_string()));
+    format.insert("count".to_string(), JsonValue::from(count as f64));
+    format.insert("size".to_string(), JsonValue::from(size as f64));
+    return Some(format.into());
+}
@@ -0,0 +1,218 @@
+/// PNG file signature.
+const PNG_SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a];
+/// Largest amount of bytes in a single stored (uncompressed) deflate block.
+const MAX_STORED_BLOCK: usize = 65535;
+
+/// Maps voxel values to 8-bit intensities. Values below `low` become black
+/// and values above `high` become white, everything in between is scaled linearly.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Window {
+    pub low: f64,
+    pub high: f64
+}
+
+impl Window {
+    pub fn new(low: f64, high: f64) -> Self {
+        return Self { low, high };
+    }
+
+    /// Parses a window given as two numbers separated by `,`, for example `0,4095`.
+    /// * `value` - the text to parse
+    pub fn from_string(value: &str) -> Option<Self> {
+        let (low, high) = value.split_once(',')?;
+        return match (low.trim().parse::<f64>(), high.trim().parse::<f64>()) {
+            (Ok(low), Ok(high)) => Some(Self::new(low, high)),
+            _ => None
+        };
+    }
+
+    /// Returns the value scaled to the window, between 0 and 1.
+    /// * `value` - the voxel value
+    fn scale(&self, value: f64) -> f64 {
+        if self.high <= self.low {
+            return if value >= self.high { 1.0 } else { 0.0 };
+        }
+        return ((value - self.low) / (self.high - self.low)).clamp(0.0, 1.0);
+    }
+
+    /// Returns the 8-bit intensity of a value.
+    /// * `value` - the voxel value
+    pub fn apply(&self, value: f64) -> u8 {
+        return (self.scale(value) * 255.0).round() as u8;
+    }
+
+    /// Returns the 16-bit intensity of a value.
+    /// * `value` - the voxel value
+    pub fn apply_u16(&self, value: f64) -> u16 {
+        return (self.scale(value) * 65535.0).round() as u16;
+    }
+}
+
+/// Computes the Adler-32 checksum used by zlib streams.
+/// * `data` - the uncompressed data
+fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    // The sums cannot overflow a u32 in 5552 bytes (see zlib).
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    return (b << 16) | a;
+}
+
+/// Wraps data in a zlib stream made of stored deflate blocks. The data is not compressed,
+/// which keeps the encoder simple and is fast enough for previews.
+/// * `data` - the data to wrap
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let block_count = data.len().div_ceil(MAX_STORED_BLOCK).max(1);
+    let mut stream = Vec::with_capacity(data.len() + 5 * block_count + 6);
+    // Deflate with a 32K window and no preset dictionary, header check bits included.
+    stream.extend_from_slice(&[0x78, 0x01]);
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        stream.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let length = chunk.len() as u16;
+        stream.push(if last { 0x01 } else { 0x00 });
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&(!length).to_le_bytes());
+        stream.extend_from_slice(chunk);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    return stream;
+}
+
+/// Appends a PNG chunk with its length and CRC.
+/// * `png` - the PNG file being written
+/// * `chunk_type` - four letter type of the chunk
+/// * `data` - contents of the chunk
+fn append_chunk(png: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(chunk_type);
+    hasher.update(data);
+    png.extend_from_slice(chunk_type);
+    png.extend_from_slice(data);
+    png.extend_from_slice(&hasher.finalize().to_be_bytes());
+}
+
+/// Encodes a grayscale image as PNG and returns the bytes of the file.
+/// * `width`, `height` - dimensions of the image
+/// * `bit_depth` - bits per pixel, 8 or 16
+/// * `samples` - pixels as big endian bytes, row by row from the top
+fn encode_png(width: u32, height: u32, bit_depth: u8, samples: &[u8]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // Grayscale, deflate, adaptive filtering, no interlace.
+    header.extend_from_slice(&[bit_depth, 0, 0, 0, 0]);
+
+    // Every row starts with its filter type, which is always `None`.
+    let row_size = (width as usize * bit_depth as usize / 8).max(1);
+    let mut scanlines = Vec::with_capacity((row_size + 1) * height as usize);
+    for row in samples.chunks(row_size) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    append_chunk(&mut png, b"IHDR", &header);
+    append_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    append_chunk(&mut png, b"IEND", &[]);
+    return png;
+}
+
+/// Encodes an 8-bit grayscale image as PNG and returns the bytes of the file.
+/// * `width`, `height` - dimensions of the image
+/// * `pixels` - intensities, row by row from the top, `width * height` long
+pub fn encode_png_gray(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    return encode_png(width, height, 8, pixels);
+}
+
+/// Encodes a 16-bit grayscale image as PNG and returns the bytes of the file.
+/// * `width`, `height` - dimensions of the image
+/// * `pixels` - intensities, row by row from the top, `width * height` long
+pub fn encode_png_gray16(width: u32, height: u32, pixels: &[u16]) -> Vec<u8> {
+    let samples: Vec<u8> = pixels.iter().flat_map(|p| p.to_be_bytes()).collect();
+    return encode_png(width, height, 16, &samples);
+}
+
+/// Appends a TIFF directory entry with a single value.
+/// * `tiff` - the TIFF file being written
+/// * `tag` - the field tag
+/// * `field_type` - 3 for SHORT, 4 for LONG and 5 for RATIONAL
+/// * `value` - the value, or the offset of the value for rationals
+fn append_tiff_entry(tiff: &mut Vec<u8>, tag: u16, field_type: u16, value: u32) {
+    tiff.extend_from_slice(&tag.to_le_bytes());
+    tiff.extend_from_slice(&field_type.to_le_bytes());
+    tiff.extend_from_slice(&1u32.to_le_bytes());
+    if field_type == 3 {
+        // Values shorter than 4 bytes are left aligned.
+        tiff.extend_from_slice(&(value as u16).to_le_bytes());
+        tiff.extend_from_slice(&[0, 0]);
+    } else {
+        tiff.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Encodes an uncompressed baseline grayscale TIFF, with the image in a single strip.
+/// * `width`, `height` - dimensions of the image
+/// * `bit_depth` - bits per pixel, 8 or 16
+/// * `samples` - pixels as little endian bytes, row by row from the top
+fn encode_tiff(width: u32, height: u32, bit_depth: u16, samples: &[u8]) -> Vec<u8> {
+    const ENTRY_COUNT: u32 = 12;
+    let directory_size = 2 + 12 * ENTRY_COUNT + 4;
+    let resolution_offset = 8 + directory_size;
+    let data_offset = resolution_offset + 16;
+
+    let mut tiff = Vec::with_capacity(data_offset as usize + samples.len());
+    tiff.extend_from_slice(b"II");
+    tiff.extend_from_slice(&42u16.to_le_bytes());
+    tiff.extend_from_slice(&8u32.to_le_bytes());
+
+    // Entries have to be sorted by their tag.
+    tiff.extend_from_slice(&(ENTRY_COUNT as u16).to_le_bytes());
+    append_tiff_entry(&mut tiff, 256, 4, width);
+    append_tiff_entry(&mut tiff, 257, 4, height);
+    append_tiff_entry(&mut tiff, 258, 3, bit_depth as u32);
+    append_tiff_entry(&mut tiff, 259, 3, 1);
+    append_tiff_entry(&mut tiff, 262, 3, 1);
+    append_tiff_entry(&mut tiff, 273, 4, data_offset);
+    append_tiff_entry(&mut tiff, 277, 3, 1);
+    append_tiff_entry(&mut tiff, 278, 4, height);
+    append_tiff_entry(&mut tiff, 279, 4, samples.len() as u32);
+    append_tiff_entry(&mut tiff, 282, 5, resolution_offset);
+    append_tiff_entry(&mut tiff, 283, 5, resolution_offset + 8);
+    append_tiff_entry(&mut tiff, 296, 3, 1);
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+
+    // X and Y resolution, both 1/1, since the image has no physical size.
+    for _ in 0..2 {
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+    }
+    tiff.extend_from_slice(samples);
+    return tiff;
+}
+
+/// Encodes an 8-bit grayscale image as TIFF and returns the bytes of the file.
+/// * `width`, `height` - dimensions of the image
+/// * `pixels` - intensities, row by row from the top, `width * height` long
+pub fn encode_tiff_gray(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    return encode_tiff(width, height, 8, pixels);
+}
+
+/// Encodes a 16-bit grayscale image as TIFF and returns the bytes of the file.
+/// * `width`, `height` - dimensions of the image
+/// * `pixels` - intensities, row by row from the top, `width * height` long
+pub fn encode_tiff_gray16(width: u32, height: u32, pixels: &[u16]) -> Vec<u8> {
+    let samples: Vec<u8> = pixels.iter().flat_map(|p| p.to_le_bytes()).collect();
+    return encode_tiff(width, height, 16, &samples);
+}
@@ -1,4 +1,6 @@
-use tinyjson::JsonValue;
+use std::{collections::HashMap, fmt};
+
+use tinyjson::{JsonGenerateError, JsonValue};
 
 use crate::{vector3::Vector3, errors::JsonError};
 
@@ -9,6 +11,13 @@ pub fn get_u32_from_json(j: &JsonValue) -> Result<u32, JsonError> {
     };
 }
 
+pub fn get_u64_from_json(j: &JsonValue) -> Result<u64, JsonError> {
+    match j {
+        JsonValue::Number(n) => return Ok(*n as u64),
+        _ => return Err(JsonError::NotANumber(j.clone()))
+    };
+}
+
 pub fn get_f32_from_json(j: &JsonValue) -> Result<f32, JsonError> {
     match j {
         JsonValue::Number(n) => return Ok(*n as f32),
@@ -16,6 +25,13 @@ pub fn get_f32_from_json(j: &JsonValue) -> Result<f32, JsonError> {
     };
 }
 
+pub fn get_f64_from_json(j: &JsonValue) -> Result<f64, JsonError> {
+    match j {
+        JsonValue::Number(n) => return Ok(*n),
+        _ => return Err(JsonError::NotANumber(j.clone()))
+    };
+}
+
 pub fn get_u32_dimensions_from_json(j: &JsonValue) -> Result<Vector3<u32>, JsonError> {
     match j {
         JsonValue::Array(a) => {
@@ -46,14 +62,248 @@ pub fn get_string_vec_from_json(j: &JsonValue) -> Result<Vec<String>, JsonError>
     let mut vec = Vec::new();
     match j {
         JsonValue::Array(a) => {
-            for el in a {
+            for (i, el) in a.iter().enumerate() {
                 match el {
                     JsonValue::String(s) => vec.push(s.clone()),
-                    _ => return Err(JsonError::NotAString(el.clone()))
+                    _ => return Err(JsonError::NotAString(el.clone()).at(&format!("/{}", i)))
                 }
             }
         },
         _ => return Err(JsonError::NotAnArray(j.clone()))
     }
     return Ok(vec);
-}
\ No newline at end of file
+}
+
+/// Returns a field of an object, or an error if it is missing.
+/// * `o` - the object
+/// * `key` - name of the field
+pub fn get_field<'a>(o: &'a HashMap<String, JsonValue>, key: &str) -> Result<&'a JsonValue, JsonError> {
+    return o.get(key).ok_or_else(|| JsonError::MissingKey(key.to_string()));
+}
+
+/// Parses a field of an object, failing with the location of the field if it is missing or invalid.
+/// * `o` - the object
+/// * `key` - name of the field
+/// * `parse` - parses the value of the field
+pub fn parse_field<T>(o: &HashMap<String, JsonValue>, key: &str, parse: impl FnOnce(&JsonValue) -> Result<T, JsonError>) -> Result<T, JsonError> {
+    return get_field(o, key).and_then(parse).map_err(|e| e.at(&format!("/{}", key)));
+}
+
+/// Describes a JSON value for error messages. Short values are written out, longer
+/// arrays and objects only by their length, so that errors in large manifests stay readable.
+/// * `j` - the value
+pub fn summary(j: &JsonValue) -> String {
+    let text = j.stringify().unwrap_or_default();
+    if text.chars().count() <= 40 {
+        return format!("`{}`", text);
+    }
+    return match j {
+        JsonValue::Array(a) => format!("an array of {} values", a.len()),
+        JsonValue::Object(o) => format!("an object with {} fields", o.len()),
+        _ => format!("`{}...`", text.chars().take(40).collect::<String>())
+    };
+}
+
+/// Converts a path of a manifest value as used by `bvp-validate`, e.g. `blocks[3].placements[0]`,
+/// to a JSON pointer, e.g. `/blocks/3/placements/0`.
+/// * `path` - the path
+pub fn to_pointer(path: &str) -> String {
+    if path.is_empty() {
+        return String::new();
+    }
+    return format!("/{}", path.replace('[', ".").replace(']', "").split('.').filter(|s| !s.is_empty()).collect::<Vec<&str>>().join("/"));
+}
+
+/// Returns JSON text in which the keys of objects are sorted, so that equal values
+/// always give the same text, for example to hash them.
+/// * `j` - the value
+pub fn canonical_string(j: &JsonValue) -> String {
+    return try_canonical_string(j).unwrap_or_default();
+}
+
+/// Same as `canonical_string`, but fails on values that have no JSON text, such as
+/// infinite numbers, instead of leaving them out. Manifests are written with it.
+/// * `j` - the value
+pub fn try_canonical_string(j: &JsonValue) -> Result<String, JsonGenerateError> {
+    return match j {
+        JsonValue::Object(o) => {
+            let mut keys: Vec<&String> = o.keys().collect();
+            keys.sort();
+            let mut members = Vec::with_capacity(keys.len());
+            for k in keys {
+                members.push(format!("{}:{}", JsonValue::from(k.to_string()).stringify()?, try_canonical_string(&o[k])?));
+            }
+            Ok(format!("{{{}}}", members.join(",")))
+        },
+        JsonValue::Array(a) => {
+            let items = a.iter().map(try_canonical_string).collect::<Result<Vec<String>, JsonGenerateError>>()?;
+            Ok(format!("[{}]", items.join(",")))
+        },
+        other => other.stringify()
+    };
+}
+
+/// How strictly manifests are read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Objects must follow their schema: unknown fields, missing required fields and values of the wrong type are errors.
+    Strict,
+    /// Unknown fields and values of the wrong type that can still be read are ignored with a warning.
+    /// Missing fields are only errors if they cannot be done without.
+    #[default]
+    Lenient
+}
+
+/// Type of the value of a field in a schema.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldKind {
+    String,
+    /// A non-negative integer, such as a count or an index
+    Integer,
+    Number,
+    /// An array of three non-negative integers
+    Dimensions,
+    /// An array of three numbers
+    Vector3,
+    Array,
+    StringArray,
+    Object,
+    Any
+}
+
+impl FieldKind {
+    /// Returns true if a value has this type.
+    /// * `j` - the value
+    pub fn matches(&self, j: &JsonValue) -> bool {
+        let integer = |j: &JsonValue| matches!(j, JsonValue::Number(n) if n.fract() == 0.0 && *n >= 0.0);
+        return match (self, j) {
+            (FieldKind::String, JsonValue::String(_)) | (FieldKind::Number, JsonValue::Number(_))
+                | (FieldKind::Array, JsonValue::Array(_)) | (FieldKind::Object, JsonValue::Object(_)) | (FieldKind::Any, _) => true,
+            (FieldKind::Integer, j) => integer(j),
+            (FieldKind::Dimensions, JsonValue::Array(a)) => a.len() == 3 && a.iter().all(integer),
+            (FieldKind::Vector3, JsonValue::Array(a)) => a.len() == 3 && a.iter().all(|c| matches!(c, JsonValue::Number(_))),
+            (FieldKind::StringArray, JsonValue::Array(a)) => a.iter().all(|c| matches!(c, JsonValue::String(_))),
+            _ => false
+        };
+    }
+}
+
+/// Describes the type, for messages.
+impl fmt::Display for FieldKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            FieldKind::String => "a string",
+            FieldKind::Integer => "a non-negative integer",
+            FieldKind::Number => "a number",
+            FieldKind::Dimensions => "an array of three non-negative integers",
+            FieldKind::Vector3 => "an array of three numbers",
+            FieldKind::Array => "an array",
+            FieldKind::StringArray => "an array of strings",
+            FieldKind::Object => "an object",
+            FieldKind::Any => "any value"
+        };
+        return write!(f, "{}", description);
+    }
+}
+
+/// A field of an object in a schema.
+#[derive(Clone, Copy, Debug)]
+pub struct Field {
+    pub name: &'static str,
+    pub kind: FieldKind,
+    pub required: bool
+}
+
+impl Field {
+    pub const fn required(name: &'static str, kind: FieldKind) -> Self {
+        return Self { name, kind, required: true };
+    }
+
+    pub const fn optional(name: &'static str, kind: FieldKind) -> Self {
+        return Self { name, kind, required: false };
+    }
+}
+
+/// A field of an object that does not follow its schema, with the path of the field, e.g. `blocks[3].format`.
+/// Messages give the field by its JSON pointer, e.g. `/blocks/3/format`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SchemaIssue {
+    Missing(String),
+    Unknown(String),
+    /// The value does not have the type of the field, given as a description
+    WrongType(String, String)
+}
+
+impl fmt::Display for SchemaIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            SchemaIssue::Missing(path) => write!(f, "`{}` is required", to_pointer(path)),
+            SchemaIssue::Unknown(path) => write!(f, "`{}` is not a known field", to_pointer(path)),
+            SchemaIssue::WrongType(path, kind) => write!(f, "`{}` must be {}", to_pointer(path), kind)
+        };
+    }
+}
+
+/// Fields an object may have. Every object may also have `extensions`, the payloads of extensions, and `extras`.
+#[derive(Clone, Copy, Debug)]
+pub struct Schema {
+    /// Name of the kind of object, such as `block`
+    pub name: &'static str,
+    pub fields: &'static [Field],
+    /// An array field whose elements are objects with a schema of their own, if any
+    pub elements: Option<(&'static str, &'static Schema)>
+}
+
+impl Schema {
+    /// Returns the fields with the given names that are missing from an object or not in the schema.
+    /// * `path` - path of the object, prefixed to the paths of its fields
+    /// * `keys` - names of the fields of the object
+    pub fn check_keys<'k>(&self, path: &str, keys: impl Iterator<Item = &'k str>) -> Vec<SchemaIssue> {
+        let keys: Vec<&str> = keys.collect();
+        let mut issues: Vec<SchemaIssue> = self.fields.iter()
+            .filter(|f| f.required && !keys.contains(&f.name))
+            .map(|f| SchemaIssue::Missing(field_path(path, f.name)))
+            .collect();
+        let mut unknown: Vec<&str> = keys.into_iter()
+            .filter(|k| !["extensions", "extras"].contains(k) && !self.fields.iter().any(|f| f.name == *k))
+            .collect();
+        unknown.sort();
+        issues.extend(unknown.into_iter().map(|k| SchemaIssue::Unknown(field_path(path, k))));
+        return issues;
+    }
+
+    /// Returns the fields of an object, and of the objects in its `elements`, that are missing, not in the schema
+    /// or of the wrong type. Values that are not objects have no issues, they are left to the parsers to report.
+    /// * `path` - path of the object, prefixed to the paths of its fields
+    /// * `j` - the object
+    pub fn check(&self, path: &str, j: &JsonValue) -> Vec<SchemaIssue> {
+        let o = match j {
+            JsonValue::Object(o) => o,
+            _ => return Vec::new()
+        };
+        let mut issues = self.check_keys(path, o.keys().map(|k| k.as_str()));
+        let extensions = Field::optional("extensions", FieldKind::Object);
+        for field in self.fields.iter().chain([&extensions]) {
+            if o.get(field.name).is_some_and(|value| !field.kind.matches(value)) {
+                issues.push(SchemaIssue::WrongType(field_path(path, field.name), field.kind.to_string()));
+            }
+        }
+        if let Some((key, schema)) = self.elements {
+            for (i, element) in o.get(key).and_then(|a| a.get::<Vec<JsonValue>>()).into_iter().flatten().enumerate() {
+                let element_path = format!("{}[{}]", field_path(path, key), i);
+                match element {
+                    JsonValue::Object(_) => issues.extend(schema.check(&element_path, element)),
+                    _ => issues.push(SchemaIssue::WrongType(element_path, FieldKind::Object.to_string()))
+                }
+            }
+        }
+        return issues;
+    }
+}
+
+/// Returns the path of a field of an object.
+/// * `path` - path of the object, empty for the root
+/// * `name` - name of the field
+fn field_path(path: &str, name: &str) -> String {
+    return if path.is_empty() { name.to_string() } else { format!("{}.{}", path, name) };
+}
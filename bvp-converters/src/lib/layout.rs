@@ -0,0 +1,91 @@
+//! Order of the microblocks inside the blocks of a format, stored in the `EXT_voxel_layout` payload of the format:
+//!
+//! ```json
+//! "extensions": {
+//!     "EXT_voxel_layout": { "order": "morton" }
+//! }
+//! ```
+//!
+//! Without the payload, microblocks are stored in linear order, X fastest and Z slowest. In Morton order
+//! they follow the Z-order curve of their position inside the block, so that voxels which are close in
+//! space are close in the data too. Blocks whose sides are not powers of two skip the positions outside of them.
+//! The extension is required, as readers have to reorder the data before they can use it.
+
+use std::{collections::HashMap, fmt};
+
+use tinyjson::JsonValue;
+
+use crate::{errors::{JsonError, LayoutError}, extensions::Extension, formats::Format, json_aux, vector3::Vector3};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoxelLayout {
+    Linear,
+    Morton
+}
+
+impl VoxelLayout {
+    pub fn from_string(s: &str) -> Result<Self, LayoutError> {
+        return match s {
+            "linear" => Ok(Self::Linear),
+            "morton" => Ok(Self::Morton),
+            _ => Err(LayoutError::UnknownOrder(s.to_string()))
+        };
+    }
+}
+
+impl fmt::Display for VoxelLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Linear => "linear",
+            Self::Morton => "morton"
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+/// Returns the layout of the blocks of a format. Formats without the extension are linear.
+/// * `format` - the format
+pub fn layout(format: &Format) -> Result<VoxelLayout, LayoutError> {
+    let payload = match format.extension_payloads.get(&Extension::ExtVoxelLayout.to_string()) {
+        Some(JsonValue::Object(o)) => o,
+        Some(j) => return Err(LayoutError::InvalidJson(JsonError::NotAnObject(j.clone()))),
+        None => return Ok(VoxelLayout::Linear)
+    };
+    return match payload.get("order") {
+        Some(j) => VoxelLayout::from_string(&json_aux::get_string_from_json(j).map_err(LayoutError::InvalidJson)?),
+        None => Err(LayoutError::MissingOrder)
+    };
+}
+
+/// Sets the layout of the blocks of a format. The linear layout removes the payload.
+/// The data of blocks is not changed, see `Block::to_layout`.
+/// * `format` - the format
+/// * `layout` - the layout
+pub fn set_layout(format: &mut Format, layout: VoxelLayout) {
+    let name = Extension::ExtVoxelLayout.to_string();
+    if layout == VoxelLayout::Linear {
+        format.extension_payloads.remove(&name);
+        return;
+    }
+    let mut payload = HashMap::new();
+    payload.insert("order".to_string(), JsonValue::from(layout.to_string()));
+    format.extension_payloads.insert(name, payload.into());
+}
+
+/// Returns the linear index of every microblock of a block, in the order the layout stores them.
+/// * `layout` - the layout
+/// * `microblocks` - number of microblocks along each axis of the block
+pub fn microblock_order(layout: VoxelLayout, microblocks: Vector3<u32>) -> Vec<usize> {
+    let mut positions = Vec::with_capacity(microblocks.x as usize * microblocks.y as usize * microblocks.z as usize);
+    for z in 0..microblocks.z {
+        for y in 0..microblocks.y {
+            for x in 0..microblocks.x {
+                positions.push(Vector3::from_xyz(x, y, z));
+            }
+        }
+    }
+    if layout == VoxelLayout::Morton {
+        positions.sort_unstable_by_key(|p| p.morton_code());
+    }
+    return positions.into_iter().map(|p| Vector3::linear_index(p, microblocks)).collect();
+}
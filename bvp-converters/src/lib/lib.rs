@@ -1,12 +1,51 @@
+pub mod anonymize;
 pub mod archives;
+#[cfg(feature = "async")]
+pub mod async_reader;
+pub mod dedup;
+pub mod detect;
+pub mod digest;
+pub mod downsample;
+pub mod ed25519;
 pub mod block;
+pub mod bytes;
 pub mod bvpfile;
+pub mod checksum;
 pub mod compressions;
+pub mod config_formats;
+#[cfg(feature = "fs")]
+pub mod convert;
 pub mod errors;
 pub mod formats;
+pub mod image;
 pub mod json_aux;
+pub mod layout;
+pub mod log;
+pub mod mapping;
+pub mod manifest;
+pub mod metrics;
+pub mod occupancy;
 pub mod placement;
+#[cfg(feature = "fs")]
+pub mod prometheus;
+pub mod progress;
+pub mod reader;
+#[cfg(feature = "fs")]
+pub mod remote;
+pub mod report;
+pub mod signature;
+pub mod source;
+pub mod texture;
+pub mod transfer_function;
+pub mod transform;
+pub mod tree;
+pub mod validate;
 pub mod vector3;
+pub mod version;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod window_level;
+pub mod writer;
 pub mod file;
 pub mod asset;
 pub mod modality;
@@ -0,0 +1,142 @@
+use std::cell::RefCell;
+use std::fmt::{self, Arguments};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Instant;
+
+/// Importance of a log message. Messages less important than the
+/// maximum level set with `set_max_level` are not printed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5
+}
+
+impl Level {
+    /// Returns the level for a command line verbosity.
+    /// * `verbosity` - number of `-v` flags, or -1 for `--quiet`
+    pub fn from_verbosity(verbosity: i32) -> Self {
+        return match verbosity {
+            i32::MIN..=-1 => Level::Error,
+            0 => Level::Warn,
+            1 => Level::Info,
+            2 => Level::Debug,
+            _ => Level::Trace
+        };
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE"
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+/// Returns how much a command line flag changes the verbosity, or `None`
+/// if the flag is not a verbosity flag. `-v`, `-vv`, `-vvv` and `--verbose` raise it by the number of `v`s,
+/// `-q` and `--quiet` return -1, which silences everything but errors.
+/// * `arg` - a single command line argument
+pub fn verbosity_flag(arg: &str) -> Option<i32> {
+    if arg == "--verbose" {
+        return Some(1);
+    }
+    if arg == "-q" || arg == "--quiet" {
+        return Some(-1);
+    }
+    return match arg.strip_prefix('-') {
+        Some(vs) if !vs.is_empty() && vs.chars().all(|c| c == 'v') => Some(vs.len() as i32),
+        _ => None
+    };
+}
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Warn as u8);
+
+thread_local! {
+    static SPANS: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Sets the most detailed level that is still printed. Defaults to `Level::Warn`.
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns true if messages of the given level are printed.
+pub fn enabled(level: Level) -> bool {
+    return level as u8 <= MAX_LEVEL.load(Ordering::Relaxed);
+}
+
+/// Prints a message to stderr, prefixed with its level and the spans
+/// the current thread is in. Use the `log_*` macros instead of calling this directly.
+pub fn log(level: Level, message: Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let spans = SPANS.with(|s| s.borrow().join(":"));
+    if spans.is_empty() {
+        eprintln!("[{}] {}", level.to_string(), message);
+    } else {
+        eprintln!("[{}] {}: {}", level.to_string(), spans, message);
+    }
+}
+
+/// A named section of work, such as a pipeline stage. While a span is alive,
+/// messages logged from the same thread are prefixed with its name. When it is
+/// dropped, the time spent in it is logged at the debug level.
+pub struct Span {
+    name: &'static str,
+    started: Instant
+}
+
+impl Span {
+    pub fn enter(name: &'static str) -> Self {
+        SPANS.with(|s| s.borrow_mut().push(name));
+        log(Level::Trace, format_args!("started"));
+        return Self { name, started: Instant::now() };
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        log(Level::Debug, format_args!("finished in {:.3} s", self.started.elapsed().as_secs_f64()));
+        SPANS.with(|s| {
+            let mut spans = s.borrow_mut();
+            if let Some(position) = spans.iter().rposition(|n| *n == self.name) {
+                spans.truncate(position);
+            }
+        });
+    }
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Error, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Warn, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Info, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Debug, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_trace {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Trace, format_args!($($arg)*)) };
+}
@@ -0,0 +1,453 @@
+use std::{collections::{HashMap, HashSet}, str::FromStr, sync::Mutex};
+
+use tinyjson::JsonValue;
+
+use crate::{asset::Asset, block::Block, errors::{AssetError, BvpFileError, JsonError, VersionError}, extensions, file::{File, FileIndex}, formats::Format, modality::Modality, version::SpecVersion};
+use crate::json_aux::{Field, FieldKind, ParseMode, Schema, SchemaIssue};
+use crate::log_warn;
+
+/// Fields of the objects of a manifest, see `ParseMode`.
+pub const ROOT_SCHEMA: Schema = Schema { name: "manifest", fields: &[
+    Field::required("asset", FieldKind::Object), Field::required("formats", FieldKind::Array),
+    Field::required("modalities", FieldKind::Array), Field::required("blocks", FieldKind::Array)
+], elements: None };
+pub const ASSET_SCHEMA: Schema = Schema { name: "asset", fields: &[
+    Field::required("version", FieldKind::String), Field::optional("name", FieldKind::String),
+    Field::optional("generator", FieldKind::String), Field::optional("author", FieldKind::String),
+    Field::optional("description", FieldKind::String), Field::optional("copyright", FieldKind::String),
+    Field::optional("acquisitionTime", FieldKind::String), Field::optional("creationTime", FieldKind::String),
+    Field::optional("extensionsUsed", FieldKind::StringArray), Field::optional("extensionsRequired", FieldKind::StringArray)
+], elements: None };
+pub const MODALITY_SCHEMA: Schema = Schema { name: "modality", fields: &[
+    Field::required("block", FieldKind::Integer), Field::optional("volumeSize", FieldKind::Vector3),
+    Field::optional("voxelSize", FieldKind::Vector3), Field::optional("name", FieldKind::String),
+    Field::optional("description", FieldKind::String), Field::optional("semanticType", FieldKind::String)
+], elements: None };
+pub const MONO_FORMAT_SCHEMA: Schema = Schema { name: "mono format", fields: &[
+    Field::required("family", FieldKind::String), Field::required("count", FieldKind::Integer),
+    Field::required("size", FieldKind::Integer), Field::required("type", FieldKind::String),
+    Field::optional("microblockSize", FieldKind::Integer), Field::optional("microblockDimensions", FieldKind::Dimensions)
+], elements: None };
+pub const MULTI_FORMAT_SCHEMA: Schema = Schema { name: "multi format", fields: &[
+    Field::required("family", FieldKind::String), Field::required("components", FieldKind::Array),
+    Field::optional("microblockSize", FieldKind::Integer), Field::optional("microblockDimensions", FieldKind::Dimensions)
+], elements: Some(("components", &COMPONENT_SCHEMA)) };
+pub const COMPONENT_SCHEMA: Schema = Schema { name: "format component", fields: &[
+    Field::required("count", FieldKind::Integer), Field::required("size", FieldKind::Integer), Field::required("type", FieldKind::String)
+], elements: None };
+pub const COMPRESSED_FORMAT_SCHEMA: Schema = Schema { name: "compressed format", fields: &[
+    Field::required("family", FieldKind::String), Field::required("scheme", FieldKind::String),
+    Field::required("microblockSize", FieldKind::Integer), Field::required("microblockDimensions", FieldKind::Dimensions)
+], elements: None };
+pub const BLOCK_SCHEMA: Schema = Schema { name: "block", fields: &[
+    Field::required("dimensions", FieldKind::Dimensions), Field::required("placements", FieldKind::Array),
+    Field::optional("format", FieldKind::Integer), Field::optional("data", FieldKind::String),
+    Field::optional("encoding", FieldKind::String), Field::optional("checksum", FieldKind::String)
+], elements: Some(("placements", &PLACEMENT_SCHEMA)) };
+pub const PLACEMENT_SCHEMA: Schema = Schema { name: "placement", fields: &[
+    Field::required("position", FieldKind::Dimensions), Field::required("block", FieldKind::Integer)
+], elements: None };
+
+/// Returns the schema of a format, which depends on its family, or None if its family is not known.
+/// * `j` - the format
+pub fn format_schema(j: &JsonValue) -> Option<&'static Schema> {
+    return match j.get::<HashMap<String, JsonValue>>()?.get("family")?.get::<String>()?.as_str() {
+        "mono" => Some(&MONO_FORMAT_SCHEMA),
+        "multi" => Some(&MULTI_FORMAT_SCHEMA),
+        "compressed" => Some(&COMPRESSED_FORMAT_SCHEMA),
+        _ => None
+    };
+}
+
+/// Returns the issues of a whole manifest: of its root, asset, modalities, formats and blocks.
+/// * `j` - the manifest
+pub fn manifest_issues(j: &JsonValue) -> Vec<SchemaIssue> {
+    let mut issues = ROOT_SCHEMA.check("", j);
+    let root = match j {
+        JsonValue::Object(o) => o,
+        _ => return issues
+    };
+    if let Some(asset) = root.get("asset") {
+        issues.extend(ASSET_SCHEMA.check("asset", asset));
+    }
+    let elements = |key: &str| root.get(key).and_then(|a| a.get::<Vec<JsonValue>>()).into_iter().flatten().enumerate();
+    for (i, modality) in elements("modalities") {
+        issues.extend(MODALITY_SCHEMA.check(&format!("modalities[{}]", i), modality));
+    }
+    for (i, format) in elements("formats") {
+        if let Some(schema) = format_schema(format) {
+            issues.extend(schema.check(&format!("formats[{}]", i), format));
+        }
+    }
+    for (i, block) in elements("blocks") {
+        issues.extend(BLOCK_SCHEMA.check(&format!("blocks[{}]", i), block));
+    }
+    return issues;
+}
+
+/// Fails on the issues of an object in strict mode. In lenient mode, unknown fields and values of the wrong type
+/// are logged, once for every field of every kind of object, and missing fields are left to the parsers.
+/// * `mode` - how strictly the manifest is read
+/// * `warned` - fields already logged, by the kind of object and the name of the field
+/// * `schema` - schema of the object
+/// * `issues` - issues of the object, see `Schema::check`
+fn report_issues(mode: ParseMode, warned: &Mutex<HashSet<String>>, schema: &Schema, issues: Vec<SchemaIssue>) -> Result<(), BvpFileError> {
+    if issues.is_empty() {
+        return Ok(());
+    }
+    if mode == ParseMode::Strict {
+        let messages: Vec<String> = issues.iter().map(|issue| issue.to_string()).collect();
+        return Err(BvpFileError::Schema(messages.join(", ")));
+    }
+    let mut warned = warned.lock().expect("Some thread panicked while reading the manifest.");
+    for issue in issues {
+        let path = match &issue {
+            SchemaIssue::Missing(_) => continue,
+            SchemaIssue::Unknown(path) | SchemaIssue::WrongType(path, _) => path
+        };
+        let field = path.rsplit('.').next().unwrap_or(path);
+        if warned.insert(format!("{}.{}", schema.name, field)) {
+            log_warn!("{}, further ones in {} objects are not reported", issue.to_string(), schema.name);
+        }
+    }
+    return Ok(());
+}
+
+/// Byte range of a JSON value inside the manifest.
+type Span = (usize, usize);
+
+/// Finds where the values of a manifest start and end, without building them.
+/// Only brackets and strings are followed, the values themselves are checked when they are parsed.
+struct Scanner<'s> {
+    text: &'s [u8],
+    position: usize
+}
+
+impl<'s> Scanner<'s> {
+    fn new(text: &'s str) -> Self {
+        return Self { text: text.as_bytes(), position: 0 };
+    }
+
+    fn error(&self, expected: &str) -> BvpFileError {
+        return match self.text.get(self.position) {
+            Some(c) => BvpFileError::BrokenManifest(format!(
+                "expected {} at byte {}, found `{}`", expected, self.position, *c as char
+            )),
+            None => BvpFileError::BrokenManifest(format!("expected {} at the end of the manifest", expected))
+        };
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.text.get(self.position) {
+            self.position += 1;
+        }
+    }
+
+    /// Moves past the byte if it is next, and returns whether it was.
+    /// * `c` - the byte
+    fn consume(&mut self, c: u8) -> bool {
+        self.skip_whitespace();
+        if self.text.get(self.position) == Some(&c) {
+            self.position += 1;
+            return true;
+        }
+        return false;
+    }
+
+    /// Moves past a string, which starts at the current position.
+    fn skip_string(&mut self) -> Result<(), BvpFileError> {
+        self.position += 1;
+        while let Some(c) = self.text.get(self.position) {
+            self.position += 1;
+            match c {
+                b'\\' => self.position += 1,
+                b'"' => return Ok(()),
+                _ => ()
+            }
+        }
+        return Err(self.error("`\"`"));
+    }
+
+    /// Moves past the next value and returns where it is.
+    fn skip_value(&mut self) -> Result<Span, BvpFileError> {
+        self.skip_whitespace();
+        let start = self.position;
+        match self.text.get(self.position) {
+            Some(b'"') => self.skip_string()?,
+            Some(b'{' | b'[') => {
+                let mut depth = 0;
+                while let Some(c) = self.text.get(self.position) {
+                    match c {
+                        b'"' => {
+                            self.skip_string()?;
+                            continue;
+                        },
+                        b'{' | b'[' => depth += 1,
+                        b'}' | b']' => depth -= 1,
+                        _ => ()
+                    }
+                    self.position += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                if depth != 0 {
+                    return Err(self.error("end of object or array"));
+                }
+            },
+            Some(_) => {
+                while let Some(c) = self.text.get(self.position) {
+                    if matches!(c, b',' | b'}' | b']' | b' ' | b'\t' | b'\n' | b'\r') {
+                        break;
+                    }
+                    self.position += 1;
+                }
+            },
+            None => return Err(self.error("a value"))
+        }
+        if self.position == start {
+            return Err(self.error("a value"));
+        }
+        return Ok((start, self.position));
+    }
+
+    /// Moves past an array and returns where each of its elements is.
+    fn skip_array(&mut self) -> Result<Vec<Span>, BvpFileError> {
+        let mut spans = Vec::new();
+        if !self.consume(b'[') {
+            return Err(self.error("`[`"));
+        }
+        if self.consume(b']') {
+            return Ok(spans);
+        }
+        loop {
+            spans.push(self.skip_value()?);
+            if self.consume(b']') {
+                return Ok(spans);
+            }
+            if !self.consume(b',') {
+                return Err(self.error("`,` or `]`"));
+            }
+        }
+    }
+}
+
+/// Parses the JSON value at a span of the manifest.
+/// * `content` - the manifest
+/// * `span` - where the value is
+fn parse_span(content: &str, span: Span) -> Result<JsonValue, BvpFileError> {
+    return JsonValue::from_str(&content[span.0..span.1]).map_err(|e| BvpFileError::BrokenManifest(e.to_string()));
+}
+
+/// Parses the JSON array at a span of the manifest, or fails if the field is missing or not an array.
+/// * `content` - the manifest
+/// * `key` - name of the field in the root of the manifest
+/// * `span` - where the value is, if the manifest has it
+fn parse_array(content: &str, key: &str, span: Option<&Span>) -> Result<Vec<JsonValue>, BvpFileError> {
+    let json = match span {
+        Some(span) => parse_span(content, *span)?,
+        None => JsonValue::Null
+    };
+    return match json {
+        JsonValue::Array(a) => Ok(a),
+        j => Err(BvpFileError::InvalidJson(JsonError::NotAnArray(j).at(&format!("/{}", key))))
+    };
+}
+
+/// Reads a manifest, parsing the blocks only when they are asked for.
+/// The asset, modalities and formats are small and are parsed right away, while for
+/// the blocks only their positions in the manifest are found. This way, opening
+/// an asset with millions of blocks only takes as long as going through its text once,
+/// and tools that only need some of the blocks do not build the others.
+pub struct ManifestReader<'a> {
+    pub asset: Asset,
+    pub modalities: Vec<Modality>,
+    pub formats: Vec<Format>,
+    content: &'a str,
+    blocks: Vec<Span>,
+    files: FileIndex<'a>,
+    mode: ParseMode,
+    warned: Mutex<HashSet<String>>
+}
+
+impl<'a> ManifestReader<'a> {
+    /// Reads the asset, modalities and formats of a manifest in lenient mode and finds its blocks.
+    /// Fails if the asset cannot be read by this library.
+    /// * `content` - the manifest
+    /// * `files` - all files of the asset, to pull block data from
+    pub fn new(content: &'a str, files: &'a [File]) -> Result<Self, BvpFileError> {
+        return Self::with_mode(content, files, ParseMode::Lenient);
+    }
+
+    /// Reads the asset, modalities and formats of a manifest and finds its blocks.
+    /// The blocks are checked against their schema as they are parsed.
+    /// * `content` - the manifest
+    /// * `files` - all files of the asset, to pull block data from
+    /// * `mode` - how strictly the manifest is read
+    pub fn with_mode(content: &'a str, files: &'a [File], mode: ParseMode) -> Result<Self, BvpFileError> {
+        let warned = Mutex::new(HashSet::new());
+        let mut scanner = Scanner::new(content);
+        let mut fields: HashMap<String, Span> = HashMap::new();
+        let mut blocks = None;
+        if !scanner.consume(b'{') {
+            return Err(BvpFileError::InvalidJson(JsonError::NotAnObject(parse_span(content, scanner.skip_value()?)?)));
+        }
+        if !scanner.consume(b'}') {
+            loop {
+                scanner.skip_whitespace();
+                if scanner.text.get(scanner.position) != Some(&b'"') {
+                    return Err(scanner.error("a key"));
+                }
+                let key = match parse_span(content, scanner.skip_value()?)? {
+                    JsonValue::String(s) => s,
+                    _ => return Err(scanner.error("a key"))
+                };
+                if !scanner.consume(b':') {
+                    return Err(scanner.error("`:`"));
+                }
+                scanner.skip_whitespace();
+                if key == "blocks" && scanner.text.get(scanner.position) == Some(&b'[') {
+                    blocks = Some(scanner.skip_array()?);
+                } else {
+                    fields.insert(key, scanner.skip_value()?);
+                }
+                if scanner.consume(b'}') {
+                    break;
+                }
+                if !scanner.consume(b',') {
+                    return Err(scanner.error("`,` or `}`"));
+                }
+            }
+        }
+        scanner.skip_whitespace();
+        if scanner.position < content.len() {
+            return Err(scanner.error("the end of the manifest"));
+        }
+        let keys = fields.keys().map(|k| k.as_str()).chain(blocks.as_ref().map(|_| "blocks"));
+        report_issues(mode, &warned, &ROOT_SCHEMA, ROOT_SCHEMA.check_keys("", keys))?;
+
+        let asset = match fields.get("asset") {
+            Some(span) => {
+                let json = parse_span(content, *span)?;
+                report_issues(mode, &warned, &ASSET_SCHEMA, ASSET_SCHEMA.check("asset", &json))?;
+                Asset::from_json(&json)
+            },
+            None => Err(AssetError::InvalidJson(JsonError::MissingKey("asset".to_string())))
+        };
+        let asset = match asset {
+            Ok(a) => a,
+            Err(e) => return Err(BvpFileError::AssetError(e.at("/asset")))
+        };
+        let version = match SpecVersion::from_string(&asset.version) {
+            Ok(v) => v,
+            Err(e) => return Err(BvpFileError::VersionError(e))
+        };
+        if !version.is_readable() {
+            return Err(BvpFileError::VersionError(VersionError::Unsupported(asset.version.clone())));
+        }
+        if version.is_newer() {
+            log_warn!(
+                "asset follows specification version {}, newer than {}, some of it may be ignored",
+                version.to_string(), SpecVersion::CURRENT.to_string()
+            );
+        }
+        // Extensions that are only used can be ignored, required ones cannot.
+        for name in &asset.extensions_required {
+            if !extensions::is_supported(name) {
+                return Err(BvpFileError::UnsupportedExtension(name.clone()));
+            }
+        }
+
+        let blocks = match (blocks, fields.get("blocks")) {
+            (Some(spans), _) => spans,
+            (None, span) => {
+                // Not an array, parsed only for the error.
+                parse_array(content, "blocks", span)?;
+                Vec::new()
+            }
+        };
+        let mut modalities = Vec::new();
+        for (i, el) in parse_array(content, "modalities", fields.get("modalities"))?.iter().enumerate() {
+            report_issues(mode, &warned, &MODALITY_SCHEMA, MODALITY_SCHEMA.check(&format!("modalities[{}]", i), el))?;
+            let modality = match Modality::from_json(i, el) {
+                Ok(m) => m,
+                Err(e) => return Err(BvpFileError::ModalityError(e.at(&format!("/modalities/{}", i))))
+            };
+            if modality.block >= blocks.len() {
+                return Err(BvpFileError::NoSuchBlock(format!("/modalities/{}/block", i), modality.block, blocks.len()));
+            }
+            modalities.push(modality);
+        }
+        let mut formats = Vec::new();
+        for (i, el) in parse_array(content, "formats", fields.get("formats"))?.iter().enumerate() {
+            // Formats of unknown families are reported by `Format::from_json`.
+            if let Some(schema) = format_schema(el) {
+                report_issues(mode, &warned, schema, schema.check(&format!("formats[{}]", i), el))?;
+            }
+            let format = match Format::from_json(el) {
+                Ok(f) => f,
+                Err(e) => return Err(BvpFileError::FormatError(e.at(&format!("/formats/{}", i))))
+            };
+            formats.push(format);
+        }
+
+        return Ok(Self { asset, modalities, formats, content, blocks, files: FileIndex::new(files), mode, warned });
+    }
+
+    /// Returns the number of blocks in the manifest.
+    pub fn block_count(&self) -> usize {
+        return self.blocks.len();
+    }
+
+    /// Parses a block of the manifest. Its data is shared with the file it is stored in.
+    /// Fails if its format or any of its placements refer past the end of their arrays.
+    /// * `index` - index of the block
+    pub fn block(&self, index: usize) -> Result<Block, BvpFileError> {
+        let span = match self.blocks.get(index) {
+            Some(s) => *s,
+            None => return Err(BvpFileError::BrokenManifest(format!("there is no block {}", index)))
+        };
+        let json = parse_span(self.content, span)?;
+        report_issues(self.mode, &self.warned, &BLOCK_SCHEMA, BLOCK_SCHEMA.check(&format!("blocks[{}]", index), &json))?;
+        let block = Block::from_json(index, &json, &self.files).map_err(|e| BvpFileError::BlockError(e.at(&format!("/blocks/{}", index))))?;
+        if let Some(format) = block.format.filter(|f| *f >= self.formats.len()) {
+            return Err(BvpFileError::NoSuchFormat(format!("/blocks/{}/format", index), format, self.formats.len()));
+        }
+        for (i, placement) in block.placements.iter().enumerate() {
+            if placement.block >= self.blocks.len() {
+                return Err(BvpFileError::NoSuchBlock(format!("/blocks/{}/placements/{}/block", index, i), placement.block, self.blocks.len()));
+            }
+        }
+        return Ok(block);
+    }
+
+    /// Returns an iterator that parses the blocks one by one, in the order of the manifest.
+    pub fn blocks(&self) -> Blocks<'_, 'a> {
+        return Blocks { reader: self, next: 0 };
+    }
+}
+
+/// Iterator over the blocks of a manifest, see `ManifestReader::blocks`.
+pub struct Blocks<'r, 'a> {
+    reader: &'r ManifestReader<'a>,
+    next: usize
+}
+
+impl<'r, 'a> Iterator for Blocks<'r, 'a> {
+    type Item = Result<Block, BvpFileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.reader.block_count() {
+            return None;
+        }
+        self.next += 1;
+        return Some(self.reader.block(self.next - 1));
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.reader.block_count() - self.next;
+        return (remaining, Some(remaining));
+    }
+}
+
+impl<'r, 'a> ExactSizeIterator for Blocks<'r, 'a> {}
@@ -0,0 +1,292 @@
+//! Mapping between the types of the manifest and JSON values. Values implement `FromJson` and `ToJson`,
+//! objects are read with an `ObjectReader` and written with an `ObjectWriter`, which put the JSON pointer
+//! of the field into errors, so an error deep in a manifest names where it is, e.g. `/extensionsUsed/2`.
+//!
+//! The mapping only knows `JsonValue`, so manifests in other formats need nothing more than a
+//! conversion to and from it, as the TOML and YAML configs have (see `config_formats`).
+
+use std::collections::HashMap;
+
+use num_traits::NumCast;
+use tinyjson::JsonValue;
+
+use crate::{errors::JsonError, extensions::{self, ExtensionPayloads}, json_aux, vector3::Vector3};
+
+/// Values that can be read from JSON.
+pub trait FromJson: Sized {
+    /// Reads the value. Errors are at pointers relative to the value, see `JsonError::at`.
+    /// * `j` - the JSON value
+    fn from_json(j: &JsonValue) -> Result<Self, JsonError>;
+}
+
+/// Values that can be written to JSON.
+pub trait ToJson {
+    fn to_json(&self) -> JsonValue;
+}
+
+impl FromJson for String {
+    fn from_json(j: &JsonValue) -> Result<Self, JsonError> {
+        return json_aux::get_string_from_json(j);
+    }
+}
+
+impl FromJson for u32 {
+    fn from_json(j: &JsonValue) -> Result<Self, JsonError> {
+        return json_aux::get_u32_from_json(j);
+    }
+}
+
+impl FromJson for u64 {
+    fn from_json(j: &JsonValue) -> Result<Self, JsonError> {
+        return json_aux::get_u64_from_json(j);
+    }
+}
+
+impl FromJson for f32 {
+    fn from_json(j: &JsonValue) -> Result<Self, JsonError> {
+        return json_aux::get_f32_from_json(j);
+    }
+}
+
+impl FromJson for f64 {
+    fn from_json(j: &JsonValue) -> Result<Self, JsonError> {
+        return json_aux::get_f64_from_json(j);
+    }
+}
+
+/// Any value, kept as it is, such as `extras`.
+impl FromJson for JsonValue {
+    fn from_json(j: &JsonValue) -> Result<Self, JsonError> {
+        return Ok(j.clone());
+    }
+}
+
+/// Arrays, with errors of the elements at their index.
+impl<T: FromJson> FromJson for Vec<T> {
+    fn from_json(j: &JsonValue) -> Result<Self, JsonError> {
+        let a = match j {
+            JsonValue::Array(a) => a,
+            _ => return Err(JsonError::NotAnArray(j.clone()))
+        };
+        let mut vec = Vec::with_capacity(a.len());
+        for (i, el) in a.iter().enumerate() {
+            vec.push(T::from_json(el).map_err(|e| e.at(&format!("/{}", i)))?);
+        }
+        return Ok(vec);
+    }
+}
+
+impl<T: NumCast + Copy> FromJson for Vector3<T> {
+    fn from_json(j: &JsonValue) -> Result<Self, JsonError> {
+        return Vector3::<T>::from_json(j);
+    }
+}
+
+impl ToJson for String {
+    fn to_json(&self) -> JsonValue {
+        return self.clone().into();
+    }
+}
+
+impl ToJson for str {
+    fn to_json(&self) -> JsonValue {
+        return self.to_string().into();
+    }
+}
+
+impl ToJson for u32 {
+    fn to_json(&self) -> JsonValue {
+        return (*self as f64).into();
+    }
+}
+
+impl ToJson for u64 {
+    fn to_json(&self) -> JsonValue {
+        return (*self as f64).into();
+    }
+}
+
+/// Indices, such as the block of a placement.
+impl ToJson for usize {
+    fn to_json(&self) -> JsonValue {
+        return (*self as f64).into();
+    }
+}
+
+impl ToJson for f32 {
+    fn to_json(&self) -> JsonValue {
+        return (*self as f64).into();
+    }
+}
+
+impl ToJson for f64 {
+    fn to_json(&self) -> JsonValue {
+        return (*self).into();
+    }
+}
+
+impl ToJson for JsonValue {
+    fn to_json(&self) -> JsonValue {
+        return self.clone();
+    }
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> JsonValue {
+        return self.iter().map(|el| el.to_json()).collect::<Vec<JsonValue>>().into();
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> JsonValue {
+        return self.as_slice().to_json();
+    }
+}
+
+impl<T: NumCast + Copy> ToJson for Vector3<T> {
+    fn to_json(&self) -> JsonValue {
+        return Vector3::<T>::to_json(self);
+    }
+}
+
+/// Returns the JSON pointer of a field, relative to its object.
+/// * `key` - name of the field
+fn pointer(key: &str) -> String {
+    return format!("/{}", key);
+}
+
+/// Reads the fields of a JSON object.
+#[derive(Clone, Copy, Debug)]
+pub struct ObjectReader<'a> {
+    object: &'a HashMap<String, JsonValue>
+}
+
+impl<'a> ObjectReader<'a> {
+    /// Fails if the value is not an object.
+    /// * `j` - the object
+    pub fn new(j: &'a JsonValue) -> Result<Self, JsonError> {
+        return match j {
+            JsonValue::Object(object) => Ok(Self { object }),
+            _ => Err(JsonError::NotAnObject(j.clone()))
+        };
+    }
+
+    /// * `object` - the fields of the object
+    pub fn from_map(object: &'a HashMap<String, JsonValue>) -> Self {
+        return Self { object };
+    }
+
+    /// Returns the fields of the object.
+    pub fn object(&self) -> &'a HashMap<String, JsonValue> {
+        return self.object;
+    }
+
+    /// Returns true if the object has a field.
+    /// * `key` - name of the field
+    pub fn contains(&self, key: &str) -> bool {
+        return self.object.contains_key(key);
+    }
+
+    /// Reads a field, failing at the field if it is missing or invalid.
+    /// * `key` - name of the field
+    pub fn required<T: FromJson>(&self, key: &str) -> Result<T, JsonError> {
+        return json_aux::parse_field(self.object, key, T::from_json);
+    }
+
+    /// Reads a field, or returns None if it is missing. Fails at the field if it is invalid.
+    /// * `key` - name of the field
+    pub fn optional<T: FromJson>(&self, key: &str) -> Result<Option<T>, JsonError> {
+        return match self.object.get(key) {
+            Some(j) => T::from_json(j).map(Some).map_err(|e| e.at(&pointer(key))),
+            None => Ok(None)
+        };
+    }
+
+    /// Reads a field, or returns the default value if it is missing, such as an empty array.
+    /// * `key` - name of the field
+    pub fn or_default<T: FromJson + Default>(&self, key: &str) -> Result<T, JsonError> {
+        return self.optional(key).map(Option::unwrap_or_default);
+    }
+
+    /// Returns the elements of an array field, for elements read with errors of their own.
+    /// * `key` - name of the field
+    pub fn elements(&self, key: &str) -> Result<&'a [JsonValue], JsonError> {
+        return match json_aux::get_field(self.object, key).map_err(|e| e.at(&pointer(key)))? {
+            JsonValue::Array(a) => Ok(a),
+            j => Err(JsonError::NotAnArray(j.clone()).at(&pointer(key)))
+        };
+    }
+
+    /// Reads the payloads of extensions in `extensions`.
+    pub fn payloads(&self) -> Result<ExtensionPayloads, JsonError> {
+        return extensions::payloads_from_json(self.object);
+    }
+
+    /// Returns the application specific data in `extras`, as it is.
+    pub fn extras(&self) -> Option<JsonValue> {
+        return self.object.get("extras").cloned();
+    }
+}
+
+/// Writes the fields of a JSON object.
+#[derive(Clone, Debug, Default)]
+pub struct ObjectWriter {
+    object: HashMap<String, JsonValue>
+}
+
+impl ObjectWriter {
+    pub fn new() -> Self {
+        return Self { object: HashMap::new() };
+    }
+
+    /// Writes a field.
+    /// * `key` - name of the field
+    /// * `value` - the value
+    pub fn field<T: ToJson + ?Sized>(mut self, key: &str, value: &T) -> Self {
+        self.object.insert(key.to_string(), value.to_json());
+        return self;
+    }
+
+    /// Writes a field if it has a value.
+    /// * `key` - name of the field
+    /// * `value` - the value, if any
+    pub fn optional<T: ToJson>(self, key: &str, value: &Option<T>) -> Self {
+        return match value {
+            Some(v) => self.field(key, v),
+            None => self
+        };
+    }
+
+    /// Writes an array field if it has elements.
+    /// * `key` - name of the field
+    /// * `values` - the elements
+    pub fn non_empty<T: ToJson>(self, key: &str, values: &[T]) -> Self {
+        return match values.is_empty() {
+            true => self,
+            false => self.field(key, values)
+        };
+    }
+
+    /// Writes the payloads of extensions to `extensions`, if there are any.
+    /// * `payloads` - the payloads
+    pub fn payloads(mut self, payloads: &ExtensionPayloads) -> Self {
+        extensions::payloads_to_json(payloads, &mut self.object);
+        return self;
+    }
+
+    /// Writes application specific data to `extras`, if there is any.
+    /// * `extras` - the data
+    pub fn extras(self, extras: &Option<JsonValue>) -> Self {
+        return self.optional("extras", extras);
+    }
+
+    /// Returns the fields written so far.
+    pub fn into_map(self) -> HashMap<String, JsonValue> {
+        return self.object;
+    }
+
+    /// Returns the object.
+    pub fn finish(self) -> JsonValue {
+        return self.object.into();
+    }
+}
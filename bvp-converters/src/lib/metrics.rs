@@ -0,0 +1,351 @@
+//! Timing and throughput of conversions, for tuning the number of workers and the queue capacity.
+
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::progress::{ProgressCounters, ProgressSink};
+#[cfg(feature = "fs")]
+use crate::prometheus::Exposition;
+
+/// Work done for every block of a conversion, in the order it is done.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PipelineStage {
+    /// Copying the block data out of its root block or tiles.
+    Extract,
+    /// Hashing the block data and looking it up in the deduplication map.
+    Deduplicate,
+    /// Encoding the block data.
+    Compress,
+    /// Writing the block file to the output.
+    Write
+}
+
+impl PipelineStage {
+    /// All stages, in the order blocks go through them.
+    pub const ALL: [PipelineStage; 4] = [Self::Extract, Self::Deduplicate, Self::Compress, Self::Write];
+
+    fn index(&self) -> usize {
+        return match self {
+            Self::Extract => 0,
+            Self::Deduplicate => 1,
+            Self::Compress => 2,
+            Self::Write => 3
+        };
+    }
+}
+
+impl fmt::Display for PipelineStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Extract => "extract",
+            Self::Deduplicate => "deduplicate",
+            Self::Compress => "compress",
+            Self::Write => "write"
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+/// What a conversion processed and where it spent its time.
+#[derive(Clone, Debug, Default)]
+pub struct PipelineMetrics {
+    /// Number of blocks the volumes were split into, duplicates included.
+    pub blocks: usize,
+    pub duplicates: usize,
+    /// Size of all inputs.
+    pub bytes_read: u64,
+    /// Size of the blocks that were stored, before and after encoding.
+    pub decoded_bytes: u64,
+    pub stored_bytes: u64,
+    /// Size of all written files, the manifest included.
+    pub bytes_written: u64,
+    /// Phases of the conversion in the order they finished, with their wall time.
+    pub stages: Vec<(String, Duration)>,
+    /// Time spent in every stage of the pipeline, summed over all workers,
+    /// so it can be longer than the wall time of the conversion.
+    pub work: Vec<(PipelineStage, Duration)>,
+    pub wall_time: Duration
+}
+
+impl PipelineMetrics {
+    pub fn unique_blocks(&self) -> usize {
+        return self.blocks - self.duplicates;
+    }
+
+    /// Returns the size of the stored blocks before encoding divided by their size after.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.stored_bytes == 0 {
+            return 1.0;
+        }
+        return self.decoded_bytes as f64 / self.stored_bytes as f64;
+    }
+
+    /// Returns the fraction of blocks that were duplicates of stored blocks.
+    pub fn dedup_hit_rate(&self) -> f64 {
+        if self.blocks == 0 {
+            return 0.0;
+        }
+        return self.duplicates as f64 / self.blocks as f64;
+    }
+
+    /// Returns the number of input bytes converted per second of wall time.
+    pub fn bytes_per_second(&self) -> f64 {
+        return per_second(self.bytes_read as f64, self.wall_time);
+    }
+
+    /// Returns the number of blocks processed per second of wall time.
+    pub fn blocks_per_second(&self) -> f64 {
+        return per_second(self.blocks as f64, self.wall_time);
+    }
+
+    /// Returns the metrics as lines of text, for printing after a conversion.
+    pub fn to_text(&self) -> String {
+        let mut lines = vec!["Conversion statistics".to_string()];
+        for (name, duration) in &self.stages {
+            lines.push(format!("  {:<12} {:>9.3} s", name, duration.as_secs_f64()));
+        }
+        lines.push(format!("  {:<12} {:>9.3} s", "total", self.wall_time.as_secs_f64()));
+
+        let busy: Duration = self.work.iter().map(|(_, duration)| *duration).sum();
+        lines.push("Worker time, summed over threads".to_string());
+        for (stage, duration) in &self.work {
+            let share = if busy.is_zero() { 0.0 } else { duration.as_secs_f64() / busy.as_secs_f64() * 100.0 };
+            lines.push(format!("  {:<12} {:>9.3} s {:>5.1}%", stage.to_string(), duration.as_secs_f64(), share));
+        }
+
+        lines.push(format!(
+            "Bytes: {} read, {} decoded, {} stored, {} written",
+            self.bytes_read, self.decoded_bytes, self.stored_bytes, self.bytes_written
+        ));
+        lines.push(format!("Compression ratio: {:.3}", self.compression_ratio()));
+        lines.push(format!(
+            "Dedup hit rate: {:.1}% ({} of {} blocks)",
+            self.dedup_hit_rate() * 100.0, self.duplicates, self.blocks
+        ));
+        lines.push(format!(
+            "Throughput: {:.1} MiB/s, {:.0} blocks/s",
+            self.bytes_per_second() / (1024.0 * 1024.0), self.blocks_per_second()
+        ));
+        return lines.join("\n");
+    }
+}
+
+fn per_second(amount: f64, duration: Duration) -> f64 {
+    if duration.is_zero() {
+        return 0.0;
+    }
+    return amount / duration.as_secs_f64();
+}
+
+/// A progress sink that records the events `PipelineMetrics` are made of,
+/// and passes all events on to another sink.
+pub struct MetricsSink<'a> {
+    inner: &'a dyn ProgressSink,
+    counters: ProgressCounters,
+    bytes_read: AtomicU64,
+    decoded_bytes: AtomicU64,
+    stored_bytes: AtomicU64,
+    stages: Mutex<Vec<(String, Duration)>>,
+    /// Nanoseconds spent in every pipeline stage, indexed by `PipelineStage::index`.
+    work: [AtomicU64; 4]
+}
+
+impl<'a> MetricsSink<'a> {
+    /// Starts measuring the wall time of the conversion.
+    /// * `inner` - the sink the events are passed on to
+    pub fn new(inner: &'a dyn ProgressSink) -> Self {
+        return Self {
+            inner,
+            counters: ProgressCounters::new(),
+            bytes_read: AtomicU64::new(0),
+            decoded_bytes: AtomicU64::new(0),
+            stored_bytes: AtomicU64::new(0),
+            stages: Mutex::new(Vec::new()),
+            work: Default::default()
+        };
+    }
+
+    /// Returns the metrics of the conversion so far.
+    pub fn metrics(&self) -> PipelineMetrics {
+        let snapshot = self.counters.snapshot();
+        return PipelineMetrics {
+            blocks: snapshot.blocks_processed,
+            duplicates: snapshot.duplicates,
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            decoded_bytes: self.decoded_bytes.load(Ordering::Relaxed),
+            stored_bytes: self.stored_bytes.load(Ordering::Relaxed),
+            bytes_written: snapshot.bytes_written,
+            stages: self.stages.lock().map(|s| s.clone()).unwrap_or_default(),
+            work: PipelineStage::ALL.iter()
+                .map(|stage| (*stage, Duration::from_nanos(self.work[stage.index()].load(Ordering::Relaxed))))
+                .collect(),
+            wall_time: snapshot.elapsed
+        };
+    }
+}
+
+impl<'a> ProgressSink for MetricsSink<'a> {
+    fn start(&self, total_blocks: usize) {
+        self.counters.start(total_blocks);
+        self.inner.start(total_blocks);
+    }
+
+    fn input_read(&self, name: &str, data: &[u8]) {
+        self.bytes_read.fetch_add(data.len() as u64, Ordering::Relaxed);
+        self.inner.input_read(name, data);
+    }
+
+    fn block_processed(&self, duplicate: bool) {
+        self.counters.block_processed(duplicate);
+        self.inner.block_processed(duplicate);
+    }
+
+    fn block_stored(&self, decoded_bytes: usize, stored_bytes: usize) {
+        self.decoded_bytes.fetch_add(decoded_bytes as u64, Ordering::Relaxed);
+        self.stored_bytes.fetch_add(stored_bytes as u64, Ordering::Relaxed);
+        self.inner.block_stored(decoded_bytes, stored_bytes);
+    }
+
+    fn bytes_written(&self, bytes: usize) {
+        self.counters.bytes_written(bytes);
+        self.inner.bytes_written(bytes);
+    }
+
+    fn stage_finished(&self, name: &str, duration: Duration) {
+        if let Ok(mut stages) = self.stages.lock() {
+            stages.push((name.to_string(), duration));
+        }
+        self.inner.stage_finished(name, duration);
+    }
+
+    fn work_done(&self, stage: PipelineStage, duration: Duration) {
+        self.work[stage.index()].fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+        self.inner.work_done(stage, duration);
+    }
+
+    fn finish(&self) {
+        self.inner.finish();
+    }
+}
+
+/// Totals of a series of conversions, such as those of a watched folder, for monitoring.
+/// Blocks and bytes are counted as they are processed, through a `BatchSink`.
+#[derive(Default)]
+pub struct BatchMetrics {
+    in_flight: AtomicU64,
+    conversions: AtomicU64,
+    failures: AtomicU64,
+    blocks: AtomicU64,
+    duplicates: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    /// Wall time of all finished conversions, in nanoseconds.
+    conversion_nanos: AtomicU64,
+    /// Blocks per second of the last finished conversion, as the bits of an `f64`.
+    last_blocks_per_second: AtomicU64
+}
+
+impl BatchMetrics {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Called before a conversion starts.
+    pub fn conversion_started(&self) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Called after a conversion has finished or failed.
+    /// * `metrics` - the metrics of the conversion, `None` if it failed
+    pub fn conversion_finished(&self, metrics: Option<&PipelineMetrics>) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        match metrics {
+            Some(metrics) => {
+                self.conversions.fetch_add(1, Ordering::Relaxed);
+                self.conversion_nanos.fetch_add(metrics.wall_time.as_nanos() as u64, Ordering::Relaxed);
+                self.last_blocks_per_second.store(metrics.blocks_per_second().to_bits(), Ordering::Relaxed);
+            },
+            None => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Returns the metrics in the Prometheus text format.
+    #[cfg(feature = "fs")]
+    pub fn to_prometheus(&self) -> String {
+        let load = |value: &AtomicU64| value.load(Ordering::Relaxed) as f64;
+        let mut exposition = Exposition::new();
+        exposition.gauge("bvp_conversions_in_flight", "Conversions that are running", load(&self.in_flight));
+        exposition.counter("bvp_conversions_total", "Conversions that finished", load(&self.conversions));
+        exposition.counter("bvp_conversion_errors_total", "Conversions that failed", load(&self.failures));
+        exposition.counter("bvp_conversion_seconds_total", "Wall time of the finished conversions", load(&self.conversion_nanos) / 1e9);
+        exposition.counter("bvp_blocks_processed_total", "Blocks extracted and deduplicated, duplicates included", load(&self.blocks));
+        exposition.counter("bvp_block_duplicates_total", "Blocks that were duplicates of stored blocks", load(&self.duplicates));
+        exposition.counter("bvp_read_bytes_total", "Size of the inputs read", load(&self.bytes_read));
+        exposition.counter("bvp_written_bytes_total", "Size of the files written", load(&self.bytes_written));
+        exposition.gauge(
+            "bvp_blocks_per_second",
+            "Blocks processed per second by the last finished conversion",
+            f64::from_bits(self.last_blocks_per_second.load(Ordering::Relaxed))
+        );
+        return exposition.finish();
+    }
+}
+
+/// A progress sink that adds the blocks and bytes of a conversion to `BatchMetrics`
+/// while it runs, and passes all events on to another sink.
+pub struct BatchSink<'a> {
+    batch: &'a BatchMetrics,
+    inner: &'a dyn ProgressSink
+}
+
+impl<'a> BatchSink<'a> {
+    /// * `batch` - the totals the events are added to
+    /// * `inner` - the sink the events are passed on to
+    pub fn new(batch: &'a BatchMetrics, inner: &'a dyn ProgressSink) -> Self {
+        return Self { batch, inner };
+    }
+}
+
+impl<'a> ProgressSink for BatchSink<'a> {
+    fn start(&self, total_blocks: usize) {
+        self.inner.start(total_blocks);
+    }
+
+    fn input_read(&self, name: &str, data: &[u8]) {
+        self.batch.bytes_read.fetch_add(data.len() as u64, Ordering::Relaxed);
+        self.inner.input_read(name, data);
+    }
+
+    fn block_processed(&self, duplicate: bool) {
+        self.batch.blocks.fetch_add(1, Ordering::Relaxed);
+        if duplicate {
+            self.batch.duplicates.fetch_add(1, Ordering::Relaxed);
+        }
+        self.inner.block_processed(duplicate);
+    }
+
+    fn block_stored(&self, decoded_bytes: usize, stored_bytes: usize) {
+        self.inner.block_stored(decoded_bytes, stored_bytes);
+    }
+
+    fn bytes_written(&self, bytes: usize) {
+        self.batch.bytes_written.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.inner.bytes_written(bytes);
+    }
+
+    fn stage_finished(&self, name: &str, duration: Duration) {
+        self.inner.stage_finished(name, duration);
+    }
+
+    fn work_done(&self, stage: PipelineStage, duration: Duration) {
+        self.inner.work_done(stage, duration);
+    }
+
+    fn finish(&self) {
+        self.inner.finish();
+    }
+}
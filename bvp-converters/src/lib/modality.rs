@@ -2,97 +2,58 @@ use std::collections::HashMap;
 
 use tinyjson::JsonValue;
 
-use crate::{vector3::Vector3, errors::{ModalityError, JsonError}, json_aux};
+use crate::{vector3::Vector3, errors::{ModalityError, JsonError}, extensions::ExtensionPayloads, mapping::{ObjectReader, ObjectWriter}};
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Modality {
     pub name: Option<String>,
-    description: Option<String>,
-    semantic_type: Option<String>,
-    volume_size: Vector3<f32>,
-    voxel_size: Option<Vector3<f32>>,
-    pub block: usize
+    pub description: Option<String>,
+    pub semantic_type: Option<String>,
+    pub volume_size: Vector3<f32>,
+    pub voxel_size: Option<Vector3<f32>>,
+    pub block: usize,
+    pub extension_payloads: ExtensionPayloads,
+    /// Application specific data, kept as it is.
+    pub extras: Option<JsonValue>
 }
 
 impl Modality {
     pub fn new(name: Option<String>, description: Option<String>, semantic_type: Option<String>,
         volume_size: Vector3<f32>, voxel_size: Option<Vector3<f32>>, block: usize) -> Self {
-        return Self { name, description, semantic_type, volume_size, voxel_size, block };
+        return Self { name, description, semantic_type, volume_size, voxel_size, block, extension_payloads: HashMap::new(), extras: None };
     }
 
     pub fn to_json(&self) -> JsonValue {
-        let mut hm = HashMap::new();
-        if self.name.is_some() {
-            hm.insert("name".to_string(), self.name.as_ref().unwrap().clone().into());
-        }
-        if self.description.is_some() {
-            hm.insert("description".to_string(), self.description.as_ref().unwrap().clone().into());
-        }
-        if self.semantic_type.is_some() {
-            hm.insert("semanticType".to_string(), self.semantic_type.as_ref().unwrap().clone().into());
-        }
-        hm.insert("volumeSize".to_string(), self.volume_size.to_json());
-        if self.voxel_size.is_some() {
-            hm.insert("voxelSize".to_string(), self.voxel_size.unwrap().to_json());
-        }
-        hm.insert("block".to_string(), (self.block as f64).into());
-        return hm.into();
+        return ObjectWriter::new()
+            .optional("name", &self.name)
+            .optional("description", &self.description)
+            .optional("semanticType", &self.semantic_type)
+            .field("volumeSize", &self.volume_size)
+            .optional("voxelSize", &self.voxel_size)
+            .field("block", &self.block)
+            .payloads(&self.extension_payloads)
+            .extras(&self.extras)
+            .finish();
     }
 
     pub fn from_json(index: usize, j: &JsonValue) -> Result<Self, ModalityError> {
-        let hashmap = match j {
-            JsonValue::Object(o) => o,
-            _ => return Err(ModalityError::InvalidJson(index, JsonError::NotAnObject(j.clone())))
-        };
-
-        let block = match json_aux::get_u32_from_json(&hashmap["block"]) {
-            Ok(b) => b as usize,
-            Err(e) => return Err(ModalityError::InvalidJson(index, e))
-        };
-        let name = match hashmap.get("name") {
-            Some(s) => {
-                match json_aux::get_string_from_json(s) {
-                    Ok(n) => Some(n),
-                    Err(e) => return Err(ModalityError::InvalidJson(index, e))
-                }
-            },
-            None => None
-        };
-        let description = match hashmap.get("description") {
-            Some(s) => {
-                match json_aux::get_string_from_json(s) {
-                    Ok(n) => Some(n),
-                    Err(e) => return Err(ModalityError::InvalidJson(index, e))
-                }
-            },
-            None => None
-        };
-        let semantic_type = match hashmap.get("semanticType") {
-            Some(s) => {
-                match json_aux::get_string_from_json(s) {
-                    Ok(n) => Some(n),
-                    Err(e) => return Err(ModalityError::InvalidJson(index, e))
-                }
-            },
-            None => None
-        };
-        let volume_size = match hashmap.get("volumeSize") {
-            Some(s) => match Vector3::<f32>::from_json(s) {
-                Ok(v) => v,
-                Err(e) => return Err(ModalityError::InvalidJson(index, e))
-            },
-            None => Vector3::<f32>{x: 0.0, y: 0.0, z: 0.0}
-        };
-        let voxel_size = match hashmap.get("voxelSize") {
-            Some(s) => {
-                match Vector3::<f32>::from_json(s) {
-                    Ok(v) => Some(v),
-                    Err(e) => return Err(ModalityError::InvalidJson(index, e))
-                }
-            },
-            None => None
-        };
+        return Self::read(j).map_err(|e| ModalityError::InvalidJson(index, e));
+    }
 
-        return Ok(Self::new(name, description, semantic_type, volume_size, voxel_size, block));
+    /// Reads a modality, with JSON errors at pointers relative to it.
+    /// * `j` - JSON value, should be an object
+    fn read(j: &JsonValue) -> Result<Self, JsonError> {
+        let o = ObjectReader::new(j)?;
+        let block = o.required::<u32>("block")? as usize;
+        let name = o.optional("name")?;
+        let description = o.optional("description")?;
+        let semantic_type = o.optional("semanticType")?;
+        let volume_size = o.optional("volumeSize")?.unwrap_or(Vector3::<f32>{x: 0.0, y: 0.0, z: 0.0});
+        let voxel_size = o.optional("voxelSize")?;
+
+        let mut modality = Self::new(name, description, semantic_type, volume_size, voxel_size, block);
+        modality.extension_payloads = o.payloads()?;
+        modality.extras = o.extras();
+        return Ok(modality);
     }
 }
\ No newline at end of file
@@ -0,0 +1,178 @@
+//! Coarse occupancy bitmaps of blocks, stored in the `EXT_occupancy` payload of a block:
+//!
+//! ```json
+//! "extensions": {
+//!     "EXT_occupancy": { "cellDimensions": [4, 4, 4], "bitmap": "ff0100000000000080" }
+//! }
+//! ```
+//!
+//! The block is divided into cells of `cellDimensions` voxels, fewer at the far sides of blocks whose sides
+//! are not multiples of them, and the bitmap has a bit for every cell, set if any voxel of the cell is not zero.
+//! Bits follow the Morton (Z-order) curve of the positions of the cells, skipping positions outside the block
+//! as the voxel layout does, and are packed into bytes from the lowest bit, written as hexadecimal text.
+//! Renderers can skip the empty cells of a block without decoding it. Cells are aligned to microblocks, and a
+//! microblock counts as empty only if all of its stored bytes are zero, so the bitmap of a compressed format
+//! may mark cells that decode to zero as occupied, but never the other way around.
+//! The extension is optional, as the data is readable without the bitmaps.
+
+use std::collections::HashMap;
+
+use tinyjson::JsonValue;
+
+use crate::{block::Block, errors::{JsonError, OccupancyError}, extensions::Extension, formats::Format, json_aux, layout::{self, VoxelLayout}, signature, vector3::Vector3};
+
+/// Occupancy of the cells of a block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Occupancy {
+    /// Dimensions of a cell in voxels
+    pub cell_dimensions: Vector3<u32>,
+    /// Number of cells along each axis of the block
+    pub cells: Vector3<u32>,
+    /// A bit for every cell, in Morton order
+    bitmap: Vec<u8>
+}
+
+impl Occupancy {
+    /// Computes the occupancy of a block from its data.
+    /// * `data` - unencoded data of the block, in the voxel layout of the format
+    /// * `block_dimensions` - dimensions of the block
+    /// * `format` - format of the block
+    /// * `cell_dimensions` - dimensions of a cell, multiples of the microblock dimensions of the format
+    pub fn compute(data: &[u8], block_dimensions: Vector3<u32>, format: &Format, cell_dimensions: Vector3<u32>) -> Result<Self, OccupancyError> {
+        let microblock = format.microblock_dimensions;
+        if cell_dimensions.x == 0 || cell_dimensions.y == 0 || cell_dimensions.z == 0 || cell_dimensions.is_any_div(&microblock) {
+            return Err(OccupancyError::InvalidCellDimensions(cell_dimensions, microblock));
+        }
+        let microblocks = block_dimensions.div_ceil(&microblock);
+        let microblock_size = format.microblock_size as usize;
+        // Sizes that overflow are larger than any data.
+        let size = microblocks.cast::<usize>().and_then(|m| m.checked_product()).and_then(|n| n.checked_mul(microblock_size));
+        if size.map_or(true, |size| data.len() < size) {
+            return Err(OccupancyError::DataTooShort(data.len()));
+        }
+        let cells = block_dimensions.div_ceil(&cell_dimensions);
+        let cell_count = cells.cast::<usize>().and_then(|c| c.checked_product()).ok_or(OccupancyError::TooManyCells(cells))?;
+        let mut occupied = vec![false; cell_count];
+        let voxel_layout = layout::layout(format).map_err(|e| OccupancyError::Layout(e.to_string()))?;
+        let order = layout::microblock_order(voxel_layout, microblocks);
+        for (stored, linear) in data.chunks_exact(microblock_size).zip(order) {
+            if stored.iter().all(|b| *b == 0) {
+                continue;
+            }
+            let position = Vector3::from_xyz(
+                (linear % microblocks.x as usize) as u32,
+                (linear / microblocks.x as usize % microblocks.y as usize) as u32,
+                (linear / (microblocks.x as usize * microblocks.y as usize)) as u32
+            );
+            let cell = (position * microblock) / cell_dimensions;
+            occupied[Vector3::linear_index(cell, cells)] = true;
+        }
+
+        let mut bitmap = vec![0u8; occupied.len().div_ceil(8)];
+        for (bit, cell) in layout::microblock_order(VoxelLayout::Morton, cells).into_iter().enumerate() {
+            if occupied[cell] {
+                bitmap[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        return Ok(Self { cell_dimensions, cells, bitmap });
+    }
+
+    /// Returns true if any voxel of a cell is not zero.
+    /// * `cell` - position of the cell in the grid of cells, inside `cells`
+    pub fn is_occupied(&self, cell: Vector3<u32>) -> bool {
+        let bit = self.bit_index(cell);
+        return self.bitmap[bit / 8] & (1 << (bit % 8)) != 0;
+    }
+
+    /// Returns the number of occupied cells.
+    pub fn occupied_cells(&self) -> usize {
+        return self.bitmap.iter().map(|b| b.count_ones() as usize).sum();
+    }
+
+    /// Returns true if all voxels of the block are zero.
+    pub fn is_empty(&self) -> bool {
+        return self.bitmap.iter().all(|b| *b == 0);
+    }
+
+    /// Returns the position of the bit of a cell in the bitmap, the number of cells of the block with a smaller Morton code.
+    fn bit_index(&self, cell: Vector3<u32>) -> usize {
+        let code = cell.morton_code();
+        let mut index = 0;
+        for z in 0..self.cells.z {
+            for y in 0..self.cells.y {
+                for x in 0..self.cells.x {
+                    if Vector3::from_xyz(x, y, z).morton_code() < code {
+                        index += 1;
+                    }
+                }
+            }
+        }
+        return index;
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut hm = HashMap::new();
+        hm.insert("cellDimensions".to_string(), self.cell_dimensions.to_json());
+        hm.insert("bitmap".to_string(), signature::to_hex(&self.bitmap).into());
+        return hm.into();
+    }
+
+    /// Reads an occupancy payload.
+    /// * `j` - the payload
+    /// * `block_dimensions` - dimensions of the block it belongs to
+    pub fn from_json(j: &JsonValue, block_dimensions: Vector3<u32>) -> Result<Self, OccupancyError> {
+        let o = match j {
+            JsonValue::Object(o) => o,
+            _ => return Err(OccupancyError::InvalidJson(JsonError::NotAnObject(j.clone())))
+        };
+        let cell_dimensions = match o.get("cellDimensions") {
+            Some(c) => Vector3::<u32>::from_json(c).map_err(OccupancyError::InvalidJson)?,
+            None => return Err(OccupancyError::MissingKey("cellDimensions".to_string()))
+        };
+        if cell_dimensions.x == 0 || cell_dimensions.y == 0 || cell_dimensions.z == 0 {
+            return Err(OccupancyError::InvalidCellDimensions(cell_dimensions, Vector3::from_xyz(1, 1, 1)));
+        }
+        let text = match o.get("bitmap") {
+            Some(b) => json_aux::get_string_from_json(b).map_err(OccupancyError::InvalidJson)?,
+            None => return Err(OccupancyError::MissingKey("bitmap".to_string()))
+        };
+        let cells = block_dimensions.div_ceil(&cell_dimensions);
+        let length = cells.cast::<usize>().and_then(|c| c.checked_product()).ok_or(OccupancyError::TooManyCells(cells))?.div_ceil(8);
+        let bitmap = from_hex(&text).filter(|b| b.len() == length).ok_or(OccupancyError::InvalidBitmap(length))?;
+        return Ok(Self { cell_dimensions, cells, bitmap });
+    }
+}
+
+/// Decodes hexadecimal text of any number of bytes, or returns None if it is not one.
+/// * `text` - the text, in upper or lowercase
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 || !text.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    return (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect();
+}
+
+/// Returns the occupancy of a block, or None if it has no bitmap.
+/// * `block` - the block
+pub fn occupancy(block: &Block) -> Result<Option<Occupancy>, OccupancyError> {
+    return match block.extension_payloads.get(&Extension::ExtOccupancy.to_string()) {
+        Some(j) => Occupancy::from_json(j, block.dimensions).map(Some),
+        None => Ok(None)
+    };
+}
+
+/// Stores the occupancy of a block in its payloads.
+/// * `block` - the block
+/// * `occupancy` - occupancy of the data of the block
+pub fn set_occupancy(block: &mut Block, occupancy: &Occupancy) {
+    block.extension_payloads.insert(Extension::ExtOccupancy.to_string(), occupancy.to_json());
+}
+
+/// Returns the dimensions of the cells that divide blocks into a grid of cells, rounded up to microblocks.
+/// * `grid` - number of cells along each axis of a block
+/// * `block_dimensions` - dimensions of the blocks
+/// * `microblock_dimensions` - microblock dimensions of the format
+pub fn cell_dimensions(grid: Vector3<u32>, block_dimensions: Vector3<u32>, microblock_dimensions: Vector3<u32>) -> Vector3<u32> {
+    let cell = block_dimensions.div_ceil(&grid);
+    return cell.div_ceil(&microblock_dimensions) * microblock_dimensions;
+}
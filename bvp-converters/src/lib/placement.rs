@@ -1,10 +1,8 @@
-use std::collections::HashMap;
-
 use tinyjson::JsonValue;
 
-use crate::{vector3::Vector3, json_aux::get_u32_from_json, errors::{PlacementError, JsonError}};
+use crate::{vector3::Vector3, mapping::{ObjectReader, ObjectWriter}, errors::{PlacementError, JsonError}};
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Placement {
     pub position: Vector3<u32>,
     pub block: usize
@@ -16,26 +14,22 @@ impl Placement {
     }
 
     pub fn to_json(&self) -> JsonValue {
-        let mut hm = HashMap::new();
-        hm.insert("position".to_string(), self.position.to_json());
-        hm.insert("block".to_string(), (self.block as f64).into());
-        return hm.into();
+        return ObjectWriter::new()
+            .field("position", &self.position)
+            .field("block", &self.block)
+            .finish();
     }
 
     pub fn from_json(block_index: usize, j: &JsonValue) -> Result<Self, PlacementError> {
-        return match j {
-            JsonValue::Object(o) => {
-                let position = match Vector3::<u32>::from_json(&o["position"]) {
-                    Ok(p) => p,
-                    Err(e) => return Err(PlacementError::InvalidJson(block_index, e))
-                };
-                let block = match get_u32_from_json(&o["block"]) {
-                    Ok(b) => b,
-                    Err(e) => return Err(PlacementError::InvalidJson(block_index, e))
-                };
-                return Ok(Placement::new(position, block as usize));
-            },
-            _ => Err(PlacementError::InvalidJson(block_index, JsonError::NotAnObject(j.clone())))
-        };
+        return Self::read(j).map_err(|e| PlacementError::InvalidJson(block_index, e));
+    }
+
+    /// Reads a placement, with JSON errors at pointers relative to it.
+    /// * `j` - JSON value, should be an object
+    fn read(j: &JsonValue) -> Result<Self, JsonError> {
+        let o = ObjectReader::new(j)?;
+        let position = o.required("position")?;
+        let block = o.required::<u32>("block")?;
+        return Ok(Placement::new(position, block as usize));
     }
 }
\ No newline at end of file
@@ -0,0 +1,170 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::metrics::PipelineStage;
+
+/// Receives progress events from a conversion.
+/// Events can come from several threads at once, so implementations must be thread safe.
+/// All methods do nothing by default.
+pub trait ProgressSink: Sync {
+    /// Called once before any block is processed.
+    /// * `total_blocks` - the number of blocks that will be processed
+    fn start(&self, _total_blocks: usize) {}
+
+    /// Called when a block has been extracted and deduplicated.
+    /// * `duplicate` - true if the block data was already stored in another block
+    fn block_processed(&self, _duplicate: bool) {}
+
+    /// Called when an input volume has been read, before it is split into blocks.
+    /// * `name` - the input file
+    /// * `data` - its contents
+    fn input_read(&self, _name: &str, _data: &[u8]) {}
+
+    /// Called when a block that is not a duplicate has been encoded, before its file is written.
+    /// * `decoded_bytes` - size of the block data
+    /// * `stored_bytes` - size of the encoded data
+    fn block_stored(&self, _decoded_bytes: usize, _stored_bytes: usize) {}
+
+    /// Called when a stage of the conversion has finished.
+    /// * `name` - the stage, `read_input`, `blocks` or `finalize`
+    /// * `duration` - time spent in it
+    fn stage_finished(&self, _name: &str, _duration: Duration) {}
+
+    /// Called when a worker has done the work of a pipeline stage for one block.
+    /// Workers run at the same time, so the durations overlap.
+    /// * `stage` - the pipeline stage
+    /// * `duration` - time spent on the block
+    fn work_done(&self, _stage: PipelineStage, _duration: Duration) {}
+
+    /// Called when a file has been written to the output.
+    /// * `bytes` - the size of the written file
+    fn bytes_written(&self, _bytes: usize) {}
+
+    /// Called once after all files have been written.
+    fn finish(&self) {}
+}
+
+/// A progress sink that ignores all events.
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
+
+/// Sinks can be lent to a conversion and still be used after it, for example to read their totals.
+impl<P: ProgressSink + ?Sized> ProgressSink for &P {
+    fn start(&self, total_blocks: usize) {
+        (**self).start(total_blocks);
+    }
+
+    fn block_processed(&self, duplicate: bool) {
+        (**self).block_processed(duplicate);
+    }
+
+    fn input_read(&self, name: &str, data: &[u8]) {
+        (**self).input_read(name, data);
+    }
+
+    fn block_stored(&self, decoded_bytes: usize, stored_bytes: usize) {
+        (**self).block_stored(decoded_bytes, stored_bytes);
+    }
+
+    fn stage_finished(&self, name: &str, duration: Duration) {
+        (**self).stage_finished(name, duration);
+    }
+
+    fn work_done(&self, stage: PipelineStage, duration: Duration) {
+        (**self).work_done(stage, duration);
+    }
+
+    fn bytes_written(&self, bytes: usize) {
+        (**self).bytes_written(bytes);
+    }
+
+    fn finish(&self) {
+        (**self).finish();
+    }
+}
+
+/// The state of a conversion at some point in time.
+#[derive(Clone, Copy, Debug)]
+pub struct ProgressSnapshot {
+    pub blocks_total: usize,
+    pub blocks_processed: usize,
+    pub duplicates: usize,
+    pub bytes_written: u64,
+    pub elapsed: Duration
+}
+
+impl ProgressSnapshot {
+    /// Returns the processed fraction of blocks, between 0 and 1.
+    pub fn fraction(&self) -> f64 {
+        if self.blocks_total == 0 {
+            return 1.0;
+        }
+        return self.blocks_processed as f64 / self.blocks_total as f64;
+    }
+
+    /// Estimates the remaining time, assuming the remaining blocks
+    /// take as long as the processed ones did on average.
+    /// Returns `None` before the first block is processed.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.blocks_processed == 0 {
+            return None;
+        }
+        let remaining = self.blocks_total.saturating_sub(self.blocks_processed);
+        let per_block = self.elapsed.as_secs_f64() / self.blocks_processed as f64;
+        return Some(Duration::from_secs_f64(per_block * remaining as f64));
+    }
+}
+
+/// Thread safe counters of progress events, for use inside `ProgressSink` implementations.
+pub struct ProgressCounters {
+    started: Instant,
+    blocks_total: AtomicUsize,
+    blocks_processed: AtomicUsize,
+    duplicates: AtomicUsize,
+    bytes_written: AtomicU64
+}
+
+impl ProgressCounters {
+    pub fn new() -> Self {
+        return Self {
+            started: Instant::now(),
+            blocks_total: AtomicUsize::new(0),
+            blocks_processed: AtomicUsize::new(0),
+            duplicates: AtomicUsize::new(0),
+            bytes_written: AtomicU64::new(0)
+        };
+    }
+
+    pub fn start(&self, total_blocks: usize) {
+        self.blocks_total.store(total_blocks, Ordering::Relaxed);
+    }
+
+    pub fn block_processed(&self, duplicate: bool) {
+        self.blocks_processed.fetch_add(1, Ordering::Relaxed);
+        if duplicate {
+            self.duplicates.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn bytes_written(&self, bytes: usize) {
+        self.bytes_written.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the current values of all counters.
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        return ProgressSnapshot {
+            blocks_total: self.blocks_total.load(Ordering::Relaxed),
+            blocks_processed: self.blocks_processed.load(Ordering::Relaxed),
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            elapsed: self.started.elapsed()
+        };
+    }
+}
+
+impl Default for ProgressCounters {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
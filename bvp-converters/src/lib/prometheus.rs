@@ -0,0 +1,111 @@
+//! Metrics in the Prometheus text format, and a minimal endpoint that serves them,
+//! for monitoring long running conversions and block servers.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use crate::errors::MetricsError;
+use crate::log_warn;
+
+/// Content type of the Prometheus text format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Scrapes that do not send their request in this time are dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Metrics being written in the Prometheus text format.
+pub struct Exposition {
+    text: String
+}
+
+impl Exposition {
+    pub fn new() -> Self {
+        return Self { text: String::new() };
+    }
+
+    /// Adds a value that only goes up, such as the number of requests served.
+    /// * `name` - name of the metric, ending in `_total` by convention
+    /// * `help` - description of the metric
+    /// * `value` - the current value
+    pub fn counter(&mut self, name: &str, help: &str, value: f64) {
+        self.metric(name, help, "counter", value);
+    }
+
+    /// Adds a value that can go up and down, such as the number of conversions in flight.
+    /// * `name` - name of the metric
+    /// * `help` - description of the metric
+    /// * `value` - the current value
+    pub fn gauge(&mut self, name: &str, help: &str, value: f64) {
+        self.metric(name, help, "gauge", value);
+    }
+
+    fn metric(&mut self, name: &str, help: &str, kind: &str, value: f64) {
+        let help = help.replace('\\', "\\\\").replace('\n', "\\n");
+        self.text.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value));
+    }
+
+    /// Returns the text of the metrics.
+    pub fn finish(self) -> String {
+        return self.text;
+    }
+}
+
+impl Default for Exposition {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+/// Answers one scrape on a connection. Only `GET /metrics` is served.
+/// * `stream` - the connection
+/// * `render` - returns the current metrics
+fn answer(stream: TcpStream, render: &dyn Fn() -> String) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers are not needed, but are read so the client does not see a reset connection.
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", CONTENT_TYPE, render()),
+        (Some("GET"), _) => ("404 Not Found", "text/plain; charset=utf-8", "Metrics are served at /metrics\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain; charset=utf-8", "Only GET is supported\n".to_string())
+    };
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, content_type, body.len()
+    );
+    writer.write_all(header.as_bytes())?;
+    writer.write_all(body.as_bytes())?;
+    return writer.flush();
+}
+
+/// Serves metrics at `http://<address>/metrics` from a background thread, one scrape at a time.
+/// Returns the address that is listened on, which tells the port when `address` asks for any free one.
+/// * `address` - address to listen on, such as `127.0.0.1:9184`
+/// * `render` - returns the current metrics, usually written with an `Exposition`
+pub fn serve<F>(address: &str, render: F) -> Result<SocketAddr, MetricsError>
+    where F: Fn() -> String + Send + 'static
+{
+    let listener = TcpListener::bind(address).map_err(|e| MetricsError::CannotListen(address.to_string(), e))?;
+    let local_address = listener.local_addr().map_err(|e| MetricsError::CannotListen(address.to_string(), e))?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| answer(stream, &render));
+            if let Err(e) = result {
+                log_warn!("cannot answer a metrics scrape: {}", e);
+            }
+        }
+    });
+    return Ok(local_address);
+}
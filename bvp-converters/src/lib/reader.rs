@@ -0,0 +1,439 @@
+use std::collections::HashSet;
+
+use crate::{block::Block, bvpfile::BVPFile, bytes::Bytes, checksum::Checksum, compressions::CompressionType};
+use crate::{errors::{BlockError, ReaderError}, formats::Format, layout, vector3::Vector3};
+
+/// Nesting depth of the block tree after which reading stops.
+const MAX_TREE_DEPTH: usize = 64;
+
+/// Keeps track of which microblocks of a reconstructed volume
+/// have been written, so missing blocks can be reported.
+struct Coverage {
+    written: Vec<bool>,
+    microblock_amount: Vector3<u32>,
+    microblock_dimensions: Vector3<u32>
+}
+
+impl Coverage {
+    /// * `dimensions` - dimensions of the reconstructed volume
+    /// * `format` - the format of the data
+    fn new(dimensions: Vector3<u32>, format: &Format) -> Self {
+        let microblock_dimensions = format.microblock_dimensions;
+        let microblock_amount = dimensions / microblock_dimensions;
+        return Self {
+            written: vec![false; Vector3::<usize>::from(microblock_amount).product()],
+            microblock_amount,
+            microblock_dimensions
+        };
+    }
+
+    /// Marks a region of the volume as written.
+    /// * `offset` - start of the region
+    /// * `extent` - dimensions of the region
+    fn mark(&mut self, offset: Vector3<u32>, extent: Vector3<u32>) {
+        let start = offset / self.microblock_dimensions;
+        let amount = extent / self.microblock_dimensions;
+        for z in start.z..start.z + amount.z {
+            for y in start.y..start.y + amount.y {
+                let row_start = Vector3::linear_index(Vector3::from_xyz(start.x, y, z), self.microblock_amount);
+                for written in &mut self.written[row_start..row_start + amount.x as usize] {
+                    *written = true;
+                }
+            }
+        }
+    }
+
+    /// Returns the number of microblocks that were never written.
+    fn unwritten(&self) -> usize {
+        return self.written.iter().filter(|w| !**w).count();
+    }
+}
+
+/// Decodes the data of a block and reorders it to linear order, if the format has another layout.
+/// * `block` - the block, required to have data
+/// * `format` - the format of the data
+/// * `verify_checksums` - whether to check the data against the recorded checksum first
+pub(crate) fn decode_block(block: &Block, format: &Format, verify_checksums: bool) -> Result<Block, ReaderError> {
+    if let (true, Some(checksum), Some(data)) = (verify_checksums, &block.checksum, &block.data) {
+        if !checksum.verify(data) {
+            return Err(ReaderError::ChecksumMismatch(block.index, checksum.to_string()));
+        }
+    }
+    // Readers always return data in linear order.
+    let layout = layout::layout(format).map_err(ReaderError::LayoutError)?;
+    return block.from_layout(format, layout).map_err(ReaderError::BlockError);
+}
+
+/// A region being reconstructed: the destination block and the blocks
+/// with data that still have to be copied into it.
+pub(crate) struct RegionPlan<'a> {
+    block_index: usize,
+    start: Vector3<u32>,
+    end: Vector3<u32>,
+    pub(crate) format: &'a Format,
+    region: Block,
+    coverage: Coverage,
+    /// Indices of the blocks with data and their positions, relative to the block the region is read from.
+    pub(crate) pieces: Vec<(usize, Vector3<u32>)>
+}
+
+impl<'a> RegionPlan<'a> {
+    /// Copies the part of a decoded block that lies inside the region to the destination block.
+    /// * `decoded` - the decoded block
+    /// * `origin` - its position, as listed in `pieces`
+    pub(crate) fn paste(&mut self, decoded: Block, origin: Vector3<u32>) -> Result<(), ReaderError> {
+        let block_end = origin + decoded.dimensions;
+        let intersection_start = origin.max(&self.start);
+        let intersection_end = block_end.min(&self.end);
+        let format = self.format;
+        // Only the part inside the region is copied, unless the whole block is inside.
+        let mut part = decoded;
+        if intersection_start != origin || intersection_end != block_end {
+            part = part.get_data_in_range(intersection_start - origin, intersection_end - origin, format)
+                .map_err(ReaderError::BlockError)?;
+        }
+        part.format = self.region.format;
+        self.region.set_data_in_range(intersection_start - self.start, &part, format).map_err(ReaderError::BlockError)?;
+        self.coverage.mark(intersection_start - self.start, intersection_end - intersection_start);
+        return Ok(());
+    }
+
+    /// Returns the reconstructed region, or fails if any part of it was not covered by a block.
+    pub(crate) fn finish(self) -> Result<Block, ReaderError> {
+        let unwritten = self.coverage.unwritten();
+        if unwritten > 0 {
+            return Err(ReaderError::RegionUncovered(self.block_index, unwritten, self.coverage.written.len()));
+        }
+        return Ok(self.region);
+    }
+}
+
+/// What is known about a block with data without decoding it.
+#[derive(Clone, Debug)]
+pub struct BlockMeta {
+    pub index: usize,
+    pub dimensions: Vector3<u32>,
+    /// Index of the format of the data, taken from the modality the block is placed in
+    /// if the block does not name one. `None` if neither does.
+    pub format: Option<usize>,
+    pub encoding: Option<CompressionType>,
+    pub data_url: Option<String>,
+    /// Size of the data as stored, before it is decoded.
+    pub stored_size: usize,
+    pub checksum: Option<Checksum>
+}
+
+/// Reconstructs volumes from the block tree of a BVP asset.
+pub struct VolumeReader<'a> {
+    bvp_file: &'a BVPFile,
+    verify_checksums: bool
+}
+
+impl<'a> VolumeReader<'a> {
+    pub fn new(bvp_file: &'a BVPFile) -> Self {
+        return Self { bvp_file, verify_checksums: false };
+    }
+
+    /// Returns the asset the volumes are reconstructed from.
+    pub fn bvp_file(&self) -> &'a BVPFile {
+        return self.bvp_file;
+    }
+
+    /// Sets whether block data is checked against its recorded checksum before it is decoded.
+    /// Blocks without a checksum are read either way.
+    /// * `verify` - whether to check the checksums
+    pub fn with_checksum_verification(mut self, verify: bool) -> Self {
+        self.verify_checksums = verify;
+        return self;
+    }
+
+    /// Decodes the data of a block, after checking its checksum if verification is enabled.
+    /// * `block` - the block, required to have data
+    /// * `format` - the format of the data
+    fn decode(&self, block: &Block, format: &Format) -> Result<Block, ReaderError> {
+        return decode_block(block, format, self.verify_checksums);
+    }
+
+    /// Goes through all nodes in the tree of blocks
+    /// and finds the first instance of format on a block.
+    /// It is assumed that formats do not differ inside blocks
+    /// of the same modality.
+    /// Blocks shared by several parents are only visited once.
+    /// * `block_index` - index of the root of the tree
+    pub fn find_format_index(&self, block_index: usize) -> Result<usize, ReaderError> {
+        let mut stack = vec![block_index];
+        let mut visited = HashSet::new();
+
+        while let Some(block_index) = stack.pop() {
+            if !visited.insert(block_index) {
+                continue;
+            }
+            let block = match self.bvp_file.blocks.get(block_index) {
+                Some(b) => b,
+                None => return Err(ReaderError::NoSuchBlock(block_index))
+            };
+            if let Some(format_index) = block.format {
+                if format_index >= self.bvp_file.formats.len() {
+                    return Err(ReaderError::NoSuchFormat(format_index));
+                }
+                return Ok(format_index);
+            }
+            for placement in &block.placements {
+                stack.push(placement.block);
+            }
+        }
+        return Err(ReaderError::NoFormat(block_index));
+    }
+
+    /// Same as `find_format_index`, but returns the format itself.
+    /// * `block_index` - index of the root of the tree
+    pub fn find_format(&self, block_index: usize) -> Result<&'a Format, ReaderError> {
+        let format_index = self.find_format_index(block_index)?;
+        return Ok(&self.bvp_file.formats[format_index]);
+    }
+
+    /// Returns the index of the root block and the format of a modality.
+    /// * `modality_index` - index of the modality
+    pub fn modality_root(&self, modality_index: usize) -> Result<(usize, &'a Format), ReaderError> {
+        let modality = match self.bvp_file.modalities.get(modality_index) {
+            Some(m) => m,
+            None => return Err(ReaderError::NoSuchModality(modality_index))
+        };
+        let format = self.find_format(modality.block)?;
+        return Ok((modality.block, format));
+    }
+
+    /// Returns the format index of every block: the one the block names, or for blocks that
+    /// do not name one, the format of the first modality whose tree they are placed in.
+    fn block_formats(&self) -> Vec<Option<usize>> {
+        let blocks = &self.bvp_file.blocks;
+        let mut formats: Vec<Option<usize>> = blocks.iter().map(|block| block.format).collect();
+        for modality in &self.bvp_file.modalities {
+            let format = match self.find_format_index(modality.block) {
+                Ok(f) => f,
+                Err(_) => continue
+            };
+            let mut visited = HashSet::new();
+            let mut stack = vec![modality.block];
+            while let Some(index) = stack.pop() {
+                if index >= blocks.len() || !visited.insert(index) {
+                    continue;
+                }
+                if formats[index].is_none() {
+                    formats[index] = Some(format);
+                }
+                for placement in &blocks[index].placements {
+                    stack.push(placement.block);
+                }
+            }
+        }
+        return formats;
+    }
+
+    /// Returns the blocks with data, in the order of the manifest, each with a function that decodes its data.
+    /// Nothing is decoded until the function is called, so tools can go through all blocks of an asset
+    /// holding only one decoded block at a time. Checksums are verified when the data is decoded,
+    /// if verification is enabled. Unencoded data is shared with the files of the asset; `Bytes::into_vec`
+    /// copies it into a vector of its own.
+    pub fn blocks(&self) -> impl Iterator<Item = (BlockMeta, impl FnOnce() -> Result<Bytes, ReaderError> + 'a)> + 'a {
+        let bvp_file = self.bvp_file;
+        let verify_checksums = self.verify_checksums;
+        let formats = self.block_formats();
+        return bvp_file.blocks.iter().zip(formats)
+            .filter(|(block, _)| block.data.is_some())
+            .map(move |(block, format_index)| {
+                let meta = BlockMeta {
+                    index: block.index,
+                    dimensions: block.dimensions,
+                    format: format_index,
+                    encoding: block.encoding,
+                    data_url: block.data_url.clone(),
+                    stored_size: block.data.as_ref().map(|data| data.len()).unwrap_or(0),
+                    checksum: block.checksum.clone()
+                };
+                let read = move || {
+                    let format = match format_index {
+                        Some(index) => match bvp_file.formats.get(index) {
+                            Some(f) => f,
+                            None => return Err(ReaderError::NoSuchFormat(index))
+                        },
+                        None => return Err(ReaderError::NoFormat(block.index))
+                    };
+                    let decoded = decode_block(block, format, verify_checksums)?;
+                    return Ok(decoded.data.unwrap_or_default());
+                };
+                return (meta, read);
+            });
+    }
+
+    /// Recursively goes through the block tree and lists the blocks with data
+    /// that intersect the region, with their positions. Depth first.
+    /// * `block_index` - index of the current block (node) being traversed
+    /// * `origin` - position of the current block, relative to the block the region is read from
+    /// * `start`, `end` - the region being read
+    /// * `pieces` - the blocks with data found so far
+    /// * `depth` - how deep in the tree the current block is
+    fn collect_region(&self, block_index: usize, origin: Vector3<u32>, start: Vector3<u32>, end: Vector3<u32>,
+        pieces: &mut Vec<(usize, Vector3<u32>)>, depth: usize) -> Result<(), ReaderError>
+    {
+        // Deeper trees are only possible if a block is placed inside itself.
+        if depth > MAX_TREE_DEPTH {
+            return Err(ReaderError::TooDeep(block_index, MAX_TREE_DEPTH));
+        }
+        let block = match self.bvp_file.blocks.get(block_index) {
+            Some(b) => b,
+            None => return Err(ReaderError::NoSuchBlock(block_index))
+        };
+        let block_end = match origin.checked_add(&block.dimensions) {
+            Some(e) => e,
+            None => return Err(ReaderError::BlockError(BlockError::Overflow(block_index)))
+        };
+        let intersection_start = origin.max(&start);
+        let intersection_end = block_end.min(&end);
+        if intersection_start.x >= intersection_end.x || intersection_start.y >= intersection_end.y || intersection_start.z >= intersection_end.z {
+            return Ok(());
+        }
+
+        if block.data.is_some() {
+            pieces.push((block_index, origin));
+            return Ok(());
+        }
+
+        for placement in &block.placements {
+            let position = match origin.checked_add(&placement.position) {
+                Some(p) => p,
+                None => return Err(ReaderError::BlockError(BlockError::Overflow(placement.block)))
+            };
+            self.collect_region(placement.block, position, start, end, pieces, depth + 1)?;
+        }
+        return Ok(());
+    }
+
+    /// Returns the blocks with data that intersect a box of a modality, with their positions in the modality.
+    /// Subtrees outside the box are not visited, so only the blocks needed to reconstruct the box are listed.
+    /// A block placed several times is listed once for every placement that intersects the box.
+    /// The box does not have to be aligned to microblocks.
+    /// * `modality_index` - index of the modality
+    /// * `start` - start of the box
+    /// * `end` - end of the box (exclusive)
+    pub fn blocks_intersecting(&self, modality_index: usize, start: Vector3<u32>, end: Vector3<u32>) -> Result<Vec<(usize, Vector3<u32>)>, ReaderError> {
+        let (root_block_index, _) = self.modality_root(modality_index)?;
+        let dimensions = self.bvp_file.blocks[root_block_index].dimensions;
+        if start.x >= end.x || start.y >= end.y || start.z >= end.z || end.is_any_gt(dimensions) {
+            return Err(ReaderError::InvalidRegion(start, end, dimensions));
+        }
+        let mut pieces = Vec::new();
+        self.collect_region(root_block_index, Vector3::from_xyz(0, 0, 0), start, end, &mut pieces, 0)?;
+        return Ok(pieces);
+    }
+
+    /// Prepares reading a region of a block: checks the region, lists the blocks with data
+    /// that intersect it, and returns them together with an empty destination block.
+    /// * `block_index` - index of the block to read from
+    /// * `start` - start of the region, relative to the block
+    /// * `end` - end of the region (exclusive), relative to the block
+    pub(crate) fn plan_region(&self, block_index: usize, start: Vector3<u32>, end: Vector3<u32>) -> Result<RegionPlan<'a>, ReaderError> {
+        let format_index = self.find_format_index(block_index)?;
+        let format = &self.bvp_file.formats[format_index];
+        let block = &self.bvp_file.blocks[block_index];
+
+        if start.x >= end.x || start.y >= end.y || start.z >= end.z || end.is_any_gt(block.dimensions) {
+            return Err(ReaderError::InvalidRegion(start, end, block.dimensions));
+        }
+        let extent = end - start;
+        if start.is_any_div(&format.microblock_dimensions) || extent.is_any_div(&format.microblock_dimensions) {
+            return Err(ReaderError::MisalignedRegion(start, end, format.microblock_dimensions));
+        }
+
+        let size = match format.checked_count_space(extent) {
+            Some(s) => s,
+            None => return Err(ReaderError::BlockError(BlockError::Overflow(block_index)))
+        };
+        let mut pieces = Vec::new();
+        self.collect_region(block_index, Vector3::from_xyz(0, 0, 0), start, end, &mut pieces, 0)?;
+        return Ok(RegionPlan {
+            block_index,
+            start,
+            end,
+            format,
+            region: Block::new(0, extent, Some(format_index), Some(vec![0u8; size])),
+            coverage: Coverage::new(extent, format),
+            pieces
+        });
+    }
+
+    /// Reconstructs a region of a block into a single unencoded block, going through
+    /// all blocks placed inside it. Only the blocks that intersect the region are decoded.
+    /// Fails if any part of the region is not covered by a block.
+    /// * `block_index` - index of the block to read from
+    /// * `start` - start of the region, relative to the block
+    /// * `end` - end of the region (exclusive), relative to the block
+    pub fn read_block_region(&self, block_index: usize, start: Vector3<u32>, end: Vector3<u32>) -> Result<Block, ReaderError> {
+        let mut plan = self.plan_region(block_index, start, end)?;
+        let pieces = std::mem::take(&mut plan.pieces);
+        for (piece_index, origin) in pieces {
+            let decoded = self.decode(&self.bvp_file.blocks[piece_index], plan.format)?;
+            plan.paste(decoded, origin)?;
+        }
+        return plan.finish();
+    }
+
+    /// Reconstructs a region of a modality into a single unencoded block.
+    /// * `modality_index` - index of the modality
+    /// * `start` - start of the region
+    /// * `end` - end of the region (exclusive)
+    pub fn read_region(&self, modality_index: usize, start: Vector3<u32>, end: Vector3<u32>) -> Result<Block, ReaderError> {
+        let (root_block_index, _) = self.modality_root(modality_index)?;
+        return self.read_block_region(root_block_index, start, end);
+    }
+
+    /// Reconstructs the whole volume of a modality into a single unencoded block.
+    /// Fails if any part of the volume is not covered by a block, since it would silently stay zero.
+    /// * `modality_index` - index of the modality
+    pub fn read_modality(&self, modality_index: usize) -> Result<Block, ReaderError> {
+        let (root_block_index, _) = self.modality_root(modality_index)?;
+        let dimensions = self.bvp_file.blocks[root_block_index].dimensions;
+        return match self.read_block_region(root_block_index, Vector3::from_xyz(0, 0, 0), dimensions) {
+            Err(ReaderError::RegionUncovered(_, unwritten, total)) => Err(ReaderError::Uncovered(modality_index, unwritten, total)),
+            result => result
+        };
+    }
+
+    /// Finds the smallest and largest value of the first component stored in the data blocks
+    /// under a block. Blocks that are placed several times are only read once.
+    /// Returns zeros if there is no data.
+    /// * `block_index` - index of the root of the tree
+    pub fn value_range(&self, block_index: usize) -> Result<(f64, f64), ReaderError> {
+        let format = self.find_format(block_index)?;
+        let mut low = f64::INFINITY;
+        let mut high = f64::NEG_INFINITY;
+        let mut visited = HashSet::new();
+        let mut stack = vec![block_index];
+        while let Some(index) = stack.pop() {
+            if !visited.insert(index) {
+                continue;
+            }
+            let block = match self.bvp_file.blocks.get(index) {
+                Some(b) => b,
+                None => return Err(ReaderError::NoSuchBlock(index))
+            };
+            if block.data.is_some() {
+                let decoded = self.decode(block, format)?;
+                let values = format.first_components(decoded.data.as_ref().unwrap())
+                    .map_err(|e| ReaderError::FormatError(e))?;
+                for value in values {
+                    low = low.min(value);
+                    high = high.max(value);
+                }
+            }
+            for placement in &block.placements {
+                stack.push(placement.block);
+            }
+        }
+        if low > high {
+            return Ok((0.0, 0.0));
+        }
+        return Ok((low, high));
+    }
+}
+
@@ -0,0 +1,285 @@
+//! Reading and writing files that are not on the local file system: `http://` and `https://`
+//! URLs, Amazon S3 objects as `s3://bucket/key` and Google Cloud Storage objects as `gs://bucket/key`.
+//!
+//! Transfers are done with the `curl` command line tool, which handles TLS and signs S3
+//! requests (`--aws-sigv4`), so the library does not need an HTTP client. Credentials are
+//! taken from the usual environment variables and given to curl as a config on its standard
+//! input, never on its command line or in a file:
+//! * S3 - `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, optionally `AWS_SESSION_TOKEN`, and
+//!   `AWS_REGION` or `AWS_DEFAULT_REGION` (`us-east-1` by default). `BVP_S3_ENDPOINT` selects
+//!   an S3 compatible service, which is then addressed as `<endpoint>/<bucket>/<key>`.
+//! * Google Cloud Storage - `GOOGLE_OAUTH_ACCESS_TOKEN`, for example from `gcloud auth print-access-token`.
+
+use std::{env, fs, io::{self, Write}, path::{Path, PathBuf}, process::{Command, Stdio}, sync::atomic::{AtomicU64, Ordering}};
+
+use crate::errors::RemoteError;
+use crate::log_debug;
+
+/// Large files are read in ranges of this size, so a failed request does not restart the whole transfer.
+const RANGE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Where a file is read from or written to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Location {
+    Local(PathBuf),
+    Http(String),
+    S3 { bucket: String, key: String },
+    Gcs { bucket: String, key: String }
+}
+
+impl Location {
+    /// Returns the location a path or URL refers to. Everything that is not a URL
+    /// with a supported scheme is a local path.
+    /// * `s` - the path or URL
+    pub fn parse(s: &str) -> Result<Self, RemoteError> {
+        if s.starts_with("http://") || s.starts_with("https://") {
+            return Ok(Location::Http(s.to_string()));
+        }
+        for (scheme, s3) in [("s3://", true), ("gs://", false)] {
+            if let Some(rest) = s.strip_prefix(scheme) {
+                let (bucket, key) = match rest.split_once('/') {
+                    Some((b, k)) if !b.is_empty() && !k.is_empty() => (b.to_string(), k.to_string()),
+                    _ => return Err(RemoteError::InvalidUrl(s.to_string()))
+                };
+                return Ok(if s3 { Location::S3 { bucket, key } } else { Location::Gcs { bucket, key } });
+            }
+        }
+        return Ok(Location::Local(PathBuf::from(s)));
+    }
+
+    pub fn is_remote(&self) -> bool {
+        return !matches!(self, Location::Local(_));
+    }
+
+    /// Returns the location of a file relative to this one, as data files are to the manifest.
+    /// * `name` - relative name of the file
+    pub fn sibling(&self, name: &str) -> Self {
+        let join = |base: &str| match base.rsplit_once('/') {
+            Some((folder, _)) => format!("{}/{}", folder, name),
+            None => name.to_string()
+        };
+        return match self {
+            Location::Local(path) => Location::Local(path.parent().unwrap_or(Path::new("")).join(name)),
+            Location::Http(url) => Location::Http(join(url)),
+            Location::S3 { bucket, key } => Location::S3 { bucket: bucket.clone(), key: join(key) },
+            Location::Gcs { bucket, key } => Location::Gcs { bucket: bucket.clone(), key: join(key) }
+        };
+    }
+
+    /// Returns the URL of a remote location and the curl options that authenticate requests to it, by name and value.
+    fn request(&self) -> (String, Vec<(&'static str, String)>) {
+        return match self {
+            Location::Local(path) => (path.display().to_string(), Vec::new()),
+            Location::Http(url) => (url.clone(), Vec::new()),
+            Location::S3 { bucket, key } => {
+                let region = env::var("AWS_REGION").or_else(|_| env::var("AWS_DEFAULT_REGION")).unwrap_or("us-east-1".to_string());
+                let url = match env::var("BVP_S3_ENDPOINT") {
+                    Ok(endpoint) => format!("{}/{}/{}", endpoint.trim_end_matches('/'), bucket, key),
+                    Err(_) => format!("https://{}.s3.{}.amazonaws.com/{}", bucket, region, key)
+                };
+                let mut options = Vec::new();
+                if let (Ok(id), Ok(secret)) = (env::var("AWS_ACCESS_KEY_ID"), env::var("AWS_SECRET_ACCESS_KEY")) {
+                    options.push(("aws-sigv4", format!("aws:amz:{}:s3", region)));
+                    options.push(("user", format!("{}:{}", id, secret)));
+                    if let Ok(token) = env::var("AWS_SESSION_TOKEN") {
+                        options.push(("header", format!("x-amz-security-token: {}", token)));
+                    }
+                }
+                (url, options)
+            },
+            Location::Gcs { bucket, key } => {
+                let url = format!("https://storage.googleapis.com/{}/{}", bucket, key);
+                let options = match env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+                    Ok(token) => vec![("header", format!("Authorization: Bearer {}", token))],
+                    Err(_) => Vec::new()
+                };
+                (url, options)
+            }
+        };
+    }
+
+    /// Runs curl for a remote location and returns what it wrote to stdout.
+    /// * `options` - options of the request, besides the URL and authentication
+    /// * `upload` - data sent with the request, if any
+    fn curl(&self, options: &[&str], upload: Option<&[u8]>) -> Result<Vec<u8>, RemoteError> {
+        let (url, mut options_config) = self.request();
+        options_config.push(("url", url.clone()));
+        let mut config = String::new();
+        for (name, value) in &options_config {
+            config.push_str(&format!("{} = {}\n", name, quote(name, value)?));
+        }
+        // Standard input carries the config, so uploads are read from a file.
+        let upload = match upload {
+            Some(data) => Some(PrivateFile::new(data)?),
+            None => None
+        };
+        log_debug!("curl {} {}", options.join(" "), url);
+        let mut command = Command::new("curl");
+        command.args(["--silent", "--show-error", "--fail", "--location", "--config", "-"]).args(options);
+        if let Some(file) = &upload {
+            command.arg("--upload-file").arg(&file.path);
+        }
+        let child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn();
+        let result = child.and_then(|mut child| {
+            // The config is read before any request is made, and dropping stdin ends it.
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(config.as_bytes())?;
+            }
+            return child.wait_with_output();
+        });
+        drop(upload);
+        let output = result.map_err(RemoteError::CannotRunCurl)?;
+        if !output.status.success() {
+            return Err(RemoteError::RequestFailed(url, String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        return Ok(output.stdout);
+    }
+
+    /// Returns the size of the file in bytes.
+    pub fn size(&self) -> Result<u64, RemoteError> {
+        if let Location::Local(path) = self {
+            return fs::metadata(path).map(|m| m.len()).map_err(|e| RemoteError::CannotAccess(path.display().to_string(), e));
+        }
+        let headers = self.curl(&["--head"], None)?;
+        // With redirects there are several responses, the last one is of the file.
+        let length = String::from_utf8_lossy(&headers).lines()
+            .filter_map(|line| line.split_once(':'))
+            .filter(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+            .last()
+            .and_then(|(_, value)| value.trim().parse::<u64>().ok());
+        return length.ok_or_else(|| RemoteError::UnknownSize(self.request().0));
+    }
+
+    /// Reads bytes of the file.
+    /// * `offset` - first byte to read
+    /// * `length` - number of bytes to read
+    pub fn read_range(&self, offset: u64, length: u64) -> Result<Vec<u8>, RemoteError> {
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+        if let Location::Local(path) = self {
+            use std::io::{Read, Seek, SeekFrom};
+            let mut file = fs::File::open(path).map_err(|e| RemoteError::CannotAccess(path.display().to_string(), e))?;
+            let mut data = vec![0u8; length as usize];
+            file.seek(SeekFrom::Start(offset))
+                .and_then(|_| file.read_exact(&mut data))
+                .map_err(|e| RemoteError::CannotAccess(path.display().to_string(), e))?;
+            return Ok(data);
+        }
+        let range = format!("{}-{}", offset, offset + length - 1);
+        let data = self.curl(&["--range", &range], None)?;
+        // Servers that ignore ranges send the whole file.
+        if data.len() as u64 != length {
+            return Err(RemoteError::RangeNotSupported(self.request().0, range));
+        }
+        return Ok(data);
+    }
+
+    /// Reads the whole file. Large remote files are read in several range requests.
+    pub fn read(&self) -> Result<Vec<u8>, RemoteError> {
+        if let Location::Local(path) = self {
+            return fs::read(path).map_err(|e| RemoteError::CannotAccess(path.display().to_string(), e));
+        }
+        let size = match self.size() {
+            Ok(s) if s > RANGE_SIZE => s,
+            // Small files, and those whose size is not known, are read with one request.
+            _ => return self.curl(&[], None)
+        };
+        let mut data = Vec::with_capacity(size as usize);
+        let mut offset = 0;
+        while offset < size {
+            let length = RANGE_SIZE.min(size - offset);
+            data.extend(self.read_range(offset, length)?);
+            offset += length;
+        }
+        return Ok(data);
+    }
+
+    /// Writes the file, replacing it if it exists.
+    /// * `data` - contents of the file
+    pub fn write(&self, data: &[u8]) -> Result<(), RemoteError> {
+        if let Location::Local(path) = self {
+            return fs::write(path, data).map_err(|e| RemoteError::CannotAccess(path.display().to_string(), e));
+        }
+        // Uploads from a file are sent with their length, which S3 needs.
+        self.curl(&["--request", "PUT", "--header", "Content-Type: application/octet-stream"], Some(data))?;
+        return Ok(());
+    }
+}
+
+/// Quotes a value of a curl config. Values with control characters are rejected, as a line break
+/// would start another option.
+/// * `name` - name of the option, for the error
+/// * `value` - the value
+fn quote(name: &str, value: &str) -> Result<String, RemoteError> {
+    if value.chars().any(|c| c.is_control()) {
+        return Err(RemoteError::InvalidOption(name.to_string()));
+    }
+    return Ok(format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")));
+}
+
+/// A file in a new folder that only the user can access, removed with the folder when dropped.
+struct PrivateFile {
+    folder: PathBuf,
+    path: PathBuf
+}
+
+impl PrivateFile {
+    /// Creates the folder and writes the file.
+    /// * `data` - contents of the file
+    fn new(data: &[u8]) -> Result<Self, RemoteError> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        loop {
+            let folder = env::temp_dir().join(format!("bvp-curl-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+            let mut builder = fs::DirBuilder::new();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::DirBuilderExt;
+                builder.mode(0o700);
+            }
+            // Creating the folder fails if anything is already there, so nothing another user made is written through.
+            match builder.create(&folder) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(RemoteError::CannotAccess(folder.display().to_string(), e))
+            }
+            let file = Self { path: folder.join("upload"), folder };
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            options.open(&file.path)
+                .and_then(|mut f| f.write_all(data))
+                .map_err(|e| RemoteError::CannotAccess(file.path.display().to_string(), e))?;
+            return Ok(file);
+        }
+    }
+}
+
+impl Drop for PrivateFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.folder);
+    }
+}
+
+/// Reads a whole file from a local path or a URL.
+/// * `path` - the path or URL
+pub fn read(path: &str) -> Result<Vec<u8>, RemoteError> {
+    return Location::parse(path)?.read();
+}
+
+/// Writes a whole file to a local path or a URL.
+/// * `path` - the path or URL
+/// * `data` - contents of the file
+pub fn write(path: &str, data: &[u8]) -> Result<(), RemoteError> {
+    return Location::parse(path)?.write(data);
+}
+
+/// Returns true if a path is a URL that is read or written with this module.
+/// * `path` - the path or URL
+pub fn is_remote(path: &str) -> bool {
+    return Location::parse(path).map_or(true, |l| l.is_remote());
+}
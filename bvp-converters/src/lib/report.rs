@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tinyjson::JsonValue;
+
+use crate::checksum::{Checksum, ChecksumType};
+use crate::metrics::{MetricsSink, PipelineStage};
+use crate::progress::ProgressSink;
+use crate::version;
+
+/// Size and hash of an input or output file.
+#[derive(Clone, Debug)]
+pub struct FileDigest {
+    pub name: String,
+    pub size: u64,
+    /// `xxh3:` followed by the hash as 16 hex digits, as block checksums are written.
+    pub hash: String
+}
+
+impl FileDigest {
+    /// * `name` - the file
+    /// * `data` - its contents
+    pub fn new(name: &str, data: &[u8]) -> Self {
+        return Self {
+            name: name.to_string(),
+            size: data.len() as u64,
+            hash: Checksum::compute(ChecksumType::Xxh3, data).to_string()
+        };
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut hm = HashMap::new();
+        hm.insert("file".to_string(), self.name.clone().into());
+        hm.insert("size".to_string(), (self.size as f64).into());
+        hm.insert("hash".to_string(), self.hash.clone().into());
+        return hm.into();
+    }
+}
+
+/// What a conversion read, produced and how long it took, for provenance tracking.
+#[derive(Clone, Debug)]
+pub struct ConversionReport {
+    pub inputs: Vec<FileDigest>,
+    /// The output as given, a path, a URL or `-`.
+    pub output_file: String,
+    /// The written archive, or the manifest of unarchived assets. `None` if it cannot be read back.
+    pub output: Option<FileDigest>,
+    /// Number of blocks the volumes were split into, duplicates included.
+    pub blocks: usize,
+    pub duplicates: usize,
+    /// Size of the blocks that were stored, before and after encoding.
+    pub decoded_bytes: u64,
+    pub stored_bytes: u64,
+    /// Size of all written files, the manifest included.
+    pub bytes_written: u64,
+    /// Stages in the order they finished, with the time spent in them.
+    pub stages: Vec<(String, Duration)>,
+    pub wall_time: Duration,
+    /// The conversion settings, as given in the configuration file and flags.
+    pub settings: HashMap<String, JsonValue>
+}
+
+impl ConversionReport {
+    pub fn unique_blocks(&self) -> usize {
+        return self.blocks - self.duplicates;
+    }
+
+    /// Returns how many blocks there are for every stored block, 1 without duplicates.
+    pub fn dedup_ratio(&self) -> f64 {
+        if self.unique_blocks() == 0 {
+            return 1.0;
+        }
+        return self.blocks as f64 / self.unique_blocks() as f64;
+    }
+
+    /// Returns the size of the stored blocks before encoding divided by their size after.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.stored_bytes == 0 {
+            return 1.0;
+        }
+        return self.decoded_bytes as f64 / self.stored_bytes as f64;
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut blocks = HashMap::new();
+        blocks.insert("total".to_string(), (self.blocks as f64).into());
+        blocks.insert("unique".to_string(), (self.unique_blocks() as f64).into());
+        blocks.insert("duplicates".to_string(), (self.duplicates as f64).into());
+        blocks.insert("dedupRatio".to_string(), self.dedup_ratio().into());
+
+        let mut bytes = HashMap::new();
+        bytes.insert("decoded".to_string(), (self.decoded_bytes as f64).into());
+        bytes.insert("stored".to_string(), (self.stored_bytes as f64).into());
+        bytes.insert("written".to_string(), (self.bytes_written as f64).into());
+        bytes.insert("compressionRatio".to_string(), self.compression_ratio().into());
+
+        let mut timings = HashMap::new();
+        for (name, duration) in &self.stages {
+            timings.insert(name.clone(), duration.as_secs_f64().into());
+        }
+        timings.insert("total".to_string(), self.wall_time.as_secs_f64().into());
+
+        let mut output = HashMap::new();
+        output.insert("file".to_string(), self.output_file.clone().into());
+        if let Some(digest) = &self.output {
+            output.insert("size".to_string(), (digest.size as f64).into());
+            output.insert("hash".to_string(), digest.hash.clone().into());
+        }
+
+        let mut hm = HashMap::new();
+        hm.insert("creationTime".to_string(), version::timestamp_now().into());
+        hm.insert("inputs".to_string(), self.inputs.iter().map(|i| i.to_json()).collect::<Vec<JsonValue>>().into());
+        hm.insert("output".to_string(), output.into());
+        hm.insert("blocks".to_string(), blocks.into());
+        hm.insert("bytes".to_string(), bytes.into());
+        hm.insert("timings".to_string(), timings.into());
+        hm.insert("settings".to_string(), self.settings.clone().into());
+        return hm.into();
+    }
+}
+
+/// A progress sink that records the events a `ConversionReport` is made of,
+/// and passes all events on to another sink, such as a progress bar.
+pub struct ReportSink<'a> {
+    metrics: MetricsSink<'a>,
+    inputs: Mutex<Vec<FileDigest>>
+}
+
+impl<'a> ReportSink<'a> {
+    /// Starts measuring the wall time of the conversion.
+    /// * `inner` - the sink the events are passed on to
+    pub fn new(inner: &'a dyn ProgressSink) -> Self {
+        return Self {
+            metrics: MetricsSink::new(inner),
+            inputs: Mutex::new(Vec::new())
+        };
+    }
+
+    /// Returns the report of the conversion so far.
+    /// * `output_file` - the output, as given
+    /// * `output` - digest of the output, if it could be read back
+    /// * `settings` - the conversion settings
+    pub fn report(&self, output_file: &str, output: Option<FileDigest>, settings: HashMap<String, JsonValue>) -> ConversionReport {
+        let metrics = self.metrics.metrics();
+        return ConversionReport {
+            inputs: self.inputs.lock().map(|i| i.clone()).unwrap_or_default(),
+            output_file: output_file.to_string(),
+            output,
+            blocks: metrics.blocks,
+            duplicates: metrics.duplicates,
+            decoded_bytes: metrics.decoded_bytes,
+            stored_bytes: metrics.stored_bytes,
+            bytes_written: metrics.bytes_written,
+            stages: metrics.stages,
+            wall_time: metrics.wall_time,
+            settings
+        };
+    }
+}
+
+impl<'a> ProgressSink for ReportSink<'a> {
+    fn start(&self, total_blocks: usize) {
+        self.metrics.start(total_blocks);
+    }
+
+    fn input_read(&self, name: &str, data: &[u8]) {
+        if let Ok(mut inputs) = self.inputs.lock() {
+            inputs.push(FileDigest::new(name, data));
+        }
+        self.metrics.input_read(name, data);
+    }
+
+    fn block_processed(&self, duplicate: bool) {
+        self.metrics.block_processed(duplicate);
+    }
+
+    fn block_stored(&self, decoded_bytes: usize, stored_bytes: usize) {
+        self.metrics.block_stored(decoded_bytes, stored_bytes);
+    }
+
+    fn bytes_written(&self, bytes: usize) {
+        self.metrics.bytes_written(bytes);
+    }
+
+    fn stage_finished(&self, name: &str, duration: Duration) {
+        self.metrics.stage_finished(name, duration);
+    }
+
+    fn work_done(&self, stage: PipelineStage, duration: Duration) {
+        self.metrics.work_done(stage, duration);
+    }
+
+    fn finish(&self) {
+        self.metrics.finish();
+    }
+}
@@ -0,0 +1,202 @@
+//! Ed25519 signatures of manifests, stored in the `EXT_signature` payload of the asset:
+//!
+//! ```json
+//! "asset": {
+//!     "extensions": {
+//!         "EXT_signature": { "algorithm": "ed25519", "publicKey": "3d4017c3...", "signature": "92a009a9..." }
+//!     }
+//! }
+//! ```
+//!
+//! The signed message is the canonical JSON text of the manifest (see `json_aux::canonical_string`)
+//! without the signature payload, and without `EXT_signature` in `extensionsUsed`. The signature covers the
+//! block data through the checksums of the block files in the manifest, so every block with data has to have a
+//! SHA-256 checksum, both to be signed and to verify. xxh3 and CRC-32 checksums only detect damage, anyone can
+//! compute them for changed data.
+//! Keys and signatures are written as lowercase hexadecimal.
+
+use std::collections::HashMap;
+
+use tinyjson::JsonValue;
+
+use crate::{bvpfile::BVPFile, checksum::Checksum, ed25519::{self, SigningKey, KEY_LENGTH, SIGNATURE_LENGTH}, errors::{JsonError, SignatureError}, extensions::Extension, json_aux};
+
+/// The only signature algorithm, for now.
+pub const ALGORITHM: &str = "ed25519";
+
+/// Returns the lowercase hexadecimal text of bytes.
+/// * `bytes` - the bytes
+pub fn to_hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+/// Decodes hexadecimal text of a fixed number of bytes, or returns None if it is not one.
+/// Only the digits `0-9`, `a-f` and `A-F` are accepted, no signs.
+/// * `text` - the text, in upper or lowercase
+pub fn from_hex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    let text = text.trim();
+    if text.len() != 2 * N || !text.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16).ok()?;
+    }
+    return Some(bytes);
+}
+
+/// Reads a signing key, given by its seed of 32 bytes as 64 hexadecimal characters, for example
+/// one made with `openssl rand -hex 32`.
+/// * `text` - contents of the key file
+pub fn signing_key_from_hex(text: &str) -> Result<SigningKey, SignatureError> {
+    return match from_hex::<KEY_LENGTH>(text) {
+        Some(seed) => Ok(SigningKey::from_seed(seed)),
+        None => Err(SignatureError::InvalidKey("a signing key has to be 64 hexadecimal characters".to_string()))
+    };
+}
+
+/// Returns the message that is signed for a manifest.
+/// * `manifest` - the root object of the manifest
+pub fn signed_message(manifest: &HashMap<String, JsonValue>) -> Vec<u8> {
+    let name = Extension::ExtSignature.to_string();
+    let mut manifest = manifest.clone();
+    if let Some(JsonValue::Object(asset)) = manifest.get_mut("asset") {
+        if let Some(JsonValue::Object(extensions)) = asset.get_mut("extensions") {
+            extensions.remove(&name);
+            if extensions.is_empty() {
+                asset.remove("extensions");
+            }
+        }
+        for key in ["extensionsUsed", "extensionsRequired"] {
+            if let Some(JsonValue::Array(list)) = asset.get_mut(key) {
+                list.retain(|e| e.get::<String>() != Some(&name));
+                if list.is_empty() {
+                    asset.remove(key);
+                }
+            }
+        }
+    }
+    return json_aux::canonical_string(&JsonValue::from(manifest)).into_bytes();
+}
+
+/// Checks that every block with data has a cryptographic checksum, which the signature covers it with.
+/// * `manifest` - the root object of the manifest
+pub fn check_block_checksums(manifest: &HashMap<String, JsonValue>) -> Result<(), SignatureError> {
+    let blocks = match manifest.get("blocks") {
+        Some(JsonValue::Array(a)) => a,
+        _ => return Ok(())
+    };
+    for (i, block) in blocks.iter().enumerate() {
+        let block = match block {
+            JsonValue::Object(o) => o,
+            _ => continue
+        };
+        if !block.contains_key("data") {
+            continue;
+        }
+        let checksum = block.get("checksum")
+            .and_then(|c| c.get::<String>())
+            .and_then(|c| Checksum::from_string(c).ok());
+        if !checksum.is_some_and(|c| c.algorithm.is_cryptographic()) {
+            return Err(SignatureError::UnsignedData(i));
+        }
+    }
+    return Ok(());
+}
+
+/// Returns the signature payload of a manifest.
+/// * `manifest` - the root object of the manifest
+/// * `key` - the signing key
+fn signature_payload(manifest: &HashMap<String, JsonValue>, key: &SigningKey) -> JsonValue {
+    let signature = key.sign(&signed_message(manifest));
+    let mut hm = HashMap::new();
+    hm.insert("algorithm".to_string(), ALGORITHM.to_string().into());
+    hm.insert("publicKey".to_string(), to_hex(&key.public_key()).into());
+    hm.insert("signature".to_string(), to_hex(&signature).into());
+    return hm.into();
+}
+
+/// Signs an asset, replacing a signature it has. Block files have to be final,
+/// since the signature covers their checksums, which have to be SHA-256.
+/// * `bvp_file` - the asset
+/// * `key` - the signing key
+pub fn sign(bvp_file: &mut BVPFile, key: &SigningKey) -> Result<(), SignatureError> {
+    let name = Extension::ExtSignature.to_string();
+    bvp_file.asset.extension_payloads.remove(&name);
+    let text = bvp_file.to_manifest().map_err(|e| SignatureError::InvalidManifest(e.to_string()))?;
+    let manifest = match String::from_utf8(text).ok().and_then(|t| t.parse::<JsonValue>().ok()) {
+        Some(JsonValue::Object(o)) => o,
+        _ => return Err(SignatureError::InvalidManifest("it is not a JSON object".to_string()))
+    };
+    check_block_checksums(&manifest)?;
+    bvp_file.asset.extension_payloads.insert(name, signature_payload(&manifest, key));
+    return Ok(());
+}
+
+/// Signs a manifest given as JSON, replacing a signature it has, and declares the extension.
+/// * `manifest` - the root object of the manifest
+/// * `key` - the signing key
+pub fn sign_manifest(manifest: &mut HashMap<String, JsonValue>, key: &SigningKey) -> Result<(), SignatureError> {
+    check_block_checksums(manifest)?;
+    let name = Extension::ExtSignature.to_string();
+    let payload = signature_payload(manifest, key);
+    let asset = match manifest.get_mut("asset") {
+        Some(JsonValue::Object(o)) => o,
+        _ => return Err(SignatureError::InvalidManifest("it has no asset object".to_string()))
+    };
+    let extensions = asset.entry("extensions".to_string()).or_insert_with(|| HashMap::<String, JsonValue>::new().into());
+    match extensions {
+        JsonValue::Object(o) => o.insert(name.clone(), payload),
+        _ => return Err(SignatureError::InvalidManifest("`asset.extensions` is not an object".to_string()))
+    };
+    let used = asset.entry("extensionsUsed".to_string()).or_insert_with(|| Vec::<JsonValue>::new().into());
+    match used {
+        JsonValue::Array(a) if !a.iter().any(|e| e.get::<String>() == Some(&name)) => a.push(name.into()),
+        JsonValue::Array(_) => {},
+        _ => return Err(SignatureError::InvalidManifest("`asset.extensionsUsed` is not an array".to_string()))
+    }
+    return Ok(());
+}
+
+/// Checks the signature of a manifest and that it covers the data of every block, and returns the public key
+/// it was made with. Whether the key belongs to whom the asset claims to come from has to be checked by the caller,
+/// and whether the block files match their checksums too, for example with `bvp::validate`.
+/// * `manifest` - the root object of the manifest
+pub fn verify_manifest(manifest: &HashMap<String, JsonValue>) -> Result<[u8; KEY_LENGTH], SignatureError> {
+    let public_key = verify_signature(manifest)?;
+    check_block_checksums(manifest)?;
+    return Ok(public_key);
+}
+
+/// Checks only the signature of a manifest, not whether it covers the blocks, and returns the public key it was made with.
+/// * `manifest` - the root object of the manifest
+pub fn verify_signature(manifest: &HashMap<String, JsonValue>) -> Result<[u8; KEY_LENGTH], SignatureError> {
+    let payload = manifest.get("asset")
+        .and_then(|a| a.get::<HashMap<String, JsonValue>>())
+        .and_then(|a| a.get("extensions"))
+        .and_then(|e| e.get::<HashMap<String, JsonValue>>())
+        .and_then(|e| e.get(&Extension::ExtSignature.to_string()));
+    let payload = match payload {
+        Some(JsonValue::Object(o)) => o,
+        Some(j) => return Err(SignatureError::InvalidJson(JsonError::NotAnObject(j.clone()))),
+        None => return Err(SignatureError::Missing)
+    };
+    let field = |key: &str| {
+        return match payload.get(key) {
+            Some(v) => json_aux::get_string_from_json(v).map_err(SignatureError::InvalidJson),
+            None => Err(SignatureError::Invalid(format!("it has no `{}`", key)))
+        };
+    };
+    let algorithm = field("algorithm")?;
+    if algorithm != ALGORITHM {
+        return Err(SignatureError::UnsupportedAlgorithm(algorithm));
+    }
+    let public_key = from_hex::<KEY_LENGTH>(&field("publicKey")?)
+        .ok_or(SignatureError::Invalid("`publicKey` has to be 64 hexadecimal characters".to_string()))?;
+    let signature = from_hex::<SIGNATURE_LENGTH>(&field("signature")?)
+        .ok_or(SignatureError::Invalid("`signature` has to be 128 hexadecimal characters".to_string()))?;
+    if !ed25519::verify(&public_key, &signed_message(manifest), &signature) {
+        return Err(SignatureError::Mismatch);
+    }
+    return Ok(public_key);
+}
@@ -0,0 +1,56 @@
+//! Provenance of converted assets. `raw2bvp` can record the raw file the top level volume was read from in
+//! the `EXT_source` payload of the asset,
+//!
+//! ```json
+//! "extensions": {
+//!     "EXT_source": { "sourceFile": "head.raw", "sourceSize": 16777216, "sourceHash": "xxh3:9f2c1e0b7a4d3c21" }
+//! }
+//! ```
+//!
+//! with the size and hash of its contents as they were read, after decompression, as in conversion reports.
+//! The file is named without its folder. A conversion of the same file again can be skipped when the size
+//! and hash of an existing output match it. The extension is optional, as the data is readable without it.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use tinyjson::JsonValue;
+
+use crate::{asset::Asset, errors::{JsonError, SourceError}, extensions::Extension, json_aux, report::FileDigest};
+
+/// Returns the source file recorded in an asset, or None if it has none.
+/// * `asset` - the asset
+pub fn source(asset: &Asset) -> Result<Option<FileDigest>, SourceError> {
+    let payload = match asset.extension_payloads.get(&Extension::ExtSource.to_string()) {
+        Some(JsonValue::Object(o)) => o,
+        Some(j) => return Err(SourceError::InvalidJson(JsonError::NotAnObject(j.clone()))),
+        None => return Ok(None)
+    };
+    let get = |key: &str| payload.get(key).ok_or_else(|| SourceError::MissingKey(key.to_string()));
+    let name = json_aux::get_string_from_json(get("sourceFile")?).map_err(SourceError::InvalidJson)?;
+    let size = json_aux::get_u64_from_json(get("sourceSize")?).map_err(SourceError::InvalidJson)?;
+    let hash = json_aux::get_string_from_json(get("sourceHash")?).map_err(SourceError::InvalidJson)?;
+    return Ok(Some(FileDigest { name, size, hash }));
+}
+
+/// Records the source file of an asset.
+/// * `asset` - the asset
+/// * `source` - size and hash of the file, whose name is recorded without its folder
+pub fn set_source(asset: &mut Asset, source: &FileDigest) {
+    let name = Path::new(&source.name).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| source.name.clone());
+    let mut payload = HashMap::new();
+    payload.insert("sourceFile".to_string(), JsonValue::from(name));
+    payload.insert("sourceSize".to_string(), JsonValue::from(source.size as f64));
+    payload.insert("sourceHash".to_string(), JsonValue::from(source.hash.clone()));
+    asset.extension_payloads.insert(Extension::ExtSource.to_string(), payload.into());
+}
+
+/// Returns true if a file has the contents recorded as the source of an asset. Names are not compared.
+/// * `recorded` - the recorded source
+/// * `data` - contents of the file, decompressed
+pub fn is_same_source(recorded: &FileDigest, data: &[u8]) -> bool {
+    if recorded.size != data.len() as u64 {
+        return false;
+    }
+    return FileDigest::new(&recorded.name, data).hash == recorded.hash;
+}
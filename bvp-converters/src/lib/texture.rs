@@ -0,0 +1,155 @@
+use std::fmt;
+
+use crate::extensions::Extension;
+use crate::formats::{CompressedFormat, Format, FormatFamily};
+use crate::vector3::Vector3;
+
+/// Dimensions of the voxels encoded together in a BC4 block.
+pub const BC4_TILE_DIMENSIONS: Vector3<u32> = Vector3 { x: 4, y: 4, z: 1 };
+/// Size of an encoded BC4 block in bytes.
+pub const BC4_BLOCK_SIZE: u32 = 8;
+
+/// GPU texture compression schemes voxel data can be encoded in, so that renderers
+/// can upload blocks without transcoding them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureCompression {
+    /// Single channel 8-bit data in tiles of 4x4 voxels (`BC4_UNORM`), 2:1 compared to `u8`.
+    Bc4
+}
+
+impl TextureCompression {
+    pub fn from_string(s: &str) -> Option<Self> {
+        return match s {
+            "bc4" | "BC4" => Some(Self::Bc4),
+            _ => None
+        }
+    }
+
+    /// Returns the block-compressed format of encoded data.
+    pub fn format(&self) -> Format {
+        return match self {
+            TextureCompression::Bc4 => Format::new(
+                BC4_TILE_DIMENSIONS,
+                BC4_BLOCK_SIZE,
+                FormatFamily::Compressed(CompressedFormat::new("bc4")),
+                vec![Extension::ExtFormatCompressed]
+            )
+        }
+    }
+
+    /// Encodes a volume of voxels and returns the encoded tiles, with X changing fastest,
+    /// as they are stored in blocks of the format. The dimensions have to be multiples of the tile dimensions.
+    /// * `data` - voxels of the volume, one byte each, with X changing fastest
+    /// * `dimensions` - dimensions of the volume
+    pub fn encode(&self, data: &[u8], dimensions: Vector3<u32>) -> Vec<u8> {
+        return match self {
+            TextureCompression::Bc4 => encode_bc4_volume(data, dimensions)
+        }
+    }
+}
+
+impl fmt::Display for TextureCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TextureCompression::Bc4 => "bc4"
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+/// Returns the 8 values a pair of BC4 endpoints encodes.
+/// * `red0`, `red1` - the endpoints
+fn bc4_palette(red0: u8, red1: u8) -> [u8; 8] {
+    let (r0, r1) = (red0 as u32, red1 as u32);
+    let mut palette = [red0, red1, 0, 0, 0, 0, 0, 255];
+    if red0 > red1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u32) * r0 + i as u32 * r1 + 3) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u32) * r0 + i as u32 * r1 + 2) / 5) as u8;
+        }
+        palette[6] = 0;
+    }
+    return palette;
+}
+
+/// Encodes 16 values with the given endpoints and returns the block and its squared error.
+/// * `values` - the values of a tile, row by row
+/// * `red0`, `red1` - the endpoints, which also select the mode of the block
+fn encode_bc4_with_endpoints(values: &[u8; 16], red0: u8, red1: u8) -> ([u8; 8], u32) {
+    let palette = bc4_palette(red0, red1);
+    let mut indices: u64 = 0;
+    let mut error = 0;
+    for (i, value) in values.iter().enumerate() {
+        let (index, distance) = palette.iter().enumerate()
+            .map(|(j, p)| (j, (*p as i32 - *value as i32).unsigned_abs()))
+            .min_by_key(|(_, distance)| *distance)
+            .unwrap();
+        indices |= (index as u64) << (3 * i);
+        error += distance * distance;
+    }
+    let mut block = [0u8; 8];
+    block[0] = red0;
+    block[1] = red1;
+    block[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+    return (block, error);
+}
+
+/// Encodes a tile of 4x4 values as a BC4 block. Both modes are tried: eight values
+/// between the extremes, or six values between the extremes other than 0 and 255,
+/// which represent those two exactly. The one with the smaller error is kept.
+/// * `values` - the values of the tile, row by row
+pub fn encode_bc4_block(values: &[u8; 16]) -> [u8; 8] {
+    let low = *values.iter().min().unwrap();
+    let high = *values.iter().max().unwrap();
+    if low == high {
+        return encode_bc4_with_endpoints(values, low, high).0;
+    }
+    let eight = encode_bc4_with_endpoints(values, high, low);
+
+    let inner = values.iter().filter(|v| **v != 0 && **v != 255);
+    let inner_low = inner.clone().min().copied().unwrap_or(low);
+    let inner_high = inner.max().copied().unwrap_or(high);
+    let six = encode_bc4_with_endpoints(values, inner_low, inner_high);
+    return if six.1 < eight.1 { six.0 } else { eight.0 };
+}
+
+/// Decodes a BC4 block and returns its 16 values, row by row.
+/// * `block` - the encoded block
+pub fn decode_bc4_block(block: &[u8; 8]) -> [u8; 16] {
+    let palette = bc4_palette(block[0], block[1]);
+    let mut index_bytes = [0u8; 8];
+    index_bytes[..6].copy_from_slice(&block[2..]);
+    let indices = u64::from_le_bytes(index_bytes);
+    let mut values = [0u8; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[((indices >> (3 * i)) & 7) as usize];
+    }
+    return values;
+}
+
+/// Encodes a volume tile by tile, see `TextureCompression::encode`.
+/// * `data` - voxels of the volume, one byte each
+/// * `dimensions` - dimensions of the volume, multiples of 4 along X and Y
+fn encode_bc4_volume(data: &[u8], dimensions: Vector3<u32>) -> Vec<u8> {
+    let (width, height) = (dimensions.x as usize, dimensions.y as usize);
+    let tile_count = dimensions / BC4_TILE_DIMENSIONS;
+    // Every tile of 16 voxels is encoded into 8 bytes, so the tiles take half the size of the data.
+    let mut encoded = Vec::with_capacity(data.len() / 2);
+    let mut tile = [0u8; 16];
+    for z in 0..dimensions.z as usize {
+        let slice = &data[z * width * height..(z + 1) * width * height];
+        for tile_y in 0..tile_count.y as usize {
+            for tile_x in 0..tile_count.x as usize {
+                for row in 0..4 {
+                    let start = (tile_y * 4 + row) * width + tile_x * 4;
+                    tile[row * 4..row * 4 + 4].copy_from_slice(&slice[start..start + 4]);
+                }
+                encoded.extend_from_slice(&encode_bc4_block(&tile));
+            }
+        }
+    }
+    return encoded;
+}
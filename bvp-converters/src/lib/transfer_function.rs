@@ -0,0 +1,293 @@
+//! Transfer functions shipped with an asset, so that viewers can render a modality with sensible default settings.
+//!
+//! They are stored in the `EXT_transfer_function` payload of a modality, as a list whose first entry is the default:
+//!
+//! ```json
+//! "extensions": {
+//!     "EXT_transfer_function": {
+//!         "transferFunctions": [
+//!             { "name": "bone", "domain": [[0, 1]], "points": [{ "position": [0.3], "color": [1, 1, 1, 0] }, { "position": [1], "color": [1, 1, 0.9, 1] }] },
+//!             { "name": "soft tissue", "domain": [[0, 1], [0, 1]], "lut": { "file": "transfer_functions/soft.rgba", "dimensions": [256, 64] } }
+//!         ]
+//!     }
+//! }
+//! ```
+//!
+//! A transfer function maps the voxel value, and for 2D transfer functions also the gradient magnitude,
+//! to a color with opacity. It is given either by control points, between which the color is interpolated,
+//! or by a lookup table of 8 bit RGBA texels in a file of the asset, row by row.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tinyjson::JsonValue;
+
+use crate::{bvpfile::BVPFile, errors::{JsonError, TransferFunctionError}, extensions::Extension, file::File, json_aux, modality::Modality};
+
+/// A color of a transfer function at a position in its domain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ControlPoint {
+    /// The voxel value, and for 2D transfer functions the gradient magnitude.
+    pub position: Vec<f32>,
+    /// Red, green, blue and opacity, between 0 and 1.
+    pub color: [f32; 4]
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransferFunctionData {
+    /// Control points, sorted by position for 1D transfer functions.
+    ControlPoints(Vec<ControlPoint>),
+    /// A lookup table of `width` by `height` RGBA texels in a file of the asset.
+    /// The X axis is the voxel value, the Y axis the gradient magnitude, 1D lookup tables are one texel high.
+    Lut { file: String, width: u32, height: u32 }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransferFunction {
+    pub name: Option<String>,
+    /// Ranges of the voxel value and gradient magnitude the transfer function spans.
+    /// Without a domain, it spans the values of the format, normalized to 0 to 1.
+    pub domain: Option<Vec<[f32; 2]>>,
+    pub data: TransferFunctionData
+}
+
+impl TransferFunctionData {
+    /// Returns 1 for transfer functions of the voxel value only, and 2 for those of the value and gradient magnitude.
+    pub fn dimensions(&self) -> usize {
+        return match self {
+            TransferFunctionData::ControlPoints(points) => points.first().map_or(1, |p| p.position.len()),
+            TransferFunctionData::Lut { height, .. } => if *height > 1 { 2 } else { 1 }
+        };
+    }
+}
+
+impl TransferFunction {
+    pub fn dimensions(&self) -> usize {
+        return self.data.dimensions();
+    }
+
+    /// Returns the texels of a lookup table, or None for transfer functions given by control points.
+    /// * `files` - files of the asset, for example `BVPFile.files`
+    pub fn lut_data<'a>(&self, files: &'a [File]) -> Result<Option<&'a [u8]>, TransferFunctionError> {
+        let (file, width, height) = match &self.data {
+            TransferFunctionData::Lut { file, width, height } => (file, *width, *height),
+            TransferFunctionData::ControlPoints(_) => return Ok(None)
+        };
+        let data = match files.iter().find(|f| &f.name == file) {
+            Some(f) => f.data.as_slice(),
+            None => return Err(TransferFunctionError::MissingLutFile(file.clone()))
+        };
+        let expected = width as usize * height as usize * 4;
+        if data.len() != expected {
+            return Err(TransferFunctionError::LutSizeMismatch(file.clone(), data.len(), expected));
+        }
+        return Ok(Some(data));
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut hm = HashMap::new();
+        if let Some(name) = &self.name {
+            hm.insert("name".to_string(), name.clone().into());
+        }
+        if let Some(domain) = &self.domain {
+            let ranges: Vec<JsonValue> = domain.iter()
+                .map(|[min, max]| vec![JsonValue::from(*min as f64), JsonValue::from(*max as f64)].into())
+                .collect();
+            hm.insert("domain".to_string(), ranges.into());
+        }
+        match &self.data {
+            TransferFunctionData::ControlPoints(points) => {
+                let points: Vec<JsonValue> = points.iter().map(|point| {
+                    let mut p = HashMap::new();
+                    p.insert("position".to_string(), floats_to_json(&point.position));
+                    p.insert("color".to_string(), floats_to_json(&point.color));
+                    return p.into();
+                }).collect();
+                hm.insert("points".to_string(), points.into());
+            },
+            TransferFunctionData::Lut { file, width, height } => {
+                let mut lut = HashMap::new();
+                lut.insert("file".to_string(), file.clone().into());
+                lut.insert("dimensions".to_string(), vec![JsonValue::from(*width as f64), JsonValue::from(*height as f64)].into());
+                hm.insert("lut".to_string(), lut.into());
+            }
+        }
+        return hm.into();
+    }
+
+    /// * `index` - position of the transfer function in the list, for errors
+    /// * `j` - the transfer function
+    pub fn from_json(index: usize, j: &JsonValue) -> Result<Self, TransferFunctionError> {
+        let hashmap = match j {
+            JsonValue::Object(o) => o,
+            _ => return Err(TransferFunctionError::InvalidJson(index, JsonError::NotAnObject(j.clone())))
+        };
+        let name = match hashmap.get("name") {
+            Some(n) => Some(json_aux::get_string_from_json(n).map_err(|e| TransferFunctionError::InvalidJson(index, e))?),
+            None => None
+        };
+
+        let data = if let Some(points) = hashmap.get("points") {
+            let points = json_aux::get_array_from_json(points).map_err(|e| TransferFunctionError::InvalidJson(index, e))?;
+            let mut control_points = Vec::new();
+            for point in &points {
+                let point = match point {
+                    JsonValue::Object(o) => o,
+                    _ => return Err(TransferFunctionError::InvalidJson(index, JsonError::NotAnObject(point.clone())))
+                };
+                let position = floats_from_json(index, point.get("position"), "points.position")?;
+                let color = floats_from_json(index, point.get("color"), "points.color")?;
+                let color: [f32; 4] = color.try_into().map_err(|_| TransferFunctionError::InvalidColor(index))?;
+                control_points.push(ControlPoint { position, color });
+            }
+            TransferFunctionData::ControlPoints(control_points)
+        } else if let Some(lut) = hashmap.get("lut") {
+            let lut = match lut {
+                JsonValue::Object(o) => o,
+                _ => return Err(TransferFunctionError::InvalidJson(index, JsonError::NotAnObject(lut.clone())))
+            };
+            let file = match lut.get("file") {
+                Some(f) => json_aux::get_string_from_json(f).map_err(|e| TransferFunctionError::InvalidJson(index, e))?,
+                None => return Err(TransferFunctionError::MissingField(index, "lut.file".to_string()))
+            };
+            let dimensions = match lut.get("dimensions") {
+                Some(d) => json_aux::get_array_from_json(d).map_err(|e| TransferFunctionError::InvalidJson(index, e))?,
+                None => return Err(TransferFunctionError::MissingField(index, "lut.dimensions".to_string()))
+            };
+            let dimensions = dimensions.iter().map(json_aux::get_u32_from_json).collect::<Result<Vec<u32>, JsonError>>()
+                .map_err(|e| TransferFunctionError::InvalidJson(index, e))?;
+            let (width, height) = match dimensions[..] {
+                [width] => (width, 1),
+                [width, height] => (width, height),
+                _ => return Err(TransferFunctionError::InvalidJson(index, JsonError::NotAnArray(hashmap["lut"].clone())))
+            };
+            TransferFunctionData::Lut { file, width, height }
+        } else {
+            return Err(TransferFunctionError::Empty(index));
+        };
+
+        let domain = match hashmap.get("domain") {
+            Some(d) => {
+                let ranges = json_aux::get_array_from_json(d).map_err(|e| TransferFunctionError::InvalidJson(index, e))?;
+                let mut domain = Vec::new();
+                for range in &ranges {
+                    let range = floats_from_json(index, Some(range), "domain")?;
+                    let range: [f32; 2] = range.try_into().map_err(|_| TransferFunctionError::InvalidDomain(index, data.dimensions()))?;
+                    domain.push(range);
+                }
+                Some(domain)
+            },
+            None => None
+        };
+
+        let transfer_function = Self { name, domain, data };
+        transfer_function.check(index)?;
+        return Ok(transfer_function);
+    }
+
+    /// Checks that the control points, colors and domain agree with the dimensions of the transfer function.
+    /// * `index` - position of the transfer function in the list, for errors
+    fn check(&self, index: usize) -> Result<(), TransferFunctionError> {
+        let dimensions = self.dimensions();
+        match &self.data {
+            TransferFunctionData::ControlPoints(points) => {
+                if points.is_empty() {
+                    return Err(TransferFunctionError::Empty(index));
+                }
+                if dimensions == 0 || dimensions > 2 || points.iter().any(|p| p.position.len() != dimensions) {
+                    return Err(TransferFunctionError::InvalidPoints(index));
+                }
+                if points.iter().any(|p| p.color.iter().any(|c| !(0.0..=1.0).contains(c))) {
+                    return Err(TransferFunctionError::InvalidColor(index));
+                }
+            },
+            TransferFunctionData::Lut { width, height, .. } => {
+                if *width == 0 || *height == 0 {
+                    return Err(TransferFunctionError::Empty(index));
+                }
+            }
+        }
+        if let Some(domain) = &self.domain {
+            if domain.len() != dimensions || domain.iter().any(|[min, max]| min >= max) {
+                return Err(TransferFunctionError::InvalidDomain(index, dimensions));
+            }
+        }
+        return Ok(());
+    }
+}
+
+fn floats_to_json(values: &[f32]) -> JsonValue {
+    return values.iter().map(|v| JsonValue::from(*v as f64)).collect::<Vec<JsonValue>>().into();
+}
+
+fn floats_from_json(index: usize, j: Option<&JsonValue>, field: &str) -> Result<Vec<f32>, TransferFunctionError> {
+    let values = match j {
+        Some(v) => json_aux::get_array_from_json(v).map_err(|e| TransferFunctionError::InvalidJson(index, e))?,
+        None => return Err(TransferFunctionError::MissingField(index, field.to_string()))
+    };
+    return values.iter().map(json_aux::get_f32_from_json).collect::<Result<Vec<f32>, JsonError>>()
+        .map_err(|e| TransferFunctionError::InvalidJson(index, e));
+}
+
+/// Returns the transfer functions of a modality, the default one first. Modalities without them return an empty list.
+/// * `modality` - the modality
+pub fn transfer_functions(modality: &Modality) -> Result<Vec<TransferFunction>, TransferFunctionError> {
+    return from_payload(modality.extension_payloads.get(&Extension::ExtTransferFunction.to_string()));
+}
+
+/// Returns the transfer functions of an `EXT_transfer_function` payload.
+/// * `payload` - the payload, if the modality has one
+pub fn from_payload(payload: Option<&JsonValue>) -> Result<Vec<TransferFunction>, TransferFunctionError> {
+    let payload = match payload {
+        Some(JsonValue::Object(o)) => o,
+        Some(j) => return Err(TransferFunctionError::InvalidJson(0, JsonError::NotAnObject(j.clone()))),
+        None => return Ok(Vec::new())
+    };
+    let list = match payload.get("transferFunctions") {
+        Some(l) => json_aux::get_array_from_json(l).map_err(|e| TransferFunctionError::InvalidJson(0, e))?,
+        None => return Ok(Vec::new())
+    };
+    return list.iter().enumerate().map(|(index, j)| TransferFunction::from_json(index, j)).collect();
+}
+
+/// Sets the transfer functions of a modality, replacing those it has. An empty list removes them.
+/// Lookup tables have to be added to the files of the asset as well, see `add_lut`.
+/// * `modality` - the modality
+/// * `transfer_functions` - the transfer functions, the default one first
+pub fn set_transfer_functions(modality: &mut Modality, transfer_functions: &[TransferFunction]) -> Result<(), TransferFunctionError> {
+    let name = Extension::ExtTransferFunction.to_string();
+    if transfer_functions.is_empty() {
+        modality.extension_payloads.remove(&name);
+        return Ok(());
+    }
+    for (index, transfer_function) in transfer_functions.iter().enumerate() {
+        transfer_function.check(index)?;
+    }
+    let list: Vec<JsonValue> = transfer_functions.iter().map(|t| t.to_json()).collect();
+    let mut payload = HashMap::new();
+    payload.insert("transferFunctions".to_string(), JsonValue::from(list));
+    modality.extension_payloads.insert(name, payload.into());
+    return Ok(());
+}
+
+/// Adds a lookup table to the files of an asset, replacing a file of the same name,
+/// and returns a transfer function that uses it.
+/// * `bvp_file` - the asset
+/// * `file` - name of the file in the asset, for example `transfer_functions/default.rgba`
+/// * `width`, `height` - texels along the voxel value and the gradient magnitude, `height` is 1 for 1D lookup tables
+/// * `texels` - 8 bit RGBA texels, row by row
+pub fn add_lut(bvp_file: &mut BVPFile, file: &str, width: u32, height: u32, texels: Vec<u8>) -> Result<TransferFunction, TransferFunctionError> {
+    let expected = width as usize * height as usize * 4;
+    if texels.len() != expected {
+        return Err(TransferFunctionError::LutSizeMismatch(file.to_string(), texels.len(), expected));
+    }
+    let transfer_function = TransferFunction {
+        name: None,
+        domain: None,
+        data: TransferFunctionData::Lut { file: file.to_string(), width, height }
+    };
+    transfer_function.check(0)?;
+    bvp_file.files.retain(|f| f.name != file);
+    bvp_file.files.push(File::new(file.to_string(), Arc::new(texels), Some("application/octet-stream".to_string())));
+    return Ok(transfer_function);
+}
@@ -0,0 +1,205 @@
+//! Orientation of a modality in patient or world space, stored in the `EXT_transform` payload of the modality.
+//!
+//! The transform is a 4x4 matrix of rows that maps voxel coordinates (X, Y, Z indices, the origin at the
+//! center of the first voxel) to positions in millimetres. It combines the voxel spacing, the direction
+//! cosines of the axes and the position of the first voxel, as given by DICOM and NIfTI headers:
+//!
+//! ```json
+//! "extensions": {
+//!     "EXT_transform": {
+//!         "matrix": [[0.5, 0, 0, -120], [0, 0.5, 0, -95.5], [0, 0, 2, 30], [0, 0, 0, 1]]
+//!     }
+//! }
+//! ```
+
+use std::collections::HashMap;
+
+use tinyjson::JsonValue;
+
+use crate::{errors::{JsonError, TransformError}, extensions::Extension, json_aux, modality::Modality};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    /// Rows of the matrix, the last one is `[0, 0, 0, 1]`.
+    pub matrix: [[f64; 4]; 4]
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        matrix: [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]]
+    };
+
+    /// Returns the transform of a volume given like in DICOM and NIfTI headers.
+    /// * `origin` - position of the center of the first voxel
+    /// * `direction` - direction cosines of the X, Y and Z axes, each a unit vector
+    /// * `spacing` - distance between voxel centers along the X, Y and Z axes
+    pub fn from_origin_direction(origin: [f64; 3], direction: [[f64; 3]; 3], spacing: [f64; 3]) -> Self {
+        let mut matrix = Self::IDENTITY.matrix;
+        for row in 0..3 {
+            for axis in 0..3 {
+                matrix[row][axis] = direction[axis][row] * spacing[axis];
+            }
+            matrix[row][3] = origin[row];
+        }
+        return Self { matrix };
+    }
+
+    /// Returns the position of the center of the first voxel.
+    pub fn origin(&self) -> [f64; 3] {
+        return [self.matrix[0][3], self.matrix[1][3], self.matrix[2][3]];
+    }
+
+    /// Returns the step between voxels along the X, Y and Z axes, the directions scaled by the spacing.
+    pub fn axes(&self) -> [[f64; 3]; 3] {
+        let mut axes = [[0.0; 3]; 3];
+        for (axis, vector) in axes.iter_mut().enumerate() {
+            for row in 0..3 {
+                vector[row] = self.matrix[row][axis];
+            }
+        }
+        return axes;
+    }
+
+    /// Returns the distance between voxel centers along the X, Y and Z axes.
+    pub fn spacing(&self) -> [f64; 3] {
+        return self.axes().map(|v| (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt());
+    }
+
+    /// Returns the direction cosines of the X, Y and Z axes.
+    pub fn direction(&self) -> [[f64; 3]; 3] {
+        let spacing = self.spacing();
+        let mut axes = self.axes();
+        for (vector, length) in axes.iter_mut().zip(spacing) {
+            if length > 0.0 {
+                *vector = vector.map(|c| c / length);
+            }
+        }
+        return axes;
+    }
+
+    /// Returns the position of a voxel.
+    /// * `voxel` - coordinates of the voxel, which can be fractional
+    pub fn apply(&self, voxel: [f64; 3]) -> [f64; 3] {
+        let mut position = [0.0; 3];
+        for (row, p) in position.iter_mut().enumerate() {
+            *p = self.matrix[row][0] * voxel[0] + self.matrix[row][1] * voxel[1] + self.matrix[row][2] * voxel[2] + self.matrix[row][3];
+        }
+        return position;
+    }
+
+    /// Returns the transform of a region of the volume, whose first voxel is at `start`.
+    /// * `start` - coordinates of the first voxel of the region
+    pub fn translated(&self, start: [f64; 3]) -> Self {
+        let mut matrix = self.matrix;
+        let origin = self.apply(start);
+        for row in 0..3 {
+            matrix[row][3] = origin[row];
+        }
+        return Self { matrix };
+    }
+
+    /// Returns an error if the matrix is not an affine transform that keeps volumes from collapsing.
+    fn check(&self) -> Result<(), TransformError> {
+        if self.matrix.iter().flatten().any(|v| !v.is_finite()) {
+            return Err(TransformError::InvalidMatrix("all values have to be finite".to_string()));
+        }
+        if self.matrix[3] != [0.0, 0.0, 0.0, 1.0] {
+            return Err(TransformError::InvalidMatrix("the last row has to be [0, 0, 0, 1]".to_string()));
+        }
+        let [a, b, c] = self.axes();
+        let determinant = a[0] * (b[1] * c[2] - b[2] * c[1]) - a[1] * (b[0] * c[2] - b[2] * c[0]) + a[2] * (b[0] * c[1] - b[1] * c[0]);
+        if determinant == 0.0 {
+            return Err(TransformError::InvalidMatrix("the axes have to be linearly independent".to_string()));
+        }
+        return Ok(());
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let rows: Vec<JsonValue> = self.matrix.iter()
+            .map(|row| row.iter().map(|v| JsonValue::from(*v)).collect::<Vec<JsonValue>>().into())
+            .collect();
+        let mut hm = HashMap::new();
+        hm.insert("matrix".to_string(), rows.into());
+        return hm.into();
+    }
+
+    /// Reads a transform given either by its `matrix`, or by its `origin`, `direction` and `spacing`
+    /// as in `from_origin_direction`.
+    /// * `j` - the transform
+    /// * `default_spacing` - spacing used if it is not given with the direction
+    pub fn from_json(j: &JsonValue, default_spacing: [f64; 3]) -> Result<Self, TransformError> {
+        let hashmap = match j {
+            JsonValue::Object(o) => o,
+            _ => return Err(TransformError::InvalidJson(JsonError::NotAnObject(j.clone())))
+        };
+        let transform = if let Some(matrix) = hashmap.get("matrix") {
+            let rows = json_aux::get_array_from_json(matrix).map_err(TransformError::InvalidJson)?;
+            if rows.len() != 4 {
+                return Err(TransformError::InvalidMatrix("it has to have 4 rows".to_string()));
+            }
+            let mut matrix = [[0.0; 4]; 4];
+            for (row, j) in matrix.iter_mut().zip(&rows) {
+                *row = floats(j)?;
+            }
+            Self { matrix }
+        } else {
+            let origin = match hashmap.get("origin") {
+                Some(o) => floats(o)?,
+                None => [0.0; 3]
+            };
+            let direction = match hashmap.get("direction") {
+                Some(d) => {
+                    let axes = json_aux::get_array_from_json(d).map_err(TransformError::InvalidJson)?;
+                    if axes.len() != 3 {
+                        return Err(TransformError::InvalidMatrix("`direction` has to have 3 axes".to_string()));
+                    }
+                    [floats(&axes[0])?, floats(&axes[1])?, floats(&axes[2])?]
+                },
+                None => [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+            };
+            let spacing = match hashmap.get("spacing") {
+                Some(s) => floats(s)?,
+                None => default_spacing
+            };
+            Self::from_origin_direction(origin, direction, spacing)
+        };
+        transform.check()?;
+        return Ok(transform);
+    }
+}
+
+/// Reads an array of a fixed number of numbers.
+fn floats<const N: usize>(j: &JsonValue) -> Result<[f64; N], TransformError> {
+    let values = json_aux::get_array_from_json(j).map_err(TransformError::InvalidJson)?;
+    let values = values.iter()
+        .map(|v| v.get::<f64>().copied().ok_or(JsonError::NotANumber(v.clone())))
+        .collect::<Result<Vec<f64>, JsonError>>()
+        .map_err(TransformError::InvalidJson)?;
+    return values.try_into().map_err(|_| TransformError::InvalidJson(JsonError::NotAnArray(j.clone())));
+}
+
+/// Returns the transform of a modality, or None if it does not have one.
+/// * `modality` - the modality
+pub fn transform(modality: &Modality) -> Result<Option<Transform>, TransformError> {
+    return match modality.extension_payloads.get(&Extension::ExtTransform.to_string()) {
+        Some(payload) => Transform::from_json(payload, [1.0; 3]).map(Some),
+        None => Ok(None)
+    };
+}
+
+/// Sets the transform of a modality, or removes it with None.
+/// * `modality` - the modality
+/// * `transform` - the transform
+pub fn set_transform(modality: &mut Modality, transform: Option<&Transform>) -> Result<(), TransformError> {
+    let name = Extension::ExtTransform.to_string();
+    match transform {
+        Some(t) => {
+            t.check()?;
+            modality.extension_payloads.insert(name, t.to_json());
+        },
+        None => {
+            modality.extension_payloads.remove(&name);
+        }
+    }
+    return Ok(());
+}
@@ -0,0 +1,184 @@
+//! Bookkeeping of the tree of blocks volumes are split into: the root blocks of the modalities, the blocks
+//! with data placed in them, and the superblocks that group those. Blocks can be added in any order, such as
+//! by parallel workers, since they are numbered by their position when the tree is built.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::{block::Block, bvpfile::BVPFile, errors::TreeError, log_info, placement::Placement, vector3::Vector3};
+
+/// Collects the blocks with data of a conversion and where they are placed, and adds them to an asset
+/// after its root blocks. Blocks placed more than once, like deduplicated ones, are added once.
+pub struct BlockTreeBuilder {
+    root_count: usize,
+    blocks: Vec<Block>,
+    /// Placements of the added blocks, with the index of the root block they are in
+    placements: Vec<(usize, Placement)>
+}
+
+impl BlockTreeBuilder {
+    /// * `root_count` - number of root blocks, which come first in the asset
+    pub fn new(root_count: usize) -> Self {
+        return Self { root_count, blocks: Vec::new(), placements: Vec::new() };
+    }
+
+    /// Adds a block with data and places it in a root block.
+    /// * `root_block_index` - index of the root block
+    /// * `position` - position of the block inside the root block
+    /// * `block` - the block, its index only has to differ from those of the other added blocks
+    pub fn add_block(&mut self, root_block_index: usize, position: Vector3<u32>, block: Block) {
+        self.placements.push((root_block_index, Placement::new(position, block.index)));
+        self.blocks.push(block);
+    }
+
+    /// Places a block in a root block. The block is added with `add_block`, before or after this.
+    /// * `root_block_index` - index of the root block
+    /// * `position` - position of the block inside the root block
+    /// * `block_index` - index of the block
+    pub fn add_placement(&mut self, root_block_index: usize, position: Vector3<u32>, block_index: usize) {
+        self.placements.push((root_block_index, Placement::new(position, block_index)));
+    }
+
+    /// Returns the added blocks.
+    pub fn blocks(&self) -> &[Block] {
+        return &self.blocks;
+    }
+
+    /// Returns the mutable added blocks, for example to set their file names. Their indices must not be changed.
+    pub fn blocks_mut(&mut self) -> &mut [Block] {
+        return &mut self.blocks;
+    }
+
+    /// Returns the placements of the added blocks, with the index of the root block they are in.
+    pub fn placements(&self) -> &[(usize, Placement)] {
+        return &self.placements;
+    }
+
+    /// Numbers the added blocks right after the root blocks, by the first position they are placed at:
+    /// root block by root block, then with Z changing slowest and X fastest. Blocks that are not placed
+    /// follow in the order they were added. Returns the new indices by the old ones.
+    /// The numbers only depend on where blocks are placed, not on the order they were added in.
+    /// Placements of blocks that were not added get numbers too, which `build` reports as an error.
+    pub fn renumber(&mut self) -> HashMap<usize, usize> {
+        self.placements.sort_by_key(|(root_block_index, placement)| {
+            let position = placement.position;
+            return (*root_block_index, position.z, position.y, position.x);
+        });
+        let mut renumbered: HashMap<usize, usize> = HashMap::with_capacity(self.blocks.len());
+        for (_, placement) in &self.placements {
+            let next = self.root_count + renumbered.len();
+            renumbered.entry(placement.block).or_insert(next);
+        }
+        for block in &self.blocks {
+            let next = self.root_count + renumbered.len();
+            renumbered.entry(block.index).or_insert(next);
+        }
+
+        for (_, placement) in &mut self.placements {
+            placement.block = renumbered[&placement.block];
+        }
+        for block in &mut self.blocks {
+            block.index = renumbered[&block.index];
+        }
+        self.blocks.sort_by_key(|block| block.index);
+        return renumbered;
+    }
+
+    /// Numbers the blocks as `renumber` does and adds them to an asset after its root blocks. The placements
+    /// of every root block are grouped into levels of superblocks, which are added after the blocks with data.
+    /// The data of the root blocks is dropped, as it is stored in the blocks placed in them.
+    /// * `bvp_file` - the asset, holding the root blocks and no other blocks
+    /// * `superblock_dimensions` - dimensions of superblocks on each level, the outermost first, or none
+    pub fn build(mut self, bvp_file: &mut BVPFile, superblock_dimensions: &[Vector3<u32>]) -> Result<(), TreeError> {
+        if bvp_file.blocks.len() != self.root_count {
+            return Err(TreeError::RootCount(self.root_count, bvp_file.blocks.len()));
+        }
+        let mut added = HashSet::with_capacity(self.blocks.len());
+        if let Some(block) = self.blocks.iter().find(|block| !added.insert(block.index)) {
+            return Err(TreeError::DuplicateBlock(block.index));
+        }
+        for (root_block_index, placement) in &self.placements {
+            if *root_block_index >= self.root_count {
+                return Err(TreeError::UnknownRoot(*root_block_index));
+            }
+            if !added.contains(&placement.block) {
+                return Err(TreeError::UnknownBlock(placement.block));
+            }
+        }
+        self.renumber();
+
+        bvp_file.blocks.extend(self.blocks);
+        let mut placements_by_root: Vec<Vec<Placement>> = (0..self.root_count).map(|_| Vec::new()).collect();
+        for (root_block_index, placement) in self.placements {
+            placements_by_root[root_block_index].push(placement);
+        }
+        for (root_block_index, placements) in placements_by_root.into_iter().enumerate() {
+            let root_placements = build_superblocks(bvp_file, placements, superblock_dimensions, root_block_index);
+            let root_block = &mut bvp_file.blocks[root_block_index];
+            root_block.placements = root_placements;
+            root_block.data = None;
+        }
+        return Ok(());
+    }
+}
+
+/// Groups the placements of a root block into levels of superblocks, which hold
+/// placements but no data, and returns the placements of the outermost level.
+/// Levels are built from the innermost out. Superblocks with the same dimensions and
+/// placements are only stored once, so uniform regions share a whole subtree.
+/// * `bvp_file` - BVPFile the superblocks are added to
+/// * `placements` - placements of the data blocks, relative to the root block
+/// * `superblock_dimensions` - dimensions of superblocks on each level, the outermost first
+/// * `root_block_index` - index of the root block
+fn build_superblocks(
+    bvp_file: &mut BVPFile,
+    mut placements: Vec<Placement>,
+    superblock_dimensions: &[Vector3<u32>],
+    root_block_index: usize,
+) -> Vec<Placement> {
+    let dimensions = bvp_file.blocks[root_block_index].dimensions;
+    let format = bvp_file.blocks[root_block_index].format;
+    for level_dimensions in superblock_dimensions.iter().rev() {
+        // Ordered by Z, Y and X, so the block indices do not depend on thread timing.
+        let mut groups: BTreeMap<(u32, u32, u32), Vec<Placement>> = BTreeMap::new();
+        for placement in placements {
+            let cell = placement.position / *level_dimensions;
+            groups.entry((cell.z, cell.y, cell.x)).or_default().push(placement);
+        }
+
+        let mut superblock_map: HashMap<((u32, u32, u32), Vec<((u32, u32, u32), usize)>), usize> = HashMap::new();
+        let mut level_placements = Vec::with_capacity(groups.len());
+        for ((z, y, x), group) in groups {
+            let start = *level_dimensions * Vector3::from_xyz(x, y, z);
+            let superblock_dimensions = (start + *level_dimensions).min(&dimensions) - start;
+            let mut contents: Vec<((u32, u32, u32), usize)> = group.iter()
+                .map(|p| {
+                    let position = p.position - start;
+                    return ((position.z, position.y, position.x), p.block);
+                })
+                .collect();
+            contents.sort();
+            let key = ((superblock_dimensions.x, superblock_dimensions.y, superblock_dimensions.z), contents);
+
+            let index = match superblock_map.get(&key) {
+                Some(index) => *index,
+                None => {
+                    let index = bvp_file.blocks.len();
+                    let mut superblock = Block::new(index, superblock_dimensions, format, None);
+                    superblock.placements = key.1.iter()
+                        .map(|((z, y, x), block)| Placement::new(Vector3::from_xyz(*x, *y, *z), *block))
+                        .collect();
+                    bvp_file.blocks.push(superblock);
+                    superblock_map.insert(key, index);
+                    index
+                }
+            };
+            level_placements.push(Placement::new(start, index));
+        }
+        log_info!(
+            "{} superblocks of {}, {} of them unique",
+            level_placements.len(), level_dimensions, superblock_map.len()
+        );
+        placements = level_placements;
+    }
+    return placements;
+}
@@ -0,0 +1,799 @@
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use tinyjson::JsonValue;
+
+use crate::{bvpfile::BVPFile, checksum::Checksum, compressions::CompressionType, ed25519::KEY_LENGTH, errors::{SignatureError, TransferFunctionError}, extensions::{self, Extension}, file::File, formats::Format, json_aux::{ParseMode, SchemaIssue}, manifest, signature, transfer_function, vector3::Vector3, version::{self, SpecVersion}};
+
+/// How bad a validation issue is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The asset does not conform to the specification and readers may fail on it.
+    Error,
+    /// The asset conforms, but something is likely unintended.
+    Warning
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning"
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+/// Machine-readable kind of a validation issue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IssueCode {
+    /// The archive or folder could not be read.
+    UnreadableArchive,
+    /// There is no `manifest.json` among the files.
+    MissingManifest,
+    /// The manifest is not valid UTF-8 JSON.
+    InvalidJson,
+    /// A required manifest field is missing or has the wrong type.
+    Schema,
+    /// A block refers to a data file that is not in the archive.
+    MissingFile,
+    /// An index (block, format) points past the end of its array.
+    InvalidReference,
+    /// A block uses an encoding this library does not support.
+    UnsupportedEncoding,
+    /// The size of unencoded block data does not match the block dimensions and format.
+    DataSizeMismatch,
+    /// Block dimensions or a placement position are not multiples of the microblock dimensions.
+    MicroblockMisaligned,
+    /// A placed block extends beyond its parent block.
+    PlacementOutOfBounds,
+    /// Two placed blocks in the same parent overlap.
+    PlacementOverlap,
+    /// The placements of a block without data leave parts of it uncovered.
+    IncompleteCoverage,
+    /// A block is (indirectly) placed inside itself.
+    PlacementCycle,
+    /// An extension is declared that this library does not know.
+    UnknownExtension,
+    /// A format needs an extension that is not declared in the asset.
+    UndeclaredExtension,
+    /// The data file of a block does not match its recorded checksum.
+    ChecksumMismatch,
+    /// The asset follows a specification version this library cannot read, or a newer minor one.
+    UnsupportedVersion,
+    /// A time of the asset is not an ISO 8601 timestamp.
+    InvalidTimestamp,
+    /// A signature was asked for, but the asset is not signed.
+    MissingSignature,
+    /// The signature is malformed or does not match the manifest.
+    InvalidSignature,
+    /// The signature is valid, but made with a key that is not trusted.
+    UntrustedSignature,
+    /// Block data has no SHA-256 checksum, so the signature of the manifest does not cover it.
+    UnsignedData,
+    /// A manifest object has a field that is not in the specification.
+    UnknownField
+}
+
+impl IssueCode {
+    /// Returns the stable identifier of the code, for use in scripts.
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            IssueCode::UnreadableArchive => "unreadable-archive",
+            IssueCode::MissingManifest => "missing-manifest",
+            IssueCode::InvalidJson => "invalid-json",
+            IssueCode::Schema => "schema",
+            IssueCode::MissingFile => "missing-file",
+            IssueCode::InvalidReference => "invalid-reference",
+            IssueCode::UnsupportedEncoding => "unsupported-encoding",
+            IssueCode::DataSizeMismatch => "data-size-mismatch",
+            IssueCode::MicroblockMisaligned => "microblock-misaligned",
+            IssueCode::PlacementOutOfBounds => "placement-out-of-bounds",
+            IssueCode::PlacementOverlap => "placement-overlap",
+            IssueCode::IncompleteCoverage => "incomplete-coverage",
+            IssueCode::PlacementCycle => "placement-cycle",
+            IssueCode::UnknownExtension => "unknown-extension",
+            IssueCode::UndeclaredExtension => "undeclared-extension",
+            IssueCode::ChecksumMismatch => "checksum-mismatch",
+            IssueCode::UnsupportedVersion => "unsupported-version",
+            IssueCode::InvalidTimestamp => "invalid-timestamp",
+            IssueCode::MissingSignature => "missing-signature",
+            IssueCode::InvalidSignature => "invalid-signature",
+            IssueCode::UntrustedSignature => "untrusted-signature",
+            IssueCode::UnsignedData => "unsigned-data",
+            IssueCode::UnknownField => "unknown-field"
+        };
+    }
+}
+
+/// A single problem found in an asset.
+/// * `path` - location of the problem in the manifest, e.g. `blocks[3].placements[0]`
+#[derive(Clone, Debug)]
+pub struct Issue {
+    pub severity: Severity,
+    pub code: IssueCode,
+    pub path: String,
+    pub message: String
+}
+
+impl Issue {
+    pub fn to_json(&self) -> JsonValue {
+        let mut hm = HashMap::new();
+        hm.insert("severity".to_string(), self.severity.to_string().into());
+        hm.insert("code".to_string(), self.code.as_str().to_string().into());
+        hm.insert("path".to_string(), self.path.clone().into());
+        hm.insert("message".to_string(), self.message.clone().into());
+        return hm.into();
+    }
+}
+
+/// A block as described in the manifest, with only the fields needed for validation.
+struct ManifestBlock {
+    dimensions: Vector3<u32>,
+    format: Option<usize>,
+    data: Option<String>,
+    encoding: Option<String>,
+    checksum: Option<String>,
+    placements: Vec<(Vector3<u32>, usize)>
+}
+
+struct Validator {
+    issues: Vec<Issue>
+}
+
+impl Validator {
+    fn error(&mut self, code: IssueCode, path: &str, message: String) {
+        self.issues.push(Issue { severity: Severity::Error, code, path: path.to_string(), message });
+    }
+
+    fn warning(&mut self, code: IssueCode, path: &str, message: String) {
+        self.issues.push(Issue { severity: Severity::Warning, code, path: path.to_string(), message });
+    }
+
+    fn object<'a>(&mut self, path: &str, j: &'a JsonValue) -> Option<&'a HashMap<String, JsonValue>> {
+        return match j {
+            JsonValue::Object(o) => Some(o),
+            _ => {
+                self.error(IssueCode::Schema, path, "must be an object".to_string());
+                None
+            }
+        };
+    }
+
+    fn array<'a>(&mut self, path: &str, j: Option<&'a JsonValue>) -> Option<&'a Vec<JsonValue>> {
+        return match j {
+            Some(JsonValue::Array(a)) => Some(a),
+            Some(_) => {
+                self.error(IssueCode::Schema, path, "must be an array".to_string());
+                None
+            },
+            None => {
+                self.error(IssueCode::Schema, path, "is required".to_string());
+                None
+            }
+        };
+    }
+
+    fn string(&mut self, path: &str, j: Option<&JsonValue>, required: bool) -> Option<String> {
+        return match j {
+            Some(JsonValue::String(s)) => Some(s.clone()),
+            Some(_) => {
+                self.error(IssueCode::Schema, path, "must be a string".to_string());
+                None
+            },
+            None => {
+                if required {
+                    self.error(IssueCode::Schema, path, "is required".to_string());
+                }
+                None
+            }
+        };
+    }
+
+    fn index(&mut self, path: &str, j: Option<&JsonValue>, required: bool) -> Option<usize> {
+        return match j {
+            Some(JsonValue::Number(n)) if n.fract() == 0.0 && *n >= 0.0 => Some(*n as usize),
+            Some(_) => {
+                self.error(IssueCode::Schema, path, "must be a non-negative integer".to_string());
+                None
+            },
+            None => {
+                if required {
+                    self.error(IssueCode::Schema, path, "is required".to_string());
+                }
+                None
+            }
+        };
+    }
+
+    fn vector3(&mut self, path: &str, j: Option<&JsonValue>) -> Option<Vector3<u32>> {
+        let array = match j {
+            Some(JsonValue::Array(a)) if a.len() == 3 => a,
+            Some(_) => {
+                self.error(IssueCode::Schema, path, "must be an array of three non-negative integers".to_string());
+                return None;
+            },
+            None => {
+                self.error(IssueCode::Schema, path, "is required".to_string());
+                return None;
+            }
+        };
+        let mut components = [0u32; 3];
+        for (i, component) in array.iter().enumerate() {
+            match component {
+                JsonValue::Number(n) if n.fract() == 0.0 && *n >= 0.0 && *n <= u32::MAX as f64 => components[i] = *n as u32,
+                _ => {
+                    self.error(IssueCode::Schema, &format!("{}[{}]", path, i), "must be a non-negative integer".to_string());
+                    return None;
+                }
+            }
+        }
+        return Some(Vector3::from_xyz(components[0], components[1], components[2]));
+    }
+
+    fn block(&mut self, path: &str, j: &JsonValue) -> Option<ManifestBlock> {
+        let o = self.object(path, j)?;
+        let dimensions = self.vector3(&format!("{}.dimensions", path), o.get("dimensions"));
+        let format = self.index(&format!("{}.format", path), o.get("format"), false);
+        let data = self.string(&format!("{}.data", path), o.get("data"), false);
+        let encoding = self.string(&format!("{}.encoding", path), o.get("encoding"), data.is_some());
+        let checksum = self.string(&format!("{}.checksum", path), o.get("checksum"), false);
+
+        let mut placements = Vec::new();
+        let placements_path = format!("{}.placements", path);
+        let mut valid = true;
+        if let Some(array) = self.array(&placements_path, o.get("placements")) {
+            for (i, placement) in array.iter().enumerate() {
+                let placement_path = format!("{}[{}]", placements_path, i);
+                let placement = match self.object(&placement_path, placement) {
+                    Some(p) => p,
+                    None => {
+                        valid = false;
+                        continue;
+                    }
+                };
+                let position = self.vector3(&format!("{}.position", placement_path), placement.get("position"));
+                let block = self.index(&format!("{}.block", placement_path), placement.get("block"), true);
+                match (position, block) {
+                    (Some(position), Some(block)) => placements.push((position, block)),
+                    _ => valid = false
+                }
+            }
+        } else {
+            valid = false;
+        }
+
+        if !valid || dimensions.is_none() {
+            return None;
+        }
+        return Some(ManifestBlock { dimensions: dimensions.unwrap(), format, data, encoding, checksum, placements });
+    }
+}
+
+/// Returns true if the boxes `[a, a + a_size)` and `[b, b + b_size)` share any voxel.
+fn boxes_overlap(a: Vector3<u32>, a_size: Vector3<u32>, b: Vector3<u32>, b_size: Vector3<u32>) -> bool {
+    let a_end = a.saturating_add(&a_size);
+    let b_end = b.saturating_add(&b_size);
+    return a.x < b_end.x && b.x < a_end.x
+        && a.y < b_end.y && b.y < a_end.y
+        && a.z < b_end.z && b.z < a_end.z;
+}
+
+/// Checks the signature of the manifest, and that the data of every block is covered by it through a SHA-256 checksum.
+/// The block files are compared with the checksums when the blocks are checked.
+/// * `root` - the root object of the manifest
+/// * `blocks` - the blocks of the manifest
+/// * `trusted_keys` - public keys the signature has to be made with, any key if it is empty
+fn check_signature(validator: &mut Validator, root: &HashMap<String, JsonValue>, blocks: &[ManifestBlock], trusted_keys: &[[u8; KEY_LENGTH]]) {
+    let path = format!("asset.extensions.{}", Extension::ExtSignature.to_string());
+    match signature::verify_signature(root) {
+        Ok(key) if !trusted_keys.is_empty() && !trusted_keys.contains(&key) => validator.error(IssueCode::UntrustedSignature, &path, format!(
+            "the manifest is signed with key {}, which is not trusted", signature::to_hex(&key)
+        )),
+        Ok(_) => {},
+        Err(SignatureError::Missing) => {
+            validator.error(IssueCode::MissingSignature, "asset", "the asset is not signed".to_string());
+            return;
+        },
+        Err(e) => validator.error(IssueCode::InvalidSignature, &path, e.to_string())
+    }
+    let unsigned = blocks.iter()
+        .filter(|b| b.data.is_some())
+        .filter(|b| !b.checksum.as_deref().and_then(|c| Checksum::from_string(c).ok()).is_some_and(|c| c.algorithm.is_cryptographic()))
+        .count();
+    if unsigned > 0 {
+        validator.error(IssueCode::UnsignedData, "blocks", format!(
+            "{} blocks have data without a SHA-256 checksum, the signature does not cover it", unsigned
+        ));
+    }
+}
+
+/// Checks placements of every block: placed blocks must fit into the parent and be aligned
+/// to microblocks, must not overlap, and must cover the whole parent if it does not have data itself.
+fn check_placements(validator: &mut Validator, blocks: &[ManifestBlock], formats: &[Option<Format>]) {
+    for (parent_index, parent) in blocks.iter().enumerate() {
+        // Already reported as invalid references.
+        let placements: Vec<(usize, Vector3<u32>, Vector3<u32>)> = parent.placements.iter()
+            .enumerate()
+            .filter(|(_, (_, block))| *block < blocks.len())
+            .map(|(i, (position, block))| (i, *position, blocks[*block].dimensions))
+            .collect();
+
+        let microblock_dimensions = parent.format
+            .and_then(|f| formats.get(f))
+            .and_then(|f| f.as_ref())
+            .map(|f| f.microblock_dimensions);
+
+        let mut placed_voxels: u128 = 0;
+        let mut in_bounds = true;
+        for (i, position, dimensions) in &placements {
+            let path = format!("blocks[{}].placements[{}]", parent_index, i);
+            if position.checked_add(dimensions).map_or(true, |end| end.is_any_gt(parent.dimensions)) {
+                validator.error(IssueCode::PlacementOutOfBounds, &path, format!(
+                    "block of dimensions {} at {} does not fit into parent of dimensions {}",
+                    dimensions, position, parent.dimensions
+                ));
+                in_bounds = false;
+            }
+            if let Some(microblock) = microblock_dimensions {
+                if position.is_any_div(&microblock) {
+                    validator.error(IssueCode::MicroblockMisaligned, &path, format!(
+                        "position {} is not a multiple of the microblock dimensions {}", position, microblock
+                    ));
+                }
+            }
+            placed_voxels += dimensions.x as u128 * dimensions.y as u128 * dimensions.z as u128;
+        }
+
+        // Sorted by z, only placements starting before the end of the current one can overlap it.
+        let mut sorted = placements.clone();
+        sorted.sort_by_key(|(_, position, _)| position.z);
+        let mut overlapping = false;
+        for a in 0..sorted.len() {
+            let (i, position_a, size_a) = sorted[a];
+            for &(j, position_b, size_b) in sorted.iter().skip(a + 1) {
+                if position_b.z >= position_a.z.saturating_add(size_a.z) {
+                    break;
+                }
+                if boxes_overlap(position_a, size_a, position_b, size_b) {
+                    validator.error(IssueCode::PlacementOverlap, &format!("blocks[{}].placements[{}]", parent_index, i), format!(
+                        "overlaps placement {} of the same block", j
+                    ));
+                    overlapping = true;
+                }
+            }
+        }
+
+        // Without overlaps and with everything in bounds, the placed volumes only add up
+        // to the parent volume if every voxel is covered.
+        let parent_voxels = parent.dimensions.x as u128 * parent.dimensions.y as u128 * parent.dimensions.z as u128;
+        if !parent.placements.is_empty() && parent.data.is_none() && in_bounds && !overlapping && placed_voxels < parent_voxels {
+            validator.error(IssueCode::IncompleteCoverage, &format!("blocks[{}]", parent_index), format!(
+                "placements cover {} of {} voxels and the block has no data of its own", placed_voxels, parent_voxels
+            ));
+        }
+    }
+}
+
+/// Reports blocks that are placed inside themselves, directly or through other blocks.
+fn check_cycles(validator: &mut Validator, blocks: &[ManifestBlock]) {
+    // 0 = not visited, 1 = on the current path, 2 = done
+    let mut state = vec![0u8; blocks.len()];
+    for root in 0..blocks.len() {
+        if state[root] != 0 {
+            continue;
+        }
+        let mut stack: Vec<(usize, usize)> = vec![(root, 0)];
+        state[root] = 1;
+        while let Some((block, next)) = stack.pop() {
+            let placements = &blocks[block].placements;
+            if next >= placements.len() {
+                state[block] = 2;
+                continue;
+            }
+            stack.push((block, next + 1));
+            let child = placements[next].1;
+            if child >= blocks.len() {
+                continue;
+            }
+            match state[child] {
+                0 => {
+                    state[child] = 1;
+                    stack.push((child, 0));
+                },
+                1 => validator.error(IssueCode::PlacementCycle, &format!("blocks[{}].placements[{}]", block, next), format!(
+                    "block {} is placed inside itself", child
+                )),
+                _ => ()
+            }
+        }
+    }
+}
+
+/// Checks the block tree of an already parsed asset: placements must fit into their parents,
+/// be aligned to microblocks, must not overlap, must cover parents without data, and no block
+/// may be placed inside itself. Returns the issues found.
+/// * `bvp_file` - the parsed asset
+pub fn validate_block_tree(bvp_file: &BVPFile) -> Vec<Issue> {
+    let mut validator = Validator { issues: Vec::new() };
+    let blocks: Vec<ManifestBlock> = bvp_file.blocks.iter().map(|block| ManifestBlock {
+        dimensions: block.dimensions,
+        format: block.format,
+        data: block.data_url.clone(),
+        encoding: block.encoding.as_ref().map(|e| e.to_string()),
+        checksum: block.checksum.as_ref().map(|c| c.to_string()),
+        placements: block.placements.iter().map(|p| (p.position, p.block)).collect()
+    }).collect();
+    let formats: Vec<Option<Format>> = bvp_file.formats.iter().map(|f| Some(f.clone())).collect();
+
+    for (i, block) in blocks.iter().enumerate() {
+        for (j, (_, placed)) in block.placements.iter().enumerate() {
+            if *placed >= blocks.len() {
+                validator.error(IssueCode::InvalidReference, &format!("blocks[{}].placements[{}].block", i, j), format!(
+                    "block {} does not exist", placed
+                ));
+            }
+        }
+    }
+    check_cycles(&mut validator, &blocks);
+    check_placements(&mut validator, &blocks, &formats);
+    return validator.issues;
+}
+
+/// Checks done in addition to the specification, see `validate_files_with_options`.
+#[derive(Clone, Debug, Default)]
+pub struct ValidationOptions {
+    /// Hash data files and compare them to their checksums.
+    pub verify_checksums: bool,
+    /// Require a valid signature of the manifest, see `signature`.
+    pub check_signature: bool,
+    /// Public keys the signature has to be made with. Any key is accepted if it is empty.
+    pub trusted_keys: Vec<[u8; KEY_LENGTH]>,
+    /// Unknown fields are errors in strict mode and warnings in lenient mode.
+    pub parse_mode: ParseMode
+}
+
+/// Validates an asset against the BVP specification and returns all issues found.
+/// An empty result means the asset is valid.
+/// * `files` - all files of the asset, as read from an archive
+pub fn validate_files(files: &Vec<File>) -> Vec<Issue> {
+    return validate_files_with_options(files, &ValidationOptions::default());
+}
+
+/// Validates an asset like `validate_files` and also checks block data against the
+/// checksums recorded in the manifest, which needs to hash every data file.
+/// * `files` - all files of the asset, as read from an archive
+pub fn validate_files_with_checksums(files: &Vec<File>) -> Vec<Issue> {
+    return validate_files_with_options(files, &ValidationOptions { verify_checksums: true, ..Default::default() });
+}
+
+/// Validates an asset and returns all issues found.
+/// * `files` - all files of the asset, as read from an archive
+/// * `options` - further checks of checksums and signatures
+pub fn validate_files_with_options(files: &Vec<File>, options: &ValidationOptions) -> Vec<Issue> {
+    let mut validator = Validator { issues: Vec::new() };
+
+    let manifest = match files.iter().find(|file| file.name.ends_with("manifest.json")) {
+        Some(m) => m,
+        None => {
+            validator.error(IssueCode::MissingManifest, "", "no manifest.json found".to_string());
+            return validator.issues;
+        }
+    };
+    let content = match std::str::from_utf8(&manifest.data) {
+        Ok(c) => c,
+        Err(e) => {
+            validator.error(IssueCode::InvalidJson, "", format!("manifest is not valid UTF-8: {}", e));
+            return validator.issues;
+        }
+    };
+    let json = match JsonValue::from_str(content) {
+        Ok(j) => j,
+        Err(e) => {
+            validator.error(IssueCode::InvalidJson, "", format!("manifest is not valid JSON: {}", e));
+            return validator.issues;
+        }
+    };
+    let root = match validator.object("", &json) {
+        Some(r) => r,
+        None => return validator.issues
+    };
+    // Missing fields and wrong types are reported by the checks below, with more detail.
+    for issue in manifest::manifest_issues(&json) {
+        if let SchemaIssue::Unknown(path) = issue {
+            let message = "is not a field of the specification, move it to `extras`".to_string();
+            if options.parse_mode == ParseMode::Strict {
+                validator.error(IssueCode::UnknownField, &path, message);
+            } else {
+                validator.warning(IssueCode::UnknownField, &path, message);
+            }
+        }
+    }
+
+    // Asset and extensions
+    let mut declared_extensions = Vec::new();
+    if let Some(asset) = root.get("asset").and_then(|a| validator.object("asset", a)) {
+        if let Some(version) = validator.string("asset.version", asset.get("version"), true) {
+            match SpecVersion::from_string(&version) {
+                Ok(v) if !v.is_readable() => validator.error(IssueCode::UnsupportedVersion, "asset.version", format!(
+                    "version `{}` is not supported, only {}.x can be read", version, SpecVersion::CURRENT.major
+                )),
+                Ok(v) if v.is_newer() => validator.warning(IssueCode::UnsupportedVersion, "asset.version", format!(
+                    "version `{}` is newer than {}, some of the asset may be ignored", version, SpecVersion::CURRENT.to_string()
+                )),
+                Ok(_) => {},
+                Err(_) => validator.error(IssueCode::Schema, "asset.version", format!(
+                    "must be a version such as `1.0`, got `{}`", version
+                ))
+            }
+        }
+        for key in ["creationTime", "acquisitionTime"] {
+            let path = format!("asset.{}", key);
+            if let Some(time) = validator.string(&path, asset.get(key), false) {
+                if !version::is_timestamp(&time) {
+                    validator.warning(IssueCode::InvalidTimestamp, &path, format!(
+                        "`{}` is not an ISO 8601 timestamp such as `2024-05-01T12:30:00Z`", time
+                    ));
+                }
+            }
+        }
+        for key in ["extensionsUsed", "extensionsRequired"] {
+            let path = format!("asset.{}", key);
+            if asset.get(key).is_none() {
+                continue;
+            }
+            if let Some(extensions) = validator.array(&path, asset.get(key)) {
+                for (i, extension) in extensions.iter().enumerate() {
+                    let extension_path = format!("{}[{}]", path, i);
+                    if let Some(name) = validator.string(&extension_path, Some(extension), true) {
+                        if !extensions::is_supported(&name) {
+                            let message = format!("extension `{}` is not known", name);
+                            if key == "extensionsRequired" {
+                                validator.error(IssueCode::UnknownExtension, &extension_path, message);
+                            } else {
+                                validator.warning(IssueCode::UnknownExtension, &extension_path, message);
+                            }
+                        }
+                        declared_extensions.push(name);
+                    }
+                }
+            }
+        }
+    } else if root.get("asset").is_none() {
+        validator.error(IssueCode::Schema, "asset", "is required".to_string());
+    }
+
+    // Formats
+    let mut formats: Vec<Option<Format>> = Vec::new();
+    if let Some(array) = validator.array("formats", root.get("formats")) {
+        for (i, j) in array.iter().enumerate() {
+            let path = format!("formats[{}]", i);
+            let o = match validator.object(&path, j) {
+                Some(o) => o,
+                None => {
+                    formats.push(None);
+                    continue;
+                }
+            };
+            let family = validator.string(&format!("{}.family", path), o.get("family"), true);
+            let fields_valid = match family.as_deref() {
+                Some("mono") => {
+                    let count = validator.index(&format!("{}.count", path), o.get("count"), true);
+                    let size = validator.index(&format!("{}.size", path), o.get("size"), true);
+                    let tp = validator.string(&format!("{}.type", path), o.get("type"), true);
+                    count.is_some() && size.is_some() && tp.is_some()
+                },
+                Some("multi") => {
+                    let components_path = format!("{}.components", path);
+                    match validator.array(&components_path, o.get("components")) {
+                        Some(components) => {
+                            let mut valid = true;
+                            for (k, component) in components.iter().enumerate() {
+                                let component_path = format!("{}[{}]", components_path, k);
+                                let c = match validator.object(&component_path, component) {
+                                    Some(c) => c,
+                                    None => {
+                                        valid = false;
+                                        continue;
+                                    }
+                                };
+                                let count = validator.index(&format!("{}.count", component_path), c.get("count"), true);
+                                let size = validator.index(&format!("{}.size", component_path), c.get("size"), true);
+                                let tp = validator.string(&format!("{}.type", component_path), c.get("type"), true);
+                                valid &= count.is_some() && size.is_some() && tp.is_some();
+                            }
+                            valid
+                        },
+                        None => false
+                    }
+                },
+                Some("compressed") => {
+                    let scheme = validator.string(&format!("{}.scheme", path), o.get("scheme"), true);
+                    let size = validator.index(&format!("{}.microblockSize", path), o.get("microblockSize"), true);
+                    let dimensions = validator.vector3(&format!("{}.microblockDimensions", path), o.get("microblockDimensions"));
+                    scheme.is_some() && size.is_some() && dimensions.is_some()
+                },
+                Some(f) => {
+                    validator.error(IssueCode::Schema, &format!("{}.family", path), format!("unsupported format family `{}`", f));
+                    false
+                },
+                None => false
+            };
+            if !fields_valid {
+                formats.push(None);
+                continue;
+            }
+            match Format::from_json(j) {
+                Ok(format) => {
+                    for extension in &format.extensions {
+                        if !declared_extensions.contains(&extension.to_string()) {
+                            validator.warning(IssueCode::UndeclaredExtension, &path, format!(
+                                "format needs extension `{}`, which is not declared in the asset", extension.to_string()
+                            ));
+                        }
+                    }
+                    formats.push(Some(format));
+                },
+                Err(e) => {
+                    validator.error(IssueCode::Schema, &path, e.to_string());
+                    formats.push(None);
+                }
+            }
+        }
+    }
+
+    // Blocks
+    let mut blocks: Vec<ManifestBlock> = Vec::new();
+    let mut blocks_valid = true;
+    if let Some(array) = validator.array("blocks", root.get("blocks")) {
+        for (i, j) in array.iter().enumerate() {
+            match validator.block(&format!("blocks[{}]", i), j) {
+                Some(b) => blocks.push(b),
+                None => blocks_valid = false
+            }
+        }
+    } else {
+        blocks_valid = false;
+    }
+
+    // Modalities
+    let mut modality_blocks = Vec::new();
+    if let Some(array) = validator.array("modalities", root.get("modalities")) {
+        for (i, j) in array.iter().enumerate() {
+            let path = format!("modalities[{}]", i);
+            if let Some(o) = validator.object(&path, j) {
+                let payload = o.get("extensions").and_then(|e| e.get::<HashMap<String, JsonValue>>())
+                    .and_then(|e| e.get(&Extension::ExtTransferFunction.to_string()));
+                match transfer_function::from_payload(payload) {
+                    Ok(transfer_functions) => for transfer_function in transfer_functions {
+                        match transfer_function.lut_data(files) {
+                            Err(e @ TransferFunctionError::LutSizeMismatch(..)) => validator.error(IssueCode::DataSizeMismatch, &format!("{}.extensions", path), e.to_string()),
+                            Err(e) => validator.error(IssueCode::MissingFile, &format!("{}.extensions", path), e.to_string()),
+                            Ok(_) => {}
+                        }
+                    },
+                    Err(e) => validator.error(IssueCode::Schema, &format!("{}.extensions", path), e.to_string())
+                }
+                if let Some(block) = validator.index(&format!("{}.block", path), o.get("block"), true) {
+                    modality_blocks.push((path, block));
+                }
+            }
+        }
+    }
+
+    if options.check_signature {
+        check_signature(&mut validator, root, &blocks, &options.trusted_keys);
+    }
+
+    // Without a complete block list, block indices cannot be checked.
+    if !blocks_valid {
+        return validator.issues;
+    }
+
+    for (path, block) in &modality_blocks {
+        if *block >= blocks.len() {
+            validator.error(IssueCode::InvalidReference, &format!("{}.block", path), format!(
+                "block {} does not exist, there are {} blocks", block, blocks.len()
+            ));
+        }
+    }
+
+    let mut references_valid = true;
+    for (i, block) in blocks.iter().enumerate() {
+        let path = format!("blocks[{}]", i);
+        let format = match block.format {
+            Some(f) if f >= formats.len() => {
+                validator.error(IssueCode::InvalidReference, &format!("{}.format", path), format!(
+                    "format {} does not exist, there are {} formats", f, formats.len()
+                ));
+                None
+            },
+            Some(f) => formats[f].as_ref(),
+            None => None
+        };
+
+        if let Some(format) = format {
+            if block.dimensions.is_any_div(&format.microblock_dimensions) {
+                validator.error(IssueCode::MicroblockMisaligned, &format!("{}.dimensions", path), format!(
+                    "dimensions {} are not a multiple of the microblock dimensions {}",
+                    block.dimensions, format.microblock_dimensions
+                ));
+            }
+        }
+
+        let encoding = match &block.encoding {
+            Some(e) => match CompressionType::from_string(e) {
+                Ok(e) => Some(e),
+                Err(_) => {
+                    validator.error(IssueCode::UnsupportedEncoding, &format!("{}.encoding", path), format!(
+                        "encoding `{}` is not supported", e
+                    ));
+                    None
+                }
+            },
+            None => None
+        };
+
+        let checksum = match &block.checksum {
+            Some(c) => match Checksum::from_string(c) {
+                Ok(c) => Some(c),
+                Err(e) => {
+                    validator.error(IssueCode::Schema, &format!("{}.checksum", path), e.to_string());
+                    None
+                }
+            },
+            None => None
+        };
+
+        if let Some(data_url) = &block.data {
+            match files.iter().find(|file| &file.name == data_url) {
+                Some(file) => {
+                    // Signed data is always checked, the signature only holds if the files match.
+                    let signed = options.check_signature && checksum.as_ref().is_some_and(|c| c.algorithm.is_cryptographic());
+                    if let (true, Some(checksum)) = (options.verify_checksums || signed, checksum) {
+                        if !checksum.verify(&file.data) {
+                            validator.error(IssueCode::ChecksumMismatch, &format!("{}.data", path), format!(
+                                "`{}` does not match checksum `{}`", data_url, checksum.to_string()
+                            ));
+                        }
+                    }
+                    if let (Some(CompressionType::None), Some(format)) = (encoding, format) {
+                        match format.checked_count_space(block.dimensions) {
+                            Some(expected) if file.data.len() != expected => {
+                                validator.error(IssueCode::DataSizeMismatch, &format!("{}.data", path), format!(
+                                    "`{}` has {} bytes, but dimensions and format need {}", data_url, file.data.len(), expected
+                                ));
+                            },
+                            Some(_) => {},
+                            None => validator.error(IssueCode::DataSizeMismatch, &format!("{}.data", path), format!(
+                                "dimensions {} are too large for any data", block.dimensions
+                            ))
+                        }
+                    }
+                },
+                None => validator.error(IssueCode::MissingFile, &format!("{}.data", path), format!(
+                    "file `{}` is not in the archive", data_url
+                ))
+            }
+            // Data needs a format to be interpreted.
+            if block.format.is_none() {
+                validator.warning(IssueCode::Schema, &format!("{}.format", path), "block has data, but no format".to_string());
+            }
+        }
+
+        for (j, (_, placed)) in block.placements.iter().enumerate() {
+            if *placed >= blocks.len() {
+                validator.error(IssueCode::InvalidReference, &format!("{}.placements[{}].block", path, j), format!(
+                    "block {} does not exist, there are {} blocks", placed, blocks.len()
+                ));
+                references_valid = false;
+            }
+        }
+    }
+
+    check_placements(&mut validator, &blocks, &formats);
+    if references_valid {
+        check_cycles(&mut validator, &blocks);
+    }
+
+    return validator.issues;
+}
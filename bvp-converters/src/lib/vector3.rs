@@ -1,11 +1,14 @@
-use std::{ops::{Sub, Div, Mul, Add}, fmt::{Display, Debug}};
+use std::{ops::{Add, AddAssign, Div, Index, IndexMut, Mul, Rem, Sub, SubAssign}, fmt::{Display, Debug}};
 
-use num_traits::{PrimInt};
+use num_traits::{NumCast, PrimInt, ToPrimitive};
 use tinyjson::JsonValue;
 
 use crate::errors::JsonError;
 
-#[derive(Clone, Copy)]
+/// Vector of three numbers, used for positions, dimensions and scales.
+/// Arithmetic operators work component-wise between vectors of the same type,
+/// and with a scalar of the component type on the right (`v * 2`).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub struct Vector3<T> {
     pub x: T,
     pub y: T,
@@ -16,9 +19,56 @@ impl<T> Vector3<T> {
     pub fn from_xyz(x: T, y: T, z: T) -> Self {
         return Self { x, y, z };
     }
+
+    /// Creates a vector from an array of its components, in order x, y, z.
+    /// * `a` - the components
+    pub fn from_array(a: [T; 3]) -> Self {
+        let [x, y, z] = a;
+        return Self { x, y, z };
+    }
+
+    /// Returns the components as an array, in order x, y, z.
+    pub fn to_array(self) -> [T; 3] {
+        return [self.x, self.y, self.z];
+    }
+
+    /// Applies a function to every component and returns the vector of the results.
+    /// * `f` - the function
+    pub fn map<U, F: Fn(T) -> U>(self, f: F) -> Vector3<U> {
+        return Vector3 { x: f(self.x), y: f(self.y), z: f(self.z) };
+    }
+}
+
+impl<T: Copy> Vector3<T> {
+    /// Creates a vector with all components set to the same value.
+    /// * `v` - value of the components
+    pub fn splat(v: T) -> Self {
+        return Self { x: v, y: v, z: v };
+    }
+
+    /// Applies a function to the pairs of corresponding components of two vectors.
+    /// * `v` - the other vector
+    /// * `f` - the function
+    pub fn zip_map<U, F: Fn(T, T) -> U>(&self, v: &Vector3<T>, f: F) -> Vector3<U> {
+        return Vector3 { x: f(self.x, v.x), y: f(self.y, v.y), z: f(self.z, v.z) };
+    }
+
+    /// Combines the components with a function, for example to sum them.
+    /// * `f` - the function
+    fn fold<F: Fn(T, T) -> T>(&self, f: F) -> T {
+        return f(f(self.x, self.y), self.z);
+    }
 }
 
-impl<T: PartialOrd> Vector3<T> {
+impl<T: ToPrimitive + Copy> Vector3<T> {
+    /// Converts the components to another numeric type.
+    /// Returns None if any of them cannot be represented in it.
+    pub fn cast<U: NumCast>(&self) -> Option<Vector3<U>> {
+        return Some(Vector3 { x: U::from(self.x)?, y: U::from(self.y)?, z: U::from(self.z)? });
+    }
+}
+
+impl<T: PartialOrd + Copy> Vector3<T> {
     /// Returns true if any component in self is lower than
     /// corresponding component in vector v, else returns false.
     /// * `v` - vector to check againts
@@ -32,6 +82,20 @@ impl<T: PartialOrd> Vector3<T> {
     pub fn is_any_gt(&self, v: Vector3<T>) -> bool {
         return self.x > v.x || self.y > v.y || self.z > v.z;
     }
+
+    /// Returns a new vector containing the minimum
+    /// of the corresponding components in both vectors.
+    /// * `v` - the other vector
+    pub fn min(&self, v: &Vector3<T>) -> Vector3<T> {
+        return self.zip_map(v, |a, b| if b < a { b } else { a });
+    }
+
+    /// Returns a new vector containing the maximum
+    /// of the corresponding components in both vectors.
+    /// * `v` - the other vector
+    pub fn max(&self, v: &Vector3<T>) -> Vector3<T> {
+        return self.zip_map(v, |a, b| if b > a { b } else { a });
+    }
 }
 
 impl<T: PrimInt> Vector3<T> {
@@ -42,83 +106,136 @@ impl<T: PrimInt> Vector3<T> {
         return !(self.x % v.x).is_zero() || !(self.y % v.y).is_zero() || !(self.z % v.z).is_zero();
     }
 
-    /// Returns a new vector containing the minimum
-    /// of the corresponding components in both vectors.
+    /// Divides component-wise and rounds up, for example to count the blocks needed to cover a volume.
+    /// * `v` - the divisor
+    pub fn div_ceil(&self, v: &Vector3<T>) -> Vector3<T> {
+        return self.zip_map(v, |a, b| {
+            let quotient = a / b;
+            if (a % b).is_zero() { quotient } else { quotient + T::one() }
+        });
+    }
+
+    /// Adds component-wise, or returns None if any component overflows.
     /// * `v` - the other vector
-    pub fn min(&self, v: &Vector3<T>) -> Vector3<T> {
-        return Vector3 {
-            x: self.x.min(v.x),
-            y: self.y.min(v.y),
-            z: self.z.min(v.z)
+    pub fn checked_add(&self, v: &Vector3<T>) -> Option<Vector3<T>> {
+        return Some(Vector3 { x: self.x.checked_add(&v.x)?, y: self.y.checked_add(&v.y)?, z: self.z.checked_add(&v.z)? });
+    }
+
+    /// Subtracts component-wise, or returns None if any component overflows
+    /// (for unsigned vectors, if any component of `v` is larger).
+    /// * `v` - the vector to subtract
+    pub fn checked_sub(&self, v: &Vector3<T>) -> Option<Vector3<T>> {
+        return Some(Vector3 { x: self.x.checked_sub(&v.x)?, y: self.y.checked_sub(&v.y)?, z: self.z.checked_sub(&v.z)? });
+    }
+
+    /// Multiplies component-wise, or returns None if any component overflows.
+    /// * `v` - the other vector
+    pub fn checked_mul(&self, v: &Vector3<T>) -> Option<Vector3<T>> {
+        return Some(Vector3 { x: self.x.checked_mul(&v.x)?, y: self.y.checked_mul(&v.y)?, z: self.z.checked_mul(&v.z)? });
+    }
+
+    /// Adds component-wise, clamping components at the largest value instead of overflowing.
+    /// * `v` - the other vector
+    pub fn saturating_add(&self, v: &Vector3<T>) -> Vector3<T> {
+        return self.zip_map(v, |a, b| a.saturating_add(b));
+    }
+
+    /// Returns the product of all components, or None if it overflows.
+    pub fn checked_product(&self) -> Option<T> {
+        return self.x.checked_mul(&self.y)?.checked_mul(&self.z);
+    }
+}
+
+impl<T: Add<Output = T> + Copy> Vector3<T> {
+    /// Returns the sum of all components.
+    pub fn sum(&self) -> T {
+        return self.fold(|a, b| a + b);
+    }
+}
+
+impl<T: Mul<Output = T> + Copy> Vector3<T> {
+    /// Returns the product of all components.
+    pub fn product(&self) -> T {
+        return self.fold(|a, b| a * b);
+    }
+}
+
+impl<T: NumCast + Copy> Vector3<T> {
+    /// Creates 3D vector from JSON array.
+    /// Fails if the array does not have three numbers that fit into the component type.
+    /// * `j` - JSON array of numbers
+    pub fn from_json(j: &JsonValue) -> Result<Self, JsonError> {
+        let a = match j {
+            JsonValue::Array(a) if a.len() == 3 => a,
+            _ => return Err(JsonError::NotAVector3(j.clone()))
         };
+        let mut components = [T::from(0).unwrap(); 3];
+        for (i, (component, value)) in components.iter_mut().zip(a).enumerate() {
+            *component = match value {
+                JsonValue::Number(n) => match T::from(*n) {
+                    Some(c) => c,
+                    None => return Err(JsonError::NotAVector3(j.clone()))
+                },
+                _ => return Err(JsonError::NotANumber(value.clone()).at(&format!("/{}", i)))
+            };
+        }
+        return Ok(Vector3::from_array(components));
     }
+
+    /// Converts 3D vector to JSON array.
+    pub fn to_json(&self) -> JsonValue {
+        let v: Vec<JsonValue> = self.to_array().iter()
+            .map(|c| c.to_f64().unwrap_or(f64::NAN).into())
+            .collect();
+        return v.into();
+    }
+}
+
+/// Moves the lowest 21 bits of a value apart, so that two zero bits follow every bit.
+/// * `v` - the value
+fn spread_bits(v: u32) -> u64 {
+    let mut v = v as u64 & 0x1f_ffff;
+    v = (v | v << 32) & 0x1f_0000_0000_ffff;
+    v = (v | v << 16) & 0x1f_0000_ff00_00ff;
+    v = (v | v << 8) & 0x100f_00f0_0f00_f00f;
+    v = (v | v << 4) & 0x10c3_0c30_c30c_30c3;
+    v = (v | v << 2) & 0x1249_2492_4924_9249;
+    return v;
 }
 
 impl Vector3<u32> {
     /// Based on 3D index vector and dimensions,
-    /// calculates the 1D index and returns it.
+    /// calculates the 1D index and returns it. The index is computed
+    /// in `usize`, so it does not overflow as long as the structure fits into memory.
     /// * `i` - 3D index vector
     /// * `dim` - dimensions of the 3D structure
     pub fn linear_index(i: Vector3<u32>, dim: Vector3<u32>) -> usize {
-        let a1 = i.x + i.y * dim.x + i.z * dim.x * dim.y;
-        return a1 as usize;
+        let (x, y, z) = (i.x as usize, i.y as usize, i.z as usize);
+        return x + dim.x as usize * (y + dim.y as usize * z);
     }
 
-    /// Returns the product of all vector components (u32).
-    pub fn multiply_elements(&self) -> u32 {
-        return self.x * self.y * self.z;
+    /// Returns the Morton (Z-order) code of a 3D index, which interleaves the bits of the
+    /// components with X in the lowest bit. Indices close in space get close codes.
+    /// Only the lowest 21 bits of every component are used, so the code fits into `u64`.
+    pub fn morton_code(&self) -> u64 {
+        return spread_bits(self.x) | spread_bits(self.y) << 1 | spread_bits(self.z) << 2;
     }
 
-    /// Creates 3D vector (u32) from JSON array.
-    /// * `j` - JSON array of numbers
-    pub fn from_json(j: &JsonValue) -> Result<Self, JsonError> {
-        match j {
-            JsonValue::Array(a) => {
-                if a.len() != 3 {
-                    return Err(JsonError::NotAVector3(j.clone()));
-                }
-                let x = match a[0] {
-                    JsonValue::Number(n) => {
-                        n as u32
-                    },
-                    _ => {
-                        return Err(JsonError::NotANumber(a[0].clone()));
-                    }
-                };
-                let y = match a[1] {
-                    JsonValue::Number(n) => {
-                        n as u32
-                    },
-                    _ => {
-                        return Err(JsonError::NotANumber(a[1].clone()));
-                    }
-                };
-                let z = match a[2] {
-                    JsonValue::Number(n) => {
-                        n as u32
-                    },
-                    _ => {
-                        return Err(JsonError::NotANumber(a[2].clone()));
-                    }
-                };
-
-                let vec = Vector3::from_xyz(x, y, z);
-
-                return Ok(vec);
-            },
-            _ => ()
-        }
-        return Err(JsonError::NotAVector3(j.clone()));
+    /// Returns the product of all vector components in `u64`, or None if it does not fit,
+    /// which happens when all three are close to the largest `u32`.
+    pub fn multiply_elements(&self) -> Option<u64> {
+        return (self.x as u64).checked_mul(self.y as u64)?.checked_mul(self.z as u64);
     }
 
-    /// Converts 3D vector (u32) to JSON array.
-    pub fn to_json(&self) -> JsonValue {
-        let v = vec![
-            (self.x as f64).into(),
-            (self.y as f64).into(),
-            (self.z as f64).into()
-        ];
-        return v.into();
+    /// Parses 3D vector (u32) from three integers separated by `,` or `x`,
+    /// for example `0,0,0` or `256x256x128`.
+    /// * `value` - the text to parse
+    pub fn from_string(value: &str) -> Option<Self> {
+        let components: Vec<u32> = value.split([',', 'x']).map(|c| c.trim().parse::<u32>()).collect::<Result<_, _>>().ok()?;
+        if components.len() != 3 {
+            return None;
+        }
+        return Some(Vector3::from_xyz(components[0], components[1], components[2]));
     }
 }
 
@@ -127,132 +244,108 @@ impl Vector3<f32> {
     /// The returned vector is (u32), so all components
     /// are expected to be positive.
     pub fn ceil(&self) -> Vector3<u32> {
-        return Vector3 {
-            x: self.x.ceil() as u32,
-            y: self.y.ceil() as u32,
-            z: self.z.ceil() as u32
-        };
+        return self.map(|c| c.ceil() as u32);
     }
 
     /// Rounds all components of the vector (f32) down.
     /// The returned vector is (u32), so all components
     /// are expected to be positive.
     pub fn to_u32(&self) -> Vector3<u32> {
-        return Vector3 {
-            x: self.x as u32,
-            y: self.y as u32,
-            z: self.z as u32
-        };
+        return self.map(|c| c as u32);
     }
+}
 
-    /// Converts 3D vector (f32) to JSON array.
-    pub fn to_json(&self) -> JsonValue {
-        let v = vec![
-            (self.x as f64).into(),
-            (self.y as f64).into(),
-            (self.z as f64).into()
-        ];
-        return v.into();
+impl From<Vector3<u32>> for Vector3<f32> {
+    fn from(v: Vector3<u32>) -> Self {
+        return v.map(|c| c as f32);
     }
+}
 
-    /// Creates 3D vector (f32) from JSON array.
-    /// * `j` - JSON array of numbers
-    pub fn from_json(j: &JsonValue) -> Result<Self, JsonError> {
-        match j {
-            JsonValue::Array(a) => {
-                if a.len() != 3 {
-                    return Err(JsonError::NotAVector3(j.clone()));
-                }
-                let x = match a[0] {
-                    JsonValue::Number(n) => {
-                        n as f32
-                    },
-                    _ => {
-                        return Err(JsonError::NotANumber(a[0].clone()));
-                    }
-                };
-                let y = match a[1] {
-                    JsonValue::Number(n) => {
-                        n as f32
-                    },
-                    _ => {
-                        return Err(JsonError::NotANumber(a[1].clone()));
-                    }
-                };
-                let z = match a[2] {
-                    JsonValue::Number(n) => {
-                        n as f32
-                    },
-                    _ => {
-                        return Err(JsonError::NotANumber(a[2].clone()));
-                    }
-                };
-
-                let vec = Vector3 { x, y, z };
-
-                return Ok(vec);
-            },
-            _ => ()
-        }
-        return Err(JsonError::NotAVector3(j.clone()));
+impl From<Vector3<u32>> for Vector3<u64> {
+    fn from(v: Vector3<u32>) -> Self {
+        return v.map(|c| c as u64);
     }
 }
 
-impl Into<Vector3<f32>> for Vector3<u32> {
-    fn into(self) -> Vector3<f32> {
-        return Vector3 {
-            x: self.x as f32,
-            y: self.y as f32,
-            z: self.z as f32
-        };
+impl From<Vector3<u32>> for Vector3<usize> {
+    fn from(v: Vector3<u32>) -> Self {
+        return v.map(|c| c as usize);
     }
 }
 
-impl Add for Vector3<u32> {
-    type Output = Vector3<u32>;
+impl<T> Index<usize> for Vector3<T> {
+    type Output = T;
 
-    fn add(self, rhs: Self) -> Self::Output {
-        return Vector3 {
-            x: self.x + rhs.x,
-            y: self.y + rhs.y,
-            z: self.z + rhs.z
+    /// Returns x, y or z for index 0, 1 or 2. Panics on other indices.
+    fn index(&self, index: usize) -> &Self::Output {
+        return match index {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vector3 index {} is out of range", index)
         };
     }
 }
 
-impl Sub for Vector3<u32> {
-    type Output = Vector3<u32>;
-
-    fn sub(self, rhs: Self) -> Self::Output {
-        return Vector3 {
-            x: self.x - rhs.x,
-            y: self.y - rhs.y,
-            z: self.z - rhs.z
+impl<T> IndexMut<usize> for Vector3<T> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        return match index {
+            0 => &mut self.x,
+            1 => &mut self.y,
+            2 => &mut self.z,
+            _ => panic!("Vector3 index {} is out of range", index)
         };
     }
 }
 
-impl Mul for Vector3<u32> {
-    type Output = Vector3<u32>;
+/// Implements a component-wise operator between vectors, and between a vector and a scalar.
+macro_rules! component_wise {
+    ($trait:ident, $method:ident, $op:tt) => {
+        impl<T: $trait<Output = T>> $trait for Vector3<T> {
+            type Output = Vector3<T>;
 
-    fn mul(self, rhs: Self) -> Self::Output {
-        return Vector3 {
-            x: self.x * rhs.x,
-            y: self.y * rhs.y,
-            z: self.z * rhs.z
-        };
-    }
+            fn $method(self, rhs: Self) -> Self::Output {
+                return Vector3 {
+                    x: self.x $op rhs.x,
+                    y: self.y $op rhs.y,
+                    z: self.z $op rhs.z
+                };
+            }
+        }
+
+        impl<T: $trait<Output = T> + Copy> $trait<T> for Vector3<T> {
+            type Output = Vector3<T>;
+
+            fn $method(self, rhs: T) -> Self::Output {
+                return Vector3 {
+                    x: self.x $op rhs,
+                    y: self.y $op rhs,
+                    z: self.z $op rhs
+                };
+            }
+        }
+    };
 }
 
-impl Div for Vector3<u32> {
-    type Output = Vector3<f32>;
+component_wise!(Add, add, +);
+component_wise!(Sub, sub, -);
+component_wise!(Mul, mul, *);
+component_wise!(Div, div, /);
+component_wise!(Rem, rem, %);
 
-    fn div(self, rhs: Self) -> Self::Output {
-        return Vector3 {
-            x: self.x as f32 / rhs.x as f32,
-            y: self.y as f32 / rhs.y as f32,
-            z: self.z as f32 / rhs.z as f32
-        };
+impl<T: AddAssign> AddAssign for Vector3<T> {
+    fn add_assign(&mut self, rhs: Self) {
+        self.x += rhs.x;
+        self.y += rhs.y;
+        self.z += rhs.z;
+    }
+}
+
+impl<T: SubAssign> SubAssign for Vector3<T> {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.x -= rhs.x;
+        self.y -= rhs.y;
+        self.z -= rhs.z;
     }
 }
 
@@ -266,4 +359,4 @@ impl<T: Debug> Debug for Vector3<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         return write!(f, "[{:?} {:?} {:?}]", self.x, self.y, self.z);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::errors::VersionError;
+
+/// Version of the BVP specification a manifest follows, as `<major>.<minor>`.
+/// Minor versions only add to the specification, so a reader can read manifests
+/// of older minor versions of its major version, and newer ones with a warning.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SpecVersion {
+    pub major: u32,
+    pub minor: u32
+}
+
+impl SpecVersion {
+    /// The version written by default.
+    pub const CURRENT: SpecVersion = SpecVersion { major: 1, minor: 0 };
+    /// Versions that can be written, oldest first.
+    pub const WRITABLE: [SpecVersion; 1] = [SpecVersion::CURRENT];
+
+    pub fn new(major: u32, minor: u32) -> Self {
+        return Self { major, minor };
+    }
+
+    /// Parses a version such as `1.0`. A missing minor version is read as 0.
+    /// * `s` - the version as written in the manifest
+    pub fn from_string(s: &str) -> Result<Self, VersionError> {
+        let (major, minor) = match s.split_once('.') {
+            Some((major, minor)) => (major, minor),
+            None => (s, "0")
+        };
+        return match (major.parse::<u32>(), minor.parse::<u32>()) {
+            (Ok(major), Ok(minor)) => Ok(Self::new(major, minor)),
+            _ => Err(VersionError::Invalid(s.to_string()))
+        };
+    }
+
+    /// Returns true if manifests of this version can be read. Assets with a newer minor
+    /// version can be read too, but may use features this library ignores.
+    pub fn is_readable(&self) -> bool {
+        return self.major == Self::CURRENT.major;
+    }
+
+    /// Returns true if the version is newer than the one this library implements.
+    pub fn is_newer(&self) -> bool {
+        return *self > Self::CURRENT;
+    }
+
+    /// Returns the version if manifests of it can be written, or an error otherwise.
+    /// * `s` - the requested version
+    pub fn writable(s: &str) -> Result<Self, VersionError> {
+        let version = Self::from_string(s)?;
+        if !Self::WRITABLE.contains(&version) {
+            return Err(VersionError::Unsupported(version.to_string()));
+        }
+        return Ok(version);
+    }
+}
+
+impl fmt::Display for SpecVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}.{}", self.major, self.minor);
+    }
+}
+
+/// Returns the current time as an ISO 8601 timestamp in UTC, for example `2024-05-01T12:30:00Z`,
+/// as used for `creationTime`.
+pub fn timestamp_now() -> String {
+    return Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+}
+
+/// Returns true if a text is an ISO 8601 date and time with a time zone, as `creationTime` should be.
+/// * `s` - the text to check
+pub fn is_timestamp(s: &str) -> bool {
+    return DateTime::parse_from_rfc3339(s).is_ok();
+}
+
+/// Returns the `generator` of assets written by a tool, with the name and version of this crate
+/// and the git commit it was built from, if known, for example `raw2bvp (bvp-tool 0.1.0, 3fc8df9)`.
+/// * `tool` - name of the tool or application writing the asset
+pub fn generator(tool: &str) -> String {
+    let crate_version = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    return match option_env!("BVP_GIT_HASH") {
+        Some(hash) => format!("{} ({}, {})", tool, crate_version, hash),
+        None => format!("{} ({})", tool, crate_version)
+    };
+}
@@ -0,0 +1,98 @@
+//! Functions exported for WebAssembly, so that viewers in the browser can use the manifest
+//! parser and the LZ4S decoder of this library. Build with
+//! `cargo build --lib --target wasm32-unknown-unknown --no-default-features --features wasm`.
+//!
+//! They take plain pointers into the memory of the module: JavaScript allocates a buffer
+//! with `bvp_alloc`, copies an ArrayBuffer into it and passes the pointer and length.
+//! Functions return 0 on success and 1 on failure. Either way they leave their result,
+//! or the error message, in a buffer described by `bvp_result_ptr` and `bvp_result_len`,
+//! which stays valid until the next call.
+
+use std::{cell::RefCell, slice, str};
+
+use crate::{bvpfile::BVPFile, compressions::CompressionType};
+
+thread_local! {
+    static RESULT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+fn set_result(result: Result<Vec<u8>, String>) -> u32 {
+    let (status, bytes) = match result {
+        Ok(r) => (0, r),
+        Err(e) => (1, e.into_bytes())
+    };
+    RESULT.with(|r| *r.borrow_mut() = bytes);
+    return status;
+}
+
+/// Returns the bytes passed to a function.
+/// # Safety
+/// `data` has to point to `length` bytes allocated with `bvp_alloc`, or `length` has to be 0.
+unsafe fn input<'a>(data: *const u8, length: usize) -> &'a [u8] {
+    if length == 0 {
+        return &[];
+    }
+    return slice::from_raw_parts(data, length);
+}
+
+/// Allocates a buffer in the memory of the module.
+/// * `length` - size of the buffer in bytes
+#[no_mangle]
+pub extern "C" fn bvp_alloc(length: usize) -> *mut u8 {
+    let mut buffer = vec![0u8; length].into_boxed_slice();
+    let pointer = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    return pointer;
+}
+
+/// Frees a buffer allocated with `bvp_alloc`.
+/// * `pointer` - the buffer
+/// * `length` - its size, as given to `bvp_alloc`
+/// # Safety
+/// `pointer` has to come from `bvp_alloc(length)` and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn bvp_free(pointer: *mut u8, length: usize) {
+    if !pointer.is_null() {
+        drop(Box::from_raw(slice::from_raw_parts_mut(pointer, length)));
+    }
+}
+
+/// Returns the result of the last call.
+#[no_mangle]
+pub extern "C" fn bvp_result_ptr() -> *const u8 {
+    return RESULT.with(|r| r.borrow().as_ptr());
+}
+
+/// Returns the length of the result of the last call.
+#[no_mangle]
+pub extern "C" fn bvp_result_len() -> usize {
+    return RESULT.with(|r| r.borrow().len());
+}
+
+/// Parses and checks a manifest, and returns it as JSON, with default values filled in.
+/// Block files are not needed, blocks keep their `data` names so they can be fetched on demand.
+/// * `manifest`, `length` - the text of `manifest.json`
+/// # Safety
+/// `manifest` has to point to `length` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bvp_parse_manifest(manifest: *const u8, length: usize) -> u32 {
+    let result = str::from_utf8(input(manifest, length))
+        .map_err(|e| format!("the manifest is not UTF-8: {}", e))
+        .and_then(|content| BVPFile::from_manifest(content, &Vec::new()).map_err(|e| e.to_string()))
+        .and_then(|bvp_file| bvp_file.to_manifest().map_err(|e| e.to_string()));
+    return set_result(result);
+}
+
+/// Decodes a block file encoded with LZ4S.
+/// * `data`, `length` - the block file
+/// * `decoded_length` - size of the decoded block, from its dimensions and format
+/// # Safety
+/// `data` has to point to `length` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bvp_decode_lz4s(data: *const u8, length: usize, decoded_length: usize) -> u32 {
+    let decoded = CompressionType::LZ4S.decompress(&input(data, length).to_vec(), decoded_length);
+    if decoded.len() != decoded_length {
+        return set_result(Err(format!("the block decodes to {} bytes instead of {}", decoded.len(), decoded_length)));
+    }
+    return set_result(Ok(decoded));
+}
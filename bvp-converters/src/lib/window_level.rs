@@ -0,0 +1,169 @@
+//! Default display ranges of a modality, stored in the `EXT_window_level` payload of the modality:
+//!
+//! ```json
+//! "extensions": {
+//!     "EXT_window_level": {
+//!         "presets": [{ "name": "auto", "center": 1040, "width": 2100 }, { "name": "bone", "center": 400, "width": 1800 }]
+//!     }
+//! }
+//! ```
+//!
+//! A preset is given by the center and width of the window, as in DICOM, so values from
+//! `center - width / 2` to `center + width / 2` are shown from black to white. The first preset is the default.
+
+use std::collections::HashMap;
+
+use tinyjson::JsonValue;
+
+use crate::{errors::{JsonError, WindowLevelError}, extensions::Extension, formats::{Format, PrimitiveType}, image::Window, json_aux, modality::Modality};
+
+/// Name of the preset computed from the histogram of a volume, see `auto_window`.
+pub const AUTO_PRESET: &str = "auto";
+/// Percentiles between which automatic windows are taken, see `auto_window`.
+pub const AUTO_PERCENTILES: [f64; 2] = [0.005, 0.995];
+/// Bins of the histogram `auto_window` computes for values that are not 8 or 16 bit integers.
+const HISTOGRAM_BINS: usize = 4096;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowPreset {
+    pub name: Option<String>,
+    pub window: Window
+}
+
+impl WindowPreset {
+    pub fn center(&self) -> f64 {
+        return (self.window.low + self.window.high) / 2.0;
+    }
+
+    pub fn width(&self) -> f64 {
+        return self.window.high - self.window.low;
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut hm = HashMap::new();
+        if let Some(name) = &self.name {
+            hm.insert("name".to_string(), name.clone().into());
+        }
+        hm.insert("center".to_string(), self.center().into());
+        hm.insert("width".to_string(), self.width().into());
+        return hm.into();
+    }
+
+    /// * `index` - position of the preset in the list, for errors
+    /// * `j` - the preset
+    pub fn from_json(index: usize, j: &JsonValue) -> Result<Self, WindowLevelError> {
+        let hashmap = match j {
+            JsonValue::Object(o) => o,
+            _ => return Err(WindowLevelError::InvalidJson(index, JsonError::NotAnObject(j.clone())))
+        };
+        let name = match hashmap.get("name") {
+            Some(n) => Some(json_aux::get_string_from_json(n).map_err(|e| WindowLevelError::InvalidJson(index, e))?),
+            None => None
+        };
+        let number = |key: &str| {
+            return match hashmap.get(key) {
+                Some(JsonValue::Number(n)) => Ok(*n),
+                Some(v) => Err(WindowLevelError::InvalidJson(index, JsonError::NotANumber(v.clone()))),
+                None => Err(WindowLevelError::MissingField(index, key.to_string()))
+            };
+        };
+        let (center, width) = (number("center")?, number("width")?);
+        if !center.is_finite() || !width.is_finite() || width <= 0.0 {
+            return Err(WindowLevelError::InvalidWindow(index));
+        }
+        return Ok(Self { name, window: Window::new(center - width / 2.0, center + width / 2.0) });
+    }
+}
+
+/// Returns the window/level presets of a modality, the default one first. Modalities without them return an empty list.
+/// * `modality` - the modality
+pub fn presets(modality: &Modality) -> Result<Vec<WindowPreset>, WindowLevelError> {
+    let payload = match modality.extension_payloads.get(&Extension::ExtWindowLevel.to_string()) {
+        Some(JsonValue::Object(o)) => o,
+        Some(j) => return Err(WindowLevelError::InvalidJson(0, JsonError::NotAnObject(j.clone()))),
+        None => return Ok(Vec::new())
+    };
+    let list = match payload.get("presets") {
+        Some(l) => json_aux::get_array_from_json(l).map_err(|e| WindowLevelError::InvalidJson(0, e))?,
+        None => return Ok(Vec::new())
+    };
+    return list.iter().enumerate().map(|(index, j)| WindowPreset::from_json(index, j)).collect();
+}
+
+/// Returns the window of the default preset of a modality, if it has presets.
+/// * `modality` - the modality
+pub fn default_window(modality: &Modality) -> Result<Option<Window>, WindowLevelError> {
+    return Ok(presets(modality)?.into_iter().next().map(|p| p.window));
+}
+
+/// Sets the window/level presets of a modality, replacing those it has. An empty list removes them.
+/// * `modality` - the modality
+/// * `presets` - the presets, the default one first
+pub fn set_presets(modality: &mut Modality, presets: &[WindowPreset]) -> Result<(), WindowLevelError> {
+    let name = Extension::ExtWindowLevel.to_string();
+    if presets.is_empty() {
+        modality.extension_payloads.remove(&name);
+        return Ok(());
+    }
+    for (index, preset) in presets.iter().enumerate() {
+        if !preset.window.low.is_finite() || !preset.window.high.is_finite() || preset.window.low >= preset.window.high {
+            return Err(WindowLevelError::InvalidWindow(index));
+        }
+    }
+    let list: Vec<JsonValue> = presets.iter().map(|p| p.to_json()).collect();
+    let mut payload = HashMap::new();
+    payload.insert("presets".to_string(), JsonValue::from(list));
+    modality.extension_payloads.insert(name, payload.into());
+    return Ok(());
+}
+
+/// Returns the window between two percentiles of the first component of a volume, so that a few
+/// outliers, like the air around a CT scan or hot pixels, do not compress the range of the rest.
+/// 8 and 16 bit integers are counted exactly, other values in a histogram of 4096 bins.
+/// Returns None for formats whose values cannot be read, and for volumes of a single value.
+/// * `format` - format of the volume
+/// * `data` - the voxels
+/// * `low_fraction`, `high_fraction` - the percentiles, as fractions between 0 and 1
+pub fn auto_window(format: &Format, data: &[u8], low_fraction: f64, high_fraction: f64) -> Option<Window> {
+    let component = format.first_component()?;
+    let voxel_size = format.microblock_size.max(1) as usize;
+    let component_size = component.component_size() as usize;
+    let values = || data.chunks_exact(voxel_size).map(|voxel| component.component_value(&voxel[..component_size]));
+
+    let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+    for value in values().filter(|v| v.is_finite()) {
+        min = min.min(value);
+        max = max.max(value);
+    }
+    if !(min < max) {
+        return None;
+    }
+    // Integers of up to 16 bits get a bin for every value.
+    let exact = component.component_type() != &PrimitiveType::Float && component_size <= 2;
+    let bins = if exact { (max - min) as usize + 1 } else { HISTOGRAM_BINS };
+    let scale = if exact { 1.0 } else { (bins - 1) as f64 / (max - min) };
+    let mut histogram = vec![0u64; bins];
+    let mut total = 0u64;
+    for value in values().filter(|v| v.is_finite()) {
+        histogram[((value - min) * scale) as usize] += 1;
+        total += 1;
+    }
+
+    let bin_value = |bin: usize| min + bin as f64 / scale;
+    let percentile = |fraction: f64| {
+        let target = (fraction.clamp(0.0, 1.0) * total as f64).ceil().max(1.0) as u64;
+        let mut count = 0;
+        for (bin, n) in histogram.iter().enumerate() {
+            count += n;
+            if count >= target {
+                return bin_value(bin);
+            }
+        }
+        return max;
+    };
+    let (low, high) = (percentile(low_fraction), percentile(high_fraction));
+    if low < high {
+        return Some(Window::new(low, high));
+    }
+    return Some(Window::new(min, max));
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use xxhash_rust::xxh3;
+
+#[cfg(feature = "fs")]
+use crate::archives::ArchiveEnum;
+use crate::{block::Block, bvpfile::BVPFile, bytes::Bytes, checksum::{Checksum, ChecksumType}, compressions::CompressionType, errors::{BlockError, WriterError}, file::File, formats::Format, modality::Modality, placement::Placement, vector3::Vector3, version};
+
+/// Writes a single volume as a BVP asset. The volume is split into blocks of the same
+/// dimensions, which are placed in one root block, and blocks with the same data are stored once.
+/// This is what `raw2bvp` does for a single input without superblocks, for applications
+/// that have the volume in memory. Options are set with the `with_` methods.
+pub struct VolumeWriter {
+    block_dimensions: Vector3<u32>,
+    compression: CompressionType,
+    checksum: Option<ChecksumType>,
+    deduplication: bool,
+    name: Option<String>,
+    generator: Option<String>
+}
+
+impl VolumeWriter {
+    /// Creates a writer with blocks of the given dimensions, uncompressed and deduplicated.
+    /// * `block_dimensions` - dimensions of the blocks, a multiple of the microblock dimensions of the format
+    pub fn new(block_dimensions: Vector3<u32>) -> Self {
+        return Self {
+            block_dimensions,
+            compression: CompressionType::None,
+            checksum: None,
+            deduplication: true,
+            name: None,
+            generator: None
+        };
+    }
+
+    /// Sets the compression of the block files.
+    /// * `compression` - the compression
+    pub fn with_compression(mut self, compression: CompressionType) -> Self {
+        self.compression = compression;
+        return self;
+    }
+
+    /// Sets whether a checksum of every block file is recorded.
+    /// * `checksum` - the algorithm, or None for no checksums
+    pub fn with_checksum(mut self, checksum: Option<ChecksumType>) -> Self {
+        self.checksum = checksum;
+        return self;
+    }
+
+    /// Sets whether blocks with the same data are stored only once.
+    /// * `deduplication` - whether to deduplicate
+    pub fn with_deduplication(mut self, deduplication: bool) -> Self {
+        self.deduplication = deduplication;
+        return self;
+    }
+
+    /// Sets the name of the asset and of its modality.
+    /// * `name` - the name
+    pub fn with_name(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        return self;
+    }
+
+    /// Sets the `generator` of the asset, by default `bvp library` with the version of the library.
+    /// * `generator` - the generator, such as the name and version of the application
+    pub fn with_generator(mut self, generator: Option<String>) -> Self {
+        self.generator = generator;
+        return self;
+    }
+
+    /// Splits a volume into blocks and returns the asset, with the block files and the manifest in `files`.
+    /// * `data` - voxels of the volume, X changing fastest
+    /// * `dimensions` - dimensions of the volume
+    /// * `format` - format of the voxels
+    pub fn build(&self, data: Vec<u8>, dimensions: Vector3<u32>, format: Format) -> Result<BVPFile, WriterError> {
+        let microblock = format.microblock_dimensions;
+        if self.block_dimensions.product() == 0 || self.block_dimensions % microblock != Vector3::splat(0) {
+            return Err(WriterError::InvalidBlockDimensions(self.block_dimensions, microblock));
+        }
+        if dimensions.product() == 0 || dimensions % microblock != Vector3::splat(0) {
+            return Err(WriterError::InvalidDimensions(dimensions, microblock));
+        }
+        let expected_size = match format.checked_count_space(dimensions) {
+            Some(s) => s,
+            None => return Err(WriterError::InvalidDimensions(dimensions, microblock))
+        };
+        if data.len() != expected_size {
+            return Err(WriterError::DataSizeMismatch(data.len(), dimensions, expected_size));
+        }
+
+        let mut bvp_file = BVPFile::new();
+        bvp_file.formats.push(format);
+        bvp_file.modalities.push(Modality::new(self.name.clone(), None, None, Vector3::splat(1.0), None, 0));
+        bvp_file.asset.name = self.name.clone();
+        bvp_file.asset.generator = Some(self.generator.clone().unwrap_or_else(|| version::generator("bvp library")));
+        bvp_file.asset.creation_time = Some(version::timestamp_now());
+
+        let root = Block::new(0, dimensions, Some(0), Some(data));
+        let mut placements = Vec::new();
+        // Created blocks, with their data before it is compressed.
+        let mut blocks: Vec<(Block, Bytes)> = Vec::new();
+        let mut files = Vec::new();
+        let mut block_map: HashMap<u64, usize> = HashMap::new();
+        let block_count = dimensions.div_ceil(&self.block_dimensions);
+        for z in 0..block_count.z {
+            for y in 0..block_count.y {
+                for x in 0..block_count.x {
+                    let block_start = self.block_dimensions * Vector3::from_xyz(x, y, z);
+                    let block_end = (block_start + self.block_dimensions).min(&dimensions);
+                    let block = root.get_data_in_range(block_start, block_end, &bvp_file.formats[0]).map_err(WriterError::BlockError)?;
+                    let block_data = match block.data {
+                        Some(d) => d,
+                        None => return Err(WriterError::BlockError(BlockError::NoData(0)))
+                    };
+
+                    let hash = if self.deduplication { xxh3::xxh3_64(&block_data) } else { 0 };
+                    // Hashes are compared first, the data only if they are equal.
+                    let existing = match block_map.get(&hash) {
+                        Some(index) if self.deduplication && blocks[*index - 1].1 == block_data => Some(*index),
+                        _ => None
+                    };
+                    let block_index = match existing {
+                        Some(index) => index,
+                        None => {
+                            let index = blocks.len() + 1;
+                            let name = format!("blocks/block_{}.{}", index, self.compression.to_string());
+                            let encoded = Bytes::from(self.compression.compress(block_data.to_vec()));
+                            let mut new_block = Block::new(index, block.dimensions, Some(0), None);
+                            new_block.encoding = Some(self.compression);
+                            new_block.data_url = Some(name.clone());
+                            new_block.checksum = self.checksum.map(|algorithm| Checksum::compute(algorithm, &encoded));
+                            new_block.data = Some(encoded.clone());
+                            files.push(File::new(name, encoded, None));
+                            if self.deduplication {
+                                block_map.entry(hash).or_insert(index);
+                            }
+                            blocks.push((new_block, block_data));
+                            index
+                        }
+                    };
+                    placements.push(Placement::new(block_start, block_index));
+                }
+            }
+        }
+
+        let mut root = Block::new(0, dimensions, Some(0), None);
+        root.placements = placements;
+        bvp_file.blocks.push(root);
+        bvp_file.blocks.extend(blocks.into_iter().map(|(block, _)| block));
+        bvp_file.block_map = block_map;
+
+        let manifest = bvp_file.to_manifest().map_err(WriterError::BvpFileError)?;
+        files.push(File::new("manifest.json".to_string(), manifest, Some("application/json".to_string())));
+        bvp_file.files = files;
+        return Ok(bvp_file);
+    }
+
+    /// Splits a volume into blocks and writes the asset.
+    /// * `path` - the archive file, or for unarchived assets the folder the manifest and blocks are written to
+    /// * `archive` - the type of archive
+    /// * `data` - voxels of the volume, X changing fastest
+    /// * `dimensions` - dimensions of the volume
+    /// * `format` - format of the voxels
+    #[cfg(feature = "fs")]
+    pub fn write(&self, path: &Path, archive: &ArchiveEnum, data: Vec<u8>, dimensions: Vector3<u32>, format: Format) -> Result<(), WriterError> {
+        let bvp_file = self.build(data, dimensions, format)?;
+        let mut writer = archive.return_writer(&path.to_string_lossy());
+        for file in &bvp_file.files {
+            writer.append_file(file).map_err(WriterError::ArchiveError)?;
+        }
+        writer.finish(path.to_string_lossy().to_string()).map_err(WriterError::ArchiveError)?;
+        return Ok(());
+    }
+}
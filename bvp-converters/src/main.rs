@@ -1,3 +1,9 @@
-fn main() {
-    println!("Hello, world!");
+//! `bvp`, which runs the tools of this crate as commands, such as `bvp pack` and `bvp info`.
+
+mod cli;
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    return cli::main(None);
 }
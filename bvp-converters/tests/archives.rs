@@ -0,0 +1,137 @@
+//! Tests of reading and writing SAF and ZIP archives in memory.
+
+use std::{fs, path::Path};
+
+use bvp::archives::{saf, zip, ArchiveEnum};
+use bvp::bytes::Bytes;
+use bvp::errors::{SafError, ZipError};
+use bvp::file::File;
+use bvp::formats::{self, Format};
+use bvp::vector3::Vector3;
+
+/// Signature of the ZIP64 end of central directory record.
+const ZIP64_EOCD_SIG: [u8; 4] = [0x50, 0x4b, 0x06, 0x06];
+
+fn files(count: usize) -> Vec<File> {
+    return (0..count).map(|i| File::new(format!("blocks/block_{}.raw", i), (i as u32).to_le_bytes().to_vec(), None)).collect();
+}
+
+fn has_zip64_records(archive: &[u8]) -> bool {
+    return archive.windows(4).any(|w| w == ZIP64_EOCD_SIG);
+}
+
+#[test]
+fn archives_with_more_than_65535_members_use_zip64() {
+    let small = zip::to_zip_archive(&files(3)).unwrap();
+    assert!(!has_zip64_records(&small));
+
+    let many = files(70_000);
+    let archive = zip::to_zip_archive(&many).unwrap();
+    assert!(has_zip64_records(&archive));
+    let read = ArchiveEnum::ZIP.read_bytes(&Bytes::from(archive)).unwrap();
+    assert_eq!(read.len(), many.len());
+    assert_eq!(read[69_999].name, "blocks/block_69999.raw");
+    assert_eq!(read[69_999].data.as_slice(), 69_999u32.to_le_bytes());
+    assert!(read == many);
+}
+
+#[test]
+fn sizes_beyond_4_gib_do_not_overflow() {
+    let format = Format::from_json(&formats::shorthand_to_json("u16").unwrap()).unwrap();
+    let dimensions = Vector3::from_xyz(2048, 2048, 1024);
+    assert_eq!(format.count_space(dimensions), Some(8 << 30));
+    assert_eq!(format.count_microblocks(dimensions.multiply_elements().unwrap()), Some(8 << 30));
+    assert_eq!(format.checked_count_space(dimensions), Some(8 << 30));
+
+    // Sizes beyond `u64` are None rather than wrapping around.
+    let largest = Vector3::splat(u32::MAX);
+    assert_eq!(format.count_space(largest), None);
+    assert_eq!(format.checked_count_space(largest), None);
+    assert_eq!(format.count_microblocks(u64::MAX), None);
+}
+
+/// Length of the identifier at the start of SAF archives.
+const SAF_IDENTIFIER_LENGTH: usize = 12;
+
+#[test]
+fn corrupt_saf_archives_are_errors() {
+    let archive = saf::to_saf_archive(&files(3)).unwrap();
+    assert!(saf::from_saf_archive(&Bytes::from(archive.clone())).unwrap() == files(3));
+    let read = |data: Vec<u8>| saf::from_saf_archive(&Bytes::from(data));
+
+    assert!(matches!(read(archive[..5].to_vec()), Err(SafError::BrokenFile)));
+    let mut identifier = archive.clone();
+    identifier[1] = b'Z';
+    assert!(matches!(read(identifier), Err(SafError::NotValidIdentifier)));
+    let mut manifest_size = archive.clone();
+    manifest_size[SAF_IDENTIFIER_LENGTH..SAF_IDENTIFIER_LENGTH + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(matches!(read(manifest_size), Err(SafError::OutOfBounds(what, _, _)) if what == "the manifest"));
+    let result = read(archive[..archive.len() - 1].to_vec());
+    assert!(matches!(&result, Err(SafError::OutOfBounds(what, _, 4)) if what == "file `blocks/block_2.raw`"), "{:?}", result.err());
+
+    // Sizes in the manifest that point past the end of the archive, or overflow, are errors too.
+    let manifest_start = SAF_IDENTIFIER_LENGTH + 4;
+    let manifest_length = u32::from_le_bytes(archive[SAF_IDENTIFIER_LENGTH..manifest_start].try_into().unwrap()) as usize;
+    let text = std::str::from_utf8(&archive[manifest_start..manifest_start + manifest_length]).unwrap();
+    for (old, new) in [(r#""size":4"#, r#""size":1e18"#), (r#""path":"#, r#""name":"#)] {
+        let manifest = text.replacen(old, new, 1);
+        assert_ne!(manifest, text, "{}", old);
+        let mut edited = archive[..SAF_IDENTIFIER_LENGTH].to_vec();
+        edited.extend_from_slice(&(manifest.len() as u32).to_le_bytes());
+        edited.extend_from_slice(manifest.as_bytes());
+        edited.extend_from_slice(&archive[manifest_start + manifest_length..]);
+        let result = read(edited);
+        assert!(matches!(result, Err(SafError::OutOfBounds(_, _, _)) | Err(SafError::MissingField(0, _))), "{}: {:?}", new, result.err());
+    }
+}
+
+#[test]
+fn corrupt_zip_archives_are_errors() {
+    let archive = zip::to_zip_archive(&files(3)).unwrap();
+    let read = |data: Vec<u8>| zip::from_zip_archive(&Bytes::from(data));
+    // The end of central directory record is 22 bytes at the end, its fields follow the 4 byte signature.
+    let eocd = archive.len() - 22;
+
+    assert!(matches!(read(archive[..eocd].to_vec()), Err(ZipError::CorruptFile(_))));
+    assert!(matches!(read(archive[..archive.len() - 4].to_vec()), Err(ZipError::OutOfBounds(_, _, _))));
+    let mut central_directory = archive.clone();
+    central_directory[eocd + 16..eocd + 20].copy_from_slice(&((archive.len() + 100) as u32).to_le_bytes());
+    assert!(matches!(read(central_directory), Err(ZipError::OutOfBounds(_, _, 46))));
+    let mut records = archive.clone();
+    records[eocd + 10..eocd + 12].copy_from_slice(&4u16.to_le_bytes());
+    // The fourth header would start at the end of central directory record, which is too short for one.
+    assert!(matches!(read(records), Err(ZipError::OutOfBounds(record, _, 46)) if record == "central directory file header"));
+    let mut local_header = archive.clone();
+    local_header[0] = 0;
+    assert!(matches!(read(local_header), Err(ZipError::InvalidSignature(record, 0)) if record == "local file header"));
+    // A ZIP64 marker without ZIP64 records.
+    let mut marker = archive.clone();
+    marker[eocd + 10..eocd + 12].copy_from_slice(&u16::MAX.to_le_bytes());
+    assert!(read(marker).is_err());
+}
+
+/// Returns the offset of the first central directory file header of a ZIP archive.
+fn central_directory(archive: &[u8]) -> usize {
+    return archive.windows(4).position(|w| w == [0x50, 0x4b, 0x01, 0x02]).unwrap();
+}
+
+#[test]
+fn deflated_members_are_checked() {
+    // Written by Python's `zipfile` to a stream, so the local headers leave the sizes and CRCs to data descriptors.
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/mono-u8-deflate-zip/asset.zip");
+    let archive = fs::read(path).unwrap();
+    let flags = u16::from_le_bytes([archive[6], archive[7]]);
+    assert_eq!(flags & 0b1000, 0b1000, "The first member has no data descriptor");
+    let files = zip::from_zip_archive(&Bytes::from(archive.clone())).unwrap();
+    assert!(files.iter().any(|f| f.name.ends_with(".json")));
+
+    let header = central_directory(&archive);
+    let mut crc = archive.clone();
+    crc[header + 16] ^= 1;
+    let result = zip::from_zip_archive(&Bytes::from(crc));
+    assert!(matches!(&result, Err(ZipError::CorruptFile(message)) if message.contains("does not match its size and CRC")), "{:?}", result.err());
+
+    let mut method = archive.clone();
+    method[header + 10..header + 12].copy_from_slice(&12u16.to_le_bytes());
+    assert!(matches!(zip::from_zip_archive(&Bytes::from(method)), Err(ZipError::UnsupportedCompression(_, 12))));
+}
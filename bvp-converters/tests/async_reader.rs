@@ -0,0 +1,65 @@
+//! Reads assets with `AsyncVolumeReader` and compares the regions to those of `VolumeReader`.
+
+use std::{future::Future, pin::pin, sync::Arc, task::{Context, Poll, Wake, Waker}, thread::{self, Thread}};
+
+use bvp::{async_reader::{AsyncVolumeReader, MemoryBlockStore}, bvpfile::BVPFile, compressions::CompressionType, formats::{self, Format}, reader::VolumeReader, vector3::Vector3, writer::VolumeWriter};
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs a future on the current thread, as the smallest possible executor.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+fn require_send<T: Send>(value: T) -> T {
+    return value;
+}
+
+fn asset(deduplication: bool) -> BVPFile {
+    let dimensions = Vector3::from_xyz(19, 14, 11);
+    // Repeating rows, so deduplication places some blocks several times.
+    let volume: Vec<u8> = (0..19 * 14 * 11).map(|i| if deduplication { (i % 7) as u8 } else { (i % 251) as u8 }).collect();
+    let format = Format::from_json(&formats::shorthand_to_json("u8").unwrap()).unwrap();
+    return VolumeWriter::new(Vector3::from_xyz(4, 4, 4))
+        .with_compression(CompressionType::LZ4S)
+        .with_deduplication(deduplication)
+        .build(volume, dimensions, format)
+        .unwrap();
+}
+
+#[test]
+fn regions_match_the_blocking_reader() {
+    for deduplication in [false, true] {
+        let bvp_file = Arc::new(asset(deduplication));
+        let expected = VolumeReader::new(&bvp_file);
+        for concurrency in [1, 3, 64] {
+            let reader = AsyncVolumeReader::new(MemoryBlockStore::new(bvp_file.clone(), 2)).with_concurrency(concurrency);
+            let whole = block_on(require_send(reader.read_modality(0))).unwrap();
+            assert_eq!(whole.data, expected.read_modality(0).unwrap().data);
+            let (start, end) = (Vector3::from_xyz(3, 5, 2), Vector3::from_xyz(17, 9, 10));
+            let region = block_on(reader.read_region(0, start, end)).unwrap();
+            assert_eq!(region.data, expected.read_region(0, start, end).unwrap().data);
+        }
+    }
+}
+
+#[test]
+fn errors_are_returned() {
+    let reader = AsyncVolumeReader::new(MemoryBlockStore::new(Arc::new(asset(false)), 1));
+    assert!(block_on(reader.read_region(1, Vector3::from_xyz(0, 0, 0), Vector3::from_xyz(1, 1, 1))).is_err());
+    assert!(block_on(reader.read_region(0, Vector3::from_xyz(0, 0, 0), Vector3::from_xyz(20, 1, 1))).is_err());
+}
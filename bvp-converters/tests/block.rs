@@ -0,0 +1,103 @@
+//! Tests of copying data between blocks with `set_data_in_range` and `get_data_in_range`,
+//! and of blocks sharing the data of the files they were read from.
+
+use std::path::Path;
+
+use bvp::archives::ArchiveEnum;
+use bvp::block::Block;
+use bvp::bvpfile::BVPFile;
+use bvp::compressions::CompressionType;
+use bvp::errors::BlockError;
+use bvp::formats::{Format, FormatFamily, MonoFormat, PrimitiveType};
+use bvp::vector3::Vector3;
+
+/// Single byte voxels in microblocks of 2x2x1 voxels, so a microblock is 4 bytes.
+fn microblock_format() -> Format {
+    return Format::new(Vector3::from_xyz(2, 2, 1), 4, FormatFamily::Mono(MonoFormat::new(1, 1, PrimitiveType::Uint)), Vec::new());
+}
+
+/// Returns the bytes of a microblock, copied one microblock at a time as a reference.
+/// * `block` - the block
+/// * `position` - position of the microblock, in microblocks
+fn microblock(block: &Block, position: Vector3<u32>) -> Vec<u8> {
+    let amount = block.dimensions / Vector3::from_xyz(2, 2, 1);
+    let index = Vector3::linear_index(position, amount) * 4;
+    return block.data.as_ref().unwrap().as_slice()[index..index + 4].to_vec();
+}
+
+fn source() -> Block {
+    // 4x4x2 voxels, 2x2x2 microblocks, every byte different.
+    return Block::new(1, Vector3::from_xyz(4, 4, 2), None, Some((0..32).collect()));
+}
+
+#[test]
+fn rows_of_microblocks_are_copied_into_place() {
+    let format = microblock_format();
+    let offset = Vector3::from_xyz(2, 2, 1);
+    for encoding in [None, Some(CompressionType::LZ4S)] {
+        let mut src = source();
+        if let Some(encoding) = &encoding {
+            src.data = Some(encoding.compress(src.data.unwrap().into_vec()).into());
+        }
+        src.encoding = encoding;
+        let mut dest = Block::new(0, Vector3::from_xyz(8, 6, 3), None, Some(vec![0xff; 8 * 6 * 3]));
+        dest.set_data_in_range(offset, &src, &format).unwrap();
+
+        let src = source();
+        for z in 0..3 {
+            for y in 0..3 {
+                for x in 0..4 {
+                    let position = Vector3::from_xyz(x, y, z);
+                    let inside = x >= 1 && x < 3 && y >= 1 && y < 3 && z >= 1;
+                    let expected = match inside {
+                        true => microblock(&src, position - Vector3::from_xyz(1, 1, 1)),
+                        false => vec![0xff; 4]
+                    };
+                    assert_eq!(microblock(&dest, position), expected, "Microblock {:?}", position);
+                }
+            }
+        }
+
+        let copied = dest.get_data_in_range(offset, offset + src.dimensions, &format).unwrap();
+        assert_eq!(copied.dimensions, src.dimensions);
+        assert_eq!(copied.data.unwrap().as_slice(), src.data.unwrap().as_slice());
+    }
+}
+
+#[test]
+fn ranges_must_fall_on_microblocks() {
+    let format = microblock_format();
+    let mut dest = Block::new(0, Vector3::from_xyz(8, 6, 3), None, Some(vec![0; 8 * 6 * 3]));
+    let result = dest.set_data_in_range(Vector3::from_xyz(1, 0, 0), &source(), &format);
+    assert!(matches!(result, Err(BlockError::BlockInvalidPosition(0, _, _))));
+    let result = dest.set_data_in_range(Vector3::from_xyz(6, 0, 0), &source(), &format);
+    assert!(matches!(result, Err(BlockError::EndOutOfBounds(0, _))));
+    let result = dest.get_data_in_range(Vector3::from_xyz(0, 0, 0), Vector3::from_xyz(3, 2, 1), &format);
+    assert!(matches!(result, Err(BlockError::BlockInvalidSize(0, _, _))));
+}
+
+#[test]
+fn blocks_share_the_data_of_their_files() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/mono-u16-shared-saf/asset.saf");
+    let bvp_file = BVPFile::open(&path, &ArchiveEnum::SAF).unwrap();
+    let mut shared = 0;
+    for block in &bvp_file.blocks {
+        let (data, url) = match (&block.data, &block.data_url) {
+            (Some(data), Some(url)) => (data, url),
+            _ => continue
+        };
+        let file = bvp_file.files.iter().find(|file| &file.name == url).unwrap();
+        assert!(data.ptr_eq(&file.data), "Block {} has a copy of `{}`", block.index, url);
+        shared += 1;
+    }
+    assert!(shared > 0);
+
+    // Writing to a shared block copies its data first, the file stays as it was.
+    let index = bvp_file.blocks.iter().position(|block| block.data.is_some()).unwrap();
+    let mut block = bvp_file.blocks[index].clone();
+    let original = block.data.as_ref().unwrap().as_slice().to_vec();
+    block.data.as_mut().unwrap().make_mut()[0] ^= 0xff;
+    let file = bvp_file.files.iter().find(|file| Some(&file.name) == block.data_url.as_ref()).unwrap();
+    assert_eq!(file.data.as_slice(), original.as_slice());
+    assert!(!block.data.unwrap().ptr_eq(&file.data));
+}
@@ -0,0 +1,61 @@
+//! Tests of block checksums: the algorithms against known values, their text form in the manifest,
+//! and reading blocks whose data does not match them.
+
+use bvp::bytes::Bytes;
+use bvp::checksum::{Checksum, ChecksumType};
+use bvp::errors::ReaderError;
+use bvp::formats::{self, Format};
+use bvp::reader::VolumeReader;
+use bvp::vector3::Vector3;
+use bvp::writer::VolumeWriter;
+
+#[test]
+fn checksums_match_known_values() {
+    let cases = [
+        (ChecksumType::Crc32, &b"123456789"[..], "crc32:cbf43926"),
+        (ChecksumType::Xxh3, &b""[..], "xxh3:2d06800538d394c2"),
+        (ChecksumType::Sha256, &b"abc"[..], "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+    ];
+    for (algorithm, data, expected) in cases {
+        let checksum = Checksum::compute(algorithm, data);
+        assert_eq!(checksum.to_string(), expected);
+        assert_eq!(checksum.value.len(), algorithm.length());
+        assert_eq!(Checksum::from_string(expected).unwrap(), checksum);
+        assert!(checksum.verify(data));
+        assert!(!checksum.verify(b"something else"));
+    }
+}
+
+#[test]
+fn checksum_strings_are_parsed() {
+    // Leading zeros can be left out.
+    assert_eq!(Checksum::from_string("crc32:1ca3").unwrap().value, vec![0, 0, 0x1c, 0xa3]);
+    assert_eq!(Checksum::from_string("CRC32:00001CA3").unwrap().to_string(), "crc32:00001ca3");
+    for invalid in ["crc32", "crc32:", "crc32:123456789", "crc32:12g4", "md5:00", ":00"] {
+        assert!(Checksum::from_string(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn damaged_blocks_fail_verification() {
+    let format = Format::from_json(&formats::shorthand_to_json("u8").unwrap()).unwrap();
+    let volume: Vec<u8> = (0..8 * 4 * 4).map(|i| i as u8).collect();
+    let mut bvp_file = VolumeWriter::new(Vector3::from_xyz(4, 4, 4))
+        .with_checksum(Some(ChecksumType::Crc32))
+        .build(volume.clone(), Vector3::from_xyz(8, 4, 4), format)
+        .unwrap();
+    assert!(bvp_file.blocks.iter().filter(|b| b.data.is_some()).all(|b| b.checksum.is_some()));
+    let (used, required) = bvp_file.declared_extensions();
+    assert!(used.contains(&"EXT_checksum".to_string()));
+    assert!(!required.contains(&"EXT_checksum".to_string()));
+
+    let index = bvp_file.blocks.iter().position(|b| b.data.is_some()).unwrap();
+    let mut data = bvp_file.blocks[index].data.as_ref().unwrap().as_slice().to_vec();
+    data[0] ^= 1;
+    bvp_file.blocks[index].data = Some(Bytes::from(data));
+
+    // The data is only checked when asked to.
+    assert!(VolumeReader::new(&bvp_file).read_modality(0).is_ok());
+    let result = VolumeReader::new(&bvp_file).with_checksum_verification(true).read_modality(0);
+    assert!(matches!(result, Err(ReaderError::ChecksumMismatch(i, _)) if i == index), "{:?}", result.err());
+}
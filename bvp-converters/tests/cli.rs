@@ -0,0 +1,877 @@
+//! Tests of the options of the `bvp` commands, run as the binary.
+
+use std::{collections::HashMap, env, fs, io::{BufRead, BufReader, Read, Write}, net::TcpStream, path::{Path, PathBuf}, process::{Command, Output, Stdio}};
+
+use tinyjson::JsonValue;
+
+use bvp::{archives::{saf, ArchiveEnum}, bvpfile::BVPFile, bytes::Bytes, checksum::{Checksum, ChecksumType}, compressions::deflate, formats::FormatFamily, reader::VolumeReader, texture::TextureCompression, vector3::Vector3};
+
+/// Returns an empty folder for a test.
+/// * `name` - name of the test
+fn test_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("bvp-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    return dir;
+}
+
+/// Returns the path of a golden asset, see `tests/golden`.
+/// * `name` - the fixture folder and the asset in it
+fn golden(name: &str) -> PathBuf {
+    return Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(name);
+}
+
+/// Runs `bvp` in a folder.
+/// * `dir` - the working directory
+/// * `arguments` - the command and its arguments
+fn bvp(dir: &Path, arguments: &[&str]) -> Output {
+    return Command::new(env!("CARGO_BIN_EXE_bvp")).current_dir(dir).args(arguments).output().unwrap();
+}
+
+/// Runs `bvp` and fails if it does not succeed. Returns its stdout.
+fn bvp_ok(dir: &Path, arguments: &[&str]) -> String {
+    let output = bvp(dir, arguments);
+    assert!(output.status.success(), "{:?} failed: {}", arguments, String::from_utf8_lossy(&output.stderr));
+    return String::from_utf8(output.stdout).unwrap();
+}
+
+/// Writes `in.raw` into a folder, a volume of 12x12x12 `u8` voxels with repeating values,
+/// and returns the arguments that pack it into `out.saf`.
+fn raw_input(dir: &Path) -> Vec<&'static str> {
+    let volume: Vec<u8> = (0..12 * 12 * 12).map(|i| ((i / 5) % 7) as u8).collect();
+    fs::write(dir.join("in.raw"), volume).unwrap();
+    return vec![
+        "pack", "--input-file", "in.raw", "--output-file", "out.saf",
+        "--dimensions", "12x12x12", "--block-dimensions", "4x4x4", "--format", "u8"
+    ];
+}
+
+/// Runs a command with the extra arguments after the given ones.
+fn with(arguments: &[&'static str], extra: &[&'static str]) -> Vec<&'static str> {
+    return arguments.iter().chain(extra).copied().collect();
+}
+
+#[test]
+fn thread_queue_and_compression_level_options() {
+    let dir = test_dir("threads");
+    let pack = raw_input(&dir);
+    let report: JsonValue = bvp_ok(&dir, &with(&pack, &["--threads", "3", "--queue-capacity", "2", "--compression-level", "1", "--report", "-"]))
+        .parse().unwrap();
+    let settings = &report["settings"];
+    assert_eq!(settings["threads"].get::<f64>(), Some(&3.0));
+    assert_eq!(settings["queueCapacity"].get::<f64>(), Some(&2.0));
+    assert_eq!(settings["compressionLevel"].get::<f64>(), Some(&1.0));
+    bvp_ok(&dir, &["unpack", "out.saf", "--output-name", "fast"]);
+
+    bvp_ok(&dir, &with(&pack, &["--threads", "1", "--queue-capacity=1", "--compression-level=9"]));
+    bvp_ok(&dir, &["unpack", "out.saf", "--output-name", "small"]);
+    assert_eq!(fs::read(dir.join("fast.raw")).unwrap(), fs::read(dir.join("in.raw")).unwrap());
+    assert_eq!(fs::read(dir.join("small.raw")).unwrap(), fs::read(dir.join("in.raw")).unwrap());
+
+    for (option, value, problem) in [
+        ("--threads", "0", "threads: must be at least 1"),
+        ("--queue-capacity", "0", "queueCapacity: must be at least 1"),
+        ("--compression-level", "12", "compressionLevel: must be between 1 and 9"),
+        ("--threads", "two", "threads")
+    ] {
+        let output = bvp(&dir, &with(&pack, &[option, value]));
+        assert_eq!(output.status.code(), Some(2), "{} {}", option, value);
+        assert!(String::from_utf8_lossy(&output.stderr).contains(problem), "{} {}", option, value);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Returns the levels of the log lines in stderr, such as `DEBUG`.
+fn log_levels(output: &Output) -> Vec<String> {
+    return String::from_utf8_lossy(&output.stderr).lines()
+        .filter_map(|line| line.strip_prefix('[').and_then(|l| l.split_once(']')).map(|(level, _)| level.to_string()))
+        .collect();
+}
+
+#[test]
+fn verbosity_flags_select_the_log_level() {
+    let dir = test_dir("verbosity");
+    let pack = raw_input(&dir);
+    // Read as raw voxels, but named like a NRRD file, which is warned about.
+    let mut volume = b"NRRD0004\n".to_vec();
+    volume.resize(12 * 12 * 12, 0);
+    fs::write(dir.join("in.raw"), volume).unwrap();
+
+    let output = bvp(&dir, &pack);
+    assert_eq!(log_levels(&output), vec!["WARN"]);
+    let output = bvp(&dir, &with(&pack, &["-q"]));
+    assert!(log_levels(&output).is_empty());
+    let output = bvp(&dir, &with(&pack, &["--verbose"]));
+    assert!(log_levels(&output).contains(&"INFO".to_string()));
+    assert!(!log_levels(&output).contains(&"DEBUG".to_string()));
+    let output = bvp(&dir, &with(&pack, &["-vv"]));
+    assert!(log_levels(&output).contains(&"DEBUG".to_string()));
+    assert!(!log_levels(&output).contains(&"TRACE".to_string()));
+
+    // Messages inside a span are prefixed with its name.
+    let output = bvp(&dir, &with(&pack, &["-vvv"]));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("[TRACE] read_input: started"), "{}", stderr);
+    assert!(stderr.contains("[DEBUG] read_input: finished in"), "{}", stderr);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn flags_override_the_config() {
+    let dir = test_dir("flags");
+    raw_input(&dir);
+    fs::write(dir.join("config.json"), r#"{
+        "inputFile": "in.raw", "outputFile": "out.saf", "name": "from the config", "author": "someone",
+        "dimensions": [1, 1, 1], "blockDimensions": [4, 4, 4],
+        "format": { "family": "mono", "count": 1, "size": 2, "type": "u" }
+    }"#).unwrap();
+    bvp_ok(&dir, &[
+        "pack", "config.json", "--name", "from a flag", "--dimensions=12x12x12", "--voxel-scale", "1,1,2.5",
+        "--format", r#"{"family": "mono", "count": 1, "size": 1, "type": "u"}"#, "--deduplication", "false"
+    ]);
+    let bvp_file = BVPFile::open(&dir.join("out.saf"), &ArchiveEnum::SAF).unwrap();
+    assert_eq!(bvp_file.modalities[0].name.as_deref(), Some("from a flag"));
+    assert_eq!(bvp_file.asset.author.as_deref(), Some("someone"));
+    assert_eq!(bvp_file.modalities[0].voxel_size, Some(Vector3::from_xyz(1.0, 1.0, 2.5)));
+    assert_eq!(bvp_file.blocks[bvp_file.modalities[0].block].dimensions, Vector3::from_xyz(12, 12, 12));
+    // The volume repeats, but without deduplication every block has data of its own.
+    assert_eq!(bvp_file.blocks.iter().filter(|b| b.data.is_some()).count(), 27);
+
+    for (option, value) in [("--dimensions", "12x12"), ("--deduplication", "maybe"), ("--voxel-scale", "1,a,1"), ("--bogus", "1")] {
+        let output = bvp(&dir, &["pack", "config.json", option, value]);
+        assert_eq!(output.status.code(), Some(2), "{} {}", option, value);
+    }
+    let output = bvp(&dir, &["pack", "config.json", "--name"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Missing value for `--name`"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn every_config_problem_is_reported_with_its_path() {
+    let dir = test_dir("config-problems");
+    fs::write(dir.join("config.json"), r#"{
+        "inputFile": 5, "outputFile": "out.saf", "dimensions": [12, 12], "blockDimensions": [4, 4, 4],
+        "format": { "family": "mono", "count": 1, "size": 1, "type": "x" }, "archive": "TAR",
+        "modalities": [{ "inputFile": "a.raw", "format": { "family": "mono", "count": 1, "size": 1, "type": "u" }, "semanticType": 3 }]
+    }"#).unwrap();
+    let output = bvp(&dir, &["pack", "config.json", "--error-format", "json"]);
+    assert_eq!(output.status.code(), Some(2));
+    let error: JsonValue = String::from_utf8_lossy(&output.stderr).lines().last().unwrap().parse().unwrap();
+    let message = error["message"].get::<String>().unwrap();
+    for problem in [
+        "inputFile: must be a string",
+        "dimensions: must be an array of three numbers",
+        "format.type: must be `u`, `i` or `f`, got `x`",
+        "archive: must be `SAF`, `ZIP` or `None`, got `TAR`",
+        "modalities[0].semanticType: must be a string"
+    ] {
+        assert!(message.lines().any(|line| line.trim() == problem), "`{}` is not in:\n{}", problem, message);
+    }
+
+    // Unknown keys are only warned about.
+    raw_input(&dir);
+    fs::write(dir.join("config.json"), r#"{
+        "inputFile": "in.raw", "outputFile": "out.saf", "dimensions": [12, 12, 12], "blockDimensions": [4, 4, 4],
+        "format": { "family": "mono", "count": 1, "size": 1, "type": "u" }, "unknownKey": 1
+    }"#).unwrap();
+    let output = bvp(&dir, &["pack", "config.json"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown config key `unknownKey` is ignored"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn config_paths_expand_variables_and_are_relative_to_the_config() {
+    let dir = test_dir("config-paths");
+    let data = dir.join("data");
+    fs::create_dir_all(data.join("volumes")).unwrap();
+    raw_input(&data.join("volumes"));
+    fs::write(data.join("config.json"), r#"{
+        "inputFile": "${BVP_TEST_VOLUMES}/in.raw", "outputFile": "volumes/out.saf", "archive": "SAF",
+        "dimensions": [12, 12, 12], "blockDimensions": [4, 4, 4],
+        "format": { "family": "mono", "count": 1, "size": 1, "type": "u" }
+    }"#).unwrap();
+
+    // Run from another folder, the paths in the config are relative to its own folder.
+    let run = |variable: Option<&str>, extra: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_bvp"));
+        command.current_dir(&dir).args(["pack", "data/config.json"]).args(extra).env_remove("BVP_TEST_VOLUMES");
+        if let Some(value) = variable {
+            command.env("BVP_TEST_VOLUMES", value);
+        }
+        return command.output().unwrap();
+    };
+    let output = run(Some("volumes"), &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(data.join("volumes").join("out.saf").is_file());
+
+    // Unset variables are errors.
+    let output = run(None, &[]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("BVP_TEST_VOLUMES"));
+
+    // Paths given as flags are relative to the working directory.
+    let output = run(Some("volumes"), &["--output-file", "flag.saf"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(dir.join("flag.saf").is_file());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn info_prints_metadata_and_block_statistics() {
+    let dir = env::temp_dir();
+    let tree = golden("mono-u8-tree/manifest.json");
+    let text = bvp_ok(&dir, &["info", tree.to_str().unwrap(), "None"]);
+    assert!(text.contains("generator:            hand-written"), "{}", text);
+    assert!(text.contains("[0] tree (root block 0, dimensions [4 3 2]"), "{}", text);
+    assert!(text.contains("with data:            2"), "{}", text);
+
+    let info: JsonValue = bvp_ok(&dir, &["info", tree.to_str().unwrap(), "None", "--json", "--blocks"]).parse().unwrap();
+    assert_eq!(info["asset"]["version"].get::<String>().map(String::as_str), Some("1.0"));
+    assert_eq!(info["modalities"][0]["name"].get::<String>().map(String::as_str), Some("tree"));
+    assert_eq!(info["blocks"]["total"].get::<f64>(), Some(&3.0));
+    assert_eq!(info["blocks"]["placements"].get::<f64>(), Some(&2.0));
+    assert_eq!(info["blocks"]["storedSize"].get::<f64>(), Some(&24.0));
+    let block_list = info["blockList"].get::<Vec<JsonValue>>().unwrap();
+    assert_eq!(block_list.len(), 3);
+    assert_eq!(block_list[1]["data"].get::<String>().map(String::as_str), Some("blocks/low.raw"));
+
+    // Blocks placed several times are counted as duplicates.
+    let shared = golden("mono-u16-shared-saf/asset.saf");
+    let info: JsonValue = bvp_ok(&dir, &["info", shared.to_str().unwrap(), "--json"]).parse().unwrap();
+    assert_eq!(info["blocks"]["duplicates"].get::<f64>(), Some(&1.0));
+    assert_eq!(info["blocks"]["bytesSaved"].get::<f64>(), Some(&12.0));
+    assert_eq!(info["blocks"]["dedupRatio"].get::<f64>(), Some(&2.0));
+    assert!(info.get::<HashMap<String, JsonValue>>().unwrap().get("blockList").is_none());
+}
+
+#[test]
+fn info_ends_quietly_when_its_output_is_closed() {
+    let dir = test_dir("info-pipe");
+    let mut pack = raw_input(&dir);
+    pack[8] = "1x1x1";
+    bvp_ok(&dir, &with(&pack, &["--archive", "SAF"]));
+    // The block list is larger than a pipe buffer, so the reader goes away while it is written.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bvp")).current_dir(&dir)
+        .args(["info", "out.saf", "--blocks"])
+        .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    let mut first = String::new();
+    BufReader::new(child.stdout.take().unwrap()).read_line(&mut first).unwrap();
+    assert_eq!(first, "Asset\n");
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Copies the unarchived golden asset into a folder and changes its manifest.
+/// Returns the path of the manifest.
+/// * `dir` - the folder
+/// * `edit` - changes the manifest
+fn edited_tree(dir: &Path, edit: impl FnOnce(&mut HashMap<String, JsonValue>)) -> PathBuf {
+    let source = golden("mono-u8-tree");
+    fs::create_dir_all(dir.join("blocks")).unwrap();
+    for name in ["blocks/low.raw", "blocks/high.raw"] {
+        fs::copy(source.join(name), dir.join(name)).unwrap();
+    }
+    let mut manifest: JsonValue = fs::read_to_string(source.join("manifest.json")).unwrap().parse().unwrap();
+    edit(manifest.get_mut().unwrap());
+    let path = dir.join("manifest.json");
+    fs::write(&path, manifest.stringify().unwrap()).unwrap();
+    return path;
+}
+
+/// Returns the codes of the issues `bvp validate --json` printed.
+fn issue_codes(stdout: &[u8]) -> Vec<String> {
+    let issues: JsonValue = String::from_utf8_lossy(stdout).parse().unwrap();
+    return issues.get::<Vec<JsonValue>>().unwrap().iter()
+        .map(|issue| issue["code"].get::<String>().unwrap().clone())
+        .collect();
+}
+
+#[test]
+fn validate_fails_on_errors_and_in_strict_mode_on_warnings() {
+    let dir = test_dir("validate");
+    let valid = bvp(&dir, &["validate", golden("mono-u8-tree/manifest.json").to_str().unwrap(), "None", "--json"]);
+    assert_eq!(valid.status.code(), Some(0));
+    assert!(issue_codes(&valid.stdout).is_empty());
+
+    let manifest = edited_tree(&dir.join("unknown"), |m| {
+        m.get_mut("asset").unwrap().get_mut::<HashMap<String, JsonValue>>().unwrap().insert("unknownField".to_string(), 1.0.into());
+    });
+    let output = bvp(&dir, &["validate", manifest.to_str().unwrap(), "None", "--json"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(issue_codes(&output.stdout), vec!["unknown-field"]);
+    let text = bvp(&dir, &["validate", manifest.to_str().unwrap(), "None"]);
+    assert!(String::from_utf8_lossy(&text.stdout).contains("warning[unknown-field] asset.unknownField"));
+    let output = bvp(&dir, &["validate", manifest.to_str().unwrap(), "None", "--strict", "--json"]);
+    assert_eq!(output.status.code(), Some(5));
+
+    let manifest = edited_tree(&dir.join("uncovered"), |m| {
+        let blocks = m.get_mut("blocks").unwrap().get_mut::<Vec<JsonValue>>().unwrap();
+        blocks[0].get_mut::<HashMap<String, JsonValue>>().unwrap()
+            .get_mut("placements").unwrap().get_mut::<Vec<JsonValue>>().unwrap().pop();
+    });
+    let output = bvp(&dir, &["validate", manifest.to_str().unwrap(), "None", "--json"]);
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(issue_codes(&output.stdout), vec!["incomplete-coverage"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn diff_compares_metadata_and_voxels() {
+    let dir = test_dir("diff");
+    let original = golden("mono-u8-tree/manifest.json");
+    let original = original.to_str().unwrap();
+    let same = bvp_ok(&dir, &["diff", original, original]);
+    assert!(same.contains("Metadata: same"), "{}", same);
+    assert!(same.contains("differing voxels: 0 of 24"), "{}", same);
+
+    let renamed = edited_tree(&dir.join("renamed"), |m| {
+        m.get_mut("asset").unwrap().get_mut::<HashMap<String, JsonValue>>().unwrap().insert("generator".to_string(), "other".to_string().into());
+    });
+    let output = bvp(&dir, &["diff", original, renamed.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("asset.generator: Some(\"hand-written\") != Some(\"other\")"));
+    bvp_ok(&dir, &["diff", original, renamed.to_str().unwrap(), "--ignore-metadata"]);
+
+    // Formats are printed with sorted keys, so the output is the same in every run.
+    let signed = edited_tree(&dir.join("signed"), |m| {
+        let formats = m.get_mut("formats").unwrap().get_mut::<Vec<JsonValue>>().unwrap();
+        formats[0].get_mut::<HashMap<String, JsonValue>>().unwrap().insert("type".to_string(), "i".to_string().into());
+    });
+    let output = bvp(&dir, &["diff", original, signed.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(5));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(r#"formats[0]: {"count":1,"family":"mono","microblockDimensions":[1,1,1],"microblockSize":1,"size":1,"type":"u"} != {"count":1,"family":"mono","microblockDimensions":[1,1,1],"microblockSize":1,"size":1,"type":"i"}"#), "{}", stdout);
+
+    // One voxel of the lower block changes from 5 to 255.
+    let changed = edited_tree(&dir.join("changed"), |_| {});
+    let mut low = fs::read(dir.join("changed/blocks/low.raw")).unwrap();
+    low[5] = 255;
+    fs::write(dir.join("changed/blocks/low.raw"), low).unwrap();
+    let output = bvp(&dir, &["diff", original, changed.to_str().unwrap(), "--archive", "None", "--ignore-metadata"]);
+    assert_eq!(output.status.code(), Some(5));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("differing voxels: 1 of 24"), "{}", stdout);
+    assert!(stdout.contains("differing blocks: 1 of 2"), "{}", stdout);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn extract_writes_blocks_and_regions() {
+    let dir = test_dir("extract");
+    let manifest = golden("mono-u8-tree/manifest.json");
+    let manifest = manifest.to_str().unwrap();
+    let high = fs::read(golden("mono-u8-tree/blocks/high.raw")).unwrap();
+    let low = fs::read(golden("mono-u8-tree/blocks/low.raw")).unwrap();
+
+    bvp_ok(&dir, &["extract", manifest, "None", "--block", "2", "--output", "by-index.raw"]);
+    assert_eq!(fs::read(dir.join("by-index.raw")).unwrap(), high);
+    bvp_ok(&dir, &["extract", manifest, "None", "--block", "blocks/low.raw", "--output", "by-name.raw"]);
+    assert_eq!(fs::read(dir.join("by-name.raw")).unwrap(), low);
+    bvp_ok(&dir, &["extract", manifest, "None", "--all-blocks", "--output", "all"]);
+    assert_eq!(fs::read(dir.join("all/block_1.raw")).unwrap(), low);
+    assert_eq!(fs::read(dir.join("all/block_2.raw")).unwrap(), high);
+
+    // The volume holds the values 0 to 11 in the lower slice and 100 to 111 in the upper one.
+    bvp_ok(&dir, &["extract", manifest, "None", "--start", "1,1,0", "--end", "3,3,2", "--output", "region.raw"]);
+    assert_eq!(fs::read(dir.join("region.raw")).unwrap(), vec![5, 6, 9, 10, 105, 106, 109, 110]);
+
+    for arguments in [["--start", "1,1,0", "--end", "5,3,2"], ["--block", "7", "--output", "none.raw"]] {
+        let output = bvp(&dir, &[&["extract", manifest, "None"], &arguments[..]].concat());
+        assert_eq!(output.status.code(), Some(2), "{:?}", arguments);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn meta_edits_asset_fields_in_place() {
+    let dir = test_dir("meta");
+    let asset = dir.join("asset.saf");
+    fs::copy(golden("mono-u16-shared-saf/asset.saf"), &asset).unwrap();
+    let path = asset.to_str().unwrap();
+
+    bvp_ok(&dir, &["meta", path, "--name", "Head", "--author", "Someone", "--extras", r#"{"scanner": 3}"#]);
+    let printed = bvp_ok(&dir, &["meta", path]);
+    assert!(printed.lines().any(|line| line == "name:              Head"), "{}", printed);
+    assert!(printed.lines().any(|line| line == "extras:            {\"scanner\":3}"), "{}", printed);
+    bvp_ok(&dir, &["meta", path, "SAF", "--unset", "author"]);
+
+    let bvp_file = BVPFile::open(&asset, &ArchiveEnum::SAF).unwrap();
+    assert_eq!(bvp_file.asset.name.as_deref(), Some("Head"));
+    assert_eq!(bvp_file.asset.author, None);
+    assert_eq!(bvp_file.asset.generator.as_deref(), Some("hand-written"));
+    // Only the manifest changed, the volume is the same.
+    let volume = VolumeReader::new(&bvp_file).read_modality(0).unwrap();
+    assert_eq!(volume.data.unwrap().as_slice(), fs::read(golden("mono-u16-shared-saf/expected.raw")).unwrap().as_slice());
+
+    for arguments in [["--unset", "version"], ["--extras", "{broken"]] {
+        let output = bvp(&dir, &[&["meta", path], &arguments[..]].concat());
+        assert_eq!(output.status.code(), Some(2), "{:?}", arguments);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn merge_collects_modalities_and_joins_tiles() {
+    let dir = test_dir("merge");
+    let tree = golden("mono-u8-tree/manifest.json");
+    let tree = tree.to_str().unwrap();
+    let shared = golden("mono-u16-shared-saf/asset.saf");
+    let tree_volume = fs::read(golden("mono-u8-tree/expected.raw")).unwrap();
+
+    bvp_ok(&dir, &["merge", tree, shared.to_str().unwrap(), "--output", "both.saf", "--output-archive", "SAF", "--name", "both"]);
+    let bvp_file = BVPFile::open(&dir.join("both.saf"), &ArchiveEnum::SAF).unwrap();
+    assert_eq!(bvp_file.asset.name.as_deref(), Some("both"));
+    let names: Vec<_> = bvp_file.modalities.iter().map(|m| m.name.as_deref()).collect();
+    assert_eq!(names, vec![Some("tree"), Some("shared")]);
+    let reader = VolumeReader::new(&bvp_file);
+    assert_eq!(reader.read_modality(0).unwrap().data.unwrap().as_slice(), tree_volume.as_slice());
+    assert_eq!(reader.read_modality(1).unwrap().data.unwrap().as_slice(), fs::read(golden("mono-u16-shared-saf/expected.raw")).unwrap().as_slice());
+
+    // Two copies of the 4x3x2 volume side by side along X, whose blocks are stored once.
+    let left = format!("{}@0,0,0", tree);
+    let right = format!("{}@4,0,0", tree);
+    bvp_ok(&dir, &["merge", "--tile", &left, &right, "--output", "tiled.saf", "--output-archive", "SAF"]);
+    let bvp_file = BVPFile::open(&dir.join("tiled.saf"), &ArchiveEnum::SAF).unwrap();
+    assert_eq!(bvp_file.blocks.iter().filter(|b| b.data.is_some()).count(), 2);
+    let volume = VolumeReader::new(&bvp_file).read_modality(0).unwrap();
+    assert_eq!(volume.dimensions, Vector3::from_xyz(8, 3, 2));
+    let expected: Vec<u8> = tree_volume.chunks(4).flat_map(|row| [row, row].concat()).collect();
+    assert_eq!(volume.data.unwrap().as_slice(), expected.as_slice());
+
+    let overlapping = format!("{}@3,0,0", tree);
+    let output = bvp(&dir, &["merge", "--tile", &left, &overlapping, "--output", "overlap.saf"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("inputs 0 and 1 overlap"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Decodes a gray PNG with 8 bits per pixel and unfiltered rows, as `bvp thumbnail` writes them.
+/// Returns the width, the height and the pixels.
+fn png_pixels(png: &[u8]) -> (usize, usize, Vec<u8>) {
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    let (mut width, mut height, mut compressed) = (0, 0, Vec::new());
+    let mut offset = 8;
+    while offset < png.len() {
+        let length = u32::from_be_bytes(png[offset..offset + 4].try_into().unwrap()) as usize;
+        let data = &png[offset + 8..offset + 8 + length];
+        match &png[offset + 4..offset + 8] {
+            b"IHDR" => {
+                width = u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize;
+                height = u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize;
+                assert_eq!(&data[8..10], &[8, 0], "not 8 bit gray");
+            },
+            b"IDAT" => compressed.extend_from_slice(data),
+            _ => ()
+        }
+        offset += length + 12;
+    }
+    // Without the zlib header, the rest is deflate data followed by a checksum.
+    let rows = deflate::inflate_prefix(&compressed[2..], height * (width + 1)).unwrap().0;
+    let mut pixels = Vec::new();
+    for row in rows.chunks(width + 1) {
+        assert_eq!(row[0], 0, "filtered row");
+        pixels.extend_from_slice(&row[1..]);
+    }
+    return (width, height, pixels);
+}
+
+#[test]
+fn thumbnail_writes_slices_and_projections() {
+    let dir = test_dir("thumbnail");
+    let manifest = golden("mono-u8-tree/manifest.json");
+    bvp_ok(&dir, &["thumbnail", manifest.to_str().unwrap(), "None", "--window", "0,255", "--output", "all"]);
+    for axis in ["x", "y", "z"] {
+        for kind in ["slice", "mip"] {
+            let (width, height, pixels) = png_pixels(&fs::read(dir.join(format!("all_{}_{}.png", kind, axis))).unwrap());
+            assert_eq!(pixels.len(), width * height);
+        }
+    }
+    // The 4x3 voxels of the upper slice, 100 to 111, are both the middle slice and the largest values along Z.
+    let upper: Vec<u8> = (100..112).collect();
+    assert_eq!(png_pixels(&fs::read(dir.join("all_mip_z.png")).unwrap()), (4, 3, upper.clone()));
+    assert_eq!(png_pixels(&fs::read(dir.join("all_slice_z.png")).unwrap()), (4, 3, upper));
+
+    bvp_ok(&dir, &["thumbnail", manifest.to_str().unwrap(), "None", "--mip", "--output", "mip"]);
+    assert!(dir.join("mip_mip_x.png").is_file());
+    assert!(!dir.join("mip_slice_x.png").exists());
+    let output = bvp(&dir, &["thumbnail", manifest.to_str().unwrap(), "None", "--modality", "3"]);
+    assert_eq!(output.status.code(), Some(2));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unpack_writes_stacks_of_slices() {
+    let dir = test_dir("slices");
+    let manifest = golden("mono-u8-tree/manifest.json");
+    bvp_ok(&dir, &["unpack", manifest.to_str().unwrap(), "None", "--slices", "png", "--window", "0,255", "--output-dir", "png"]);
+    assert_eq!(png_pixels(&fs::read(dir.join("png/tree_0000.png")).unwrap()), (4, 3, (0..12).collect()));
+    assert_eq!(png_pixels(&fs::read(dir.join("png/tree_0001.png")).unwrap()), (4, 3, (100..112).collect()));
+    assert!(!dir.join("png/tree_0002.png").exists());
+
+    let shared = golden("mono-u16-shared-saf/asset.saf");
+    bvp_ok(&dir, &["unpack", shared.to_str().unwrap(), "--slices", "tiff", "--bit-depth", "16", "--output-dir", "tiff"]);
+    let tiff = fs::read(dir.join("tiff/shared_0000.tiff")).unwrap();
+    assert_eq!(&tiff[..4], b"II*\0");
+    // 6x2 pixels of 16 bits, stored uncompressed.
+    assert!(tiff.len() > 6 * 2 * 2);
+
+    for (option, value) in [("--slices", "bmp"), ("--bit-depth", "12")] {
+        let output = bvp(&dir, &["unpack", manifest.to_str().unwrap(), "None", "--slices", "png", option, value, "--output-dir", "bad"]);
+        assert_eq!(output.status.code(), Some(2), "{} {}", option, value);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unpack_reconstructs_regions() {
+    let dir = test_dir("region");
+    let manifest = golden("mono-u8-tree/manifest.json");
+    bvp_ok(&dir, &["unpack", manifest.to_str().unwrap(), "None", "--start", "1,1,0", "--end", "3,3,2", "--output-name", "region"]);
+    assert_eq!(fs::read(dir.join("region.raw")).unwrap(), vec![5, 6, 9, 10, 105, 106, 109, 110]);
+    let output = bvp(&dir, &["unpack", manifest.to_str().unwrap(), "None", "--start", "1,1,0", "--end", "5,3,2", "--output-name", "outside"]);
+    assert_eq!(output.status.code(), Some(2));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unpack_names_outputs_and_keeps_existing_files() {
+    let dir = test_dir("output-names");
+    let manifest = golden("mono-u8-tree/manifest.json");
+    let expected = fs::read(golden("mono-u8-tree/expected.raw")).unwrap();
+    let unpack = |extra: &[&str]| bvp(&dir, &[&["unpack", manifest.to_str().unwrap(), "None", "--output-dir", "out/nested"], extra].concat());
+
+    let output = unpack(&["--output-name", "{index}-{modality}"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(fs::read(dir.join("out/nested/0-tree.raw")).unwrap(), expected);
+
+    // Existing files are only overwritten with `--force`.
+    fs::write(dir.join("out/nested/0-tree.raw"), b"old").unwrap();
+    let output = unpack(&["--output-name", "{index}-{modality}"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists, use `--force` to overwrite it"));
+    assert_eq!(fs::read(dir.join("out/nested/0-tree.raw")).unwrap(), b"old");
+    assert!(unpack(&["--output-name", "{index}-{modality}", "--force"]).status.success());
+    assert_eq!(fs::read(dir.join("out/nested/0-tree.raw")).unwrap(), expected);
+
+    // `-` writes the volume to stdout.
+    assert_eq!(bvp(&dir, &["unpack", manifest.to_str().unwrap(), "None", "--output-name", "-"]).stdout, expected);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn streamed_volumes_are_the_same() {
+    let dir = test_dir("stream");
+    let pack = raw_input(&dir);
+    bvp_ok(&dir, &with(&pack, &["--archive", "SAF"]));
+    let volume = fs::read(dir.join("in.raw")).unwrap();
+    for (i, extra) in [vec!["--stream"], vec!["--slab-thickness", "1"], vec!["--slab-thickness", "5"], vec!["--slab-thickness", "64"]].iter().enumerate() {
+        let name = format!("streamed-{}", i);
+        bvp_ok(&dir, &[&["unpack", "out.saf", "--output-name", &name], &extra[..]].concat());
+        assert_eq!(fs::read(dir.join(format!("{}.raw", name))).unwrap(), volume, "{:?}", extra);
+    }
+    // Streamed regions start and end inside slabs.
+    bvp_ok(&dir, &["unpack", "out.saf", "--output-name", "region", "--slab-thickness", "3", "--start", "0,0,2", "--end", "12,12,9"]);
+    assert_eq!(fs::read(dir.join("region.raw")).unwrap(), volume[2 * 144..9 * 144]);
+    let output = bvp(&dir, &["unpack", "out.saf", "--output-name", "none", "--slab-thickness", "0"]);
+    assert_eq!(output.status.code(), Some(2));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unpack_detects_the_archive_type_of_stdin() {
+    let dir = test_dir("unpack-stdin");
+    let pack = raw_input(&dir);
+    bvp_ok(&dir, &with(&pack, &["--archive", "SAF"]));
+    let volume = fs::read(dir.join("in.raw")).unwrap();
+    let unpack = |input: &[u8], arguments: &[&str]| -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_bvp")).current_dir(&dir).args(arguments)
+            .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        return child.wait_with_output().unwrap();
+    };
+    let archive = fs::read(dir.join("out.saf")).unwrap();
+    let output = unpack(&archive, &["unpack", "-", "--output-name", "-"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(output.stdout, volume);
+
+    // Data that is not an archive is reported with its kind.
+    let output = unpack(&volume, &["unpack", "-", "--output-name", "-"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("stdin is a raw file, not a BVP asset"), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = unpack(b"{\"blocks\": []}", &["unpack", "-", "--output-name", "-"]);
+    assert_eq!(output.status.code(), Some(2));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unpack_verifies_the_block_tree_first() {
+    let dir = test_dir("verify-tree");
+    let placements = |m: &mut HashMap<String, JsonValue>| -> Vec<JsonValue> {
+        let blocks = m.get_mut("blocks").unwrap().get_mut::<Vec<JsonValue>>().unwrap();
+        return blocks[0]["placements"].get::<Vec<JsonValue>>().unwrap().clone();
+    };
+    let set_placements = |m: &mut HashMap<String, JsonValue>, placements: Vec<JsonValue>| {
+        let blocks = m.get_mut("blocks").unwrap().get_mut::<Vec<JsonValue>>().unwrap();
+        blocks[0].get_mut::<HashMap<String, JsonValue>>().unwrap().insert("placements".to_string(), placements.into());
+    };
+    // Both blocks are placed at the bottom, so they overlap and leave the upper slice empty.
+    let overlapping = edited_tree(&dir.join("overlapping"), |m| {
+        let mut p = placements(m);
+        p[1] = p[0].clone();
+        set_placements(m, p);
+    });
+    let output = bvp(&dir, &["unpack", overlapping.to_str().unwrap(), "None", "--output-name", "overlapping"]);
+    assert_eq!(output.status.code(), Some(5));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("[placement-overlap] blocks[0].placements[0]"), "{}", stderr);
+    assert!(stderr.contains("use `--no-verify` to skip"), "{}", stderr);
+    assert!(!dir.join("overlapping.raw").exists());
+
+    // Without the check, the gap is still found while the volume is reconstructed,
+    // and neither a partial raw file nor its temporary file is left behind.
+    let output = bvp(&dir, &["unpack", overlapping.to_str().unwrap(), "None", "--output-name", "unchecked", "--no-verify"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("are not covered by any block"));
+    assert!(!dir.join("unchecked.raw").exists());
+    assert!(!dir.join("unchecked.raw.tmp").exists());
+    // An existing file that is overwritten is kept if the reconstruction fails.
+    fs::write(dir.join("kept.raw"), b"old").unwrap();
+    let output = bvp(&dir, &["unpack", overlapping.to_str().unwrap(), "None", "--output-name", "kept", "--no-verify", "--force"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(fs::read(dir.join("kept.raw")).unwrap(), b"old");
+
+    let uncovered = edited_tree(&dir.join("uncovered"), |m| {
+        let mut p = placements(m);
+        p.pop();
+        set_placements(m, p);
+    });
+    let output = bvp(&dir, &["unpack", uncovered.to_str().unwrap(), "None", "--output-name", "uncovered"]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[incomplete-coverage] blocks[0]"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Returns voxels that do not repeat in any block, so blocks are only the same as blocks of other volumes.
+/// * `length` - number of voxels
+fn noise(length: usize) -> Vec<u8> {
+    let mut state = 0x2545f491u32;
+    return (0..length).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        return (state >> 24) as u8;
+    }).collect();
+}
+
+/// Returns the indices of the blocks placed in the root block of a modality.
+fn placed_blocks(bvp_file: &BVPFile, modality: usize) -> Vec<usize> {
+    return bvp_file.blocks[bvp_file.modalities[modality].block].placements.iter().map(|p| p.block).collect();
+}
+
+#[test]
+fn modalities_are_deduplicated_together() {
+    let dir = test_dir("modalities-dedup");
+    let volume = noise(12 * 12 * 12);
+    fs::write(dir.join("first.raw"), &volume).unwrap();
+    fs::write(dir.join("copy.raw"), &volume).unwrap();
+    // The same volume, except for the last slice, so the nine blocks of the last layer differ.
+    let mut changed = volume.clone();
+    changed[12 * 12 * 11..].fill(0);
+    fs::write(dir.join("changed.raw"), &changed).unwrap();
+    fs::write(dir.join("config.json"), r#"{
+        "inputFile": "first.raw", "outputFile": "out.saf", "archive": "SAF",
+        "dimensions": [12, 12, 12], "blockDimensions": [4, 4, 4],
+        "format": { "family": "mono", "count": 1, "size": 1, "type": "u" },
+        "modalities": [{ "inputFile": "copy.raw" }, { "inputFile": "changed.raw" }]
+    }"#).unwrap();
+    bvp_ok(&dir, &["pack", "config.json"]);
+
+    let bvp_file = BVPFile::open(&dir.join("out.saf"), &ArchiveEnum::SAF).unwrap();
+    assert_eq!(bvp_file.modalities.len(), 3);
+    assert_eq!(bvp_file.blocks.iter().filter(|b| b.data.is_some()).count(), 27 + 9);
+    assert_eq!(placed_blocks(&bvp_file, 0), placed_blocks(&bvp_file, 1));
+    let shared = placed_blocks(&bvp_file, 0).iter().zip(placed_blocks(&bvp_file, 2)).filter(|(a, b)| **a == *b).count();
+    assert_eq!(shared, 27 - 9);
+    let reader = VolumeReader::new(&bvp_file);
+    assert_eq!(reader.read_modality(1).unwrap().data.unwrap().as_slice(), volume.as_slice());
+    assert_eq!(reader.read_modality(2).unwrap().data.unwrap().as_slice(), changed.as_slice());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn deduplication_can_be_switched_off_and_is_reported() {
+    let dir = test_dir("dedup-stats");
+    let pack = raw_input(&dir);
+    // The lower layer of 3x3 blocks is zero, the rest does not repeat.
+    let mut volume = noise(12 * 12 * 12);
+    volume[..12 * 12 * 4].fill(0);
+    fs::write(dir.join("in.raw"), &volume).unwrap();
+
+    for (deduplication, unique, duplicates) in [("true", 19.0, 8.0), ("false", 27.0, 0.0)] {
+        let output = bvp(&dir, &with(&pack, &["--archive", "SAF", "--deduplication", deduplication, "--report", "report.json", "--stats"]));
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let report: JsonValue = fs::read_to_string(dir.join("report.json")).unwrap().parse().unwrap();
+        let blocks = &report["blocks"];
+        assert_eq!(blocks["total"].get::<f64>(), Some(&27.0));
+        assert_eq!(blocks["unique"].get::<f64>(), Some(&unique), "{}", deduplication);
+        assert_eq!(blocks["duplicates"].get::<f64>(), Some(&duplicates), "{}", deduplication);
+        assert_eq!(blocks["dedupRatio"].get::<f64>(), Some(&(27.0 / unique)), "{}", deduplication);
+        let stats = String::from_utf8_lossy(&output.stderr);
+        assert!(stats.contains(&format!("({} of 27 blocks)", duplicates)), "{}", stats);
+
+        let bvp_file = BVPFile::open(&dir.join("out.saf"), &ArchiveEnum::SAF).unwrap();
+        assert_eq!(bvp_file.blocks.iter().filter(|b| b.data.is_some()).count() as f64, unique);
+        assert_eq!(VolumeReader::new(&bvp_file).read_modality(0).unwrap().data.unwrap().as_slice(), volume.as_slice());
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn content_named_blocks_are_named_by_their_hash() {
+    let dir = test_dir("content-naming");
+    let pack = raw_input(&dir);
+    let volume = noise(12 * 12 * 12);
+    fs::write(dir.join("in.raw"), &volume).unwrap();
+    let names = |output: &'static str| -> Vec<String> {
+        bvp_ok(&dir, &with(&pack, &["--output-file", output, "--block-naming", "content", "--compression", "LZ4S"]));
+        let bvp_file = BVPFile::open(&dir.join(output), &ArchiveEnum::None).unwrap();
+        let mut names = Vec::new();
+        for block in bvp_file.blocks.iter().filter(|b| b.data.is_some()) {
+            let name = block.data_url.clone().unwrap();
+            let hash = Checksum::compute(ChecksumType::Xxh3, block.data.as_ref().unwrap().as_slice()).to_string();
+            assert_eq!(name, format!("blocks/{}.lz4s", &hash["xxh3:".len()..]));
+            names.push(name);
+        }
+        return names;
+    };
+    // The same data gets the same names in every run.
+    let first = names("first");
+    assert_eq!(first.len(), 27);
+    assert_eq!(first, names("second"));
+    assert!(dir.join("first").join(&first[0]).is_file());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn texture_compression_stores_bc4_tiles() {
+    let dir = test_dir("texture-compression");
+    let pack = raw_input(&dir);
+    bvp_ok(&dir, &with(&pack, &["--archive", "SAF", "--texture-compression", "bc4"]));
+    let bvp_file = BVPFile::open(&dir.join("out.saf"), &ArchiveEnum::SAF).unwrap();
+    assert_eq!(bvp_file.formats.len(), 1);
+    assert!(matches!(bvp_file.formats[0].family(), FormatFamily::Compressed(c) if c.scheme() == "bc4"));
+
+    // The volume is reconstructed as the encoded tiles of the input.
+    let volume = fs::read(dir.join("in.raw")).unwrap();
+    let tiles = TextureCompression::Bc4.encode(&volume, Vector3::from_xyz(12, 12, 12));
+    let read = VolumeReader::new(&bvp_file).read_modality(0).unwrap();
+    assert_eq!(read.data.unwrap().as_slice(), tiles.as_slice());
+
+    // Only `u8` volumes with dimensions made of whole tiles can be encoded.
+    let output = bvp(&dir, &with(&pack, &["--texture-compression", "bc4", "--format", "u16", "--dimensions", "12x12x6"]));
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("format: must be `u8` for texture compression `bc4`"));
+    let output = bvp(&dir, &with(&pack, &["--texture-compression", "bc4", "--dimensions", "6x12x12", "--block-dimensions", "2x4x4"]));
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("must be a multiple of the microblock dimension 4"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn deduplication_moved_to_the_disk_finds_the_same_duplicates() {
+    let dir = test_dir("dedup-spill");
+    let pack = raw_input(&dir);
+    let mut volume = noise(12 * 12 * 12);
+    volume[..12 * 12 * 4].fill(0);
+    fs::write(dir.join("in.raw"), &volume).unwrap();
+
+    for memory_blocks in ["4194304", "1", "5"] {
+        bvp_ok(&dir, &with(&pack, &["--archive", "SAF", "--threads", "2", "--dedup-memory-blocks", memory_blocks, "--report", "report.json"]));
+        let report: JsonValue = fs::read_to_string(dir.join("report.json")).unwrap().parse().unwrap();
+        assert_eq!(report["blocks"]["unique"].get::<f64>(), Some(&19.0), "{}", memory_blocks);
+        assert_eq!(report["blocks"]["duplicates"].get::<f64>(), Some(&8.0), "{}", memory_blocks);
+        let bvp_file = BVPFile::open(&dir.join("out.saf"), &ArchiveEnum::SAF).unwrap();
+        assert_eq!(VolumeReader::new(&bvp_file).read_modality(0).unwrap().data.unwrap().as_slice(), volume.as_slice());
+        // The index files are removed with the conversion.
+        assert!(!dir.join("out.saf.dedup").exists(), "{}", memory_blocks);
+    }
+
+    let output = bvp(&dir, &with(&pack, &["--dedup-memory-blocks", "0"]));
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("dedupMemoryBlocks: must be at least 1"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Returns the manifest of a packed SAF archive, with the creation time left out.
+/// * `path` - the archive
+fn manifest_without_creation_time(path: &Path) -> String {
+    let files = saf::from_saf_archive(&Bytes::from(fs::read(path).unwrap())).unwrap();
+    let manifest = files.iter().find(|f| f.name == "manifest.json").unwrap();
+    let text = String::from_utf8(manifest.data.as_slice().to_vec()).unwrap();
+    let start = text.find(r#""creationTime":""#).unwrap() + r#""creationTime":""#.len();
+    let end = start + text[start..].find('"').unwrap();
+    return format!("{}{}", &text[..start], &text[end..]);
+}
+
+#[test]
+fn repeated_runs_write_the_same_manifest() {
+    let dir = test_dir("same-manifest");
+    let pack = raw_input(&dir);
+    // Files are stored in the order they are finished unless the order is given, which varies with more threads.
+    let settings = ["--archive", "SAF", "--block-order", "grid"];
+    bvp_ok(&dir, &[&pack[..], &settings, &["--output-file", "first.saf", "--threads", "1"]].concat());
+    bvp_ok(&dir, &[&pack[..], &settings, &["--output-file", "second.saf", "--threads", "3"]].concat());
+    let first = manifest_without_creation_time(&dir.join("first.saf"));
+    assert_eq!(first, manifest_without_creation_time(&dir.join("second.saf")));
+    assert!(first.starts_with(r#"{"asset":{"#), "{}", first);
+    // The listing of the files at the start of the archive is written with sorted keys too.
+    let listing = |name: &str| -> Vec<u8> {
+        let archive = fs::read(dir.join(name)).unwrap();
+        let size = u32::from_le_bytes(archive[12..16].try_into().unwrap()) as usize;
+        return archive[16..16 + size].to_vec();
+    };
+    assert_eq!(listing("first.saf"), listing("second.saf"));
+    assert!(listing("first.saf").starts_with(br#"[{"path":"blocks/block_1.raw","size":64}"#));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Sends a request to a server and returns the status of the response.
+/// * `address` - address of the server
+/// * `request` - the request line and headers
+fn status_of(address: &str, request: &[u8]) -> u16 {
+    let mut stream = TcpStream::connect(address).unwrap();
+    // The server may answer and close the connection before the whole request is sent.
+    let _ = stream.write_all(request);
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    let response = String::from_utf8_lossy(&response);
+    return response.split(' ').nth(1).and_then(|s| s.parse().ok()).unwrap_or_else(|| panic!("Not a response: {}", response));
+}
+
+#[test]
+fn serve_rejects_requests_that_are_too_large() {
+    let asset = golden("mono-u8-tree").join("manifest.json");
+    let mut server = Command::new(env!("CARGO_BIN_EXE_bvp"))
+        .args(["serve", asset.to_str().unwrap(), "None", "--address", "127.0.0.1:0", "--threads", "2"])
+        .stderr(Stdio::piped())
+        .spawn().unwrap();
+    let mut banner = String::new();
+    BufReader::new(server.stderr.take().unwrap()).read_line(&mut banner).unwrap();
+    let address = banner.trim_end().rsplit("http://").next().unwrap().to_string();
+
+    assert_eq!(status_of(&address, b"GET /manifest HTTP/1.1\r\nConnection: close\r\n\r\n"), 200);
+    let long_line = format!("GET /manifest?{} HTTP/1.1\r\n\r\n", "a".repeat(10_000));
+    assert_eq!(status_of(&address, long_line.as_bytes()), 400);
+    let long_header = format!("GET /manifest HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(10_000));
+    assert_eq!(status_of(&address, long_header.as_bytes()), 431);
+    let many_headers = format!("GET /manifest HTTP/1.1\r\n{}\r\n", "X-Header: 1\r\n".repeat(101));
+    assert_eq!(status_of(&address, many_headers.as_bytes()), 431);
+    let headers = format!("GET /manifest HTTP/1.1\r\n{}Connection: close\r\n\r\n", "X-Header: 1\r\n".repeat(99));
+    assert_eq!(status_of(&address, headers.as_bytes()), 200);
+    server.kill().unwrap();
+    let _ = server.wait();
+}
@@ -0,0 +1,18 @@
+//! Tests of the block compressions.
+
+use bvp::compressions::{lz4s, CompressionType};
+
+#[test]
+fn declared_sizes_are_not_trusted_for_allocation() {
+    let zeros = vec![0u8; 1 << 20];
+    let compressed = CompressionType::LZ4S.compress(zeros.clone());
+    assert_eq!(lz4s::decompress_lz4s(&compressed, zeros.len()), zeros);
+
+    // A size from a crafted manifest allocates no more than the data can decode to.
+    let decoded = lz4s::decompress_lz4s(&compressed, usize::MAX);
+    assert_eq!(decoded.len(), zeros.len());
+    assert!(decoded.capacity() <= 2 * zeros.len());
+    assert!(lz4s::decompress_lz4s(&[0x10, 7, 0], usize::MAX).capacity() <= 3 * 255);
+    assert_eq!(lz4s::decompress_lz4s(&[0x10, 7, 0], usize::MAX), vec![7]);
+    assert!(lz4s::decompress_lz4s(&[], usize::MAX).is_empty());
+}
@@ -0,0 +1,98 @@
+//! TOML and YAML configs are read into the same JSON values as the equivalent JSON config.
+
+use tinyjson::JsonValue;
+
+use bvp::config_formats::{self, ConfigFormat};
+use bvp::formats::Format;
+
+const JSON: &str = r#"{
+    "inputFile": "head.raw",
+    "name": "it's a \"head\"",
+    "dimensions": [256, 256, 128],
+    "voxelScale": [1, 1, 2.5],
+    "deduplication": false,
+    "acquisitionTime": "2023-05-17T10:00:00Z",
+    "format": { "family": "mono", "count": 1, "size": 2, "type": "u" },
+    "additionalModalities": [
+        { "inputFile": "labels.raw", "format": { "family": "mono", "count": 1, "size": 1, "type": "u" } },
+        { "inputFile": "mask.raw", "description": "first line\nsecond line\n" }
+    ]
+}"#;
+
+const TOML: &str = r#"
+# A config of raw2bvp
+inputFile = "head.raw"
+name = "it's a \"head\""
+dimensions = [
+    256, 256, 128,  # trailing commas are allowed
+]
+voxelScale = [1, 1, 2.5]
+deduplication = false
+acquisitionTime = 2023-05-17T10:00:00Z
+format = { family = "mono", count = 1, size = 2, type = "u" }
+
+[[additionalModalities]]
+inputFile = "labels.raw"
+format.family = "mono"
+format.count = 1
+format.size = 1
+format.type = "u"
+
+[[additionalModalities]]
+inputFile = "mask.raw"
+description = """
+first line
+second line
+"""
+"#;
+
+const YAML: &str = r#"
+# A config of raw2bvp
+---
+inputFile: head.raw
+name: 'it''s a "head"'
+dimensions: [256, 256,
+  128]
+voxelScale: [1, 1, 2.5]
+deduplication: false
+acquisitionTime: "2023-05-17T10:00:00Z"  # quoted, so it stays a string
+format: {family: mono, count: 1, size: 2, type: u}
+additionalModalities:
+- inputFile: labels.raw
+  format:
+    family: mono
+    count: 1
+    size: 1
+    type: u
+- inputFile: mask.raw
+  description: |
+    first line
+    second line
+"#;
+
+#[test]
+fn toml_and_yaml_configs_read_as_json() {
+    let expected: JsonValue = JSON.parse().unwrap();
+    assert_eq!(config_formats::parse(TOML, ConfigFormat::Toml).unwrap(), expected);
+    assert_eq!(config_formats::parse(YAML, ConfigFormat::Yaml).unwrap(), expected);
+    assert_eq!(config_formats::parse(JSON, ConfigFormat::Json).unwrap(), expected);
+}
+
+#[test]
+fn errors_name_the_line() {
+    let error = config_formats::parse("a = 1\nb = [1, 2\nc = 3\n", ConfigFormat::Toml).unwrap_err();
+    assert_eq!(error.to_string(), "Invalid TOML on line 3: expected `,` or `]` in an array");
+    let error = config_formats::parse("a: 1\n  b: 2\n", ConfigFormat::Yaml).unwrap_err();
+    assert!(error.to_string().starts_with("Invalid YAML on line 2:"), "{}", error);
+    assert_eq!(ConfigFormat::from_path("dir/config.YML".as_ref()), ConfigFormat::Yaml);
+}
+
+#[test]
+fn formats_in_toml_and_yaml_map_like_json() {
+    let toml = "family = \"multi\"\n[[components]]\ncount = 3\nsize = 3\ntype = \"u\"\n[[components]]\ncount = 1\nsize = 2\ntype = \"i\"\n";
+    let yaml = "family: multi\ncomponents:\n  - { count: 3, size: 3, type: u }\n  - count: 1\n    size: 2\n    type: i\n";
+    let json = r#"{ "family": "multi", "components": [{ "count": 3, "size": 3, "type": "u" }, { "count": 1, "size": 2, "type": "i" }] }"#;
+    let expected = Format::from_json(&json.parse().unwrap()).unwrap();
+    assert_eq!(Format::from_json(&config_formats::parse(toml, ConfigFormat::Toml).unwrap()).unwrap(), expected);
+    assert_eq!(Format::from_json(&config_formats::parse(yaml, ConfigFormat::Yaml).unwrap()).unwrap(), expected);
+}
@@ -0,0 +1,580 @@
+//! Tests of the conversions of `bvp::convert` with output and progress handled by the application.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bvp::archives::{ArchiveEnum, ArchiveWriter};
+use bvp::bvpfile::BVPFile;
+use bvp::compressions::{deflate, CompressionType};
+use bvp::convert::filters::{self, Filter};
+use bvp::convert::mask::IsosurfaceMask;
+use bvp::convert::rescale::Rescale;
+use bvp::convert::{self, BlockOrder, InputLayout, ParallelMode, Parameters, ParametersBuilder, RawExportOptions};
+use bvp::errors::{ArchiveError, ConvertError, ParametersError};
+use bvp::file::File;
+use bvp::formats::{self, Format};
+use bvp::layout::VoxelLayout;
+use bvp::occupancy;
+use bvp::metrics::PipelineStage;
+use bvp::progress::{ProgressCounters, ProgressSink, ProgressSnapshot};
+use bvp::reader::VolumeReader;
+use bvp::source;
+use bvp::vector3::Vector3;
+
+/// Keeps the written files in memory.
+#[derive(Default)]
+struct MemoryWriter {
+    files: Mutex<Vec<File>>,
+    finished: Mutex<Option<String>>
+}
+
+impl ArchiveWriter for &MemoryWriter {
+    fn append_file(&mut self, file: &File) -> Result<(), ArchiveError> {
+        self.files.lock().unwrap().push(file.clone());
+        return Ok(());
+    }
+
+    fn finish(&mut self, path: String) -> Result<(), ArchiveError> {
+        *self.finished.lock().unwrap() = Some(path);
+        return Ok(());
+    }
+}
+
+/// Counts processed blocks.
+#[derive(Default)]
+struct BlockCounter {
+    total: AtomicUsize,
+    processed: AtomicUsize
+}
+
+impl ProgressSink for BlockCounter {
+    fn start(&self, total_blocks: usize) {
+        self.total.store(total_blocks, Ordering::Relaxed);
+    }
+
+    fn block_processed(&self, _duplicate: bool) {
+        self.processed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn u8_format() -> Format {
+    return Format::from_json(&formats::shorthand_to_json("u8").unwrap()).unwrap();
+}
+
+/// Returns the builder of the parameters the tests convert with: a volume of 20x12x9 voxels in blocks
+/// of 8x8x4, compressed with LZ4 and written to a SAF archive.
+/// * `input_file` - the input
+/// * `input_format` - format of the voxels in the input
+/// * `parallel_mode` - how the conversion is parallelized
+fn builder(input_file: &Path, input_format: Format, parallel_mode: ParallelMode) -> ParametersBuilder {
+    return Parameters::builder(input_file.to_string_lossy(), "volume.saf", Vector3::from_xyz(20, 12, 9), Vector3::from_xyz(8, 8, 4), input_format)
+        .with_name(Some("volume".to_string()))
+        .with_archive(ArchiveEnum::SAF)
+        .with_compression(CompressionType::LZ4S)
+        .with_threads(Some(2))
+        .with_block_order(BlockOrder::Grid)
+        .with_parallel_mode(parallel_mode);
+}
+
+/// Converts into memory and returns the asset.
+/// * `parameters` - the conversion
+fn convert_to_memory(parameters: ParametersBuilder) -> BVPFile {
+    let writer = MemoryWriter::default();
+    convert::raw_to_bvp(parameters.build().unwrap(), &writer, BlockCounter::default()).unwrap();
+    return BVPFile::from_files(writer.files.into_inner().unwrap()).unwrap();
+}
+
+/// Returns the voxels of a modality, as `bvp2raw` writes them.
+/// * `bvp_file` - the asset
+/// * `modality_index` - the modality
+fn export(bvp_file: &BVPFile, modality_index: usize) -> Vec<u8> {
+    let mut raw = Vec::new();
+    let options = RawExportOptions { region: None, slab_thickness: None };
+    convert::bvp_to_raw(&VolumeReader::new(bvp_file), modality_index, &options, &mut raw, BlockCounter::default()).unwrap();
+    return raw;
+}
+
+#[test]
+fn conversions_write_to_custom_sinks() {
+    let dimensions = Vector3::from_xyz(20, 12, 9);
+    // The lower half of the volume is zero, so the blocks there with the same dimensions are duplicates.
+    let data: Vec<u8> = (0..20 * 12 * 9).map(|i| if i < 20 * 12 * 4 { 0 } else { (i % 251) as u8 }).collect();
+    let input = env::temp_dir().join(format!("bvp-convert-{}.raw", std::process::id()));
+    fs::write(&input, &data).unwrap();
+
+    for parallel_mode in [ParallelMode::Pipeline, ParallelMode::Data] {
+        let writer = MemoryWriter::default();
+        let progress = BlockCounter::default();
+        let metrics = convert::raw_to_bvp(builder(&input, u8_format(), parallel_mode).build().unwrap(), &writer, &progress).unwrap();
+        // 3x2x3 blocks, of which the lower 3x2 are zero, in 4 different dimensions
+        assert_eq!(progress.total.load(Ordering::Relaxed), 18);
+        assert_eq!(progress.processed.load(Ordering::Relaxed), 18);
+        assert_eq!((metrics.blocks, metrics.duplicates), (18, 2));
+        assert_eq!(writer.finished.lock().unwrap().as_deref(), Some("volume.saf"));
+
+        let bvp_file = BVPFile::from_files(writer.files.into_inner().unwrap()).unwrap();
+        assert_eq!(bvp_file.modalities[0].name.as_deref(), Some("volume"));
+        assert!(bvp_file.asset.generator.as_deref().unwrap().starts_with("raw2bvp (bvp-tool "));
+        let reader = VolumeReader::new(&bvp_file);
+        let mut raw = Vec::new();
+        let options = RawExportOptions { region: None, slab_thickness: Some(4) };
+        convert::bvp_to_raw(&reader, 0, &options, &mut raw, BlockCounter::default()).unwrap();
+        assert_eq!(raw, data, "{:?}", parallel_mode);
+
+        let options = RawExportOptions { region: Some((Vector3::from_xyz(1, 2, 3), Vector3::from_xyz(5, 6, 8))), slab_thickness: Some(2) };
+        let slabs: Vec<_> = convert::slabs(&reader, 0, &options).unwrap().map(|slab| slab.unwrap()).collect();
+        assert_eq!(slabs.iter().map(|s| (s.start.z, s.end.z)).collect::<Vec<_>>(), vec![(3, 5), (5, 7), (7, 8)]);
+        assert_eq!(slabs[0].data[0], data[Vector3::linear_index(Vector3::from_xyz(1, 2, 3), dimensions)]);
+    }
+    fs::remove_file(&input).unwrap();
+
+    let bvp_file = BVPFile::new();
+    let reader = VolumeReader::new(&bvp_file);
+    let empty = RawExportOptions { region: Some((Vector3::from_xyz(1, 0, 0), Vector3::from_xyz(1, 4, 4))), slab_thickness: None };
+    assert!(matches!(convert::export_region(&reader, 0, &empty), Err(ConvertError::EmptyRegion(_, _))));
+}
+
+/// Records the progress after every processed block, as the progress line does.
+#[derive(Default)]
+struct ProgressRecorder {
+    counters: ProgressCounters,
+    snapshots: Mutex<Vec<ProgressSnapshot>>
+}
+
+impl ProgressSink for ProgressRecorder {
+    fn start(&self, total_blocks: usize) {
+        self.counters.start(total_blocks);
+    }
+
+    fn block_processed(&self, duplicate: bool) {
+        // Locked first, so the snapshots are in the order the blocks were counted in.
+        let mut snapshots = self.snapshots.lock().unwrap();
+        self.counters.block_processed(duplicate);
+        snapshots.push(self.counters.snapshot());
+    }
+
+    fn bytes_written(&self, bytes: usize) {
+        self.counters.bytes_written(bytes);
+    }
+}
+
+#[test]
+fn progress_is_reported_with_an_estimate() {
+    let snapshot = ProgressSnapshot { blocks_total: 8, blocks_processed: 2, duplicates: 0, bytes_written: 0, elapsed: Duration::from_secs(3) };
+    assert_eq!(snapshot.fraction(), 0.25);
+    assert_eq!(snapshot.eta(), Some(Duration::from_secs(9)));
+    assert_eq!(ProgressSnapshot { blocks_processed: 0, ..snapshot }.eta(), None);
+    assert_eq!(ProgressSnapshot { blocks_processed: 8, ..snapshot }.eta(), Some(Duration::ZERO));
+    assert_eq!(ProgressSnapshot { blocks_total: 0, blocks_processed: 0, ..snapshot }.fraction(), 1.0);
+
+    let data: Vec<u8> = (0..20 * 12 * 9).map(|i| if i < 20 * 12 * 4 { 0 } else { (i % 251) as u8 }).collect();
+    let input = env::temp_dir().join(format!("bvp-convert-progress-{}.raw", std::process::id()));
+    fs::write(&input, &data).unwrap();
+    for parallel_mode in [ParallelMode::Pipeline, ParallelMode::Data] {
+        let progress = ProgressRecorder::default();
+        convert::raw_to_bvp(builder(&input, u8_format(), parallel_mode).build().unwrap(), &MemoryWriter::default(), &progress).unwrap();
+        let snapshots = progress.snapshots.into_inner().unwrap();
+        assert_eq!(snapshots.len(), 18);
+        assert!(snapshots.iter().all(|s| s.blocks_total == 18 && s.eta().is_some()));
+        assert!(snapshots.windows(2).all(|w| w[0].fraction() <= w[1].fraction()));
+        let last = progress.counters.snapshot();
+        assert_eq!((last.blocks_processed, last.duplicates, last.fraction()), (18, 2, 1.0));
+        assert!(last.bytes_written > 0, "{:?}", parallel_mode);
+    }
+    fs::remove_file(&input).unwrap();
+}
+
+#[test]
+fn built_parameters_are_validated() {
+    let builder = |dimensions: Vector3<u32>, block_dimensions: Vector3<u32>| {
+        return Parameters::builder("volume.raw", "volume.saf", dimensions, block_dimensions, u8_format())
+            .with_archive(ArchiveEnum::SAF);
+    };
+    let parameters = builder(Vector3::from_xyz(20, 12, 9), Vector3::from_xyz(8, 8, 4)).build().unwrap();
+    assert_eq!(parameters.compression_level, bvp::compressions::MAX_COMPRESSION_LEVEL);
+    assert_eq!(parameters.block_order, BlockOrder::Completion);
+
+    let result = builder(Vector3::from_xyz(20, 0, 9), Vector3::from_xyz(8, 8, 4)).build();
+    assert!(matches!(result, Err(ParametersError::ZeroDimensions(field, _)) if field == "dimensions"));
+    let result = builder(Vector3::from_xyz(20, 12, 9), Vector3::from_xyz(32, 8, 4)).build();
+    assert!(matches!(result, Err(ParametersError::BlockLargerThanVolume(_, _))));
+    let result = builder(Vector3::from_xyz(20, 12, 9), Vector3::from_xyz(8, 8, 4))
+        .with_superblock_dimensions(vec![Vector3::from_xyz(16, 12, 8)])
+        .build();
+    assert!(matches!(result, Err(ParametersError::NotMultiple(field, _, _)) if field == "superblock_dimensions[0]"));
+    let result = builder(Vector3::from_xyz(20, 12, 9), Vector3::from_xyz(8, 8, 4))
+        .with_archive(ArchiveEnum::None)
+        .build();
+    assert!(result.is_ok());
+    let result = Parameters::builder("volume.raw", "-", Vector3::from_xyz(20, 12, 9), Vector3::from_xyz(8, 8, 4), u8_format()).build();
+    assert!(matches!(result, Err(ParametersError::UnarchivedOutput(_))));
+}
+
+#[test]
+fn compressed_inputs_are_decompressed() {
+    let data: Vec<u8> = (0..20 * 12 * 9).map(|i| if i < 960 { 0 } else { ((i % 400) * (i % 400) / 5 % 13) as u8 }).collect();
+    let mut gzip = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    gzip.extend(deflate::deflate(&data, 9));
+    gzip.extend(crc32fast::hash(&data).to_le_bytes());
+    gzip.extend((data.len() as u32).to_le_bytes());
+    // `zstd -19` of the data, with Huffman coded literals and FSE coded sequences
+    let zstd = vec![
+        0x28, 0xb5, 0x2f, 0xfd, 0x64, 0x70, 0x07, 0xdd, 0x01, 0x00, 0x22, 0x04, 0x09, 0x8b, 0x21, 0x11,
+        0x02, 0x12, 0x11, 0x11, 0x67, 0x94, 0x0e, 0x6a, 0xe1, 0xf7, 0x43, 0xc7, 0x59, 0x5e, 0xae, 0x00,
+        0x49, 0x12, 0xa4, 0xae, 0x8b, 0x2f, 0xcc, 0x79, 0xef, 0xb0, 0xc2, 0x51, 0x83, 0x3f, 0xab, 0xb2,
+        0x63, 0x05, 0x00, 0x2d, 0x26, 0x57, 0x05, 0x4c, 0xa0, 0x82, 0xa1, 0x26, 0x41, 0xc0, 0x22, 0x02,
+        0x65, 0x78, 0x57, 0x40, 0x08, 0x66, 0x8c, 0x47, 0x43
+    ];
+
+    // The name of the modality leaves out both extensions.
+    for (extension, compressed) in [("raw.gz", gzip.clone()), ("raw.zst", zstd)] {
+        let input = env::temp_dir().join(format!("bvp-convert-{}.{}", std::process::id(), extension));
+        fs::write(&input, &compressed).unwrap();
+        let bvp_file = convert_to_memory(builder(&input, u8_format(), ParallelMode::Pipeline));
+        fs::remove_file(&input).unwrap();
+        assert_eq!(export(&bvp_file, 0), data, "{}", extension);
+    }
+
+    // Damaged members are not converted, and neither are files that are named like compressed ones but are not.
+    let mut damaged = gzip;
+    let crc = damaged.len() - 8;
+    damaged[crc] ^= 1;
+    for (name, content) in [("damaged.raw.gz", damaged), ("plain.raw.gz", data.clone())] {
+        let input = env::temp_dir().join(format!("bvp-convert-{}-{}", std::process::id(), name));
+        fs::write(&input, &content).unwrap();
+        let writer = MemoryWriter::default();
+        let result = convert::raw_to_bvp(builder(&input, u8_format(), ParallelMode::Pipeline).build().unwrap(), &writer, BlockCounter::default());
+        fs::remove_file(&input).unwrap();
+        assert!(result.is_err(), "{}", name);
+    }
+    let error = convert::raw_to_bvp(builder(Path::new("missing.raw.gz"), u8_format(), ParallelMode::Pipeline).build().unwrap(), &MemoryWriter::default(), BlockCounter::default());
+    assert!(error.is_err());
+}
+
+#[test]
+fn headers_and_padding_are_skipped() {
+    let data: Vec<u8> = (0..20 * 12 * 9).map(|i| (i % 251) as u8).collect();
+    // A 16 byte header, rows padded to 24 bytes and 8 bytes after every plane but the last
+    let mut padded = vec![0xff; 16];
+    for (z, plane) in data.chunks(20 * 12).enumerate() {
+        for row in plane.chunks(20) {
+            padded.extend(row);
+            padded.extend([0xff; 4]);
+        }
+        if z < 8 {
+            padded.extend([0xff; 8]);
+        }
+    }
+    let input = env::temp_dir().join(format!("bvp-convert-{}-padded.raw", std::process::id()));
+    fs::write(&input, &padded).unwrap();
+    let layout = InputLayout { offset: 16, row_stride: Some(24), plane_padding: 8 };
+    // The padding of the last row does not have to be in the file.
+    assert_eq!(layout.file_size(Vector3::from_xyz(20, 12, 9), &u8_format()), padded.len() as u64 - 4);
+    let bvp_file = convert_to_memory(builder(&input, u8_format(), ParallelMode::Data).with_input_layout(layout));
+    // None of the bytes that were skipped is in the volume.
+    let raw = export(&bvp_file, 0);
+    assert_eq!(raw, data);
+
+    // Without the layout, the file is too large for the volume.
+    let writer = MemoryWriter::default();
+    let error = convert::raw_to_bvp(builder(&input, u8_format(), ParallelMode::Data).build().unwrap(), &writer, BlockCounter::default()).unwrap_err();
+    assert!(error.to_string().contains(&format!("holds {} bytes", padded.len())), "{}", error);
+    fs::remove_file(&input).unwrap();
+
+    let result = builder(&input, u8_format(), ParallelMode::Data)
+        .with_input_layout(InputLayout { offset: 0, row_stride: Some(19), plane_padding: 0 })
+        .build();
+    assert!(matches!(result, Err(ParametersError::RowStride(_, 19, 20))));
+}
+
+#[test]
+fn values_are_rescaled_on_import() {
+    // Stored CT values, which are Hounsfield units after subtracting 1024
+    let values: Vec<u16> = (0..20 * 12 * 9).map(|i| (i * 7 % 3000) as u16).collect();
+    let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let input = env::temp_dir().join(format!("bvp-convert-{}-ct.raw", std::process::id()));
+    fs::write(&input, &data).unwrap();
+    let u16_format = Format::from_json(&formats::shorthand_to_json("u16").unwrap()).unwrap();
+    let f32_format = Format::from_json(&formats::shorthand_to_json("f32").unwrap()).unwrap();
+    assert_eq!(Rescale::new(0.5, 0.0).output_format(&u16_format, &data).unwrap().to_json(), f32_format.to_json());
+
+    let bvp_file = convert_to_memory(builder(&input, u16_format, ParallelMode::Pipeline).with_rescale(Some(Rescale::new(1.0, -1024.0))));
+    fs::remove_file(&input).unwrap();
+
+    let format = bvp_file.formats[0].first_component().unwrap();
+    assert_eq!((format.component_type(), format.size()), (&formats::PrimitiveType::Int, 2));
+    let raw = export(&bvp_file, 0);
+    let rescaled: Vec<i16> = raw.chunks(2).map(|c| i16::from_le_bytes([c[0], c[1]])).collect();
+    assert_eq!(rescaled, values.iter().map(|v| *v as i16 - 1024).collect::<Vec<_>>());
+}
+
+#[test]
+fn filters_clean_noisy_volumes() {
+    let dimensions = Vector3::from_xyz(20, 12, 9);
+    let u16_format = Format::from_json(&formats::shorthand_to_json("u16").unwrap()).unwrap();
+    let volume = |values: &[u16]| values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>();
+    let values = |data: &[u8]| data.chunks(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect::<Vec<u16>>();
+    // A constant volume with a few hot voxels, one of them in the plane at the edge of the first slab
+    let mut noisy = vec![100u16; 20 * 12 * 9];
+    for i in [0, 517, 20 * 12 * 7 + 33, noisy.len() - 1] {
+        noisy[i] = 60000;
+    }
+
+    let median = filters::apply(&[Filter::Median { radius: 1 }], volume(&noisy), dimensions, &u16_format, 3);
+    assert_eq!(values(&median), vec![100; noisy.len()]);
+    let filters = [Filter::Gaussian { sigma: 1.0 }, Filter::Clamp { min: 0.0, max: 150.0 }];
+    let smoothed = values(&filters::apply(&filters, volume(&noisy), dimensions, &u16_format, 2));
+    assert!(smoothed.iter().all(|v| (100..=150).contains(v)));
+    assert_eq!(smoothed[20 * 12 * 4 + 20 * 6 + 10], 100);
+    assert_eq!(smoothed[517], 150);
+
+    // The same filters are applied to the input of a conversion, across the edges of the slabs it is filtered in.
+    let input = env::temp_dir().join(format!("bvp-convert-{}-noisy.raw", std::process::id()));
+    fs::write(&input, volume(&noisy)).unwrap();
+    let bvp_file = convert_to_memory(builder(&input, u16_format.clone(), ParallelMode::Data).with_filters(vec![Filter::Median { radius: 1 }]));
+    fs::remove_file(&input).unwrap();
+    assert_eq!(values(&export(&bvp_file, 0)), vec![100; noisy.len()]);
+    assert!(bvp_file.blocks[0].placements.len() > 1);
+
+    let json = "[{\"type\": \"median\", \"radius\": 9}, {\"type\": \"clamp\", \"min\": 0}]".parse::<tinyjson::JsonValue>().unwrap();
+    let json: &Vec<tinyjson::JsonValue> = json.get().unwrap();
+    assert!(matches!(Filter::from_json(&json[0]), Err(bvp::errors::FilterError::InvalidValue(field, _, _)) if field == "radius"));
+    assert_eq!(Filter::from_json(&json[1]).unwrap(), Filter::Clamp { min: 0.0, max: f64::INFINITY });
+}
+
+#[test]
+fn isosurface_masks_are_stored_as_modalities() {
+    let data: Vec<u8> = (0..20 * 12 * 9).map(|i| (i * 31 % 256) as u8).collect();
+    let input = env::temp_dir().join(format!("bvp-convert-{}-masked.raw", std::process::id()));
+    fs::write(&input, &data).unwrap();
+    // Rows of 1 bit masks are stored 8 voxels to a byte.
+    let result = builder(&input, u8_format(), ParallelMode::Data)
+        .with_isosurface_mask(Some(IsosurfaceMask { isovalue: 128.0, bits: 1, name: None }))
+        .build();
+    assert!(matches!(result, Err(ParametersError::NotMultiple(field, _, _)) if field == "isosurface_mask"));
+    let bvp_file = convert_to_memory(builder(&input, u8_format(), ParallelMode::Data).with_isosurface_mask(Some(IsosurfaceMask::new(128.0))));
+    fs::remove_file(&input).unwrap();
+
+    assert_eq!(bvp_file.modalities[1].name.as_deref(), Some("mask"));
+    assert_eq!(export(&bvp_file, 0), data);
+    assert_eq!(export(&bvp_file, 1), data.iter().map(|v| (*v >= 128) as u8).collect::<Vec<_>>());
+
+    let mask = IsosurfaceMask { isovalue: 128.0, bits: 1, name: None };
+    assert_eq!(mask.generate(&data[..16], &u8_format()).unwrap(), vec![0b1110_0000, 0b1110_0001]);
+}
+
+#[test]
+fn blocks_record_their_occupancy() {
+    // Two voxels are set, one in the first block and one in the last.
+    let mut data = vec![0u8; 20 * 12 * 9];
+    data[1 + 20 * (1 + 12 * 1)] = 7;
+    data[17 + 20 * (10 + 12 * 8)] = 9;
+    let input = env::temp_dir().join(format!("bvp-convert-{}-occupancy.raw", std::process::id()));
+    fs::write(&input, &data).unwrap();
+
+    for (parallel_mode, voxel_layout) in [(ParallelMode::Pipeline, VoxelLayout::Linear), (ParallelMode::Data, VoxelLayout::Morton)] {
+        let bvp_file = convert_to_memory(builder(&input, u8_format(), parallel_mode)
+            .with_voxel_layout(voxel_layout)
+            .with_occupancy_grid(Some(Vector3::from_xyz(4, 4, 4))));
+        let block_at = |position: Vector3<u32>| {
+            let placement = bvp_file.blocks[0].placements.iter().find(|p| p.position == position).unwrap();
+            return occupancy::occupancy(&bvp_file.blocks[placement.block]).unwrap().unwrap();
+        };
+        // Cells of blocks of 8x8x4 voxels are 2x2x1 voxels, and the last blocks are cut to 4x4x1 voxels.
+        let first = block_at(Vector3::from_xyz(0, 0, 0));
+        assert_eq!((first.cell_dimensions, first.cells), (Vector3::from_xyz(2, 2, 1), Vector3::from_xyz(4, 4, 4)));
+        assert_eq!(first.occupied_cells(), 1);
+        assert!(first.is_occupied(Vector3::from_xyz(0, 0, 1)));
+        let last = block_at(Vector3::from_xyz(16, 8, 8));
+        assert_eq!(last.cells, Vector3::from_xyz(2, 2, 1));
+        assert_eq!(last.occupied_cells(), 1);
+        assert!(last.is_occupied(Vector3::from_xyz(0, 1, 0)));
+        assert!(block_at(Vector3::from_xyz(8, 0, 0)).is_empty());
+    }
+    fs::remove_file(&input).unwrap();
+}
+
+#[test]
+fn assets_record_their_source() {
+    let data: Vec<u8> = (0..20 * 12 * 9).map(|i| (i % 7) as u8).collect();
+    let input = env::temp_dir().join(format!("bvp-convert-{}-source.raw", std::process::id()));
+    fs::write(&input, &data).unwrap();
+
+    let bvp_file = convert_to_memory(builder(&input, u8_format(), ParallelMode::Pipeline).with_record_source(true));
+    let recorded = source::source(&bvp_file.asset).unwrap().unwrap();
+    assert_eq!(recorded.name, input.file_name().unwrap().to_string_lossy());
+    assert_eq!(recorded.size, data.len() as u64);
+    assert!(source::is_same_source(&recorded, &data));
+    let mut changed = data.clone();
+    changed[5] += 1;
+    assert!(!source::is_same_source(&recorded, &changed));
+    assert!(bvp_file.asset.extensions_used.contains(&"EXT_source".to_string()));
+    fs::remove_file(&input).unwrap();
+}
+
+#[test]
+fn inputs_of_the_wrong_size_are_rejected() {
+    let data: Vec<u8> = (0..20 * 12 * 9).map(|i| (i % 251) as u8).collect();
+    let input = env::temp_dir().join(format!("bvp-convert-{}-size.raw", std::process::id()));
+    for size in [data.len() - 1, data.len() + 1] {
+        let mut file = data.clone();
+        file.resize(size, 3);
+        fs::write(&input, &file).unwrap();
+        let writer = MemoryWriter::default();
+        let error = convert::raw_to_bvp(builder(&input, u8_format(), ParallelMode::Pipeline).build().unwrap(), &writer, BlockCounter::default()).unwrap_err();
+        assert!(error.to_string().contains(&format!("holds {} bytes", size)), "{}", error);
+
+        // Allowed, the missing voxel is zero and the extra byte is left out.
+        let bvp_file = convert_to_memory(builder(&input, u8_format(), ParallelMode::Data).with_allow_size_mismatch(true));
+        file.resize(data.len(), 0);
+        assert_eq!(export(&bvp_file, 0), file);
+    }
+    fs::remove_file(&input).unwrap();
+}
+
+/// Fails on the first file, like a conversion that is stopped after converting the blocks.
+struct FailingWriter;
+
+impl ArchiveWriter for FailingWriter {
+    fn append_file(&mut self, file: &File) -> Result<(), ArchiveError> {
+        return Err(ArchiveError::CannotWrite(file.name.clone()));
+    }
+
+    fn finish(&mut self, path: String) -> Result<(), ArchiveError> {
+        return Err(ArchiveError::CannotWrite(path));
+    }
+}
+
+/// Counts blocks that are compressed, rather than taken from a checkpoint.
+#[derive(Default)]
+struct CompressionCounter {
+    compressed: AtomicUsize
+}
+
+impl ProgressSink for CompressionCounter {
+    fn work_done(&self, stage: PipelineStage, _duration: Duration) {
+        if stage == PipelineStage::Compress {
+            self.compressed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[test]
+fn interrupted_conversions_resume_from_the_checkpoint() {
+    let dimensions = Vector3::from_xyz(20, 12, 9);
+    let data: Vec<u8> = (0..20 * 12 * 9).map(|i| if i < 20 * 12 * 4 { 0 } else { (i % 251) as u8 }).collect();
+    let dir = env::temp_dir().join(format!("bvp-convert-checkpoint-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("volume.raw");
+    fs::write(&input, &data).unwrap();
+    let output = dir.join("volume.saf");
+    let checkpoint = dir.join("volume.saf.checkpoint");
+
+    for parallel_mode in [ParallelMode::Pipeline, ParallelMode::Data] {
+        let parameters = || {
+            return Parameters::builder(input.to_string_lossy(), output.to_string_lossy(), dimensions, Vector3::from_xyz(8, 8, 4), u8_format())
+                .with_archive(ArchiveEnum::SAF)
+                .with_compression(CompressionType::LZ4S)
+                .with_block_order(BlockOrder::Grid)
+                .with_parallel_mode(parallel_mode)
+                .with_checkpoint(true)
+                .build()
+                .unwrap();
+        };
+        let _ = fs::remove_dir_all(&checkpoint);
+        let progress = CompressionCounter::default();
+        assert!(convert::raw_to_bvp(parameters(), FailingWriter, &progress).is_err());
+        // 18 blocks, of which 2 are duplicates.
+        assert_eq!(progress.compressed.load(Ordering::Relaxed), 16);
+
+        // Keep the ranges of the first half of the journal, as if the conversion had been stopped
+        // after syncing them, with a line and block data written after the sync.
+        let journal: Vec<String> = fs::read_to_string(checkpoint.join("journal.jsonl")).unwrap().lines().map(|l| l.to_string()).collect();
+        assert_eq!(journal.len(), 1 + 18);
+        let kept = &journal[..1 + 9];
+        let kept_stored = kept[1..].iter().filter(|line| line.contains("\"block\"")).count();
+        fs::write(checkpoint.join("journal.jsonl"), format!("{}\n{{\"root\":0,\"sta", kept.join("\n"))).unwrap();
+        let mut blocks = fs::read(checkpoint.join("blocks.bin")).unwrap();
+        blocks.extend_from_slice(&[0xab; 100]);
+        fs::write(checkpoint.join("blocks.bin"), blocks).unwrap();
+
+        let writer = MemoryWriter::default();
+        let progress = CompressionCounter::default();
+        let metrics = convert::raw_to_bvp(parameters(), &writer, &progress).unwrap();
+        assert_eq!(progress.compressed.load(Ordering::Relaxed), 16 - kept_stored, "{:?}", parallel_mode);
+        assert_eq!((metrics.blocks, metrics.duplicates), (18, 2));
+
+        let bvp_file = BVPFile::from_files(writer.files.into_inner().unwrap()).unwrap();
+        assert_eq!(export(&bvp_file, 0), data, "{:?}", parallel_mode);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn both_parallel_modes_write_the_same_asset() {
+    let data: Vec<u8> = (0..20 * 12 * 9).map(|i| if i < 20 * 12 * 4 { 0 } else { (i * 13 % 251) as u8 }).collect();
+    let input = env::temp_dir().join(format!("bvp-convert-{}-modes.raw", std::process::id()));
+    fs::write(&input, &data).unwrap();
+    // The data mode has to be chosen.
+    let parameters = Parameters::builder("volume.raw", "volume.saf", Vector3::from_xyz(20, 12, 9), Vector3::from_xyz(8, 8, 4), u8_format())
+        .with_archive(ArchiveEnum::SAF)
+        .build()
+        .unwrap();
+    assert!(matches!(parameters.parallel_mode, ParallelMode::Pipeline));
+
+    // Blocks are numbered and stored in the same order, so the block files are the same.
+    let block_files = |parallel_mode: ParallelMode| {
+        let writer = MemoryWriter::default();
+        convert::raw_to_bvp(builder(&input, u8_format(), parallel_mode).build().unwrap(), &writer, BlockCounter::default()).unwrap();
+        return writer.files.into_inner().unwrap().into_iter()
+            .filter(|file| !file.name.ends_with("manifest.json"))
+            .map(|file| (file.name.clone(), file.data.to_vec()))
+            .collect::<Vec<_>>();
+    };
+    let pipeline = block_files(ParallelMode::Pipeline);
+    assert_eq!(pipeline.len(), 16);
+    assert_eq!(block_files(ParallelMode::Data), pipeline);
+    fs::remove_file(&input).unwrap();
+}
+
+/// Returns the number of levels of blocks below a block, counting itself.
+/// * `bvp_file` - the asset
+/// * `block` - index of the block
+fn depth(bvp_file: &BVPFile, block: usize) -> usize {
+    return 1 + bvp_file.blocks[block].placements.iter().map(|p| depth(bvp_file, p.block)).max().unwrap_or(0);
+}
+
+#[test]
+fn superblocks_group_blocks_into_levels() {
+    // Zero except for the last slices, so the superblocks of the lower half are the same.
+    let data: Vec<u8> = (0..20 * 12 * 9).map(|i| if i < 20 * 12 * 8 { 0 } else { (i % 251) as u8 }).collect();
+    let input = env::temp_dir().join(format!("bvp-convert-{}-superblocks.raw", std::process::id()));
+    fs::write(&input, &data).unwrap();
+    for parallel_mode in [ParallelMode::Pipeline, ParallelMode::Data] {
+        for (levels, expected_depth) in [(vec![], 2), (vec![Vector3::from_xyz(16, 16, 8)], 3), (vec![Vector3::from_xyz(16, 16, 8), Vector3::from_xyz(8, 8, 8)], 4)] {
+            let bvp_file = convert_to_memory(builder(&input, u8_format(), parallel_mode).with_superblock_dimensions(levels.clone()));
+            let root = bvp_file.modalities[0].block;
+            assert_eq!(depth(&bvp_file, root), expected_depth, "{:?}", levels);
+            // Superblocks only hold placements, data is in the innermost blocks.
+            for block in &bvp_file.blocks {
+                assert!(block.data.is_none() || block.placements.is_empty());
+            }
+            assert_eq!(export(&bvp_file, 0), data, "{:?} {:?}", parallel_mode, levels);
+        }
+        // The first two superblocks of 8x8x8 are zero and are stored once, the third is cut off by the volume.
+        let bvp_file = convert_to_memory(builder(&input, u8_format(), parallel_mode).with_superblock_dimensions(vec![Vector3::from_xyz(8, 8, 8)]));
+        let root = &bvp_file.blocks[bvp_file.modalities[0].block];
+        assert_eq!(root.placements.len(), 3 * 2 * 2);
+        assert_eq!(root.placements[0].block, root.placements[1].block);
+    }
+    fs::remove_file(&input).unwrap();
+
+    for levels in [vec![Vector3::from_xyz(12, 8, 4)], vec![Vector3::from_xyz(16, 16, 8), Vector3::from_xyz(16, 16, 16)]] {
+        let result = builder(&input, u8_format(), ParallelMode::Pipeline).with_superblock_dimensions(levels.clone()).build();
+        assert!(matches!(result, Err(ParametersError::NotMultiple(_, _, _))), "{:?}", levels);
+    }
+}
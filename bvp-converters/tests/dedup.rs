@@ -0,0 +1,147 @@
+//! Tests of the sharded deduplication map: lookups from many threads at once,
+//! hash collisions and entries moved to the disk.
+
+use std::{cell::Cell, collections::HashSet, env, fs, sync::{mpsc, Arc}, thread, time::Duration};
+
+use bvp::dedup::{DedupResult, ShardedBlockMap, SpillSettings};
+
+/// Data of the blocks the tests look up, many of them the same.
+fn block_data(i: usize) -> u32 {
+    return (i % 97) as u32;
+}
+
+/// Deliberately weak hash, so different data collides.
+fn weak_hash(data: u32) -> u64 {
+    return (data % 13) as u64;
+}
+
+fn look_up_from_threads(map: Arc<ShardedBlockMap<u32>>, threads: usize, blocks: usize) -> Vec<(usize, DedupResult)> {
+    let mut handles = Vec::new();
+    for t in 0..threads {
+        let map = map.clone();
+        handles.push(thread::spawn(move || {
+            let mut results = Vec::new();
+            for i in (t..blocks).step_by(threads) {
+                let data = block_data(i);
+                let result = map.find_or_insert(weak_hash(data), data, |entry| entry.key == data)
+                    .expect("Lookup failed!");
+                results.push((i, result));
+            }
+            return results;
+        }));
+    }
+    return handles.into_iter().flat_map(|h| h.join().unwrap()).collect();
+}
+
+fn check_results(results: &[(usize, DedupResult)]) {
+    let mut block_of_data = vec![None; 97];
+    let mut new_blocks = HashSet::new();
+    for (i, result) in results {
+        let block = match result {
+            DedupResult::New(block) => {
+                assert!(new_blocks.insert(*block), "Block {} was allocated twice", block);
+                *block
+            },
+            DedupResult::Existing(block) => *block
+        };
+        let data = block_data(*i);
+        match block_of_data[data as usize] {
+            None => block_of_data[data as usize] = Some(block),
+            Some(previous) => assert_eq!(previous, block, "Same data got different blocks")
+        }
+    }
+    // Every distinct piece of data got exactly one block, and indices are contiguous.
+    assert_eq!(new_blocks.len(), 97);
+    assert_eq!(new_blocks, (5..5 + 97).collect::<HashSet<_>>());
+}
+
+#[test]
+fn concurrent_lookups_store_each_data_once() {
+    let map = Arc::new(ShardedBlockMap::new(5));
+    let results = look_up_from_threads(map, 8, 5000);
+    assert_eq!(results.len(), 5000);
+    check_results(&results);
+}
+
+#[test]
+fn concurrent_lookups_with_spilled_entries_store_each_data_once() {
+    let folder = env::temp_dir().join(format!("bvp-dedup-{}", std::process::id()));
+    let map = Arc::new(ShardedBlockMap::with_spill(5, SpillSettings {
+        folder: folder.clone(),
+        memory_entries: 1
+    }));
+    let results = look_up_from_threads(map.clone(), 4, 2000);
+    check_results(&results);
+    drop(map);
+    assert!(!folder.exists(), "Index files were not removed with the map");
+}
+
+#[test]
+fn spilled_entries_are_in_the_block_map() {
+    let folder = env::temp_dir().join(format!("bvp-dedup-block-map-{}", std::process::id()));
+    // An index file of an earlier map that was not dropped is removed with the folder.
+    fs::create_dir_all(&folder).unwrap();
+    fs::write(folder.join("shard-05.dedup"), b"stale").unwrap();
+    let map = ShardedBlockMap::with_spill(0, SpillSettings { folder: folder.clone(), memory_entries: 1 });
+    assert!(!folder.exists(), "The stale index file was kept");
+
+    for data in 0..500u32 {
+        assert!(matches!(map.find_or_insert(data as u64, data, |entry| entry.key == data).unwrap(), DedupResult::New(_)));
+    }
+    assert!(folder.exists(), "Nothing was spilled");
+    let block_map = map.into_block_map().unwrap();
+    assert_eq!(block_map.len(), 500);
+    assert!((0..500u64).all(|hash| block_map[&hash] == hash as usize));
+    assert!(!folder.exists());
+}
+
+#[test]
+fn colliding_hashes_of_different_data_get_their_own_blocks() {
+    let map = ShardedBlockMap::new(0);
+    let first = map.find_or_insert(7, 1u32, |entry| entry.key == 1).unwrap();
+    let second = map.find_or_insert(7, 2u32, |entry| entry.key == 2).unwrap();
+    let again = map.find_or_insert(7, 2u32, |entry| entry.key == 2).unwrap();
+    assert!(matches!(first, DedupResult::New(0)));
+    assert!(matches!(second, DedupResult::New(1)));
+    assert!(matches!(again, DedupResult::Existing(1)));
+
+    // The first block stored with a hash is kept in the plain map.
+    let block_map = map.into_block_map().unwrap();
+    assert_eq!(block_map.get(&7), Some(&0));
+}
+
+#[test]
+fn given_indices_and_restored_entries_are_kept() {
+    let map = ShardedBlockMap::new(10);
+    map.insert(3, 30u32, 2).unwrap();
+    assert!(matches!(map.find_or_insert(3, 30, |entry| entry.key == 30).unwrap(), DedupResult::Existing(2)));
+    assert!(matches!(map.find_or_insert_as(4, 40, 7, |entry| entry.key == 40).unwrap(), DedupResult::New(7)));
+    assert!(matches!(map.find_or_insert_as(4, 40, 8, |entry| entry.key == 40).unwrap(), DedupResult::Existing(7)));
+    assert_eq!(map.allocate_index(), 10);
+    assert!(matches!(map.find_or_insert(5, 50, |entry| entry.key == 50).unwrap(), DedupResult::New(11)));
+}
+
+#[test]
+fn other_lookups_of_the_shard_go_on_while_data_is_compared() {
+    let map = ShardedBlockMap::new(1);
+    map.insert(3, 30u32, 0).unwrap();
+    let compared = Cell::new(0);
+    let result = map.find_or_insert(3, 31, |entry| {
+        compared.set(compared.get() + 1);
+        if compared.get() > 1 {
+            return entry.key == 31;
+        }
+        // Lookups of the same hash are in the same shard, which must not be locked now.
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(|| sender.send(map.find_or_insert(3, 32, |entry| entry.key == 32).unwrap()).unwrap());
+            let other = receiver.recv_timeout(Duration::from_secs(10)).expect("The shard was locked while comparing data");
+            assert!(matches!(other, DedupResult::New(1)));
+        });
+        return entry.key == 31;
+    }).unwrap();
+    // The entry stored in the meantime is compared as well, before a new block is allocated.
+    assert!(matches!(result, DedupResult::New(2)));
+    assert!(compared.get() >= 3, "The entry stored in the meantime was not compared");
+    assert_eq!(map.allocate_index(), 3);
+}
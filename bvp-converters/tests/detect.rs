@@ -0,0 +1,57 @@
+//! Tests of detecting the kind of input files with `bvp::detect`.
+
+use std::env;
+use std::fs;
+
+use bvp::archives::{saf, ArchiveEnum};
+use bvp::bytes::Bytes;
+use bvp::detect::{self, FileKind, SIGNATURE_LENGTH};
+use bvp::errors::DetectError;
+use bvp::file::File;
+
+/// Returns a header of `SIGNATURE_LENGTH` zeros with the bytes at the offset.
+fn header(offset: usize, bytes: &[u8]) -> Vec<u8> {
+    let mut head = vec![0; SIGNATURE_LENGTH];
+    head[offset..offset + bytes.len()].copy_from_slice(bytes);
+    return head;
+}
+
+#[test]
+fn signatures_are_detected() {
+    let cases = [
+        (header(0, b"PK\x03\x04"), FileKind::Zip),
+        (b"NRRD0004\ntype: uint8\n".to_vec(), FileKind::Nrrd),
+        (header(344, b"n+1\0"), FileKind::Nifti),
+        (header(4, b"ni2\0"), FileKind::Nifti),
+        (header(128, b"DICM"), FileKind::Dicom),
+        (header(0, b"MM\0*"), FileKind::Tiff),
+        (header(0, &[0x1f, 0x8b, 0x08]), FileKind::Gzip),
+        (header(0, &[0x28, 0xb5, 0x2f, 0xfd]), FileKind::Zstd),
+        (b"  {\n  \"asset\": {}}".to_vec(), FileKind::Manifest),
+        (b"{{{{".to_vec(), FileKind::Raw),
+        (header(0, b"NRR"), FileKind::Raw),
+        (Vec::new(), FileKind::Raw)
+    ];
+    for (head, kind) in cases {
+        assert_eq!(detect::detect_bytes(&head), kind, "{:?}", &head[..head.len().min(8)]);
+    }
+    let archive = saf::to_saf_archive(&vec![File::new("manifest.json".to_string(), Bytes::from(b"{}".to_vec()), None)]).unwrap();
+    assert_eq!(detect::detect_bytes(&archive), FileKind::Saf);
+}
+
+#[test]
+fn archives_are_detected_from_files() {
+    let folder = env::temp_dir().join(format!("bvp-detect-{}", std::process::id()));
+    fs::create_dir_all(&folder).unwrap();
+    let zip = folder.join("asset.bin");
+    fs::write(&zip, header(0, b"PK\x03\x04")).unwrap();
+    let nifti = folder.join("scan.nii");
+    fs::write(&nifti, header(344, b"n+1\0")).unwrap();
+
+    assert!(matches!(detect::detect_archive(&zip), Ok(ArchiveEnum::ZIP)));
+    assert!(matches!(detect::detect_archive(&folder), Ok(ArchiveEnum::None)));
+    assert!(matches!(detect::detect_archive(&nifti), Err(DetectError::NotAnAsset(_, kind)) if kind == "NIfTI"));
+    assert!(matches!(detect::detect_archive("-".as_ref()), Err(DetectError::Undetectable(_))));
+    assert!(matches!(detect::detect_archive("https://example.com/volume.SAF".as_ref()), Ok(ArchiveEnum::SAF)));
+    fs::remove_dir_all(&folder).unwrap();
+}
@@ -0,0 +1,41 @@
+//! Tests of downsampling volumes with the filters of `bvp::downsample`.
+
+use bvp::downsample::{self, DownsampleFilter};
+use bvp::formats::Format;
+use bvp::vector3::Vector3;
+
+fn format(shorthand: &str) -> Format {
+    return Format::from_json(&bvp::formats::shorthand_to_json(shorthand).unwrap()).unwrap();
+}
+
+#[test]
+fn filters_combine_boxes() {
+    // 3x2x2 volume, so the last box along X has a single column.
+    let data: Vec<u8> = vec![1, 2, 9, 3, 4, 9, 5, 6, 0, 7, 8, 0];
+    let dimensions = Vector3::from_xyz(3, 2, 2);
+    assert_eq!(downsample::downsampled_dimensions(dimensions, 2), Vector3::from_xyz(2, 1, 1));
+    let u8_format = format("u8");
+    let cases = [
+        (DownsampleFilter::Mean, vec![5, 5]),
+        (DownsampleFilter::Min, vec![1, 0]),
+        (DownsampleFilter::Max, vec![8, 9]),
+        (DownsampleFilter::Nearest, vec![1, 9]),
+    ];
+    for (filter, expected) in cases {
+        assert_eq!(downsample::downsample(&u8_format, &data, dimensions, 2, filter).unwrap(), expected, "{:?}", filter);
+    }
+    assert!(downsample::downsample(&u8_format, &data, dimensions, 1, DownsampleFilter::Mean).is_err());
+    assert!(downsample::downsample(&u8_format, &data[1..], dimensions, 2, DownsampleFilter::Mean).is_err());
+}
+
+#[test]
+fn components_are_filtered_separately() {
+    let dimensions = Vector3::from_xyz(2, 1, 1);
+    let data: Vec<u8> = [[10u16, 1000], [20, 3000]].iter().flatten().flat_map(|v| v.to_le_bytes()).collect();
+    let result = downsample::downsample(&format("u16x2"), &data, dimensions, 2, DownsampleFilter::Mean).unwrap();
+    assert_eq!(result, [15u16, 2000].iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>());
+
+    let data: Vec<u8> = [-1.5f32, 2.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+    let result = downsample::downsample(&format("f32"), &data, dimensions, 2, DownsampleFilter::Mean).unwrap();
+    assert_eq!(result, 0.25f32.to_le_bytes());
+}
@@ -0,0 +1,57 @@
+//! Tests of the error types: converting them into `BvpError` and following their sources.
+
+use std::{env, error::Error, fs};
+
+use bvp::bvpfile::BVPFile;
+use bvp::errors::{BvpError, BvpFileError, FileError, FormatError};
+use bvp::file::File;
+use bvp::formats::Format;
+
+/// A manifest whose only format has a size that is not a multiple of its count.
+const BROKEN_FORMAT: &str = r#"{
+    "asset": { "version": "1.0" },
+    "modalities": [],
+    "formats": [{ "family": "mono", "type": "u", "count": 2, "size": 3, "microblockDimensions": [1, 1, 1], "microblockSize": 3 }],
+    "blocks": []
+}"#;
+
+/// Reads a manifest and then a format, with `?` on both.
+fn read(manifest: &str, format: &str) -> Result<(BVPFile, Format), BvpError> {
+    let bvp_file = BVPFile::from_manifest(manifest, &Vec::new())?;
+    let format = Format::from_json(&format.parse().unwrap())?;
+    return Ok((bvp_file, format));
+}
+
+#[test]
+fn library_errors_convert_into_bvp_errors() {
+    match read(BROKEN_FORMAT, "{}") {
+        Err(BvpError::BvpFile(BvpFileError::FormatError(FormatError::InvalidSize(2, 3)))) => (),
+        result => panic!("{:?}", result.err())
+    }
+    let manifest = BROKEN_FORMAT.replace(r#""size": 3"#, r#""size": 4"#).replace(r#""microblockSize": 3"#, r#""microblockSize": 4"#);
+    match read(&manifest, r#"{ "family": "stereo" }"#) {
+        Err(BvpError::Format(FormatError::UnsupportedFormatFamily(family))) => assert_eq!(family, "stereo"),
+        result => panic!("{:?}", result.err())
+    }
+    assert!(read(&manifest, r#"{ "family": "mono", "type": "u", "count": 1, "size": 1 }"#).is_ok());
+}
+
+#[test]
+fn errors_keep_their_sources() {
+    let error = BVPFile::from_manifest(BROKEN_FORMAT, &Vec::new()).err().unwrap();
+    let source = error.source().expect("The format error is the source");
+    assert!(matches!(source.downcast_ref::<FormatError>(), Some(FormatError::InvalidSize(2, 3))));
+    assert_eq!(source.to_string(), FormatError::InvalidSize(2, 3).to_string());
+
+    // Writing into a path below a file fails with the error of the file system.
+    let dir = env::temp_dir().join(format!("bvp-errors-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("plain"), b"not a folder").unwrap();
+    let file = File::new(dir.join("plain").join("blocks").join("b.raw").to_str().unwrap().to_string(), vec![1, 2, 3], None);
+    let error = file.write().err().unwrap();
+    assert!(matches!(error, FileError::CannotCreateFolder(_, _)));
+    assert!(error.source().unwrap().downcast_ref::<std::io::Error>().is_some());
+    let error: BvpError = error.into();
+    assert!(matches!(error, BvpError::File(_)));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,132 @@
+//! Exit codes and JSON error output of the `bvp` binary, which scripts branch on.
+
+use std::{env, fs, path::Path, process::{Command, Stdio}, thread, time::{Duration, Instant}};
+
+use tinyjson::JsonValue;
+
+/// Runs `bvp` with JSON error output and returns its exit code and the `kind` of the error it printed.
+fn run(arguments: &[&str]) -> (i32, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bvp")).args(arguments).args(["--error-format", "json"])
+        .stdout(Stdio::null()).stderr(Stdio::piped()).spawn().unwrap();
+    // Tools that loop on bad input would otherwise hang the test.
+    let start = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if start.elapsed() > Duration::from_secs(60) {
+            child.kill().unwrap();
+            panic!("{:?} did not finish", arguments);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let result = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    let error: JsonValue = match stderr.trim().parse() {
+        Ok(e) => e,
+        Err(e) => panic!("stderr of {:?} is not JSON ({}): {}", arguments, e, stderr)
+    };
+    let kind = error["kind"].get::<String>().expect("the error has no kind").clone();
+    assert_eq!(error["exitCode"].get::<f64>(), Some(&(result.status.code().unwrap() as f64)));
+    return (result.status.code().unwrap(), kind);
+}
+
+#[test]
+fn failures_have_the_exit_code_of_their_kind() {
+    let dir = env::temp_dir().join(format!("bvp-exit-codes-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let broken = dir.join("broken.saf");
+    fs::write(&broken, b"not an archive").unwrap();
+    let missing = dir.join("missing.saf");
+
+    assert_eq!(run(&["info", "--no-such-option"]), (2, "config".to_string()));
+    assert_eq!(run(&["info", missing.to_str().unwrap()]), (3, "io".to_string()));
+    assert_eq!(run(&["info", broken.to_str().unwrap(), "SAF"]), (4, "corruptAsset".to_string()));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cyclic_placements_are_rejected() {
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join("mono-u8-tree");
+    let dir = env::temp_dir().join(format!("bvp-exit-codes-cycle-{}", std::process::id()));
+    fs::create_dir_all(dir.join("blocks")).unwrap();
+    for name in ["low.raw", "high.raw"] {
+        fs::copy(golden.join("blocks").join(name), dir.join("blocks").join(name)).unwrap();
+    }
+    // Block 1 is placed in block 0, and now block 0 in block 1 as well.
+    let manifest = fs::read_to_string(golden.join("manifest.json")).unwrap();
+    let mut json: JsonValue = manifest.parse().unwrap();
+    let placement: JsonValue = "{\"block\": 0, \"position\": [0, 0, 0]}".parse().unwrap();
+    if let JsonValue::Array(blocks) = &mut json["blocks"] {
+        blocks[1]["placements"] = JsonValue::Array(vec![placement]);
+    }
+    let manifest_path = dir.join("manifest.json");
+    fs::write(&manifest_path, json.stringify().unwrap()).unwrap();
+
+    let output = dir.join("out.saf");
+    let (code, kind) = run(&["downsample", manifest_path.to_str().unwrap(), "None", "--output", output.to_str().unwrap(), "--output-archive", "SAF"]);
+    assert_eq!((code, kind.as_str()), (4, "corruptAsset"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn missing_and_broken_inputs_of_every_tool() {
+    let dir = env::temp_dir().join(format!("bvp-exit-codes-inputs-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let broken = dir.join("broken.saf");
+    fs::write(&broken, b"not an archive").unwrap();
+    let missing = dir.join("missing.saf");
+    let output = dir.join("out");
+    let output = output.to_str().unwrap();
+
+    let commands: [&[&str]; 9] = [
+        &["info", "INPUT", "SAF"],
+        &["validate", "INPUT", "SAF"],
+        &["unpack", "INPUT", "SAF", output],
+        &["extract", "INPUT", "SAF", "--block", "0", "--output", output],
+        &["meta", "INPUT", "SAF"],
+        &["merge", "INPUT", "--archive", "SAF", "--output", output],
+        &["downsample", "INPUT", "SAF", "--output", output, "--output-archive", "SAF"],
+        &["thumbnail", "INPUT", "SAF", "--output", output],
+        &["serve", "INPUT", "SAF"]
+    ];
+    for command in commands {
+        let with = |input: &Path| -> Vec<String> {
+            return command.iter().map(|a| if *a == "INPUT" { input.to_str().unwrap().to_string() } else { a.to_string() }).collect();
+        };
+        let arguments = with(&missing);
+        let arguments: Vec<&str> = arguments.iter().map(|a| a.as_str()).collect();
+        assert_eq!(run(&arguments), (3, "io".to_string()), "{:?}", arguments);
+
+        // The validator reports a broken archive as an issue of the asset.
+        let expected = if command[0] == "validate" { (5, "verification".to_string()) } else { (4, "corruptAsset".to_string()) };
+        let arguments = with(&broken);
+        let arguments: Vec<&str> = arguments.iter().map(|a| a.as_str()).collect();
+        assert_eq!(run(&arguments), expected, "{:?}", arguments);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn damaged_block_data_is_a_corrupt_asset() {
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join("mono-u8-tree");
+    let dir = env::temp_dir().join(format!("bvp-exit-codes-damaged-{}", std::process::id()));
+    fs::create_dir_all(dir.join("blocks")).unwrap();
+    fs::copy(golden.join("manifest.json"), dir.join("manifest.json")).unwrap();
+    fs::copy(golden.join("blocks").join("high.raw"), dir.join("blocks").join("high.raw")).unwrap();
+    let low = fs::read(golden.join("blocks").join("low.raw")).unwrap();
+    fs::write(dir.join("blocks").join("low.raw"), &low[..low.len() / 2]).unwrap();
+    let manifest = dir.join("manifest.json");
+    let manifest = manifest.to_str().unwrap();
+    let intact = golden.join("manifest.json");
+    let output = dir.join("out");
+    let output = output.to_str().unwrap();
+
+    let commands: [&[&str]; 4] = [
+        &["thumbnail", manifest, "None", "--output", output],
+        &["diff", manifest, intact.to_str().unwrap()],
+        &["extract", manifest, "None", "--all-blocks", "--output", output],
+        &["downsample", manifest, "None", "--output", output, "--output-archive", "SAF"]
+    ];
+    for arguments in commands {
+        assert_eq!(run(arguments), (4, "corruptAsset".to_string()), "{:?}", arguments);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
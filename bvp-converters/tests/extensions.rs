@@ -0,0 +1,75 @@
+//! Tests of the extension registry: declaring the extensions an asset uses, rejecting
+//! required extensions that are not supported, and keeping payloads of extensions.
+
+use tinyjson::JsonValue;
+
+use bvp::bvpfile::BVPFile;
+use bvp::errors::BvpFileError;
+use bvp::extensions::{self, Extension};
+
+/// A manifest with one empty block, and the extensions of the asset.
+/// * `asset_extensions` - the `extensionsUsed` and `extensionsRequired` members of the asset, if any
+fn manifest(asset_extensions: &str) -> String {
+    return format!(r#"{{
+        "asset": {{ "version": "1.0"{} }},
+        "modalities": [{{ "name": "m", "block": 0, "extensions": {{ "EXT_my_labels": {{ "labels": ["bone", "skin"] }} }} }}],
+        "formats": [{{ "family": "mono", "type": "u", "count": 1, "size": 3, "microblockDimensions": [1, 1, 1], "microblockSize": 3 }}],
+        "blocks": [{{ "dimensions": [2, 2, 2], "format": 0, "placements": [] }}]
+    }}"#, asset_extensions);
+}
+
+#[test]
+fn extensions_are_looked_up_by_name() {
+    for extension in Extension::ALL {
+        assert_eq!(Extension::from_string(&extension.to_string()), Some(extension));
+        assert!(extensions::is_supported(&extension.to_string()));
+    }
+    assert!(Extension::ExtFormatMulti.is_required());
+    assert!(!Extension::ExtChecksum.is_required());
+    assert_eq!(Extension::from_string("EXT_unknown"), None);
+    assert!(!extensions::is_supported("EXT_unknown"));
+}
+
+#[test]
+fn required_extensions_have_to_be_supported() {
+    let required = manifest(r#", "extensionsUsed": ["EXT_my_volumes"], "extensionsRequired": ["EXT_my_volumes"]"#);
+    let result = BVPFile::from_manifest(&required, &Vec::new());
+    assert!(matches!(&result, Err(BvpFileError::UnsupportedExtension(name)) if name == "EXT_my_volumes"), "{:?}", result.err());
+
+    extensions::register_extension("EXT_my_volumes");
+    extensions::register_extension("EXT_my_volumes");
+    assert!(extensions::is_supported("EXT_my_volumes"));
+    let bvp_file = BVPFile::from_manifest(&required, &Vec::new()).unwrap();
+    let (used, required) = bvp_file.declared_extensions();
+    assert!(used.contains(&"EXT_my_volumes".to_string()));
+    assert_eq!(required, vec!["EXT_format_mono".to_string(), "EXT_my_volumes".to_string()]);
+}
+
+#[test]
+fn payloads_and_unknown_extensions_are_written_back() {
+    // Extensions that are only used do not have to be supported.
+    let bvp_file = BVPFile::from_manifest(&manifest(r#", "extensionsUsed": ["EXT_vendor_notes"]"#), &Vec::new()).unwrap();
+    let payload = &bvp_file.modalities[0].extension_payloads["EXT_my_labels"];
+    assert_eq!(payload["labels"][1].get::<String>().map(|s| s.as_str()), Some("skin"));
+
+    let manifest: JsonValue = String::from_utf8(bvp_file.to_manifest().unwrap()).unwrap().parse().unwrap();
+    let names = |key: &str| -> Vec<String> {
+        return match &manifest["asset"][key] {
+            JsonValue::Array(a) => a.iter().map(|n| n.get::<String>().unwrap().clone()).collect(),
+            _ => Vec::new()
+        };
+    };
+    // The 3 byte component is not a native type, so the format needs `EXT_format_mono`.
+    assert_eq!(names("extensionsUsed"), vec!["EXT_format_mono", "EXT_my_labels", "EXT_vendor_notes"]);
+    assert_eq!(names("extensionsRequired"), vec!["EXT_format_mono"]);
+    assert_eq!(&manifest["modalities"][0]["extensions"]["EXT_my_labels"], payload);
+
+    // Payloads that are not objects are rejected.
+    let invalid = r#"{
+        "asset": { "version": "1.0" },
+        "modalities": [{ "name": "m", "block": 0, "extensions": [] }],
+        "formats": [{ "family": "mono", "type": "u", "count": 1, "size": 1, "microblockDimensions": [1, 1, 1], "microblockSize": 1 }],
+        "blocks": [{ "dimensions": [2, 2, 2], "format": 0, "placements": [] }]
+    }"#;
+    assert!(BVPFile::from_manifest(invalid, &Vec::new()).is_err());
+}
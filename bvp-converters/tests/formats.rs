@@ -0,0 +1,105 @@
+//! Tests of the format families: reading them from JSON, the extensions they need
+//! and the voxel values they describe.
+
+use std::{env, fs};
+
+use tinyjson::JsonValue;
+
+use bvp::archives::ArchiveEnum;
+use bvp::bvpfile::BVPFile;
+use bvp::errors::FormatError;
+use bvp::extensions::Extension;
+use bvp::formats::{self, Format, FormatFamily};
+use bvp::reader::VolumeReader;
+use bvp::vector3::Vector3;
+use bvp::writer::VolumeWriter;
+
+fn format(json: &str) -> Result<Format, FormatError> {
+    return Format::from_json(&json.parse::<JsonValue>().unwrap());
+}
+
+/// An RGB color of `u8` followed by a signed 16-bit label and a 24-bit value.
+const MULTI: &str = r#"{
+    "family": "multi",
+    "components": [
+        { "count": 3, "size": 3, "type": "u" },
+        { "count": 1, "size": 2, "type": "i" },
+        { "count": 1, "size": 3, "type": "u" }
+    ]
+}"#;
+
+#[test]
+fn multi_formats_read_every_component() {
+    let format = format(MULTI).unwrap();
+    assert_eq!(format.microblock_dimensions, Vector3::from_xyz(1, 1, 1));
+    assert_eq!(format.microblock_size, 8);
+    assert_eq!(format.component_count(), 5);
+    // The 24-bit component has no native type.
+    assert_eq!(format.extensions, vec![Extension::ExtFormatMulti, Extension::ExtFormatMono]);
+
+    let voxel = [10, 20, 30, 0xfe, 0xff, 0x01, 0x02, 0x03];
+    assert_eq!(format.voxel_values(&voxel).unwrap(), vec![10.0, 20.0, 30.0, -2.0, 197121.0]);
+    let two_voxels = [voxel, voxel].concat();
+    assert_eq!(format.first_components(&two_voxels).unwrap(), vec![10.0, 10.0]);
+
+    // The format is written back as it was read.
+    let again = Format::from_json(&format.to_json()).unwrap();
+    assert_eq!(again, format);
+    match again.family() {
+        FormatFamily::Multi(multi) => assert_eq!(multi.components().len(), 3),
+        family => panic!("Read a {} format", family.name())
+    }
+}
+
+#[test]
+fn invalid_multi_formats_are_rejected() {
+    assert!(matches!(format(r#"{ "family": "multi", "components": [] }"#), Err(FormatError::NoComponents)));
+    let result = format(r#"{ "family": "multi", "components": [{ "count": 1, "size": 1, "type": "u" }, { "count": 2, "size": 3, "type": "u" }] }"#);
+    assert!(matches!(result, Err(FormatError::InvalidSize(2, 3))));
+    let result = format(r#"{ "family": "multi", "components": [{ "count": 1, "type": "u" }] }"#);
+    assert!(matches!(&result, Err(FormatError::MissingField(field)) if field == "/components/0/size"), "{:?}", result);
+    assert!(matches!(format(r#"{ "family": "multi" }"#), Err(FormatError::MissingField(_))));
+}
+
+#[test]
+fn shorthands_of_too_large_voxels_are_rejected() {
+    let format = formats::shorthand_to_json("u16x3").unwrap();
+    assert_eq!(format["size"], JsonValue::from(6.0));
+    assert_eq!(formats::shorthand_to_json("u32x1073741823").unwrap()["size"], JsonValue::from(4294967292.0));
+    for shorthand in ["u32x1073741824", "u64x4294967295", "f4294967288x9", "u12", "x3", "u8x0"] {
+        assert!(formats::shorthand_to_json(shorthand).is_none(), "{} was accepted", shorthand);
+    }
+}
+
+#[test]
+fn compressed_formats_have_no_voxel_values() {
+    let format = format(r#"{ "family": "compressed", "scheme": "bc4", "microblockDimensions": [4, 4, 1], "microblockSize": 8 }"#).unwrap();
+    assert_eq!(format.microblock_dimensions, Vector3::from_xyz(4, 4, 1));
+    assert_eq!(format.extensions, vec![Extension::ExtFormatCompressed]);
+    assert_eq!(format.count_space(Vector3::from_xyz(8, 8, 2)), Some(4 * 2 * 8));
+    assert_eq!(format.component_count(), 0);
+    assert!(format.first_component().is_none());
+    assert!(format.voxel_values(&[0; 8]).is_none());
+    assert!(matches!(format.first_components(&[0; 8]), Err(FormatError::NoVoxelValues(_))));
+    assert_eq!(Format::from_json(&format.to_json()).unwrap(), format);
+
+    let result = Format::from_json(&r#"{ "family": "compressed", "scheme": "bc4", "microblockDimensions": [4, 0, 1], "microblockSize": 8 }"#.parse().unwrap());
+    assert!(matches!(result, Err(FormatError::InvalidMicroblock(_, 8))));
+}
+
+#[test]
+fn multi_volumes_are_written_and_read_back() {
+    let format = format(MULTI).unwrap();
+    let volume: Vec<u8> = (0..4 * 4 * 2 * 8).map(|i| (i * 7 % 256) as u8).collect();
+    let path = env::temp_dir().join(format!("bvp-formats-{}", std::process::id()));
+    VolumeWriter::new(Vector3::from_xyz(2, 2, 2))
+        .write(&path, &ArchiveEnum::None, volume.clone(), Vector3::from_xyz(4, 4, 2), format.clone())
+        .unwrap();
+
+    let bvp_file = BVPFile::open(&path, &ArchiveEnum::None).unwrap();
+    assert!(bvp_file.declared_extensions().0.contains(&"EXT_format_multi".to_string()));
+    assert_eq!(bvp_file.formats[0], format);
+    let read = VolumeReader::new(&bvp_file).read_modality(0).unwrap();
+    assert_eq!(read.data.unwrap().as_slice(), volume.as_slice());
+    fs::remove_dir_all(&path).unwrap();
+}
@@ -0,0 +1,88 @@
+//! Runs the parsers of the fuzz targets (see `fuzz/`) on their seeds, and on seeds
+//! that are cut short or have a damaged byte. None of them may panic.
+
+use std::{fs, path::{Path, PathBuf}};
+
+use bvp::archives::{saf, zip};
+use bvp::bvpfile::BVPFile;
+use bvp::bytes::Bytes;
+use bvp::compressions::lz4s;
+use bvp::file::File;
+
+fn seeds(target: &str) -> Vec<(PathBuf, Vec<u8>)> {
+    let folder = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz").join("corpus").join(target);
+    let mut seeds: Vec<_> = fs::read_dir(&folder).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .map(|path| {
+            let data = fs::read(&path).unwrap();
+            return (path, data);
+        })
+        .collect();
+    seeds.sort();
+    assert!(!seeds.is_empty(), "{} has no seeds", folder.display());
+    return seeds;
+}
+
+/// Returns the seed cut short at every length and with every byte inverted, in turn.
+/// * `seed` - the seed
+fn damaged(seed: &[u8]) -> impl Iterator<Item = Vec<u8>> + '_ {
+    let truncated = (0..seed.len()).map(move |length| seed[..length].to_vec());
+    let flipped = (0..seed.len()).map(move |i| {
+        let mut data = seed.to_vec();
+        data[i] ^= 0xff;
+        return data;
+    });
+    return truncated.chain(flipped);
+}
+
+/// Same as the manifest fuzz target, the blocks the seeds refer to.
+fn block_files() -> Vec<File> {
+    return (1..=8).map(|i| File::new(format!("blocks/block_{}.raw", i), vec![0u8; 64], None)).collect();
+}
+
+#[test]
+fn manifest_seeds_are_read() {
+    let files = block_files();
+    for (path, seed) in seeds("manifest") {
+        let manifest = std::str::from_utf8(&seed).unwrap();
+        assert!(BVPFile::from_manifest(manifest, &files).is_ok(), "{}", path.display());
+        for data in damaged(&seed) {
+            if let Ok(manifest) = std::str::from_utf8(&data) {
+                let _ = BVPFile::from_manifest(manifest, &files);
+            }
+        }
+    }
+}
+
+#[test]
+fn saf_seeds_are_read() {
+    for (path, seed) in seeds("saf") {
+        assert!(saf::from_saf_archive(&Bytes::from(seed.clone())).is_ok(), "{}", path.display());
+        for data in damaged(&seed) {
+            let _ = saf::from_saf_archive(&Bytes::from(data));
+        }
+    }
+}
+
+#[test]
+fn zip_seeds_are_read() {
+    for (path, seed) in seeds("zip") {
+        assert!(zip::from_zip_archive(&Bytes::from(seed.clone())).is_ok(), "{}", path.display());
+        for data in damaged(&seed) {
+            let _ = zip::from_zip_archive(&Bytes::from(data));
+        }
+    }
+}
+
+#[test]
+fn lz4s_seeds_are_decoded() {
+    for (path, seed) in seeds("lz4s") {
+        // As in the fuzz target, the first 4 bytes are the decoded size.
+        let size = u32::from_le_bytes([seed[0], seed[1], seed[2], seed[3]]) as usize;
+        assert_eq!(lz4s::decompress_lz4s(&seed[4..], size).len(), size, "{}", path.display());
+        for data in damaged(&seed).filter(|d| d.len() >= 4) {
+            let size = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize % ((1 << 20) + 1);
+            let _ = lz4s::decompress_lz4s(&data[4..], size);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Compatibility tests against reference assets in `tests/golden`.
+//!
+//! Every folder in `tests/golden` holds one asset, as `asset.saf`, `asset.zip` or `manifest.json`
+//! with its block files, and `expected.raw` with the voxels of its first modality. The assets are
+//! not written by this crate, so reading them checks that the reader follows the specification
+//! rather than the writer, see `tests/golden/README.md` for where they come from.
+//!
+//! If `BVP_REFERENCE_VALIDATOR` is set to a command, it is also run on assets written by `raw2bvp`,
+//! with the path of the asset as its last argument, and has to succeed.
+
+use std::{env, fs, path::{Path, PathBuf}, process::Command};
+
+use bvp::{archives::ArchiveEnum, bvpfile::BVPFile, errors::BvpFileError, file::File, json_aux::ParseMode, reader::VolumeReader, validate::{self, IssueCode, Severity}, vector3::Vector3};
+
+fn golden_dir() -> PathBuf {
+    return Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+}
+
+/// Returns the path and archive type of the asset in a fixture folder.
+/// * `dir` - the fixture folder
+fn find_asset(dir: &Path) -> (PathBuf, ArchiveEnum) {
+    for (name, archive) in [("asset.saf", ArchiveEnum::SAF), ("asset.zip", ArchiveEnum::ZIP), ("manifest.json", ArchiveEnum::None)] {
+        let path = dir.join(name);
+        if path.is_file() {
+            return (path, archive);
+        }
+    }
+    panic!("{} has no asset", dir.display());
+}
+
+/// Fails if the validator of this crate reports errors for an asset.
+fn assert_valid(path: &Path, archive: &ArchiveEnum) {
+    let files = archive.read_archive(path).unwrap();
+    let errors: Vec<String> = validate::validate_files_with_checksums(&files).iter()
+        .filter(|issue| matches!(issue.severity, Severity::Error))
+        .map(|issue| format!("{}: {}", issue.path, issue.message))
+        .collect();
+    assert!(errors.is_empty(), "{} is not valid: {:?}", path.display(), errors);
+}
+
+#[test]
+fn golden_assets_read_identically() {
+    let mut fixtures: Vec<PathBuf> = fs::read_dir(golden_dir()).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect();
+    fixtures.sort();
+    assert!(!fixtures.is_empty(), "no golden assets found");
+
+    for dir in fixtures {
+        let (path, archive) = find_asset(&dir);
+        assert_valid(&path, &archive);
+        let bvp_file = match BVPFile::open_with_mode(&path, &archive, ParseMode::Strict) {
+            Ok(b) => b,
+            Err(e) => panic!("cannot open {}: {}", path.display(), e)
+        };
+        let volume = match VolumeReader::new(&bvp_file).with_checksum_verification(true).read_modality(0) {
+            Ok(v) => v,
+            Err(e) => panic!("cannot read {}: {}", path.display(), e)
+        };
+        let expected = fs::read(dir.join("expected.raw")).unwrap();
+        assert_eq!(volume.data.as_deref(), Some(expected.as_slice()), "{} is not read as expected", dir.display());
+    }
+}
+
+#[test]
+fn unknown_fields_fail_only_strict_parsing() {
+    let (path, archive) = find_asset(&golden_dir().join("mono-u8-tree"));
+    let mut files = archive.read_archive(&path).unwrap();
+    let manifest = files.iter().position(|file| file.name.ends_with("manifest.json")).unwrap();
+    let content = String::from_utf8(files[manifest].data.to_vec()).unwrap();
+    let edited = content.replacen("\"placements\"", "\"tileSize\": 4, \"placements\"", 1);
+    files[manifest] = File::new(files[manifest].name.clone(), edited.into_bytes(), None);
+
+    assert!(BVPFile::from_files_with_mode(files.clone(), ParseMode::Lenient).is_ok());
+    match BVPFile::from_files_with_mode(files.clone(), ParseMode::Strict) {
+        Err(BvpFileError::Schema(message)) => assert!(message.contains("/blocks/0/tileSize"), "{}", message),
+        other => panic!("expected a schema error, got {:?}", other.map(|_| ()))
+    }
+    let issues = validate::validate_files_with_options(&files, &validate::ValidationOptions { parse_mode: ParseMode::Strict, ..Default::default() });
+    assert!(issues.iter().any(|issue| issue.code == IssueCode::UnknownField && matches!(issue.severity, Severity::Error)));
+
+    let missing = content.replacen("\"version\": \"1.0\",", "", 1);
+    files[manifest] = File::new(files[manifest].name.clone(), missing.into_bytes(), None);
+    assert!(BVPFile::from_files_with_mode(files, ParseMode::Lenient).is_err());
+}
+
+#[test]
+fn parse_errors_point_at_the_value() {
+    let (path, archive) = find_asset(&golden_dir().join("mono-u8-tree"));
+    let mut files = archive.read_archive(&path).unwrap();
+    let manifest = files.iter().position(|file| file.name.ends_with("manifest.json")).unwrap();
+    let content = String::from_utf8(files[manifest].data.to_vec()).unwrap();
+    let edited = content.replacen("\"position\"", "\"position\": \"origin\", \"offset\"", 1);
+    files[manifest] = File::new(files[manifest].name.clone(), edited.into_bytes(), None);
+
+    let error = BVPFile::from_files(files).err().unwrap();
+    assert_eq!(error.pointer(), Some("/blocks/0/placements/0/position"));
+    assert!(error.to_string().contains("got `\"origin\"`"), "{}", error);
+}
+
+#[test]
+fn dangling_references_are_rejected_on_load() {
+    let (path, archive) = find_asset(&golden_dir().join("mono-u8-tree"));
+    let mut files = archive.read_archive(&path).unwrap();
+    let manifest = files.iter().position(|file| file.name.ends_with("manifest.json")).unwrap();
+    let content = String::from_utf8(files[manifest].data.to_vec()).unwrap();
+
+    let edited = content.replacen("\"block\": 0", "\"block\": 9", 1);
+    files[manifest] = File::new(files[manifest].name.clone(), edited.into_bytes(), None);
+    assert!(matches!(BVPFile::from_files(files.clone()), Err(BvpFileError::NoSuchBlock(pointer, 9, _)) if pointer == "/modalities/0/block"));
+
+    let edited = content.replacen("\"block\": 1", "\"block\": 7", 1);
+    files[manifest] = File::new(files[manifest].name.clone(), edited.into_bytes(), None);
+    assert!(matches!(BVPFile::from_files(files), Err(BvpFileError::NoSuchBlock(pointer, 7, _)) if pointer == "/blocks/0/placements/0/block"));
+}
+
+#[test]
+fn golden_blocks_decode_lazily() {
+    for entry in fs::read_dir(golden_dir()).unwrap() {
+        let dir = entry.unwrap().path();
+        if !dir.is_dir() {
+            continue;
+        }
+        let (path, archive) = find_asset(&dir);
+        let bvp_file = BVPFile::open(&path, &archive).unwrap();
+        let data_blocks = bvp_file.blocks.iter().filter(|block| block.data.is_some()).count();
+        let mut read_blocks = 0;
+        for (meta, read) in bvp_file.iter_blocks() {
+            let format = &bvp_file.formats[meta.format.expect("a data block has no format")];
+            let data = match read() {
+                Ok(d) => d,
+                Err(e) => panic!("cannot decode block {} of {}: {}", meta.index, path.display(), e)
+            };
+            assert_eq!(Some(data.len() as u64), format.count_space(meta.dimensions), "block {} of {} has the wrong size", meta.index, path.display());
+            read_blocks += 1;
+        }
+        assert_eq!(read_blocks, data_blocks, "not every block of {} is listed", path.display());
+    }
+}
+
+#[test]
+fn golden_region_queries_cover_the_box() {
+    for entry in fs::read_dir(golden_dir()).unwrap() {
+        let dir = entry.unwrap().path();
+        if !dir.is_dir() {
+            continue;
+        }
+        let (path, archive) = find_asset(&dir);
+        let bvp_file = BVPFile::open(&path, &archive).unwrap();
+        let reader = VolumeReader::new(&bvp_file);
+        let dimensions = bvp_file.blocks[bvp_file.modalities[0].block].dimensions;
+        let start = dimensions / Vector3::splat(4);
+        let end = dimensions - start;
+        let pieces = reader.blocks_intersecting(0, start, end).unwrap();
+        // Placements do not overlap, so the parts of the blocks inside the box add up to it.
+        let mut covered = 0u64;
+        for (index, position) in pieces {
+            let block = &bvp_file.blocks[index];
+            assert!(block.data.is_some(), "block {} of {} has no data", index, path.display());
+            let part = (position + block.dimensions).min(&end) - position.max(&start);
+            assert!(part.x > 0 && part.y > 0 && part.z > 0, "block {} of {} is outside the box", index, path.display());
+            covered += Vector3::<u64>::from(part).product();
+        }
+        assert_eq!(covered, Vector3::<u64>::from(end - start).product(), "the blocks of {} do not cover the box", path.display());
+    }
+}
+
+#[test]
+fn outputs_pass_reference_validator() {
+    let validator = match env::var("BVP_REFERENCE_VALIDATOR") {
+        Ok(v) => v,
+        Err(_) => {
+            eprintln!("BVP_REFERENCE_VALIDATOR is not set, outputs are not checked with the reference validator");
+            return;
+        }
+    };
+    let mut validator = validator.split_whitespace();
+    let program = validator.next().expect("BVP_REFERENCE_VALIDATOR is empty");
+    let validator_args: Vec<&str> = validator.collect();
+
+    let dir = env::temp_dir().join(format!("bvp-golden-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("input.raw"), (0..16 * 12 * 8).map(|i| (i % 7) as u8).collect::<Vec<u8>>()).unwrap();
+    for (output, archive) in [("asset.saf", "SAF"), ("asset.zip", "ZIP")] {
+        for compression in ["RAW", "LZ4S"] {
+            let result = Command::new(env!("CARGO_BIN_EXE_raw2bvp"))
+                .current_dir(&dir)
+                .args([
+                    "--input-file", "input.raw", "--output-file", output,
+                    "--dimensions", "16x12x8", "--block-dimensions", "8x8x8", "--format", "u8",
+                    "--archive", archive, "--compression", compression, "--no-progress", "-q"
+                ])
+                .output().unwrap();
+            assert!(result.status.success(), "raw2bvp failed: {}", String::from_utf8_lossy(&result.stderr));
+
+            let result = Command::new(program).args(&validator_args).arg(dir.join(output)).output().unwrap();
+            assert!(
+                result.status.success(), "the reference validator rejects a {} archive with {} blocks: {}{}",
+                archive, compression, String::from_utf8_lossy(&result.stdout), String::from_utf8_lossy(&result.stderr)
+            );
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,209 @@
+//! Tests of reading and writing manifests.
+
+use std::str;
+
+use tinyjson::JsonValue;
+
+use bvp::bvpfile::BVPFile;
+use bvp::errors::{BvpFileError, VersionError};
+use bvp::file::File;
+use bvp::formats::{self, Format};
+use bvp::manifest::ManifestReader;
+use bvp::version::{self, SpecVersion};
+use bvp::vector3::Vector3;
+use bvp::writer::VolumeWriter;
+
+/// A manifest with an asset, a modality and a block that have `extras`.
+const EXTRAS: &str = r#"{
+    "asset": { "version": "1.0", "extras": { "scanner": "XT-200", "protocol": 7, "settings": [1.5, null, true] } },
+    "modalities": [{ "name": "m", "block": 0, "extras": "a plain string" }],
+    "formats": [{ "family": "mono", "type": "u", "count": 1, "size": 1, "microblockDimensions": [1, 1, 1], "microblockSize": 1 }],
+    "blocks": [{ "dimensions": [2, 2, 2], "format": 0, "placements": [], "extras": { "source": { "slice": 12 } } }]
+}"#;
+
+#[test]
+fn extras_are_written_back_as_they_were() {
+    let original: JsonValue = EXTRAS.parse().unwrap();
+    let bvp_file = BVPFile::from_manifest(EXTRAS, &Vec::new()).unwrap();
+    assert_eq!(bvp_file.asset.extras.as_ref(), Some(&original["asset"]["extras"]));
+    assert_eq!(bvp_file.modalities[0].extras.as_ref(), Some(&original["modalities"][0]["extras"]));
+    assert_eq!(bvp_file.blocks[0].extras.as_ref(), Some(&original["blocks"][0]["extras"]));
+
+    let manifest: JsonValue = String::from_utf8(bvp_file.to_manifest().unwrap()).unwrap().parse().unwrap();
+    assert_eq!(manifest["asset"]["extras"], original["asset"]["extras"]);
+    assert_eq!(manifest["modalities"][0]["extras"], original["modalities"][0]["extras"]);
+    assert_eq!(manifest["blocks"][0]["extras"], original["blocks"][0]["extras"]);
+
+    // Nodes without extras get none.
+    let mut bvp_file = bvp_file;
+    bvp_file.asset.extras = None;
+    let manifest: JsonValue = String::from_utf8(bvp_file.to_manifest().unwrap()).unwrap().parse().unwrap();
+    match &manifest["asset"] {
+        JsonValue::Object(asset) => assert!(!asset.contains_key("extras")),
+        asset => panic!("The asset is {:?}", asset)
+    }
+}
+
+/// A manifest of one empty block, in a version of the specification.
+/// * `version` - the `version` of the asset
+fn versioned(version: &str) -> String {
+    return format!(r#"{{
+        "asset": {{ "version": "{}", "creationTime": "2024-05-01T12:30:00Z" }},
+        "modalities": [{{ "name": "m", "block": 0 }}],
+        "formats": [{{ "family": "mono", "type": "u", "count": 1, "size": 1, "microblockDimensions": [1, 1, 1], "microblockSize": 1 }}],
+        "blocks": [{{ "dimensions": [2, 2, 2], "format": 0, "placements": [] }}]
+    }}"#, version);
+}
+
+#[test]
+fn spec_versions_are_checked() {
+    assert_eq!(SpecVersion::from_string("1.2").unwrap(), SpecVersion::new(1, 2));
+    assert_eq!(SpecVersion::from_string("1").unwrap(), SpecVersion::new(1, 0));
+    assert!(matches!(SpecVersion::from_string("1.x"), Err(VersionError::Invalid(_))));
+    assert!(SpecVersion::new(1, 4).is_readable() && SpecVersion::new(1, 4).is_newer());
+    assert!(!SpecVersion::new(2, 0).is_readable());
+    assert!(!SpecVersion::CURRENT.is_newer());
+    assert_eq!(SpecVersion::writable("1.0").unwrap(), SpecVersion::CURRENT);
+    assert!(matches!(SpecVersion::writable("1.1"), Err(VersionError::Unsupported(v)) if v == "1.1"));
+
+    // Newer minor versions are read, other major versions are not.
+    for readable in ["1.0", "1.7"] {
+        let bvp_file = BVPFile::from_manifest(&versioned(readable), &Vec::new()).unwrap();
+        assert_eq!(bvp_file.asset.version, readable);
+    }
+    for (unreadable, error) in [("2.0", "Unsupported"), ("one", "Invalid")] {
+        match BVPFile::from_manifest(&versioned(unreadable), &Vec::new()) {
+            Err(BvpFileError::VersionError(e)) => assert!(format!("{:?}", e).starts_with(error), "{:?}", e),
+            result => panic!("Read version {}: {:?}", unreadable, result.err())
+        }
+    }
+}
+
+#[test]
+fn creation_times_are_iso_8601() {
+    let now = version::timestamp_now();
+    assert!(version::is_timestamp(&now), "{}", now);
+    assert!(now.ends_with('Z') && now.len() == "2024-05-01T12:30:00Z".len(), "{}", now);
+    assert!(version::is_timestamp("2024-05-01T14:30:00+02:00"));
+    for invalid in ["2024-05-01", "2024-05-01 12:30:00", "yesterday", "2024-13-01T12:30:00Z"] {
+        assert!(!version::is_timestamp(invalid), "{}", invalid);
+    }
+    assert!(version::generator("raw2bvp").starts_with(&format!("raw2bvp (bvp-tool {}", env!("CARGO_PKG_VERSION"))));
+
+    // New assets follow the current version.
+    let bvp_file = BVPFile::new();
+    assert_eq!(bvp_file.asset.version, SpecVersion::CURRENT.to_string());
+}
+
+/// A manifest whose last block cannot be parsed, with text in `extras` that looks like JSON structure.
+const LAZY: &str = r#"{
+    "asset": { "version": "1.0" },
+    "modalities": [{ "name": "m", "block": 0 }],
+    "formats": [{ "family": "mono", "type": "u", "count": 1, "size": 1, "microblockDimensions": [1, 1, 1], "microblockSize": 1 }],
+    "blocks": [
+        { "dimensions": [4, 3, 2], "placements": [{ "block": 1, "position": [0, 0, 0] }], "extras": "] }, { \"[" },
+        { "dimensions": [4, 3, 1], "format": 0, "data": "blocks/low.raw", "encoding": "raw", "placements": [] },
+        { "dimensions": [4, 3, 1], "placements": "not an array" }
+    ]
+}"#;
+
+#[test]
+fn blocks_are_parsed_when_they_are_asked_for() {
+    let files = vec![File::new("blocks/low.raw".to_string(), (0..12).collect::<Vec<u8>>(), None)];
+    let reader = ManifestReader::new(LAZY, &files).unwrap();
+    assert_eq!(reader.block_count(), 3);
+    assert_eq!(reader.modalities.len(), 1);
+    assert_eq!(reader.formats.len(), 1);
+
+    let root = reader.block(0).unwrap();
+    assert_eq!(root.extras.as_ref().and_then(|e| e.get::<String>()).map(|s| s.as_str()), Some("] }, { \"["));
+    // Data is shared with the file, not copied.
+    let low = reader.block(1).unwrap();
+    assert!(low.data.unwrap().ptr_eq(&files[0].data));
+    assert!(matches!(reader.block(2), Err(BvpFileError::BlockError(_))));
+    assert!(matches!(reader.block(3), Err(BvpFileError::BrokenManifest(_))));
+
+    let mut blocks = reader.blocks();
+    assert_eq!(blocks.len(), 3);
+    assert!(blocks.next().unwrap().is_ok());
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(blocks.filter(|b| b.is_err()).count(), 1);
+
+    // Reading the whole manifest parses every block.
+    assert!(matches!(BVPFile::from_manifest(LAZY, &files), Err(BvpFileError::BlockError(_))));
+}
+
+#[test]
+fn references_past_the_arrays_are_errors() {
+    let placement = LAZY.replace(r#""block": 1, "position""#, r#""block": 5, "position""#);
+    let reader = ManifestReader::new(&placement, &[]).unwrap();
+    let result = reader.block(0);
+    assert!(matches!(&result, Err(BvpFileError::NoSuchBlock(pointer, 5, 3)) if pointer == "/blocks/0/placements/0/block"), "{:?}", result.err());
+
+    let format = LAZY.replace(r#""format": 0, "data""#, r#""format": 1, "data""#);
+    let reader = ManifestReader::new(&format, &[]).unwrap();
+    assert!(matches!(reader.block(1), Err(BvpFileError::NoSuchFormat(_, 1, 1))));
+
+    let modality = LAZY.replace(r#""name": "m", "block": 0"#, r#""name": "m", "block": 3"#);
+    assert!(matches!(ManifestReader::new(&modality, &[]), Err(BvpFileError::NoSuchBlock(_, 3, 3))));
+
+    for broken in [format!("{} x", LAZY), LAZY.replacen("{", "[", 1), LAZY.replace(r#""asset":"#, r#""asset""#)] {
+        assert!(ManifestReader::new(&broken, &[]).is_err(), "{}", broken);
+    }
+}
+
+#[test]
+fn manifests_with_many_blocks_are_opened() {
+    let blocks = vec![r#"{ "dimensions": [1, 1, 1], "placements": [] }"#; 200_000].join(",");
+    let manifest = format!(r#"{{ "asset": {{ "version": "1.0" }}, "modalities": [], "formats": [], "blocks": [{}] }}"#, blocks);
+    let reader = ManifestReader::new(&manifest, &[]).unwrap();
+    assert_eq!(reader.block_count(), 200_000);
+    assert_eq!(reader.block(199_999).unwrap().index, 199_999);
+}
+
+#[test]
+fn assets_read_back_from_their_manifest_are_equal() {
+    let volume: Vec<u8> = (0..8 * 4 * 4).map(|i| (i / 3) as u8).collect();
+    let format = Format::from_json(&formats::shorthand_to_json("u8").unwrap()).unwrap();
+    let bvp_file = VolumeWriter::new(Vector3::from_xyz(4, 4, 4))
+        .build(volume, Vector3::from_xyz(8, 4, 4), format)
+        .unwrap();
+    let copy = bvp_file.clone();
+    assert_eq!(copy, bvp_file);
+
+    let manifest = String::from_utf8(bvp_file.to_manifest().unwrap()).unwrap();
+    let mut read = BVPFile::from_manifest(&manifest, &bvp_file.files).unwrap();
+    read.files = bvp_file.files.clone();
+    assert_eq!(read.asset, bvp_file.asset);
+    assert_eq!(read.formats, bvp_file.formats);
+    assert_eq!(read.blocks, bvp_file.blocks);
+
+    // Blocks with other data are not equal, even with the same name.
+    let index = read.blocks.iter().position(|b| b.data.is_some()).unwrap();
+    let mut data = read.blocks[index].data.as_ref().unwrap().as_slice().to_vec();
+    data[0] ^= 1;
+    read.blocks[index].data = Some(data.into());
+    assert_ne!(read.blocks[index], bvp_file.blocks[index]);
+    assert_ne!(read, bvp_file);
+}
+
+#[test]
+fn manifests_are_written_with_sorted_keys() {
+    let bvp_file = BVPFile::from_manifest(EXTRAS, &Vec::new()).unwrap();
+    let manifest = bvp_file.to_manifest().unwrap();
+    // The objects are new hash maps in every call, and every copy, so their order would change.
+    for _ in 0..10 {
+        assert_eq!(bvp_file.to_manifest().unwrap(), manifest);
+        let read = BVPFile::from_manifest(str::from_utf8(&manifest).unwrap(), &Vec::new()).unwrap();
+        assert_eq!(read.to_manifest().unwrap(), manifest);
+    }
+    let text = String::from_utf8(manifest).unwrap();
+    assert!(text.starts_with(r#"{"asset":{"extras":{"protocol":7,"scanner":"XT-200","settings":[1.5,null,true]}"#), "{}", text);
+    let top: Vec<usize> = ["\"blocks\":", "\"formats\":", "\"modalities\":"].iter().map(|k| text.rfind(k).unwrap()).collect();
+    assert!(top.windows(2).all(|w| w[0] < w[1]), "{}", text);
+
+    // Values without JSON text are still errors.
+    let mut infinite = bvp_file;
+    infinite.asset.extras = Some(JsonValue::Number(f64::INFINITY));
+    assert!(matches!(infinite.to_manifest(), Err(BvpFileError::CannotSerialize(_))));
+}
@@ -0,0 +1,83 @@
+//! Tests of the mapping between manifest types and JSON: field paths in errors,
+//! optional fields and round trips of the types of the manifest.
+
+use tinyjson::JsonValue;
+
+use bvp::asset::Asset;
+use bvp::errors::{AssetError, BlockError, FormatError, JsonError, ModalityError};
+use bvp::block::Block;
+use bvp::file::FileIndex;
+use bvp::formats::Format;
+use bvp::mapping::{ObjectReader, ObjectWriter};
+use bvp::modality::Modality;
+use bvp::vector3::Vector3;
+
+fn json(text: &str) -> JsonValue {
+    return text.parse().unwrap();
+}
+
+#[test]
+fn fields_are_read_with_their_paths() {
+    let j = json(r#"{ "name": "a", "sizes": [[1, 2, 3], [4, 5, "6"]], "count": 7 }"#);
+    let o = ObjectReader::new(&j).unwrap();
+    assert_eq!(o.required::<String>("name").unwrap(), "a");
+    assert_eq!(o.required::<u32>("count").unwrap(), 7);
+    assert_eq!(o.optional::<String>("missing").unwrap(), None);
+    assert_eq!(o.or_default::<Vec<String>>("missing").unwrap(), Vec::<String>::new());
+
+    let e = o.required::<Vec<Vector3<u32>>>("sizes").unwrap_err();
+    assert_eq!(e.pointer(), Some("/sizes/1/2"));
+    assert_eq!(o.required::<u32>("missing").unwrap_err().pointer(), Some("/missing"));
+    assert_eq!(o.optional::<u32>("name").unwrap_err().pointer(), Some("/name"));
+    assert_eq!(o.elements("count").unwrap_err().pointer(), Some("/count"));
+    assert!(matches!(ObjectReader::new(&json("[]")), Err(JsonError::NotAnObject(_))));
+}
+
+#[test]
+fn missing_values_and_empty_arrays_are_not_written() {
+    let j = ObjectWriter::new()
+        .field("name", "a")
+        .optional::<u32>("count", &None)
+        .optional("size", &Some(Vector3::from_xyz(1u32, 2, 3)))
+        .non_empty::<String>("used", &[])
+        .non_empty("required", &["EXT_checksum".to_string()])
+        .extras(&None)
+        .finish();
+    assert_eq!(j, json(r#"{ "name": "a", "size": [1, 2, 3], "required": ["EXT_checksum"] }"#));
+}
+
+#[test]
+fn errors_of_manifest_types_name_the_field() {
+    let asset = Asset::from_json(&json(r#"{ "version": "1.0", "extensionsUsed": ["EXT_checksum", 3] }"#));
+    assert!(matches!(asset, Err(AssetError::InvalidJson(e)) if e.pointer() == Some("/extensionsUsed/1")));
+
+    let modality = Modality::from_json(2, &json(r#"{ "block": 0, "voxelSize": [1, 1] }"#));
+    assert!(matches!(modality, Err(ModalityError::InvalidJson(2, e)) if e.pointer() == Some("/voxelSize")));
+
+    let block = Block::from_json(4, &json(r#"{ "dimensions": [1, 1, 1], "placements": [{ "position": [0, 0, 0], "block": "x" }] }"#), &FileIndex::new(&[]));
+    let e = block.unwrap_err().at("/blocks/4");
+    assert!(matches!(&e, BlockError::InvalidPlacement(4, _)));
+    assert!(e.to_string().contains("/blocks/4/placements/0/block"), "{}", e);
+
+    let format = Format::from_json(&json(r#"{ "family": "mono", "count": 1, "size": 1 }"#));
+    assert!(matches!(format, Err(FormatError::MissingField(f)) if f == "/type"));
+}
+
+#[test]
+fn manifest_types_are_written_as_they_are_read() {
+    let asset = json(r#"{
+        "version": "1.0", "name": "head", "creationTime": "2024-01-01T00:00:00Z",
+        "extensionsUsed": ["EXT_checksum"], "extras": { "scanner": 3 }
+    }"#);
+    let read = Asset::from_json(&asset).unwrap();
+    assert_eq!(read.to_json(&read.extensions_used, &read.extensions_required), asset);
+
+    let modality = json(r#"{ "name": "ct", "volumeSize": [1, 2, 3], "voxelSize": [0.5, 0.5, 1], "block": 0 }"#);
+    assert_eq!(Modality::from_json(0, &modality).unwrap().to_json(), modality);
+
+    let block = json(r#"{
+        "dimensions": [4, 4, 4], "format": 0, "data": "blocks/0.raw", "encoding": "raw",
+        "placements": [{ "position": [0, 4, 0], "block": 1 }]
+    }"#);
+    assert_eq!(Block::from_json(0, &block, &FileIndex::new(&[])).unwrap().to_json(), block);
+}
@@ -0,0 +1,111 @@
+//! Tests of reconstructing volumes and regions with `VolumeReader`, and of rejecting broken geometry.
+
+use bvp::{bvpfile::BVPFile, errors::{BlockError, ReaderError}, file::File, formats::{self, Format}, reader::VolumeReader, validate, vector3::Vector3, writer::VolumeWriter};
+
+/// A volume of 8x8x4 voxels, split into four blocks of 4x4x4.
+fn asset() -> (BVPFile, Vec<u8>) {
+    let volume: Vec<u8> = (0..8 * 8 * 4).map(|i| (i % 251) as u8 + 1).collect();
+    let format = Format::from_json(&formats::shorthand_to_json("u8").unwrap()).unwrap();
+    let bvp_file = VolumeWriter::new(Vector3::from_xyz(4, 4, 4))
+        .build(volume.clone(), Vector3::from_xyz(8, 8, 4), format)
+        .unwrap();
+    return (bvp_file, volume);
+}
+
+#[test]
+fn uncovered_parts_of_the_volume_are_reported() {
+    let (mut bvp_file, volume) = asset();
+    let whole = VolumeReader::new(&bvp_file).read_modality(0).unwrap();
+    assert_eq!(whole.data.unwrap().as_slice(), volume.as_slice());
+
+    // Without one of its blocks, a quarter of the volume is never written.
+    let (root, _) = VolumeReader::new(&bvp_file).modality_root(0).unwrap();
+    bvp_file.blocks[root].placements.pop();
+    let result = VolumeReader::new(&bvp_file).read_modality(0);
+    assert!(matches!(result, Err(ReaderError::Uncovered(0, 64, 256))), "{:?}", result.err());
+
+    // Regions that only touch the remaining blocks can still be read.
+    let region = VolumeReader::new(&bvp_file).read_region(0, Vector3::from_xyz(0, 0, 0), Vector3::from_xyz(4, 4, 4));
+    assert!(region.is_ok());
+}
+
+/// Copies a region out of a volume of 8x8x4 voxels, one voxel at a time.
+fn region_of(volume: &[u8], start: Vector3<u32>, end: Vector3<u32>) -> Vec<u8> {
+    let mut region = Vec::new();
+    for z in start.z..end.z {
+        for y in start.y..end.y {
+            for x in start.x..end.x {
+                region.push(volume[Vector3::linear_index(Vector3::from_xyz(x, y, z), Vector3::from_xyz(8, 8, 4))]);
+            }
+        }
+    }
+    return region;
+}
+
+#[test]
+fn regions_across_blocks_are_reconstructed() {
+    let (bvp_file, volume) = asset();
+    let reader = VolumeReader::new(&bvp_file);
+    for (start, end) in [
+        ((0, 0, 0), (8, 8, 4)),
+        ((3, 2, 1), (6, 7, 3)),
+        ((4, 4, 0), (8, 8, 4)),
+        ((7, 0, 3), (8, 1, 4))
+    ] {
+        let (start, end) = (Vector3::from_xyz(start.0, start.1, start.2), Vector3::from_xyz(end.0, end.1, end.2));
+        let region = reader.read_region(0, start, end).unwrap();
+        assert_eq!(region.dimensions, end - start);
+        assert_eq!(region.data.unwrap().as_slice(), region_of(&volume, start, end).as_slice(), "{:?} to {:?}", start, end);
+    }
+    // Only the blocks the region touches are decoded.
+    let pieces = reader.blocks_intersecting(0, Vector3::from_xyz(3, 2, 1), Vector3::from_xyz(4, 3, 2)).unwrap();
+    assert_eq!(pieces.len(), 1);
+
+    for (start, end) in [((2, 0, 0), (1, 1, 1)), ((0, 0, 0), (9, 1, 1))] {
+        let (start, end) = (Vector3::from_xyz(start.0, start.1, start.2), Vector3::from_xyz(end.0, end.1, end.2));
+        assert!(reader.read_region(0, start, end).is_err(), "{:?} to {:?}", start, end);
+    }
+}
+
+/// A manifest of a `u8` volume with a root block and a block with data placed in it.
+/// * `root` - dimensions of the root block
+/// * `position` - position of the data block in the root
+/// * `dimensions` - dimensions of the data block
+fn placed(root: &str, position: &str, dimensions: &str) -> String {
+    return format!(r#"{{
+        "asset": {{ "version": "1.0" }},
+        "modalities": [{{ "name": "m", "block": 0 }}],
+        "formats": [{{ "family": "mono", "type": "u", "count": 1, "size": 1, "microblockDimensions": [1, 1, 1], "microblockSize": 1 }}],
+        "blocks": [
+            {{ "dimensions": {}, "format": 0, "placements": [{{ "block": 1, "position": {} }}] }},
+            {{ "dimensions": {}, "format": 0, "data": "blocks/b.raw", "encoding": "raw", "placements": [] }}
+        ]
+    }}"#, root, position, dimensions);
+}
+
+#[test]
+fn geometry_that_overflows_is_an_error() {
+    let files = vec![File::new("blocks/b.raw".to_string(), vec![7u8; 8], None)];
+    let max = u32::MAX;
+    for (root, position, dimensions) in [
+        // The size of the root does not fit into memory.
+        (format!("[{}, {}, {}]", max, max, max), "[0, 0, 0]".to_string(), "[2, 2, 2]".to_string()),
+        // The end of the block is past the largest position.
+        ("[4, 4, 4]".to_string(), format!("[{}, 0, 0]", max - 1), "[2, 2, 2]".to_string()),
+        ("[4, 4, 4]".to_string(), "[0, 0, 0]".to_string(), format!("[{}, {}, {}]", max, max, 2))
+    ] {
+        let manifest = placed(&root, &position, &dimensions);
+        let bvp_file = BVPFile::from_manifest(&manifest, &files).unwrap();
+        let result = VolumeReader::new(&bvp_file).read_modality(0);
+        assert!(matches!(result, Err(ReaderError::BlockError(BlockError::Overflow(_)))), "{} {} {}: {:?}", root, position, dimensions, result.err());
+        assert!(!validate::validate_block_tree(&bvp_file).is_empty(), "{} {} {}", root, position, dimensions);
+    }
+}
+
+#[test]
+fn blocks_with_too_little_data_are_an_error() {
+    let files = vec![File::new("blocks/b.raw".to_string(), vec![7u8; 5], None)];
+    let bvp_file = BVPFile::from_manifest(&placed("[4, 4, 4]", "[0, 0, 0]", "[2, 2, 2]"), &files).unwrap();
+    let result = VolumeReader::new(&bvp_file).read_modality(0);
+    assert!(matches!(result, Err(ReaderError::BlockError(BlockError::DataSizeMismatch(1, 5, 8)))), "{:?}", result.err());
+}
@@ -0,0 +1,14 @@
+//! Tests of remote locations that do not need a server.
+
+use std::env;
+
+use bvp::{errors::RemoteError, remote::Location};
+
+#[test]
+fn credentials_with_line_breaks_are_rejected() {
+    // A line break would end the header option and let the rest of the value set other curl options.
+    env::set_var("GOOGLE_OAUTH_ACCESS_TOKEN", "token\nurl = \"http://example.com/\"");
+    let location = Location::parse("gs://bucket/volume.raw").unwrap();
+    assert!(matches!(location.read(), Err(RemoteError::InvalidOption(name)) if name == "header"));
+    assert!(matches!(location.write(b"data"), Err(RemoteError::InvalidOption(_))));
+}
@@ -0,0 +1,285 @@
+//! Round trip tests: random volumes are converted with `raw2bvp` and read back with the library,
+//! which has to reconstruct them byte for byte.
+//!
+//! Cases are generated from a seed, so a failing case can be run again on its own with
+//! `BVP_ROUNDTRIP_SEED=<seed> BVP_ROUNDTRIP_CASES=1 cargo test --test roundtrip`.
+//! `BVP_ROUNDTRIP_CASES` also sets how many cases are run, 64 by default.
+
+use std::{env, fs, path::{Path, PathBuf}, process::Command};
+
+use std::sync::Arc;
+
+use bvp::{archives::ArchiveEnum, bvpfile::BVPFile, errors::ArchiveError, file::File, formats::{self, Format}, reader::VolumeReader};
+use bvp::{transfer_function, vector3::Vector3, writer::VolumeWriter};
+
+/// Small xorshift generator, so that cases can be reproduced from their seed.
+struct Generator {
+    state: u64
+}
+
+impl Generator {
+    fn new(seed: u64) -> Self {
+        return Self { state: seed.max(1) };
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        return self.state;
+    }
+
+    /// Returns a number from `min` to `max`, both included.
+    fn range(&mut self, min: u32, max: u32) -> u32 {
+        return min + (self.next() % (max - min + 1) as u64) as u32;
+    }
+
+    fn choose<T: Copy>(&mut self, values: &[T]) -> T {
+        return values[self.next() as usize % values.len()];
+    }
+}
+
+/// Parameters of one conversion.
+#[derive(Debug)]
+struct Case {
+    seed: u64,
+    dimensions: [u32; 3],
+    block_dimensions: [u32; 3],
+    superblocks: bool,
+    format: &'static str,
+    voxel_size: usize,
+    archive: &'static str,
+    archive_compression: &'static str,
+    compression: &'static str,
+    parallel: &'static str,
+    deduplication: bool,
+    dedup_memory_blocks: u32,
+    block_order: &'static str,
+    voxel_layout: &'static str,
+    checksum: &'static str
+}
+
+impl Case {
+    fn generate(seed: u64) -> Self {
+        let mut g = Generator::new(seed);
+        let dimensions = [g.range(1, 40), g.range(1, 40), g.range(1, 40)];
+        let block_dimensions = dimensions.map(|d| g.range(1, d));
+        let (format, voxel_size) = g.choose(&[("u8", 1), ("u16", 2), ("f32", 4), ("u8x3", 3)]);
+        let mut case = Self {
+            seed,
+            dimensions,
+            block_dimensions,
+            superblocks: g.next() % 4 == 0,
+            format,
+            voxel_size,
+            archive: g.choose(&["SAF", "ZIP", "None"]),
+            archive_compression: "none",
+            compression: g.choose(&["LZ4S", "RAW"]),
+            parallel: g.choose(&["pipeline", "data"]),
+            deduplication: g.next() % 2 == 0,
+            dedup_memory_blocks: 4194304,
+            block_order: "completion",
+            voxel_layout: "linear",
+            checksum: g.choose(&["none", "xxh3", "crc32"])
+        };
+        if case.archive == "ZIP" {
+            case.archive_compression = g.choose(&["none", "deflate"]);
+        }
+        // Small limits make the deduplication map spill to the disk, most shards after a single entry.
+        case.dedup_memory_blocks = g.choose(&[4194304, 1, 64]);
+        case.block_order = g.choose(&["completion", "grid", "morton"]);
+        case.voxel_layout = g.choose(&["linear", "morton"]);
+        return case;
+    }
+
+    /// Returns random voxels. Most volumes have runs of equal voxels, so that blocks
+    /// repeat and are deduplicated, and that LZ4S finds matches.
+    fn volume(&self) -> Vec<u8> {
+        let mut g = Generator::new(self.seed ^ 0x5eed);
+        let size = self.dimensions.iter().product::<u32>() as usize * self.voxel_size;
+        let values = if g.next() % 3 == 0 { 256 } else { g.range(1, 4) as u64 };
+        let mut data = Vec::with_capacity(size);
+        while data.len() < size {
+            let value = (g.next() % values) as u8;
+            let run = g.range(1, 64) as usize;
+            data.extend(std::iter::repeat(value).take(run.min(size - data.len())));
+        }
+        return data;
+    }
+}
+
+fn dimensions_arg(d: [u32; 3]) -> String {
+    return format!("{}x{}x{}", d[0], d[1], d[2]);
+}
+
+/// Converts the volume of a case in `dir` and returns the path to open the asset from.
+fn convert(case: &Case, dir: &Path) -> PathBuf {
+    fs::write(dir.join("input.raw"), case.volume()).unwrap();
+    let output = match case.archive {
+        "SAF" => "asset.saf",
+        "ZIP" => "asset.zip",
+        // Unarchived assets are written to a folder.
+        _ => "asset"
+    };
+    let mut command = Command::new(env!("CARGO_BIN_EXE_raw2bvp"));
+    command.current_dir(dir).args([
+        "--input-file", "input.raw",
+        "--output-file", output,
+        "--dimensions", &dimensions_arg(case.dimensions),
+        "--block-dimensions", &dimensions_arg(case.block_dimensions),
+        "--format", case.format,
+        "--archive", case.archive,
+        "--archive-compression", case.archive_compression,
+        "--compression", case.compression,
+        "--checksum", case.checksum,
+        "--deduplication", if case.deduplication { "true" } else { "false" },
+        "--dedup-memory-blocks", &case.dedup_memory_blocks.to_string(),
+        "--block-order", case.block_order,
+        "--voxel-layout", case.voxel_layout,
+        &format!("--parallel={}", case.parallel),
+        "--threads", "2",
+        "--no-progress", "-q"
+    ]);
+    if case.superblocks {
+        command.args(["--superblock-dimensions", &dimensions_arg(case.block_dimensions.map(|d| d * 2))]);
+    }
+    let result = command.output().unwrap();
+    assert!(result.status.success(), "raw2bvp failed for {:?}: {}", case, String::from_utf8_lossy(&result.stderr));
+    return dir.join(output);
+}
+
+fn archive_type(case: &Case) -> ArchiveEnum {
+    return ArchiveEnum::from_string(case.archive.to_string()).unwrap();
+}
+
+fn run_case(case: &Case) {
+    let dir = env::temp_dir().join(format!("bvp-roundtrip-{}-{}", std::process::id(), case.seed));
+    fs::create_dir_all(&dir).unwrap();
+    let path = convert(case, &dir);
+    let leftovers: Vec<_> = fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .filter(|name| name.to_string_lossy().ends_with(".dedup"))
+        .collect();
+    assert!(leftovers.is_empty(), "deduplication index left behind for {:?}: {:?}", case, leftovers);
+
+    let bvp_file = match BVPFile::open(&path, &archive_type(case)) {
+        Ok(b) => b,
+        Err(e) => panic!("cannot open the asset of {:?}: {}", case, e)
+    };
+    let volume = match VolumeReader::new(&bvp_file).with_checksum_verification(true).read_modality(0) {
+        Ok(v) => v,
+        Err(e) => panic!("cannot read the volume of {:?}: {}", case, e)
+    };
+    let expected = fs::read(dir.join("input.raw")).unwrap();
+    let data = volume.data.expect("the volume has no data");
+    assert_eq!(data.len(), expected.len(), "wrong size for {:?}", case);
+    if let Some(i) = data.iter().zip(expected.iter()).position(|(a, b)| a != b) {
+        panic!("first difference at byte {} for {:?}", i, case);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn random_volumes_round_trip() {
+    let seed = match env::var("BVP_ROUNDTRIP_SEED") {
+        Ok(s) => s.parse::<u64>().expect("BVP_ROUNDTRIP_SEED has to be a number"),
+        Err(_) => 0x6276_7000
+    };
+    let cases = match env::var("BVP_ROUNDTRIP_CASES") {
+        Ok(s) => s.parse::<u64>().expect("BVP_ROUNDTRIP_CASES has to be a number"),
+        Err(_) => 64
+    };
+    for i in 0..cases {
+        run_case(&Case::generate(seed + i));
+    }
+}
+
+#[test]
+fn every_archive_and_compression_round_trips() {
+    let mut seed = 0x6276_7100;
+    for (archive, archive_compression) in [("SAF", "none"), ("ZIP", "none"), ("ZIP", "deflate"), ("None", "none")] {
+        for compression in ["LZ4S", "RAW"] {
+            let mut case = Case::generate(seed);
+            case.dimensions = [17, 9, 12];
+            case.block_dimensions = [5, 4, 7];
+            case.archive = archive;
+            case.archive_compression = archive_compression;
+            case.compression = compression;
+            run_case(&case);
+            seed += 1;
+        }
+    }
+}
+
+#[test]
+fn block_indices_do_not_depend_on_the_parallel_mode() {
+    let mut case = Case::generate(0x6276_7200);
+    case.dimensions = [31, 26, 19];
+    case.block_dimensions = [4, 3, 5];
+    case.archive = "SAF";
+    case.deduplication = true;
+    case.block_order = "completion";
+    let mut assets = Vec::new();
+    for parallel in ["pipeline", "data"] {
+        case.parallel = parallel;
+        let dir = env::temp_dir().join(format!("bvp-roundtrip-{}-{}", std::process::id(), parallel));
+        fs::create_dir_all(&dir).unwrap();
+        assets.push(BVPFile::open(&convert(&case, &dir), &archive_type(&case)).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    assert!(assets[0].blocks.len() > 1, "{:?} has no blocks besides the root block", case);
+    assert_eq!(assets[0].blocks, assets[1].blocks, "blocks differ for {:?}", case);
+}
+
+#[test]
+fn unarchived_assets_keep_files_of_extensions() {
+    let dir = env::temp_dir().join(format!("bvp-roundtrip-{}-lut", std::process::id()));
+    let format = Format::from_json(&formats::shorthand_to_json("u8").unwrap()).unwrap();
+    let mut bvp_file = VolumeWriter::new(Vector3::splat(4)).build(vec![7; 8 * 8 * 8], Vector3::splat(8), format).unwrap();
+    let texels: Vec<u8> = (0..=255).flat_map(|v| [v, v, v, 255]).collect();
+    let lut = transfer_function::add_lut(&mut bvp_file, "transfer_functions/gray.rgba", 256, 1, texels.clone()).unwrap();
+    transfer_function::set_transfer_functions(&mut bvp_file.modalities[0], &[lut]).unwrap();
+    bvp_file.files.retain(|f| f.name != "manifest.json");
+    bvp_file.files.push(File::new("manifest.json".to_string(), Arc::new(bvp_file.to_manifest().unwrap()), None));
+
+    let mut writer = ArchiveEnum::None.return_writer(&dir.to_string_lossy());
+    for file in &bvp_file.files {
+        writer.append_file(file).unwrap();
+    }
+    writer.finish(dir.to_string_lossy().to_string()).unwrap();
+
+    let read = BVPFile::open(&dir, &ArchiveEnum::None).unwrap();
+    let lut = &transfer_function::transfer_functions(&read.modalities[0]).unwrap()[0];
+    assert_eq!(lut.lut_data(&read.files).unwrap(), Some(texels.as_slice()));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unarchived_blocks_outside_the_asset_are_errors() {
+    let dir = env::temp_dir().join(format!("bvp-roundtrip-{}-outside", std::process::id()));
+    let asset = dir.join("asset");
+    fs::create_dir_all(asset.join("blocks")).unwrap();
+    fs::write(dir.join("secret.raw"), [1u8; 8]).unwrap();
+    fs::write(asset.join("blocks").join("block.raw"), [2u8; 8]).unwrap();
+    let absolute = dir.join("secret.raw").to_string_lossy().replace('\\', "/");
+    let manifest = |data: &str| format!(r#"{{
+        "asset": {{ "version": "1.0" }},
+        "modalities": [{{ "name": "m", "block": 0 }}],
+        "formats": [{{ "family": "mono", "type": "u", "count": 1, "size": 1, "microblockDimensions": [1, 1, 1], "microblockSize": 1 }}],
+        "blocks": [{{ "dimensions": [2, 2, 2], "format": 0, "data": "{}", "placements": [] }}]
+    }}"#, data);
+
+    fs::write(asset.join("manifest.json"), manifest("./blocks/block.raw")).unwrap();
+    assert!(ArchiveEnum::None.read_archive(&asset).is_ok());
+    for outside in ["../secret.raw", "blocks/../../secret.raw", absolute.as_str(), ""] {
+        fs::write(asset.join("manifest.json"), manifest(outside)).unwrap();
+        let result = ArchiveEnum::None.read_archive(&asset);
+        assert!(matches!(&result, Err(ArchiveError::NotValidFile(message)) if message.contains("not a file of the asset")), "{}: {:?}", outside, result.err());
+    }
+
+    // Manifests without blocks are errors, not panics.
+    fs::write(asset.join("manifest.json"), r#"{ "asset": { "version": "1.0" } }"#).unwrap();
+    assert!(matches!(ArchiveEnum::None.read_archive(&asset), Err(ArchiveError::NotValidFile(_))));
+    fs::remove_dir_all(&dir).unwrap();
+}
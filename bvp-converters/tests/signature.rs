@@ -0,0 +1,231 @@
+//! Tests of manifest signatures: the Ed25519 implementation against the test vectors of RFC 8032 and
+//! signatures made with other implementations, and signing and verifying manifests.
+
+use std::{collections::HashMap, path::Path};
+
+use tinyjson::JsonValue;
+
+use bvp::archives::ArchiveEnum;
+use bvp::checksum::{Checksum, ChecksumType};
+use bvp::ed25519::{self, SigningKey};
+use bvp::file::File;
+use bvp::validate::{self, IssueCode, Severity, ValidationOptions};
+use bvp::errors::SignatureError;
+use bvp::signature::{self, from_hex, to_hex};
+
+/// Seed, public key, message and signature, all in hexadecimal.
+const VECTORS: [(&str, &str, &str, &str); 4] = [
+    (
+        "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+        "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+        "",
+        "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+    ),
+    (
+        "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+        "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+        "72",
+        "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00"
+    ),
+    (
+        "c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+        "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+        "af82",
+        "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a"
+    ),
+    (
+        // TEST SHA(abc), the message is the SHA-512 hash of "abc".
+        "833fe62409237b9d62ec77587520911e9a759cec1d19755b7da901b96dca3d42",
+        "ec172b93ad5e563bf4932c70e1245034c35467ef2efd4d64ebf819683467e2bf",
+        "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+        "dc2a4459e7369633a52b1bf277839a00201009a3efbf3ecb69bea2186c26b58909351fc9ac90b3ecfdfbc7c66431e0303dca179c138ac17ad9bef1177331a704"
+    )
+];
+
+/// Order of the base point, in little-endian bytes.
+const ORDER: [u8; 32] = [
+    0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10
+];
+
+fn hex_bytes(text: &str) -> Vec<u8> {
+    return (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect();
+}
+
+#[test]
+fn rfc_8032_vectors() {
+    for (seed, public_key, message, signature) in VECTORS {
+        let key = SigningKey::from_seed(from_hex(seed).unwrap());
+        assert_eq!(to_hex(&key.public_key()), public_key);
+        let message = hex_bytes(message);
+        let made = key.sign(&message);
+        assert_eq!(to_hex(&made), signature);
+        assert!(ed25519::verify(&key.public_key(), &message, &made));
+    }
+}
+
+#[test]
+fn rfc_8032_vector_1024_key() {
+    // The 1023 bytes of the message of TEST 1024 are not repeated here, `long_message` covers long messages.
+    let key = SigningKey::from_seed(from_hex("f5e5767cf153319517630f226876b86c8160cc583bc013744c6bf255f5cc0ee5").unwrap());
+    assert_eq!(to_hex(&key.public_key()), "278117fc144c72340f67d0f2316e8386ceffbf2b2428c9c51fef7c597f1d426e");
+}
+
+#[test]
+fn long_message() {
+    // Spans several SHA-512 blocks, the signature was made with OpenSSL.
+    let seed: Vec<u8> = (0..32).map(|i| (i * 13 + 1) as u8).collect();
+    let message: Vec<u8> = (0..1000).map(|i| (i * 7 + 3) as u8).collect();
+    let key = SigningKey::from_seed(seed.try_into().unwrap());
+    assert_eq!(to_hex(&key.public_key()), "098f9f3908b407db2cbfb61df028acc52d5f8da74a9d7e03ab9c2f8656f6a9f0");
+    assert_eq!(
+        to_hex(&key.sign(&message)),
+        "ad6747681fa44fb8baec5435967e918a3fa2d42692704fd1c804b257c69137771e5da623d26aa7ee8cad01e9db34096ade1486d884fdb85e99ceac2c9e6b3e0c"
+    );
+}
+
+#[test]
+fn altered_signatures_are_rejected() {
+    let (seed, _, message, _) = VECTORS[1];
+    let key = SigningKey::from_seed(from_hex(seed).unwrap());
+    let message = hex_bytes(message);
+    let made = key.sign(&message);
+    assert!(!ed25519::verify(&key.public_key(), b"other message", &made));
+    for byte in [0, 31, 32, 63] {
+        let mut altered = made;
+        altered[byte] ^= 1;
+        assert!(!ed25519::verify(&key.public_key(), &message, &altered));
+    }
+    let other = SigningKey::from_seed([7; 32]);
+    assert!(!ed25519::verify(&other.public_key(), &message, &made));
+}
+
+#[test]
+fn non_canonical_scalars_are_rejected() {
+    // Adding the order to S gives a signature that passes the equation, but is a different encoding.
+    let (seed, _, message, _) = VECTORS[0];
+    let key = SigningKey::from_seed(from_hex(seed).unwrap());
+    let message = hex_bytes(message);
+    let made = key.sign(&message);
+    let mut altered = made;
+    let mut carry = 0u16;
+    for i in 0..32 {
+        let sum = altered[32 + i] as u16 + ORDER[i] as u16 + carry;
+        altered[32 + i] = sum as u8;
+        carry = sum >> 8;
+    }
+    assert_eq!(carry, 0);
+    assert!(ed25519::verify(&key.public_key(), &message, &made));
+    assert!(!ed25519::verify(&key.public_key(), &message, &altered));
+
+    // S equal to the order is not canonical either.
+    let mut altered = made;
+    altered[32..].copy_from_slice(&ORDER);
+    assert!(!ed25519::verify(&key.public_key(), &message, &altered));
+}
+
+#[test]
+fn hex_with_other_characters_is_rejected() {
+    assert_eq!(from_hex::<2>("0aFf"), Some([0x0a, 0xff]));
+    assert_eq!(from_hex::<2>(" 0aff\n"), Some([0x0a, 0xff]));
+    for text in ["+f0a", "0a+f", "-f0a", "0x0a", "0a f", "0g00", "0aé"] {
+        assert_eq!(from_hex::<2>(text), None, "{:?} was accepted", text);
+    }
+    let key = "+".to_string() + &"f".repeat(63);
+    assert!(matches!(signature::signing_key_from_hex(&key), Err(SignatureError::InvalidKey(_))));
+}
+
+fn manifest() -> HashMap<String, JsonValue> {
+    let text = r#"{"asset":{"version":"1.0","author":"Lab"},"formats":[],"modalities":[],"blocks":[{"dimensions":[1,1,1],"data":"blocks/block_0.raw","checksum":"sha256:00000000000000000000000000000000000000000000000000000000000000ff"}]}"#;
+    return match text.parse::<JsonValue>().unwrap() {
+        JsonValue::Object(o) => o,
+        _ => unreachable!()
+    };
+}
+
+#[test]
+fn signed_manifests_verify() {
+    let key = SigningKey::from_seed([3; 32]);
+    let mut manifest = manifest();
+    assert!(matches!(signature::verify_manifest(&manifest), Err(SignatureError::Missing)));
+    signature::sign_manifest(&mut manifest, &key).unwrap();
+    assert_eq!(signature::verify_manifest(&manifest).unwrap(), key.public_key());
+
+    // Signing again replaces the signature, and the text can be written and read back.
+    signature::sign_manifest(&mut manifest, &key).unwrap();
+    let text = JsonValue::from(manifest.clone()).stringify().unwrap();
+    let read = match text.parse::<JsonValue>().unwrap() {
+        JsonValue::Object(o) => o,
+        _ => unreachable!()
+    };
+    assert_eq!(signature::verify_manifest(&read).unwrap(), key.public_key());
+}
+
+#[test]
+fn changed_manifests_do_not_verify() {
+    let key = SigningKey::from_seed([3; 32]);
+    let mut manifest = manifest();
+    signature::sign_manifest(&mut manifest, &key).unwrap();
+    let text = JsonValue::from(manifest).stringify().unwrap();
+    let changed = text.replace("00ff\"", "00fe\"");
+    let changed = match changed.parse::<JsonValue>().unwrap() {
+        JsonValue::Object(o) => o,
+        _ => unreachable!()
+    };
+    assert!(matches!(signature::verify_manifest(&changed), Err(SignatureError::Mismatch)));
+}
+
+#[test]
+fn data_without_cryptographic_checksums_is_not_signed() {
+    let key = SigningKey::from_seed([3; 32]);
+    for checksum in [r#","checksum":"xxh3:00ff""#, ""] {
+        let text = format!(r#"{{"asset":{{"version":"1.0"}},"formats":[],"modalities":[],"blocks":[{{"dimensions":[1,1,1],"data":"blocks/block_0.raw"{}}}]}}"#, checksum);
+        let mut manifest = match text.parse::<JsonValue>().unwrap() {
+            JsonValue::Object(o) => o,
+            _ => unreachable!()
+        };
+        assert!(matches!(signature::sign_manifest(&mut manifest, &key), Err(SignatureError::UnsignedData(0))));
+    }
+}
+
+/// Returns the errors the validator reports with the signature check.
+/// * `files` - the files of the asset
+fn signature_errors(files: &Vec<File>) -> Vec<IssueCode> {
+    let options = ValidationOptions { check_signature: true, ..Default::default() };
+    return validate::validate_files_with_options(files, &options).into_iter()
+        .filter(|issue| matches!(issue.severity, Severity::Error))
+        .map(|issue| issue.code)
+        .collect();
+}
+
+#[test]
+fn changed_block_data_does_not_verify() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/mono-u8-tree/manifest.json");
+    let mut files = ArchiveEnum::None.read_archive(&path).unwrap();
+    let index = files.iter().position(|file| file.name.ends_with("manifest.json")).unwrap();
+    let mut manifest = match String::from_utf8(files[index].data.to_vec()).unwrap().parse::<JsonValue>().unwrap() {
+        JsonValue::Object(o) => o,
+        _ => unreachable!()
+    };
+    if let Some(JsonValue::Array(blocks)) = manifest.get_mut("blocks") {
+        for block in blocks {
+            let data = block.get::<HashMap<String, JsonValue>>().and_then(|b| b.get("data")).and_then(|d| d.get::<String>()).cloned();
+            if let (JsonValue::Object(block), Some(data)) = (block, data) {
+                let file = files.iter().find(|file| file.name == data).unwrap();
+                let checksum = Checksum::compute(ChecksumType::Sha256, &file.data);
+                block.insert("checksum".to_string(), checksum.to_string().into());
+            }
+        }
+    }
+    signature::sign_manifest(&mut manifest, &SigningKey::from_seed([3; 32])).unwrap();
+    let text = JsonValue::from(manifest).stringify().unwrap();
+    files[index] = File::new(files[index].name.clone(), text.into_bytes(), None);
+    assert_eq!(signature_errors(&files), Vec::<IssueCode>::new());
+
+    // The manifest is unchanged, so the signature still matches it, but not the data.
+    let block = files.iter().position(|file| file.name.ends_with("low.raw")).unwrap();
+    let mut data = files[block].data.to_vec();
+    data[0] ^= 1;
+    files[block] = File::new(files[block].name.clone(), data, None);
+    assert_eq!(signature_errors(&files), vec![IssueCode::ChecksumMismatch]);
+}
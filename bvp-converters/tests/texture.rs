@@ -0,0 +1,54 @@
+//! Tests of encoding volumes as BC4 textures.
+
+use bvp::formats::FormatFamily;
+use bvp::texture::{decode_bc4_block, encode_bc4_block, TextureCompression, BC4_BLOCK_SIZE};
+use bvp::vector3::Vector3;
+
+#[test]
+fn bc4_blocks_decode_close_to_their_values() {
+    // A flat tile is stored exactly.
+    assert_eq!(decode_bc4_block(&encode_bc4_block(&[77; 16])), [77; 16]);
+
+    // Tiles with two values are stored exactly in either mode.
+    let two: [u8; 16] = std::array::from_fn(|i| if i % 3 == 0 { 10 } else { 200 });
+    assert_eq!(decode_bc4_block(&encode_bc4_block(&two)), two);
+
+    // The extremes 0 and 255 are kept exactly next to values in between.
+    let mut extremes: [u8; 16] = std::array::from_fn(|i| 100 + i as u8);
+    extremes[0] = 0;
+    extremes[15] = 255;
+    let decoded = decode_bc4_block(&encode_bc4_block(&extremes));
+    assert_eq!((decoded[0], decoded[15]), (0, 255));
+    for i in 1..15 {
+        assert!((decoded[i] as i32 - extremes[i] as i32).abs() <= 2, "{:?} for {:?}", decoded, extremes);
+    }
+
+    // A gradient is off by at most half a step of the palette.
+    let gradient: [u8; 16] = std::array::from_fn(|i| (i * 17) as u8);
+    let decoded = decode_bc4_block(&encode_bc4_block(&gradient));
+    for i in 0..16 {
+        assert!((decoded[i] as i32 - gradient[i] as i32).abs() <= 19, "{:?} for {:?}", decoded, gradient);
+    }
+}
+
+#[test]
+fn volumes_are_encoded_tile_by_tile() {
+    let compression = TextureCompression::Bc4;
+    let format = compression.format();
+    assert!(matches!(format.family(), FormatFamily::Compressed(c) if c.scheme() == "bc4"));
+    assert_eq!(TextureCompression::from_string(&compression.to_string()), Some(compression));
+
+    // 8x4x2 voxels make two tiles in every slice, each of them flat with its own value.
+    let dimensions = Vector3::from_xyz(8, 4, 2);
+    let mut volume = vec![0u8; 8 * 4 * 2];
+    for (i, voxel) in volume.iter_mut().enumerate() {
+        let (x, z) = (i % 8, i / 32);
+        *voxel = (z * 2 + x / 4) as u8 * 50;
+    }
+    let encoded = compression.encode(&volume, dimensions);
+    assert_eq!(Some(encoded.len() as u64), format.count_space(dimensions));
+    assert_eq!(encoded.len(), 4 * BC4_BLOCK_SIZE as usize);
+    for (tile, block) in encoded.chunks(BC4_BLOCK_SIZE as usize).enumerate() {
+        assert_eq!(decode_bc4_block(block.try_into().unwrap()), [tile as u8 * 50; 16], "Tile {}", tile);
+    }
+}
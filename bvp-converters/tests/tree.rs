@@ -0,0 +1,89 @@
+//! Tests of building block trees with `bvp::tree::BlockTreeBuilder`.
+
+use bvp::block::Block;
+use bvp::bvpfile::BVPFile;
+use bvp::errors::TreeError;
+use bvp::placement::Placement;
+use bvp::tree::BlockTreeBuilder;
+use bvp::vector3::Vector3;
+
+/// An asset with a single 4x4x1 root block, split into 2x2x1 blocks.
+fn asset() -> BVPFile {
+    let mut bvp_file = BVPFile::new();
+    bvp_file.blocks.push(Block::new(0, Vector3::from_xyz(4, 4, 1), None, Some(vec![0; 16])));
+    return bvp_file;
+}
+
+fn block(index: usize) -> Block {
+    return Block::new(index, Vector3::from_xyz(2, 2, 1), None, None);
+}
+
+#[test]
+fn blocks_are_numbered_by_position() {
+    let positions = [(0, 0), (2, 0), (0, 2), (2, 2)];
+    let mut built = Vec::new();
+    // The same tree, with blocks added in grid order and in reverse.
+    for order in [[0, 1, 2, 3], [3, 2, 1, 0]] {
+        let mut tree = BlockTreeBuilder::new(1);
+        for i in order {
+            let (x, y) = positions[i];
+            // Provisional indices with gaps, as workers might choose them.
+            tree.add_block(0, Vector3::from_xyz(x, y, 0), block(100 + 10 * i));
+        }
+        let mut bvp_file = asset();
+        tree.build(&mut bvp_file, &[]).unwrap();
+        built.push(bvp_file.blocks);
+    }
+    assert_eq!(built[0], built[1]);
+
+    let blocks = &built[0];
+    assert_eq!(blocks.iter().map(|b| b.index).collect::<Vec<usize>>(), vec![0, 1, 2, 3, 4]);
+    assert_eq!(blocks[0].data, None);
+    let expected: Vec<Placement> = positions.iter().enumerate()
+        .map(|(i, (x, y))| Placement::new(Vector3::from_xyz(*x, *y, 0), 1 + i))
+        .collect();
+    assert_eq!(blocks[0].placements, expected);
+}
+
+#[test]
+fn placed_blocks_are_shared() {
+    let mut tree = BlockTreeBuilder::new(1);
+    // Both rows hold blocks 7 and 3. The block placed first is numbered first, although it was added last.
+    tree.add_placement(0, Vector3::from_xyz(0, 2, 0), 7);
+    tree.add_placement(0, Vector3::from_xyz(2, 2, 0), 3);
+    tree.add_block(0, Vector3::from_xyz(2, 0, 0), block(3));
+    tree.add_block(0, Vector3::from_xyz(0, 0, 0), block(7));
+    let renumbered = tree.renumber();
+    assert_eq!(renumbered[&7], 1);
+    assert_eq!(renumbered[&3], 2);
+
+    let mut bvp_file = asset();
+    // 4x2x1 superblocks, both holding the same two blocks at the same positions.
+    tree.build(&mut bvp_file, &[Vector3::from_xyz(4, 2, 1)]).unwrap();
+    assert_eq!(bvp_file.blocks.len(), 4);
+    let root_placements = &bvp_file.blocks[0].placements;
+    assert_eq!(root_placements.len(), 2);
+    assert!(root_placements.iter().all(|p| p.block == 3));
+    let superblock = &bvp_file.blocks[3];
+    assert_eq!(superblock.dimensions, Vector3::from_xyz(4, 2, 1));
+    assert_eq!(superblock.placements.iter().map(|p| p.block).collect::<Vec<usize>>(), vec![1, 2]);
+}
+
+#[test]
+fn invalid_trees_are_rejected() {
+    let mut tree = BlockTreeBuilder::new(1);
+    tree.add_placement(0, Vector3::from_xyz(0, 0, 0), 5);
+    assert!(matches!(tree.build(&mut asset(), &[]), Err(TreeError::UnknownBlock(_))));
+
+    let mut tree = BlockTreeBuilder::new(1);
+    tree.add_block(1, Vector3::from_xyz(0, 0, 0), block(5));
+    assert!(matches!(tree.build(&mut asset(), &[]), Err(TreeError::UnknownRoot(1))));
+
+    let mut tree = BlockTreeBuilder::new(1);
+    tree.add_block(0, Vector3::from_xyz(0, 0, 0), block(5));
+    tree.add_block(0, Vector3::from_xyz(2, 0, 0), block(5));
+    assert!(matches!(tree.build(&mut asset(), &[]), Err(TreeError::DuplicateBlock(5))));
+
+    let tree = BlockTreeBuilder::new(2);
+    assert!(matches!(tree.build(&mut asset(), &[]), Err(TreeError::RootCount(2, 1))));
+}
@@ -0,0 +1,67 @@
+//! Tests of `bvp2raw` run as a program, on assets written by `raw2bvp`.
+
+use std::{env, fs, process::Command};
+
+#[test]
+fn config_file_selects_the_region_and_flags_override_it() {
+    let dir = env::temp_dir().join(format!("bvp-unpack-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let volume: Vec<u8> = (0..8 * 8 * 8).map(|i| i as u8).collect();
+    fs::write(dir.join("input.raw"), &volume).unwrap();
+    let result = Command::new(env!("CARGO_BIN_EXE_raw2bvp")).current_dir(&dir).args([
+        "--input-file", "input.raw", "--output-file", "asset.saf", "--archive", "SAF", "--name", "head",
+        "--dimensions", "8x8x8", "--block-dimensions", "4x4x4", "--format", "u8", "--no-progress", "-q"
+    ]).output().unwrap();
+    assert!(result.status.success(), "raw2bvp failed: {}", String::from_utf8_lossy(&result.stderr));
+
+    // Paths in the config file are relative to its folder, not to the working directory.
+    fs::write(dir.join("extract.json"), r#"{
+        "inputFile": "asset.saf",
+        "modalities": ["head"],
+        "start": [0, 0, 2],
+        "end": [8, 8, 4],
+        "outputDir": "out",
+        "outputName": "region"
+    }"#).unwrap();
+    let unpack = |extra: &[&str]| {
+        let result = Command::new(env!("CARGO_BIN_EXE_bvp2raw")).arg("--config").arg(dir.join("extract.json"))
+            .args(extra).arg("-q").output().unwrap();
+        assert!(result.status.success(), "bvp2raw failed: {}", String::from_utf8_lossy(&result.stderr));
+    };
+    unpack(&[]);
+    assert_eq!(fs::read(dir.join("out/region.raw")).unwrap(), volume[2 * 64..4 * 64]);
+    unpack(&["--end", "8,8,3", "--force"]);
+    assert_eq!(fs::read(dir.join("out/region.raw")).unwrap(), volume[2 * 64..3 * 64]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reconstructed_volumes_are_checked_against_their_digest() {
+    let dir = env::temp_dir().join(format!("bvp-unpack-digest-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let volume: Vec<u8> = (0..8 * 8 * 8).map(|i| (i * 7) as u8).collect();
+    fs::write(dir.join("input.raw"), &volume).unwrap();
+    let result = Command::new(env!("CARGO_BIN_EXE_raw2bvp")).current_dir(&dir).args([
+        "--input-file", "input.raw", "--output-file", "asset", "--archive", "None", "--name", "head", "--volume-checksum", "sha256",
+        "--dimensions", "8x8x8", "--block-dimensions", "4x4x4", "--format", "u8", "--no-progress", "-q"
+    ]).output().unwrap();
+    assert!(result.status.success(), "raw2bvp failed: {}", String::from_utf8_lossy(&result.stderr));
+
+    let unpack = || {
+        return Command::new(env!("CARGO_BIN_EXE_bvp2raw")).current_dir(&dir)
+            .args(["asset/manifest.json", "None", "--output-dir", "out", "--checksum", "sha256", "--force", "-q"]).output().unwrap();
+    };
+    let result = unpack();
+    assert!(result.status.success(), "bvp2raw failed: {}", String::from_utf8_lossy(&result.stderr));
+    let printed = String::from_utf8(result.stdout).unwrap();
+    assert!(printed.starts_with("sha256:") && printed.trim_end().ends_with("head.raw"), "{}", printed);
+
+    // A manifest whose digest does not match the blocks fails verification.
+    let manifest = fs::read_to_string(dir.join("asset/manifest.json")).unwrap();
+    let digest = &printed[7..71];
+    fs::write(dir.join("asset/manifest.json"), manifest.replace(digest, &"0".repeat(64))).unwrap();
+    let result = unpack();
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("but the manifest records"));
+    fs::remove_dir_all(&dir).unwrap();
+}
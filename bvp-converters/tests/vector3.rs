@@ -0,0 +1,80 @@
+//! Tests of `Vector3`: the operators, checked arithmetic and conversions.
+
+use tinyjson::JsonValue;
+
+use bvp::vector3::Vector3;
+
+#[test]
+fn operators_work_component_wise() {
+    let a = Vector3::from_xyz(7u32, 12, 5);
+    let b = Vector3::from_xyz(2u32, 4, 5);
+    assert_eq!(a + b, Vector3::from_xyz(9, 16, 10));
+    assert_eq!(a - b, Vector3::from_xyz(5, 8, 0));
+    assert_eq!(a * b, Vector3::from_xyz(14, 48, 25));
+    assert_eq!(a / b, Vector3::from_xyz(3, 3, 1));
+    assert_eq!(a % b, Vector3::from_xyz(1, 0, 0));
+    assert_eq!(a * 2, Vector3::from_xyz(14, 24, 10));
+    assert_eq!(a / 2, Vector3::from_xyz(3, 6, 2));
+    let mut c = a;
+    c += b;
+    c -= Vector3::splat(1);
+    assert_eq!(c, Vector3::from_xyz(8, 15, 9));
+
+    // Other component types work the same.
+    let f = Vector3::from_xyz(1.5f32, 0.25, 2.0) * 2.0;
+    assert_eq!(f, Vector3::from_xyz(3.0, 0.5, 4.0));
+    assert_eq!(Vector3::from_xyz(-3i64, 4, 0) - Vector3::splat(1), Vector3::from_xyz(-4, 3, -1));
+
+    assert_eq!(a.min(&b), Vector3::from_xyz(2, 4, 5));
+    assert_eq!(a.max(&b), a);
+    assert_eq!(a.div_ceil(&b), Vector3::from_xyz(4, 3, 1));
+    assert!(a.is_any_div(&b) && !b.is_any_div(&b));
+    assert!(b.is_any_lt(a) && !b.is_any_gt(a));
+    assert_eq!((a.sum(), a.product()), (24, 420));
+    assert_eq!(a[1], 12);
+    assert_eq!(Vector3::from_array(a.to_array()), a);
+    assert_eq!(a.zip_map(&b, |x, y| x > y), Vector3::from_xyz(true, true, false));
+    assert_eq!(format!("{}", a), "[7 12 5]");
+}
+
+#[test]
+fn checked_arithmetic_reports_overflow() {
+    let big = Vector3::from_xyz(u32::MAX, 2, 3);
+    let one = Vector3::splat(1u32);
+    assert_eq!(big.checked_add(&one), None);
+    assert_eq!(one.checked_sub(&big), None);
+    assert_eq!(big.checked_mul(&Vector3::from_xyz(2, 1, 1)), None);
+    assert_eq!(big.checked_product(), None);
+    assert_eq!(one.checked_add(&one), Some(Vector3::splat(2)));
+    assert_eq!(big.saturating_add(&one), Vector3::from_xyz(u32::MAX, 3, 4));
+    assert_eq!(big.multiply_elements(), Some(u32::MAX as u64 * 6));
+    // Products of three `u32` components can exceed `u64`.
+    assert_eq!(Vector3::splat(u32::MAX).multiply_elements(), None);
+
+    assert_eq!(big.cast::<u64>(), Some(Vector3::from_xyz(u32::MAX as u64, 2, 3)));
+    assert_eq!(big.cast::<u16>(), None);
+    assert_eq!(Vector3::from_xyz(-1i32, 0, 0).cast::<u32>(), None);
+}
+
+#[test]
+fn vectors_are_read_from_json_and_text() {
+    let json: JsonValue = "[256, 256, 128]".parse().unwrap();
+    let v = Vector3::<u32>::from_json(&json).unwrap();
+    assert_eq!(v, Vector3::from_xyz(256, 256, 128));
+    assert_eq!(v.to_json(), json);
+    assert!(Vector3::<u8>::from_json(&json).is_err());
+    for invalid in ["[1, 2]", "[1, 2, \"3\"]", "{}", "[-1, 0, 0]"] {
+        assert!(Vector3::<u32>::from_json(&invalid.parse().unwrap()).is_err(), "{}", invalid);
+    }
+    assert_eq!(Vector3::<f32>::from_json(&"[0.5, 1, 2.5]".parse().unwrap()).unwrap(), Vector3::from_xyz(0.5, 1.0, 2.5));
+
+    assert_eq!(Vector3::from_string("256x256x128"), Some(v));
+    assert_eq!(Vector3::from_string("1, 2, 3"), Some(Vector3::from_xyz(1, 2, 3)));
+    for invalid in ["1x2", "1x2x3x4", "ax2x3", ""] {
+        assert_eq!(Vector3::from_string(invalid), None, "{}", invalid);
+    }
+    assert_eq!(Vector3::from_xyz(1.2f32, 3.0, 0.1).ceil(), Vector3::from_xyz(2, 3, 1));
+    // X in the lowest bit, then Y and Z: 3 = 0b11, 1 = 0b01 and 2 = 0b10 interleave into 0b101_011.
+    assert_eq!(Vector3::from_xyz(3, 1, 2).morton_code(), 0b101_011);
+    assert_eq!(Vector3::linear_index(Vector3::from_xyz(1, 2, 3), Vector3::from_xyz(4, 5, 6)), 1 + 4 * (2 + 5 * 3));
+}
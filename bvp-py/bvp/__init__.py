@@ -0,0 +1,255 @@
+"""Reading and writing BVP assets from Python.
+
+The functions call the C API of the bvp library (``libbvp_capi``, built from the ``bvp-capi``
+crate), so assets are read and written by the same code as the command line tools. Volumes are
+returned as numpy arrays indexed ``[z, y, x]``, with a last axis for formats with several
+components. Without numpy, ``read_volume`` and ``read_region`` return the voxels as ``bytes``.
+
+The library is looked up in ``BVP_CAPI_LIBRARY``, next to this package, and in the build
+folders of ``bvp-capi``.
+"""
+
+import ctypes
+import json
+import os
+import sys
+
+try:
+    import numpy
+except ImportError:
+    numpy = None
+
+__all__ = ["BvpError", "Asset", "read_volume", "read_region", "write_volume"]
+
+ARCHIVES = {"none": 0, "saf": 1, "zip": 2}
+COMPRESSIONS = {"none": 0, "raw": 0, "lz4s": 1}
+_BUFFER_TOO_SMALL = 4
+
+
+class BvpError(Exception):
+    """Raised when a call of the library fails, with its status and message."""
+
+    def __init__(self, status, message):
+        super().__init__(message)
+        self.status = status
+
+
+def _library_names():
+    if sys.platform == "win32":
+        return ["bvp_capi.dll"]
+    if sys.platform == "darwin":
+        return ["libbvp_capi.dylib"]
+    return ["libbvp_capi.so"]
+
+
+def _find_library():
+    path = os.environ.get("BVP_CAPI_LIBRARY")
+    if path:
+        return path
+    here = os.path.dirname(os.path.abspath(__file__))
+    capi = os.path.join(here, "..", "..", "bvp-capi", "target")
+    for folder in [here, os.path.join(capi, "release"), os.path.join(capi, "debug")]:
+        for name in _library_names():
+            candidate = os.path.join(folder, name)
+            if os.path.isfile(candidate):
+                return candidate
+    raise ImportError("libbvp_capi is not found, build bvp-capi or set BVP_CAPI_LIBRARY")
+
+
+def _load():
+    lib = ctypes.CDLL(_find_library())
+    size = ctypes.c_size_t
+    u32_3 = ctypes.POINTER(ctypes.c_uint32)
+    lib.bvp_last_error.restype = ctypes.c_char_p
+    lib.bvp_last_error.argtypes = []
+    lib.bvp_open.restype = ctypes.c_int
+    lib.bvp_open.argtypes = [ctypes.c_char_p, ctypes.c_int, ctypes.POINTER(ctypes.c_void_p)]
+    lib.bvp_close.restype = None
+    lib.bvp_close.argtypes = [ctypes.c_void_p]
+    lib.bvp_get_modality_count.restype = size
+    lib.bvp_get_modality_count.argtypes = [ctypes.c_void_p]
+    lib.bvp_get_modality_dimensions.restype = ctypes.c_int
+    lib.bvp_get_modality_dimensions.argtypes = [ctypes.c_void_p, size, u32_3]
+    lib.bvp_get_format_json.restype = ctypes.c_int
+    lib.bvp_get_format_json.argtypes = [ctypes.c_void_p, size, ctypes.c_char_p, size, ctypes.POINTER(size)]
+    lib.bvp_get_region_size.restype = ctypes.c_int
+    lib.bvp_get_region_size.argtypes = [ctypes.c_void_p, size, u32_3, u32_3, ctypes.POINTER(size)]
+    lib.bvp_read_region.restype = ctypes.c_int
+    lib.bvp_read_region.argtypes = [ctypes.c_void_p, size, u32_3, u32_3, ctypes.c_void_p, size]
+    lib.bvp_write_volume.restype = ctypes.c_int
+    lib.bvp_write_volume.argtypes = [
+        ctypes.c_char_p, ctypes.c_int, ctypes.c_void_p, size, u32_3, u32_3, ctypes.c_char_p, ctypes.c_int
+    ]
+    return lib
+
+
+_lib = _load()
+
+
+def _check(status):
+    if status != 0:
+        message = _lib.bvp_last_error()
+        raise BvpError(status, message.decode("utf-8", "replace") if message else "unknown error")
+
+
+def _vector(values):
+    values = [int(v) for v in values]
+    if len(values) != 3:
+        raise ValueError("expected three values, got {}".format(len(values)))
+    return (ctypes.c_uint32 * 3)(*values)
+
+
+def _option(options, value, name):
+    try:
+        return options[value.lower()]
+    except KeyError:
+        raise ValueError("unknown {} {!r}, expected one of {}".format(name, value, ", ".join(options))) from None
+
+
+def _archive_of(path):
+    if os.path.isdir(path) or path.endswith(".json"):
+        return "none"
+    return "zip" if path.lower().endswith(".zip") else "saf"
+
+
+def _dtype(format_json):
+    """Returns the numpy dtype and component count of a format, or None if the voxels cannot be typed."""
+    if format_json.get("family") == "mono":
+        components = [format_json]
+    elif format_json.get("family") == "multi":
+        components = format_json.get("components", [])
+    else:
+        return None
+    types = {(c.get("type"), c.get("size", 0) // max(c.get("count", 1), 1)) for c in components}
+    if len(types) != 1:
+        return None
+    kind, size = types.pop()
+    if kind not in ("u", "i", "f") or size not in (1, 2, 4, 8) or (kind == "f" and size == 1):
+        return None
+    return numpy.dtype("<{}{}".format(kind, size)), sum(c.get("count", 1) for c in components)
+
+
+class Asset:
+    """An opened asset. Use it as a context manager, or call ``close``.
+
+    * ``path`` - the archive file, or for unarchived assets the folder or the manifest file
+    * ``archive`` - ``"saf"``, ``"zip"`` or ``"none"``, guessed from the path if not given
+    """
+
+    def __init__(self, path, archive=None):
+        self._handle = ctypes.c_void_p()
+        archive = _option(ARCHIVES, archive or _archive_of(str(path)), "archive")
+        _check(_lib.bvp_open(os.fsencode(path), archive, ctypes.byref(self._handle)))
+
+    def close(self):
+        if self._handle:
+            _lib.bvp_close(self._handle)
+            self._handle = ctypes.c_void_p()
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *exc):
+        self.close()
+
+    def __del__(self):
+        self.close()
+
+    @property
+    def modality_count(self):
+        return _lib.bvp_get_modality_count(self._handle)
+
+    def dimensions(self, modality=0):
+        """Returns the dimensions of a modality as ``(x, y, z)``."""
+        out = (ctypes.c_uint32 * 3)()
+        _check(_lib.bvp_get_modality_dimensions(self._handle, modality, out))
+        return tuple(out)
+
+    def format(self, modality=0):
+        """Returns the format of a modality as a dict, as it is in the manifest."""
+        length = ctypes.c_size_t()
+        status = _lib.bvp_get_format_json(self._handle, modality, None, 0, ctypes.byref(length))
+        if status != _BUFFER_TOO_SMALL:
+            _check(status)
+        buffer = ctypes.create_string_buffer(length.value + 1)
+        _check(_lib.bvp_get_format_json(self._handle, modality, buffer, len(buffer), ctypes.byref(length)))
+        return json.loads(buffer.value.decode("utf-8"))
+
+    def read_region(self, start, end, modality=0):
+        """Reconstructs the voxels from ``start`` to ``end`` (exclusive), both given as ``(x, y, z)``.
+
+        Returns a numpy array of shape ``(z, y, x)`` or ``(z, y, x, components)``, or ``bytes``
+        without numpy or for formats numpy cannot represent.
+        """
+        start, end = _vector(start), _vector(end)
+        size = ctypes.c_size_t()
+        _check(_lib.bvp_get_region_size(self._handle, modality, start, end, ctypes.byref(size)))
+        buffer = ctypes.create_string_buffer(size.value)
+        _check(_lib.bvp_read_region(self._handle, modality, start, end, buffer, size.value))
+        data = buffer.raw
+        typed = _dtype(self.format(modality)) if numpy is not None else None
+        if typed is None:
+            return data
+        dtype, components = typed
+        shape = tuple(int(e) - int(s) for s, e in zip(reversed(start), reversed(end)))
+        if components > 1:
+            shape += (components,)
+        return numpy.frombuffer(data, dtype=dtype).reshape(shape)
+
+    def read_volume(self, modality=0):
+        """Reconstructs the whole volume of a modality, see ``read_region``."""
+        return self.read_region((0, 0, 0), self.dimensions(modality), modality)
+
+
+def read_volume(path, modality=0, archive=None):
+    """Reads the whole volume of a modality of an asset, see ``Asset.read_region``."""
+    with Asset(path, archive) as asset:
+        return asset.read_volume(modality)
+
+
+def read_region(path, start, end, modality=0, archive=None):
+    """Reads a region of a modality of an asset, see ``Asset.read_region``."""
+    with Asset(path, archive) as asset:
+        return asset.read_region(start, end, modality)
+
+
+def _format_of(array):
+    kind = {"u": "u", "i": "i", "f": "f"}.get(array.dtype.kind)
+    if kind is None or (kind == "f" and array.dtype.itemsize == 1):
+        raise ValueError("arrays of {} cannot be written".format(array.dtype))
+    name = "{}{}".format(kind, array.dtype.itemsize * 8)
+    if array.ndim == 4:
+        return "{}x{}".format(name, array.shape[3])
+    return name
+
+
+def write_volume(path, volume, block_size, compression="lz4s", archive=None, format=None, dimensions=None):
+    """Writes a volume as an asset with a single modality.
+
+    * ``path`` - the archive file, or for unarchived assets the folder to write to
+    * ``volume`` - a numpy array of shape ``(z, y, x)`` or ``(z, y, x, components)``, or ``bytes``
+      together with ``format`` and ``dimensions``
+    * ``block_size`` - dimensions of the blocks as ``(x, y, z)``, or one number for cubes
+    * ``compression`` - ``"lz4s"`` or ``"none"``
+    * ``archive`` - ``"saf"``, ``"zip"`` or ``"none"``, guessed from the path if not given
+    * ``format`` - the format as a shorthand such as ``"u16"`` or a dict, taken from the array if not given
+    * ``dimensions`` - dimensions of the volume as ``(x, y, z)``, taken from the array if not given
+    """
+    if numpy is not None and isinstance(volume, numpy.ndarray):
+        if volume.ndim not in (3, 4):
+            raise ValueError("expected an array of shape (z, y, x) or (z, y, x, components)")
+        format = format or _format_of(volume)
+        dimensions = dimensions or (volume.shape[2], volume.shape[1], volume.shape[0])
+        data = numpy.ascontiguousarray(volume, dtype=volume.dtype.newbyteorder("<")).tobytes()
+    else:
+        if format is None or dimensions is None:
+            raise ValueError("format and dimensions are needed to write bytes")
+        data = bytes(volume)
+    if isinstance(format, dict):
+        format = json.dumps(format)
+    if isinstance(block_size, int):
+        block_size = (block_size, block_size, block_size)
+    _check(_lib.bvp_write_volume(
+        os.fsencode(path), _option(ARCHIVES, archive or _archive_of(str(path)), "archive"), data, len(data),
+        _vector(dimensions), _vector(block_size), format.encode("utf-8"), _option(COMPRESSIONS, compression, "compression")
+    ))
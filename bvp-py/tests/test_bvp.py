@@ -0,0 +1,57 @@
+"""Round trips through the Python bindings. Run with ``python -m unittest`` from ``bvp-py``
+after building ``bvp-capi``."""
+
+import os
+import shutil
+import tempfile
+import unittest
+
+import bvp
+
+try:
+    import numpy
+except ImportError:
+    numpy = None
+
+
+class RoundTrip(unittest.TestCase):
+    def setUp(self):
+        self.dir = tempfile.mkdtemp()
+
+    def tearDown(self):
+        shutil.rmtree(self.dir)
+
+    def test_bytes(self):
+        data = bytes(i % 11 for i in range(8 * 6 * 4))
+        for name in ["asset.saf", "asset.zip"]:
+            path = os.path.join(self.dir, name)
+            bvp.write_volume(path, data, 4, format="u8", dimensions=(8, 6, 4), compression="lz4s")
+            with bvp.Asset(path) as asset:
+                self.assertEqual(asset.modality_count, 1)
+                self.assertEqual(asset.dimensions(), (8, 6, 4))
+                self.assertEqual(asset.format()["family"], "mono")
+                if numpy is None:
+                    self.assertEqual(asset.read_volume(), data)
+                    region = asset.read_region((2, 1, 0), (5, 3, 2))
+                    expected = bytes(data[x + y * 8 + z * 48] for z in range(0, 2) for y in range(1, 3) for x in range(2, 5))
+                    self.assertEqual(region, expected)
+
+    def test_errors(self):
+        with self.assertRaises(bvp.BvpError):
+            bvp.read_volume(os.path.join(self.dir, "missing.saf"))
+        with self.assertRaises(bvp.BvpError):
+            bvp.write_volume(os.path.join(self.dir, "a.saf"), b"\0", 1, format="u9", dimensions=(1, 1, 1))
+
+    @unittest.skipIf(numpy is None, "numpy is not installed")
+    def test_arrays(self):
+        for dtype, shape in [("uint8", (5, 6, 7)), ("uint16", (5, 6, 7)), ("float32", (4, 4, 4)), ("uint8", (4, 6, 8, 3))]:
+            volume = (numpy.arange(numpy.prod(shape)) % 200).astype(dtype).reshape(shape)
+            path = os.path.join(self.dir, "asset.saf")
+            bvp.write_volume(path, volume, (2, 3, 2))
+            numpy.testing.assert_array_equal(bvp.read_volume(path), volume)
+            region = bvp.read_region(path, (1, 2, 1), (3, 4, 2))
+            numpy.testing.assert_array_equal(region, volume[1:2, 2:4, 1:3])
+
+
+if __name__ == "__main__":
+    unittest.main()